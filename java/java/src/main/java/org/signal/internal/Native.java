@@ -7,11 +7,14 @@
 
 package org.signal.client.internal;
 
+import org.whispersystems.libsignal.EncryptedMessage;
+import org.whispersystems.libsignal.PreKeyBundleReport;
 import org.whispersystems.libsignal.protocol.CiphertextMessage;
 import org.whispersystems.libsignal.state.IdentityKeyStore;
 import org.whispersystems.libsignal.state.SessionStore;
 import org.whispersystems.libsignal.state.PreKeyStore;
 import org.whispersystems.libsignal.state.SignedPreKeyStore;
+import org.whispersystems.libsignal.groups.GroupEncryptedMessage;
 import org.whispersystems.libsignal.groups.state.SenderKeyStore;
 
 import java.io.File;
@@ -20,6 +23,7 @@ import java.io.IOException;
 import java.io.InputStream;
 import java.io.OutputStream;
 import java.nio.file.Files;
+import java.util.concurrent.CompletableFuture;
 
 public final class Native {
   private static void copyToTempFileAndLoad(InputStream in, String extension) throws IOException {
@@ -66,15 +70,31 @@ public final class Native {
 
   private Native() {}
 
+  public static native CiphertextMessage CiphertextMessage_Deserialize(int messageType, byte[] data);
+
+  public static native byte[] Crypto_HmacSha512(byte[] key, byte[] input);
+  public static native byte[] Crypto_Sha512(byte[] input);
+
+  public static native long DecryptionErrorMessage_Deserialize(byte[] data);
+  public static native void DecryptionErrorMessage_Destroy(long handle);
+  public static native long DecryptionErrorMessage_ExtractFromSerializedContent(byte[] data);
+  public static native long DecryptionErrorMessage_ForOriginalMessage(byte[] originalBytes, int originalType, long originalTimestamp, int originalSenderDeviceId);
+  public static native int DecryptionErrorMessage_GetDeviceId(long handle);
+  public static native long DecryptionErrorMessage_GetRatchetKey(long handle);
+  public static native byte[] DecryptionErrorMessage_GetSerialized(long handle);
+  public static native long DecryptionErrorMessage_GetTimestamp(long handle);
+
   public static native String DisplayableFingerprint_Format(byte[] local, byte[] remote);
 
   public static native byte[] ECPrivateKey_Agree(long privateKeyHandle, long publicKeyHandle);
   public static native long ECPrivateKey_Deserialize(byte[] data);
   public static native void ECPrivateKey_Destroy(long handle);
   public static native long ECPrivateKey_Generate();
+  public static native long ECPrivateKey_GenerateWithTestRng(long rng);
   public static native long ECPrivateKey_GetPublicKey(long handle);
   public static native byte[] ECPrivateKey_Serialize(long handle);
   public static native byte[] ECPrivateKey_Sign(long handle, byte[] message);
+  public static native byte[] ECPrivateKey_SignWithRandomness(long handle, byte[] message, byte[] randomness);
 
   public static native int ECPublicKey_Compare(long key1, long key2);
   public static native long ECPublicKey_Deserialize(byte[] data, int offset);
@@ -85,18 +105,34 @@ public final class Native {
 
   public static native byte[] GroupCipher_DecryptMessage(long senderKeyName, byte[] message, SenderKeyStore store);
   public static native byte[] GroupCipher_EncryptMessage(long senderKeyName, byte[] message, SenderKeyStore store);
+  public static native GroupEncryptedMessage GroupCipher_EncryptMessageWithMetadata(long senderKeyName, byte[] message, SenderKeyStore store);
 
   public static native long GroupSessionBuilder_CreateSenderKeyDistributionMessage(long senderKeyName, SenderKeyStore store);
   public static native void GroupSessionBuilder_ProcessSenderKeyDistributionMessage(long senderKeyName, long senderKeyDistributionMessage, SenderKeyStore store);
+  public static native void GroupSessionBuilder_ProcessSenderKeyDistributionMessageWithExpectedId(long senderKeyName, long senderKeyDistributionMessage, int expectedId, SenderKeyStore store);
 
   public static native byte[] HKDF_DeriveSecrets(int version, byte[] inputKeyMaterial, byte[] salt, byte[] info, int outputLength);
 
   public static native byte[] IdentityKeyPair_Serialize(long publicKeyHandle, long privateKeyHandle);
+  public static native byte[] IdentityKeyPair_SignAlternateIdentity(long publicKeyHandle, long privateKeyHandle, long otherIdentityHandle);
+
+  public static native boolean IdentityKey_VerifyAlternateIdentity(long publicKeyHandle, long otherIdentityHandle, byte[] signature);
+
+  public static native byte[] MessagePadding_Pad(byte[] body);
+  public static native byte[] MessagePadding_Unpad(byte[] padded);
 
   public static native void NumericFingerprintGenerator_Destroy(long handle);
   public static native String NumericFingerprintGenerator_GetDisplayString(long handle);
   public static native byte[] NumericFingerprintGenerator_GetScannableEncoding(long handle);
   public static native long NumericFingerprintGenerator_New(int iterations, int version, byte[] localIdentifier, byte[] localKey, byte[] remoteIdentifier, byte[] remoteKey);
+  public static native String NumericFingerprintGenerator_NewDisplayable(int iterations, byte[] localIdentifier, byte[] localKey, byte[] remoteIdentifier, byte[] remoteKey);
+  public static native long NumericFingerprintGenerator_NewMulti(int iterations, byte[][] localIdentifiers, byte[] localKey, byte[][] remoteIdentifiers, byte[] remoteKey);
+
+  public static native long PlaintextContent_Deserialize(byte[] data);
+  public static native void PlaintextContent_Destroy(long handle);
+  public static native long PlaintextContent_FromDecryptionErrorMessage(long decryptionErrorMessageHandle);
+  public static native byte[] PlaintextContent_GetBody(long handle);
+  public static native byte[] PlaintextContent_GetSerialized(long handle);
 
   public static native void PreKeyBundle_Destroy(long handle);
   public static native int PreKeyBundle_GetDeviceId(long handle);
@@ -133,8 +169,15 @@ public final class Native {
   public static native int ProtocolAddress_DeviceId(long handle);
   public static native String ProtocolAddress_Name(long handle);
   public static native long ProtocolAddress_New(String name, int deviceId);
+  public static native long ProtocolAddress_NewFromServiceId(byte[] serviceId, int deviceId);
+  public static native byte[] ProtocolAddress_ServiceId(long handle);
 
   public static native boolean ScannableFingerprint_Compare(byte[] fprint1, byte[] fprint2);
+  public static native int[] ScannableFingerprint_CompareScanned(byte[] fprint1, byte[] fprint2);
+
+  public static native byte[] SealedSender_DeriveUnidentifiedAccessKey(byte[] profileKey);
+  public static native byte[] SealedSender_UnrestrictedUnidentifiedAccessKey();
+  public static native byte[] SealedSender_UnrestrictedUnidentifiedAccessKeyWithTestRng(long rng);
 
   public static native long SenderKeyDistributionMessage_Deserialize(byte[] data);
   public static native void SenderKeyDistributionMessage_Destroy(long handle);
@@ -162,15 +205,26 @@ public final class Native {
 
   public static native long SenderKeyRecord_Deserialize(byte[] data);
   public static native void SenderKeyRecord_Destroy(long handle);
+  public static native int[] SenderKeyRecord_GetChainIds(long handle);
+  public static native int SenderKeyRecord_GetMaxIterationForChain(long handle, int chainId);
   public static native byte[] SenderKeyRecord_GetSerialized(long handle);
+  public static native int SenderKeyRecord_GetStateCount(long handle);
   public static native long SenderKeyRecord_New();
 
-  public static native void SessionBuilder_ProcessPreKeyBundle(long bundle, long protocolAddress, SessionStore sessionStore, IdentityKeyStore identityKeyStore);
+  public static native PreKeyBundleReport SessionBuilder_ProcessPreKeyBundle(long bundle, long protocolAddress, SessionStore sessionStore, IdentityKeyStore identityKeyStore);
 
   public static native byte[] SessionCipher_DecryptPreKeySignalMessage(long message, long protocolAddress, SessionStore sessionStore, IdentityKeyStore identityKeyStore, PreKeyStore prekeyStore, SignedPreKeyStore signedPrekeyStore);
+  public static native CompletableFuture SessionCipher_DecryptPreKeySignalMessageAsync(long message, long protocolAddress, SessionStore sessionStore, IdentityKeyStore identityKeyStore, PreKeyStore prekeyStore, SignedPreKeyStore signedPrekeyStore, Object sessionLock);
   public static native byte[] SessionCipher_DecryptSignalMessage(long message, long protocolAddress, SessionStore sessionStore, IdentityKeyStore identityKeyStore);
+  public static native CompletableFuture SessionCipher_DecryptSignalMessageAsync(long message, long protocolAddress, SessionStore sessionStore, IdentityKeyStore identityKeyStore, Object sessionLock);
   public static native CiphertextMessage SessionCipher_EncryptMessage(byte[] message, long protocolAddress, SessionStore sessionStore, IdentityKeyStore identityKeyStore);
+  public static native CompletableFuture SessionCipher_EncryptMessageAsync(byte[] message, long protocolAddress, SessionStore sessionStore, IdentityKeyStore identityKeyStore, Object sessionLock);
+  public static native EncryptedMessage SessionCipher_EncryptMessageWithDetails(byte[] message, long protocolAddress, SessionStore sessionStore, IdentityKeyStore identityKeyStore);
 
+  public static native boolean SessionState_DebugHasUsableSenderChain(byte[] sessionState);
+  public static native int SessionState_DebugNumberOfReceiverChains(byte[] sessionState);
+  public static native int SessionState_DebugReceiverChainCounter(byte[] sessionState, long theirEphemeral);
+  public static native int SessionState_DebugSenderChainCounter(byte[] sessionState);
   public static native byte[] SessionState_InitializeAliceSession(long identityKeyPrivate, long identityKeyPublic, long basePrivate, long basePublic, long theirIdentityKey, long theirSignedPrekey, long theirRatchetKey);
   public static native byte[] SessionState_InitializeBobSession(long identityKeyPrivate, long identityKeyPublic, long signedPrekeyPrivate, long signedPrekeyPublic, long ephPrivate, long ephPublic, long theirIdentityKey, long theirBaseKey);
 
@@ -193,4 +247,7 @@ public final class Native {
   public static native byte[] SignedPreKeyRecord_GetSignature(long handle);
   public static native long SignedPreKeyRecord_GetTimestamp(long handle);
   public static native long SignedPreKeyRecord_New(int id, long timestamp, long pubKeyHandle, long privKeyHandle, byte[] signature);
+
+  public static native void TestRng_Destroy(long handle);
+  public static native long TestRng_New(long seed);
 }
@@ -4,9 +4,11 @@ package org.signal.libsignal.metadata;
 import org.signal.libsignal.metadata.certificate.CertificateValidator;
 import org.signal.libsignal.metadata.certificate.InvalidCertificateException;
 import org.signal.libsignal.metadata.certificate.SenderCertificate;
+import org.signal.libsignal.metadata.protocol.SealedSenderMultiRecipientMessage;
 import org.signal.libsignal.metadata.protocol.UnidentifiedSenderMessage;
 import org.signal.libsignal.metadata.protocol.UnidentifiedSenderMessageContent;
 import org.whispersystems.libsignal.DuplicateMessageException;
+import org.whispersystems.libsignal.IdentityKey;
 import org.whispersystems.libsignal.IdentityKeyPair;
 import org.whispersystems.libsignal.InvalidKeyException;
 import org.whispersystems.libsignal.InvalidKeyIdException;
@@ -33,7 +35,10 @@ import org.whispersystems.libsignal.util.guava.Optional;
 import java.security.InvalidAlgorithmParameterException;
 import java.security.MessageDigest;
 import java.security.NoSuchAlgorithmException;
+import java.security.SecureRandom;
 import java.text.ParseException;
+import java.util.LinkedList;
+import java.util.List;
 import java.util.UUID;
 
 import javax.crypto.BadPaddingException;
@@ -67,23 +72,81 @@ public class SealedSessionCipher {
   public byte[] encrypt(SignalProtocolAddress destinationAddress, SenderCertificate senderCertificate, byte[] paddedPlaintext)
       throws InvalidKeyException, UntrustedIdentityException
   {
-    CiphertextMessage message       = new SessionCipher(signalProtocolStore, destinationAddress).encrypt(paddedPlaintext);
-    IdentityKeyPair   ourIdentity   = signalProtocolStore.getIdentityKeyPair();
-    ECPublicKey       theirIdentity = signalProtocolStore.getIdentity(destinationAddress).getPublicKey();
+    CiphertextMessage                message = new SessionCipher(signalProtocolStore, destinationAddress).encrypt(paddedPlaintext);
+    UnidentifiedSenderMessageContent content = new UnidentifiedSenderMessageContent(message.getType(), senderCertificate, message.serialize());
+
+    return encrypt(destinationAddress, content);
+  }
+
+  /**
+   * Seals already-built content for {@code destinationAddress} without touching session state.
+   * This lets content recovered with {@link #decryptToUsmc(byte[])} be resent to another recipient.
+   */
+  public byte[] encrypt(SignalProtocolAddress destinationAddress, UnidentifiedSenderMessageContent content)
+      throws InvalidKeyException
+  {
+    IdentityKeyPair ourIdentity   = signalProtocolStore.getIdentityKeyPair();
+    ECPublicKey     theirIdentity = signalProtocolStore.getIdentity(destinationAddress).getPublicKey();
 
     ECKeyPair     ephemeral           = Curve.generateKeyPair();
     byte[]        ephemeralSalt       = ByteUtil.combine("UnidentifiedDelivery".getBytes(), theirIdentity.serialize(), ephemeral.getPublicKey().serialize());
     EphemeralKeys ephemeralKeys       = calculateEphemeralKeys(theirIdentity, ephemeral.getPrivateKey(), ephemeralSalt);
     byte[]        staticKeyCiphertext = encrypt(ephemeralKeys.cipherKey, ephemeralKeys.macKey, ourIdentity.getPublicKey().getPublicKey().serialize());
 
-    byte[]                           staticSalt   = ByteUtil.combine(ephemeralKeys.chainKey, staticKeyCiphertext);
-    StaticKeys                       staticKeys   = calculateStaticKeys(theirIdentity, ourIdentity.getPrivateKey(), staticSalt);
-    UnidentifiedSenderMessageContent content      = new UnidentifiedSenderMessageContent(message.getType(), senderCertificate, message.serialize());
-    byte[]                           messageBytes = encrypt(staticKeys.cipherKey, staticKeys.macKey, content.getSerialized());
+    byte[]     staticSalt   = ByteUtil.combine(ephemeralKeys.chainKey, staticKeyCiphertext);
+    StaticKeys staticKeys   = calculateStaticKeys(theirIdentity, ourIdentity.getPrivateKey(), staticSalt);
+    byte[]     messageBytes = encrypt(staticKeys.cipherKey, staticKeys.macKey, content.getSerialized());
 
     return new UnidentifiedSenderMessage(ephemeral.getPublicKey(), staticKeyCiphertext, messageBytes).getSerialized();
   }
 
+  /**
+   * Seals {@code content} for every one of {@code destinations} at once.
+   *
+   * The content is encrypted a single time, under a random key; each destination only adds a
+   * small header with its own sealed copy of that key. The result is meant for a server, which
+   * splits it with {@link SealedSenderMultiRecipientMessage#split()}. Each split message is opened
+   * with {@link #decrypt} or {@link #decryptToUsmc} like any other.
+   *
+   * No session state is read or modified, so {@code content} is typically a sender key message.
+   */
+  public byte[] multiRecipientEncrypt(List<SignalProtocolAddress> destinations, UnidentifiedSenderMessageContent content)
+      throws InvalidKeyException, InvalidMetadataMessageException
+  {
+    IdentityKeyPair ourIdentity = signalProtocolStore.getIdentityKeyPair();
+    ECKeyPair       ephemeral   = Curve.generateKeyPair();
+    byte[]          messageKey  = new byte[32];
+
+    new SecureRandom().nextBytes(messageKey);
+
+    MessageKeys messageKeys      = calculateMessageKeys(messageKey);
+    byte[]      encryptedMessage = encrypt(messageKeys.cipherKey, messageKeys.macKey, content.getSerialized());
+    byte[]      sealedKey        = ByteUtil.combine(messageKey, sha256(encryptedMessage));
+
+    List<SealedSenderMultiRecipientMessage.Recipient> recipients = new LinkedList<>();
+
+    for (SignalProtocolAddress destination : destinations) {
+      IdentityKey theirIdentityKey = signalProtocolStore.getIdentity(destination);
+
+      if (theirIdentityKey == null) {
+        throw new InvalidKeyException("No identity key for " + destination);
+      }
+
+      ECPublicKey   theirIdentity       = theirIdentityKey.getPublicKey();
+      byte[]        ephemeralSalt       = ByteUtil.combine("UnidentifiedDelivery".getBytes(), theirIdentity.serialize(), ephemeral.getPublicKey().serialize());
+      EphemeralKeys ephemeralKeys       = calculateEphemeralKeys(theirIdentity, ephemeral.getPrivateKey(), ephemeralSalt);
+      byte[]        staticKeyCiphertext = encrypt(ephemeralKeys.cipherKey, ephemeralKeys.macKey, ourIdentity.getPublicKey().getPublicKey().serialize());
+
+      byte[]     staticSalt = ByteUtil.combine(ephemeralKeys.chainKey, staticKeyCiphertext);
+      StaticKeys staticKeys = calculateStaticKeys(theirIdentity, ourIdentity.getPrivateKey(), staticSalt);
+
+      recipients.add(new SealedSenderMultiRecipientMessage.Recipient(destination, staticKeyCiphertext,
+                                                                     encrypt(staticKeys.cipherKey, staticKeys.macKey, sealedKey)));
+    }
+
+    return new SealedSenderMultiRecipientMessage(ephemeral.getPublicKey(), recipients, encryptedMessage).getSerialized();
+  }
+
   public DecryptionResult decrypt(CertificateValidator validator, byte[] ciphertext, long timestamp)
       throws
       InvalidMetadataMessageException, InvalidMetadataVersionException,
@@ -93,42 +156,23 @@ public class SealedSessionCipher {
       ProtocolInvalidKeyIdException, ProtocolUntrustedIdentityException,
       SelfSendException
   {
-    UnidentifiedSenderMessageContent content;
+    UnidentifiedSenderMessageContent content = decryptToUsmc(ciphertext);
 
     try {
-      IdentityKeyPair           ourIdentity    = signalProtocolStore.getIdentityKeyPair();
-      UnidentifiedSenderMessage wrapper        = new UnidentifiedSenderMessage(ciphertext);
-      byte[]                    ephemeralSalt  = ByteUtil.combine("UnidentifiedDelivery".getBytes(), ourIdentity.getPublicKey().getPublicKey().serialize(), wrapper.getEphemeral().serialize());
-      EphemeralKeys             ephemeralKeys  = calculateEphemeralKeys(wrapper.getEphemeral(), ourIdentity.getPrivateKey(), ephemeralSalt);
-      byte[]                    staticKeyBytes = decrypt(ephemeralKeys.cipherKey, ephemeralKeys.macKey, wrapper.getEncryptedStatic());
-
-      ECPublicKey staticKey    = Curve.decodePoint(staticKeyBytes, 0);
-      byte[]      staticSalt   = ByteUtil.combine(ephemeralKeys.chainKey, wrapper.getEncryptedStatic());
-      StaticKeys  staticKeys   = calculateStaticKeys(staticKey, ourIdentity.getPrivateKey(), staticSalt);
-      byte[]      messageBytes = decrypt(staticKeys.cipherKey, staticKeys.macKey, wrapper.getEncryptedMessage());
-
-      content = new UnidentifiedSenderMessageContent(messageBytes);
       validator.validate(content.getSenderCertificate(), timestamp);
 
-      if (!MessageDigest.isEqual(content.getSenderCertificate().getKey().serialize(), staticKeyBytes)) {
-        throw new InvalidKeyException("Sender's certificate key does not match key used in message");
-      }
-
       boolean isLocalE164 = localE164Address != null && localE164Address.equals(content.getSenderCertificate().getSenderE164().orNull());
       boolean isLocalUuid = localUuidAddress != null && localUuidAddress.equals(content.getSenderCertificate().getSenderUuid().orNull());
 
       if ((isLocalE164 || isLocalUuid) && content.getSenderCertificate().getSenderDeviceId() == localDeviceId) {
         throw new SelfSendException();
       }
-    } catch (InvalidKeyException | InvalidMacException | InvalidCertificateException e) {
+    } catch (InvalidCertificateException e) {
       throw new InvalidMetadataMessageException(e);
     }
 
     try {
-      return new DecryptionResult(content.getSenderCertificate().getSenderUuid(),
-                                  content.getSenderCertificate().getSenderE164(),
-                                  content.getSenderCertificate().getSenderDeviceId(),
-                                  decrypt(content));
+      return new DecryptionResult(content.getSenderCertificate(), decrypt(content));
     } catch (InvalidMessageException e) {
       throw new ProtocolInvalidMessageException(e, content.getSenderCertificate().getSender(), content.getSenderCertificate().getSenderDeviceId());
     } catch (InvalidKeyException e) {
@@ -148,6 +192,61 @@ public class SealedSessionCipher {
     }
   }
 
+  /**
+   * Removes the sealed sender envelope and returns the content inside, after checking that the
+   * sender certificate's key matches the key the envelope was sealed with.
+   *
+   * The sender certificate is <em>not</em> validated against a trust root, and the inner message
+   * is not decrypted, so no session state is read or modified.
+   */
+  public UnidentifiedSenderMessageContent decryptToUsmc(byte[] ciphertext)
+      throws InvalidMetadataMessageException, InvalidMetadataVersionException
+  {
+    try {
+      IdentityKeyPair           ourIdentity    = signalProtocolStore.getIdentityKeyPair();
+      UnidentifiedSenderMessage wrapper        = new UnidentifiedSenderMessage(ciphertext);
+      byte[]                    ephemeralSalt  = ByteUtil.combine("UnidentifiedDelivery".getBytes(), ourIdentity.getPublicKey().getPublicKey().serialize(), wrapper.getEphemeral().serialize());
+      EphemeralKeys             ephemeralKeys  = calculateEphemeralKeys(wrapper.getEphemeral(), ourIdentity.getPrivateKey(), ephemeralSalt);
+      byte[]                    staticKeyBytes = decrypt(ephemeralKeys.cipherKey, ephemeralKeys.macKey, wrapper.getEncryptedStatic());
+
+      ECPublicKey staticKey  = Curve.decodePoint(staticKeyBytes, 0);
+      byte[]      staticSalt = ByteUtil.combine(ephemeralKeys.chainKey, wrapper.getEncryptedStatic());
+      StaticKeys  staticKeys = calculateStaticKeys(staticKey, ourIdentity.getPrivateKey(), staticSalt);
+      byte[]      messageBytes;
+
+      if (wrapper.getEncryptedMessageKey().isPresent()) {
+        byte[] sealedKey = decrypt(staticKeys.cipherKey, staticKeys.macKey, wrapper.getEncryptedMessageKey().get());
+
+        if (sealedKey.length != 64) {
+          throw new InvalidKeyException("Bad message key length: " + sealedKey.length);
+        }
+
+        byte[][] sealedKeyParts = ByteUtil.split(sealedKey, 32, 32);
+
+        // The shared ciphertext is bound to this recipient's header, so another recipient who
+        // knows the message key can't swap in different content.
+        if (!MessageDigest.isEqual(sealedKeyParts[1], sha256(wrapper.getEncryptedMessage()))) {
+          throw new InvalidKeyException("Message key does not match the shared ciphertext");
+        }
+
+        MessageKeys messageKeys = calculateMessageKeys(sealedKeyParts[0]);
+        messageBytes = decrypt(messageKeys.cipherKey, messageKeys.macKey, wrapper.getEncryptedMessage());
+      } else {
+        messageBytes = decrypt(staticKeys.cipherKey, staticKeys.macKey, wrapper.getEncryptedMessage());
+      }
+
+      UnidentifiedSenderMessageContent content = new UnidentifiedSenderMessageContent(messageBytes);
+
+      if (!MessageDigest.isEqual(content.getSenderCertificate().getKey().serialize(), staticKeyBytes)) {
+        throw new InvalidKeyException("Sender's certificate key does not match key used in message");
+      }
+
+      return content;
+    } catch (InvalidKeyException | InvalidMacException | InvalidCertificateException e) {
+      throw new InvalidMetadataMessageException(e);
+    }
+  }
+
   public int getSessionVersion(SignalProtocolAddress remoteAddress) {
     return new SessionCipher(signalProtocolStore, remoteAddress).getSessionVersion();
   }
@@ -180,15 +279,31 @@ public class SealedSessionCipher {
     }
   }
 
+  private MessageKeys calculateMessageKeys(byte[] messageKey) {
+    byte[]   messageDerived      = new HKDFv3().deriveSecrets(messageKey, "UnidentifiedDelivery_MultiRecipient".getBytes(), 64);
+    byte[][] messageDerivedParts = ByteUtil.split(messageDerived, 32, 32);
+
+    return new MessageKeys(messageDerivedParts[0], messageDerivedParts[1]);
+  }
+
+  private static byte[] sha256(byte[] input) {
+    try {
+      return MessageDigest.getInstance("SHA-256").digest(input);
+    } catch (NoSuchAlgorithmException e) {
+      throw new AssertionError(e);
+    }
+  }
+
   private byte[] decrypt(UnidentifiedSenderMessageContent message)
       throws InvalidVersionException, InvalidMessageException, InvalidKeyException, DuplicateMessageException, InvalidKeyIdException, UntrustedIdentityException, LegacyMessageException, NoSessionException
   {
     SignalProtocolAddress sender = getPreferredAddress(signalProtocolStore, message.getSenderCertificate());
 
     switch (message.getType()) {
-      case CiphertextMessage.WHISPER_TYPE: return new SessionCipher(signalProtocolStore, sender).decrypt(new SignalMessage(message.getContent()));
-      case CiphertextMessage.PREKEY_TYPE:  return new SessionCipher(signalProtocolStore, sender).decrypt(new PreKeySignalMessage(message.getContent()));
-      default:                             throw new InvalidMessageException("Unknown type: " + message.getType());
+      case CiphertextMessage.WHISPER_TYPE:   return new SessionCipher(signalProtocolStore, sender).decrypt(new SignalMessage(message.getContent()));
+      case CiphertextMessage.PREKEY_TYPE:    return new SessionCipher(signalProtocolStore, sender).decrypt(new PreKeySignalMessage(message.getContent()));
+      case CiphertextMessage.SENDERKEY_TYPE: throw new InvalidMessageException("Sender key content must be decrypted with a GroupCipher");
+      default:                               throw new InvalidMessageException("Unknown type: " + message.getType());
     }
   }
 
@@ -252,28 +367,32 @@ public class SealedSessionCipher {
   }
 
   public static class DecryptionResult {
-    private final Optional<String> senderUuid;
-    private final Optional<String> senderE164;
-    private final int              deviceId;
-    private final byte[]           paddedMessage;
-
-    private DecryptionResult(Optional<String> senderUuid, Optional<String> senderE164, int deviceId, byte[] paddedMessage) {
-      this.senderUuid    = senderUuid;
-      this.senderE164    = senderE164;
-      this.deviceId      = deviceId;
-      this.paddedMessage = paddedMessage;
+    private final SenderCertificate senderCertificate;
+    private final byte[]            paddedMessage;
+
+    private DecryptionResult(SenderCertificate senderCertificate, byte[] paddedMessage) {
+      this.senderCertificate = senderCertificate;
+      this.paddedMessage     = paddedMessage;
     }
 
     public Optional<String> getSenderUuid() {
-      return senderUuid;
+      return senderCertificate.getSenderUuid();
     }
 
     public Optional<String> getSenderE164() {
-      return senderE164;
+      return senderCertificate.getSenderE164();
     }
 
     public int getDeviceId() {
-      return deviceId;
+      return senderCertificate.getSenderDeviceId();
+    }
+
+    /**
+     * @return the sender certificate that was validated for this message, for callers that need
+     *         its expiration or its signer's key ID (e.g. for revocation checks).
+     */
+    public SenderCertificate getSenderCertificate() {
+      return senderCertificate;
     }
 
     public byte[] getPaddedMessage() {
@@ -303,4 +422,14 @@ public class SealedSessionCipher {
     }
   }
 
+  private static class MessageKeys {
+    private final SecretKeySpec cipherKey;
+    private final SecretKeySpec macKey;
+
+    private MessageKeys(byte[] cipherKey, byte[] macKey) {
+      this.cipherKey = new SecretKeySpec(cipherKey, "AES");
+      this.macKey    = new SecretKeySpec(macKey, "HmacSHA256");
+    }
+  }
+
 }
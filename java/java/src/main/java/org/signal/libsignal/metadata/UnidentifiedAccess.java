@@ -0,0 +1,24 @@
+package org.signal.libsignal.metadata;
+
+
+import org.signal.client.internal.Native;
+
+public class UnidentifiedAccess {
+
+  private UnidentifiedAccess() {}
+
+  /**
+   * Derives the access key that lets others send sealed sender messages to the owner of a
+   * 32-byte profile key, the same way the official clients do.
+   */
+  public static byte[] deriveAccessKeyFrom(byte[] profileKey) {
+    return Native.SealedSender_DeriveUnidentifiedAccessKey(profileKey);
+  }
+
+  /**
+   * @return a random access key, for recipients who accept sealed sender messages from anyone.
+   */
+  public static byte[] generateUnrestrictedAccessKey() {
+    return Native.SealedSender_UnrestrictedUnidentifiedAccessKey();
+  }
+}
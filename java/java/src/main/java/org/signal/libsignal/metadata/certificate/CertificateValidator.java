@@ -5,7 +5,10 @@ import org.whispersystems.libsignal.InvalidKeyException;
 import org.whispersystems.libsignal.ecc.Curve;
 import org.whispersystems.libsignal.ecc.ECPublicKey;
 
+import java.util.ArrayList;
+import java.util.Collections;
 import java.util.HashSet;
+import java.util.List;
 import java.util.Set;
 
 public class CertificateValidator {
@@ -15,16 +18,28 @@ public class CertificateValidator {
 
   }};
 
-  private final ECPublicKey trustRoot;
+  private final List<TrustRoot> trustRoots;
 
   public CertificateValidator(ECPublicKey trustRoot) {
-    this.trustRoot = trustRoot;
+    this(Collections.singletonList(new TrustRoot(trustRoot)));
+  }
+
+  /**
+   * Accepts server certificates signed by any of the given roots, as long as the validation time
+   * is within that root's cutoff. Useful while rotating from one trust root to another.
+   */
+  public CertificateValidator(List<TrustRoot> trustRoots) {
+    if (trustRoots.isEmpty()) {
+      throw new IllegalArgumentException("At least one trust root is required");
+    }
+
+    this.trustRoots = Collections.unmodifiableList(new ArrayList<>(trustRoots));
   }
 
   public void validate(SenderCertificate certificate, long validationTime) throws InvalidCertificateException {
     try {
       ServerCertificate serverCertificate = certificate.getSigner();
-      validate(serverCertificate);
+      validate(serverCertificate, validationTime);
 
       if (!Curve.verifySignature(serverCertificate.getKey(), certificate.getCertificate(), certificate.getSignature())) {
         throw new InvalidCertificateException("Signature failed");
@@ -38,10 +53,25 @@ public class CertificateValidator {
     }
   }
 
-  // VisibleForTesting
+  // VisibleForTesting: checks the signature against every trust root, ignoring cutoffs.
   void validate(ServerCertificate certificate) throws InvalidCertificateException {
+    validate(certificate, Long.MIN_VALUE);
+  }
+
+  private void validate(ServerCertificate certificate, long validationTime) throws InvalidCertificateException {
     try {
-      if (!Curve.verifySignature(trustRoot, certificate.getCertificate(), certificate.getSignature())) {
+      boolean signedByTrustedRoot = false;
+
+      for (TrustRoot trustRoot : trustRoots) {
+        if (trustRoot.isTrustedAt(validationTime) &&
+            Curve.verifySignature(trustRoot.getKey(), certificate.getCertificate(), certificate.getSignature()))
+        {
+          signedByTrustedRoot = true;
+          break;
+        }
+      }
+
+      if (!signedByTrustedRoot) {
         throw new InvalidCertificateException("Signature failed");
       }
 
@@ -53,4 +83,3 @@ public class CertificateValidator {
     }
   }
 }
-
@@ -1,11 +1,13 @@
 package org.signal.libsignal.metadata.certificate;
 
 
+import com.google.protobuf.ByteString;
 import com.google.protobuf.InvalidProtocolBufferException;
 
 import org.signal.libsignal.metadata.SignalProtos;
 import org.whispersystems.libsignal.InvalidKeyException;
 import org.whispersystems.libsignal.ecc.Curve;
+import org.whispersystems.libsignal.ecc.ECPrivateKey;
 import org.whispersystems.libsignal.ecc.ECPublicKey;
 import org.whispersystems.libsignal.util.guava.Optional;
 
@@ -42,6 +44,10 @@ public class SenderCertificate {
         throw new InvalidCertificateException("Missing fields");
       }
 
+      if (certificate.getSenderDevice() < 1 || certificate.getSenderDevice() > 127) {
+        throw new InvalidCertificateException("Invalid sender device id: " + certificate.getSenderDevice());
+      }
+
       this.signer         = new ServerCertificate(certificate.getSigner().toByteArray());
       this.key            = Curve.decodePoint(certificate.getIdentityKey().toByteArray(), 0);
       this.senderUuid     = certificate.hasSenderUuid() ? Optional.of(certificate.getSenderUuid()) : Optional.<String>absent();
@@ -58,6 +64,49 @@ public class SenderCertificate {
     }
   }
 
+  /**
+   * Issues a new sender certificate, signed by the server key that {@code signer} certifies.
+   *
+   * For use by servers and test harnesses; clients only ever parse certificates.
+   */
+  public SenderCertificate(ServerCertificate signer, ECPrivateKey signerKey,
+                           String senderUuid, Optional<String> senderE164, int senderDeviceId,
+                           ECPublicKey identityKey, long expiration)
+      throws InvalidKeyException, InvalidCertificateException
+  {
+    this(sign(signer, signerKey, senderUuid, senderE164, senderDeviceId, identityKey, expiration));
+  }
+
+  private static byte[] sign(ServerCertificate signer, ECPrivateKey signerKey,
+                             String senderUuid, Optional<String> senderE164, int senderDeviceId,
+                             ECPublicKey identityKey, long expiration)
+      throws InvalidKeyException, InvalidCertificateException
+  {
+    try {
+      SignalProtos.SenderCertificate.Certificate.Builder builder = SignalProtos.SenderCertificate.Certificate.newBuilder()
+                                                                                                   .setSenderUuid(senderUuid)
+                                                                                                   .setSenderDevice(senderDeviceId)
+                                                                                                   .setExpires(expiration)
+                                                                                                   .setIdentityKey(ByteString.copyFrom(identityKey.serialize()))
+                                                                                                   .setSigner(SignalProtos.ServerCertificate.parseFrom(signer.getSerialized()));
+
+      if (senderE164.isPresent()) {
+        builder.setSenderE164(senderE164.get());
+      }
+
+      byte[] certificate = builder.build().toByteArray();
+      byte[] signature   = Curve.calculateSignature(signerKey, certificate);
+
+      return SignalProtos.SenderCertificate.newBuilder()
+                                           .setCertificate(ByteString.copyFrom(certificate))
+                                           .setSignature(ByteString.copyFrom(signature))
+                                           .build()
+                                           .toByteArray();
+    } catch (InvalidProtocolBufferException e) {
+      throw new InvalidCertificateException(e);
+    }
+  }
+
   public ServerCertificate getSigner() {
     return signer;
   }
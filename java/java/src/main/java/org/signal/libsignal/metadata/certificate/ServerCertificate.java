@@ -1,11 +1,13 @@
 package org.signal.libsignal.metadata.certificate;
 
 
+import com.google.protobuf.ByteString;
 import com.google.protobuf.InvalidProtocolBufferException;
 
 import org.signal.libsignal.metadata.SignalProtos;
 import org.whispersystems.libsignal.InvalidKeyException;
 import org.whispersystems.libsignal.ecc.Curve;
+import org.whispersystems.libsignal.ecc.ECPrivateKey;
 import org.whispersystems.libsignal.ecc.ECPublicKey;
 
 public class ServerCertificate {
@@ -42,6 +44,33 @@ public class ServerCertificate {
     }
   }
 
+  /**
+   * Issues a new server certificate for {@code key}, signed by {@code trustRoot}.
+   *
+   * For use by servers and test harnesses; clients only ever parse certificates.
+   */
+  public ServerCertificate(ECPrivateKey trustRoot, int keyId, ECPublicKey key)
+      throws InvalidKeyException, InvalidCertificateException
+  {
+    this(sign(trustRoot, keyId, key));
+  }
+
+  private static byte[] sign(ECPrivateKey trustRoot, int keyId, ECPublicKey key) throws InvalidKeyException {
+    byte[] certificate = SignalProtos.ServerCertificate.Certificate.newBuilder()
+                                                                   .setId(keyId)
+                                                                   .setKey(ByteString.copyFrom(key.serialize()))
+                                                                   .build()
+                                                                   .toByteArray();
+
+    byte[] signature = Curve.calculateSignature(trustRoot, certificate);
+
+    return SignalProtos.ServerCertificate.newBuilder()
+                                         .setCertificate(ByteString.copyFrom(certificate))
+                                         .setSignature(ByteString.copyFrom(signature))
+                                         .build()
+                                         .toByteArray();
+  }
+
   public int getKeyId() {
     return keyId;
   }
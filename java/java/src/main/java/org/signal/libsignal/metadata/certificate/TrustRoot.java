@@ -0,0 +1,44 @@
+package org.signal.libsignal.metadata.certificate;
+
+
+import org.whispersystems.libsignal.ecc.ECPublicKey;
+import org.whispersystems.libsignal.util.guava.Optional;
+
+/**
+ * A key trusted to sign server certificates, optionally only until a cutoff time.
+ */
+public class TrustRoot {
+
+  private final ECPublicKey    key;
+  private final Optional<Long> notAfter;
+
+  public TrustRoot(ECPublicKey key) {
+    this(key, Optional.<Long>absent());
+  }
+
+  /**
+   * @param notAfter the last validation time (in the same units passed to
+   *                 {@link CertificateValidator#validate(SenderCertificate, long)}) at which
+   *                 certificates signed by this root are accepted.
+   */
+  public TrustRoot(ECPublicKey key, long notAfter) {
+    this(key, Optional.of(notAfter));
+  }
+
+  private TrustRoot(ECPublicKey key, Optional<Long> notAfter) {
+    this.key      = key;
+    this.notAfter = notAfter;
+  }
+
+  public ECPublicKey getKey() {
+    return key;
+  }
+
+  public Optional<Long> getNotAfter() {
+    return notAfter;
+  }
+
+  boolean isTrustedAt(long validationTime) {
+    return !notAfter.isPresent() || validationTime <= notAfter.get();
+  }
+}
@@ -0,0 +1,57 @@
+package org.signal.libsignal.metadata.protocol;
+
+import org.signal.libsignal.metadata.SignalProtos;
+
+/**
+ * Tells the recipient of a sealed sender message how to handle a failure to decrypt it.
+ *
+ * Values this version doesn't recognize are kept as-is, so they are serialized unchanged.
+ */
+public final class ContentHint {
+
+  /** No hint; the recipient should show an error to the user. */
+  public static final ContentHint DEFAULT    = new ContentHint(SignalProtos.UnidentifiedSenderMessage.Message.ContentHint.DEFAULT_VALUE);
+
+  /** The sender can resend the message, so the recipient should ask for that rather than show an error. */
+  public static final ContentHint RESENDABLE = new ContentHint(SignalProtos.UnidentifiedSenderMessage.Message.ContentHint.RESENDABLE_VALUE);
+
+  /** The message isn't visible to the user, so a failure can be dropped silently. */
+  public static final ContentHint IMPLICIT   = new ContentHint(SignalProtos.UnidentifiedSenderMessage.Message.ContentHint.IMPLICIT_VALUE);
+
+  private final int value;
+
+  private ContentHint(int value) {
+    this.value = value;
+  }
+
+  public static ContentHint fromValue(int value) {
+    if      (value == DEFAULT.value)    return DEFAULT;
+    else if (value == RESENDABLE.value) return RESENDABLE;
+    else if (value == IMPLICIT.value)   return IMPLICIT;
+    else                                return new ContentHint(value);
+  }
+
+  public int getValue() {
+    return value;
+  }
+
+  public boolean isKnown() {
+    return this == DEFAULT || this == RESENDABLE || this == IMPLICIT;
+  }
+
+  @Override
+  public boolean equals(Object other) {
+    if (!(other instanceof ContentHint)) return false;
+    return value == ((ContentHint)other).value;
+  }
+
+  @Override
+  public int hashCode() {
+    return value;
+  }
+
+  @Override
+  public String toString() {
+    return "ContentHint(" + value + ")";
+  }
+}
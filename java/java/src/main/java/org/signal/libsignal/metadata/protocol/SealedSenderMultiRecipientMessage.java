@@ -0,0 +1,178 @@
+package org.signal.libsignal.metadata.protocol;
+
+
+import com.google.protobuf.ByteString;
+import com.google.protobuf.InvalidProtocolBufferException;
+
+import org.signal.libsignal.metadata.InvalidMetadataMessageException;
+import org.signal.libsignal.metadata.InvalidMetadataVersionException;
+import org.signal.libsignal.metadata.SignalProtos;
+import org.whispersystems.libsignal.InvalidKeyException;
+import org.whispersystems.libsignal.SignalProtocolAddress;
+import org.whispersystems.libsignal.ecc.Curve;
+import org.whispersystems.libsignal.ecc.ECPublicKey;
+import org.whispersystems.libsignal.util.ByteUtil;
+
+import java.util.Collections;
+import java.util.HashSet;
+import java.util.LinkedHashMap;
+import java.util.LinkedList;
+import java.util.List;
+import java.util.Map;
+import java.util.Set;
+
+/**
+ * A sealed sender message for many recipients at once, as built by
+ * {@link org.signal.libsignal.metadata.SealedSessionCipher#multiRecipientEncrypt}.
+ *
+ * The content is encrypted once and shared; each recipient has a small header holding its own
+ * sealed copy of the sender's identity and of the content key. {@link #split()} turns this into
+ * the individual messages a server delivers.
+ */
+public class SealedSenderMultiRecipientMessage {
+
+  private static final int VERSION = 2;
+
+  private final ECPublicKey     ephemeral;
+  private final List<Recipient> recipients;
+  private final byte[]          encryptedMessage;
+  private final byte[]          serialized;
+
+  public SealedSenderMultiRecipientMessage(byte[] serialized)
+      throws InvalidMetadataMessageException, InvalidMetadataVersionException
+  {
+    try {
+      if (serialized.length < 1) {
+        throw new InvalidMetadataMessageException("Empty message");
+      }
+
+      int version = ByteUtil.highBitsToInt(serialized[0]);
+
+      if (version != VERSION) {
+        throw new InvalidMetadataVersionException("Unknown version: " + version);
+      }
+
+      SignalProtos.UnidentifiedSenderMultiRecipientMessage message = SignalProtos.UnidentifiedSenderMultiRecipientMessage.parseFrom(ByteString.copyFrom(serialized, 1, serialized.length - 1));
+
+      if (!message.hasEphemeralPublic() || !message.hasEncryptedMessage() || message.getRecipientsCount() == 0) {
+        throw new InvalidMetadataMessageException("Missing fields");
+      }
+
+      List<Recipient> recipients = new LinkedList<>();
+
+      for (SignalProtos.UnidentifiedSenderMultiRecipientMessage.Recipient recipient : message.getRecipientsList()) {
+        if (!recipient.hasAddress() || !recipient.hasDeviceId() || !recipient.hasEncryptedStatic() || !recipient.hasEncryptedMessageKey()) {
+          throw new InvalidMetadataMessageException("Missing recipient fields");
+        }
+
+        recipients.add(new Recipient(new SignalProtocolAddress(recipient.getAddress(), recipient.getDeviceId()),
+                                     recipient.getEncryptedStatic().toByteArray(),
+                                     recipient.getEncryptedMessageKey().toByteArray()));
+      }
+
+      this.ephemeral        = Curve.decodePoint(message.getEphemeralPublic().toByteArray(), 0);
+      this.recipients       = Collections.unmodifiableList(recipients);
+      this.encryptedMessage = message.getEncryptedMessage().toByteArray();
+      this.serialized       = serialized;
+
+      checkDistinct(this.recipients);
+    } catch (InvalidProtocolBufferException | InvalidKeyException | IllegalArgumentException e) {
+      throw new InvalidMetadataMessageException(e);
+    }
+  }
+
+  public SealedSenderMultiRecipientMessage(ECPublicKey ephemeral, List<Recipient> recipients, byte[] encryptedMessage)
+      throws InvalidMetadataMessageException
+  {
+    checkDistinct(recipients);
+
+    this.ephemeral        = ephemeral;
+    this.recipients       = Collections.unmodifiableList(new LinkedList<>(recipients));
+    this.encryptedMessage = encryptedMessage;
+
+    SignalProtos.UnidentifiedSenderMultiRecipientMessage.Builder builder = SignalProtos.UnidentifiedSenderMultiRecipientMessage.newBuilder()
+                                                                                                                               .setEphemeralPublic(ByteString.copyFrom(ephemeral.serialize()))
+                                                                                                                               .setEncryptedMessage(ByteString.copyFrom(encryptedMessage));
+
+    for (Recipient recipient : recipients) {
+      builder.addRecipients(SignalProtos.UnidentifiedSenderMultiRecipientMessage.Recipient.newBuilder()
+                                                                                          .setAddress(recipient.getAddress().getName())
+                                                                                          .setDeviceId(recipient.getAddress().getDeviceId())
+                                                                                          .setEncryptedStatic(ByteString.copyFrom(recipient.getEncryptedStatic()))
+                                                                                          .setEncryptedMessageKey(ByteString.copyFrom(recipient.getEncryptedMessageKey())));
+    }
+
+    byte[] versionBytes = {ByteUtil.intsToByteHighAndLow(VERSION, VERSION)};
+    byte[] messageBytes = builder.build().toByteArray();
+
+    this.serialized = ByteUtil.combine(versionBytes, messageBytes);
+  }
+
+  public ECPublicKey getEphemeral() {
+    return ephemeral;
+  }
+
+  public List<Recipient> getRecipients() {
+    return recipients;
+  }
+
+  public byte[] getEncryptedMessage() {
+    return encryptedMessage;
+  }
+
+  public byte[] getSerialized() {
+    return serialized;
+  }
+
+  /**
+   * Splits this message for delivery, without decrypting anything.
+   *
+   * @return each recipient's serialized {@link UnidentifiedSenderMessage}, in recipient order.
+   */
+  public Map<SignalProtocolAddress, byte[]> split() {
+    Map<SignalProtocolAddress, byte[]> messages = new LinkedHashMap<>();
+
+    for (Recipient recipient : recipients) {
+      messages.put(recipient.getAddress(), new UnidentifiedSenderMessage(ephemeral,
+                                                                         recipient.getEncryptedStatic(),
+                                                                         recipient.getEncryptedMessageKey(),
+                                                                         encryptedMessage).getSerialized());
+    }
+
+    return messages;
+  }
+
+  private static void checkDistinct(List<Recipient> recipients) throws InvalidMetadataMessageException {
+    Set<SignalProtocolAddress> seen = new HashSet<>();
+
+    for (Recipient recipient : recipients) {
+      if (!seen.add(recipient.getAddress())) {
+        throw new InvalidMetadataMessageException("Duplicate recipient: " + recipient.getAddress());
+      }
+    }
+  }
+
+  public static class Recipient {
+    private final SignalProtocolAddress address;
+    private final byte[]                encryptedStatic;
+    private final byte[]                encryptedMessageKey;
+
+    public Recipient(SignalProtocolAddress address, byte[] encryptedStatic, byte[] encryptedMessageKey) {
+      this.address             = address;
+      this.encryptedStatic     = encryptedStatic;
+      this.encryptedMessageKey = encryptedMessageKey;
+    }
+
+    public SignalProtocolAddress getAddress() {
+      return address;
+    }
+
+    public byte[] getEncryptedStatic() {
+      return encryptedStatic;
+    }
+
+    public byte[] getEncryptedMessageKey() {
+      return encryptedMessageKey;
+    }
+  }
+}
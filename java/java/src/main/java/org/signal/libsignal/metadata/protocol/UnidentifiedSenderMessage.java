@@ -13,16 +13,19 @@ import org.whispersystems.libsignal.InvalidVersionException;
 import org.whispersystems.libsignal.ecc.Curve;
 import org.whispersystems.libsignal.ecc.ECPublicKey;
 import org.whispersystems.libsignal.util.ByteUtil;
+import org.whispersystems.libsignal.util.guava.Optional;
 
 public class UnidentifiedSenderMessage {
 
-  private static final int CIPHERTEXT_VERSION = 1;
+  private static final int CIPHERTEXT_VERSION      = 1;
+  private static final int MULTI_RECIPIENT_VERSION = 2;
 
-  private final int         version;
-  private final ECPublicKey ephemeral;
-  private final byte[]      encryptedStatic;
-  private final byte[]      encryptedMessage;
-  private final byte[]      serialized;
+  private final int              version;
+  private final ECPublicKey      ephemeral;
+  private final byte[]           encryptedStatic;
+  private final byte[]           encryptedMessage;
+  private final Optional<byte[]> encryptedMessageKey;
+  private final byte[]           serialized;
 
   public UnidentifiedSenderMessage(byte[] serialized)
       throws InvalidMetadataMessageException, InvalidMetadataVersionException
@@ -30,7 +33,7 @@ public class UnidentifiedSenderMessage {
     try {
       this.version = ByteUtil.highBitsToInt(serialized[0]);
 
-      if (version > CIPHERTEXT_VERSION) {
+      if (version > MULTI_RECIPIENT_VERSION) {
         throw new InvalidMetadataVersionException("Unknown version: " + this.version);
       }
 
@@ -43,32 +46,59 @@ public class UnidentifiedSenderMessage {
         throw new InvalidMetadataMessageException("Missing fields");
       }
 
-      this.ephemeral        = Curve.decodePoint(unidentifiedSenderMessage.getEphemeralPublic().toByteArray(), 0);
-      this.encryptedStatic  = unidentifiedSenderMessage.getEncryptedStatic().toByteArray();
-      this.encryptedMessage = unidentifiedSenderMessage.getEncryptedMessage().toByteArray();
-      this.serialized       = serialized;
+      if (version == MULTI_RECIPIENT_VERSION && !unidentifiedSenderMessage.hasEncryptedMessageKey()) {
+        throw new InvalidMetadataMessageException("Missing message key");
+      }
+
+      this.ephemeral           = Curve.decodePoint(unidentifiedSenderMessage.getEphemeralPublic().toByteArray(), 0);
+      this.encryptedStatic     = unidentifiedSenderMessage.getEncryptedStatic().toByteArray();
+      this.encryptedMessage    = unidentifiedSenderMessage.getEncryptedMessage().toByteArray();
+      this.encryptedMessageKey = version == MULTI_RECIPIENT_VERSION ? Optional.of(unidentifiedSenderMessage.getEncryptedMessageKey().toByteArray())
+                                                                    : Optional.<byte[]>absent();
+      this.serialized          = serialized;
     } catch (InvalidProtocolBufferException | InvalidKeyException e) {
       throw new InvalidMetadataMessageException(e);
     }
   }
 
   public UnidentifiedSenderMessage(ECPublicKey ephemeral, byte[] encryptedStatic, byte[] encryptedMessage) {
-    this.version          = CIPHERTEXT_VERSION;
-    this.ephemeral        = ephemeral;
-    this.encryptedStatic  = encryptedStatic;
-    this.encryptedMessage = encryptedMessage;
-
-    byte[] versionBytes = {ByteUtil.intsToByteHighAndLow(CIPHERTEXT_VERSION, CIPHERTEXT_VERSION)};
-    byte[] messageBytes = SignalProtos.UnidentifiedSenderMessage.newBuilder()
-                                                                .setEncryptedMessage(ByteString.copyFrom(encryptedMessage))
-                                                                .setEncryptedStatic(ByteString.copyFrom(encryptedStatic))
-                                                                .setEphemeralPublic(ByteString.copyFrom(ephemeral.serialize()))
-                                                                .build()
-                                                                .toByteArray();
+    this(CIPHERTEXT_VERSION, ephemeral, encryptedStatic, encryptedMessage, Optional.<byte[]>absent());
+  }
+
+  /**
+   * Builds a version 2 message, whose {@code encryptedMessage} is shared with other recipients
+   * and is encrypted under the key sealed in {@code encryptedMessageKey}.
+   */
+  public UnidentifiedSenderMessage(ECPublicKey ephemeral, byte[] encryptedStatic, byte[] encryptedMessageKey, byte[] encryptedMessage) {
+    this(MULTI_RECIPIENT_VERSION, ephemeral, encryptedStatic, encryptedMessage, Optional.of(encryptedMessageKey));
+  }
+
+  private UnidentifiedSenderMessage(int version, ECPublicKey ephemeral, byte[] encryptedStatic, byte[] encryptedMessage, Optional<byte[]> encryptedMessageKey) {
+    this.version             = version;
+    this.ephemeral           = ephemeral;
+    this.encryptedStatic     = encryptedStatic;
+    this.encryptedMessage    = encryptedMessage;
+    this.encryptedMessageKey = encryptedMessageKey;
+
+    SignalProtos.UnidentifiedSenderMessage.Builder builder = SignalProtos.UnidentifiedSenderMessage.newBuilder()
+                                                                                                   .setEncryptedMessage(ByteString.copyFrom(encryptedMessage))
+                                                                                                   .setEncryptedStatic(ByteString.copyFrom(encryptedStatic))
+                                                                                                   .setEphemeralPublic(ByteString.copyFrom(ephemeral.serialize()));
+
+    if (encryptedMessageKey.isPresent()) {
+      builder.setEncryptedMessageKey(ByteString.copyFrom(encryptedMessageKey.get()));
+    }
+
+    byte[] versionBytes = {ByteUtil.intsToByteHighAndLow(version, version)};
+    byte[] messageBytes = builder.build().toByteArray();
 
     this.serialized = ByteUtil.combine(versionBytes, messageBytes);
   }
 
+  public int getVersion() {
+    return version;
+  }
+
   public ECPublicKey getEphemeral() {
     return ephemeral;
   }
@@ -81,6 +111,13 @@ public class UnidentifiedSenderMessage {
     return encryptedMessage;
   }
 
+  /**
+   * @return the sealed message key of a version 2 message, or absent for version 1.
+   */
+  public Optional<byte[]> getEncryptedMessageKey() {
+    return encryptedMessageKey;
+  }
+
   public byte[] getSerialized() {
     return serialized;
   }
@@ -10,12 +10,15 @@ import org.signal.libsignal.metadata.certificate.InvalidCertificateException;
 import org.signal.libsignal.metadata.certificate.SenderCertificate;
 import org.whispersystems.libsignal.InvalidMessageException;
 import org.whispersystems.libsignal.protocol.CiphertextMessage;
+import org.whispersystems.libsignal.util.guava.Optional;
 
 public class UnidentifiedSenderMessageContent {
 
   private final int               type;
   private final SenderCertificate senderCertificate;
   private final byte[]            content;
+  private final ContentHint       contentHint;
+  private final Optional<byte[]>  groupId;
   private final byte[]            serialized;
 
   public UnidentifiedSenderMessageContent(byte[] serialized) throws InvalidMetadataMessageException, InvalidCertificateException {
@@ -27,13 +30,16 @@ public class UnidentifiedSenderMessageContent {
       }
 
       switch (message.getType()) {
-        case MESSAGE:        this.type = CiphertextMessage.WHISPER_TYPE;        break;
-        case PREKEY_MESSAGE: this.type = CiphertextMessage.PREKEY_TYPE;         break;
+        case MESSAGE:           this.type = CiphertextMessage.WHISPER_TYPE;   break;
+        case PREKEY_MESSAGE:    this.type = CiphertextMessage.PREKEY_TYPE;    break;
+        case SENDERKEY_MESSAGE: this.type = CiphertextMessage.SENDERKEY_TYPE; break;
         default:             throw new InvalidMetadataMessageException("Unknown type: " + message.getType().getNumber());
       }
 
       this.senderCertificate = new SenderCertificate(message.getSenderCertificate().toByteArray());
       this.content           = message.getContent().toByteArray();
+      this.contentHint       = ContentHint.fromValue(message.getContentHint());
+      this.groupId           = message.hasGroupId() ? Optional.of(message.getGroupId().toByteArray()) : Optional.<byte[]>absent();
       this.serialized        = serialized;
     } catch (InvalidProtocolBufferException e) {
       throw new InvalidMetadataMessageException(e);
@@ -41,17 +47,31 @@ public class UnidentifiedSenderMessageContent {
   }
 
   public UnidentifiedSenderMessageContent(int type, SenderCertificate senderCertificate, byte[] content) {
+    this(type, senderCertificate, content, ContentHint.DEFAULT, Optional.<byte[]>absent());
+  }
+
+  public UnidentifiedSenderMessageContent(int type, SenderCertificate senderCertificate, byte[] content, ContentHint contentHint, Optional<byte[]> groupId) {
     try {
-      this.serialized = SignalProtos.UnidentifiedSenderMessage.Message.newBuilder()
-                                                                      .setType(SignalProtos.UnidentifiedSenderMessage.Message.Type.valueOf(getProtoType(type)))
-                                                                      .setSenderCertificate(SignalProtos.SenderCertificate.parseFrom(senderCertificate.getSerialized()))
-                                                                      .setContent(ByteString.copyFrom(content))
-                                                                      .build()
-                                                                      .toByteArray();
+      SignalProtos.UnidentifiedSenderMessage.Message.Builder builder = SignalProtos.UnidentifiedSenderMessage.Message.newBuilder()
+                                                                                                                     .setType(SignalProtos.UnidentifiedSenderMessage.Message.Type.valueOf(getProtoType(type)))
+                                                                                                                     .setSenderCertificate(SignalProtos.SenderCertificate.parseFrom(senderCertificate.getSerialized()))
+                                                                                                                     .setContent(ByteString.copyFrom(content));
+
+      if (!contentHint.equals(ContentHint.DEFAULT)) {
+        builder.setContentHint(contentHint.getValue());
+      }
+
+      if (groupId.isPresent()) {
+        builder.setGroupId(ByteString.copyFrom(groupId.get()));
+      }
+
+      this.serialized = builder.build().toByteArray();
 
       this.type = type;
       this.senderCertificate = senderCertificate;
       this.content = content;
+      this.contentHint = contentHint;
+      this.groupId = groupId;
     } catch (InvalidProtocolBufferException e) {
       throw new AssertionError(e);
     }
@@ -69,15 +89,24 @@ public class UnidentifiedSenderMessageContent {
     return content;
   }
 
+  public ContentHint getContentHint() {
+    return contentHint;
+  }
+
+  public Optional<byte[]> getGroupId() {
+    return groupId;
+  }
+
   public byte[] getSerialized() {
     return serialized;
   }
 
   private int getProtoType(int type) {
     switch (type) {
-      case CiphertextMessage.WHISPER_TYPE: return SignalProtos.UnidentifiedSenderMessage.Message.Type.MESSAGE_VALUE;
-      case CiphertextMessage.PREKEY_TYPE:  return SignalProtos.UnidentifiedSenderMessage.Message.Type.PREKEY_MESSAGE_VALUE;
-      default:                             throw new AssertionError(type);
+      case CiphertextMessage.WHISPER_TYPE:   return SignalProtos.UnidentifiedSenderMessage.Message.Type.MESSAGE_VALUE;
+      case CiphertextMessage.PREKEY_TYPE:    return SignalProtos.UnidentifiedSenderMessage.Message.Type.PREKEY_MESSAGE_VALUE;
+      case CiphertextMessage.SENDERKEY_TYPE: return SignalProtos.UnidentifiedSenderMessage.Message.Type.SENDERKEY_MESSAGE_VALUE;
+      default:                               throw new AssertionError(type);
     }
   }
 
@@ -6,7 +6,30 @@
 package org.whispersystems.libsignal;
 
 public class DuplicateMessageException extends Exception {
+  private final int chainIndex;
+  private final int counter;
+
   public DuplicateMessageException(String s) {
+    this(s, -1, -1);
+  }
+
+  public DuplicateMessageException(String s, int chainIndex, int counter) {
     super(s);
+    this.chainIndex = chainIndex;
+    this.counter    = counter;
+  }
+
+  /**
+   * @return The index the receiving chain had already reached, or -1 if unknown.
+   */
+  public int getChainIndex() {
+    return chainIndex;
+  }
+
+  /**
+   * @return The counter of the duplicated message, or -1 if unknown.
+   */
+  public int getCounter() {
+    return counter;
   }
 }
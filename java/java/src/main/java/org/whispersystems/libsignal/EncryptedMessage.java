@@ -0,0 +1,39 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal;
+
+/**
+ * A session message with what is needed to address it, from
+ * {@link SessionCipher#encryptWithDetails}.
+ */
+public class EncryptedMessage {
+  private final byte[] serialized;
+  private final int    type;
+  private final int    remoteRegistrationId;
+
+  // Called from native code.
+  EncryptedMessage(byte[] serialized, int type, int remoteRegistrationId) {
+    this.serialized           = serialized;
+    this.type                 = type;
+    this.remoteRegistrationId = remoteRegistrationId;
+  }
+
+  public byte[] serialize() {
+    return serialized;
+  }
+
+  /**
+   * @return {@link org.whispersystems.libsignal.protocol.CiphertextMessage#WHISPER_TYPE} or
+   *         {@link org.whispersystems.libsignal.protocol.CiphertextMessage#PREKEY_TYPE}.
+   */
+  public int getType() {
+    return type;
+  }
+
+  public int getRemoteRegistrationId() {
+    return remoteRegistrationId;
+  }
+}
@@ -0,0 +1,323 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal;
+
+import org.whispersystems.libsignal.state.IdentityKeyStore;
+import org.whispersystems.libsignal.state.PreKeyRecord;
+import org.whispersystems.libsignal.state.PreKeyStore;
+import org.whispersystems.libsignal.state.SessionRecord;
+import org.whispersystems.libsignal.state.SessionStore;
+import org.whispersystems.libsignal.state.SignedPreKeyRecord;
+import org.whispersystems.libsignal.state.SignedPreKeyStore;
+
+import java.util.List;
+import java.util.concurrent.Callable;
+import java.util.concurrent.ExecutionException;
+import java.util.concurrent.Executor;
+import java.util.concurrent.FutureTask;
+
+/**
+ * Stores that run every call on an {@link Executor}, for the asynchronous
+ * {@link SessionCipher} operations, whose native work happens on a thread the
+ * caller does not control.
+ *
+ * Each call blocks the native thread until the executor has run it.
+ */
+final class ExecutorStores {
+  private ExecutorStores() {}
+
+  private static <T> T callChecked(Executor executor, Callable<T> callable) throws Exception {
+    FutureTask<T> task = new FutureTask<T>(callable);
+    executor.execute(task);
+
+    try {
+      return task.get();
+    } catch (InterruptedException e) {
+      Thread.currentThread().interrupt();
+      throw new IllegalStateException(e);
+    } catch (ExecutionException e) {
+      Throwable cause = e.getCause();
+      if (cause instanceof Exception) {
+        throw (Exception) cause;
+      }
+      if (cause instanceof Error) {
+        throw (Error) cause;
+      }
+      throw new AssertionError(cause);
+    }
+  }
+
+  private static <T> T call(Executor executor, Callable<T> callable) {
+    try {
+      return callChecked(executor, callable);
+    } catch (RuntimeException e) {
+      throw e;
+    } catch (Exception e) {
+      throw new AssertionError(e);
+    }
+  }
+
+  private static <T> T callThrowingInvalidKeyId(Executor executor, Callable<T> callable)
+      throws InvalidKeyIdException
+  {
+    try {
+      return callChecked(executor, callable);
+    } catch (InvalidKeyIdException | RuntimeException e) {
+      throw e;
+    } catch (Exception e) {
+      throw new AssertionError(e);
+    }
+  }
+
+  static final class Sessions implements SessionStore {
+    private final SessionStore store;
+    private final Executor     executor;
+
+    Sessions(SessionStore store, Executor executor) {
+      this.store    = store;
+      this.executor = executor;
+    }
+
+    @Override
+    public SessionRecord loadSession(final SignalProtocolAddress address) {
+      return call(executor, new Callable<SessionRecord>() {
+        @Override
+        public SessionRecord call() {
+          return store.loadSession(address);
+        }
+      });
+    }
+
+    @Override
+    public List<Integer> getSubDeviceSessions(final String name) {
+      return call(executor, new Callable<List<Integer>>() {
+        @Override
+        public List<Integer> call() {
+          return store.getSubDeviceSessions(name);
+        }
+      });
+    }
+
+    @Override
+    public void storeSession(final SignalProtocolAddress address, final SessionRecord record) {
+      call(executor, new Callable<Void>() {
+        @Override
+        public Void call() {
+          store.storeSession(address, record);
+          return null;
+        }
+      });
+    }
+
+    @Override
+    public boolean containsSession(final SignalProtocolAddress address) {
+      return call(executor, new Callable<Boolean>() {
+        @Override
+        public Boolean call() {
+          return store.containsSession(address);
+        }
+      });
+    }
+
+    @Override
+    public void deleteSession(final SignalProtocolAddress address) {
+      call(executor, new Callable<Void>() {
+        @Override
+        public Void call() {
+          store.deleteSession(address);
+          return null;
+        }
+      });
+    }
+
+    @Override
+    public void deleteAllSessions(final String name) {
+      call(executor, new Callable<Void>() {
+        @Override
+        public Void call() {
+          store.deleteAllSessions(name);
+          return null;
+        }
+      });
+    }
+  }
+
+  static final class Identities implements IdentityKeyStore {
+    private final IdentityKeyStore store;
+    private final Executor         executor;
+
+    Identities(IdentityKeyStore store, Executor executor) {
+      this.store    = store;
+      this.executor = executor;
+    }
+
+    @Override
+    public IdentityKeyPair getIdentityKeyPair() {
+      return call(executor, new Callable<IdentityKeyPair>() {
+        @Override
+        public IdentityKeyPair call() {
+          return store.getIdentityKeyPair();
+        }
+      });
+    }
+
+    @Override
+    public int getLocalRegistrationId() {
+      return call(executor, new Callable<Integer>() {
+        @Override
+        public Integer call() {
+          return store.getLocalRegistrationId();
+        }
+      });
+    }
+
+    @Override
+    public IdentityChange saveIdentity(final SignalProtocolAddress address, final IdentityKey identityKey) {
+      return call(executor, new Callable<IdentityChange>() {
+        @Override
+        public IdentityChange call() {
+          return store.saveIdentity(address, identityKey);
+        }
+      });
+    }
+
+    @Override
+    public boolean isTrustedIdentity(final SignalProtocolAddress address, final IdentityKey identityKey,
+                                     final Direction direction)
+    {
+      return call(executor, new Callable<Boolean>() {
+        @Override
+        public Boolean call() {
+          return store.isTrustedIdentity(address, identityKey, direction);
+        }
+      });
+    }
+
+    @Override
+    public IdentityKey getIdentity(final SignalProtocolAddress address) {
+      return call(executor, new Callable<IdentityKey>() {
+        @Override
+        public IdentityKey call() {
+          return store.getIdentity(address);
+        }
+      });
+    }
+  }
+
+  static final class PreKeys implements PreKeyStore {
+    private final PreKeyStore store;
+    private final Executor    executor;
+
+    PreKeys(PreKeyStore store, Executor executor) {
+      this.store    = store;
+      this.executor = executor;
+    }
+
+    @Override
+    public PreKeyRecord loadPreKey(final int preKeyId) throws InvalidKeyIdException {
+      return callThrowingInvalidKeyId(executor, new Callable<PreKeyRecord>() {
+        @Override
+        public PreKeyRecord call() throws InvalidKeyIdException {
+          return store.loadPreKey(preKeyId);
+        }
+      });
+    }
+
+    @Override
+    public void storePreKey(final int preKeyId, final PreKeyRecord record) {
+      call(executor, new Callable<Void>() {
+        @Override
+        public Void call() {
+          store.storePreKey(preKeyId, record);
+          return null;
+        }
+      });
+    }
+
+    @Override
+    public boolean containsPreKey(final int preKeyId) {
+      return call(executor, new Callable<Boolean>() {
+        @Override
+        public Boolean call() {
+          return store.containsPreKey(preKeyId);
+        }
+      });
+    }
+
+    @Override
+    public void removePreKey(final int preKeyId) {
+      call(executor, new Callable<Void>() {
+        @Override
+        public Void call() {
+          store.removePreKey(preKeyId);
+          return null;
+        }
+      });
+    }
+  }
+
+  static final class SignedPreKeys implements SignedPreKeyStore {
+    private final SignedPreKeyStore store;
+    private final Executor          executor;
+
+    SignedPreKeys(SignedPreKeyStore store, Executor executor) {
+      this.store    = store;
+      this.executor = executor;
+    }
+
+    @Override
+    public SignedPreKeyRecord loadSignedPreKey(final int signedPreKeyId) throws InvalidKeyIdException {
+      return callThrowingInvalidKeyId(executor, new Callable<SignedPreKeyRecord>() {
+        @Override
+        public SignedPreKeyRecord call() throws InvalidKeyIdException {
+          return store.loadSignedPreKey(signedPreKeyId);
+        }
+      });
+    }
+
+    @Override
+    public List<SignedPreKeyRecord> loadSignedPreKeys() {
+      return call(executor, new Callable<List<SignedPreKeyRecord>>() {
+        @Override
+        public List<SignedPreKeyRecord> call() {
+          return store.loadSignedPreKeys();
+        }
+      });
+    }
+
+    @Override
+    public void storeSignedPreKey(final int signedPreKeyId, final SignedPreKeyRecord record) {
+      call(executor, new Callable<Void>() {
+        @Override
+        public Void call() {
+          store.storeSignedPreKey(signedPreKeyId, record);
+          return null;
+        }
+      });
+    }
+
+    @Override
+    public boolean containsSignedPreKey(final int signedPreKeyId) {
+      return call(executor, new Callable<Boolean>() {
+        @Override
+        public Boolean call() {
+          return store.containsSignedPreKey(signedPreKeyId);
+        }
+      });
+    }
+
+    @Override
+    public void removeSignedPreKey(final int signedPreKeyId) {
+      call(executor, new Callable<Void>() {
+        @Override
+        public Void call() {
+          store.removeSignedPreKey(signedPreKeyId);
+          return null;
+        }
+      });
+    }
+  }
+}
@@ -5,6 +5,7 @@
  */
 package org.whispersystems.libsignal;
 
+import org.signal.client.internal.Native;
 
 import org.whispersystems.libsignal.ecc.Curve;
 import org.whispersystems.libsignal.ecc.ECPublicKey;
@@ -40,6 +41,10 @@ public class IdentityKey {
     return publicKey.serialize();
   }
 
+  public boolean verifyAlternateIdentity(IdentityKey other, byte[] signature) {
+    return Native.IdentityKey_VerifyAlternateIdentity(this.nativeHandle(), other.nativeHandle(), signature);
+  }
+
   public String getFingerprint() {
     return Hex.toString(publicKey.serialize());
   }
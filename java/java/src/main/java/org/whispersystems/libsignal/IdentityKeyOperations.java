@@ -0,0 +1,28 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal;
+
+import org.whispersystems.libsignal.ecc.ECPublicKey;
+
+/**
+ * The private-key operations of an identity key that is kept outside the library, such as in a
+ * hardware keystore.
+ *
+ * Both methods may be called on any thread, including one the library is running a store
+ * callback on.
+ */
+public interface IdentityKeyOperations {
+  /**
+   * Returns an XEdDSA signature of {@code message}, as
+   * {@link org.whispersystems.libsignal.ecc.Curve#calculateSignature} would produce.
+   */
+  byte[] calculateSignature(byte[] message);
+
+  /**
+   * Returns the 32-byte X25519 agreement between the identity key and {@code theirKey}.
+   */
+  byte[] calculateAgreement(ECPublicKey theirKey);
+}
@@ -15,12 +15,26 @@ import org.whispersystems.libsignal.ecc.ECPrivateKey;
  * @author Moxie Marlinspike
  */
 public class IdentityKeyPair {
-  private final IdentityKey  publicKey;
-  private final ECPrivateKey privateKey;
+  private final IdentityKey           publicKey;
+  private final ECPrivateKey          privateKey;
+  private final IdentityKeyOperations operations;
 
   public IdentityKeyPair(IdentityKey publicKey, ECPrivateKey privateKey) {
     this.publicKey  = publicKey;
     this.privateKey = privateKey;
+    this.operations = null;
+  }
+
+  /**
+   * An identity key pair whose private key stays behind {@code operations}. Such a pair can be
+   * returned from {@link org.whispersystems.libsignal.state.IdentityKeyStore#getIdentityKeyPair}
+   * for session setup, but {@link #getPrivateKey}, {@link #signAlternateIdentity}, and
+   * serialization are not available.
+   */
+  public IdentityKeyPair(IdentityKey publicKey, IdentityKeyOperations operations) {
+    this.publicKey  = publicKey;
+    this.privateKey = null;
+    this.operations = operations;
   }
 
   public IdentityKey getPublicKey() {
@@ -28,10 +42,30 @@ public class IdentityKeyPair {
   }
 
   public ECPrivateKey getPrivateKey() {
+    return requirePrivateKey();
+  }
+
+  IdentityKeyOperations getOperations() {
+    return operations;
+  }
+
+  private ECPrivateKey requirePrivateKey() {
+    if (privateKey == null) {
+      throw new UnsupportedOperationException("private key is held by IdentityKeyOperations");
+    }
     return privateKey;
   }
 
+  /**
+   * Signs a statement that {@code other} is an alternate identity of the same user.
+   *
+   * @see IdentityKey#verifyAlternateIdentity
+   */
+  public byte[] signAlternateIdentity(IdentityKey other) {
+    return Native.IdentityKeyPair_SignAlternateIdentity(this.publicKey.nativeHandle(), requirePrivateKey().nativeHandle(), other.nativeHandle());
+  }
+
   byte[] serialize() {
-    return Native.IdentityKeyPair_Serialize(this.publicKey.nativeHandle(), this.privateKey.nativeHandle());
+    return Native.IdentityKeyPair_Serialize(this.publicKey.nativeHandle(), requirePrivateKey().nativeHandle());
   }
 }
@@ -0,0 +1,57 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal;
+
+import org.whispersystems.libsignal.state.IdentityKeyStore;
+import org.whispersystems.libsignal.util.guava.Optional;
+
+/**
+ * What {@link SessionBuilder#processWithReport} did with a
+ * {@link org.whispersystems.libsignal.state.PreKeyBundle}.
+ */
+public class PreKeyBundleReport {
+  private final Optional<Integer>               preKeyId;
+  private final int                             signedPreKeyId;
+  private final int                             sessionVersion;
+  private final IdentityKeyStore.IdentityChange identityChange;
+
+  // Called from native code.
+  PreKeyBundleReport(boolean hasPreKeyId, int preKeyId, int signedPreKeyId, int sessionVersion, int identityChange) {
+    this.preKeyId       = hasPreKeyId ? Optional.of(preKeyId) : Optional.<Integer>absent();
+    this.signedPreKeyId = signedPreKeyId;
+    this.sessionVersion = sessionVersion;
+
+    switch (identityChange) {
+      case 0:  this.identityChange = IdentityKeyStore.IdentityChange.NEW_IDENTITY;      break;
+      case 1:  this.identityChange = IdentityKeyStore.IdentityChange.REPLACED_EXISTING; break;
+      case 2:  this.identityChange = IdentityKeyStore.IdentityChange.UNCHANGED;         break;
+      default: throw new AssertionError("unexpected identity change " + identityChange);
+    }
+  }
+
+  /**
+   * @return the one-time pre-key the session was built with, if the bundle had one.
+   */
+  public Optional<Integer> getPreKeyId() {
+    return preKeyId;
+  }
+
+  public int getSignedPreKeyId() {
+    return signedPreKeyId;
+  }
+
+  public int getSessionVersion() {
+    return sessionVersion;
+  }
+
+  /**
+   * @return how the recipient's identity key was saved; {@link IdentityKeyStore.IdentityChange#REPLACED_EXISTING}
+   *         means their safety number has changed.
+   */
+  public IdentityKeyStore.IdentityChange getIdentityChange() {
+    return identityChange;
+  }
+}
@@ -0,0 +1,91 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal;
+
+import java.nio.ByteBuffer;
+import java.util.UUID;
+
+/**
+ * An account identifier, tagged with the kind of identity it belongs to.
+ */
+public final class ServiceId {
+
+  public enum Kind {
+    /** An account's primary identity. */
+    ACI,
+    /** An account's phone number identity. */
+    PNI
+  }
+
+  private static final int BINARY_LENGTH = 17;
+
+  private final Kind kind;
+  private final UUID uuid;
+
+  public ServiceId(Kind kind, UUID uuid) {
+    if (kind == null || uuid == null) {
+      throw new NullPointerException();
+    }
+
+    this.kind = kind;
+    this.uuid = uuid;
+  }
+
+  public static ServiceId aci(UUID uuid) {
+    return new ServiceId(Kind.ACI, uuid);
+  }
+
+  public static ServiceId pni(UUID uuid) {
+    return new ServiceId(Kind.PNI, uuid);
+  }
+
+  public Kind getKind() {
+    return kind;
+  }
+
+  public UUID getUuid() {
+    return uuid;
+  }
+
+  /**
+   * @return The 17-byte fixed-width encoding: a kind byte (0 for ACI, 1 for PNI) followed by the UUID.
+   */
+  public byte[] toServiceIdBinary() {
+    return ByteBuffer.allocate(BINARY_LENGTH)
+                     .put((byte) kind.ordinal())
+                     .putLong(uuid.getMostSignificantBits())
+                     .putLong(uuid.getLeastSignificantBits())
+                     .array();
+  }
+
+  static ServiceId fromServiceIdBinary(byte[] serialized) {
+    if (serialized.length != BINARY_LENGTH) {
+      throw new IllegalArgumentException("service ID must be " + BINARY_LENGTH + " bytes");
+    }
+
+    ByteBuffer buffer = ByteBuffer.wrap(serialized);
+    Kind       kind   = Kind.values()[buffer.get()];
+    return new ServiceId(kind, new UUID(buffer.getLong(), buffer.getLong()));
+  }
+
+  @Override
+  public String toString() {
+    return kind == Kind.PNI ? "PNI:" + uuid : uuid.toString();
+  }
+
+  @Override
+  public boolean equals(Object other) {
+    if (!(other instanceof ServiceId)) return false;
+
+    ServiceId that = (ServiceId)other;
+    return this.kind == that.kind && this.uuid.equals(that.uuid);
+  }
+
+  @Override
+  public int hashCode() {
+    return kind.hashCode() ^ uuid.hashCode();
+  }
+}
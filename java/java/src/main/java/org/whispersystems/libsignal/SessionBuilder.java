@@ -76,18 +76,36 @@ public class SessionBuilder {
    * a server.
    *
    * @param preKey A PreKey for the destination recipient, retrieved from a server.
+   * @return how the recipient's identity key was saved; {@link IdentityKeyStore.IdentityChange#REPLACED_EXISTING}
+   *         means their safety number has changed.
    * @throws InvalidKeyException when the {@link org.whispersystems.libsignal.state.PreKeyBundle} is
    *                             badly formatted.
    * @throws org.whispersystems.libsignal.UntrustedIdentityException when the sender's
    *                                                                  {@link IdentityKey} is not
    *                                                                  trusted.
    */
-  public void process(PreKeyBundle preKey) throws InvalidKeyException, UntrustedIdentityException {
+  public IdentityKeyStore.IdentityChange process(PreKeyBundle preKey) throws InvalidKeyException, UntrustedIdentityException {
+    return processWithReport(preKey).getIdentityChange();
+  }
+
+  /**
+   * Build a new session from a {@link org.whispersystems.libsignal.state.PreKeyBundle}, as
+   * {@link #process(PreKeyBundle)} does, and report which of the bundle's keys were used.
+   *
+   * @param preKey A PreKey for the destination recipient, retrieved from a server.
+   * @return the pre-keys the session was built with, and how the recipient's identity key was saved.
+   * @throws InvalidKeyException when the {@link org.whispersystems.libsignal.state.PreKeyBundle} is
+   *                             badly formatted.
+   * @throws org.whispersystems.libsignal.UntrustedIdentityException when the sender's
+   *                                                                  {@link IdentityKey} is not
+   *                                                                  trusted.
+   */
+  public PreKeyBundleReport processWithReport(PreKeyBundle preKey) throws InvalidKeyException, UntrustedIdentityException {
     synchronized (SessionCipher.SESSION_LOCK) {
-      Native.SessionBuilder_ProcessPreKeyBundle(preKey.nativeHandle(),
-                          remoteAddress.nativeHandle(),
-                          sessionStore,
-                          identityKeyStore);
+      return Native.SessionBuilder_ProcessPreKeyBundle(preKey.nativeHandle(),
+                 remoteAddress.nativeHandle(),
+                 sessionStore,
+                 identityKeyStore);
     }
   }
 
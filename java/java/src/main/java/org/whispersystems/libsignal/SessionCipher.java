@@ -5,6 +5,8 @@
  */
 package org.whispersystems.libsignal;
 
+import androidx.annotation.RequiresApi;
+
 import org.signal.client.internal.Native;
 
 import org.whispersystems.libsignal.ecc.ECPublicKey;
@@ -20,6 +22,8 @@ import org.whispersystems.libsignal.state.SignedPreKeyStore;
 
 import java.security.InvalidAlgorithmParameterException;
 import java.security.NoSuchAlgorithmException;
+import java.util.concurrent.CompletableFuture;
+import java.util.concurrent.Executor;
 
 /**
  * The main entry point for Signal Protocol encrypt/decrypt operations.
@@ -77,6 +81,22 @@ public class SessionCipher {
     }
   }
 
+  /**
+   * Encrypt a message, also returning the recipient's registration id from the
+   * session that was used.
+   *
+   * @param  paddedMessage The plaintext message bytes, optionally padded to a constant multiple.
+   * @return The serialized message with its type and the recipient's registration id.
+   */
+  public EncryptedMessage encryptWithDetails(byte[] paddedMessage) throws UntrustedIdentityException {
+    synchronized (SESSION_LOCK) {
+      return Native.SessionCipher_EncryptMessageWithDetails(paddedMessage,
+                                                            this.remoteAddress.nativeHandle(),
+                                                            sessionStore,
+                                                            identityKeyStore);
+    }
+  }
+
   /**
    * Decrypt a message.
    *
@@ -130,6 +150,64 @@ public class SessionCipher {
     }
   }
 
+  /**
+   * Encrypt a message without blocking the calling thread.
+   *
+   * The work runs on a native thread, and every call to the stores runs on
+   * {@code executor}. The native thread holds {@link #SESSION_LOCK} while it
+   * works, just as {@link #encrypt(byte[])} does, so {@code executor} must not
+   * run anything that waits for that lock, such as a blocking
+   * {@link SessionCipher} call.
+   *
+   * @return A future for the ciphertext message, completed exceptionally with
+   *         the exception {@link #encrypt(byte[])} would have thrown.
+   */
+  @RequiresApi(24)
+  @SuppressWarnings("unchecked")
+  public CompletableFuture<CiphertextMessage> encryptAsync(byte[] paddedMessage, Executor executor) {
+    return Native.SessionCipher_EncryptMessageAsync(paddedMessage,
+                                                    remoteAddress.nativeHandle(),
+                                                    new ExecutorStores.Sessions(sessionStore, executor),
+                                                    new ExecutorStores.Identities(identityKeyStore, executor),
+                                                    SESSION_LOCK);
+  }
+
+  /**
+   * Decrypt a message without blocking the calling thread; see
+   * {@link #encryptAsync}.
+   *
+   * @return A future for the plaintext, completed exceptionally with the
+   *         exception {@link #decrypt(PreKeySignalMessage)} would have thrown.
+   */
+  @RequiresApi(24)
+  @SuppressWarnings("unchecked")
+  public CompletableFuture<byte[]> decryptAsync(PreKeySignalMessage ciphertext, Executor executor) {
+    return Native.SessionCipher_DecryptPreKeySignalMessageAsync(ciphertext.nativeHandle(),
+                                                                remoteAddress.nativeHandle(),
+                                                                new ExecutorStores.Sessions(sessionStore, executor),
+                                                                new ExecutorStores.Identities(identityKeyStore, executor),
+                                                                new ExecutorStores.PreKeys(preKeyStore, executor),
+                                                                new ExecutorStores.SignedPreKeys(signedPreKeyStore, executor),
+                                                                SESSION_LOCK);
+  }
+
+  /**
+   * Decrypt a message without blocking the calling thread; see
+   * {@link #encryptAsync}.
+   *
+   * @return A future for the plaintext, completed exceptionally with the
+   *         exception {@link #decrypt(SignalMessage)} would have thrown.
+   */
+  @RequiresApi(24)
+  @SuppressWarnings("unchecked")
+  public CompletableFuture<byte[]> decryptAsync(SignalMessage ciphertext, Executor executor) {
+    return Native.SessionCipher_DecryptSignalMessageAsync(ciphertext.nativeHandle(),
+                                                          remoteAddress.nativeHandle(),
+                                                          new ExecutorStores.Sessions(sessionStore, executor),
+                                                          new ExecutorStores.Identities(identityKeyStore, executor),
+                                                          SESSION_LOCK);
+  }
+
   public int getRemoteRegistrationId() {
     synchronized (SESSION_LOCK) {
       SessionRecord record = sessionStore.loadSession(remoteAddress);
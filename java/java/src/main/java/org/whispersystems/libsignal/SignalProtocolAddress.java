@@ -6,14 +6,27 @@
 package org.whispersystems.libsignal;
 
 import org.signal.client.internal.Native;
+import org.whispersystems.libsignal.util.guava.Optional;
 
 public class SignalProtocolAddress {
   private final long handle;
 
+  /**
+   * @throws IllegalArgumentException if {@code deviceId} is not between 1 and 127.
+   */
   public SignalProtocolAddress(String name, int deviceId) {
     this.handle = Native.ProtocolAddress_New(name, deviceId);
   }
 
+  /**
+   * Create an address whose name is the canonical string form of a service ID.
+   *
+   * @throws IllegalArgumentException if {@code deviceId} is not between 1 and 127.
+   */
+  public SignalProtocolAddress(ServiceId serviceId, int deviceId) {
+    this.handle = Native.ProtocolAddress_NewFromServiceId(serviceId.toServiceIdBinary(), deviceId);
+  }
+
   @Override
   protected void finalize() {
     Native.ProtocolAddress_Destroy(this.handle);
@@ -27,6 +40,19 @@ public class SignalProtocolAddress {
     return Native.ProtocolAddress_DeviceId(this.handle);
   }
 
+  /**
+   * @return The service ID named by this address, or absent if the name is not a service ID.
+   */
+  public Optional<ServiceId> getServiceId() {
+    byte[] serviceId = Native.ProtocolAddress_ServiceId(this.handle);
+
+    if (serviceId == null) {
+      return Optional.absent();
+    }
+
+    return Optional.of(ServiceId.fromServiceIdBinary(serviceId));
+  }
+
   @Override
   public String toString() {
     return getName() + ":" + getDeviceId();
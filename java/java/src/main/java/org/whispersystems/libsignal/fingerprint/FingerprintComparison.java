@@ -0,0 +1,20 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.fingerprint;
+
+/**
+ * Why a scanned fingerprint did or did not match.
+ *
+ * Scanning a fingerprint with a different version is reported separately, as a
+ * {@link FingerprintVersionMismatchException}.
+ */
+public enum FingerprintComparison {
+  MATCH,
+  /** The scanning party sees a different identity key for us than our own. */
+  LOCAL_MISMATCH,
+  /** The scanning party's identity key is not the one we have for them. */
+  REMOTE_MISMATCH
+}
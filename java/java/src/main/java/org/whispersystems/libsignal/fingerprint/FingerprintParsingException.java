@@ -11,4 +11,8 @@ public class FingerprintParsingException extends Exception {
     super(nested);
   }
 
+  public FingerprintParsingException(String message) {
+    super(message);
+  }
+
 }
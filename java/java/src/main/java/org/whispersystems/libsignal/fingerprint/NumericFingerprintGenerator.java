@@ -9,6 +9,8 @@ import org.signal.client.internal.Native;
 import org.whispersystems.libsignal.IdentityKey;
 
 public class NumericFingerprintGenerator implements FingerprintGenerator {
+  public static final int MULTI_IDENTIFIER_VERSION = 3;
+
   private final int iterations;
 
   /**
@@ -23,6 +25,8 @@ public class NumericFingerprintGenerator implements FingerprintGenerator {
    *                   - 1024 ~ 109.7 bits
    *                   - 1400 > 110 bits
    *                   - 5200 > 112 bits
+   *
+   *                   Counts below 1000 are rejected when a fingerprint is created.
    */
   public NumericFingerprintGenerator(int iterations) {
     this.iterations = iterations;
@@ -51,6 +55,35 @@ public class NumericFingerprintGenerator implements FingerprintGenerator {
                       remoteStableIdentifier,
                       remoteIdentityKey.serialize());
 
+    return consumeFingerprint(handle);
+  }
+
+  /**
+   * Generate a scannable and displayable fingerprint over several identifiers per party, such as
+   * a UUID and an E164. The identifiers may be given in any order.
+   *
+   * The scannable fingerprint always has version {@link #MULTI_IDENTIFIER_VERSION}.
+   *
+   * @param localIdentifiers The client's identifiers.
+   * @param localIdentityKey The client's identity key.
+   * @param remoteIdentifiers The remote party's identifiers.
+   * @param remoteIdentityKey The remote party's identity key.
+   * @return A unique fingerprint for this conversation.
+   */
+  public Fingerprint createMultiFor(byte[][] localIdentifiers,
+                                    final IdentityKey localIdentityKey,
+                                    byte[][] remoteIdentifiers,
+                                    final IdentityKey remoteIdentityKey) {
+    long handle = Native.NumericFingerprintGenerator_NewMulti(this.iterations,
+                      localIdentifiers,
+                      localIdentityKey.serialize(),
+                      remoteIdentifiers,
+                      remoteIdentityKey.serialize());
+
+    return consumeFingerprint(handle);
+  }
+
+  private static Fingerprint consumeFingerprint(long handle) {
     DisplayableFingerprint displayableFingerprint = new DisplayableFingerprint(Native.NumericFingerprintGenerator_GetDisplayString(handle));
 
     ScannableFingerprint scannableFingerprint = new ScannableFingerprint(Native.NumericFingerprintGenerator_GetScannableEncoding(handle));
@@ -60,4 +93,24 @@ public class NumericFingerprintGenerator implements FingerprintGenerator {
     return new Fingerprint(displayableFingerprint, scannableFingerprint);
   }
 
+  /**
+   * Generate only the displayable half of a fingerprint, skipping the scannable encoding.
+   *
+   * @param localStableIdentifier The client's "stable" identifier.
+   * @param localIdentityKey The client's identity key.
+   * @param remoteStableIdentifier The remote party's "stable" identifier.
+   * @param remoteIdentityKey The remote party's identity key.
+   * @return The same displayable fingerprint {@link #createFor} would produce.
+   */
+  public DisplayableFingerprint createDisplayableFor(byte[] localStableIdentifier,
+                                                     final IdentityKey localIdentityKey,
+                                                     byte[] remoteStableIdentifier,
+                                                     final IdentityKey remoteIdentityKey) {
+    return new DisplayableFingerprint(Native.NumericFingerprintGenerator_NewDisplayable(this.iterations,
+                                          localStableIdentifier,
+                                          localIdentityKey.serialize(),
+                                          remoteStableIdentifier,
+                                          remoteIdentityKey.serialize()));
+  }
+
 }
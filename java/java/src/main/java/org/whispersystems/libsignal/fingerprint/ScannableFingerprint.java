@@ -32,6 +32,29 @@ public class ScannableFingerprint {
       throws FingerprintVersionMismatchException,
              FingerprintParsingException
   {
-    return Native.ScannableFingerprint_Compare(this.encodedFingerprint, scannedFingerprintData);
+    return compareScanned(scannedFingerprintData) == FingerprintComparison.MATCH;
+  }
+
+  /**
+   * Compare a scanned QR code with what we expect, reporting why they differ.
+   *
+   * @param scannedFingerprintData The scanned data
+   * @return Whether the fingerprints match, and if not, which half differs.
+   * @throws FingerprintVersionMismatchException if the scanned fingerprint is the wrong version.
+   * @throws FingerprintParsingException if the scanned data is corrupted or incomplete.
+   */
+  public FingerprintComparison compareScanned(byte[] scannedFingerprintData)
+      throws FingerprintVersionMismatchException,
+             FingerprintParsingException
+  {
+    int[] result = Native.ScannableFingerprint_CompareScanned(this.encodedFingerprint, scannedFingerprintData);
+
+    switch (result[0]) {
+      case 0:  return FingerprintComparison.MATCH;
+      case 1:  return FingerprintComparison.LOCAL_MISMATCH;
+      case 2:  return FingerprintComparison.REMOTE_MISMATCH;
+      case 3:  throw new FingerprintVersionMismatchException(result[2], result[1]);
+      default: throw new AssertionError("unexpected comparison result " + result[0]);
+    }
   }
 }
@@ -55,6 +55,23 @@ public class GroupCipher {
     }
   }
 
+  /**
+   * Encrypt a message, reporting the chain ID and iteration it was encrypted with.
+   *
+   * @param paddedPlaintext The plaintext message bytes, optionally padded.
+   * @return Ciphertext, with the chain position used.
+   * @throws NoSessionException
+   */
+  public GroupEncryptedMessage encryptWithMetadata(byte[] paddedPlaintext) throws NoSessionException {
+    synchronized (LOCK) {
+    try {
+      return Native.GroupCipher_EncryptMessageWithMetadata(this.senderKeyId.nativeHandle(), paddedPlaintext, this.senderKeyStore);
+    } catch (IllegalStateException e) {
+      throw new NoSessionException(e);
+    }
+    }
+  }
+
   /**
    * Decrypt a SenderKey group message.
    *
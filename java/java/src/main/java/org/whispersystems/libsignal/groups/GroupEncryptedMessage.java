@@ -0,0 +1,38 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.groups;
+
+/**
+ * A serialized SenderKeyMessage along with the chain key position it was encrypted at, from
+ * {@link GroupCipher#encryptWithMetadata}.
+ */
+public class GroupEncryptedMessage {
+  private final byte[] serialized;
+  private final int    chainId;
+  private final int    iteration;
+
+  // Called from native code.
+  GroupEncryptedMessage(byte[] serialized, int chainId, int iteration) {
+    this.serialized = serialized;
+    this.chainId    = chainId;
+    this.iteration  = iteration;
+  }
+
+  public byte[] serialize() {
+    return serialized;
+  }
+
+  public int getChainId() {
+    return chainId;
+  }
+
+  /**
+   * @return The message's position in its chain, which advances by one with each message.
+   */
+  public int getIteration() {
+    return iteration;
+  }
+}
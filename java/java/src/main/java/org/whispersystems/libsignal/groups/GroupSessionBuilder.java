@@ -6,6 +6,7 @@
 package org.whispersystems.libsignal.groups;
 
 import org.signal.client.internal.Native;
+import org.whispersystems.libsignal.InvalidKeyIdException;
 import org.whispersystems.libsignal.groups.state.SenderKeyStore;
 import org.whispersystems.libsignal.protocol.SenderKeyDistributionMessage;
 
@@ -46,6 +47,26 @@ public class GroupSessionBuilder {
     }
   }
 
+  /**
+   * Construct a group session for receiving messages from senderKeyName, provided the
+   * SenderKeyDistributionMessage is for the expected sender key.
+   *
+   * @param senderKeyName The (groupId, senderId, deviceId) tuple associated with the SenderKeyDistributionMessage.
+   * @param senderKeyDistributionMessage A received SenderKeyDistributionMessage.
+   * @param expectedId The sender key ID the message must carry.
+   * @throws InvalidKeyIdException if the message is for a different sender key.
+   */
+  public void process(SenderKeyName senderKeyName, SenderKeyDistributionMessage senderKeyDistributionMessage, int expectedId)
+      throws InvalidKeyIdException
+  {
+    synchronized (GroupCipher.LOCK) {
+       Native.GroupSessionBuilder_ProcessSenderKeyDistributionMessageWithExpectedId(senderKeyName.nativeHandle(),
+                                           senderKeyDistributionMessage.nativeHandle(),
+                                           expectedId,
+                                           senderKeyStore);
+    }
+  }
+
   /**
    * Construct a group session for sending messages.
    *
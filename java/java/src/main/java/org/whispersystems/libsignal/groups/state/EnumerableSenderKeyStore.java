@@ -0,0 +1,33 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.groups.state;
+
+import org.whispersystems.libsignal.SignalProtocolAddress;
+
+/**
+ * A {@link SenderKeyStore} that can list and forget the sender keys it has saved for a group.
+ *
+ * Enumeration is optional because not every store can do it cheaply; asking a store that doesn't
+ * implement this interface to do so throws {@link UnsupportedOperationException}.
+ */
+public interface EnumerableSenderKeyStore extends SenderKeyStore {
+
+  /**
+   * Return every sender that has a sender key saved for a group.
+   *
+   * @param groupId The group.
+   * @return the senders, in no particular order.
+   */
+  public SignalProtocolAddress[] getSendersForGroup(String groupId);
+
+  /**
+   * Forget every sender key saved for a group, such as when the group is destroyed.
+   *
+   * @param groupId The group.
+   */
+  public void deleteSenderKeysForGroup(String groupId);
+
+}
@@ -6,6 +6,7 @@
 package org.whispersystems.libsignal.groups.state;
 
 import org.signal.client.internal.Native;
+import org.whispersystems.libsignal.util.guava.Optional;
 import java.io.IOException;
 
 /**
@@ -38,6 +39,26 @@ public class SenderKeyRecord {
     return Native.SenderKeyRecord_GetSerialized(this.handle);
   }
 
+  public int getStateCount() {
+    return Native.SenderKeyRecord_GetStateCount(this.handle);
+  }
+
+  /**
+   * @return the sender key IDs of this record's states, most recent first.
+   */
+  public int[] getChainIds() {
+    return Native.SenderKeyRecord_GetChainIds(this.handle);
+  }
+
+  /**
+   * @return the iteration of the next message key the given chain will produce, or absent if
+   *         this record has no chain with that ID.
+   */
+  public Optional<Integer> getMaxIterationForChain(int chainId) {
+    int iteration = Native.SenderKeyRecord_GetMaxIterationForChain(this.handle, chainId);
+    return iteration < 0 ? Optional.<Integer>absent() : Optional.of(iteration);
+  }
+
   public long nativeHandle() {
     return this.handle;
   }
@@ -13,6 +13,7 @@ public interface CiphertextMessage {
   public static final int PREKEY_TYPE                 = 3;
   public static final int SENDERKEY_TYPE              = 4;
   public static final int SENDERKEY_DISTRIBUTION_TYPE = 5;
+  public static final int PLAINTEXT_CONTENT_TYPE      = 8;
 
   // This should be the worst case (worse than V2).  So not always accurate, but good enough for padding.
   public static final int ENCRYPTED_MESSAGE_OVERHEAD = 53;
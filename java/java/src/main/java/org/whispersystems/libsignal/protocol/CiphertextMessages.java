@@ -0,0 +1,30 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.protocol;
+
+import org.signal.client.internal.Native;
+
+import org.whispersystems.libsignal.InvalidMessageException;
+import org.whispersystems.libsignal.LegacyMessageException;
+
+public final class CiphertextMessages {
+
+  private CiphertextMessages() {}
+
+  /**
+   * Parses a message received with envelope type {@code type}, such as
+   * {@link CiphertextMessage#WHISPER_TYPE}.
+   *
+   * @return The {@link SignalMessage}, {@link PreKeySignalMessage}, {@link SenderKeyMessage},
+   *         {@link SenderKeyDistributionMessage}, or {@link PlaintextContent} for {@code type}.
+   * @throws InvalidMessageException if {@code type} is unknown or the message is malformed.
+   */
+  public static CiphertextMessage deserialize(int type, byte[] serialized)
+      throws InvalidMessageException, LegacyMessageException
+  {
+    return Native.CiphertextMessage_Deserialize(type, serialized);
+  }
+}
@@ -0,0 +1,86 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.protocol;
+
+import org.signal.client.internal.Native;
+
+import org.whispersystems.libsignal.InvalidMessageException;
+import org.whispersystems.libsignal.LegacyMessageException;
+import org.whispersystems.libsignal.ecc.ECPublicKey;
+import org.whispersystems.libsignal.util.guava.Optional;
+
+/**
+ * Asks the sender of a message that could not be decrypted to reset the session it was sent on.
+ *
+ * Send it back to the original sender wrapped in a {@link PlaintextContent}.
+ */
+public final class DecryptionErrorMessage {
+
+  private final long handle;
+
+  @Override
+  protected void finalize() {
+     Native.DecryptionErrorMessage_Destroy(this.handle);
+  }
+
+  DecryptionErrorMessage(long handle) {
+    this.handle = handle;
+  }
+
+  public DecryptionErrorMessage(byte[] serialized) throws InvalidMessageException {
+    handle = Native.DecryptionErrorMessage_Deserialize(serialized);
+  }
+
+  /**
+   * @param originalBytes the serialized message that failed to decrypt.
+   * @param messageType the {@link CiphertextMessage} type of that message.
+   * @param timestamp the timestamp of that message.
+   * @param originalSenderDeviceId the device the message was sent from.
+   */
+  public static DecryptionErrorMessage forOriginalMessage(byte[] originalBytes, int messageType, long timestamp, int originalSenderDeviceId)
+      throws InvalidMessageException, LegacyMessageException
+  {
+    return new DecryptionErrorMessage(
+        Native.DecryptionErrorMessage_ForOriginalMessage(originalBytes, messageType, timestamp, originalSenderDeviceId));
+  }
+
+  /**
+   * Extracts a message from the body of a {@link PlaintextContent}.
+   */
+  public static DecryptionErrorMessage extractFromSerializedContent(byte[] serializedContentBytes)
+      throws InvalidMessageException
+  {
+    return new DecryptionErrorMessage(
+        Native.DecryptionErrorMessage_ExtractFromSerializedContent(serializedContentBytes));
+  }
+
+  public byte[] serialize() {
+    return Native.DecryptionErrorMessage_GetSerialized(this.handle);
+  }
+
+  /**
+   * The sender's ratchet key from the original message, or absent for sender key messages.
+   */
+  public Optional<ECPublicKey> getRatchetKey() {
+    long keyHandle = Native.DecryptionErrorMessage_GetRatchetKey(this.handle);
+    if (keyHandle == 0) {
+      return Optional.absent();
+    }
+    return Optional.of(new ECPublicKey(keyHandle));
+  }
+
+  public long getTimestamp() {
+    return Native.DecryptionErrorMessage_GetTimestamp(this.handle);
+  }
+
+  public int getDeviceId() {
+    return Native.DecryptionErrorMessage_GetDeviceId(this.handle);
+  }
+
+  public long nativeHandle() {
+    return this.handle;
+  }
+}
@@ -0,0 +1,57 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.protocol;
+
+import org.signal.client.internal.Native;
+
+import org.whispersystems.libsignal.InvalidMessageException;
+
+/**
+ * Content sent without encryption or padding, such as a {@link DecryptionErrorMessage}.
+ */
+public final class PlaintextContent implements CiphertextMessage {
+
+  private final long handle;
+
+  @Override
+  protected void finalize() {
+     Native.PlaintextContent_Destroy(this.handle);
+  }
+
+  public PlaintextContent(DecryptionErrorMessage message) {
+    handle = Native.PlaintextContent_FromDecryptionErrorMessage(message.nativeHandle());
+  }
+
+  public PlaintextContent(byte[] serialized) throws InvalidMessageException {
+    handle = Native.PlaintextContent_Deserialize(serialized);
+  }
+
+  // Called from native code.
+  PlaintextContent(long handle) {
+    this.handle = handle;
+  }
+
+  @Override
+  public byte[] serialize() {
+    return Native.PlaintextContent_GetSerialized(this.handle);
+  }
+
+  @Override
+  public int getType() {
+    return PLAINTEXT_CONTENT_TYPE;
+  }
+
+  /**
+   * The content to hand to the application, as if it had been decrypted.
+   */
+  public byte[] getBody() {
+    return Native.PlaintextContent_GetBody(this.handle);
+  }
+
+  public long nativeHandle() {
+    return this.handle;
+  }
+}
@@ -0,0 +1,31 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.state;
+
+/**
+ * A {@link PreKeyStore} that can store and remove many records in a single operation.
+ *
+ * Stores that don't implement this interface have batch operations performed one record at a
+ * time.
+ */
+public interface BatchPreKeyStore extends PreKeyStore {
+
+  /**
+   * Store several local PreKeyRecords.
+   *
+   * @param preKeyIds the IDs of the PreKeyRecords to store.
+   * @param records the PreKeyRecords, in the same order as their IDs.
+   */
+  public void storePreKeys(int[] preKeyIds, PreKeyRecord[] records);
+
+  /**
+   * Delete several PreKeyRecords from local storage.
+   *
+   * @param preKeyIds The IDs of the PreKeyRecords to remove.
+   */
+  public void removePreKeys(int[] preKeyIds);
+
+}
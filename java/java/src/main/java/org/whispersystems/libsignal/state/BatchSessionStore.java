@@ -0,0 +1,26 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.state;
+
+import org.whispersystems.libsignal.SignalProtocolAddress;
+
+/**
+ * A {@link SessionStore} that can load the sessions for many addresses in a single operation.
+ *
+ * Stores that don't implement this interface have batch loads performed one address at a time.
+ */
+public interface BatchSessionStore extends SessionStore {
+
+  /**
+   * Returns copies of the existing SessionRecords for several remote clients.
+   *
+   * @param addresses The names and device IDs of the remote clients.
+   * @return the SessionRecords, in the same order as the addresses, with a null entry for any
+   *         address that does not have a session.
+   */
+  public SessionRecord[] loadSessions(SignalProtocolAddress[] addresses);
+
+}
@@ -0,0 +1,36 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.state;
+
+import org.whispersystems.libsignal.IdentityKey;
+import org.whispersystems.libsignal.SignalProtocolAddress;
+
+import java.util.Map;
+
+/**
+ * An {@link IdentityKeyStore} that can list and forget the identities it has saved.
+ *
+ * Enumeration is optional because not every store can do it cheaply; asking a store that doesn't
+ * implement this interface to do so throws {@link UnsupportedOperationException}.
+ */
+public interface EnumerableIdentityKeyStore extends IdentityKeyStore {
+
+  /**
+   * Return every saved identity.
+   *
+   * @return the saved identities, keyed by the address of the remote client.
+   */
+  public Map<SignalProtocolAddress, IdentityKey> getAllIdentities();
+
+  /**
+   * Forget the saved identity of a remote client.
+   *
+   * @param address The address of the remote client.
+   * @return true if an identity was removed, false if none was saved.
+   */
+  public boolean deleteIdentity(SignalProtocolAddress address);
+
+}
@@ -20,6 +20,18 @@ public interface IdentityKeyStore {
     SENDING, RECEIVING
   }
 
+  /**
+   * What {@link #saveIdentity} did with the identity it was given.
+   */
+  public enum IdentityChange {
+    /** There was no identity saved for the address before. */
+    NEW_IDENTITY,
+    /** A different identity was saved for the address, and has been replaced. */
+    REPLACED_EXISTING,
+    /** The same identity was already saved for the address. */
+    UNCHANGED
+  }
+
   /**
    * Get the local client's identity key pair.
    *
@@ -44,9 +56,10 @@ public interface IdentityKeyStore {
    *
    * @param address     The address of the remote client.
    * @param identityKey The remote client's identity key.
-   * @return True if the identity key replaces a previous identity, false if not
+   * @return {@link IdentityChange#REPLACED_EXISTING} if the identity key replaces a different
+   *         previous identity, so that the user should be told their safety number has changed.
    */
-  public boolean         saveIdentity(SignalProtocolAddress address, IdentityKey identityKey);
+  public IdentityChange  saveIdentity(SignalProtocolAddress address, IdentityKey identityKey);
 
 
   /**
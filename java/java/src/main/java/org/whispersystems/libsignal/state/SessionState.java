@@ -196,4 +196,24 @@ public class SessionState {
   public byte[] serialize() {
     return sessionStructure.toByteArray();
   }
+
+  // Debugging aids: these report counters and flags, never keys.
+
+  public boolean debugHasUsableSenderChain() {
+    return Native.SessionState_DebugHasUsableSenderChain(serialize());
+  }
+
+  public Optional<Integer> debugSenderChainCounter() {
+    int counter = Native.SessionState_DebugSenderChainCounter(serialize());
+    return counter < 0 ? Optional.<Integer>absent() : Optional.of(counter);
+  }
+
+  public Optional<Integer> debugReceiverChainCounter(ECPublicKey theirEphemeral) {
+    int counter = Native.SessionState_DebugReceiverChainCounter(serialize(), theirEphemeral.nativeHandle());
+    return counter < 0 ? Optional.<Integer>absent() : Optional.of(counter);
+  }
+
+  public int debugNumberOfReceiverChains() {
+    return Native.SessionState_DebugNumberOfReceiverChains(serialize());
+  }
 }
@@ -0,0 +1,93 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.state;
+
+import org.whispersystems.libsignal.IdentityKey;
+import org.whispersystems.libsignal.SignalProtocolAddress;
+
+/**
+ * A single store write that decrypting a message needs, passed to
+ * {@link TransactionalStore#commit}.
+ */
+public final class StoreOp {
+
+  public enum Type {
+    SAVE_IDENTITY,
+    STORE_SESSION,
+    REMOVE_PRE_KEY,
+  }
+
+  private final Type                  type;
+  private final SignalProtocolAddress address;
+  private final IdentityKey           identityKey;
+  private final SessionRecord         sessionRecord;
+  private final int                   preKeyId;
+
+  private StoreOp(Type type, SignalProtocolAddress address, IdentityKey identityKey,
+                  SessionRecord sessionRecord, int preKeyId)
+  {
+    this.type          = type;
+    this.address       = address;
+    this.identityKey   = identityKey;
+    this.sessionRecord = sessionRecord;
+    this.preKeyId      = preKeyId;
+  }
+
+  public static StoreOp saveIdentity(SignalProtocolAddress address, IdentityKey identityKey) {
+    return new StoreOp(Type.SAVE_IDENTITY, address, identityKey, null, 0);
+  }
+
+  public static StoreOp storeSession(SignalProtocolAddress address, SessionRecord record) {
+    return new StoreOp(Type.STORE_SESSION, address, null, record, 0);
+  }
+
+  public static StoreOp removePreKey(int preKeyId) {
+    return new StoreOp(Type.REMOVE_PRE_KEY, null, null, null, preKeyId);
+  }
+
+  public Type getType() {
+    return type;
+  }
+
+  /**
+   * @return the address, for {@link Type#SAVE_IDENTITY} and {@link Type#STORE_SESSION}.
+   */
+  public SignalProtocolAddress getAddress() {
+    return address;
+  }
+
+  /**
+   * @return the identity to save, for {@link Type#SAVE_IDENTITY}.
+   */
+  public IdentityKey getIdentityKey() {
+    return identityKey;
+  }
+
+  /**
+   * @return the session to store, for {@link Type#STORE_SESSION}.
+   */
+  public SessionRecord getSessionRecord() {
+    return sessionRecord;
+  }
+
+  /**
+   * @return the ID of the one-time PreKey to remove, for {@link Type#REMOVE_PRE_KEY}.
+   */
+  public int getPreKeyId() {
+    return preKeyId;
+  }
+
+  /**
+   * Performs this write on the given stores.
+   */
+  public void apply(SessionStore sessionStore, IdentityKeyStore identityKeyStore, PreKeyStore preKeyStore) {
+    switch (type) {
+      case SAVE_IDENTITY:  identityKeyStore.saveIdentity(address, identityKey); break;
+      case STORE_SESSION:  sessionStore.storeSession(address, sessionRecord);   break;
+      case REMOVE_PRE_KEY: preKeyStore.removePreKey(preKeyId);                  break;
+    }
+  }
+}
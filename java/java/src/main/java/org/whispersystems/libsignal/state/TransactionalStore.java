@@ -0,0 +1,24 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.state;
+
+/**
+ * A store that can perform several writes as a single unit, such as one database transaction.
+ *
+ * When the SessionStore given to a {@link org.whispersystems.libsignal.SessionCipher} implements
+ * this interface, the writes needed to decrypt a message are passed to {@link #commit} together
+ * once decryption has succeeded. Other stores have the writes made one at a time.
+ */
+public interface TransactionalStore {
+
+  /**
+   * Performs every one of the writes, in order, or none of them.
+   *
+   * @param ops The writes, in the order they must be performed.
+   */
+  public void commit(StoreOp[] ops);
+
+}
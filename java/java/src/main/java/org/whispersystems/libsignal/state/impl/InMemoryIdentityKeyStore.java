@@ -8,12 +8,12 @@ package org.whispersystems.libsignal.state.impl;
 import org.whispersystems.libsignal.IdentityKey;
 import org.whispersystems.libsignal.IdentityKeyPair;
 import org.whispersystems.libsignal.SignalProtocolAddress;
-import org.whispersystems.libsignal.state.IdentityKeyStore;
+import org.whispersystems.libsignal.state.EnumerableIdentityKeyStore;
 
 import java.util.HashMap;
 import java.util.Map;
 
-public class InMemoryIdentityKeyStore implements IdentityKeyStore {
+public class InMemoryIdentityKeyStore implements EnumerableIdentityKeyStore {
 
   private final Map<SignalProtocolAddress, IdentityKey> trustedKeys = new HashMap<>();
 
@@ -36,14 +36,17 @@ public class InMemoryIdentityKeyStore implements IdentityKeyStore {
   }
 
   @Override
-  public boolean saveIdentity(SignalProtocolAddress address, IdentityKey identityKey) {
+  public IdentityChange saveIdentity(SignalProtocolAddress address, IdentityKey identityKey) {
     IdentityKey existing = trustedKeys.get(address);
 
-    if (!identityKey.equals(existing)) {
+    if (existing == null) {
       trustedKeys.put(address, identityKey);
-      return true;
+      return IdentityChange.NEW_IDENTITY;
+    } else if (!identityKey.equals(existing)) {
+      trustedKeys.put(address, identityKey);
+      return IdentityChange.REPLACED_EXISTING;
     } else {
-      return false;
+      return IdentityChange.UNCHANGED;
     }
   }
 
@@ -57,4 +60,14 @@ public class InMemoryIdentityKeyStore implements IdentityKeyStore {
   public IdentityKey getIdentity(SignalProtocolAddress address) {
     return trustedKeys.get(address);
   }
+
+  @Override
+  public Map<SignalProtocolAddress, IdentityKey> getAllIdentities() {
+    return new HashMap<>(trustedKeys);
+  }
+
+  @Override
+  public boolean deleteIdentity(SignalProtocolAddress address) {
+    return trustedKeys.remove(address) != null;
+  }
 }
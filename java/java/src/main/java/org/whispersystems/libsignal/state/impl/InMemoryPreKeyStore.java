@@ -6,14 +6,14 @@
 package org.whispersystems.libsignal.state.impl;
 
 import org.whispersystems.libsignal.InvalidKeyIdException;
+import org.whispersystems.libsignal.state.BatchPreKeyStore;
 import org.whispersystems.libsignal.state.PreKeyRecord;
-import org.whispersystems.libsignal.state.PreKeyStore;
 
 import java.io.IOException;
 import java.util.HashMap;
 import java.util.Map;
 
-public class InMemoryPreKeyStore implements PreKeyStore {
+public class InMemoryPreKeyStore implements BatchPreKeyStore {
 
   private final Map<Integer, byte[]> store = new HashMap<>();
 
@@ -44,4 +44,18 @@ public class InMemoryPreKeyStore implements PreKeyStore {
   public void removePreKey(int preKeyId) {
     store.remove(preKeyId);
   }
+
+  @Override
+  public void storePreKeys(int[] preKeyIds, PreKeyRecord[] records) {
+    for (int i = 0; i < preKeyIds.length; i++) {
+      store.put(preKeyIds[i], records[i].serialize());
+    }
+  }
+
+  @Override
+  public void removePreKeys(int[] preKeyIds) {
+    for (int preKeyId : preKeyIds) {
+      store.remove(preKeyId);
+    }
+  }
 }
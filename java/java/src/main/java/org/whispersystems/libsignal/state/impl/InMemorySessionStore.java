@@ -6,8 +6,8 @@
 package org.whispersystems.libsignal.state.impl;
 
 import org.whispersystems.libsignal.SignalProtocolAddress;
+import org.whispersystems.libsignal.state.BatchSessionStore;
 import org.whispersystems.libsignal.state.SessionRecord;
-import org.whispersystems.libsignal.state.SessionStore;
 
 import java.io.IOException;
 import java.util.HashMap;
@@ -15,7 +15,7 @@ import java.util.LinkedList;
 import java.util.List;
 import java.util.Map;
 
-public class InMemorySessionStore implements SessionStore {
+public class InMemorySessionStore implements BatchSessionStore {
 
   private Map<SignalProtocolAddress, byte[]> sessions = new HashMap<>();
 
@@ -34,6 +34,25 @@ public class InMemorySessionStore implements SessionStore {
     }
   }
 
+  @Override
+  public synchronized SessionRecord[] loadSessions(SignalProtocolAddress[] addresses) {
+    try {
+      SessionRecord[] records = new SessionRecord[addresses.length];
+
+      for (int i = 0; i < addresses.length; i++) {
+        byte[] serialized = sessions.get(addresses[i]);
+
+        if (serialized != null) {
+          records[i] = new SessionRecord(serialized);
+        }
+      }
+
+      return records;
+    } catch (IOException e) {
+      throw new AssertionError(e);
+    }
+  }
+
   @Override
   public synchronized List<Integer> getSubDeviceSessions(String name) {
     List<Integer> deviceIds = new LinkedList<>();
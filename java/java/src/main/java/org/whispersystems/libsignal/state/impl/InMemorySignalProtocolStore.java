@@ -13,10 +13,12 @@ import org.whispersystems.libsignal.state.SignalProtocolStore;
 import org.whispersystems.libsignal.state.PreKeyRecord;
 import org.whispersystems.libsignal.state.SessionRecord;
 import org.whispersystems.libsignal.state.SignedPreKeyRecord;
+import org.whispersystems.libsignal.state.StoreOp;
+import org.whispersystems.libsignal.state.TransactionalStore;
 
 import java.util.List;
 
-public class InMemorySignalProtocolStore implements SignalProtocolStore {
+public class InMemorySignalProtocolStore implements SignalProtocolStore, TransactionalStore {
 
   private final InMemoryPreKeyStore       preKeyStore       = new InMemoryPreKeyStore();
   private final InMemorySessionStore      sessionStore      = new InMemorySessionStore();
@@ -39,7 +41,7 @@ public class InMemorySignalProtocolStore implements SignalProtocolStore {
   }
 
   @Override
-  public boolean saveIdentity(SignalProtocolAddress address, IdentityKey identityKey) {
+  public IdentityChange saveIdentity(SignalProtocolAddress address, IdentityKey identityKey) {
     return identityKeyStore.saveIdentity(address, identityKey);
   }
 
@@ -127,4 +129,11 @@ public class InMemorySignalProtocolStore implements SignalProtocolStore {
   public void removeSignedPreKey(int signedPreKeyId) {
     signedPreKeyStore.removeSignedPreKey(signedPreKeyId);
   }
+
+  @Override
+  public void commit(StoreOp[] ops) {
+    for (StoreOp op : ops) {
+      op.apply(this, this, this);
+    }
+  }
 }
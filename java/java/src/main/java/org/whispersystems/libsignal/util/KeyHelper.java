@@ -31,21 +31,30 @@ public class KeyHelper {
    * Generate a registration ID.  Clients should only do this once,
    * at install time.
    *
-   * @param extendedRange By default (false), the generated registration
-   *                      ID is sized to require the minimal possible protobuf
-   *                      encoding overhead. Specify true if the caller needs
-   *                      the full range of MAX_INT at the cost of slightly
-   *                      higher encoding overhead.
-   * @return the generated registration ID.
+   * @return the generated registration ID, between 1 and 16380.
    */
-  public static int generateRegistrationId(boolean extendedRange) {
+  public static int generateRegistrationId() {
     try {
       SecureRandom secureRandom = SecureRandom.getInstance("SHA1PRNG");
-      if (extendedRange) return secureRandom.nextInt(Integer.MAX_VALUE - 1) + 1;
-      else               return secureRandom.nextInt(16380) + 1;
+      return secureRandom.nextInt(16380) + 1;
     } catch (NoSuchAlgorithmException e) {
       throw new AssertionError(e);
     }
   }
 
+  /**
+   * Generate a registration ID.  Clients should only do this once,
+   * at install time.
+   *
+   * @param extendedRange Ignored. IDs above 16383 are rejected when building
+   *                      sessions, since sealed sender carries registration
+   *                      IDs in 14 bits, so the range is no longer extended.
+   * @return the generated registration ID, between 1 and 16380.
+   * @deprecated Use {@link #generateRegistrationId()}.
+   */
+  @Deprecated
+  public static int generateRegistrationId(boolean extendedRange) {
+    return generateRegistrationId();
+  }
+
 }
@@ -0,0 +1,30 @@
+/**
+ * Copyright (C) 2020 Signal Messenger, LLC.
+ *
+ * Licensed according to the LICENSE file in this repository.
+ */
+package org.whispersystems.libsignal.util;
+
+import org.signal.client.internal.Native;
+
+import org.whispersystems.libsignal.InvalidMessageException;
+
+/**
+ * The padding applied to message bodies before encryption: a 0x80 terminator followed by zeros,
+ * up to one byte short of a multiple of 160 bytes.
+ */
+public final class MessagePadding {
+
+  private MessagePadding() {}
+
+  public static byte[] pad(byte[] body) {
+    return Native.MessagePadding_Pad(body);
+  }
+
+  /**
+   * @throws InvalidMessageException if the terminator is missing or followed by anything but zeros.
+   */
+  public static byte[] unpad(byte[] padded) throws InvalidMessageException {
+    return Native.MessagePadding_Unpad(padded);
+  }
+}
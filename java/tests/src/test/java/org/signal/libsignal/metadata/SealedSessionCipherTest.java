@@ -10,6 +10,11 @@ import org.signal.libsignal.metadata.certificate.CertificateValidator;
 import org.signal.libsignal.metadata.certificate.InvalidCertificateException;
 import org.signal.libsignal.metadata.certificate.SenderCertificate;
 import org.signal.libsignal.metadata.certificate.ServerCertificate;
+import org.signal.libsignal.metadata.certificate.TrustRoot;
+import org.signal.libsignal.metadata.protocol.ContentHint;
+import org.signal.libsignal.metadata.protocol.SealedSenderMultiRecipientMessage;
+import org.signal.libsignal.metadata.protocol.UnidentifiedSenderMessage;
+import org.signal.libsignal.metadata.protocol.UnidentifiedSenderMessageContent;
 import org.whispersystems.libsignal.IdentityKeyPair;
 import org.whispersystems.libsignal.InvalidKeyException;
 import org.whispersystems.libsignal.SessionBuilder;
@@ -18,12 +23,18 @@ import org.whispersystems.libsignal.UntrustedIdentityException;
 import org.whispersystems.libsignal.ecc.Curve;
 import org.whispersystems.libsignal.ecc.ECKeyPair;
 import org.whispersystems.libsignal.ecc.ECPublicKey;
+import org.whispersystems.libsignal.protocol.CiphertextMessage;
 import org.whispersystems.libsignal.state.PreKeyBundle;
 import org.whispersystems.libsignal.state.PreKeyRecord;
 import org.whispersystems.libsignal.state.SignedPreKeyRecord;
+import org.whispersystems.libsignal.util.Hex;
 
 import org.whispersystems.libsignal.util.Pair;
+import org.whispersystems.libsignal.util.guava.Optional;
 
+import java.util.ArrayList;
+import java.util.Arrays;
+import java.util.Map;
 import java.util.UUID;
 
 public class SealedSessionCipherTest extends TestCase {
@@ -59,6 +70,9 @@ public class SealedSessionCipherTest extends TestCase {
     assertEquals(plaintext.getSenderUuid().get(), "9d0652a3-dcc3-4d11-975f-74d61598733f");
     assertEquals(plaintext.getSenderE164().get(), "+14151111111");
     assertEquals(plaintext.getDeviceId(), 1);
+    assertEquals(plaintext.getSenderCertificate().getExpiration(), 31337);
+    assertEquals(plaintext.getSenderCertificate().getSigner().getKeyId(), 1);
+    assertTrue(Arrays.equals(plaintext.getSenderCertificate().getSerialized(), senderCertificate.getSerialized()));
   }
 
   public void testEncryptDecryptUntrusted() throws Exception {
@@ -108,6 +122,35 @@ public class SealedSessionCipherTest extends TestCase {
     }
   }
 
+  public void testEncryptDecryptDuringTrustRootRotation() throws Exception {
+    TestInMemorySignalProtocolStore aliceStore = new TestInMemorySignalProtocolStore();
+    TestInMemorySignalProtocolStore bobStore   = new TestInMemorySignalProtocolStore();
+
+    initializeSessions(aliceStore, bobStore);
+
+    ECKeyPair           oldTrustRoot      = Curve.generateKeyPair();
+    ECKeyPair           newTrustRoot      = Curve.generateKeyPair();
+    SenderCertificate   senderCertificate = createCertificateFor(oldTrustRoot, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1, aliceStore.getIdentityKeyPair().getPublicKey().getPublicKey(), 31337);
+    SealedSessionCipher aliceCipher       = new SealedSessionCipher(aliceStore, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1);
+
+    byte[] ciphertext = aliceCipher.encrypt(new SignalProtocolAddress("+14152222222", 1),
+                                            senderCertificate, "smert za smert".getBytes());
+
+    SealedSessionCipher  bobCipher = new SealedSessionCipher(bobStore, UUID.fromString("e80f7bbe-5b94-471e-bd8c-2173654ea3d1"), "+14152222222", 1);
+    CertificateValidator validator = new CertificateValidator(Arrays.asList(new TrustRoot(newTrustRoot.getPublicKey()),
+                                                                            new TrustRoot(oldTrustRoot.getPublicKey(), 31334)));
+
+    try {
+      bobCipher.decrypt(validator, ciphertext, 31335);
+      throw new AssertionError();
+    } catch (InvalidMetadataMessageException e) {
+      // good
+    }
+
+    DecryptionResult plaintext = bobCipher.decrypt(validator, ciphertext, 31334);
+    assertEquals(new String(plaintext.getPaddedMessage()), "smert za smert");
+  }
+
   public void testEncryptFromWrongIdentity() throws Exception {
     TestInMemorySignalProtocolStore aliceStore = new TestInMemorySignalProtocolStore();
     TestInMemorySignalProtocolStore bobStore   = new TestInMemorySignalProtocolStore();
@@ -132,6 +175,189 @@ public class SealedSessionCipherTest extends TestCase {
     }
   }
 
+  public void testDecryptToUsmcAndResend() throws Exception {
+    TestInMemorySignalProtocolStore aliceStore = new TestInMemorySignalProtocolStore();
+    TestInMemorySignalProtocolStore bobStore   = new TestInMemorySignalProtocolStore();
+    TestInMemorySignalProtocolStore carolStore = new TestInMemorySignalProtocolStore();
+
+    initializeSessions(aliceStore, bobStore);
+
+    SignalProtocolAddress carolAddress = new SignalProtocolAddress("+14153333333", 1);
+    aliceStore.saveIdentity(carolAddress, carolStore.getIdentityKeyPair().getPublicKey());
+
+    ECKeyPair           trustRoot         = Curve.generateKeyPair();
+    SenderCertificate   senderCertificate = createCertificateFor(trustRoot, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1, aliceStore.getIdentityKeyPair().getPublicKey().getPublicKey(), 31337);
+    SealedSessionCipher aliceCipher       = new SealedSessionCipher(aliceStore, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1);
+
+    byte[] ciphertext = aliceCipher.encrypt(new SignalProtocolAddress("+14152222222", 1),
+                                            senderCertificate, "smert za smert".getBytes());
+
+    SealedSessionCipher              bobCipher  = new SealedSessionCipher(bobStore, UUID.fromString("e80f7bbe-5b94-471e-bd8c-2173654ea3d1"), "+14152222222", 1);
+    UnidentifiedSenderMessageContent bobContent = bobCipher.decryptToUsmc(ciphertext);
+
+    assertEquals(CiphertextMessage.PREKEY_TYPE, bobContent.getType());
+    assertTrue(Arrays.equals(senderCertificate.getSerialized(), bobContent.getSenderCertificate().getSerialized()));
+    assertFalse(bobStore.containsSession(new SignalProtocolAddress("+14151111111", 1)));
+
+    byte[] resent = aliceCipher.encrypt(carolAddress, bobContent);
+
+    SealedSessionCipher              carolCipher  = new SealedSessionCipher(carolStore, UUID.fromString("2cdfba12-6d0b-4bf8-9a3d-e8bc2e1a5c44"), "+14153333333", 1);
+    UnidentifiedSenderMessageContent carolContent = carolCipher.decryptToUsmc(resent);
+
+    assertEquals(bobContent.getType(), carolContent.getType());
+    assertTrue(Arrays.equals(bobContent.getSenderCertificate().getSerialized(), carolContent.getSenderCertificate().getSerialized()));
+    assertTrue(Arrays.equals(bobContent.getContent(), carolContent.getContent()));
+
+    DecryptionResult plaintext = bobCipher.decrypt(new CertificateValidator(trustRoot.getPublicKey()), ciphertext, 31335);
+    assertEquals(new String(plaintext.getPaddedMessage()), "smert za smert");
+  }
+
+  public void testContentHintAndGroupIdRoundTrip() throws Exception {
+    TestInMemorySignalProtocolStore aliceStore = new TestInMemorySignalProtocolStore();
+    TestInMemorySignalProtocolStore bobStore   = new TestInMemorySignalProtocolStore();
+
+    initializeSessions(aliceStore, bobStore);
+
+    ECKeyPair           trustRoot         = Curve.generateKeyPair();
+    SenderCertificate   senderCertificate = createCertificateFor(trustRoot, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1, aliceStore.getIdentityKeyPair().getPublicKey().getPublicKey(), 31337);
+    SealedSessionCipher aliceCipher       = new SealedSessionCipher(aliceStore, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1);
+    byte[]              groupId           = new byte[] {0x0a, 0x0b, 0x0c};
+
+    UnidentifiedSenderMessageContent content = new UnidentifiedSenderMessageContent(CiphertextMessage.WHISPER_TYPE, senderCertificate, "content".getBytes(),
+                                                                                    ContentHint.RESENDABLE, Optional.of(groupId));
+
+    byte[] ciphertext = aliceCipher.encrypt(new SignalProtocolAddress("+14152222222", 1), content);
+
+    SealedSessionCipher              bobCipher = new SealedSessionCipher(bobStore, UUID.fromString("e80f7bbe-5b94-471e-bd8c-2173654ea3d1"), "+14152222222", 1);
+    UnidentifiedSenderMessageContent received  = bobCipher.decryptToUsmc(ciphertext);
+
+    assertEquals(ContentHint.RESENDABLE, received.getContentHint());
+    assertTrue(Arrays.equals(groupId, received.getGroupId().get()));
+    assertTrue(Arrays.equals(content.getSerialized(), received.getSerialized()));
+
+    UnidentifiedSenderMessageContent plain = new UnidentifiedSenderMessageContent(CiphertextMessage.WHISPER_TYPE, senderCertificate, "content".getBytes());
+    UnidentifiedSenderMessageContent explicitDefault = new UnidentifiedSenderMessageContent(CiphertextMessage.WHISPER_TYPE, senderCertificate, "content".getBytes(),
+                                                                                            ContentHint.DEFAULT, Optional.<byte[]>absent());
+
+    assertTrue(Arrays.equals(plain.getSerialized(), explicitDefault.getSerialized()));
+    assertEquals(ContentHint.DEFAULT, new UnidentifiedSenderMessageContent(plain.getSerialized()).getContentHint());
+    assertFalse(new UnidentifiedSenderMessageContent(plain.getSerialized()).getGroupId().isPresent());
+  }
+
+  public void testUnknownContentHintRoundTrip() throws Exception {
+    ECKeyPair         trustRoot         = Curve.generateKeyPair();
+    SenderCertificate senderCertificate = createCertificateFor(trustRoot, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1, Curve.generateKeyPair().getPublicKey(), 31337);
+
+    UnidentifiedSenderMessageContent content = new UnidentifiedSenderMessageContent(CiphertextMessage.PREKEY_TYPE, senderCertificate, "content".getBytes(),
+                                                                                    ContentHint.fromValue(42), Optional.<byte[]>absent());
+    UnidentifiedSenderMessageContent parsed  = new UnidentifiedSenderMessageContent(content.getSerialized());
+
+    assertEquals(42, parsed.getContentHint().getValue());
+    assertFalse(parsed.getContentHint().isKnown());
+
+    UnidentifiedSenderMessageContent rebuilt = new UnidentifiedSenderMessageContent(parsed.getType(), parsed.getSenderCertificate(), parsed.getContent(),
+                                                                                    parsed.getContentHint(), parsed.getGroupId());
+
+    assertTrue(Arrays.equals(content.getSerialized(), rebuilt.getSerialized()));
+  }
+
+
+  public void testMultiRecipientEncryptDecrypt() throws Exception {
+    TestInMemorySignalProtocolStore aliceStore = new TestInMemorySignalProtocolStore();
+    TestInMemorySignalProtocolStore bobStore   = new TestInMemorySignalProtocolStore();
+    TestInMemorySignalProtocolStore carolStore = new TestInMemorySignalProtocolStore();
+
+    SignalProtocolAddress bobAddress   = new SignalProtocolAddress("+14152222222", 1);
+    SignalProtocolAddress carolAddress = new SignalProtocolAddress("+14153333333", 1);
+    aliceStore.saveIdentity(bobAddress, bobStore.getIdentityKeyPair().getPublicKey());
+    aliceStore.saveIdentity(carolAddress, carolStore.getIdentityKeyPair().getPublicKey());
+
+    ECKeyPair           trustRoot         = Curve.generateKeyPair();
+    SenderCertificate   senderCertificate = createCertificateFor(trustRoot, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1, aliceStore.getIdentityKeyPair().getPublicKey().getPublicKey(), 31337);
+    SealedSessionCipher aliceCipher       = new SealedSessionCipher(aliceStore, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1);
+
+    UnidentifiedSenderMessageContent content = new UnidentifiedSenderMessageContent(CiphertextMessage.SENDERKEY_TYPE, senderCertificate, "group content".getBytes(),
+                                                                                    ContentHint.RESENDABLE, Optional.of(new byte[] {0x0a, 0x0b}));
+
+    byte[]                             multiRecipient = aliceCipher.multiRecipientEncrypt(Arrays.asList(bobAddress, carolAddress), content);
+    Map<SignalProtocolAddress, byte[]> messages       = new SealedSenderMultiRecipientMessage(multiRecipient).split();
+
+    assertEquals(Arrays.asList(bobAddress, carolAddress), new ArrayList<>(messages.keySet()));
+
+    SealedSessionCipher              bobCipher  = new SealedSessionCipher(bobStore, UUID.fromString("e80f7bbe-5b94-471e-bd8c-2173654ea3d1"), "+14152222222", 1);
+    UnidentifiedSenderMessageContent bobContent = bobCipher.decryptToUsmc(messages.get(bobAddress));
+
+    assertEquals(CiphertextMessage.SENDERKEY_TYPE, bobContent.getType());
+    assertTrue(Arrays.equals(content.getSerialized(), bobContent.getSerialized()));
+
+    SealedSessionCipher              carolCipher  = new SealedSessionCipher(carolStore, UUID.fromString("2cdfba12-6d0b-4bf8-9a3d-e8bc2e1a5c44"), "+14153333333", 1);
+    UnidentifiedSenderMessageContent carolContent = carolCipher.decryptToUsmc(messages.get(carolAddress));
+
+    assertTrue(Arrays.equals(content.getSerialized(), carolContent.getSerialized()));
+
+    try {
+      carolCipher.decryptToUsmc(messages.get(bobAddress));
+      throw new AssertionError();
+    } catch (InvalidMetadataMessageException e) {
+      // good
+    }
+  }
+
+  public void testMultiRecipientSharedCiphertextIsBound() throws Exception {
+    TestInMemorySignalProtocolStore aliceStore = new TestInMemorySignalProtocolStore();
+    TestInMemorySignalProtocolStore bobStore   = new TestInMemorySignalProtocolStore();
+
+    SignalProtocolAddress bobAddress = new SignalProtocolAddress("+14152222222", 1);
+    aliceStore.saveIdentity(bobAddress, bobStore.getIdentityKeyPair().getPublicKey());
+
+    ECKeyPair           trustRoot         = Curve.generateKeyPair();
+    SenderCertificate   senderCertificate = createCertificateFor(trustRoot, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1, aliceStore.getIdentityKeyPair().getPublicKey().getPublicKey(), 31337);
+    SealedSessionCipher aliceCipher       = new SealedSessionCipher(aliceStore, UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f"), "+14151111111", 1);
+
+    UnidentifiedSenderMessageContent  content  = new UnidentifiedSenderMessageContent(CiphertextMessage.SENDERKEY_TYPE, senderCertificate, "group content".getBytes());
+    SealedSenderMultiRecipientMessage original = new SealedSenderMultiRecipientMessage(aliceCipher.multiRecipientEncrypt(Arrays.asList(bobAddress), content));
+    SealedSenderMultiRecipientMessage.Recipient recipient = original.getRecipients().get(0);
+
+    byte[] tamperedCiphertext = original.getEncryptedMessage().clone();
+    tamperedCiphertext[0] ^= 1;
+
+    byte[] tampered = new UnidentifiedSenderMessage(original.getEphemeral(), recipient.getEncryptedStatic(),
+                                                    recipient.getEncryptedMessageKey(), tamperedCiphertext).getSerialized();
+
+    SealedSessionCipher bobCipher = new SealedSessionCipher(bobStore, UUID.fromString("e80f7bbe-5b94-471e-bd8c-2173654ea3d1"), "+14152222222", 1);
+
+    try {
+      bobCipher.decryptToUsmc(tampered);
+      throw new AssertionError();
+    } catch (InvalidMetadataMessageException e) {
+      // good
+    }
+  }
+
+  public void testMultiRecipientWireFormat() throws Exception {
+    ECPublicKey                                 ephemeral = Curve.decodePoint(Hex.fromStringCondensed("051111111111111111111111111111111111111111111111111111111111111111"), 0);
+    SignalProtocolAddress                       address   = new SignalProtocolAddress("+14152222222", 1);
+    SealedSenderMultiRecipientMessage.Recipient recipient = new SealedSenderMultiRecipientMessage.Recipient(address, new byte[] {1, 2, 3}, new byte[] {4, 5});
+
+    SealedSenderMultiRecipientMessage message = new SealedSenderMultiRecipientMessage(ephemeral, Arrays.asList(recipient), new byte[] {6, 7, 8});
+
+    byte[] expected = Hex.fromStringCondensed("220a2105111111111111111111111111111111111111111111111111111111111111111112190a0c2b313431353232323232323210011a03010203220204051a03060708");
+    assertTrue(Arrays.equals(expected, message.getSerialized()));
+
+    SealedSenderMultiRecipientMessage parsed = new SealedSenderMultiRecipientMessage(expected);
+    assertEquals(address, parsed.getRecipients().get(0).getAddress());
+    assertTrue(Arrays.equals(new byte[] {6, 7, 8}, parsed.getEncryptedMessage()));
+
+    byte[] expectedSplit = Hex.fromStringCondensed("220a2105111111111111111111111111111111111111111111111111111111111111111112030102031a0306070822020405");
+    assertTrue(Arrays.equals(expectedSplit, parsed.split().get(address)));
+
+    try {
+      new SealedSenderMultiRecipientMessage(ephemeral, Arrays.asList(recipient, recipient), new byte[] {6, 7, 8});
+      throw new AssertionError();
+    } catch (InvalidMetadataMessageException e) {
+      // good
+    }
+  }
 
 
   private SenderCertificate createCertificateFor(ECKeyPair trustRoot, UUID uuid, String e164, int deviceId, ECPublicKey identityKey, long expires)
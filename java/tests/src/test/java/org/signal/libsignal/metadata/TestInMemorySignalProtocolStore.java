@@ -22,6 +22,6 @@ public class TestInMemorySignalProtocolStore extends InMemorySignalProtocolStore
   }
 
   private static int generateRegistrationId() {
-    return KeyHelper.generateRegistrationId(false);
+    return KeyHelper.generateRegistrationId();
   }
 }
\ No newline at end of file
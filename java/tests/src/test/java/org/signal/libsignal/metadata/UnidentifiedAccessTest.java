@@ -0,0 +1,37 @@
+package org.signal.libsignal.metadata;
+
+import junit.framework.TestCase;
+
+import org.whispersystems.libsignal.util.Hex;
+
+import java.util.Arrays;
+
+public class UnidentifiedAccessTest extends TestCase {
+
+  public void testDeriveAccessKey() throws Exception {
+    byte[] profileKey = new byte[32];
+    for (int i = 0; i < profileKey.length; i++) {
+      profileKey[i] = (byte) i;
+    }
+
+    assertTrue(Arrays.equals(Hex.fromStringCondensed("cea7403d4d606b6e074ec5d3baf39d18"),
+                             UnidentifiedAccess.deriveAccessKeyFrom(new byte[32])));
+    assertTrue(Arrays.equals(Hex.fromStringCondensed("0ebcb5deb52c83bd08a8a935182c9199"),
+                             UnidentifiedAccess.deriveAccessKeyFrom(profileKey)));
+
+    try {
+      UnidentifiedAccess.deriveAccessKeyFrom(new byte[16]);
+      throw new AssertionError();
+    } catch (IllegalArgumentException e) {
+      // good
+    }
+  }
+
+  public void testUnrestrictedAccessKey() {
+    byte[] first  = UnidentifiedAccess.generateUnrestrictedAccessKey();
+    byte[] second = UnidentifiedAccess.generateUnrestrictedAccessKey();
+
+    assertEquals(16, first.length);
+    assertFalse(Arrays.equals(first, second));
+  }
+}
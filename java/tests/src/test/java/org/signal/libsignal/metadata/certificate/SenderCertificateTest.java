@@ -9,6 +9,7 @@ import org.signal.libsignal.metadata.SignalProtos;
 import org.whispersystems.libsignal.InvalidKeyException;
 import org.whispersystems.libsignal.ecc.Curve;
 import org.whispersystems.libsignal.ecc.ECKeyPair;
+import org.whispersystems.libsignal.util.guava.Optional;
 
 public class SenderCertificateTest extends TestCase {
 
@@ -39,6 +40,60 @@ public class SenderCertificateTest extends TestCase {
     new CertificateValidator(trustRoot.getPublicKey()).validate(senderCertificate, 31336);
   }
 
+  public void testIssuedCertificate() throws InvalidCertificateException, InvalidKeyException {
+    ECKeyPair         serverKey         = Curve.generateKeyPair();
+    ECKeyPair         key               = Curve.generateKeyPair();
+    ServerCertificate serverCertificate = new ServerCertificate(trustRoot.getPrivateKey(), 1, serverKey.getPublicKey());
+
+    SenderCertificate certificate = new SenderCertificate(serverCertificate, serverKey.getPrivateKey(),
+                                                          "9d0652a3-dcc3-4d11-975f-74d61598733f", Optional.of("+14152222222"), 1,
+                                                          key.getPublicKey(), 31337);
+    SenderCertificate parsed      = new SenderCertificate(certificate.getSerialized());
+
+    assertEquals("9d0652a3-dcc3-4d11-975f-74d61598733f", parsed.getSenderUuid().get());
+    assertEquals("+14152222222", parsed.getSenderE164().get());
+    assertEquals(1, parsed.getSenderDeviceId());
+    assertEquals(key.getPublicKey(), parsed.getKey());
+    assertEquals(31337, parsed.getExpiration());
+    new CertificateValidator(trustRoot.getPublicKey()).validate(parsed, 31336);
+
+    SenderCertificate withoutE164 = new SenderCertificate(serverCertificate, serverKey.getPrivateKey(),
+                                                          "9d0652a3-dcc3-4d11-975f-74d61598733f", Optional.<String>absent(), 1,
+                                                          key.getPublicKey(), 31337);
+    assertFalse(new SenderCertificate(withoutE164.getSerialized()).getSenderE164().isPresent());
+
+    SenderCertificate wrongSigner = new SenderCertificate(serverCertificate, Curve.generateKeyPair().getPrivateKey(),
+                                                          "9d0652a3-dcc3-4d11-975f-74d61598733f", Optional.<String>absent(), 1,
+                                                          key.getPublicKey(), 31337);
+    try {
+      new CertificateValidator(trustRoot.getPublicKey()).validate(wrongSigner, 31336);
+      throw new AssertionError();
+    } catch (InvalidCertificateException e) {
+      // good
+    }
+  }
+
+  public void testDeviceIdRange() throws InvalidCertificateException, InvalidKeyException {
+    ECKeyPair         serverKey         = Curve.generateKeyPair();
+    ECKeyPair         key               = Curve.generateKeyPair();
+    ServerCertificate serverCertificate = new ServerCertificate(trustRoot.getPrivateKey(), 1, serverKey.getPublicKey());
+
+    assertEquals(127, new SenderCertificate(serverCertificate, serverKey.getPrivateKey(),
+                                            "9d0652a3-dcc3-4d11-975f-74d61598733f", Optional.<String>absent(), 127,
+                                            key.getPublicKey(), 31337).getSenderDeviceId());
+
+    for (int deviceId : new int[] {0, 128}) {
+      try {
+        new SenderCertificate(serverCertificate, serverKey.getPrivateKey(),
+                              "9d0652a3-dcc3-4d11-975f-74d61598733f", Optional.<String>absent(), deviceId,
+                              key.getPublicKey(), 31337);
+        throw new AssertionError();
+      } catch (InvalidCertificateException e) {
+        // good
+      }
+    }
+  }
+
   public void testExpiredSignature() throws InvalidCertificateException, InvalidKeyException {
     ECKeyPair serverKey = Curve.generateKeyPair();
     ECKeyPair key       = Curve.generateKeyPair();
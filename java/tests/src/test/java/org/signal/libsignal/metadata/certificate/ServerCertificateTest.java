@@ -58,6 +58,25 @@ public class ServerCertificateTest extends TestCase {
     new CertificateValidator(trustRoot.getPublicKey()).validate(new ServerCertificate(serialized));
   }
 
+  public void testIssuedCertificate() throws InvalidKeyException, InvalidCertificateException {
+    ECKeyPair trustRoot = Curve.generateKeyPair();
+    ECKeyPair keyPair   = Curve.generateKeyPair();
+
+    ServerCertificate certificate = new ServerCertificate(trustRoot.getPrivateKey(), 7, keyPair.getPublicKey());
+    ServerCertificate parsed      = new ServerCertificate(certificate.getSerialized());
+
+    assertEquals(7, parsed.getKeyId());
+    assertEquals(keyPair.getPublicKey(), parsed.getKey());
+    new CertificateValidator(trustRoot.getPublicKey()).validate(parsed);
+
+    try {
+      new CertificateValidator(Curve.generateKeyPair().getPublicKey()).validate(parsed);
+      throw new AssertionError();
+    } catch (InvalidCertificateException e) {
+      // good
+    }
+  }
+
   public void testBadSignature() throws Exception {
     ECKeyPair trustRoot = Curve.generateKeyPair();
     ECKeyPair keyPair   = Curve.generateKeyPair();
@@ -4,6 +4,7 @@ import junit.framework.TestCase;
 
 import org.whispersystems.libsignal.ecc.Curve;
 import org.whispersystems.libsignal.ecc.ECKeyPair;
+import org.whispersystems.libsignal.ecc.ECPublicKey;
 import org.whispersystems.libsignal.protocol.CiphertextMessage;
 import org.whispersystems.libsignal.protocol.PreKeySignalMessage;
 import org.whispersystems.libsignal.protocol.SignalMessage;
@@ -12,16 +13,80 @@ import org.whispersystems.libsignal.state.PreKeyBundle;
 import org.whispersystems.libsignal.state.PreKeyRecord;
 import org.whispersystems.libsignal.state.SignalProtocolStore;
 import org.whispersystems.libsignal.state.SignedPreKeyRecord;
+import org.whispersystems.libsignal.state.StoreOp;
+import org.whispersystems.libsignal.state.impl.InMemorySignalProtocolStore;
 import org.whispersystems.libsignal.util.Pair;
 
+import java.util.ArrayList;
 import java.util.HashSet;
+import java.util.List;
 import java.util.Set;
+import java.util.concurrent.atomic.AtomicInteger;
 
 public class SessionBuilderTest extends TestCase {
 
   private static final SignalProtocolAddress ALICE_ADDRESS = new SignalProtocolAddress("+14151111111", 1);
   private static final SignalProtocolAddress BOB_ADDRESS   = new SignalProtocolAddress("+14152222222", 1);
 
+  public void testProcessWithReport() throws InvalidKeyException, UntrustedIdentityException, InvalidMessageException, InvalidVersionException {
+    SignalProtocolStore aliceStore = new TestInMemorySignalProtocolStore();
+    SignalProtocolStore bobStore   = new TestInMemorySignalProtocolStore();
+
+    ECKeyPair bobPreKeyPair            = Curve.generateKeyPair();
+    ECKeyPair bobSignedPreKeyPair      = Curve.generateKeyPair();
+    byte[]    bobSignedPreKeySignature = Curve.calculateSignature(bobStore.getIdentityKeyPair().getPrivateKey(),
+                                                                  bobSignedPreKeyPair.getPublicKey().serialize());
+
+    PreKeyBundle withPreKey = new PreKeyBundle(bobStore.getLocalRegistrationId(), 1,
+                                               31337, bobPreKeyPair.getPublicKey(),
+                                               22, bobSignedPreKeyPair.getPublicKey(),
+                                               bobSignedPreKeySignature,
+                                               bobStore.getIdentityKeyPair().getPublicKey());
+
+    PreKeyBundleReport report = new SessionBuilder(aliceStore, BOB_ADDRESS).processWithReport(withPreKey);
+    assertEquals(31337, (int)report.getPreKeyId().get());
+    assertEquals(22, report.getSignedPreKeyId());
+    assertEquals(3, report.getSessionVersion());
+    assertEquals(IdentityKeyStore.IdentityChange.NEW_IDENTITY, report.getIdentityChange());
+
+    PreKeyBundle withoutPreKey = new PreKeyBundle(bobStore.getLocalRegistrationId(), 1,
+                                                  -1, null,
+                                                  22, bobSignedPreKeyPair.getPublicKey(),
+                                                  bobSignedPreKeySignature,
+                                                  bobStore.getIdentityKeyPair().getPublicKey());
+
+    report = new SessionBuilder(aliceStore, BOB_ADDRESS).processWithReport(withoutPreKey);
+    assertFalse(report.getPreKeyId().isPresent());
+    assertEquals(22, report.getSignedPreKeyId());
+    assertEquals(IdentityKeyStore.IdentityChange.UNCHANGED, report.getIdentityChange());
+
+    EncryptedMessage encrypted = new SessionCipher(aliceStore, BOB_ADDRESS).encryptWithDetails("hi".getBytes());
+    assertEquals(CiphertextMessage.PREKEY_TYPE, encrypted.getType());
+    assertEquals(bobStore.getLocalRegistrationId(), encrypted.getRemoteRegistrationId());
+    new PreKeySignalMessage(encrypted.serialize());
+  }
+
+  public void testRegistrationIdRange() throws InvalidKeyException {
+    SignalProtocolStore bobStore                 = new TestInMemorySignalProtocolStore();
+    ECKeyPair           bobSignedPreKeyPair      = Curve.generateKeyPair();
+    byte[]              bobSignedPreKeySignature = Curve.calculateSignature(bobStore.getIdentityKeyPair().getPrivateKey(),
+                                                                            bobSignedPreKeyPair.getPublicKey().serialize());
+
+    PreKeyBundle bundle = new PreKeyBundle(16383, 1, -1, null,
+                                           22, bobSignedPreKeyPair.getPublicKey(), bobSignedPreKeySignature,
+                                           bobStore.getIdentityKeyPair().getPublicKey());
+    assertEquals(16383, bundle.getRegistrationId());
+
+    try {
+      new PreKeyBundle(16384, 1, -1, null,
+                       22, bobSignedPreKeyPair.getPublicKey(), bobSignedPreKeySignature,
+                       bobStore.getIdentityKeyPair().getPublicKey());
+      fail();
+    } catch (IllegalArgumentException e) {
+      // good
+    }
+  }
+
   public void testBasicPreKeyV3()
       throws InvalidKeyException, InvalidVersionException, InvalidMessageException, InvalidKeyIdException, DuplicateMessageException, LegacyMessageException, UntrustedIdentityException, NoSessionException {
     SignalProtocolStore aliceStore          = new TestInMemorySignalProtocolStore();
@@ -39,7 +104,7 @@ public class SessionBuilderTest extends TestCase {
                                               bobSignedPreKeySignature,
                                               bobStore.getIdentityKeyPair().getPublicKey());
 
-    aliceSessionBuilder.process(bobPreKey);
+    assertEquals(IdentityKeyStore.IdentityChange.NEW_IDENTITY, aliceSessionBuilder.process(bobPreKey));
 
     assertTrue(aliceStore.containsSession(BOB_ADDRESS));
     assertTrue(aliceStore.loadSession(BOB_ADDRESS).getSessionState().getSessionVersion() == 3);
@@ -92,7 +157,9 @@ public class SessionBuilderTest extends TestCase {
       plaintext = bobSessionCipher.decrypt(new PreKeySignalMessage(outgoingMessage.serialize()));
       throw new AssertionError("shouldn't be trusted!");
     } catch (UntrustedIdentityException uie) {
-      bobStore.saveIdentity(ALICE_ADDRESS, new PreKeySignalMessage(outgoingMessage.serialize()).getIdentityKey());
+      IdentityKey newAliceIdentity = new PreKeySignalMessage(outgoingMessage.serialize()).getIdentityKey();
+      assertEquals(IdentityKeyStore.IdentityChange.REPLACED_EXISTING, bobStore.saveIdentity(ALICE_ADDRESS, newAliceIdentity));
+      assertEquals(IdentityKeyStore.IdentityChange.UNCHANGED, bobStore.saveIdentity(ALICE_ADDRESS, newAliceIdentity));
     }
 
     plaintext = bobSessionCipher.decrypt(new PreKeySignalMessage(outgoingMessage.serialize()));
@@ -299,6 +366,126 @@ public class SessionBuilderTest extends TestCase {
     assertTrue(originalMessage.equals(new String(plaintext)));
   }
 
+  public void testExternalIdentityKey() throws Exception {
+    SignalProtocolStore aliceStore          = new TestInMemorySignalProtocolStore();
+    SessionBuilder      aliceSessionBuilder = new SessionBuilder(aliceStore, BOB_ADDRESS);
+
+    final ECKeyPair     bobIdentityKeyPair = Curve.generateKeyPair();
+    final AtomicInteger agreements         = new AtomicInteger();
+    IdentityKeyOperations bobIdentityOperations = new IdentityKeyOperations() {
+      @Override
+      public byte[] calculateSignature(byte[] message) {
+        return bobIdentityKeyPair.getPrivateKey().calculateSignature(message);
+      }
+
+      @Override
+      public byte[] calculateAgreement(ECPublicKey theirKey) {
+        agreements.incrementAndGet();
+        return bobIdentityKeyPair.getPrivateKey().calculateAgreement(theirKey);
+      }
+    };
+    IdentityKeyPair     bobIdentityKey = new IdentityKeyPair(new IdentityKey(bobIdentityKeyPair.getPublicKey()), bobIdentityOperations);
+    SignalProtocolStore bobStore       = new InMemorySignalProtocolStore(bobIdentityKey, 1);
+
+    try {
+      bobIdentityKey.getPrivateKey();
+      fail();
+    } catch (UnsupportedOperationException e) {
+      // good
+    }
+
+    ECKeyPair bobSignedPreKeyPair      = Curve.generateKeyPair();
+    byte[]    bobSignedPreKeySignature = bobIdentityOperations.calculateSignature(bobSignedPreKeyPair.getPublicKey().serialize());
+
+    PreKeyBundle bobPreKey = new PreKeyBundle(bobStore.getLocalRegistrationId(), 1,
+                                              -1, null,
+                                              22, bobSignedPreKeyPair.getPublicKey(),
+                                              bobSignedPreKeySignature,
+                                              bobIdentityKey.getPublicKey());
+    bobStore.storeSignedPreKey(22, new SignedPreKeyRecord(22, System.currentTimeMillis(), bobSignedPreKeyPair, bobSignedPreKeySignature));
+
+    aliceSessionBuilder.process(bobPreKey);
+
+    String            originalMessage    = "Good, fast, cheap: pick two";
+    SessionCipher     aliceSessionCipher = new SessionCipher(aliceStore, BOB_ADDRESS);
+    CiphertextMessage outgoingMessage    = aliceSessionCipher.encrypt(originalMessage.getBytes());
+
+    SessionCipher bobSessionCipher = new SessionCipher(bobStore, ALICE_ADDRESS);
+    byte[]        plaintext        = bobSessionCipher.decrypt(new PreKeySignalMessage(outgoingMessage.serialize()));
+
+    assertTrue(originalMessage.equals(new String(plaintext)));
+    assertEquals(1, agreements.get());
+
+    CiphertextMessage bobOutgoingMessage = bobSessionCipher.encrypt(originalMessage.getBytes());
+    byte[]            alicePlaintext     = aliceSessionCipher.decrypt(new SignalMessage(bobOutgoingMessage.serialize()));
+
+    assertTrue(originalMessage.equals(new String(alicePlaintext)));
+  }
+
+  public void testDecryptionCommitsWritesTogether() throws Exception {
+    SignalProtocolStore aliceStore          = new TestInMemorySignalProtocolStore();
+    SessionBuilder      aliceSessionBuilder = new SessionBuilder(aliceStore, BOB_ADDRESS);
+
+    final List<StoreOp[]> commits  = new ArrayList<>();
+    SignalProtocolStore   bobStore = new TestInMemorySignalProtocolStore() {
+      @Override
+      public void commit(StoreOp[] ops) {
+        commits.add(ops);
+        super.commit(ops);
+      }
+    };
+
+    ECKeyPair bobPreKeyPair            = Curve.generateKeyPair();
+    ECKeyPair bobSignedPreKeyPair      = Curve.generateKeyPair();
+    byte[]    bobSignedPreKeySignature = Curve.calculateSignature(bobStore.getIdentityKeyPair().getPrivateKey(),
+                                                                  bobSignedPreKeyPair.getPublicKey().serialize());
+
+    PreKeyBundle bobPreKey = new PreKeyBundle(bobStore.getLocalRegistrationId(), 1,
+                                              31337, bobPreKeyPair.getPublicKey(),
+                                              22, bobSignedPreKeyPair.getPublicKey(),
+                                              bobSignedPreKeySignature,
+                                              bobStore.getIdentityKeyPair().getPublicKey());
+
+    bobStore.storePreKey(31337, new PreKeyRecord(31337, bobPreKeyPair));
+    bobStore.storeSignedPreKey(22, new SignedPreKeyRecord(22, System.currentTimeMillis(), bobSignedPreKeyPair, bobSignedPreKeySignature));
+
+    aliceSessionBuilder.process(bobPreKey);
+
+    SessionCipher     aliceSessionCipher = new SessionCipher(aliceStore, BOB_ADDRESS);
+    CiphertextMessage outgoingMessage    = aliceSessionCipher.encrypt("hello".getBytes());
+
+    SessionCipher bobSessionCipher = new SessionCipher(bobStore, ALICE_ADDRESS);
+    byte[]        plaintext        = bobSessionCipher.decrypt(new PreKeySignalMessage(outgoingMessage.serialize()));
+    assertEquals("hello", new String(plaintext));
+
+    assertEquals(1, commits.size());
+    StoreOp[] ops = commits.get(0);
+    assertEquals(3, ops.length);
+    assertEquals(StoreOp.Type.SAVE_IDENTITY, ops[0].getType());
+    assertEquals(ALICE_ADDRESS, ops[0].getAddress());
+    assertEquals(aliceStore.getIdentityKeyPair().getPublicKey(), ops[0].getIdentityKey());
+    assertEquals(StoreOp.Type.STORE_SESSION, ops[1].getType());
+    assertEquals(ALICE_ADDRESS, ops[1].getAddress());
+    assertEquals(StoreOp.Type.REMOVE_PRE_KEY, ops[2].getType());
+    assertEquals(31337, ops[2].getPreKeyId());
+
+    assertTrue(bobStore.containsSession(ALICE_ADDRESS));
+    assertFalse(bobStore.containsPreKey(31337));
+
+    CiphertextMessage reply = bobSessionCipher.encrypt("hi".getBytes());
+    aliceSessionCipher.decrypt(new SignalMessage(reply.serialize()));
+
+    CiphertextMessage followUp = aliceSessionCipher.encrypt("again".getBytes());
+    plaintext = bobSessionCipher.decrypt(new SignalMessage(followUp.serialize()));
+    assertEquals("again", new String(plaintext));
+
+    assertEquals(2, commits.size());
+    ops = commits.get(1);
+    assertEquals(2, ops.length);
+    assertEquals(StoreOp.Type.SAVE_IDENTITY, ops[0].getType());
+    assertEquals(StoreOp.Type.STORE_SESSION, ops[1].getType());
+  }
+
 
   private void runInteraction(SignalProtocolStore aliceStore, SignalProtocolStore bobStore)
       throws DuplicateMessageException, LegacyMessageException, InvalidMessageException, NoSessionException, UntrustedIdentityException
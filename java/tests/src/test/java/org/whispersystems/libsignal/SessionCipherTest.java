@@ -20,6 +20,10 @@ import java.util.Collections;
 import java.util.LinkedList;
 import java.util.List;
 import java.util.Random;
+import java.util.concurrent.ExecutionException;
+import java.util.concurrent.Executor;
+import java.util.concurrent.atomic.AtomicBoolean;
+import java.util.concurrent.atomic.AtomicInteger;
 
 
 public class SessionCipherTest extends TestCase {
@@ -63,7 +67,72 @@ public class SessionCipherTest extends TestCase {
       bobCipher.decrypt(new SignalMessage(inflight.get(0).serialize()));
       throw new AssertionError("Should have failed!");
     } catch (DuplicateMessageException dme) {
-      // good
+      assertEquals(0, dme.getCounter());
+      assertEquals(2010, dme.getChainIndex());
+    }
+  }
+
+  public void testStoreExceptionIsKeptAsCause() throws UntrustedIdentityException {
+    final IllegalStateException thrown = new IllegalStateException("store unavailable");
+    SignalProtocolStore store = new TestInMemorySignalProtocolStore() {
+      @Override
+      public SessionRecord loadSession(SignalProtocolAddress address) {
+        throw thrown;
+      }
+    };
+    SessionCipher cipher = new SessionCipher(store, new SignalProtocolAddress("+14159999999", 1));
+
+    try {
+      cipher.encrypt("hello".getBytes());
+      fail("expected the store's exception");
+    } catch (RuntimeException e) {
+      assertSame(thrown, e.getCause());
+    }
+  }
+
+  public void testAsyncStoreCallsRunOnExecutor() throws InterruptedException {
+    if (!hasCompletableFuture()) {
+      return;
+    }
+
+    final IllegalStateException thrown = new IllegalStateException("store unavailable");
+    SignalProtocolStore store = new TestInMemorySignalProtocolStore() {
+      @Override
+      public SessionRecord loadSession(SignalProtocolAddress address) {
+        throw thrown;
+      }
+    };
+    final AtomicInteger executed    = new AtomicInteger();
+    final AtomicBoolean lockWasHeld = new AtomicBoolean(true);
+    Executor executor = new Executor() {
+      @Override
+      public void execute(Runnable command) {
+        executed.incrementAndGet();
+        // This executor runs each store call on the native thread, which holds the lock.
+        if (!Thread.holdsLock(SessionCipher.SESSION_LOCK)) {
+          lockWasHeld.set(false);
+        }
+        command.run();
+      }
+    };
+    SessionCipher cipher = new SessionCipher(store, new SignalProtocolAddress("+14159999999", 1));
+
+    try {
+      cipher.encryptAsync("hello".getBytes(), executor).get();
+      fail("expected the store's exception");
+    } catch (ExecutionException e) {
+      assertSame(thrown, e.getCause().getCause());
+    }
+    assertTrue(executed.get() > 0);
+    assertTrue(lockWasHeld.get());
+  }
+
+  private static boolean hasCompletableFuture() {
+    try {
+      Class.forName("java.util.concurrent.CompletableFuture");
+      return true;
+    } catch (ClassNotFoundException e) {
+      return false;
     }
   }
 
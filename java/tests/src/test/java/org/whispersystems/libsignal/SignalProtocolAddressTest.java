@@ -0,0 +1,41 @@
+package org.whispersystems.libsignal;
+
+import junit.framework.TestCase;
+
+import java.util.UUID;
+
+public class SignalProtocolAddressTest extends TestCase {
+
+  private static final UUID UUID_1 = UUID.fromString("9d0652a3-dcc3-4d11-975f-74d61598733f");
+
+  public void testServiceIdAddresses() {
+    SignalProtocolAddress aci = new SignalProtocolAddress(ServiceId.aci(UUID_1), 1);
+    SignalProtocolAddress pni = new SignalProtocolAddress(ServiceId.pni(UUID_1), 2);
+
+    assertEquals("9d0652a3-dcc3-4d11-975f-74d61598733f", aci.getName());
+    assertEquals("PNI:9d0652a3-dcc3-4d11-975f-74d61598733f", pni.getName());
+    assertEquals(2, pni.getDeviceId());
+
+    assertEquals(ServiceId.aci(UUID_1), aci.getServiceId().get());
+    assertEquals(ServiceId.pni(UUID_1), pni.getServiceId().get());
+    assertEquals(ServiceId.pni(UUID_1), new SignalProtocolAddress(pni.getName(), 2).getServiceId().get());
+  }
+
+  public void testDeviceIdRange() {
+    assertEquals(127, new SignalProtocolAddress("+14152222222", 127).getDeviceId());
+
+    for (int deviceId : new int[] {0, 128}) {
+      try {
+        new SignalProtocolAddress("+14152222222", deviceId);
+        fail("device id " + deviceId + " should have been rejected");
+      } catch (IllegalArgumentException e) {
+        // good
+      }
+    }
+  }
+
+  public void testNonServiceIdNames() {
+    assertFalse(new SignalProtocolAddress("+14152222222", 1).getServiceId().isPresent());
+    assertFalse(new SignalProtocolAddress("PNI:9d0652a3-dcc3-4d11-975f-74d6159873", 1).getServiceId().isPresent());
+  }
+}
@@ -17,7 +17,7 @@ public class TestInMemoryIdentityKeyStore extends org.whispersystems.libsignal.s
   }
 
   private static int generateRegistrationId() {
-    return KeyHelper.generateRegistrationId(false);
+    return KeyHelper.generateRegistrationId();
   }
 
 }
@@ -18,6 +18,6 @@ public class TestInMemorySignalProtocolStore extends InMemorySignalProtocolStore
   }
 
   private static int generateRegistrationId() {
-    return KeyHelper.generateRegistrationId(false);
+    return KeyHelper.generateRegistrationId();
   }
 }
@@ -23,6 +23,13 @@ public class NumericFingerprintGeneratorTest extends TestCase {
   private static final byte[] ALICE_SCANNABLE_FINGERPRINT_V2 = new byte[]{(byte)0x08, (byte)0x02, (byte)0x12, (byte)0x22, (byte)0x0a, (byte)0x20, (byte)0x1e, (byte)0x30, (byte)0x1a, (byte)0x03, (byte)0x53, (byte)0xdc, (byte)0xe3, (byte)0xdb, (byte)0xe7, (byte)0x68, (byte)0x4c, (byte)0xb8, (byte)0x33, (byte)0x6e, (byte)0x85, (byte)0x13, (byte)0x6c, (byte)0xdc, (byte)0x0e, (byte)0xe9, (byte)0x62, (byte)0x19, (byte)0x49, (byte)0x4a, (byte)0xda, (byte)0x30, (byte)0x5d, (byte)0x62, (byte)0xa7, (byte)0xbd, (byte)0x61, (byte)0xdf, (byte)0x1a, (byte)0x22, (byte)0x0a, (byte)0x20, (byte)0xd6, (byte)0x2c, (byte)0xbf, (byte)0x73, (byte)0xa1, (byte)0x15, (byte)0x92, (byte)0x01, (byte)0x5b, (byte)0x6b, (byte)0x9f, (byte)0x16, (byte)0x82, (byte)0xac, (byte)0x30, (byte)0x6f, (byte)0xea, (byte)0x3a, (byte)0xaf, (byte)0x38, (byte)0x85, (byte)0xb8, (byte)0x4d, (byte)0x12, (byte)0xbc, (byte)0xa6, (byte)0x31, (byte)0xe9, (byte)0xd4, (byte)0xfb, (byte)0x3a, (byte)0x4d};
   private static final byte[] BOB_SCANNABLE_FINGERPRINT_V2   = new byte[]{(byte)0x08, (byte)0x02, (byte)0x12, (byte)0x22, (byte)0x0a, (byte)0x20, (byte)0xd6, (byte)0x2c, (byte)0xbf, (byte)0x73, (byte)0xa1, (byte)0x15, (byte)0x92, (byte)0x01, (byte)0x5b, (byte)0x6b, (byte)0x9f, (byte)0x16, (byte)0x82, (byte)0xac, (byte)0x30, (byte)0x6f, (byte)0xea, (byte)0x3a, (byte)0xaf, (byte)0x38, (byte)0x85, (byte)0xb8, (byte)0x4d, (byte)0x12, (byte)0xbc, (byte)0xa6, (byte)0x31, (byte)0xe9, (byte)0xd4, (byte)0xfb, (byte)0x3a, (byte)0x4d, (byte)0x1a, (byte)0x22, (byte)0x0a, (byte)0x20, (byte)0x1e, (byte)0x30, (byte)0x1a, (byte)0x03, (byte)0x53, (byte)0xdc, (byte)0xe3, (byte)0xdb, (byte)0xe7, (byte)0x68, (byte)0x4c, (byte)0xb8, (byte)0x33, (byte)0x6e, (byte)0x85, (byte)0x13, (byte)0x6c, (byte)0xdc, (byte)0x0e, (byte)0xe9, (byte)0x62, (byte)0x19, (byte)0x49, (byte)0x4a, (byte)0xda, (byte)0x30, (byte)0x5d, (byte)0x62, (byte)0xa7, (byte)0xbd, (byte)0x61, (byte)0xdf};
 
+  private static final byte[] ALICE_UUID = "6a3d4c8e-1f0b-4d2a-9c5e-7b8f0a1d2e3c".getBytes();
+  private static final byte[] BOB_UUID   = "0f9e8d7c-6b5a-4e3d-8c2b-1a0f9e8d7c6b".getBytes();
+
+  private static final String DISPLAYABLE_FINGERPRINT_MULTI     = "632860439795916348638199572294663952284327893052686922753464";
+  private static final byte[] ALICE_SCANNABLE_FINGERPRINT_MULTI = new byte[]{(byte)0x08, (byte)0x03, (byte)0x12, (byte)0x22, (byte)0x0a, (byte)0x20, (byte)0x52, (byte)0x52, (byte)0xe0, (byte)0x81, (byte)0xd6, (byte)0x6d, (byte)0x9f, (byte)0xa7, (byte)0xb8, (byte)0xad, (byte)0x67, (byte)0x97, (byte)0xc3, (byte)0x62, (byte)0xcc, (byte)0x7a, (byte)0xd3, (byte)0x05, (byte)0x11, (byte)0x8f, (byte)0xf6, (byte)0x6f, (byte)0x9f, (byte)0x8c, (byte)0x8b, (byte)0xfd, (byte)0xdc, (byte)0x5c, (byte)0x73, (byte)0x86, (byte)0xd5, (byte)0x34, (byte)0x1a, (byte)0x22, (byte)0x0a, (byte)0x20, (byte)0x13, (byte)0x17, (byte)0x4c, (byte)0x79, (byte)0x5b, (byte)0xc7, (byte)0xaf, (byte)0x43, (byte)0x6a, (byte)0x5b, (byte)0x20, (byte)0xc3, (byte)0x18, (byte)0xad, (byte)0x95, (byte)0x70, (byte)0x7f, (byte)0xd6, (byte)0x90, (byte)0x74, (byte)0x23, (byte)0x8b, (byte)0x78, (byte)0x40, (byte)0x8b, (byte)0x43, (byte)0xb0, (byte)0x8e, (byte)0xbd, (byte)0x78, (byte)0x44, (byte)0x81};
+  private static final byte[] BOB_SCANNABLE_FINGERPRINT_MULTI   = new byte[]{(byte)0x08, (byte)0x03, (byte)0x12, (byte)0x22, (byte)0x0a, (byte)0x20, (byte)0x13, (byte)0x17, (byte)0x4c, (byte)0x79, (byte)0x5b, (byte)0xc7, (byte)0xaf, (byte)0x43, (byte)0x6a, (byte)0x5b, (byte)0x20, (byte)0xc3, (byte)0x18, (byte)0xad, (byte)0x95, (byte)0x70, (byte)0x7f, (byte)0xd6, (byte)0x90, (byte)0x74, (byte)0x23, (byte)0x8b, (byte)0x78, (byte)0x40, (byte)0x8b, (byte)0x43, (byte)0xb0, (byte)0x8e, (byte)0xbd, (byte)0x78, (byte)0x44, (byte)0x81, (byte)0x1a, (byte)0x22, (byte)0x0a, (byte)0x20, (byte)0x52, (byte)0x52, (byte)0xe0, (byte)0x81, (byte)0xd6, (byte)0x6d, (byte)0x9f, (byte)0xa7, (byte)0xb8, (byte)0xad, (byte)0x67, (byte)0x97, (byte)0xc3, (byte)0x62, (byte)0xcc, (byte)0x7a, (byte)0xd3, (byte)0x05, (byte)0x11, (byte)0x8f, (byte)0xf6, (byte)0x6f, (byte)0x9f, (byte)0x8c, (byte)0x8b, (byte)0xfd, (byte)0xdc, (byte)0x5c, (byte)0x73, (byte)0x86, (byte)0xd5, (byte)0x34};
+
   public void testVectorsVersion1() throws Exception {
     IdentityKey aliceIdentityKey = new IdentityKey(ALICE_IDENTITY, 0);
     IdentityKey bobIdentityKey   = new IdentityKey(BOB_IDENTITY, 0);
@@ -69,6 +76,61 @@ public class NumericFingerprintGeneratorTest extends TestCase {
     assertTrue(Arrays.equals(bobFingerprint.getScannableFingerprint().getSerialized(), BOB_SCANNABLE_FINGERPRINT_V2));
   }
 
+  public void testMultiIdentifierVectors() throws Exception {
+    IdentityKey aliceIdentityKey = new IdentityKey(ALICE_IDENTITY, 0);
+    IdentityKey bobIdentityKey   = new IdentityKey(BOB_IDENTITY, 0);
+    byte[]      aliceStableId    = "+14152222222".getBytes();
+    byte[]      bobStableId      = "+14153333333".getBytes();
+
+    NumericFingerprintGenerator generator = new NumericFingerprintGenerator(5200);
+
+    Fingerprint aliceFingerprint = generator.createMultiFor(new byte[][]{ALICE_UUID, aliceStableId}, aliceIdentityKey,
+                                                            new byte[][]{bobStableId, BOB_UUID}, bobIdentityKey);
+
+    Fingerprint bobFingerprint = generator.createMultiFor(new byte[][]{bobStableId, BOB_UUID}, bobIdentityKey,
+                                                          new byte[][]{aliceStableId, ALICE_UUID}, aliceIdentityKey);
+
+    assertEquals(aliceFingerprint.getDisplayableFingerprint().getDisplayText(), DISPLAYABLE_FINGERPRINT_MULTI);
+    assertEquals(bobFingerprint.getDisplayableFingerprint().getDisplayText(), DISPLAYABLE_FINGERPRINT_MULTI);
+
+    assertTrue(Arrays.equals(aliceFingerprint.getScannableFingerprint().getSerialized(), ALICE_SCANNABLE_FINGERPRINT_MULTI));
+    assertTrue(Arrays.equals(bobFingerprint.getScannableFingerprint().getSerialized(), BOB_SCANNABLE_FINGERPRINT_MULTI));
+  }
+
+  public void testDisplayableOnlyVectors() throws Exception {
+    IdentityKey aliceIdentityKey = new IdentityKey(ALICE_IDENTITY, 0);
+    IdentityKey bobIdentityKey   = new IdentityKey(BOB_IDENTITY, 0);
+    byte[]      aliceStableId    = "+14152222222".getBytes();
+    byte[]      bobStableId      = "+14153333333".getBytes();
+
+    NumericFingerprintGenerator generator = new NumericFingerprintGenerator(5200);
+
+    DisplayableFingerprint aliceFingerprint = generator.createDisplayableFor(aliceStableId, aliceIdentityKey,
+                                                                             bobStableId, bobIdentityKey);
+
+    DisplayableFingerprint bobFingerprint = generator.createDisplayableFor(bobStableId, bobIdentityKey,
+                                                                           aliceStableId, aliceIdentityKey);
+
+    assertEquals(aliceFingerprint.getDisplayText(), DISPLAYABLE_FINGERPRINT_V1);
+    assertEquals(bobFingerprint.getDisplayText(), DISPLAYABLE_FINGERPRINT_V1);
+  }
+
+  public void testRejectsLowIterationCounts() throws Exception {
+    IdentityKey aliceIdentityKey = new IdentityKey(ALICE_IDENTITY, 0);
+    IdentityKey bobIdentityKey   = new IdentityKey(BOB_IDENTITY, 0);
+    byte[]      aliceStableId    = "+14152222222".getBytes();
+    byte[]      bobStableId      = "+14153333333".getBytes();
+
+    NumericFingerprintGenerator generator = new NumericFingerprintGenerator(999);
+
+    try {
+      generator.createDisplayableFor(aliceStableId, aliceIdentityKey, bobStableId, bobIdentityKey);
+      fail("iteration count should have been rejected");
+    } catch (IllegalArgumentException e) {
+      // good
+    }
+  }
+
   public void testMatchingFingerprints() throws FingerprintVersionMismatchException, FingerprintIdentifierMismatchException, FingerprintParsingException {
     ECKeyPair aliceKeyPair = Curve.generateKeyPair();
     ECKeyPair bobKeyPair   = Curve.generateKeyPair();
@@ -166,4 +228,46 @@ public class NumericFingerprintGeneratorTest extends TestCase {
                               aliceFingerprintV2.getScannableFingerprint().getSerialized()));
   }
 
+  public void testCompareScannedReportsReason() throws Exception {
+    IdentityKey aliceIdentityKey = new IdentityKey(ALICE_IDENTITY, 0);
+    IdentityKey bobIdentityKey   = new IdentityKey(BOB_IDENTITY, 0);
+    IdentityKey mitmIdentityKey  = new IdentityKey(Curve.generateKeyPair().getPublicKey());
+    byte[]      aliceStableId    = "+14152222222".getBytes();
+    byte[]      bobStableId      = "+14153333333".getBytes();
+
+    NumericFingerprintGenerator generator = new NumericFingerprintGenerator(1024);
+
+    Fingerprint aliceFingerprint = generator.createFor(VERSION_1,
+                                                       aliceStableId, aliceIdentityKey,
+                                                       bobStableId, mitmIdentityKey);
+
+    Fingerprint bobFingerprint = generator.createFor(VERSION_1,
+                                                     bobStableId, bobIdentityKey,
+                                                     aliceStableId, aliceIdentityKey);
+
+    assertEquals(FingerprintComparison.REMOTE_MISMATCH,
+                 aliceFingerprint.getScannableFingerprint().compareScanned(bobFingerprint.getScannableFingerprint().getSerialized()));
+    assertEquals(FingerprintComparison.LOCAL_MISMATCH,
+                 bobFingerprint.getScannableFingerprint().compareScanned(aliceFingerprint.getScannableFingerprint().getSerialized()));
+
+    Fingerprint bobFingerprintV2 = generator.createFor(VERSION_2,
+                                                       bobStableId, bobIdentityKey,
+                                                       aliceStableId, aliceIdentityKey);
+
+    try {
+      aliceFingerprint.getScannableFingerprint().compareScanned(bobFingerprintV2.getScannableFingerprint().getSerialized());
+      fail("version mismatch should have been reported");
+    } catch (FingerprintVersionMismatchException e) {
+      assertEquals(VERSION_2, e.getTheirVersion());
+      assertEquals(VERSION_1, e.getOurVersion());
+    }
+
+    try {
+      aliceFingerprint.getScannableFingerprint().compareScanned(new byte[] {0x08, 0x01});
+      fail("incomplete fingerprint should have been rejected");
+    } catch (FingerprintParsingException e) {
+      // good
+    }
+  }
+
 }
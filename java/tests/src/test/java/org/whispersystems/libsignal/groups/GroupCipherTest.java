@@ -4,9 +4,11 @@ import junit.framework.TestCase;
 
 import org.whispersystems.libsignal.SignalProtocolAddress;
 import org.whispersystems.libsignal.DuplicateMessageException;
+import org.whispersystems.libsignal.InvalidKeyIdException;
 import org.whispersystems.libsignal.InvalidMessageException;
 import org.whispersystems.libsignal.LegacyMessageException;
 import org.whispersystems.libsignal.NoSessionException;
+import org.whispersystems.libsignal.groups.state.SenderKeyRecord;
 import org.whispersystems.libsignal.protocol.SenderKeyDistributionMessage;
 
 import java.security.NoSuchAlgorithmException;
@@ -68,6 +70,79 @@ public class GroupCipherTest extends TestCase {
     assertTrue(new String(plaintextFromAlice).equals("smert ze smert"));
   }
 
+  public void testExpectedDistributionId()
+      throws LegacyMessageException, DuplicateMessageException, InvalidMessageException, NoSessionException, InvalidKeyIdException
+  {
+    InMemorySenderKeyStore aliceStore = new InMemorySenderKeyStore();
+    InMemorySenderKeyStore bobStore   = new InMemorySenderKeyStore();
+
+    GroupSessionBuilder aliceSessionBuilder = new GroupSessionBuilder(aliceStore);
+    GroupSessionBuilder bobSessionBuilder   = new GroupSessionBuilder(bobStore);
+
+    GroupCipher aliceGroupCipher = new GroupCipher(aliceStore, GROUP_SENDER);
+    GroupCipher bobGroupCipher   = new GroupCipher(bobStore, GROUP_SENDER);
+
+    SenderKeyDistributionMessage distributionMessage = aliceSessionBuilder.create(GROUP_SENDER);
+
+    try {
+      bobSessionBuilder.process(GROUP_SENDER, distributionMessage, distributionMessage.getId() ^ 1);
+      throw new AssertionError("Should have rejected the mismatched id!");
+    } catch (InvalidKeyIdException e) {
+      // good
+    }
+    assertEquals(0, bobStore.loadSenderKey(GROUP_SENDER).getStateCount());
+
+    bobSessionBuilder.process(GROUP_SENDER, distributionMessage, distributionMessage.getId());
+
+    byte[] ciphertextFromAlice = aliceGroupCipher.encrypt("smert ze smert".getBytes());
+    byte[] plaintextFromAlice  = bobGroupCipher.decrypt(ciphertextFromAlice);
+
+    assertTrue(new String(plaintextFromAlice).equals("smert ze smert"));
+  }
+
+  public void testEncryptWithMetadata()
+      throws LegacyMessageException, DuplicateMessageException, InvalidMessageException, NoSessionException
+  {
+    InMemorySenderKeyStore aliceStore = new InMemorySenderKeyStore();
+    InMemorySenderKeyStore bobStore   = new InMemorySenderKeyStore();
+
+    GroupSessionBuilder aliceSessionBuilder = new GroupSessionBuilder(aliceStore);
+    GroupSessionBuilder bobSessionBuilder   = new GroupSessionBuilder(bobStore);
+
+    GroupCipher aliceGroupCipher = new GroupCipher(aliceStore, GROUP_SENDER);
+    GroupCipher bobGroupCipher   = new GroupCipher(bobStore, GROUP_SENDER);
+
+    SenderKeyDistributionMessage distributionMessage = aliceSessionBuilder.create(GROUP_SENDER);
+    bobSessionBuilder.process(GROUP_SENDER, distributionMessage);
+
+    GroupEncryptedMessage first  = aliceGroupCipher.encryptWithMetadata("smert ze smert".getBytes());
+    GroupEncryptedMessage second = aliceGroupCipher.encryptWithMetadata("smert ze smert".getBytes());
+
+    assertEquals(distributionMessage.getId(), first.getChainId());
+    assertEquals(distributionMessage.getId(), second.getChainId());
+    assertEquals(first.getIteration() + 1, second.getIteration());
+
+    assertTrue(new String(bobGroupCipher.decrypt(second.serialize())).equals("smert ze smert"));
+  }
+
+  public void testRecordIntrospection()
+      throws LegacyMessageException, DuplicateMessageException, InvalidMessageException, NoSessionException
+  {
+    InMemorySenderKeyStore aliceStore = new InMemorySenderKeyStore();
+    GroupSessionBuilder aliceSessionBuilder = new GroupSessionBuilder(aliceStore);
+    GroupCipher aliceGroupCipher = new GroupCipher(aliceStore, GROUP_SENDER);
+
+    SenderKeyDistributionMessage distributionMessage = aliceSessionBuilder.create(GROUP_SENDER);
+    aliceGroupCipher.encrypt("smert ze smert".getBytes());
+    aliceGroupCipher.encrypt("smert ze smert".getBytes());
+
+    SenderKeyRecord record = aliceStore.loadSenderKey(GROUP_SENDER);
+    assertEquals(1, record.getStateCount());
+    assertTrue(Arrays.equals(new int[] {distributionMessage.getId()}, record.getChainIds()));
+    assertEquals(2, (int) record.getMaxIterationForChain(distributionMessage.getId()).get());
+    assertFalse(record.getMaxIterationForChain(distributionMessage.getId() ^ 1).isPresent());
+  }
+
   public void testLargeMessages() throws InvalidMessageException, LegacyMessageException, NoSessionException, DuplicateMessageException {
     InMemorySenderKeyStore aliceStore = new InMemorySenderKeyStore();
     InMemorySenderKeyStore bobStore   = new InMemorySenderKeyStore();
@@ -1,13 +1,17 @@
 package org.whispersystems.libsignal.groups;
 
+import org.whispersystems.libsignal.SignalProtocolAddress;
+import org.whispersystems.libsignal.groups.state.EnumerableSenderKeyStore;
 import org.whispersystems.libsignal.groups.state.SenderKeyRecord;
-import org.whispersystems.libsignal.groups.state.SenderKeyStore;
 
 import java.io.IOException;
+import java.util.ArrayList;
 import java.util.HashMap;
+import java.util.Iterator;
+import java.util.List;
 import java.util.Map;
 
-public class InMemorySenderKeyStore implements SenderKeyStore {
+public class InMemorySenderKeyStore implements EnumerableSenderKeyStore {
 
   private final Map<SenderKeyName, SenderKeyRecord> store = new HashMap<>();
 
@@ -30,4 +34,28 @@ public class InMemorySenderKeyStore implements SenderKeyStore {
       throw new AssertionError(e);
     }
   }
+
+  @Override
+  public SignalProtocolAddress[] getSendersForGroup(String groupId) {
+    List<SignalProtocolAddress> senders = new ArrayList<>();
+
+    for (SenderKeyName name : store.keySet()) {
+      if (name.getGroupId().equals(groupId)) {
+        senders.add(name.getSender());
+      }
+    }
+
+    return senders.toArray(new SignalProtocolAddress[0]);
+  }
+
+  @Override
+  public void deleteSenderKeysForGroup(String groupId) {
+    Iterator<SenderKeyName> names = store.keySet().iterator();
+
+    while (names.hasNext()) {
+      if (names.next().getGroupId().equals(groupId)) {
+        names.remove();
+      }
+    }
+  }
 }
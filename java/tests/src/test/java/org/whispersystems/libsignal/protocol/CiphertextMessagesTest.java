@@ -0,0 +1,49 @@
+package org.whispersystems.libsignal.protocol;
+
+import junit.framework.TestCase;
+
+import org.whispersystems.libsignal.IdentityKey;
+import org.whispersystems.libsignal.InvalidMessageException;
+import org.whispersystems.libsignal.LegacyMessageException;
+import org.whispersystems.libsignal.ecc.Curve;
+import org.whispersystems.libsignal.ecc.ECKeyPair;
+import org.whispersystems.libsignal.util.guava.Optional;
+
+import javax.crypto.spec.SecretKeySpec;
+import java.util.Arrays;
+
+public class CiphertextMessagesTest extends TestCase {
+
+  private static void assertRoundTrips(CiphertextMessage message) throws InvalidMessageException, LegacyMessageException {
+    CiphertextMessage deserialized = CiphertextMessages.deserialize(message.getType(), message.serialize());
+    assertEquals(message.getClass(), deserialized.getClass());
+    assertEquals(message.getType(), deserialized.getType());
+    assertTrue(Arrays.equals(message.serialize(), deserialized.serialize()));
+  }
+
+  public void testDeserializeEachType() throws InvalidMessageException, LegacyMessageException {
+    ECKeyPair     keyPair       = Curve.generateKeyPair();
+    IdentityKey   identityKey   = new IdentityKey(keyPair.getPublicKey());
+    SignalMessage signalMessage = new SignalMessage(3, new SecretKeySpec(new byte[32], "HmacSHA256"),
+                                                    keyPair.getPublicKey(), 42, 41, new byte[] {1, 2, 3},
+                                                    identityKey, identityKey);
+
+    assertRoundTrips(signalMessage);
+    assertRoundTrips(new PreKeySignalMessage(3, 365, Optional.<Integer>absent(), 97,
+                                             keyPair.getPublicKey(), identityKey, signalMessage));
+    assertRoundTrips(new SenderKeyMessage(1, 2, new byte[] {1, 2, 3}, keyPair.getPrivateKey()));
+    assertRoundTrips(new SenderKeyDistributionMessage(1, 2, new byte[32], keyPair.getPublicKey()));
+    assertRoundTrips(new PlaintextContent(DecryptionErrorMessage.forOriginalMessage(signalMessage.serialize(),
+                                                                                   CiphertextMessage.WHISPER_TYPE,
+                                                                                   1000, 7)));
+  }
+
+  public void testUnknownType() throws LegacyMessageException {
+    try {
+      CiphertextMessages.deserialize(7, new byte[] {1, 2, 3});
+      fail();
+    } catch (InvalidMessageException e) {
+      // good
+    }
+  }
+}
@@ -0,0 +1,68 @@
+package org.whispersystems.libsignal.protocol;
+
+import junit.framework.TestCase;
+
+import org.whispersystems.libsignal.IdentityKey;
+import org.whispersystems.libsignal.InvalidMessageException;
+import org.whispersystems.libsignal.LegacyMessageException;
+import org.whispersystems.libsignal.ecc.Curve;
+import org.whispersystems.libsignal.ecc.ECKeyPair;
+import org.whispersystems.libsignal.util.guava.Optional;
+
+import javax.crypto.spec.SecretKeySpec;
+import java.util.Arrays;
+
+public class DecryptionErrorMessageTest extends TestCase {
+
+  private static SignalMessage createSignalMessage(ECKeyPair ratchetKeyPair) {
+    SecretKeySpec macKey              = new SecretKeySpec(new byte[32], "HmacSHA256");
+    IdentityKey   senderIdentityKey   = new IdentityKey(Curve.generateKeyPair().getPublicKey());
+    IdentityKey   receiverIdentityKey = new IdentityKey(Curve.generateKeyPair().getPublicKey());
+
+    return new SignalMessage(3, macKey, ratchetKeyPair.getPublicKey(), 42, 41, new byte[] {1, 2, 3},
+                             senderIdentityKey, receiverIdentityKey);
+  }
+
+  public void testForSignalMessage() throws InvalidMessageException, LegacyMessageException {
+    ECKeyPair     ratchetKeyPair = Curve.generateKeyPair();
+    SignalMessage message        = createSignalMessage(ratchetKeyPair);
+
+    DecryptionErrorMessage errorMessage = DecryptionErrorMessage.forOriginalMessage(message.serialize(), CiphertextMessage.WHISPER_TYPE, 1000, 7);
+    assertEquals(ratchetKeyPair.getPublicKey(), errorMessage.getRatchetKey().get());
+    assertEquals(1000, errorMessage.getTimestamp());
+    assertEquals(7, errorMessage.getDeviceId());
+
+    DecryptionErrorMessage deserialized = new DecryptionErrorMessage(errorMessage.serialize());
+    assertEquals(ratchetKeyPair.getPublicKey(), deserialized.getRatchetKey().get());
+    assertEquals(1000, deserialized.getTimestamp());
+    assertEquals(7, deserialized.getDeviceId());
+  }
+
+  public void testForPreKeySignalMessage() throws InvalidMessageException, LegacyMessageException {
+    ECKeyPair           ratchetKeyPair = Curve.generateKeyPair();
+    ECKeyPair           baseKeyPair    = Curve.generateKeyPair();
+    IdentityKey         identityKey    = new IdentityKey(Curve.generateKeyPair().getPublicKey());
+    PreKeySignalMessage message        = new PreKeySignalMessage(3, 365, Optional.<Integer>absent(), 97,
+                                                                 baseKeyPair.getPublicKey(), identityKey,
+                                                                 createSignalMessage(ratchetKeyPair));
+
+    DecryptionErrorMessage errorMessage = DecryptionErrorMessage.forOriginalMessage(message.serialize(), CiphertextMessage.PREKEY_TYPE, 1000, 2);
+    assertEquals(ratchetKeyPair.getPublicKey(), errorMessage.getRatchetKey().get());
+    assertEquals(1000, errorMessage.getTimestamp());
+    assertEquals(2, errorMessage.getDeviceId());
+  }
+
+  public void testPlaintextContent() throws InvalidMessageException, LegacyMessageException {
+    ECKeyPair              ratchetKeyPair = Curve.generateKeyPair();
+    SignalMessage          message        = createSignalMessage(ratchetKeyPair);
+    DecryptionErrorMessage errorMessage   = DecryptionErrorMessage.forOriginalMessage(message.serialize(), CiphertextMessage.WHISPER_TYPE, 1000, 7);
+
+    PlaintextContent content = new PlaintextContent(errorMessage);
+    assertEquals(CiphertextMessage.PLAINTEXT_CONTENT_TYPE, content.getType());
+
+    PlaintextContent       deserialized = new PlaintextContent(content.serialize());
+    DecryptionErrorMessage extracted    = DecryptionErrorMessage.extractFromSerializedContent(deserialized.getBody());
+    assertTrue(Arrays.equals(errorMessage.serialize(), extracted.serialize()));
+    assertEquals(ratchetKeyPair.getPublicKey(), extracted.getRatchetKey().get());
+  }
+}
@@ -0,0 +1,40 @@
+package org.whispersystems.libsignal.util;
+
+import junit.framework.TestCase;
+
+import org.whispersystems.libsignal.InvalidMessageException;
+
+import java.util.Arrays;
+
+public class MessagePaddingTest extends TestCase {
+
+  public void testBucketLengths() {
+    assertEquals(159, MessagePadding.pad(new byte[0]).length);
+    assertEquals(159, MessagePadding.pad(new byte[158]).length);
+    assertEquals(319, MessagePadding.pad(new byte[159]).length);
+  }
+
+  public void testRoundTrip() throws InvalidMessageException {
+    byte[] body   = "Hello, world!".getBytes();
+    byte[] padded = MessagePadding.pad(body);
+
+    assertEquals((byte)0x80, padded[body.length]);
+    assertTrue(Arrays.equals(body, MessagePadding.unpad(padded)));
+  }
+
+  public void testMalformedPadding() {
+    try {
+      MessagePadding.unpad(new byte[] {(byte)0x80, 0, 1});
+      fail();
+    } catch (InvalidMessageException e) {
+      // good
+    }
+
+    try {
+      MessagePadding.unpad(new byte[3]);
+      fail();
+    } catch (InvalidMessageException e) {
+      // good
+    }
+  }
+}
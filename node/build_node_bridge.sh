@@ -47,10 +47,13 @@ case ${CONFIGURATION_NAME} in
   Debug )
     CARGO_PROFILE_ARG=
     CARGO_PROFILE_DIR=debug
+    # Debug builds export TestRng for the cross-language test vectors.
+    CARGO_FEATURES_ARG=--features=test-rng
     ;;
   Release )
     CARGO_PROFILE_ARG=--release
     CARGO_PROFILE_DIR=Release
+    CARGO_FEATURES_ARG=
     ;;
   * )
     echo 'error: unexpected CONFIGURATION_NAME:' ${CONFIGURATION_NAME} >&2
@@ -61,7 +64,8 @@ OUT_DIR=${OUT_DIR:-build/${CONFIGURATION_NAME}}
 
 check_rust
 
-echo_then_run cargo build -p libsignal-node ${CARGO_PROFILE_ARG}
+# Use --manifest-path rather than -p so that --features applies to the node crate.
+echo_then_run cargo build --manifest-path rust/bridge/node/Cargo.toml ${CARGO_PROFILE_ARG} ${CARGO_FEATURES_ARG}
 
 for possible_library_name in libsignal_node.dylib libsignal_node.so signal_node.dll; do
   possible_library_path="${CARGO_BUILD_TARGET_DIR:-target}/${CARGO_BUILD_TARGET:-}/${CARGO_PROFILE_DIR}/${possible_library_name}"
@@ -6,6 +6,87 @@
 import bindings = require('bindings'); // eslint-disable-line @typescript-eslint/no-require-imports
 import * as SignalClient from './libsignal_client';
 
-export const { PrivateKey } = bindings(
-  'libsignal_client'
-) as typeof SignalClient;
+const SC = bindings('libsignal_client') as typeof SignalClient;
+
+export type Bytes = SignalClient.Bytes;
+
+export const {
+  PrivateKey,
+  SessionRecord,
+  SenderKeyRecord,
+  PreKeyRecord,
+  SignedPreKeyRecord,
+  SignalMessage,
+  PreKeySignalMessage,
+  DecryptionErrorMessage,
+  PlaintextContent,
+  TestRng,
+  decryptionErrorMessageForOriginal,
+  extractDecryptionErrorMessageFromSerializedContent,
+  sha512,
+  hmacSha512,
+  padMessage,
+  unpadMessage,
+  hkdfDeriveSecrets,
+  deriveUnidentifiedAccessKey,
+  unrestrictedUnidentifiedAccessKey,
+  verifyAlternateIdentity,
+  fingerprint,
+  displayableFingerprint,
+  compareScannableFingerprints,
+} = SC;
+
+export enum CiphertextMessageType {
+  Whisper = 2,
+  PreKey = 3,
+  SenderKey = 4,
+  SenderKeyDistribution = 5,
+  Plaintext = 8,
+}
+
+export enum LogLevel {
+  Error = 1,
+  Warn,
+  Info,
+  Debug,
+  Trace,
+}
+
+export function initLogger(
+  maxLevel: LogLevel,
+  callback: (
+    level: LogLevel,
+    target: string,
+    file: string | null,
+    line: number | null,
+    message: string
+  ) => void
+): void {
+  SC.initLogger(maxLevel, callback);
+}
+
+export function aes256GcmSivEncrypt(
+  ptext: Bytes,
+  key: Bytes,
+  nonce: Bytes,
+  aad: Bytes
+): Promise<Buffer> {
+  return new Promise((resolve, reject) => {
+    SC.aes256GcmSivEncrypt(ptext, key, nonce, aad, (err, ctext) =>
+      err ? reject(err) : resolve(ctext)
+    );
+  });
+}
+
+export function aes256GcmSivDecrypt(
+  ctext: Bytes,
+  key: Bytes,
+  nonce: Bytes,
+  aad: Bytes
+): Promise<Buffer> {
+  return new Promise((resolve, reject) => {
+    SC.aes256GcmSivDecrypt(ctext, key, nonce, aad, (err, ptext) =>
+      err ? reject(err) : resolve(ptext)
+    );
+  });
+}
@@ -3,9 +3,167 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
-/* eslint-disable import/prefer-default-export */
+// Generated by rust/bridge/node/src/bin/gen_ts_decl.rs; do not edit.
+
+export type Bytes = ArrayBuffer | ArrayBufferView;
 
 export class PrivateKey {
-  constructor();
+  constructor(rng?: TestRng);
+  serialize(): Buffer;
+  serializePublicKey(): Buffer;
+  signWithRandomness(message: Bytes, randomness: Bytes): Buffer;
+  signAlternateIdentity(otherIdentityKey: Bytes, rng?: TestRng): Buffer;
+}
+
+export class SessionRecord {
+  constructor(serialized: Bytes);
+  localRegistrationId(): number;
+  remoteRegistrationId(): number;
+  aliceBaseKey(): Buffer;
+  sessionVersion(): number;
+  remoteIdentityKey(): Buffer | null;
+  debugHasUsableSenderChain(): boolean;
+  debugSenderChainCounter(): number | null;
+  debugReceiverChainCounter(theirEphemeral: Bytes): number | null;
+  debugNumberOfReceiverChains(): number;
+}
+
+export class SenderKeyRecord {
+  constructor(serialized: Bytes);
+  serialize(): Buffer;
+  stateCount(): number;
+  chainIds(): number[];
+  maxIterationForChain(chainId: number): number | null;
+}
+
+export class PreKeyRecord {
+  constructor(serialized: Bytes);
+  serialize(): Buffer;
+  id(): number;
+  publicKey(): Buffer;
+  privateKey(): Buffer;
+}
+
+export class SignedPreKeyRecord {
+  constructor(serialized: Bytes);
+  serialize(): Buffer;
+  id(): number;
+  timestamp(): number;
+  signature(): Buffer;
+  publicKey(): Buffer;
+  privateKey(): Buffer;
+}
+
+export class SignalMessage {
+  constructor(serialized: Bytes);
+  serialize(): Buffer;
+  messageVersion(): number;
+  counter(): number;
+  senderRatchetKey(): Buffer;
+  body(): Buffer;
+  verifyMac(
+    senderIdentityKey: Bytes,
+    receiverIdentityKey: Bytes,
+    macKey: Bytes
+  ): boolean;
+}
+
+export class PreKeySignalMessage {
+  constructor(serialized: Bytes);
+  serialize(): Buffer;
+  messageVersion(): number;
+  registrationId(): number;
+  preKeyId(): number | null;
+  signedPreKeyId(): number;
+  baseKey(): Buffer;
+  identityKey(): Buffer;
+}
+
+export class DecryptionErrorMessage {
+  constructor(serialized: Bytes);
+  serialize(): Buffer;
+  timestamp(): number;
+  deviceId(): number;
+  ratchetKey(): Buffer | null;
+  toPlaintextContent(): PlaintextContent;
+}
+
+export class PlaintextContent {
+  constructor(serialized: Bytes);
   serialize(): Buffer;
+  body(): Buffer;
 }
+
+export class TestRng {
+  constructor(seed: number);
+}
+
+export function decryptionErrorMessageForOriginal(
+  originalBytes: Bytes,
+  originalType: number,
+  originalTimestamp: number,
+  originalSenderDeviceId: number
+): DecryptionErrorMessage;
+export function extractDecryptionErrorMessageFromSerializedContent(
+  body: Bytes
+): DecryptionErrorMessage;
+
+export function verifyAlternateIdentity(
+  identityKey: Bytes,
+  otherIdentityKey: Bytes,
+  signature: Bytes
+): boolean;
+
+export function fingerprint(
+  version: number,
+  iterations: number,
+  localIdentifier: Bytes,
+  localKey: Bytes,
+  remoteIdentifier: Bytes,
+  remoteKey: Bytes
+): { displayable: string; scannable: Buffer };
+export function displayableFingerprint(
+  iterations: number,
+  localIdentifier: Bytes,
+  localKey: Bytes,
+  remoteIdentifier: Bytes,
+  remoteKey: Bytes
+): string;
+export function compareScannableFingerprints(
+  ours: Bytes,
+  scanned: Bytes
+): { result: 'match' | 'localMismatch' | 'remoteMismatch' | 'versionMismatch'; ourVersion?: number; theirVersion?: number };
+
+export function sha512(input: Bytes): Buffer;
+export function hmacSha512(key: Bytes, input: Bytes): Buffer;
+export function padMessage(body: Bytes): Buffer;
+export function unpadMessage(padded: Bytes): Buffer;
+export function hkdfDeriveSecrets(
+  outputLength: number,
+  version: number,
+  inputKeyMaterial: Bytes,
+  info: Bytes,
+  salt: Bytes | null
+): Buffer;
+export function deriveUnidentifiedAccessKey(profileKey: Bytes): Buffer;
+export function unrestrictedUnidentifiedAccessKey(rng?: TestRng): Buffer;
+
+export function aes256GcmSivEncrypt(
+  ptext: Bytes,
+  key: Bytes,
+  nonce: Bytes,
+  aad: Bytes,
+  callback: (err: Error | null, ctext: Buffer) => void
+): void;
+export function aes256GcmSivDecrypt(
+  ctext: Bytes,
+  key: Bytes,
+  nonce: Bytes,
+  aad: Bytes,
+  callback: (err: Error | null, ptext: Buffer) => void
+): void;
+
+export function initLogger(
+  maxLevel: number,
+  callback: (level: number, target: string, file: string | null, line: number | null, message: string) => void
+): void;
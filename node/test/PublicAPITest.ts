@@ -4,6 +4,9 @@
 //
 
 import { assert } from 'chai';
+import * as crypto from 'crypto';
+import * as fs from 'fs';
+import * as path from 'path';
 import * as SignalClient from '../index';
 
 describe('SignalClient', () => {
@@ -17,4 +20,541 @@ describe('SignalClient', () => {
       'different for different keys'
     );
   });
+  it('signs deterministically with caller-supplied randomness', () => {
+    const key = new SignalClient.PrivateKey();
+    const message = Buffer.from('message');
+    const randomness = Buffer.alloc(64, 0x5a);
+    const sig = key.signWithRandomness(message, randomness);
+    assert.equal(sig.length, 64, 'correct length');
+    assert(sig.equals(key.signWithRandomness(message, randomness)));
+    assert(!sig.equals(key.signWithRandomness(message, Buffer.alloc(64, 1))));
+    assert.throws(() => key.signWithRandomness(message, Buffer.alloc(32)));
+  });
+  it('signs and verifies alternate identities', () => {
+    const primary = new SignalClient.PrivateKey();
+    const secondary = new SignalClient.PrivateKey();
+    const primaryKey = primary.serializePublicKey();
+    const secondaryKey = secondary.serializePublicKey();
+    assert.equal(primaryKey.length, 33, 'correct length');
+
+    const sig = secondary.signAlternateIdentity(primaryKey);
+    assert(
+      SignalClient.verifyAlternateIdentity(secondaryKey, primaryKey, sig),
+      'valid signature'
+    );
+    assert(
+      !SignalClient.verifyAlternateIdentity(primaryKey, secondaryKey, sig),
+      'not symmetric'
+    );
+    assert.throws(() => secondary.signAlternateIdentity(Buffer.alloc(5)));
+  });
+  it('reports an error for accessors on a fresh SessionRecord', () => {
+    const record = new SignalClient.SessionRecord(Buffer.alloc(0));
+    assert.throws(() => record.localRegistrationId(), /No session/);
+    assert.throws(() => record.remoteRegistrationId(), /No session/);
+    assert.throws(() => record.aliceBaseKey(), /No session/);
+    assert.throws(() => record.sessionVersion(), /No session/);
+    assert.throws(() => record.remoteIdentityKey(), /No session/);
+    assert.throws(() => record.debugHasUsableSenderChain(), /No session/);
+    assert.throws(() => record.debugSenderChainCounter(), /No session/);
+    assert.throws(() => record.debugNumberOfReceiverChains(), /No session/);
+    assert.throws(() => new SignalClient.SessionRecord(Buffer.from([0xff])));
+  });
+  it('inspects SenderKeyRecords', () => {
+    const empty = new SignalClient.SenderKeyRecord(Buffer.alloc(0));
+    assert.equal(empty.stateCount(), 0);
+    assert.deepEqual(empty.chainIds(), []);
+    assert.isNull(empty.maxIterationForChain(7));
+
+    // One state with sender key ID 7 whose chain is at iteration 5.
+    const record = new SignalClient.SenderKeyRecord(
+      Buffer.from('0a060807120208051001', 'hex')
+    );
+    assert.equal(record.stateCount(), 1);
+    assert.deepEqual(record.chainIds(), [7]);
+    assert.equal(record.maxIterationForChain(7), 5);
+    assert.isNull(record.maxIterationForChain(8));
+    assert.equal(
+      new SignalClient.SenderKeyRecord(record.serialize()).stateCount(),
+      1
+    );
+
+    // Records from a future format version are rejected.
+    assert.throws(
+      () => new SignalClient.SenderKeyRecord(Buffer.from([0x10, 0x02])),
+      /version/
+    );
+  });
+  it('reads pre-key records', () => {
+    const data = path.join(__dirname, '../../../rust/protocol/tests/data');
+
+    const preKeyBytes = fs.readFileSync(
+      path.join(data, 'pre-key-record-v1.bin')
+    );
+    const preKey = new SignalClient.PreKeyRecord(preKeyBytes);
+    assert.equal(preKey.id(), 7);
+    assert.lengthOf(preKey.publicKey(), 33);
+    assert.lengthOf(preKey.privateKey(), 32);
+    assert.deepEqual(preKey.serialize(), preKeyBytes);
+
+    const signedPreKeyBytes = fs.readFileSync(
+      path.join(data, 'signed-pre-key-record-v1.bin')
+    );
+    const signedPreKey = new SignalClient.SignedPreKeyRecord(signedPreKeyBytes);
+    assert.equal(signedPreKey.id(), 9);
+    assert.equal(signedPreKey.timestamp(), 1600000000000);
+    assert.lengthOf(signedPreKey.signature(), 64);
+    assert.deepEqual(signedPreKey.publicKey(), preKey.publicKey());
+    assert.deepEqual(signedPreKey.privateKey(), preKey.privateKey());
+    assert.deepEqual(signedPreKey.serialize(), signedPreKeyBytes);
+
+    assert.throws(() => new SignalClient.PreKeyRecord(Buffer.from([0xff])));
+  });
+  it('reads message fields without a session', () => {
+    const signalMessageBytes = Buffer.from(
+      '330a2105a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8' +
+        'a209102a1829220a636970686572746578748b506a04ce17c4e8',
+      'hex'
+    );
+    const preKeyMessageBytes = Buffer.from(
+      '3308d209122105ac01b2209e86354fb853237b5de0f4fab13c7fcbf433a61c019369' +
+        '617fecf10b1a2105ce8d3ad1ccb633ec7b70c17814a5c76ecd029685050d344745ba' +
+        '05870e587d59223c' +
+        signalMessageBytes.toString('hex') +
+        '28ed023061',
+      'hex'
+    );
+
+    const message = new SignalClient.SignalMessage(signalMessageBytes);
+    assert.equal(message.messageVersion(), 3);
+    assert.equal(message.counter(), 42);
+    assert.equal(
+      message.senderRatchetKey().toString('hex'),
+      '05a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8a209'
+    );
+    assert.equal(message.body().toString(), 'ciphertext');
+    assert.deepEqual(message.serialize(), signalMessageBytes);
+
+    const preKeyMessage = new SignalClient.PreKeySignalMessage(
+      preKeyMessageBytes
+    );
+    assert.equal(preKeyMessage.messageVersion(), 3);
+    assert.equal(preKeyMessage.registrationId(), 365);
+    assert.equal(preKeyMessage.preKeyId(), 1234);
+    assert.equal(preKeyMessage.signedPreKeyId(), 97);
+    assert.equal(
+      preKeyMessage.baseKey().toString('hex'),
+      '05ac01b2209e86354fb853237b5de0f4fab13c7fcbf433a61c019369617fecf10b'
+    );
+    assert.equal(
+      preKeyMessage.identityKey().toString('hex'),
+      '05ce8d3ad1ccb633ec7b70c17814a5c76ecd029685050d344745ba05870e587d59'
+    );
+    assert.deepEqual(preKeyMessage.serialize(), preKeyMessageBytes);
+
+    assert.throws(() => new SignalClient.SignalMessage(Buffer.from([0x33])));
+    assert.throws(
+      () => new SignalClient.PreKeySignalMessage(Buffer.from([0x33, 0x08]))
+    );
+  });
+  it('verifies SignalMessage MACs without a session', () => {
+    const sender = new SignalClient.PrivateKey().serializePublicKey();
+    const receiver = new SignalClient.PrivateKey().serializePublicKey();
+    const macKey = Buffer.alloc(32, 7);
+    const unsigned = Buffer.from(
+      '330a2105a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8' +
+        'a209102a1829220a63697068657274657874',
+      'hex'
+    );
+    const mac = crypto
+      .createHmac('sha256', macKey)
+      .update(Buffer.concat([sender, receiver, unsigned]))
+      .digest()
+      .slice(0, 8);
+
+    const message = new SignalClient.SignalMessage(
+      Buffer.concat([unsigned, mac])
+    );
+    assert(message.verifyMac(sender, receiver, macKey));
+    assert(!message.verifyMac(receiver, sender, macKey));
+    assert.throws(() => message.verifyMac(sender, receiver, Buffer.alloc(16)));
+
+    const tampered = Buffer.concat([unsigned, mac]);
+    tampered[unsigned.length - 1] ^= 1;
+    assert(
+      !new SignalClient.SignalMessage(tampered).verifyMac(
+        sender,
+        receiver,
+        macKey
+      )
+    );
+  });
+  it('forwards Rust log records to the JS logger', async () => {
+    const logged = new Promise<string>(resolve => {
+      SignalClient.initLogger(
+        SignalClient.LogLevel.Info,
+        (level, target, _file, _line, message) => {
+          if (level === SignalClient.LogLevel.Info && target.length > 0) {
+            resolve(message);
+          }
+        }
+      );
+    });
+
+    assert.include(await logged, 'Initializing libsignal-client');
+    assert.throws(() =>
+      SignalClient.initLogger(SignalClient.LogLevel.Warn, () => {})
+    );
+  });
+  it('builds decryption error messages for the original message', () => {
+    const signalMessageBytes = Buffer.from(
+      '330a2105a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8' +
+        'a209102a1829220a636970686572746578748b506a04ce17c4e8',
+      'hex'
+    );
+    const preKeyMessageBytes = Buffer.from(
+      '3308d209122105ac01b2209e86354fb853237b5de0f4fab13c7fcbf433a61c019369' +
+        '617fecf10b1a2105ce8d3ad1ccb633ec7b70c17814a5c76ecd029685050d344745ba' +
+        '05870e587d59223c' +
+        signalMessageBytes.toString('hex') +
+        '28ed023061',
+      'hex'
+    );
+    const ratchetKey = Buffer.from(
+      '05a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8a209',
+      'hex'
+    );
+
+    const fromSignalMessage = SignalClient.decryptionErrorMessageForOriginal(
+      signalMessageBytes,
+      SignalClient.CiphertextMessageType.Whisper,
+      1000,
+      7
+    );
+    assert.deepEqual(fromSignalMessage.ratchetKey(), ratchetKey);
+    assert.equal(fromSignalMessage.timestamp(), 1000);
+    assert.equal(fromSignalMessage.deviceId(), 7);
+
+    const fromPreKeyMessage = SignalClient.decryptionErrorMessageForOriginal(
+      preKeyMessageBytes,
+      SignalClient.CiphertextMessageType.PreKey,
+      1000,
+      7
+    );
+    assert.deepEqual(fromPreKeyMessage.ratchetKey(), ratchetKey);
+    assert.deepEqual(
+      fromPreKeyMessage.serialize(),
+      fromSignalMessage.serialize()
+    );
+
+    const content = new SignalClient.PlaintextContent(
+      fromSignalMessage.toPlaintextContent().serialize()
+    );
+    const extracted = SignalClient.extractDecryptionErrorMessageFromSerializedContent(
+      content.body()
+    );
+    assert.deepEqual(extracted.serialize(), fromSignalMessage.serialize());
+
+    assert.throws(() =>
+      SignalClient.decryptionErrorMessageForOriginal(
+        preKeyMessageBytes,
+        SignalClient.CiphertextMessageType.Whisper,
+        1000,
+        7
+      )
+    );
+  });
+  it('computes fingerprints', () => {
+    const aliceIdentity = Buffer.from(
+      '0506863bc66d02b40d27b8d49ca7c09e9239236f9d7d25d6fcca5ce13c7064d868',
+      'hex'
+    );
+    const bobIdentity = Buffer.from(
+      '05f781b6fb32fed9ba1cf2de978d4d5da28dc34046ae814402b5c0dbd96fda907b',
+      'hex'
+    );
+    const aliceStableId = Buffer.from('+14152222222');
+    const bobStableId = Buffer.from('+14153333333');
+    const expected =
+      '300354477692869396892869876765458257569162576843440918079131';
+
+    const aliceFingerprint = SignalClient.fingerprint(
+      1,
+      5200,
+      aliceStableId,
+      aliceIdentity,
+      bobStableId,
+      bobIdentity
+    );
+    assert.equal(aliceFingerprint.displayable, expected);
+    assert.equal(
+      aliceFingerprint.scannable.toString('hex'),
+      '080112220a201e301a0353dce3dbe7684cb8336e85136cdc0ee96219494ada305d62' +
+        'a7bd61df1a220a20d62cbf73a11592015b6b9f1682ac306fea3aaf3885b84d12bca6' +
+        '31e9d4fb3a4d'
+    );
+
+    assert.equal(
+      SignalClient.displayableFingerprint(
+        5200,
+        bobStableId,
+        bobIdentity,
+        aliceStableId,
+        aliceIdentity
+      ),
+      expected
+    );
+    assert.throws(() =>
+      SignalClient.displayableFingerprint(
+        999,
+        bobStableId,
+        bobIdentity,
+        aliceStableId,
+        aliceIdentity
+      )
+    );
+  });
+  it('reports why scanned fingerprints differ', () => {
+    const aliceIdentity = Buffer.from(
+      '0506863bc66d02b40d27b8d49ca7c09e9239236f9d7d25d6fcca5ce13c7064d868',
+      'hex'
+    );
+    const bobIdentity = Buffer.from(
+      '05f781b6fb32fed9ba1cf2de978d4d5da28dc34046ae814402b5c0dbd96fda907b',
+      'hex'
+    );
+    const aliceStableId = Buffer.from('+14152222222');
+    const bobStableId = Buffer.from('+14153333333');
+
+    const alice = SignalClient.fingerprint(
+      1,
+      1024,
+      aliceStableId,
+      aliceIdentity,
+      bobStableId,
+      bobIdentity
+    );
+    const bob = SignalClient.fingerprint(
+      1,
+      1024,
+      bobStableId,
+      bobIdentity,
+      aliceStableId,
+      aliceIdentity
+    );
+    const bobV2 = SignalClient.fingerprint(
+      2,
+      1024,
+      bobStableId,
+      bobIdentity,
+      aliceStableId,
+      aliceIdentity
+    );
+    const mallory = SignalClient.fingerprint(
+      1,
+      1024,
+      bobStableId,
+      aliceIdentity,
+      aliceStableId,
+      aliceIdentity
+    );
+
+    assert.deepEqual(
+      SignalClient.compareScannableFingerprints(alice.scannable, bob.scannable),
+      { result: 'match' }
+    );
+    assert.deepEqual(
+      SignalClient.compareScannableFingerprints(
+        alice.scannable,
+        mallory.scannable
+      ),
+      { result: 'remoteMismatch' }
+    );
+    assert.deepEqual(
+      SignalClient.compareScannableFingerprints(
+        alice.scannable,
+        bobV2.scannable
+      ),
+      { result: 'versionMismatch', ourVersion: 1, theirVersion: 2 }
+    );
+    assert.throws(() =>
+      SignalClient.compareScannableFingerprints(
+        alice.scannable,
+        Buffer.from([0x08, 0x01])
+      )
+    );
+  });
+  it('derives unidentified access keys', () => {
+    assert.equal(
+      SignalClient.deriveUnidentifiedAccessKey(Buffer.alloc(32)).toString(
+        'hex'
+      ),
+      'cea7403d4d606b6e074ec5d3baf39d18'
+    );
+    const profileKey = Buffer.from(Array.from(Array(32).keys()));
+    assert.equal(
+      SignalClient.deriveUnidentifiedAccessKey(profileKey).toString('hex'),
+      '0ebcb5deb52c83bd08a8a935182c9199'
+    );
+    assert.throws(() =>
+      SignalClient.deriveUnidentifiedAccessKey(Buffer.alloc(16))
+    );
+
+    const unrestricted = SignalClient.unrestrictedUnidentifiedAccessKey();
+    assert.equal(unrestricted.length, 16);
+    assert(
+      !unrestricted.equals(SignalClient.unrestrictedUnidentifiedAccessKey())
+    );
+  });
+  it('reproduces the shared TestRng vectors', function() {
+    // TestRng only exists in builds with the test-rng feature.
+    try {
+      new SignalClient.TestRng(0);
+    } catch (e) {
+      this.skip();
+    }
+
+    const vectors = new Map<string, Buffer>();
+    const file = path.join(
+      __dirname,
+      '../../../rust/protocol/tests/data/test-rng-vectors.txt'
+    );
+    for (const line of fs.readFileSync(file, 'utf8').split('\n')) {
+      if (line === '' || line.startsWith('#')) {
+        continue;
+      }
+      const [name, value] = line.split(' ');
+      vectors.set(name, Buffer.from(value, 'hex'));
+    }
+
+    const key = new SignalClient.PrivateKey(new SignalClient.TestRng(1));
+    assert.deepEqual(key.serialize(), vectors.get('private_key'));
+    assert.deepEqual(key.serializePublicKey(), vectors.get('public_key'));
+
+    const otherKey = new SignalClient.PrivateKey(new SignalClient.TestRng(3));
+    assert.deepEqual(
+      key.signAlternateIdentity(
+        otherKey.serializePublicKey(),
+        new SignalClient.TestRng(4)
+      ),
+      vectors.get('alternate_identity_signature')
+    );
+
+    assert.deepEqual(
+      SignalClient.unrestrictedUnidentifiedAccessKey(
+        new SignalClient.TestRng(5)
+      ),
+      vectors.get('unidentified_access_key')
+    );
+  });
+
+  it('computes SHA-512 and HMAC-SHA512', () => {
+    assert.equal(
+      SignalClient.sha512(Buffer.from('abc')).toString('hex'),
+      'ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a' +
+        '2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f'
+    );
+    assert.equal(
+      SignalClient.hmacSha512(
+        Buffer.from('Jefe'),
+        Buffer.from('what do ya want for nothing?')
+      ).toString('hex'),
+      '164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554' +
+        '9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737'
+    );
+  });
+  it('pads messages like the other clients', () => {
+    const file = path.join(
+      __dirname,
+      '../../../rust/protocol/tests/data/padding-vectors.txt'
+    );
+    for (const line of fs.readFileSync(file, 'utf8').split('\n')) {
+      if (line === '' || line.startsWith('#')) {
+        continue;
+      }
+      const [name, body, padded] = line.split(' ');
+      const bodyBytes = Buffer.from(body === '-' ? '' : body, 'hex');
+      const paddedBytes = Buffer.from(padded, 'hex');
+      assert.deepEqual(SignalClient.padMessage(bodyBytes), paddedBytes, name);
+      assert.deepEqual(SignalClient.unpadMessage(paddedBytes), bodyBytes, name);
+    }
+
+    assert.throws(() => SignalClient.unpadMessage(Buffer.from([0x80, 0, 1])));
+    assert.throws(() => SignalClient.unpadMessage(Buffer.alloc(3)));
+  });
+  it('accepts ArrayBuffers and views of them as input', () => {
+    const expected = SignalClient.sha512(Buffer.from('abc'));
+
+    const backing = new ArrayBuffer(8);
+    const bytes = new Uint8Array(backing, 2, 3);
+    bytes.set([0x61, 0x62, 0x63]);
+    assert(SignalClient.sha512(bytes).equals(expected), 'Uint8Array');
+    assert(
+      SignalClient.sha512(new DataView(backing, 2, 3)).equals(expected),
+      'DataView'
+    );
+    assert(
+      SignalClient.sha512(backing.slice(2, 5)).equals(expected),
+      'ArrayBuffer'
+    );
+
+    assert.throws(
+      () => SignalClient.sha512(('abc' as unknown) as SignalClient.Bytes),
+      TypeError,
+      'input must be a Buffer'
+    );
+  });
+  it('derives secrets with HKDF', () => {
+    // RFC 5869 A.1
+    const ikm = Buffer.alloc(22, 0x0b);
+    const salt = Buffer.from('000102030405060708090a0b0c', 'hex');
+    const info = Buffer.from('f0f1f2f3f4f5f6f7f8f9', 'hex');
+    assert.equal(
+      SignalClient.hkdfDeriveSecrets(42, 3, ikm, info, salt).toString('hex'),
+      '3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf' +
+        '34007208d5b887185865'
+    );
+    assert.equal(
+      SignalClient.hkdfDeriveSecrets(20, 3, ikm, info, null).length,
+      20
+    );
+    assert.throws(() => SignalClient.hkdfDeriveSecrets(42, 4, ikm, info, salt));
+  });
+  it('encrypts and decrypts with AES-256-GCM-SIV', async () => {
+    const key = Buffer.from(
+      'bae8e37fc83441b16034566b7a806c46bb91c3c5aedb64a6c590bc84d1a5e269',
+      'hex'
+    );
+    const nonce = Buffer.from('e4b47801afc0577e34699b9e', 'hex');
+    const aad = Buffer.from('874296d5cc1fd16132', 'hex');
+    const ptext = Buffer.from('671fdd4fbdc66f146545fc880c94a95198', 'hex');
+
+    const ctext = await SignalClient.aes256GcmSivEncrypt(
+      ptext,
+      key,
+      nonce,
+      aad
+    );
+    assert.equal(
+      ctext.toString('hex'),
+      '9209cfae7372e0a3ec2e5d072d5e26b7b9f3acb73908e54cddf7be1864914e13cf'
+    );
+
+    const recovered = await SignalClient.aes256GcmSivDecrypt(
+      ctext,
+      key,
+      nonce,
+      aad
+    );
+    assert(recovered.equals(ptext));
+
+    ctext[0] ^= 1;
+    let failed = false;
+    try {
+      await SignalClient.aes256GcmSivDecrypt(ctext, key, nonce, aad);
+    } catch (e) {
+      failed = true;
+    }
+    assert(failed, 'tampered ciphertext is rejected');
+  });
 });
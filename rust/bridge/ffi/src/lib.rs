@@ -14,6 +14,10 @@ use std::convert::TryFrom;
 use std::ffi::{c_void, CString};
 
 mod util;
+mod worker;
+
+#[cfg(feature = "test-rng")]
+mod test_rng;
 
 use crate::util::*;
 
@@ -47,7 +51,10 @@ pub unsafe extern "C" fn signal_error_get_message(
         if err.is_null() {
             return Err(SignalFfiError::NullPointer);
         }
-        let msg = format!("{}", *err);
+        let msg = match &*err {
+            SignalFfiError::Signal(e) => e.display_with_causes(),
+            e => format!("{}", e),
+        };
         write_cstr_to(out, Ok(msg))
     })();
 
@@ -68,6 +75,53 @@ pub unsafe extern "C" fn signal_error_get_type(err: *const SignalFfiError) -> u3
     }
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn signal_error_get_duplicated_message_counter(
+    err: *const SignalFfiError,
+    current_iteration: *mut c_uint,
+    counter: *mut c_uint,
+) -> *mut SignalFfiError {
+    let result = (|| match err.as_ref() {
+        None => Err(SignalFfiError::NullPointer),
+        Some(SignalFfiError::Signal(SignalProtocolError::DuplicatedMessage {
+            current_iteration: i,
+            counter: c,
+        })) => {
+            write_uint32_to(current_iteration, Ok(*i))?;
+            write_uint32_to(counter, Ok(*c))
+        }
+        Some(_) => Err(SignalFfiError::InvalidType),
+    })();
+
+    match result {
+        Ok(()) => std::ptr::null_mut(),
+        Err(e) => Box::into_raw(Box::new(e)),
+    }
+}
+
+/// Writes a new handle to the address named by an `UntrustedIdentity` or `SessionNotFound`
+/// error, to be freed with [signal_address_destroy]. Fails with `InvalidType` for other errors,
+/// including a `SessionNotFound` error that does not name an address.
+#[no_mangle]
+pub unsafe extern "C" fn signal_error_get_address(
+    err: *const SignalFfiError,
+    out: *mut *mut ProtocolAddress,
+) -> *mut SignalFfiError {
+    let result = (|| match err.as_ref() {
+        None => Err(SignalFfiError::NullPointer),
+        Some(SignalFfiError::Signal(SignalProtocolError::UntrustedIdentity(address)))
+        | Some(SignalFfiError::Signal(SignalProtocolError::SessionNotFoundForAddress(address))) => {
+            box_object(out, Ok(address.clone()))
+        }
+        Some(_) => Err(SignalFfiError::InvalidType),
+    })();
+
+    match result {
+        Ok(()) => std::ptr::null_mut(),
+        Err(e) => Box::into_raw(Box::new(e)),
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn signal_error_free(err: *mut SignalFfiError) {
     if !err.is_null() {
@@ -75,6 +129,21 @@ pub unsafe extern "C" fn signal_error_free(err: *mut SignalFfiError) {
     }
 }
 
+/// Takes a copy of the last error returned by a bridge function on the calling thread, or null
+/// if there is none. The slot is emptied, so a second call returns null.
+///
+/// This is for callers that only kept the error code; the copy works with the other
+/// `signal_error_` functions and must be freed with [signal_error_free]. Errors passed to the
+/// completion callback of an `_async` function are not saved, and neither are errors from the
+/// `signal_error_` functions themselves.
+#[no_mangle]
+pub unsafe extern "C" fn signal_error_take_last() -> *mut SignalFfiError {
+    match take_last_error() {
+        Some(e) => Box::into_raw(Box::new(e)),
+        None => std::ptr::null_mut(),
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn signal_hkdf_derive(
     output: *mut c_uchar,
@@ -107,6 +176,96 @@ pub unsafe extern "C" fn signal_hkdf_derive(
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn signal_sha512(
+    digest: *mut *const c_uchar,
+    digest_len: *mut size_t,
+    input: *const c_uchar,
+    input_len: size_t,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let input = as_slice(input, input_len)?;
+        write_bytearray_to(digest, digest_len, Ok(sha512(input).to_vec()))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_hmac_sha512(
+    mac: *mut *const c_uchar,
+    mac_len: *mut size_t,
+    key: *const c_uchar,
+    key_len: size_t,
+    input: *const c_uchar,
+    input_len: size_t,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let key = as_slice(key, key_len)?;
+        let input = as_slice(input, input_len)?;
+        write_bytearray_to(mac, mac_len, hmac_sha512(key, input).map(|m| m.to_vec()))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_pad_message(
+    padded: *mut *const c_uchar,
+    padded_len: *mut size_t,
+    body: *const c_uchar,
+    body_len: size_t,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let body = as_slice(body, body_len)?;
+        write_bytearray_to(padded, padded_len, Ok(pad_message(body)))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_unpad_message(
+    body: *mut *const c_uchar,
+    body_len: *mut size_t,
+    padded: *const c_uchar,
+    padded_len: size_t,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let padded = as_slice(padded, padded_len)?;
+        write_bytearray_to(body, body_len, unpad_message(padded).map(|b| b.to_vec()))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_derive_unidentified_access_key(
+    access_key: *mut *const c_uchar,
+    access_key_len: *mut size_t,
+    profile_key: *const c_uchar,
+    profile_key_len: size_t,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let profile_key = as_slice(profile_key, profile_key_len)?;
+        let profile_key = <&[u8; 32]>::try_from(profile_key).map_err(|_| {
+            SignalProtocolError::InvalidArgument(format!(
+                "profile key must be 32 bytes, not {}",
+                profile_key.len()
+            ))
+        })?;
+        write_bytearray_to(
+            access_key,
+            access_key_len,
+            derive_unidentified_access_key(profile_key).map(|k| k.to_vec()),
+        )
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_unrestricted_unidentified_access_key(
+    access_key: *mut *const c_uchar,
+    access_key_len: *mut size_t,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let mut rng = rand::rngs::OsRng;
+        let key = unrestricted_unidentified_access_key(&mut rng);
+        write_bytearray_to(access_key, access_key_len, Ok(key.to_vec()))
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn signal_address_new(
     address: *mut *mut ProtocolAddress,
@@ -115,15 +274,41 @@ pub unsafe extern "C" fn signal_address_new(
 ) -> *mut SignalFfiError {
     run_ffi_safe(|| {
         let name = read_c_string(name)?;
+        let device_id = DeviceId::try_from(device_id)?;
         box_object(address, Ok(ProtocolAddress::new(name, device_id)))
     })
 }
 
+/// Creates an address from a service ID in its 17-byte fixed-width binary encoding.
+#[no_mangle]
+pub unsafe extern "C" fn signal_address_new_from_service_id(
+    address: *mut *mut ProtocolAddress,
+    service_id: *const c_uchar,
+    service_id_len: size_t,
+    device_id: c_uint,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let service_id = as_slice(service_id, service_id_len)?;
+        let service_id = ServiceId::parse_from_service_id_binary(service_id)?;
+        let device_id = DeviceId::try_from(device_id)?;
+        box_object(
+            address,
+            Ok(ProtocolAddress::from_service_id(service_id, device_id)),
+        )
+    })
+}
+
 ffi_fn_get_cstring!(signal_address_get_name(ProtocolAddress) using
                     |p: &ProtocolAddress| Ok(p.name().to_string()));
 
 ffi_fn_get_uint32!(signal_address_get_device_id(ProtocolAddress) using
-                   |obj: &ProtocolAddress| { Ok(obj.device_id()) });
+                   |obj: &ProtocolAddress| { Ok(obj.device_id().into()) });
+
+// An address whose name is not a service ID produces an empty buffer.
+ffi_fn_get_bytearray!(signal_address_get_service_id(ProtocolAddress) using
+                      |p: &ProtocolAddress| Ok(p
+                          .service_id()
+                          .map_or_else(Vec::new, |id| id.service_id_binary().to_vec())));
 
 ffi_fn_destroy!(signal_address_destroy destroys ProtocolAddress);
 
@@ -214,6 +399,33 @@ pub unsafe extern "C" fn signal_privatekey_sign(
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn signal_privatekey_sign_with_randomness(
+    signature: *mut *const c_uchar,
+    signature_len: *mut size_t,
+    key: *const PrivateKey,
+    message: *const c_uchar,
+    message_len: size_t,
+    randomness: *const c_uchar,
+    randomness_len: size_t,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let message = as_slice(message, message_len)?;
+        let randomness = as_slice(randomness, randomness_len)?;
+        let key = native_handle_cast::<PrivateKey>(key)?;
+
+        let randomness = <&[u8; 64]>::try_from(randomness).map_err(|_| {
+            SignalProtocolError::InvalidArgument(format!(
+                "signature randomness must be 64 bytes, got {}",
+                randomness_len
+            ))
+        })?;
+
+        let sig = key.calculate_signature_with_randomness(&message, randomness);
+        write_bytearray_to(signature, signature_len, sig)
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn signal_privatekey_agree(
     shared_secret: *mut *const c_uchar,
@@ -244,7 +456,47 @@ pub unsafe extern "C" fn signal_identitykeypair_serialize(
         let private_key = *native_handle_cast::<PrivateKey>(private_key)?;
         let public_key = *native_handle_cast::<PublicKey>(public_key)?;
         let identity_key_pair = IdentityKeyPair::new(IdentityKey::new(public_key), private_key);
-        write_bytearray_to(output, output_len, Ok(identity_key_pair.serialize()))
+        write_bytearray_to(output, output_len, identity_key_pair.serialize())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_identitykeypair_sign_alternate_identity(
+    signature: *mut *const c_uchar,
+    signature_len: *mut size_t,
+    public_key: *const PublicKey,
+    private_key: *const PrivateKey,
+    other_identity: *const PublicKey,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let public_key = *native_handle_cast::<PublicKey>(public_key)?;
+        let private_key = *native_handle_cast::<PrivateKey>(private_key)?;
+        let other_identity = *native_handle_cast::<PublicKey>(other_identity)?;
+        let identity_key_pair = IdentityKeyPair::new(IdentityKey::new(public_key), private_key);
+        let mut rng = rand::rngs::OsRng;
+        let sig =
+            identity_key_pair.sign_alternate_identity(&IdentityKey::new(other_identity), &mut rng);
+        write_bytearray_to(signature, signature_len, sig)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_identitykey_verify_alternate_identity(
+    result: *mut bool,
+    public_key: *const PublicKey,
+    other_identity: *const PublicKey,
+    signature: *const c_uchar,
+    signature_len: size_t,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        *result = false; // pre-set to invalid state
+        let public_key = *native_handle_cast::<PublicKey>(public_key)?;
+        let other_identity = *native_handle_cast::<PublicKey>(other_identity)?;
+        let signature = as_slice(signature, signature_len)?;
+
+        *result = IdentityKey::new(public_key)
+            .verify_alternate_identity(&IdentityKey::new(other_identity), signature)?;
+        Ok(())
     })
 }
 
@@ -259,7 +511,7 @@ pub unsafe extern "C" fn signal_identitykeypair_deserialize(
         let input = as_slice(input, input_len)?;
         let identity_key_pair = IdentityKeyPair::try_from(input)?;
         box_object::<PublicKey>(public_key, Ok(*identity_key_pair.public_key()))?;
-        box_object::<PrivateKey>(private_key, Ok(*identity_key_pair.private_key()))
+        box_object::<PrivateKey>(private_key, identity_key_pair.private_key().map(|k| *k))
     })
 }
 
@@ -269,7 +521,7 @@ ffi_fn_get_bytearray!(signal_session_record_serialize(SessionRecord) using
                       |s: &SessionRecord| s.serialize());
 
 ffi_fn_get_uint32!(signal_session_record_get_remote_registration_id(SessionRecord) using
-                   |s: &SessionRecord| s.session_state()?.remote_registration_id());
+                   |s: &SessionRecord| Ok(s.session_state()?.remote_registration_id()?.into()));
 
 #[no_mangle]
 pub unsafe extern "C" fn signal_session_record_archive_current_state(
@@ -282,6 +534,45 @@ pub unsafe extern "C" fn signal_session_record_archive_current_state(
     })
 }
 
+// Debugging aids: these report counters and flags of the current session state, never keys.
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_session_record_debug_has_usable_sender_chain(
+    result: *mut bool,
+    session_record: *const SessionRecord,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        *result = false;
+        let session_record = native_handle_cast::<SessionRecord>(session_record)?;
+        *result = session_record.session_state()?.has_usable_sender_chain()?;
+        Ok(())
+    })
+}
+
+ffi_fn_get_optional_uint32!(signal_session_record_debug_sender_chain_counter(SessionRecord) using
+                            |s: &SessionRecord| s.session_state()?.sender_chain_counter());
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_session_record_debug_receiver_chain_counter(
+    session_record: *const SessionRecord,
+    their_ephemeral: *const PublicKey,
+    out: *mut c_uint,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let session_record = native_handle_cast::<SessionRecord>(session_record)?;
+        let their_ephemeral = native_handle_cast::<PublicKey>(their_ephemeral)?;
+        write_optional_uint32_to(
+            out,
+            session_record
+                .session_state()
+                .and_then(|s| s.receiver_chain_counter(their_ephemeral)),
+        )
+    })
+}
+
+ffi_fn_get_uint32!(signal_session_record_debug_number_of_receiver_chains(SessionRecord) using
+                   |s: &SessionRecord| s.session_state()?.number_of_receiver_chains().map(|n| n as u32));
+
 ffi_fn_destroy!(signal_session_record_destroy destroys SessionRecord);
 
 ffi_fn_clone!(signal_session_record_clone clones SessionRecord);
@@ -334,6 +625,80 @@ pub unsafe extern "C" fn signal_fingerprint_new(
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn signal_fingerprint_new_displayable(
+    fprint: *mut *const c_char,
+    iterations: c_uint,
+    local_identifier: *const c_uchar,
+    local_identifier_len: size_t,
+    local_key: *const PublicKey,
+    remote_identifier: *const c_uchar,
+    remote_identifier_len: size_t,
+    remote_key: *const PublicKey,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let local_identifier = as_slice(local_identifier, local_identifier_len)?;
+        let local_key = native_handle_cast::<PublicKey>(local_key)?;
+
+        let remote_identifier = as_slice(remote_identifier, remote_identifier_len)?;
+        let remote_key = native_handle_cast::<PublicKey>(remote_key)?;
+
+        let fingerprint = Fingerprint::new_displayable(
+            iterations,
+            local_identifier,
+            &IdentityKey::new(*local_key),
+            remote_identifier,
+            &IdentityKey::new(*remote_key),
+        )
+        .map(|f| format!("{}", f));
+        write_cstr_to(fprint, fingerprint)
+    })
+}
+
+/// Computes a fingerprint over several identifiers per party.
+///
+/// Each party's identifiers are passed concatenated in one buffer, with `*_identifier_lens`
+/// giving the length of each of the `*_identifier_count` identifiers.
+#[no_mangle]
+pub unsafe extern "C" fn signal_fingerprint_new_multi(
+    obj: *mut *mut Fingerprint,
+    iterations: c_uint,
+    local_identifiers: *const c_uchar,
+    local_identifier_lens: *const size_t,
+    local_identifier_count: size_t,
+    local_key: *const PublicKey,
+    remote_identifiers: *const c_uchar,
+    remote_identifier_lens: *const size_t,
+    remote_identifier_count: size_t,
+    remote_key: *const PublicKey,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let local_identifiers = as_slices(
+            local_identifiers,
+            local_identifier_lens,
+            local_identifier_count,
+        )?;
+        let local_key = native_handle_cast::<PublicKey>(local_key)?;
+
+        let remote_identifiers = as_slices(
+            remote_identifiers,
+            remote_identifier_lens,
+            remote_identifier_count,
+        )?;
+        let remote_key = native_handle_cast::<PublicKey>(remote_key)?;
+
+        let fprint = Fingerprint::new_multi(
+            iterations,
+            &local_identifiers,
+            &IdentityKey::new(*local_key),
+            &remote_identifiers,
+            &IdentityKey::new(*remote_key),
+        );
+
+        box_object::<Fingerprint>(obj, fprint)
+    })
+}
+
 ffi_fn_destroy!(signal_fingerprint_destroy destroys Fingerprint);
 
 ffi_fn_clone!(signal_fingerprint_clone clones Fingerprint);
@@ -364,6 +729,51 @@ pub unsafe extern "C" fn signal_fingerprint_compare(
     })
 }
 
+#[derive(Debug)]
+#[repr(C)]
+pub enum FfiFingerprintComparison {
+    Match = 0,
+    LocalMismatch = 1,
+    RemoteMismatch = 2,
+    VersionMismatch = 3,
+}
+
+/// Compares a scanned fingerprint (`fprint2`) against our own (`fprint1`).
+///
+/// `result` receives a FfiFingerprintComparison value. On a version mismatch, `our_version` and
+/// `their_version` receive the two versions; otherwise they are left unchanged.
+#[no_mangle]
+pub unsafe extern "C" fn signal_fingerprint_compare_scanned(
+    result: *mut u8,
+    our_version: *mut c_uint,
+    their_version: *mut c_uint,
+    fprint1: *const c_uchar,
+    fprint1_len: size_t,
+    fprint2: *const c_uchar,
+    fprint2_len: size_t,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        if result.is_null() || our_version.is_null() || their_version.is_null() {
+            return Err(SignalFfiError::NullPointer);
+        }
+        let fprint1 = as_slice(fprint1, fprint1_len)?;
+        let fprint2 = as_slice(fprint2, fprint2_len)?;
+
+        let fprint1 = ScannableFingerprint::deserialize(&fprint1)?;
+        *result = match fprint1.compare_scanned(&fprint2)? {
+            FingerprintComparison::Match => FfiFingerprintComparison::Match,
+            FingerprintComparison::LocalMismatch => FfiFingerprintComparison::LocalMismatch,
+            FingerprintComparison::RemoteMismatch => FfiFingerprintComparison::RemoteMismatch,
+            FingerprintComparison::VersionMismatch { ours, theirs } => {
+                *our_version = ours;
+                *their_version = theirs;
+                FfiFingerprintComparison::VersionMismatch
+            }
+        } as u8;
+        Ok(())
+    })
+}
+
 ffi_fn_deserialize!(signal_message_deserialize(SignalMessage) is SignalMessage::try_from);
 
 #[no_mangle]
@@ -466,7 +876,7 @@ pub unsafe extern "C" fn signal_pre_key_signal_message_new(
 
         let msg = PreKeySignalMessage::new(
             message_version,
-            registration_id,
+            RegistrationId::try_from(registration_id)?,
             pre_key_id,
             signed_pre_key_id,
             *base_key,
@@ -485,7 +895,7 @@ ffi_fn_get_uint32!(signal_pre_key_signal_message_get_version(PreKeySignalMessage
                    |m: &PreKeySignalMessage| Ok(m.message_version() as u32));
 
 ffi_fn_get_uint32!(signal_pre_key_signal_message_get_registration_id(PreKeySignalMessage) using
-                   |m: &PreKeySignalMessage| Ok(m.registration_id()));
+                   |m: &PreKeySignalMessage| Ok(m.registration_id().into()));
 
 ffi_fn_get_optional_uint32!(signal_pre_key_signal_message_get_pre_key_id(PreKeySignalMessage) using
                             |m: &PreKeySignalMessage| Ok(m.pre_key_id()));
@@ -594,6 +1004,71 @@ ffi_fn_get_new_boxed_obj!(signal_sender_key_distribution_message_get_signature_k
 ffi_fn_get_bytearray!(signal_sender_key_distribution_message_serialize(SenderKeyDistributionMessage) using
                       |m: &SenderKeyDistributionMessage| Ok(m.serialized().to_vec()));
 
+#[no_mangle]
+pub unsafe extern "C" fn signal_decryption_error_message_for_original_message(
+    obj: *mut *mut DecryptionErrorMessage,
+    original_bytes: *const c_uchar,
+    original_bytes_len: size_t,
+    original_type: u8,
+    original_timestamp: c_ulonglong,
+    original_sender_device_id: c_uint,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let original_bytes = as_slice(original_bytes, original_bytes_len)?;
+        let original_type = CiphertextMessageType::try_from(original_type)?;
+        let message = DecryptionErrorMessage::for_original(
+            original_bytes,
+            original_type,
+            original_timestamp as u64,
+            original_sender_device_id,
+        );
+        box_object::<DecryptionErrorMessage>(obj, message)
+    })
+}
+
+ffi_fn_deserialize!(signal_decryption_error_message_deserialize(DecryptionErrorMessage) is DecryptionErrorMessage::try_from);
+
+ffi_fn_deserialize!(signal_decryption_error_message_extract_from_serialized_content(DecryptionErrorMessage) is extract_decryption_error_message_from_serialized_content);
+
+ffi_fn_destroy!(signal_decryption_error_message_destroy destroys DecryptionErrorMessage);
+
+ffi_fn_clone!(signal_decryption_error_message_clone clones DecryptionErrorMessage);
+
+ffi_fn_get_uint64!(signal_decryption_error_message_get_timestamp(DecryptionErrorMessage) using
+                   |m: &DecryptionErrorMessage| Ok(m.timestamp()));
+
+ffi_fn_get_uint32!(signal_decryption_error_message_get_device_id(DecryptionErrorMessage) using
+                   |m: &DecryptionErrorMessage| Ok(m.device_id()));
+
+ffi_fn_get_new_boxed_optional_obj!(signal_decryption_error_message_get_ratchet_key(PublicKey) from DecryptionErrorMessage,
+                                   |m: &DecryptionErrorMessage| Ok(m.ratchet_key().copied()));
+
+ffi_fn_get_bytearray!(signal_decryption_error_message_serialize(DecryptionErrorMessage) using
+                      |m: &DecryptionErrorMessage| Ok(m.serialized().to_vec()));
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_plaintext_content_from_decryption_error_message(
+    obj: *mut *mut PlaintextContent,
+    message: *const DecryptionErrorMessage,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let message = native_handle_cast::<DecryptionErrorMessage>(message)?;
+        box_object::<PlaintextContent>(obj, Ok(PlaintextContent::from(message.clone())))
+    })
+}
+
+ffi_fn_deserialize!(signal_plaintext_content_deserialize(PlaintextContent) is PlaintextContent::try_from);
+
+ffi_fn_destroy!(signal_plaintext_content_destroy destroys PlaintextContent);
+
+ffi_fn_clone!(signal_plaintext_content_clone clones PlaintextContent);
+
+ffi_fn_get_bytearray!(signal_plaintext_content_get_body(PlaintextContent) using
+                      |m: &PlaintextContent| Ok(m.body().to_vec()));
+
+ffi_fn_get_bytearray!(signal_plaintext_content_serialize(PlaintextContent) using
+                      |m: &PlaintextContent| Ok(m.serialized().to_vec()));
+
 #[no_mangle]
 pub unsafe extern "C" fn signal_pre_key_bundle_new(
     obj: *mut *mut PreKeyBundle,
@@ -617,10 +1092,15 @@ pub unsafe extern "C" fn signal_pre_key_bundle_new(
         let prekey_id = get_optional_uint32(prekey_id);
         let identity_key = IdentityKey::new(*(identity_key as *const PublicKey));
 
+        let prekey = match (prekey_id, prekey) {
+            (Some(id), Some(key)) => Some((id, key)),
+            (None, None) => None,
+            _ => return Err(SignalProtocolError::InvalidPreKeyBundle.into()),
+        };
+
         let bundle = PreKeyBundle::new(
-            registration_id,
-            device_id,
-            prekey_id,
+            RegistrationId::try_from(registration_id)?,
+            DeviceId::try_from(device_id)?,
             prekey,
             signed_prekey_id,
             *signed_prekey,
@@ -637,10 +1117,10 @@ ffi_fn_destroy!(signal_pre_key_bundle_destroy destroys PreKeyBundle);
 ffi_fn_clone!(signal_pre_key_bundle_clone clones PreKeyBundle);
 
 ffi_fn_get_uint32!(signal_pre_key_bundle_get_registration_id(PreKeyBundle) using
-                   |m: &PreKeyBundle| m.registration_id());
+                   |m: &PreKeyBundle| Ok(m.registration_id()?.into()));
 
 ffi_fn_get_uint32!(signal_pre_key_bundle_get_device_id(PreKeyBundle) using
-                   |m: &PreKeyBundle| m.device_id());
+                   |m: &PreKeyBundle| Ok(m.device_id()?.into()));
 
 ffi_fn_get_uint32!(signal_pre_key_bundle_get_signed_pre_key_id(PreKeyBundle) using
                    |m: &PreKeyBundle| m.signed_pre_key_id());
@@ -760,6 +1240,7 @@ pub unsafe extern "C" fn signal_sender_key_name_new(
     run_ffi_safe(|| {
         let group_id = read_c_string(group_id)?;
         let sender_name = read_c_string(sender_name)?;
+        let sender_device_id = DeviceId::try_from(sender_device_id)?;
         let name = SenderKeyName::new(
             group_id,
             ProtocolAddress::new(sender_name, sender_device_id),
@@ -779,7 +1260,7 @@ ffi_fn_get_cstring!(signal_sender_key_name_get_sender_name(SenderKeyName) using
                     |skn: &SenderKeyName| { Ok(skn.sender()?.name().to_string()) });
 
 ffi_fn_get_uint32!(signal_sender_key_name_get_sender_device_id(SenderKeyName) using
-                   |m: &SenderKeyName| Ok(m.sender()?.device_id()));
+                   |m: &SenderKeyName| Ok(m.sender()?.device_id().into()));
 
 #[no_mangle]
 pub unsafe extern "C" fn signal_sender_key_record_new_fresh(
@@ -828,6 +1309,25 @@ pub enum FfiDirection {
     Receiving = 1,
 }
 
+/// Returned by the `save_identity` callback, and by [signal_process_prekey_bundle].
+#[derive(Debug)]
+#[repr(C)]
+pub enum FfiIdentityChange {
+    NewIdentity = 0,
+    ReplacedExisting = 1,
+    Unchanged = 2,
+}
+
+impl From<IdentityChange> for FfiIdentityChange {
+    fn from(change: IdentityChange) -> Self {
+        match change {
+            IdentityChange::NewIdentity => Self::NewIdentity,
+            IdentityChange::ReplacedExisting => Self::ReplacedExisting,
+            IdentityChange::Unchanged => Self::Unchanged,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct FfiIdentityKeyStoreStruct {
@@ -880,7 +1380,10 @@ impl IdentityKeyStore for FfiIdentityKeyStore {
         Ok(IdentityKeyPair::new(IdentityKey::new(pub_key), *priv_key))
     }
 
-    async fn get_local_registration_id(&self, ctx: Context) -> Result<u32, SignalProtocolError> {
+    async fn get_local_registration_id(
+        &self,
+        ctx: Context,
+    ) -> Result<RegistrationId, SignalProtocolError> {
         let ctx = ctx.unwrap_or(std::ptr::null_mut());
         let mut id = 0;
         let result = (self.store.get_local_registration_id)(self.store.ctx, &mut id, ctx);
@@ -894,7 +1397,7 @@ impl IdentityKeyStore for FfiIdentityKeyStore {
             );
         }
 
-        Ok(id)
+        RegistrationId::try_from(id)
     }
 
     async fn save_identity(
@@ -902,14 +1405,17 @@ impl IdentityKeyStore for FfiIdentityKeyStore {
         address: &ProtocolAddress,
         identity: &IdentityKey,
         ctx: Context,
-    ) -> Result<bool, SignalProtocolError> {
+    ) -> Result<IdentityChange, SignalProtocolError> {
         let ctx = ctx.unwrap_or(std::ptr::null_mut());
         let result =
             (self.store.save_identity)(self.store.ctx, &*address, &*identity.public_key(), ctx);
 
         match result {
-            0 => Ok(false),
-            1 => Ok(true),
+            r if r == FfiIdentityChange::NewIdentity as c_int => Ok(IdentityChange::NewIdentity),
+            r if r == FfiIdentityChange::ReplacedExisting as c_int => {
+                Ok(IdentityChange::ReplacedExisting)
+            }
+            r if r == FfiIdentityChange::Unchanged as c_int => Ok(IdentityChange::Unchanged),
             r => Err(
                 SignalProtocolError::ApplicationCallbackReturnedIntegerError("save_identity", r),
             ),
@@ -1177,13 +1683,41 @@ type StoreSession = extern "C" fn(
     record: *const SessionRecord,
     ctx: *mut c_void,
 ) -> c_int;
-
+type GetSubDeviceSessions = extern "C" fn(
+    store_ctx: *mut c_void,
+    device_ids: *mut u32,
+    device_ids_capacity: size_t,
+    device_ids_len: *mut size_t,
+    name: *const c_char,
+    ctx: *mut c_void,
+) -> c_int;
+type DeleteSession = extern "C" fn(
+    store_ctx: *mut c_void,
+    address: *const ProtocolAddress,
+    ctx: *mut c_void,
+) -> c_int;
+type DeleteAllSessions =
+    extern "C" fn(store_ctx: *mut c_void, name: *const c_char, ctx: *mut c_void) -> c_int;
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct FfiSessionStoreStruct {
     ctx: *mut c_void,
     load_session: LoadSession,
     store_session: StoreSession,
+    /// Optional; if null, [SessionStore::get_sub_device_sessions] fails with
+    /// OperationNotSupported.
+    get_sub_device_sessions: Option<GetSubDeviceSessions>,
+    /// Optional; if null, [SessionStore::delete_session] fails with OperationNotSupported.
+    delete_session: Option<DeleteSession>,
+    /// Optional; if null, [SessionStore::delete_all_sessions] fails with OperationNotSupported.
+    delete_all_sessions: Option<DeleteAllSessions>,
+}
+
+fn session_store_name(name: &str) -> Result<CString, SignalProtocolError> {
+    CString::new(name).map_err(|_| {
+        SignalProtocolError::InvalidArgument("name must not contain NUL bytes".to_string())
+    })
 }
 
 pub struct FfiSessionStore {
@@ -1247,10 +1781,106 @@ impl SessionStore for FfiSessionStore {
 
         Ok(())
     }
+
+    async fn get_sub_device_sessions(
+        &self,
+        name: &str,
+        ctx: Context,
+    ) -> Result<Vec<u32>, SignalProtocolError> {
+        let get_sub_device_sessions = self.store.get_sub_device_sessions.ok_or(
+            SignalProtocolError::OperationNotSupported("get_sub_device_sessions"),
+        )?;
+        let ctx = ctx.unwrap_or(std::ptr::null_mut());
+        let name = session_store_name(name)?;
+        // Device IDs are bounded, so a buffer with room for every valid ID is always enough.
+        let mut device_ids = vec![0u32; DeviceId::MAX as usize];
+        let mut device_ids_len = 0;
+        let result = get_sub_device_sessions(
+            self.store.ctx,
+            device_ids.as_mut_ptr(),
+            device_ids.len(),
+            &mut device_ids_len,
+            name.as_ptr(),
+            ctx,
+        );
+
+        if result != 0 {
+            return Err(
+                SignalProtocolError::ApplicationCallbackReturnedIntegerError(
+                    "get_sub_device_sessions",
+                    result,
+                ),
+            );
+        }
+
+        if device_ids_len > device_ids.len() {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "get_sub_device_sessions returned {} device IDs",
+                device_ids_len
+            )));
+        }
+
+        device_ids.truncate(device_ids_len);
+        Ok(device_ids)
+    }
+
+    async fn delete_session(
+        &mut self,
+        address: &ProtocolAddress,
+        ctx: Context,
+    ) -> Result<(), SignalProtocolError> {
+        let delete_session = self
+            .store
+            .delete_session
+            .ok_or(SignalProtocolError::OperationNotSupported("delete_session"))?;
+        let ctx = ctx.unwrap_or(std::ptr::null_mut());
+        let result = delete_session(self.store.ctx, &*address, ctx);
+
+        if result != 0 {
+            return Err(
+                SignalProtocolError::ApplicationCallbackReturnedIntegerError(
+                    "delete_session",
+                    result,
+                ),
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn delete_all_sessions(
+        &mut self,
+        name: &str,
+        ctx: Context,
+    ) -> Result<(), SignalProtocolError> {
+        let delete_all_sessions =
+            self.store
+                .delete_all_sessions
+                .ok_or(SignalProtocolError::OperationNotSupported(
+                    "delete_all_sessions",
+                ))?;
+        let ctx = ctx.unwrap_or(std::ptr::null_mut());
+        let name = session_store_name(name)?;
+        let result = delete_all_sessions(self.store.ctx, name.as_ptr(), ctx);
+
+        if result != 0 {
+            return Err(
+                SignalProtocolError::ApplicationCallbackReturnedIntegerError(
+                    "delete_all_sessions",
+                    result,
+                ),
+            );
+        }
+
+        Ok(())
+    }
 }
 
+/// `identity_change` receives a FfiIdentityChange value describing how the bundle's identity was
+/// saved.
 #[no_mangle]
 pub unsafe extern "C" fn signal_process_prekey_bundle(
+    identity_change: *mut u8,
     bundle: *mut PreKeyBundle,
     protocol_address: *const ProtocolAddress,
     session_store: *const FfiSessionStoreStruct,
@@ -1264,16 +1894,76 @@ pub unsafe extern "C" fn signal_process_prekey_bundle(
         let mut identity_key_store = FfiIdentityKeyStore::new(identity_key_store)?;
         let mut session_store = FfiSessionStore::new(session_store)?;
 
+        if identity_change.is_null() {
+            return Err(SignalFfiError::NullPointer);
+        }
+
         let mut csprng = rand::rngs::OsRng;
-        expect_ready(process_prekey_bundle(
+        *identity_change = FfiIdentityChange::from(expect_ready(process_prekey_bundle(
             &protocol_address,
             &mut session_store,
             &mut identity_key_store,
             bundle,
             &mut csprng,
             Some(ctx),
+        ))?) as u8;
+
+        Ok(())
+    })
+}
+
+/// Filled in by [signal_process_prekey_bundle_with_report].
+#[derive(Debug)]
+#[repr(C)]
+pub struct FfiPreKeyBundleReport {
+    /// If false, the bundle had no one-time pre-key and `pre_key_id` is 0.
+    has_pre_key_id: bool,
+    pre_key_id: u32,
+    signed_pre_key_id: u32,
+    session_version: u32,
+    /// An [FfiIdentityChange].
+    identity_change: u8,
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_process_prekey_bundle_with_report(
+    report: *mut FfiPreKeyBundleReport,
+    bundle: *mut PreKeyBundle,
+    protocol_address: *const ProtocolAddress,
+    session_store: *const FfiSessionStoreStruct,
+    identity_key_store: *const FfiIdentityKeyStoreStruct,
+    ctx: *mut c_void,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let bundle = native_handle_cast::<PreKeyBundle>(bundle)?;
+        let protocol_address = native_handle_cast::<ProtocolAddress>(protocol_address)?;
+
+        let mut identity_key_store = FfiIdentityKeyStore::new(identity_key_store)?;
+        let mut session_store = FfiSessionStore::new(session_store)?;
+
+        if report.is_null() {
+            return Err(SignalFfiError::NullPointer);
+        }
+
+        let mut csprng = rand::rngs::OsRng;
+        let result = expect_ready(process_prekey_bundle_with_report(
+            &protocol_address,
+            &mut session_store,
+            &mut identity_key_store,
+            bundle,
+            &SessionConfig::default(),
+            &mut csprng,
+            Some(ctx),
         ))?;
 
+        *report = FfiPreKeyBundleReport {
+            has_pre_key_id: result.pre_key_id.is_some(),
+            pre_key_id: result.pre_key_id.unwrap_or(0),
+            signed_pre_key_id: result.signed_pre_key_id,
+            session_version: result.session_version,
+            identity_change: FfiIdentityChange::from(result.identity_change) as u8,
+        };
+
         Ok(())
     })
 }
@@ -1307,6 +1997,59 @@ pub unsafe extern "C" fn signal_encrypt_message(
     })
 }
 
+/// Filled in by [signal_encrypt_message_with_details].
+#[derive(Debug)]
+#[repr(C)]
+pub struct FfiEncryptedMessage {
+    /// Owned by the caller, who frees it with [signal_free_buffer].
+    serialized: *const c_uchar,
+    serialized_len: size_t,
+    /// An [FfiCiphertextMessageType].
+    message_type: u8,
+    remote_registration_id: u32,
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_encrypt_message_with_details(
+    out: *mut FfiEncryptedMessage,
+    ptext: *const c_uchar,
+    ptext_len: size_t,
+    protocol_address: *const ProtocolAddress,
+    session_store: *const FfiSessionStoreStruct,
+    identity_key_store: *const FfiIdentityKeyStoreStruct,
+    ctx: *mut c_void,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let ptext = as_slice(ptext, ptext_len)?;
+        let protocol_address = native_handle_cast::<ProtocolAddress>(protocol_address)?;
+
+        let mut identity_key_store = FfiIdentityKeyStore::new(identity_key_store)?;
+        let mut session_store = FfiSessionStore::new(session_store)?;
+
+        if out.is_null() {
+            return Err(SignalFfiError::NullPointer);
+        }
+
+        let encrypted = expect_ready(message_encrypt_with_details(
+            &ptext,
+            &protocol_address,
+            &mut session_store,
+            &mut identity_key_store,
+            Some(ctx),
+        ))?;
+
+        let serialized = encrypted.serialized.into_boxed_slice();
+        *out = FfiEncryptedMessage {
+            serialized_len: serialized.len(),
+            serialized: Box::into_raw(serialized) as *const c_uchar,
+            message_type: encrypted.message_type as u8,
+            remote_registration_id: encrypted.remote_registration_id.into(),
+        };
+
+        Ok(())
+    })
+}
+
 ffi_fn_destroy!(signal_ciphertext_message_destroy destroys CiphertextMessage);
 
 #[derive(Debug)]
@@ -1316,6 +2059,7 @@ pub enum FfiCiphertextMessageType {
     PreKey = 3,
     SenderKey = 4,
     SenderKeyDistribution = 5,
+    Plaintext = 8,
 }
 
 const_assert_eq!(
@@ -1334,6 +2078,25 @@ const_assert_eq!(
     FfiCiphertextMessageType::SenderKeyDistribution as u8,
     CiphertextMessageType::SenderKeyDistribution as u8
 );
+const_assert_eq!(
+    FfiCiphertextMessageType::Plaintext as u8,
+    CiphertextMessageType::Plaintext as u8
+);
+
+/// Parses a message received with envelope type `message_type`, an [FfiCiphertextMessageType].
+#[no_mangle]
+pub unsafe extern "C" fn signal_ciphertext_message_deserialize(
+    msg: *mut *mut CiphertextMessage,
+    message_type: u8,
+    data: *const c_uchar,
+    data_len: size_t,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let data = as_slice(data, data_len)?;
+        let message_type = CiphertextMessageType::try_from(message_type)?;
+        box_object(msg, CiphertextMessage::deserialize(message_type, data))
+    })
+}
 
 #[no_mangle]
 pub unsafe extern "C" fn signal_ciphertext_message_type(
@@ -1426,6 +2189,254 @@ pub unsafe extern "C" fn signal_decrypt_pre_key_message(
     })
 }
 
+/// Completion callback for the `_async` functions that produce a byte array.
+///
+/// Called exactly once. Exactly one of `error` and `result` is non-null, and the callback takes
+/// ownership of it: free `error` with [signal_error_free] and `result` with [signal_free_buffer].
+type BytesCallback = extern "C" fn(
+    callback_ctx: *mut c_void,
+    error: *mut SignalFfiError,
+    result: *const c_uchar,
+    result_len: size_t,
+);
+
+/// Completion callback for [signal_encrypt_message_async].
+///
+/// Called exactly once. Exactly one of `error` and `result` is non-null, and the callback takes
+/// ownership of it: free `error` with [signal_error_free] and `result` with
+/// [signal_ciphertext_message_destroy].
+type CiphertextMessageCallback = extern "C" fn(
+    callback_ctx: *mut c_void,
+    error: *mut SignalFfiError,
+    result: *mut CiphertextMessage,
+);
+
+fn complete_with_bytes(
+    callback: BytesCallback,
+    callback_ctx: *mut c_void,
+    result: Result<Vec<u8>, SignalFfiError>,
+) {
+    match result {
+        Ok(bytes) => {
+            let bytes = bytes.into_boxed_slice();
+            let len = bytes.len();
+            let mem = Box::into_raw(bytes);
+            callback(
+                callback_ctx,
+                std::ptr::null_mut(),
+                unsafe { (*mem).as_ptr() },
+                len,
+            )
+        }
+        Err(e) => callback(
+            callback_ctx,
+            Box::into_raw(Box::new(e)),
+            std::ptr::null(),
+            0,
+        ),
+    }
+}
+
+fn complete_with_ciphertext_message(
+    callback: CiphertextMessageCallback,
+    callback_ctx: *mut c_void,
+    result: Result<CiphertextMessage, SignalFfiError>,
+) {
+    match result {
+        Ok(message) => callback(
+            callback_ctx,
+            std::ptr::null_mut(),
+            Box::into_raw(Box::new(message)),
+        ),
+        Err(e) => callback(
+            callback_ctx,
+            Box::into_raw(Box::new(e)),
+            std::ptr::null_mut(),
+        ),
+    }
+}
+
+/// What an `_async` function hands to its worker thread.
+///
+/// The message handles and store structs are copied before the function returns, but the store
+/// contexts, `ctx`, and `callback_ctx` are only pointers. The caller must keep them valid, and
+/// allow the store callbacks to be called from another thread, until the completion callback
+/// has run.
+struct WorkerJob<F>(F);
+
+unsafe impl<F> Send for WorkerJob<F> {}
+
+/// Runs `work` on a library worker thread and passes its result to `complete` on the same
+/// thread.
+///
+/// `complete` is called exactly once. If no worker thread is available it gets the error
+/// instead, on the calling thread.
+fn spawn_ffi_job<T, W, C>(work: W, complete: C)
+where
+    W: FnOnce() -> Result<T, SignalFfiError> + 'static,
+    C: FnOnce(Result<T, SignalFfiError>) + 'static,
+{
+    let job = WorkerJob((work, complete));
+    worker::submit(Box::new(move |started| {
+        let WorkerJob((work, complete)) = job;
+        let result = started.and_then(|()| {
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(work))
+                .unwrap_or_else(|r| Err(SignalFfiError::UnexpectedPanic(r)))
+        });
+        complete(result)
+    }));
+}
+
+/// Like [signal_encrypt_message], but encrypts on a library-managed thread and reports the
+/// result through `callback`.
+///
+/// If the arguments are invalid, `callback` is called with the error before this returns.
+///
+/// The store callbacks, and `callback` itself, are called from a library worker thread, so they
+/// must be safe to call from any thread. `ctx`, `callback_ctx`, and the stores' `ctx` pointers
+/// must stay valid until `callback` has run. The library runs a small fixed number of these jobs
+/// at a time and queues the rest; a job cannot be cancelled once this returns.
+#[no_mangle]
+pub unsafe extern "C" fn signal_encrypt_message_async(
+    ptext: *const c_uchar,
+    ptext_len: size_t,
+    protocol_address: *const ProtocolAddress,
+    session_store: *const FfiSessionStoreStruct,
+    identity_key_store: *const FfiIdentityKeyStoreStruct,
+    ctx: *mut c_void,
+    callback_ctx: *mut c_void,
+    callback: CiphertextMessageCallback,
+) {
+    let args = (|| -> Result<_, SignalFfiError> {
+        Ok((
+            as_slice(ptext, ptext_len)?.to_vec(),
+            native_handle_cast::<ProtocolAddress>(protocol_address)?.clone(),
+            FfiSessionStore::new(session_store)?,
+            FfiIdentityKeyStore::new(identity_key_store)?,
+        ))
+    })();
+    let (ptext, protocol_address, mut session_store, mut identity_key_store) = match args {
+        Ok(args) => args,
+        Err(e) => return complete_with_ciphertext_message(callback, callback_ctx, Err(e)),
+    };
+
+    spawn_ffi_job(
+        move || {
+            Ok(expect_ready(message_encrypt(
+                &ptext,
+                &protocol_address,
+                &mut session_store,
+                &mut identity_key_store,
+                Some(ctx),
+            ))?)
+        },
+        move |result| complete_with_ciphertext_message(callback, callback_ctx, result),
+    );
+}
+
+/// Like [signal_decrypt_message], but decrypts on a library-managed thread and reports the
+/// result through `callback`.
+///
+/// If the arguments are invalid, `callback` is called with the error before this returns.
+///
+/// The same threading rules as [signal_encrypt_message_async] apply.
+#[no_mangle]
+pub unsafe extern "C" fn signal_decrypt_message_async(
+    message: *const SignalMessage,
+    protocol_address: *const ProtocolAddress,
+    session_store: *const FfiSessionStoreStruct,
+    identity_key_store: *const FfiIdentityKeyStoreStruct,
+    ctx: *mut c_void,
+    callback_ctx: *mut c_void,
+    callback: BytesCallback,
+) {
+    let args = (|| -> Result<_, SignalFfiError> {
+        Ok((
+            native_handle_cast::<SignalMessage>(message)?.clone(),
+            native_handle_cast::<ProtocolAddress>(protocol_address)?.clone(),
+            FfiSessionStore::new(session_store)?,
+            FfiIdentityKeyStore::new(identity_key_store)?,
+        ))
+    })();
+    let (message, protocol_address, mut session_store, mut identity_key_store) = match args {
+        Ok(args) => args,
+        Err(e) => return complete_with_bytes(callback, callback_ctx, Err(e)),
+    };
+
+    spawn_ffi_job(
+        move || {
+            let mut csprng = rand::rngs::OsRng;
+            Ok(expect_ready(message_decrypt_signal(
+                &message,
+                &protocol_address,
+                &mut session_store,
+                &mut identity_key_store,
+                &mut csprng,
+                Some(ctx),
+            ))?)
+        },
+        move |result| complete_with_bytes(callback, callback_ctx, result),
+    );
+}
+
+/// Like [signal_decrypt_pre_key_message], but decrypts on a library-managed thread and reports
+/// the result through `callback`.
+///
+/// If the arguments are invalid, `callback` is called with the error before this returns.
+///
+/// The same threading rules as [signal_encrypt_message_async] apply.
+#[no_mangle]
+pub unsafe extern "C" fn signal_decrypt_pre_key_message_async(
+    message: *const PreKeySignalMessage,
+    protocol_address: *const ProtocolAddress,
+    session_store: *const FfiSessionStoreStruct,
+    identity_key_store: *const FfiIdentityKeyStoreStruct,
+    prekey_store: *const FfiPreKeyStoreStruct,
+    signed_prekey_store: *const FfiSignedPreKeyStoreStruct,
+    ctx: *mut c_void,
+    callback_ctx: *mut c_void,
+    callback: BytesCallback,
+) {
+    let args = (|| -> Result<_, SignalFfiError> {
+        Ok((
+            native_handle_cast::<PreKeySignalMessage>(message)?.clone(),
+            native_handle_cast::<ProtocolAddress>(protocol_address)?.clone(),
+            FfiSessionStore::new(session_store)?,
+            FfiIdentityKeyStore::new(identity_key_store)?,
+            FfiPreKeyStore::new(prekey_store)?,
+            FfiSignedPreKeyStore::new(signed_prekey_store)?,
+        ))
+    })();
+    let (
+        message,
+        protocol_address,
+        mut session_store,
+        mut identity_key_store,
+        mut prekey_store,
+        mut signed_prekey_store,
+    ) = match args {
+        Ok(args) => args,
+        Err(e) => return complete_with_bytes(callback, callback_ctx, Err(e)),
+    };
+
+    spawn_ffi_job(
+        move || {
+            let mut csprng = rand::rngs::OsRng;
+            Ok(expect_ready(message_decrypt_prekey(
+                &message,
+                &protocol_address,
+                &mut session_store,
+                &mut identity_key_store,
+                &mut prekey_store,
+                &mut signed_prekey_store,
+                &mut csprng,
+                Some(ctx),
+            ))?)
+        },
+        move |result| complete_with_bytes(callback, callback_ctx, result),
+    );
+}
+
 type LoadSenderKey = extern "C" fn(
     store_ctx: *mut c_void,
     *mut *mut SenderKeyRecord,
@@ -1563,6 +2574,32 @@ pub unsafe extern "C" fn signal_process_sender_key_distribution_message(
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn signal_process_sender_key_distribution_message_with_expected_id(
+    sender_key_name: *const SenderKeyName,
+    sender_key_distribution_message: *const SenderKeyDistributionMessage,
+    expected_id: u32,
+    store: *const FfiSenderKeyStoreStruct,
+    ctx: *mut c_void,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let sender_key_name = native_handle_cast::<SenderKeyName>(sender_key_name)?;
+        let sender_key_distribution_message =
+            native_handle_cast::<SenderKeyDistributionMessage>(sender_key_distribution_message)?;
+        let mut sender_key_store = FfiSenderKeyStore::new(store)?;
+
+        expect_ready(process_sender_key_distribution_message_with_expected_id(
+            sender_key_name,
+            sender_key_distribution_message,
+            Some(expected_id),
+            &mut sender_key_store,
+            Some(ctx),
+        ))?;
+
+        Ok(())
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn signal_group_encrypt_message(
     out: *mut *const c_uchar,
@@ -1589,6 +2626,56 @@ pub unsafe extern "C" fn signal_group_encrypt_message(
     })
 }
 
+/// Filled in by [signal_group_encrypt_message_with_metadata].
+#[derive(Debug)]
+#[repr(C)]
+pub struct FfiGroupEncryptedMessage {
+    /// Owned by the caller, who frees it with [signal_free_buffer].
+    serialized: *const c_uchar,
+    serialized_len: size_t,
+    chain_id: u32,
+    iteration: u32,
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_group_encrypt_message_with_metadata(
+    out: *mut FfiGroupEncryptedMessage,
+    sender_key_name: *const SenderKeyName,
+    message: *const c_uchar,
+    message_len: size_t,
+    store: *const FfiSenderKeyStoreStruct,
+    ctx: *mut c_void,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let sender_key_name = native_handle_cast::<SenderKeyName>(sender_key_name)?;
+        let message = as_slice(message, message_len)?;
+        let mut sender_key_store = FfiSenderKeyStore::new(store)?;
+
+        if out.is_null() {
+            return Err(SignalFfiError::NullPointer);
+        }
+
+        let mut rng = rand::rngs::OsRng;
+        let encrypted = expect_ready(group_encrypt_with_metadata(
+            &mut sender_key_store,
+            &sender_key_name,
+            &message,
+            &mut rng,
+            Some(ctx),
+        ))?;
+
+        let serialized = encrypted.serialized.into_boxed_slice();
+        *out = FfiGroupEncryptedMessage {
+            serialized_len: serialized.len(),
+            serialized: Box::into_raw(serialized) as *const c_uchar,
+            chain_id: encrypted.chain_id,
+            iteration: encrypted.iteration,
+        };
+
+        Ok(())
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn signal_group_decrypt_message(
     out: *mut *const c_uchar,
@@ -1613,3 +2700,392 @@ pub unsafe extern "C" fn signal_group_decrypt_message(
         write_bytearray_to(out, out_len, ptext)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Mutex};
+    use std::time::Duration;
+
+    type Outcome = Result<Vec<u8>, u32>;
+
+    extern "C" fn record_outcome(
+        callback_ctx: *mut c_void,
+        error: *mut SignalFfiError,
+        result: *const c_uchar,
+        result_len: size_t,
+    ) {
+        let sender = unsafe { &*(callback_ctx as *const Mutex<mpsc::Sender<Outcome>>) };
+        let outcome = unsafe {
+            if error.is_null() {
+                let bytes = as_slice(result, result_len).expect("result").to_vec();
+                signal_free_buffer(result, result_len);
+                Ok(bytes)
+            } else {
+                let code = signal_error_get_type(error);
+                signal_error_free(error);
+                Err(code)
+            }
+        };
+        sender.lock().expect("lock").send(outcome).expect("send");
+    }
+
+    extern "C" fn no_session(
+        _store_ctx: *mut c_void,
+        recordp: *mut *mut SessionRecord,
+        _address: *const ProtocolAddress,
+        _ctx: *mut c_void,
+    ) -> c_int {
+        unsafe { *recordp = std::ptr::null_mut() };
+        0
+    }
+    extern "C" fn store_session(
+        _: *mut c_void,
+        _: *const ProtocolAddress,
+        _: *const SessionRecord,
+        _: *mut c_void,
+    ) -> c_int {
+        -1
+    }
+
+    extern "C" fn get_identity_key_pair(
+        _: *mut c_void,
+        _: *mut *mut PrivateKey,
+        _: *mut c_void,
+    ) -> c_int {
+        -1
+    }
+    extern "C" fn get_local_registration_id(_: *mut c_void, _: *mut u32, _: *mut c_void) -> c_int {
+        -1
+    }
+    extern "C" fn save_identity(
+        _: *mut c_void,
+        _: *const ProtocolAddress,
+        _: *const PublicKey,
+        _: *mut c_void,
+    ) -> c_int {
+        -1
+    }
+    extern "C" fn get_identity(
+        _: *mut c_void,
+        _: *mut *mut PublicKey,
+        _: *const ProtocolAddress,
+        _: *mut c_void,
+    ) -> c_int {
+        -1
+    }
+    extern "C" fn is_trusted_identity(
+        _: *mut c_void,
+        _: *const ProtocolAddress,
+        _: *const PublicKey,
+        _: c_uint,
+        _: *mut c_void,
+    ) -> c_int {
+        -1
+    }
+
+    fn session_store() -> FfiSessionStoreStruct {
+        FfiSessionStoreStruct {
+            ctx: std::ptr::null_mut(),
+            load_session: no_session,
+            store_session,
+            get_sub_device_sessions: None,
+            delete_session: None,
+            delete_all_sessions: None,
+        }
+    }
+
+    fn identity_key_store() -> FfiIdentityKeyStoreStruct {
+        FfiIdentityKeyStoreStruct {
+            ctx: std::ptr::null_mut(),
+            get_identity_key_pair,
+            get_local_registration_id,
+            save_identity,
+            get_identity,
+            is_trusted_identity,
+        }
+    }
+
+    fn decrypt_async(message: *const SignalMessage) -> mpsc::Receiver<Outcome> {
+        let (sender, receiver) = mpsc::channel();
+        // Leaked so that it outlives a callback that never comes.
+        let sender = Box::leak(Box::new(Mutex::new(sender)));
+        let device_id = DeviceId::try_from(1).expect("valid");
+        let address = ProtocolAddress::new("+14151111111".to_string(), device_id);
+        let session_store = session_store();
+        let identity_key_store = identity_key_store();
+        unsafe {
+            signal_decrypt_message_async(
+                message,
+                &address,
+                &session_store,
+                &identity_key_store,
+                std::ptr::null_mut(),
+                sender as *mut _ as *mut c_void,
+                record_outcome,
+            );
+        }
+        receiver
+    }
+
+    fn expect_single_outcome(receiver: mpsc::Receiver<Outcome>) -> Outcome {
+        let outcome = receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("callback was called");
+        assert!(
+            receiver.recv_timeout(Duration::from_millis(100)).is_err(),
+            "callback was called more than once"
+        );
+        outcome
+    }
+
+    fn error_code(err: SignalProtocolError) -> u32 {
+        let err = Box::into_raw(Box::new(SignalFfiError::Signal(err)));
+        unsafe {
+            let code = signal_error_get_type(err);
+            signal_error_free(err);
+            code
+        }
+    }
+
+    fn error_message(err: SignalProtocolError) -> String {
+        let err = Box::into_raw(Box::new(SignalFfiError::Signal(err)));
+        let mut message = std::ptr::null();
+        unsafe {
+            assert!(signal_error_get_message(err, &mut message).is_null());
+            signal_error_free(err);
+            let result = std::ffi::CStr::from_ptr(message)
+                .to_str()
+                .expect("utf8")
+                .to_string();
+            signal_free_string(message);
+            result
+        }
+    }
+
+    fn error_address(err: SignalProtocolError) -> Option<ProtocolAddress> {
+        let err = Box::into_raw(Box::new(SignalFfiError::Signal(err)));
+        let mut address = std::ptr::null_mut();
+        unsafe {
+            let lookup = signal_error_get_address(err, &mut address);
+            signal_error_free(err);
+            if lookup.is_null() {
+                Some(*Box::from_raw(address))
+            } else {
+                assert_eq!(
+                    signal_error_get_type(lookup),
+                    SignalErrorCode::InvalidType as u32
+                );
+                signal_error_free(lookup);
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn protocol_errors_keep_their_code_and_detail() {
+        let address = ProtocolAddress::new(
+            "+14152222222".to_string(),
+            DeviceId::try_from(3).expect("valid"),
+        );
+        let cases = vec![
+            (
+                SignalProtocolError::InvalidArgument("bad input".to_string()),
+                SignalErrorCode::InvalidArgument,
+                "bad input",
+            ),
+            (
+                SignalProtocolError::InvalidState("load", "no record".to_string()),
+                SignalErrorCode::InvalidState,
+                "no record",
+            ),
+            (
+                SignalProtocolError::BadKeyType(7),
+                SignalErrorCode::InvalidKey,
+                "7",
+            ),
+            (
+                SignalProtocolError::InvalidMacKeyLength(16),
+                SignalErrorCode::InvalidKey,
+                "16",
+            ),
+            (
+                SignalProtocolError::LegacyCiphertextVersion(2),
+                SignalErrorCode::LegacyCiphertextVersion,
+                "2",
+            ),
+            (
+                SignalProtocolError::InvalidPreKeyBundle,
+                SignalErrorCode::InvalidPreKeyBundle,
+                "bundle",
+            ),
+            (
+                SignalProtocolError::SignaturePubkeyMissing,
+                SignalErrorCode::InvalidSignature,
+                "",
+            ),
+            (
+                SignalProtocolError::InvalidSenderKeyId,
+                SignalErrorCode::InvalidKeyIdentifier,
+                "",
+            ),
+            (
+                SignalProtocolError::OperationNotSupported("delete_all_sessions"),
+                SignalErrorCode::OperationNotSupported,
+                "delete_all_sessions",
+            ),
+            (
+                SignalProtocolError::StorageIoError(
+                    "/tmp/store".to_string(),
+                    ErrorCause::new(std::io::Error::new(std::io::ErrorKind::Other, "disk full")),
+                ),
+                SignalErrorCode::StorageError,
+                "/tmp/store",
+            ),
+            (
+                SignalProtocolError::ApplicationCallbackError(
+                    "load_session",
+                    ErrorCause::new("database locked"),
+                ),
+                SignalErrorCode::CallbackError,
+                "database locked",
+            ),
+            (
+                SignalProtocolError::ApplicationCallbackReturnedIntegerError("load_session", -7),
+                SignalErrorCode::CallbackError,
+                "-7",
+            ),
+            (
+                SignalProtocolError::SessionNotFoundForAddress(address.clone()),
+                SignalErrorCode::SessionNotFound,
+                "+14152222222",
+            ),
+            (
+                SignalProtocolError::UntrustedIdentity(address.clone()),
+                SignalErrorCode::UntrustedIdentity,
+                "+14152222222",
+            ),
+        ];
+
+        for (err, code, detail) in cases {
+            let description = format!("{:?}", err);
+            assert_eq!(error_code(err.clone()), code as u32, "{}", description);
+            assert!(
+                error_message(err).contains(detail),
+                "{} lost {:?}",
+                description,
+                detail
+            );
+        }
+    }
+
+    #[test]
+    fn errors_report_their_address() {
+        let address = ProtocolAddress::new(
+            "+14152222222".to_string(),
+            DeviceId::try_from(3).expect("valid"),
+        );
+        assert_eq!(
+            error_address(SignalProtocolError::SessionNotFoundForAddress(
+                address.clone()
+            )),
+            Some(address.clone())
+        );
+        assert_eq!(
+            error_address(SignalProtocolError::UntrustedIdentity(address.clone())),
+            Some(address)
+        );
+        assert_eq!(error_address(SignalProtocolError::SessionNotFound), None);
+    }
+
+    #[test]
+    fn last_error_is_saved_per_thread() {
+        let mut key = std::ptr::null_mut();
+        let data = [0x05u8; 10];
+        unsafe {
+            assert!(signal_error_take_last().is_null());
+
+            let err = signal_publickey_deserialize(&mut key, data.as_ptr(), data.len());
+            assert!(!err.is_null());
+            let code = signal_error_get_type(err);
+            signal_error_free(err);
+
+            let other_thread = std::thread::spawn(|| signal_error_take_last().is_null());
+            assert!(other_thread.join().expect("no panic"));
+
+            let last = signal_error_take_last();
+            assert!(!last.is_null());
+            assert_eq!(signal_error_get_type(last), code);
+            assert_eq!(code, SignalErrorCode::InvalidKey as u32);
+            let mut message = std::ptr::null();
+            assert!(signal_error_get_message(last, &mut message).is_null());
+            let message_text = std::ffi::CStr::from_ptr(message).to_str().expect("utf8");
+            assert!(message_text.contains("10"), "{}", message_text);
+            signal_free_string(message);
+            signal_error_free(last);
+
+            assert!(signal_error_take_last().is_null());
+        }
+    }
+
+    #[test]
+    fn missing_session_store_callbacks_are_not_supported() {
+        let store = session_store();
+        let mut store = FfiSessionStore::new(&store).expect("non-null");
+        let address = ProtocolAddress::new(
+            "+14152222222".to_string(),
+            DeviceId::try_from(1).expect("valid"),
+        );
+
+        assert!(matches!(
+            expect_ready(store.get_sub_device_sessions("+14152222222", None)),
+            Err(SignalProtocolError::OperationNotSupported(
+                "get_sub_device_sessions"
+            ))
+        ));
+        assert!(matches!(
+            expect_ready(store.delete_session(&address, None)),
+            Err(SignalProtocolError::OperationNotSupported("delete_session"))
+        ));
+        assert!(matches!(
+            expect_ready(store.delete_all_sessions("+14152222222", None)),
+            Err(SignalProtocolError::OperationNotSupported(
+                "delete_all_sessions"
+            ))
+        ));
+    }
+
+    #[test]
+    fn async_decrypt_reports_invalid_arguments() {
+        let outcome = expect_single_outcome(decrypt_async(std::ptr::null()));
+        assert_eq!(outcome, Err(SignalErrorCode::NullParameter as u32));
+    }
+
+    /// A message for an address with no session.
+    fn undecryptable_message() -> SignalMessage {
+        let mut serialized = hex::decode(
+            "330a2105a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8\
+             a209102a1829220a63697068657274657874",
+        )
+        .expect("valid hex");
+        serialized.extend_from_slice(&[0; 8]);
+        SignalMessage::try_from(&serialized[..]).expect("valid message")
+    }
+
+    #[test]
+    fn async_decrypt_reports_errors_from_the_worker_thread() {
+        let message = undecryptable_message();
+        let outcome = expect_single_outcome(decrypt_async(&message));
+        assert_eq!(outcome, Err(SignalErrorCode::SessionNotFound as u32));
+    }
+
+    #[test]
+    fn async_jobs_beyond_the_worker_count_are_queued() {
+        let message = undecryptable_message();
+        let receivers: Vec<_> = (0..12).map(|_| decrypt_async(&message)).collect();
+        for receiver in receivers {
+            let outcome = expect_single_outcome(receiver);
+            assert_eq!(outcome, Err(SignalErrorCode::SessionNotFound as u32));
+        }
+    }
+}
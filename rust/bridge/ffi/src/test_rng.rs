@@ -0,0 +1,48 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Entry points that take a seeded `TestRng` in place of OsRng, so that callers can reproduce
+//! the shared test vectors. Only built with the test-rng feature.
+
+use libc::{c_uchar, size_t};
+use libsignal_protocol_rust::*;
+
+use crate::ffi_fn_destroy;
+use crate::util::*;
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_test_rng_new(
+    rng: *mut *mut TestRng,
+    seed: u64,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| box_object::<TestRng>(rng, Ok(TestRng::new(seed))))
+}
+
+ffi_fn_destroy!(signal_test_rng_destroy destroys TestRng);
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_privatekey_generate_with_test_rng(
+    key: *mut *mut PrivateKey,
+    rng: *mut TestRng,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let rng = native_handle_cast_mut::<TestRng>(rng)?;
+        let keypair = KeyPair::generate(rng);
+        box_object::<PrivateKey>(key, Ok(keypair.private_key))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn signal_unrestricted_unidentified_access_key_with_test_rng(
+    access_key: *mut *const c_uchar,
+    access_key_len: *mut size_t,
+    rng: *mut TestRng,
+) -> *mut SignalFfiError {
+    run_ffi_safe(|| {
+        let rng = native_handle_cast_mut::<TestRng>(rng)?;
+        let key = unrestricted_unidentified_access_key(rng);
+        write_bytearray_to(access_key, access_key_len, Ok(key.to_vec()))
+    })
+}
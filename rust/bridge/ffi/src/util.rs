@@ -7,6 +7,7 @@ use futures::pin_mut;
 use futures::task::noop_waker_ref;
 use libc::{c_char, c_uchar, c_uint, c_ulonglong, size_t};
 use libsignal_protocol_rust::*;
+use std::cell::RefCell;
 use std::ffi::{CStr, CString};
 use std::fmt;
 use std::future::Future;
@@ -26,6 +27,9 @@ pub enum SignalFfiError {
 #[derive(Debug)]
 #[repr(C)]
 pub enum SignalErrorCode {
+    // No error maps to this any more, but the value stays reserved so that codes already handled
+    // by callers keep their meaning.
+    #[allow(dead_code)]
     UnknownError = 1,
     InvalidState = 2,
     InternalError = 3,
@@ -34,6 +38,7 @@ pub enum SignalErrorCode {
     InvalidType = 6,
     InvalidUtf8String = 7,
     InsufficientOutputSize = 8,
+    OperationNotSupported = 9,
 
     ProtobufError = 10,
 
@@ -42,12 +47,14 @@ pub enum SignalErrorCode {
     UnknownCiphertextVersion = 22,
     UnrecognizedMessageVersion = 23,
     InvalidMessage = 30,
+    InvalidPreKeyBundle = 31,
 
     InvalidKey = 40,
     InvalidSignature = 41,
 
     FingerprintIdentifierMismatch = 50,
     FingerprintVersionMismatch = 51,
+    FingerprintParsingError = 52,
 
     UntrustedIdentity = 60,
 
@@ -58,93 +65,120 @@ pub enum SignalErrorCode {
     DuplicatedMessage = 90,
 
     CallbackError = 100,
+
+    StorageError = 110,
 }
 
 impl From<&SignalFfiError> for SignalErrorCode {
     fn from(err: &SignalFfiError) -> Self {
         match err {
+            SignalFfiError::Signal(e) => e.into(),
             SignalFfiError::NullPointer => SignalErrorCode::NullParameter,
             SignalFfiError::InvalidType => SignalErrorCode::InvalidType,
             SignalFfiError::UnexpectedPanic(_) => SignalErrorCode::InternalError,
-
             SignalFfiError::CallbackError(_) => SignalErrorCode::CallbackError,
             SignalFfiError::InvalidUtf8String => SignalErrorCode::InvalidUtf8String,
             SignalFfiError::InsufficientOutputSize(_, _) => SignalErrorCode::InsufficientOutputSize,
+        }
+    }
+}
 
-            SignalFfiError::Signal(SignalProtocolError::ProtobufEncodingError(_))
-            | SignalFfiError::Signal(SignalProtocolError::ProtobufDecodingError(_)) => {
-                SignalErrorCode::ProtobufError
-            }
+/// Every variant is listed, with no catch-all arm, so that a new variant has to be given a code.
+impl From<&SignalProtocolError> for SignalErrorCode {
+    fn from(err: &SignalProtocolError) -> Self {
+        match err {
+            SignalProtocolError::InvalidArgument(_) => SignalErrorCode::InvalidArgument,
 
-            SignalFfiError::Signal(SignalProtocolError::DuplicatedMessage(_, _)) => {
-                SignalErrorCode::DuplicatedMessage
-            }
+            SignalProtocolError::InvalidState(_, _)
+            | SignalProtocolError::NoSenderKeyState
+            | SignalProtocolError::SenderKeySigningKeyMissing
+            | SignalProtocolError::InvalidSessionStructure => SignalErrorCode::InvalidState,
 
-            SignalFfiError::Signal(SignalProtocolError::InvalidPreKeyId)
-            | SignalFfiError::Signal(SignalProtocolError::InvalidSignedPreKeyId)
-            | SignalFfiError::Signal(SignalProtocolError::InvalidSenderKeyId) => {
-                SignalErrorCode::InvalidKeyIdentifier
+            SignalProtocolError::InternalError(_) | SignalProtocolError::FfiBindingError(_) => {
+                SignalErrorCode::InternalError
             }
 
-            SignalFfiError::Signal(SignalProtocolError::SignatureValidationFailed) => {
-                SignalErrorCode::InvalidSignature
-            }
+            SignalProtocolError::OperationNotSupported(_) => SignalErrorCode::OperationNotSupported,
 
-            SignalFfiError::Signal(SignalProtocolError::NoKeyTypeIdentifier)
-            | SignalFfiError::Signal(SignalProtocolError::BadKeyType(_))
-            | SignalFfiError::Signal(SignalProtocolError::BadKeyLength(_, _)) => {
-                SignalErrorCode::InvalidKey
-            }
+            SignalProtocolError::ProtobufEncodingError(_)
+            | SignalProtocolError::ProtobufDecodingError(_) => SignalErrorCode::ProtobufError,
 
-            SignalFfiError::Signal(SignalProtocolError::SessionNotFound) => {
-                SignalErrorCode::SessionNotFound
-            }
+            SignalProtocolError::CiphertextMessageTooShort(_)
+            | SignalProtocolError::InvalidCiphertext
+            | SignalProtocolError::InvalidCiphertextLength(_)
+            | SignalProtocolError::InvalidCiphertextPadding => SignalErrorCode::InvalidCiphertext,
 
-            SignalFfiError::Signal(SignalProtocolError::FingerprintIdentifierMismatch) => {
-                SignalErrorCode::FingerprintIdentifierMismatch
+            SignalProtocolError::LegacyCiphertextVersion(_) => {
+                SignalErrorCode::LegacyCiphertextVersion
             }
 
-            SignalFfiError::Signal(SignalProtocolError::FingerprintVersionMismatch) => {
-                SignalErrorCode::FingerprintVersionMismatch
+            SignalProtocolError::UnrecognizedCiphertextVersion(_) => {
+                SignalErrorCode::UnknownCiphertextVersion
             }
 
-            SignalFfiError::Signal(SignalProtocolError::CiphertextMessageTooShort(_))
-            | SignalFfiError::Signal(SignalProtocolError::InvalidCiphertext) => {
-                SignalErrorCode::InvalidCiphertext
+            SignalProtocolError::UnrecognizedMessageVersion(_) => {
+                SignalErrorCode::UnrecognizedMessageVersion
             }
 
-            SignalFfiError::Signal(SignalProtocolError::UnrecognizedMessageVersion(_)) => {
-                SignalErrorCode::UnrecognizedMessageVersion
+            SignalProtocolError::InvalidMessage(_)
+            | SignalProtocolError::MessageTooFarInFuture { .. }
+            | SignalProtocolError::InvalidProtobufEncoding
+            | SignalProtocolError::InvalidRegistrationId(_)
+            | SignalProtocolError::UnrecognizedRecordVersion(_)
+            | SignalProtocolError::UnrecognizedCiphertextMessageType(_) => {
+                SignalErrorCode::InvalidMessage
             }
 
-            SignalFfiError::Signal(SignalProtocolError::UnrecognizedCiphertextVersion(_)) => {
-                SignalErrorCode::UnknownCiphertextVersion
+            SignalProtocolError::InvalidPreKeyBundle => SignalErrorCode::InvalidPreKeyBundle,
+
+            SignalProtocolError::NoKeyTypeIdentifier
+            | SignalProtocolError::BadKeyType(_)
+            | SignalProtocolError::BadKeyLength(_, _)
+            | SignalProtocolError::MismatchedKeyTypes(_, _)
+            | SignalProtocolError::MismatchedSignatureLengthForKey(_, _)
+            | SignalProtocolError::InvalidRootKeyLength(_)
+            | SignalProtocolError::InvalidChainKeyLength(_)
+            | SignalProtocolError::InvalidMacKeyLength(_)
+            | SignalProtocolError::InvalidCipherCryptographicParameters(_, _) => {
+                SignalErrorCode::InvalidKey
             }
 
-            SignalFfiError::Signal(SignalProtocolError::InvalidMessage(_))
-            | SignalFfiError::Signal(SignalProtocolError::InvalidProtobufEncoding) => {
-                SignalErrorCode::InvalidMessage
+            SignalProtocolError::SignatureValidationFailed
+            | SignalProtocolError::SignaturePubkeyMissing => SignalErrorCode::InvalidSignature,
+
+            SignalProtocolError::FingerprintIdentifierMismatch => {
+                SignalErrorCode::FingerprintIdentifierMismatch
             }
 
-            SignalFfiError::Signal(SignalProtocolError::LegacyCiphertextVersion(_)) => {
-                SignalErrorCode::LegacyCiphertextVersion
+            SignalProtocolError::FingerprintVersionMismatch => {
+                SignalErrorCode::FingerprintVersionMismatch
             }
 
-            SignalFfiError::Signal(SignalProtocolError::UntrustedIdentity(_)) => {
-                SignalErrorCode::UntrustedIdentity
+            SignalProtocolError::FingerprintParsingError(_) => {
+                SignalErrorCode::FingerprintParsingError
             }
 
-            SignalFfiError::Signal(SignalProtocolError::InvalidState(_, _))
-            | SignalFfiError::Signal(SignalProtocolError::NoSenderKeyState)
-            | SignalFfiError::Signal(SignalProtocolError::InvalidSessionStructure) => {
-                SignalErrorCode::InvalidState
+            SignalProtocolError::UntrustedIdentity(_) => SignalErrorCode::UntrustedIdentity,
+
+            SignalProtocolError::InvalidPreKeyId
+            | SignalProtocolError::InvalidSignedPreKeyId
+            | SignalProtocolError::InvalidSenderKeyId
+            | SignalProtocolError::SenderKeyDistributionIdMismatch { .. } => {
+                SignalErrorCode::InvalidKeyIdentifier
             }
 
-            SignalFfiError::Signal(SignalProtocolError::InvalidArgument(_)) => {
-                SignalErrorCode::InvalidArgument
+            SignalProtocolError::SessionNotFound
+            | SignalProtocolError::SessionNotFoundForAddress(_) => SignalErrorCode::SessionNotFound,
+
+            SignalProtocolError::DuplicatedMessage { .. } => SignalErrorCode::DuplicatedMessage,
+
+            SignalProtocolError::ApplicationCallbackError(_, _)
+            | SignalProtocolError::ApplicationCallbackThrewException(_, _, _)
+            | SignalProtocolError::ApplicationCallbackReturnedIntegerError(_, _) => {
+                SignalErrorCode::CallbackError
             }
 
-            _ => SignalErrorCode::UnknownError,
+            SignalProtocolError::StorageIoError(_, _) => SignalErrorCode::StorageError,
         }
     }
 }
@@ -163,7 +197,7 @@ impl fmt::Display for SignalFfiError {
                 write!(f, "needed {} elements only {} provided", n, h)
             }
 
-            SignalFfiError::UnexpectedPanic(e) => match e.downcast_ref::<&'static str>() {
+            SignalFfiError::UnexpectedPanic(e) => match panic_message(e.as_ref()) {
                 Some(s) => write!(f, "unexpected panic: {}", s),
                 None => write!(f, "unknown unexpected panic"),
             },
@@ -171,6 +205,45 @@ impl fmt::Display for SignalFfiError {
     }
 }
 
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> Option<&str> {
+    payload
+        .downcast_ref::<&'static str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
+
+impl SignalFfiError {
+    /// Makes an independent copy of this error, with a panic payload reduced to its message.
+    fn duplicate(&self) -> SignalFfiError {
+        match self {
+            SignalFfiError::Signal(e) => SignalFfiError::Signal(e.clone()),
+            SignalFfiError::InsufficientOutputSize(n, h) => {
+                SignalFfiError::InsufficientOutputSize(*n, *h)
+            }
+            SignalFfiError::NullPointer => SignalFfiError::NullPointer,
+            SignalFfiError::InvalidUtf8String => SignalFfiError::InvalidUtf8String,
+            SignalFfiError::UnexpectedPanic(e) => {
+                SignalFfiError::UnexpectedPanic(match panic_message(e.as_ref()) {
+                    Some(message) => Box::new(message.to_string()),
+                    None => Box::new(()),
+                })
+            }
+            SignalFfiError::CallbackError(c) => SignalFfiError::CallbackError(*c),
+            SignalFfiError::InvalidType => SignalFfiError::InvalidType,
+        }
+    }
+}
+
+thread_local! {
+    /// A copy of the last error returned through [run_ffi_safe] on this thread.
+    static LAST_ERROR: RefCell<Option<SignalFfiError>> = RefCell::new(None);
+}
+
+/// Removes and returns the error saved by the last failing [run_ffi_safe] call on this thread.
+pub fn take_last_error() -> Option<SignalFfiError> {
+    LAST_ERROR.with(|last| last.borrow_mut().take())
+}
+
 impl From<SignalProtocolError> for SignalFfiError {
     fn from(e: SignalProtocolError) -> SignalFfiError {
         SignalFfiError::Signal(e)
@@ -188,7 +261,10 @@ pub fn run_ffi_safe<F: FnOnce() -> Result<(), SignalFfiError> + std::panic::Unwi
 
     match result {
         Ok(()) => std::ptr::null_mut(),
-        Err(e) => Box::into_raw(Box::new(e)),
+        Err(e) => {
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some(e.duplicate()));
+            Box::into_raw(Box::new(e))
+        }
     }
 }
 
@@ -243,6 +319,11 @@ pub unsafe fn box_optional_object<T>(
     }
 }
 
+/// Borrows `input_len` bytes at `input` without copying them.
+///
+/// The caller only guarantees the buffer for the duration of the FFI call, so the slice must not
+/// be kept past it; anything that outlives the call (a deserialized object, work handed to another
+/// thread) has to copy the bytes.
 pub unsafe fn as_slice<'a>(
     input: *const c_uchar,
     input_len: size_t,
@@ -258,6 +339,35 @@ pub unsafe fn as_slice<'a>(
     Ok(std::slice::from_raw_parts(input, input_len as usize))
 }
 
+/// Splits a buffer of concatenated byte strings, as described by their lengths, into slices.
+pub unsafe fn as_slices<'a>(
+    input: *const c_uchar,
+    input_lens: *const size_t,
+    input_count: size_t,
+) -> Result<Vec<&'a [u8]>, SignalFfiError> {
+    let input_lens = std::slice::from_raw_parts(
+        if input_lens.is_null() {
+            if input_count != 0 {
+                return Err(SignalFfiError::NullPointer);
+            }
+            std::ptr::NonNull::dangling().as_ptr()
+        } else {
+            input_lens
+        },
+        input_count as usize,
+    );
+    let total_len = input_lens.iter().sum();
+    let mut input = as_slice(input, total_len)?;
+
+    let mut result = Vec::with_capacity(input_lens.len());
+    for &len in input_lens {
+        let (head, tail) = input.split_at(len as usize);
+        result.push(head);
+        input = tail;
+    }
+    Ok(result)
+}
+
 pub unsafe fn as_slice_mut<'a>(
     input: *mut c_uchar,
     input_len: size_t,
@@ -0,0 +1,80 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! The library-owned threads that run the `_async` entry points.
+//!
+//! [WORKER_COUNT] threads are started the first time a job is submitted and live for the rest of
+//! the process. Jobs wait for a free thread in the order they were submitted. There is no way to
+//! cancel a job once it has been submitted.
+
+use crate::util::SignalFfiError;
+use libsignal_protocol_rust::SignalProtocolError;
+use once_cell::sync::Lazy;
+use std::panic::AssertUnwindSafe;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// How many jobs can run at once.
+const WORKER_COUNT: usize = 4;
+
+/// Work for a worker thread.
+///
+/// It is called exactly once: with `Ok(())` on a worker thread, or with an error on the
+/// submitting thread if no worker could take it.
+pub type Job = Box<dyn FnOnce(Result<(), SignalFfiError>) + Send>;
+
+/// Sends jobs to the workers, or `None` if not a single worker thread could be started.
+static JOBS: Lazy<Option<Mutex<mpsc::Sender<Job>>>> = Lazy::new(start_workers);
+
+fn start_workers() -> Option<Mutex<mpsc::Sender<Job>>> {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let mut started = 0;
+    for i in 0..WORKER_COUNT {
+        let receiver = receiver.clone();
+        let spawned = thread::Builder::new()
+            .name(format!("signal-ffi-worker-{}", i))
+            .spawn(move || loop {
+                // The lock is only held while waiting, not while the job runs.
+                let job = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(poisoned) => poisoned.into_inner().recv(),
+                };
+                match job {
+                    // A panic in one job must not take the thread down with it.
+                    Ok(job) => {
+                        let _ = std::panic::catch_unwind(AssertUnwindSafe(|| job(Ok(()))));
+                    }
+                    Err(mpsc::RecvError) => return,
+                }
+            });
+        if spawned.is_ok() {
+            started += 1;
+        }
+    }
+    if started == 0 {
+        None
+    } else {
+        Some(Mutex::new(sender))
+    }
+}
+
+/// Queues `job` for the next free worker thread.
+pub fn submit(job: Job) {
+    let unsent = match &*JOBS {
+        Some(jobs) => match jobs.lock() {
+            Ok(jobs) => jobs.send(job),
+            Err(poisoned) => poisoned.into_inner().send(job),
+        }
+        .err()
+        .map(|mpsc::SendError(job)| job),
+        None => Some(job),
+    };
+    if let Some(job) = unsent {
+        job(Err(SignalFfiError::Signal(
+            SignalProtocolError::InternalError("could not start a worker thread"),
+        )));
+    }
+}
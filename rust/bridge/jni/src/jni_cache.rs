@@ -0,0 +1,383 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Classes and method IDs resolved once, when the library is loaded.
+//!
+//! `FindClass` and `GetMethodID` look their arguments up by name, and every store callback used
+//! to pay for both. `JNI_OnLoad` runs with the class loader that loaded the library, so resolving
+//! the classes there also works when that is not the system class loader. That loader is kept
+//! as well, for classes that are not listed in [CLASSES].
+//!
+//! Classes in [OPTIONAL_CLASSES] may be missing, so that loading the library does not depend on
+//! them. Android, for one, only has `CompletableFuture` from API level 24.
+
+use jni::objects::{GlobalRef, JClass, JMethodID, JObject, JValue};
+use jni::signature::{JavaType, TypeSignature};
+use jni::sys::{jint, jmethodID, JNI_ERR, JNI_VERSION_1_6};
+use jni::{JNIEnv, JavaVM};
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::ffi::c_void;
+
+use crate::util::SignalJniError;
+
+/// The class loader is taken from the first class.
+const CLASSES: &[&str] = &[
+    "org/whispersystems/libsignal/SignalProtocolAddress",
+    "org/whispersystems/libsignal/IdentityKey",
+    "org/whispersystems/libsignal/IdentityKeyOperations",
+    "org/whispersystems/libsignal/IdentityKeyPair",
+    "org/whispersystems/libsignal/PreKeyBundleReport",
+    "org/whispersystems/libsignal/EncryptedMessage",
+    "org/whispersystems/libsignal/groups/SenderKeyName",
+    "org/whispersystems/libsignal/groups/state/SenderKeyRecord",
+    "org/whispersystems/libsignal/groups/state/SenderKeyStore",
+    "org/whispersystems/libsignal/groups/state/EnumerableSenderKeyStore",
+    "org/whispersystems/libsignal/protocol/PreKeySignalMessage",
+    "org/whispersystems/libsignal/protocol/SignalMessage",
+    "org/whispersystems/libsignal/state/BatchPreKeyStore",
+    "org/whispersystems/libsignal/state/BatchSessionStore",
+    "org/whispersystems/libsignal/state/EnumerableIdentityKeyStore",
+    "org/whispersystems/libsignal/state/IdentityKeyStore",
+    "org/whispersystems/libsignal/state/IdentityKeyStore$Direction",
+    "org/whispersystems/libsignal/state/PreKeyRecord",
+    "org/whispersystems/libsignal/state/PreKeyStore",
+    "org/whispersystems/libsignal/state/SessionRecord",
+    "org/whispersystems/libsignal/state/SessionStore",
+    "org/whispersystems/libsignal/state/SignedPreKeyRecord",
+    "org/whispersystems/libsignal/state/SignedPreKeyStore",
+    "org/whispersystems/libsignal/state/StoreOp",
+    "org/whispersystems/libsignal/state/TransactionalStore",
+    "org/whispersystems/libsignal/DuplicateMessageException",
+    "org/whispersystems/libsignal/InvalidKeyException",
+    "org/whispersystems/libsignal/InvalidKeyIdException",
+    "org/whispersystems/libsignal/InvalidMessageException",
+    "org/whispersystems/libsignal/LegacyMessageException",
+    "org/whispersystems/libsignal/NoSessionException",
+    "org/whispersystems/libsignal/UntrustedIdentityException",
+    "org/whispersystems/libsignal/fingerprint/FingerprintParsingException",
+    "java/lang/AssertionError",
+    "java/lang/IllegalArgumentException",
+    "java/lang/IllegalStateException",
+    "java/lang/NullPointerException",
+    "java/lang/RuntimeException",
+    "java/lang/UnsupportedOperationException",
+];
+
+/// Classes that are cached if the platform has them. Methods of a missing class are left out of
+/// the cache too, and [find_class] fails for the class when it is used.
+const OPTIONAL_CLASSES: &[&str] = &["java/util/concurrent/CompletableFuture"];
+
+/// The store callbacks and the other methods the bridge calls, as (declaring class, name,
+/// signature).
+const METHODS: &[(&str, &str, &str)] = &[
+    (
+        "org/whispersystems/libsignal/IdentityKeyPair",
+        "getOperations",
+        "()Lorg/whispersystems/libsignal/IdentityKeyOperations;",
+    ),
+    (
+        "org/whispersystems/libsignal/IdentityKeyOperations",
+        "calculateSignature",
+        "([B)[B",
+    ),
+    (
+        "org/whispersystems/libsignal/IdentityKeyOperations",
+        "calculateAgreement",
+        "(Lorg/whispersystems/libsignal/ecc/ECPublicKey;)[B",
+    ),
+    (
+        "org/whispersystems/libsignal/state/IdentityKeyStore",
+        "getIdentityKeyPair",
+        "()Lorg/whispersystems/libsignal/IdentityKeyPair;",
+    ),
+    (
+        "org/whispersystems/libsignal/state/IdentityKeyStore",
+        "getLocalRegistrationId",
+        "()I",
+    ),
+    (
+        "org/whispersystems/libsignal/state/IdentityKeyStore",
+        "saveIdentity",
+        "(Lorg/whispersystems/libsignal/SignalProtocolAddress;Lorg/whispersystems/libsignal/IdentityKey;)Lorg/whispersystems/libsignal/state/IdentityKeyStore$IdentityChange;",
+    ),
+    (
+        "org/whispersystems/libsignal/state/IdentityKeyStore",
+        "isTrustedIdentity",
+        "(Lorg/whispersystems/libsignal/SignalProtocolAddress;Lorg/whispersystems/libsignal/IdentityKey;Lorg/whispersystems/libsignal/state/IdentityKeyStore$Direction;)Z",
+    ),
+    (
+        "org/whispersystems/libsignal/state/IdentityKeyStore",
+        "getIdentity",
+        "(Lorg/whispersystems/libsignal/SignalProtocolAddress;)Lorg/whispersystems/libsignal/IdentityKey;",
+    ),
+    (
+        "org/whispersystems/libsignal/state/EnumerableIdentityKeyStore",
+        "getAllIdentities",
+        "()Ljava/util/Map;",
+    ),
+    (
+        "org/whispersystems/libsignal/state/EnumerableIdentityKeyStore",
+        "deleteIdentity",
+        "(Lorg/whispersystems/libsignal/SignalProtocolAddress;)Z",
+    ),
+    (
+        "org/whispersystems/libsignal/state/PreKeyStore",
+        "loadPreKey",
+        "(I)Lorg/whispersystems/libsignal/state/PreKeyRecord;",
+    ),
+    (
+        "org/whispersystems/libsignal/state/PreKeyStore",
+        "storePreKey",
+        "(ILorg/whispersystems/libsignal/state/PreKeyRecord;)V",
+    ),
+    (
+        "org/whispersystems/libsignal/state/PreKeyStore",
+        "removePreKey",
+        "(I)V",
+    ),
+    (
+        "org/whispersystems/libsignal/state/BatchPreKeyStore",
+        "storePreKeys",
+        "([I[Lorg/whispersystems/libsignal/state/PreKeyRecord;)V",
+    ),
+    (
+        "org/whispersystems/libsignal/state/BatchPreKeyStore",
+        "removePreKeys",
+        "([I)V",
+    ),
+    (
+        "org/whispersystems/libsignal/state/SignedPreKeyStore",
+        "loadSignedPreKey",
+        "(I)Lorg/whispersystems/libsignal/state/SignedPreKeyRecord;",
+    ),
+    (
+        "org/whispersystems/libsignal/state/SignedPreKeyStore",
+        "storeSignedPreKey",
+        "(ILorg/whispersystems/libsignal/state/SignedPreKeyRecord;)V",
+    ),
+    (
+        "org/whispersystems/libsignal/state/SessionStore",
+        "loadSession",
+        "(Lorg/whispersystems/libsignal/SignalProtocolAddress;)Lorg/whispersystems/libsignal/state/SessionRecord;",
+    ),
+    (
+        "org/whispersystems/libsignal/state/SessionStore",
+        "storeSession",
+        "(Lorg/whispersystems/libsignal/SignalProtocolAddress;Lorg/whispersystems/libsignal/state/SessionRecord;)V",
+    ),
+    (
+        "org/whispersystems/libsignal/state/SessionStore",
+        "getSubDeviceSessions",
+        "(Ljava/lang/String;)Ljava/util/List;",
+    ),
+    (
+        "org/whispersystems/libsignal/state/SessionStore",
+        "deleteSession",
+        "(Lorg/whispersystems/libsignal/SignalProtocolAddress;)V",
+    ),
+    (
+        "org/whispersystems/libsignal/state/SessionStore",
+        "deleteAllSessions",
+        "(Ljava/lang/String;)V",
+    ),
+    (
+        "org/whispersystems/libsignal/state/BatchSessionStore",
+        "loadSessions",
+        "([Lorg/whispersystems/libsignal/SignalProtocolAddress;)[Lorg/whispersystems/libsignal/state/SessionRecord;",
+    ),
+    (
+        "org/whispersystems/libsignal/state/TransactionalStore",
+        "commit",
+        "([Lorg/whispersystems/libsignal/state/StoreOp;)V",
+    ),
+    (
+        "org/whispersystems/libsignal/groups/state/SenderKeyStore",
+        "storeSenderKey",
+        "(Lorg/whispersystems/libsignal/groups/SenderKeyName;Lorg/whispersystems/libsignal/groups/state/SenderKeyRecord;)V",
+    ),
+    (
+        "org/whispersystems/libsignal/groups/state/SenderKeyStore",
+        "loadSenderKey",
+        "(Lorg/whispersystems/libsignal/groups/SenderKeyName;)Lorg/whispersystems/libsignal/groups/state/SenderKeyRecord;",
+    ),
+    (
+        "org/whispersystems/libsignal/groups/state/EnumerableSenderKeyStore",
+        "getSendersForGroup",
+        "(Ljava/lang/String;)[Lorg/whispersystems/libsignal/SignalProtocolAddress;",
+    ),
+    (
+        "org/whispersystems/libsignal/groups/state/EnumerableSenderKeyStore",
+        "deleteSenderKeysForGroup",
+        "(Ljava/lang/String;)V",
+    ),
+    ("java/util/concurrent/CompletableFuture", "<init>", "()V"),
+    (
+        "java/util/concurrent/CompletableFuture",
+        "complete",
+        "(Ljava/lang/Object;)Z",
+    ),
+    (
+        "java/util/concurrent/CompletableFuture",
+        "completeExceptionally",
+        "(Ljava/lang/Throwable;)Z",
+    ),
+];
+
+/// A resolved method.
+pub struct Method {
+    pub class: &'static str,
+    id: jmethodID,
+    pub ret: JavaType,
+}
+
+impl Method {
+    pub fn id(&self) -> JMethodID<'static> {
+        JMethodID::from(self.id)
+    }
+}
+
+struct JniCache {
+    class_loader: GlobalRef,
+    classes: HashMap<&'static str, GlobalRef>,
+    /// Keyed by name and signature, which are unique across [METHODS].
+    methods: HashMap<(&'static str, &'static str), Method>,
+}
+
+// Method IDs stay valid for as long as their class is loaded, which the global references to the
+// classes guarantee, and may be used from any thread.
+unsafe impl Send for JniCache {}
+unsafe impl Sync for JniCache {}
+
+static CACHE: OnceCell<JniCache> = OnceCell::new();
+
+impl JniCache {
+    fn new(
+        env: &JNIEnv,
+        class_names: &[&'static str],
+        optional_class_names: &[&'static str],
+        method_names: &[(&'static str, &'static str, &'static str)],
+    ) -> Result<Self, SignalJniError> {
+        let mut classes = HashMap::new();
+        for &name in class_names {
+            let class = env.find_class(name)?;
+            classes.insert(name, env.new_global_ref(JObject::from(class))?);
+        }
+        for &name in optional_class_names {
+            match env.find_class(name) {
+                Ok(class) => {
+                    classes.insert(name, env.new_global_ref(JObject::from(class))?);
+                }
+                // FindClass leaves a NoClassDefFoundError pending.
+                Err(_) => env.exception_clear()?,
+            }
+        }
+
+        let mut methods = HashMap::new();
+        for &(class, name, sig) in method_names {
+            // Only an optional class can be missing.
+            let class_ref = match classes.get(class) {
+                Some(class_ref) => class_ref,
+                None => continue,
+            };
+            let id = env.get_method_id(JClass::from(class_ref.as_obj()), name, sig)?;
+            let ret = TypeSignature::from_str(sig)?.ret;
+            let method = Method {
+                class,
+                id: id.into_inner(),
+                ret,
+            };
+            methods.insert((name, sig), method);
+        }
+
+        // Classes from the bootstrap class loader report a null loader.
+        let mut class_loader = env
+            .call_method(
+                classes[class_names[0]].as_obj(),
+                "getClassLoader",
+                "()Ljava/lang/ClassLoader;",
+                &[],
+            )?
+            .l()?;
+        if class_loader.is_null() {
+            class_loader = env
+                .call_static_method(
+                    "java/lang/ClassLoader",
+                    "getSystemClassLoader",
+                    "()Ljava/lang/ClassLoader;",
+                    &[],
+                )?
+                .l()?;
+        }
+
+        Ok(Self {
+            class_loader: env.new_global_ref(class_loader)?,
+            classes,
+            methods,
+        })
+    }
+}
+
+fn cache() -> Result<&'static JniCache, SignalJniError> {
+    CACHE.get().ok_or(SignalJniError::CacheNotInitialized)
+}
+
+/// Resolves `classes`, whichever of `optional_classes` are present, and `methods`, taking the
+/// class loader from the first class. Only the first call has any effect.
+pub(crate) fn init(
+    env: &JNIEnv,
+    classes: &[&'static str],
+    optional_classes: &[&'static str],
+    methods: &[(&'static str, &'static str, &'static str)],
+) -> Result<(), SignalJniError> {
+    CACHE.get_or_try_init(|| JniCache::new(env, classes, optional_classes, methods))?;
+    Ok(())
+}
+
+#[no_mangle]
+pub extern "system" fn JNI_OnLoad(vm: JavaVM, _reserved: *mut c_void) -> jint {
+    let env = match vm.get_env() {
+        Ok(env) => env,
+        Err(_) => return JNI_ERR,
+    };
+    // On failure the exception from FindClass or GetMethodID is left pending, and
+    // System.loadLibrary reports it.
+    match init(&env, CLASSES, OPTIONAL_CLASSES, METHODS) {
+        Ok(()) => JNI_VERSION_1_6,
+        Err(_) => JNI_ERR,
+    }
+}
+
+/// Looks up `name`, such as `"java/lang/String"`, in the cache, and otherwise loads it with the
+/// class loader that loaded this library.
+pub fn find_class<'a>(env: &JNIEnv<'a>, name: &'static str) -> Result<JClass<'a>, SignalJniError> {
+    let cache = cache()?;
+    if let Some(class) = cache.classes.get(name) {
+        return Ok(JClass::from(class.as_obj()));
+    }
+
+    let binary_name = env.new_string(name.replace('/', "."))?;
+    let class = env.call_method(
+        cache.class_loader.as_obj(),
+        "loadClass",
+        "(Ljava/lang/String;)Ljava/lang/Class;",
+        &[JValue::from(JObject::from(binary_name))],
+    );
+    match class {
+        Ok(class) => Ok(JClass::from(class.l()?)),
+        Err(_) => {
+            env.exception_clear()?;
+            Err(SignalJniError::BadJniParameter(name))
+        }
+    }
+}
+
+/// The resolved method with this name and signature, if there is one.
+pub fn method(
+    name: &'static str,
+    sig: &'static str,
+) -> Result<Option<&'static Method>, SignalJniError> {
+    Ok(cache()?.methods.get(&(name, sig)))
+}
@@ -7,14 +7,21 @@
 #![deny(warnings)]
 
 use async_trait::async_trait;
-use jni::objects::{JClass, JObject, JString, JValue};
-use jni::sys::{jboolean, jbyteArray, jint, jlong, jobject, jstring};
-use jni::JNIEnv;
+use jni::objects::{GlobalRef, JClass, JObject, JString, JValue};
+use jni::sys::{
+    jboolean, jbyteArray, jint, jintArray, jlong, jobject, jobjectArray, jsize, jstring,
+};
+use jni::{JNIEnv, JavaVM};
 use libsignal_protocol_rust::*;
 use std::convert::TryFrom;
+use std::sync::Arc;
 
+mod jni_cache;
 mod util;
 
+#[cfg(feature = "test-rng")]
+mod test_rng;
+
 use crate::util::*;
 
 type JavaSessionStore = jobject;
@@ -22,7 +29,12 @@ type JavaIdentityKeyStore = jobject;
 type JavaPreKeyStore = jobject;
 type JavaSignedPreKeyStore = jobject;
 type JavaCiphertextMessage = jobject;
+type JavaPreKeyBundleReport = jobject;
+type JavaEncryptedMessage = jobject;
+type JavaGroupEncryptedMessage = jobject;
 type JavaSenderKeyStore = jobject;
+type JavaCompletableFuture = jobject;
+type JavaObject = jobject;
 
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ProtocolAddress_1New(
@@ -33,19 +45,49 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ProtocolAddress_
 ) -> ObjectHandle {
     run_ffi_safe(&env, || {
         let name: String = env.get_string(name)?.into();
-        let device_id = jint_to_u32(device_id)?;
+        let device_id = DeviceId::try_from(jint_to_u32(device_id)?)?;
         let address = ProtocolAddress::new(name, device_id);
         box_object::<ProtocolAddress>(Ok(address))
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ProtocolAddress_1NewFromServiceId(
+    env: JNIEnv,
+    _class: JClass,
+    service_id: jbyteArray,
+    device_id: jint,
+) -> ObjectHandle {
+    run_ffi_safe(&env, || {
+        let service_id = env.convert_byte_array(service_id)?;
+        let device_id = DeviceId::try_from(jint_to_u32(device_id)?)?;
+        let service_id = ServiceId::parse_from_service_id_binary(&service_id)?;
+        box_object::<ProtocolAddress>(Ok(ProtocolAddress::from_service_id(service_id, device_id)))
+    })
+}
+
 jni_fn_destroy!(Java_org_signal_client_internal_Native_ProtocolAddress_1Destroy destroys ProtocolAddress);
 
 jni_fn_get_jstring!(Java_org_signal_client_internal_Native_ProtocolAddress_1Name(ProtocolAddress) using
                     |p: &ProtocolAddress| Ok(p.name().to_string()));
 
 jni_fn_get_jint!(Java_org_signal_client_internal_Native_ProtocolAddress_1DeviceId(ProtocolAddress) using
-                 |obj: &ProtocolAddress| { Ok(obj.device_id()) });
+                 |obj: &ProtocolAddress| { Ok(obj.device_id().into()) });
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ProtocolAddress_1ServiceId(
+    env: JNIEnv,
+    _class: JClass,
+    handle: ObjectHandle,
+) -> jbyteArray {
+    run_ffi_safe(&env, || {
+        let address = native_handle_cast::<ProtocolAddress>(handle)?;
+        match address.service_id() {
+            Some(service_id) => to_jbytearray(&env, Ok(service_id.service_id_binary())),
+            None => Ok(std::ptr::null_mut()),
+        }
+    })
+}
 
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ECPublicKey_1Deserialize(
@@ -141,6 +183,31 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ECPrivateKey_1Si
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ECPrivateKey_1SignWithRandomness(
+    env: JNIEnv,
+    _class: JClass,
+    handle: ObjectHandle,
+    message: jbyteArray,
+    randomness: jbyteArray,
+) -> jbyteArray {
+    run_ffi_safe(&env, || {
+        let message = env.convert_byte_array(message)?;
+        let randomness = env.convert_byte_array(randomness)?;
+        let key = native_handle_cast::<PrivateKey>(handle)?;
+
+        let randomness = <&[u8; 64]>::try_from(&randomness[..]).map_err(|_| {
+            SignalProtocolError::InvalidArgument(format!(
+                "signature randomness must be 64 bytes, got {}",
+                randomness.len()
+            ))
+        })?;
+
+        let sig = key.calculate_signature_with_randomness(&message, randomness)?;
+        to_jbytearray(&env, Ok(sig))
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ECPrivateKey_1Agree(
     env: JNIEnv,
@@ -170,7 +237,46 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_IdentityKeyPair_
         let private_key = native_handle_cast::<PrivateKey>(private_key_handle)?;
         let identity_key = IdentityKey::new(*public_key);
         let identity_key_pair = IdentityKeyPair::new(identity_key, *private_key);
-        to_jbytearray(&env, Ok(identity_key_pair.serialize()))
+        to_jbytearray(&env, identity_key_pair.serialize())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_IdentityKeyPair_1SignAlternateIdentity(
+    env: JNIEnv,
+    _class: JClass,
+    public_key_handle: ObjectHandle,
+    private_key_handle: ObjectHandle,
+    other_identity_handle: ObjectHandle,
+) -> jbyteArray {
+    run_ffi_safe(&env, || {
+        let public_key = native_handle_cast::<PublicKey>(public_key_handle)?;
+        let private_key = native_handle_cast::<PrivateKey>(private_key_handle)?;
+        let other_identity = native_handle_cast::<PublicKey>(other_identity_handle)?;
+        let identity_key_pair = IdentityKeyPair::new(IdentityKey::new(*public_key), *private_key);
+        let mut rng = rand::rngs::OsRng;
+        let signature =
+            identity_key_pair.sign_alternate_identity(&IdentityKey::new(*other_identity), &mut rng);
+        to_jbytearray(&env, signature)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_IdentityKey_1VerifyAlternateIdentity(
+    env: JNIEnv,
+    _class: JClass,
+    public_key_handle: ObjectHandle,
+    other_identity_handle: ObjectHandle,
+    signature: jbyteArray,
+) -> jboolean {
+    run_ffi_safe(&env, || {
+        let public_key = native_handle_cast::<PublicKey>(public_key_handle)?;
+        let other_identity = native_handle_cast::<PublicKey>(other_identity_handle)?;
+        let signature = env.convert_byte_array(signature)?;
+
+        Ok(IdentityKey::new(*public_key)
+            .verify_alternate_identity(&IdentityKey::new(*other_identity), &signature)?
+            as jboolean)
     })
 }
 
@@ -226,6 +332,76 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_NumericFingerpri
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_NumericFingerprintGenerator_1NewMulti(
+    env: JNIEnv,
+    _class: JClass,
+    iterations: jint,
+    local_identifiers: jobjectArray,
+    local_key: jbyteArray,
+    remote_identifiers: jobjectArray,
+    remote_key: jbyteArray,
+) -> ObjectHandle {
+    run_ffi_safe(&env, || {
+        let iterations = jint_to_u32(iterations)?;
+
+        let local_identifiers = convert_byte_array_array(&env, local_identifiers)?;
+        let local_key = env.convert_byte_array(local_key)?;
+
+        let remote_identifiers = convert_byte_array_array(&env, remote_identifiers)?;
+        let remote_key = env.convert_byte_array(remote_key)?;
+
+        let local_identifiers: Vec<&[u8]> = local_identifiers.iter().map(Vec::as_slice).collect();
+        let remote_identifiers: Vec<&[u8]> = remote_identifiers.iter().map(Vec::as_slice).collect();
+
+        let local_key = IdentityKey::decode(&local_key)?;
+        let remote_key = IdentityKey::decode(&remote_key)?;
+        let fprint = Fingerprint::new_multi(
+            iterations,
+            &local_identifiers,
+            &local_key,
+            &remote_identifiers,
+            &remote_key,
+        )?;
+
+        box_object::<Fingerprint>(Ok(fprint))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_NumericFingerprintGenerator_1NewDisplayable(
+    env: JNIEnv,
+    _class: JClass,
+    iterations: jint,
+    local_identifier: jbyteArray,
+    local_key: jbyteArray,
+    remote_identifier: jbyteArray,
+    remote_key: jbyteArray,
+) -> jstring {
+    run_ffi_safe(&env, || {
+        let iterations = jint_to_u32(iterations)?;
+
+        let local_identifier = env.convert_byte_array(local_identifier)?;
+        let local_key = env.convert_byte_array(local_key)?;
+
+        let remote_identifier = env.convert_byte_array(remote_identifier)?;
+        let remote_key = env.convert_byte_array(remote_key)?;
+
+        let local_key = IdentityKey::decode(&local_key)?;
+        let remote_key = IdentityKey::decode(&remote_key)?;
+        let fingerprint = Fingerprint::new_displayable(
+            iterations,
+            &local_identifier,
+            &local_key,
+            &remote_identifier,
+            &remote_key,
+        )?;
+
+        let result = env.new_string(format!("{}", fingerprint))?;
+        Ok(result.into_inner())
+    })
+}
+
 jni_fn_destroy!(Java_org_signal_client_internal_Native_NumericFingerprintGenerator_1Destroy destroys Fingerprint);
 
 jni_fn_get_jstring!(Java_org_signal_client_internal_Native_NumericFingerprintGenerator_1GetDisplayString(Fingerprint) using
@@ -250,6 +426,70 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ScannableFingerp
     })
 }
 
+/// Returns `[comparison, ourVersion, theirVersion]`, where `comparison` is 0 for a match, 1 for a
+/// local mismatch, 2 for a remote mismatch, and 3 for a version mismatch. The versions are only
+/// meaningful for a version mismatch.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ScannableFingerprint_1CompareScanned(
+    env: JNIEnv,
+    _class: JClass,
+    fprint1: jbyteArray,
+    fprint2: jbyteArray,
+) -> jintArray {
+    run_ffi_safe(&env, || {
+        let fprint1 = env.convert_byte_array(fprint1)?;
+        let fprint2 = env.convert_byte_array(fprint2)?;
+
+        let fprint1 = ScannableFingerprint::deserialize(&fprint1)?;
+        let comparison = fprint1.compare_scanned(&fprint2).map_err(|e| match e {
+            SignalProtocolError::ProtobufDecodingError(_) => {
+                SignalProtocolError::FingerprintParsingError("corrupted encoding")
+            }
+            e => e,
+        })?;
+        let values = match comparison {
+            FingerprintComparison::Match => [0, 0, 0],
+            FingerprintComparison::LocalMismatch => [1, 0, 0],
+            FingerprintComparison::RemoteMismatch => [2, 0, 0],
+            FingerprintComparison::VersionMismatch { ours, theirs } => {
+                [3, jint_from_u32(Ok(ours))?, jint_from_u32(Ok(theirs))?]
+            }
+        };
+        let result = env.new_int_array(values.len() as jsize)?;
+        env.set_int_array_region(result, 0, &values)?;
+        Ok(result)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SealedSender_1DeriveUnidentifiedAccessKey(
+    env: JNIEnv,
+    _class: JClass,
+    profile_key: jbyteArray,
+) -> jbyteArray {
+    run_ffi_safe(&env, || {
+        let profile_key = env.convert_byte_array(profile_key)?;
+        let profile_key = <&[u8; 32]>::try_from(&profile_key[..]).map_err(|_| {
+            SignalProtocolError::InvalidArgument(format!(
+                "profile key must be 32 bytes, not {}",
+                profile_key.len()
+            ))
+        })?;
+        to_jbytearray(&env, derive_unidentified_access_key(profile_key))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SealedSender_1UnrestrictedUnidentifiedAccessKey(
+    env: JNIEnv,
+    _class: JClass,
+) -> jbyteArray {
+    run_ffi_safe(&env, || {
+        let mut rng = rand::rngs::OsRng;
+        to_jbytearray(&env, Ok(unrestricted_unidentified_access_key(&mut rng)))
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_signal_client_internal_Native_HKDF_1DeriveSecrets(
     env: JNIEnv,
@@ -289,6 +529,99 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_HKDF_1DeriveSecr
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_Crypto_1Sha512(
+    env: JNIEnv,
+    _class: JClass,
+    input: jbyteArray,
+) -> jbyteArray {
+    run_ffi_safe(&env, || {
+        let input = env.convert_byte_array(input)?;
+        to_jbytearray(&env, Ok(sha512(&input).to_vec()))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_Crypto_1HmacSha512(
+    env: JNIEnv,
+    _class: JClass,
+    key: jbyteArray,
+    input: jbyteArray,
+) -> jbyteArray {
+    run_ffi_safe(&env, || {
+        let key = env.convert_byte_array(key)?;
+        let input = env.convert_byte_array(input)?;
+        to_jbytearray(&env, hmac_sha512(&key, &input).map(|m| m.to_vec()))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_MessagePadding_1Pad(
+    env: JNIEnv,
+    _class: JClass,
+    body: jbyteArray,
+) -> jbyteArray {
+    run_ffi_safe(&env, || {
+        let body = env.convert_byte_array(body)?;
+        to_jbytearray(&env, Ok(pad_message(&body)))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_MessagePadding_1Unpad(
+    env: JNIEnv,
+    _class: JClass,
+    padded: jbyteArray,
+) -> jbyteArray {
+    run_ffi_safe(&env, || {
+        let padded = env.convert_byte_array(padded)?;
+        to_jbytearray(&env, unpad_message(&padded))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_CiphertextMessage_1Deserialize(
+    env: JNIEnv,
+    _class: JClass,
+    message_type: jint,
+    data: jbyteArray,
+) -> JavaCiphertextMessage {
+    run_ffi_safe(&env, || {
+        let message_type = CiphertextMessageType::try_from(jint_to_u8(message_type)?)?;
+        let data = env.convert_byte_array(data)?;
+
+        let obj = match CiphertextMessage::deserialize(message_type, &data)? {
+            CiphertextMessage::SignalMessage(m) => jobject_from_native_handle(
+                &env,
+                "org/whispersystems/libsignal/protocol/SignalMessage",
+                box_object::<SignalMessage>(Ok(m))?,
+            ),
+            CiphertextMessage::PreKeySignalMessage(m) => jobject_from_native_handle(
+                &env,
+                "org/whispersystems/libsignal/protocol/PreKeySignalMessage",
+                box_object::<PreKeySignalMessage>(Ok(m))?,
+            ),
+            CiphertextMessage::SenderKeyMessage(m) => jobject_from_native_handle(
+                &env,
+                "org/whispersystems/libsignal/protocol/SenderKeyMessage",
+                box_object::<SenderKeyMessage>(Ok(m))?,
+            ),
+            CiphertextMessage::SenderKeyDistributionMessage(m) => jobject_from_native_handle(
+                &env,
+                "org/whispersystems/libsignal/protocol/SenderKeyDistributionMessage",
+                box_object::<SenderKeyDistributionMessage>(Ok(m))?,
+            ),
+            CiphertextMessage::PlaintextContent(m) => jobject_from_native_handle(
+                &env,
+                "org/whispersystems/libsignal/protocol/PlaintextContent",
+                box_object::<PlaintextContent>(Ok(m))?,
+            ),
+        };
+
+        Ok(obj?.into_inner())
+    })
+}
+
 jni_fn_deserialize!(Java_org_signal_client_internal_Native_SignalMessage_1Deserialize is SignalMessage::try_from);
 
 #[no_mangle]
@@ -387,7 +720,7 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_PreKeySignalMess
 ) -> ObjectHandle {
     run_ffi_safe(&env, || {
         let message_version = message_version as u8;
-        let registration_id = jint_to_u32(registration_id)?;
+        let registration_id = RegistrationId::try_from(jint_to_u32(registration_id)?)?;
         let pre_key_id = if pre_key_id < 0 {
             None
         } else {
@@ -417,7 +750,7 @@ jni_fn_get_jint!(Java_org_signal_client_internal_Native_PreKeySignalMessage_1Get
                  |m: &PreKeySignalMessage| Ok(m.message_version() as u32));
 
 jni_fn_get_jint!(Java_org_signal_client_internal_Native_PreKeySignalMessage_1GetRegistrationId(PreKeySignalMessage) using
-                 |m: &PreKeySignalMessage| Ok(m.registration_id()));
+                 |m: &PreKeySignalMessage| Ok(m.registration_id().into()));
 
 // Special logic to handle optionality:
 #[no_mangle]
@@ -539,6 +872,78 @@ jni_fn_get_jbytearray!(Java_org_signal_client_internal_Native_SenderKeyDistribut
 jni_fn_get_jbytearray!(Java_org_signal_client_internal_Native_SenderKeyDistributionMessage_1GetSerialized(SenderKeyDistributionMessage) using
                        |m: &SenderKeyDistributionMessage| Ok(m.serialized().to_vec()));
 
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_DecryptionErrorMessage_1ForOriginalMessage(
+    env: JNIEnv,
+    _class: JClass,
+    original_bytes: jbyteArray,
+    original_type: jint,
+    original_timestamp: jlong,
+    original_sender_device_id: jint,
+) -> ObjectHandle {
+    run_ffi_safe(&env, || {
+        let original_bytes = env.convert_byte_array(original_bytes)?;
+        let original_type = CiphertextMessageType::try_from(jint_to_u8(original_type)?)?;
+        let original_sender_device_id = jint_to_u32(original_sender_device_id)?;
+        let message = DecryptionErrorMessage::for_original(
+            &original_bytes,
+            original_type,
+            original_timestamp as u64,
+            original_sender_device_id,
+        );
+        box_object::<DecryptionErrorMessage>(message)
+    })
+}
+
+jni_fn_deserialize!(Java_org_signal_client_internal_Native_DecryptionErrorMessage_1Deserialize is DecryptionErrorMessage::try_from);
+
+jni_fn_deserialize!(Java_org_signal_client_internal_Native_DecryptionErrorMessage_1ExtractFromSerializedContent is extract_decryption_error_message_from_serialized_content);
+
+jni_fn_destroy!(Java_org_signal_client_internal_Native_DecryptionErrorMessage_1Destroy destroys DecryptionErrorMessage);
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_DecryptionErrorMessage_1GetTimestamp(
+    env: JNIEnv,
+    _class: JClass,
+    handle: ObjectHandle,
+) -> jlong {
+    run_ffi_safe(&env, || {
+        let message = native_handle_cast::<DecryptionErrorMessage>(handle)?;
+        jlong_from_u64(Ok(message.timestamp()))
+    })
+}
+
+jni_fn_get_jint!(Java_org_signal_client_internal_Native_DecryptionErrorMessage_1GetDeviceId(DecryptionErrorMessage) using
+                 |m: &DecryptionErrorMessage| Ok(m.device_id()));
+
+jni_fn_get_new_boxed_optional_obj!(Java_org_signal_client_internal_Native_DecryptionErrorMessage_1GetRatchetKey(PublicKey) from DecryptionErrorMessage,
+                                   |m: &DecryptionErrorMessage| Ok::<_, SignalProtocolError>(m.ratchet_key().copied()));
+
+jni_fn_get_jbytearray!(Java_org_signal_client_internal_Native_DecryptionErrorMessage_1GetSerialized(DecryptionErrorMessage) using
+                       |m: &DecryptionErrorMessage| Ok(m.serialized().to_vec()));
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_PlaintextContent_1FromDecryptionErrorMessage(
+    env: JNIEnv,
+    _class: JClass,
+    message: ObjectHandle,
+) -> ObjectHandle {
+    run_ffi_safe(&env, || {
+        let message = native_handle_cast::<DecryptionErrorMessage>(message)?;
+        box_object::<PlaintextContent>(Ok(PlaintextContent::from(message.clone())))
+    })
+}
+
+jni_fn_deserialize!(Java_org_signal_client_internal_Native_PlaintextContent_1Deserialize is PlaintextContent::try_from);
+
+jni_fn_destroy!(Java_org_signal_client_internal_Native_PlaintextContent_1Destroy destroys PlaintextContent);
+
+jni_fn_get_jbytearray!(Java_org_signal_client_internal_Native_PlaintextContent_1GetBody(PlaintextContent) using
+                       |m: &PlaintextContent| Ok(m.body().to_vec()));
+
+jni_fn_get_jbytearray!(Java_org_signal_client_internal_Native_PlaintextContent_1GetSerialized(PlaintextContent) using
+                       |m: &PlaintextContent| Ok(m.serialized().to_vec()));
+
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_signal_client_internal_Native_PreKeyBundle_1New(
     env: JNIEnv,
@@ -553,7 +958,7 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_PreKeyBundle_1Ne
     identity_key_handle: ObjectHandle,
 ) -> ObjectHandle {
     run_ffi_safe(&env, || {
-        let registration_id = jint_to_u32(registration_id)?;
+        let registration_id = RegistrationId::try_from(jint_to_u32(registration_id)?)?;
         let device_id = jint_to_u32(device_id)?;
         let signed_prekey_id = jint_to_u32(signed_prekey_id)?;
         let signed_prekey = native_handle_cast::<PublicKey>(signed_prekey_handle)?;
@@ -569,10 +974,15 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_PreKeyBundle_1Ne
 
         let identity_key = IdentityKey::new(*(identity_key_handle as *mut PublicKey));
 
+        let prekey = match (prekey_id, prekey) {
+            (Some(id), Some(key)) => Some((id, key)),
+            (None, None) => None,
+            _ => return Err(SignalProtocolError::InvalidPreKeyBundle.into()),
+        };
+
         let bundle = PreKeyBundle::new(
             registration_id,
-            device_id,
-            prekey_id,
+            DeviceId::try_from(device_id)?,
             prekey,
             signed_prekey_id,
             *signed_prekey,
@@ -587,10 +997,10 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_PreKeyBundle_1Ne
 jni_fn_destroy!(Java_org_signal_client_internal_Native_PreKeyBundle_1Destroy destroys PreKeyBundle);
 
 jni_fn_get_jint!(Java_org_signal_client_internal_Native_PreKeyBundle_1GetRegistrationId(PreKeyBundle) using
-                 PreKeyBundle::registration_id);
+                 |m: &PreKeyBundle| Ok(m.registration_id()?.into()));
 
 jni_fn_get_jint!(Java_org_signal_client_internal_Native_PreKeyBundle_1GetDeviceId(PreKeyBundle) using
-                 PreKeyBundle::device_id);
+                 |m: &PreKeyBundle| Ok(m.device_id()?.into()));
 
 jni_fn_get_jint!(Java_org_signal_client_internal_Native_PreKeyBundle_1GetSignedPreKeyId(PreKeyBundle) using
                  PreKeyBundle::signed_pre_key_id);
@@ -731,7 +1141,7 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SenderKeyName_1N
     run_ffi_safe(&env, || {
         let group_id: String = env.get_string(group_id)?.into();
         let sender_name = env.get_string(sender_name)?.into();
-        let sender_id = jint_to_u32(sender_device_id)?;
+        let sender_id = DeviceId::try_from(jint_to_u32(sender_device_id)?)?;
         let name = SenderKeyName::new(group_id, ProtocolAddress::new(sender_name, sender_id));
         box_object::<SenderKeyName>(name)
     })
@@ -746,7 +1156,7 @@ jni_fn_get_jstring!(Java_org_signal_client_internal_Native_SenderKeyName_1GetSen
                     |skn: &SenderKeyName| { Ok(skn.sender()?.name().to_string()) });
 
 jni_fn_get_jint!(Java_org_signal_client_internal_Native_SenderKeyName_1GetSenderDeviceId(SenderKeyName) using
-                 |m: &SenderKeyName| Ok(m.sender()?.device_id()));
+                 |m: &SenderKeyName| Ok(m.sender()?.device_id().into()));
 
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SenderKeyRecord_1New(
@@ -766,12 +1176,50 @@ jni_fn_deserialize!(Java_org_signal_client_internal_Native_SenderKeyRecord_1Dese
 jni_fn_get_jbytearray!(Java_org_signal_client_internal_Native_SenderKeyRecord_1GetSerialized(SenderKeyRecord) using
                        SenderKeyRecord::serialize);
 
-fn sender_key_name_to_jobject<'a>(
-    env: &'a JNIEnv,
-    sender_key_name: &SenderKeyName,
-) -> Result<JObject<'a>, SignalJniError> {
+jni_fn_get_jint!(Java_org_signal_client_internal_Native_SenderKeyRecord_1GetStateCount(SenderKeyRecord) using
+                 |skr: &SenderKeyRecord| Ok(skr.state_count()? as u32));
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SenderKeyRecord_1GetChainIds(
+    env: JNIEnv,
+    _class: JClass,
+    handle: ObjectHandle,
+) -> jintArray {
+    run_ffi_safe(&env, || {
+        let skr = native_handle_cast::<SenderKeyRecord>(handle)?;
+        let chain_ids = skr
+            .chain_ids()?
+            .into_iter()
+            .map(|id| jint_from_u32(Ok(id)))
+            .collect::<Result<Vec<jint>, SignalJniError>>()?;
+        let result = env.new_int_array(chain_ids.len() as jsize)?;
+        env.set_int_array_region(result, 0, &chain_ids)?;
+        Ok(result)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SenderKeyRecord_1GetMaxIterationForChain(
+    env: JNIEnv,
+    _class: JClass,
+    handle: ObjectHandle,
+    chain_id: jint,
+) -> jint {
+    run_ffi_safe(&env, || {
+        let skr = native_handle_cast::<SenderKeyRecord>(handle)?;
+        match skr.max_iteration_for_chain(jint_to_u32(chain_id)?)? {
+            Some(iteration) => jint_from_u32(Ok(iteration)),
+            None => Ok(-1),
+        }
+    })
+}
+
+fn sender_key_name_to_jobject<'a>(
+    env: &'a JNIEnv,
+    sender_key_name: &SenderKeyName,
+) -> Result<JObject<'a>, SignalJniError> {
     let sender_key_name_class =
-        env.find_class("org/whispersystems/libsignal/groups/SenderKeyName")?;
+        jni_cache::find_class(env, "org/whispersystems/libsignal/groups/SenderKeyName")?;
     let sender_key_name_ctor_args = [
         JObject::from(env.new_string(sender_key_name.group_id()?)?).into(),
         JObject::from(env.new_string(sender_key_name.sender_name()?)?).into(),
@@ -791,10 +1239,11 @@ fn protocol_address_to_jobject<'a>(
     env: &'a JNIEnv,
     address: &ProtocolAddress,
 ) -> Result<JObject<'a>, SignalJniError> {
-    let address_class = env.find_class("org/whispersystems/libsignal/SignalProtocolAddress")?;
+    let address_class =
+        jni_cache::find_class(env, "org/whispersystems/libsignal/SignalProtocolAddress")?;
     let address_ctor_args = [
         JObject::from(env.new_string(address.name())?).into(),
-        JValue::from(jint_from_u32(Ok(address.device_id()))?),
+        JValue::from(jint_from_u32(Ok(address.device_id().into()))?),
     ];
 
     let address_ctor_sig = "(Ljava/lang/String;I)V";
@@ -802,9 +1251,99 @@ fn protocol_address_to_jobject<'a>(
     Ok(address_jobject)
 }
 
+fn protocol_address_from_jobject(
+    env: &JNIEnv,
+    address: JObject,
+) -> Result<ProtocolAddress, SignalJniError> {
+    let handle = call_method_checked(env, address, "nativeHandle", "()J", &[])?;
+    match handle {
+        JValue::Long(handle) => {
+            Ok(unsafe { native_handle_cast::<ProtocolAddress>(handle)? }.clone())
+        }
+        _ => Err(SignalJniError::UnexpectedJniResultType(
+            "nativeHandle",
+            handle.type_name(),
+        )),
+    }
+}
+
+/// Forwards to a Java `IdentityKeyOperations`, for identity keys that are never handed to the
+/// library. The session code may call it from a thread other than the one that made the
+/// original call, so it holds the VM rather than an environment.
+struct JniPrivateKeyOperations {
+    vm: JavaVM,
+    operations: GlobalRef,
+}
+
+impl JniPrivateKeyOperations {
+    fn do_calculate_signature(&self, message: &[u8]) -> Result<Box<[u8]>, SignalJniError> {
+        let env = self.vm.attach_current_thread()?;
+        let message = to_jbytearray(&env, Ok(message))?;
+        let result = call_method_checked(
+            &env,
+            self.operations.as_obj(),
+            "calculateSignature",
+            "([B)[B",
+            &[JValue::from(message)],
+        )?;
+        byte_array_result(&env, "calculateSignature", result)
+    }
+
+    fn do_calculate_agreement(&self, their_key: &PublicKey) -> Result<Box<[u8]>, SignalJniError> {
+        let env = self.vm.attach_current_thread()?;
+        let their_key = jobject_from_native_handle(
+            &env,
+            "org/whispersystems/libsignal/ecc/ECPublicKey",
+            box_object::<PublicKey>(Ok(*their_key))?,
+        )?;
+        let result = call_method_checked(
+            &env,
+            self.operations.as_obj(),
+            "calculateAgreement",
+            "(Lorg/whispersystems/libsignal/ecc/ECPublicKey;)[B",
+            &[JValue::from(their_key)],
+        )?;
+        byte_array_result(&env, "calculateAgreement", result)
+    }
+}
+
+fn byte_array_result(
+    env: &JNIEnv,
+    callback_fn: &'static str,
+    result: JValue,
+) -> Result<Box<[u8]>, SignalJniError> {
+    match result {
+        JValue::Object(o) if o.is_null() => Err(SignalJniError::Signal(
+            SignalProtocolError::InternalError("IdentityKeyOperations returned null"),
+        )),
+        JValue::Object(o) => Ok(env.convert_byte_array(*o)?.into_boxed_slice()),
+        _ => Err(SignalJniError::UnexpectedJniResultType(
+            callback_fn,
+            result.type_name(),
+        )),
+    }
+}
+
+impl PrivateKeyOperations for JniPrivateKeyOperations {
+    fn calculate_signature(
+        &self,
+        message: &[u8],
+        _randomness: &[u8; 64],
+    ) -> Result<Box<[u8]>, SignalProtocolError> {
+        self.do_calculate_signature(message)
+            .map_err(|e| e.to_signal_protocol_error())
+    }
+
+    fn calculate_agreement(&self, their_key: &PublicKey) -> Result<Box<[u8]>, SignalProtocolError> {
+        self.do_calculate_agreement(their_key)
+            .map_err(|e| e.to_signal_protocol_error())
+    }
+}
+
 pub struct JniIdentityKeyStore<'a> {
     env: &'a JNIEnv<'a>,
     store: jobject,
+    supports_enumeration: bool,
 }
 
 impl<'a> JniIdentityKeyStore<'a> {
@@ -814,39 +1353,88 @@ impl<'a> JniIdentityKeyStore<'a> {
             store,
             "org/whispersystems/libsignal/state/IdentityKeyStore",
         )?;
-        Ok(Self { env, store })
+        let supports_enumeration = env.is_instance_of(
+            store,
+            "org/whispersystems/libsignal/state/EnumerableIdentityKeyStore",
+        )?;
+        Ok(Self {
+            env,
+            store,
+            supports_enumeration,
+        })
     }
 }
 
 impl<'a> JniIdentityKeyStore<'a> {
     fn do_get_identity_key_pair(&self) -> Result<IdentityKeyPair, SignalJniError> {
         let callback_sig = "()Lorg/whispersystems/libsignal/IdentityKeyPair;";
-        let bits = get_object_with_serialization(
+        let rvalue = call_method_checked(
             self.env,
             self.store,
-            &[],
-            callback_sig,
             "getIdentityKeyPair",
+            callback_sig,
+            &[],
         )?;
+        let key_pair = match rvalue {
+            JValue::Object(o) if o.is_null() => {
+                return Err(SignalJniError::Signal(SignalProtocolError::InternalError(
+                    "getIdentityKeyPair returned null",
+                )))
+            }
+            JValue::Object(o) => o,
+            _ => {
+                return Err(SignalJniError::UnexpectedJniResultType(
+                    "getIdentityKeyPair",
+                    rvalue.type_name(),
+                ))
+            }
+        };
 
-        match bits {
-            None => Err(SignalJniError::Signal(SignalProtocolError::InternalError(
-                "getIdentityKeyPair returned null",
-            ))),
-            Some(k) => Ok(IdentityKeyPair::try_from(k.as_ref())?),
+        let operations = call_method_checked(
+            self.env,
+            key_pair,
+            "getOperations",
+            "()Lorg/whispersystems/libsignal/IdentityKeyOperations;",
+            &[],
+        )?
+        .l()?;
+        if operations.is_null() {
+            let bytes = call_method_checked(self.env, key_pair, "serialize", "()[B", &[])?.l()?;
+            return Ok(IdentityKeyPair::try_from(
+                self.env.convert_byte_array(*bytes)?.as_ref(),
+            )?);
         }
+
+        let public_key = get_object_with_native_handle::<PublicKey>(
+            self.env,
+            *key_pair,
+            &[],
+            "()Lorg/whispersystems/libsignal/IdentityKey;",
+            "getPublicKey",
+        )?
+        .ok_or(SignalJniError::NullHandle)?;
+        Ok(IdentityKeyPair::with_operations(
+            IdentityKey::new(public_key),
+            Arc::new(JniPrivateKeyOperations {
+                vm: self.env.get_java_vm()?,
+                operations: self.env.new_global_ref(operations)?,
+            }),
+        ))
     }
 
-    fn do_get_local_registration_id(&self) -> Result<u32, SignalJniError> {
+    fn do_get_local_registration_id(&self) -> Result<RegistrationId, SignalJniError> {
         let callback_sig = "()I";
 
-        let rvalue =
-            self.env
-                .call_method(self.store, "getLocalRegistrationId", callback_sig, &[])?;
-        exception_check(self.env, "getLocalRegistrationId")?;
+        let rvalue = call_method_checked(
+            self.env,
+            self.store,
+            "getLocalRegistrationId",
+            callback_sig,
+            &[],
+        )?;
 
         match rvalue {
-            JValue::Int(i) => jint_to_u32(i),
+            JValue::Int(i) => Ok(RegistrationId::try_from(jint_to_u32(i)?)?),
             _ => Err(SignalJniError::UnexpectedJniResultType(
                 "getLocalRegistrationId",
                 rvalue.type_name(),
@@ -858,25 +1446,51 @@ impl<'a> JniIdentityKeyStore<'a> {
         &mut self,
         address: &ProtocolAddress,
         identity: &IdentityKey,
-    ) -> Result<bool, SignalJniError> {
+    ) -> Result<IdentityChange, SignalJniError> {
         let address_jobject = protocol_address_to_jobject(self.env, address)?;
         let key_jobject = jobject_from_serialized(
             self.env,
             "org/whispersystems/libsignal/IdentityKey",
             identity.serialize().as_ref(),
         )?;
-        let callback_sig = "(Lorg/whispersystems/libsignal/SignalProtocolAddress;Lorg/whispersystems/libsignal/IdentityKey;)Z";
+        let callback_sig = "(Lorg/whispersystems/libsignal/SignalProtocolAddress;Lorg/whispersystems/libsignal/IdentityKey;)Lorg/whispersystems/libsignal/state/IdentityKeyStore$IdentityChange;";
         let callback_args = [address_jobject.into(), key_jobject.into()];
-        let result =
-            self.env
-                .call_method(self.store, "saveIdentity", callback_sig, &callback_args)?;
-        exception_check(self.env, "saveIdentity")?;
+        let result = call_method_checked(
+            self.env,
+            self.store,
+            "saveIdentity",
+            callback_sig,
+            &callback_args,
+        )?;
 
-        match result {
-            JValue::Bool(b) => Ok(b != 0),
+        let change = match result {
+            JValue::Object(o) if !o.is_null() => o,
+            _ => {
+                return Err(SignalJniError::UnexpectedJniResultType(
+                    "saveIdentity",
+                    result.type_name(),
+                ))
+            }
+        };
+
+        let name = call_method_checked(self.env, change, "name", "()Ljava/lang/String;", &[])?;
+        let name: String = match name {
+            JValue::Object(o) => self.env.get_string(JString::from(o))?.into(),
+            _ => {
+                return Err(SignalJniError::UnexpectedJniResultType(
+                    "name",
+                    name.type_name(),
+                ))
+            }
+        };
+
+        match name.as_str() {
+            "NEW_IDENTITY" => Ok(IdentityChange::NewIdentity),
+            "REPLACED_EXISTING" => Ok(IdentityChange::ReplacedExisting),
+            "UNCHANGED" => Ok(IdentityChange::Unchanged),
             _ => Err(SignalJniError::UnexpectedJniResultType(
                 "saveIdentity",
-                result.type_name(),
+                "unknown IdentityChange",
             )),
         }
     }
@@ -894,9 +1508,10 @@ impl<'a> JniIdentityKeyStore<'a> {
             identity.serialize().as_ref(),
         )?;
 
-        let direction_class = self
-            .env
-            .find_class("org/whispersystems/libsignal/state/IdentityKeyStore$Direction")?;
+        let direction_class = jni_cache::find_class(
+            self.env,
+            "org/whispersystems/libsignal/state/IdentityKeyStore$Direction",
+        )?;
         let field_name = match direction {
             Direction::Sending => "SENDING",
             Direction::Receiving => "RECEIVING",
@@ -910,13 +1525,13 @@ impl<'a> JniIdentityKeyStore<'a> {
 
         let callback_sig = "(Lorg/whispersystems/libsignal/SignalProtocolAddress;Lorg/whispersystems/libsignal/IdentityKey;Lorg/whispersystems/libsignal/state/IdentityKeyStore$Direction;)Z";
         let callback_args = [address_jobject.into(), key_jobject.into(), field_value];
-        let result = self.env.call_method(
+        let result = call_method_checked(
+            self.env,
             self.store,
             "isTrustedIdentity",
             callback_sig,
             &callback_args,
         )?;
-        exception_check(self.env, "isTrustedIdentity")?;
 
         match result {
             JValue::Bool(b) => Ok(b != 0),
@@ -948,6 +1563,62 @@ impl<'a> JniIdentityKeyStore<'a> {
             Some(k) => Ok(Some(IdentityKey::decode(&k)?)),
         }
     }
+
+    fn do_get_all_identities(&self) -> Result<Vec<(ProtocolAddress, IdentityKey)>, SignalJniError> {
+        let callback_sig = "()Ljava/util/Map;";
+        let rvalue =
+            call_method_checked(self.env, self.store, "getAllIdentities", callback_sig, &[])?;
+
+        let identities = match rvalue {
+            JValue::Object(o) if !o.is_null() => self.env.get_map(o)?,
+            _ => {
+                return Err(SignalJniError::UnexpectedJniResultType(
+                    "getAllIdentities",
+                    rvalue.type_name(),
+                ))
+            }
+        };
+
+        let mut result = Vec::new();
+        for (address_jobject, key_jobject) in identities.iter()? {
+            let address = protocol_address_from_jobject(self.env, address_jobject)?;
+
+            let bytes = call_method_checked(self.env, key_jobject, "serialize", "()[B", &[])?;
+            let identity = match bytes {
+                JValue::Object(o) => IdentityKey::decode(&self.env.convert_byte_array(*o)?)?,
+                _ => {
+                    return Err(SignalJniError::UnexpectedJniResultType(
+                        "serialize",
+                        bytes.type_name(),
+                    ))
+                }
+            };
+            result.push((address, identity));
+        }
+        Ok(result)
+    }
+
+    fn do_delete_identity(&mut self, address: &ProtocolAddress) -> Result<bool, SignalJniError> {
+        let address_jobject = protocol_address_to_jobject(self.env, address)?;
+
+        let callback_sig = "(Lorg/whispersystems/libsignal/SignalProtocolAddress;)Z";
+        let callback_args = [address_jobject.into()];
+        let rvalue = call_method_checked(
+            self.env,
+            self.store,
+            "deleteIdentity",
+            callback_sig,
+            &callback_args,
+        )?;
+
+        match rvalue {
+            JValue::Bool(b) => Ok(b != 0),
+            _ => Err(SignalJniError::UnexpectedJniResultType(
+                "deleteIdentity",
+                rvalue.type_name(),
+            )),
+        }
+    }
 }
 
 #[async_trait(?Send)]
@@ -959,7 +1630,10 @@ impl<'a> IdentityKeyStore for JniIdentityKeyStore<'a> {
         Ok(self.do_get_identity_key_pair()?)
     }
 
-    async fn get_local_registration_id(&self, _ctx: Context) -> Result<u32, SignalProtocolError> {
+    async fn get_local_registration_id(
+        &self,
+        _ctx: Context,
+    ) -> Result<RegistrationId, SignalProtocolError> {
         Ok(self.do_get_local_registration_id()?)
     }
 
@@ -968,7 +1642,7 @@ impl<'a> IdentityKeyStore for JniIdentityKeyStore<'a> {
         address: &ProtocolAddress,
         identity: &IdentityKey,
         _ctx: Context,
-    ) -> Result<bool, SignalProtocolError> {
+    ) -> Result<IdentityChange, SignalProtocolError> {
         Ok(self.do_save_identity(address, identity)?)
     }
 
@@ -989,11 +1663,35 @@ impl<'a> IdentityKeyStore for JniIdentityKeyStore<'a> {
     ) -> Result<Option<IdentityKey>, SignalProtocolError> {
         Ok(self.do_get_identity(address)?)
     }
+
+    async fn all_identities(
+        &self,
+        _ctx: Context,
+    ) -> Result<Vec<(ProtocolAddress, IdentityKey)>, SignalProtocolError> {
+        if !self.supports_enumeration {
+            return Err(SignalProtocolError::OperationNotSupported("all_identities"));
+        }
+        Ok(self.do_get_all_identities()?)
+    }
+
+    async fn delete_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        _ctx: Context,
+    ) -> Result<bool, SignalProtocolError> {
+        if !self.supports_enumeration {
+            return Err(SignalProtocolError::OperationNotSupported(
+                "delete_identity",
+            ));
+        }
+        Ok(self.do_delete_identity(address)?)
+    }
 }
 
 pub struct JniPreKeyStore<'a> {
     env: &'a JNIEnv<'a>,
     store: jobject,
+    supports_batches: bool,
 }
 
 impl<'a> JniPreKeyStore<'a> {
@@ -1003,7 +1701,13 @@ impl<'a> JniPreKeyStore<'a> {
             store,
             "org/whispersystems/libsignal/state/PreKeyStore",
         )?;
-        Ok(Self { env, store })
+        let supports_batches =
+            env.is_instance_of(store, "org/whispersystems/libsignal/state/BatchPreKeyStore")?;
+        Ok(Self {
+            env,
+            store,
+            supports_batches,
+        })
     }
 }
 
@@ -1034,23 +1738,85 @@ impl<'a> JniPreKeyStore<'a> {
             "org/whispersystems/libsignal/state/PreKeyRecord",
             &record.serialize()?,
         )?;
-        let callback_sig = "(I,Lorg/whispersystems/libsignal/state/PreKeyRecord;)V";
+        let callback_sig = "(ILorg/whispersystems/libsignal/state/PreKeyRecord;)V";
         let callback_args = [
             JValue::from(jint_from_u32(Ok(prekey_id))?),
             jobject_record.into(),
         ];
-        self.env
-            .call_method(self.store, "storePreKey", callback_sig, &callback_args)?;
-        exception_check(self.env, "storePreKey")?;
+        call_method_checked(
+            self.env,
+            self.store,
+            "storePreKey",
+            callback_sig,
+            &callback_args,
+        )?;
         Ok(())
     }
 
     fn do_remove_pre_key(&mut self, prekey_id: u32) -> Result<(), SignalJniError> {
         let callback_sig = "(I)V";
         let callback_args = [JValue::from(jint_from_u32(Ok(prekey_id))?)];
-        self.env
-            .call_method(self.store, "removePreKey", callback_sig, &callback_args)?;
-        exception_check(self.env, "removePreKey")?;
+        call_method_checked(
+            self.env,
+            self.store,
+            "removePreKey",
+            callback_sig,
+            &callback_args,
+        )?;
+        Ok(())
+    }
+
+    fn new_prekey_id_array(&self, prekey_ids: &[u32]) -> Result<jintArray, SignalJniError> {
+        let prekey_ids = prekey_ids
+            .iter()
+            .map(|id| jint_from_u32(Ok(*id)))
+            .collect::<Result<Vec<jint>, _>>()?;
+        let array = self.env.new_int_array(prekey_ids.len() as jsize)?;
+        self.env.set_int_array_region(array, 0, &prekey_ids)?;
+        Ok(array)
+    }
+
+    fn do_save_pre_keys(&mut self, records: &[(u32, PreKeyRecord)]) -> Result<(), SignalJniError> {
+        let record_class = "org/whispersystems/libsignal/state/PreKeyRecord";
+        let prekey_ids: Vec<u32> = records.iter().map(|(id, _)| *id).collect();
+        let prekey_ids = self.new_prekey_id_array(&prekey_ids)?;
+        let jobject_records = self.env.new_object_array(
+            records.len() as jsize,
+            jni_cache::find_class(self.env, record_class)?,
+            JObject::null(),
+        )?;
+        for (i, (_, record)) in records.iter().enumerate() {
+            let jobject_record =
+                jobject_from_serialized(self.env, record_class, &record.serialize()?)?;
+            self.env
+                .set_object_array_element(jobject_records, i as jsize, jobject_record)?;
+        }
+
+        let callback_sig = "([I[Lorg/whispersystems/libsignal/state/PreKeyRecord;)V";
+        let callback_args = [
+            JObject::from(prekey_ids).into(),
+            JObject::from(jobject_records).into(),
+        ];
+        call_method_checked(
+            self.env,
+            self.store,
+            "storePreKeys",
+            callback_sig,
+            &callback_args,
+        )?;
+        Ok(())
+    }
+
+    fn do_remove_pre_keys(&mut self, prekey_ids: &[u32]) -> Result<(), SignalJniError> {
+        let callback_sig = "([I)V";
+        let callback_args = [JObject::from(self.new_prekey_id_array(prekey_ids)?).into()];
+        call_method_checked(
+            self.env,
+            self.store,
+            "removePreKeys",
+            callback_sig,
+            &callback_args,
+        )?;
         Ok(())
     }
 }
@@ -1081,6 +1847,34 @@ impl<'a> PreKeyStore for JniPreKeyStore<'a> {
     ) -> Result<(), SignalProtocolError> {
         Ok(self.do_remove_pre_key(prekey_id)?)
     }
+
+    async fn save_pre_keys(
+        &mut self,
+        records: &[(u32, PreKeyRecord)],
+        _ctx: Context,
+    ) -> Result<(), SignalProtocolError> {
+        if !self.supports_batches {
+            for (prekey_id, record) in records {
+                self.do_save_pre_key(*prekey_id, record)?;
+            }
+            return Ok(());
+        }
+        Ok(self.do_save_pre_keys(records)?)
+    }
+
+    async fn remove_pre_keys(
+        &mut self,
+        prekey_ids: &[u32],
+        _ctx: Context,
+    ) -> Result<(), SignalProtocolError> {
+        if !self.supports_batches {
+            for prekey_id in prekey_ids {
+                self.do_remove_pre_key(*prekey_id)?;
+            }
+            return Ok(());
+        }
+        Ok(self.do_remove_pre_keys(prekey_ids)?)
+    }
 }
 
 pub struct JniSignedPreKeyStore<'a> {
@@ -1128,18 +1922,18 @@ impl<'a> JniSignedPreKeyStore<'a> {
             "org/whispersystems/libsignal/state/SignedPreKeyRecord",
             &record.serialize()?,
         )?;
-        let callback_sig = "(I,Lorg/whispersystems/libsignal/state/SignedPreKeyRecord;)V";
+        let callback_sig = "(ILorg/whispersystems/libsignal/state/SignedPreKeyRecord;)V";
         let callback_args = [
             JValue::from(jint_from_u32(Ok(prekey_id))?),
             jobject_record.into(),
         ];
-        self.env.call_method(
+        call_method_checked(
+            self.env,
             self.store,
             "storeSignedPreKey",
             callback_sig,
             &callback_args,
         )?;
-        exception_check(self.env, "storeSignedPreKey")?;
         Ok(())
     }
 }
@@ -1167,6 +1961,7 @@ impl<'a> SignedPreKeyStore for JniSignedPreKeyStore<'a> {
 pub struct JniSessionStore<'a> {
     env: &'a JNIEnv<'a>,
     store: jobject,
+    supports_batches: bool,
 }
 
 impl<'a> JniSessionStore<'a> {
@@ -1176,7 +1971,15 @@ impl<'a> JniSessionStore<'a> {
             store,
             "org/whispersystems/libsignal/state/SessionStore",
         )?;
-        Ok(Self { env, store })
+        let supports_batches = env.is_instance_of(
+            store,
+            "org/whispersystems/libsignal/state/BatchSessionStore",
+        )?;
+        Ok(Self {
+            env,
+            store,
+            supports_batches,
+        })
     }
 }
 
@@ -1217,9 +2020,140 @@ impl<'a> JniSessionStore<'a> {
 
         let callback_sig = "(Lorg/whispersystems/libsignal/SignalProtocolAddress;Lorg/whispersystems/libsignal/state/SessionRecord;)V";
         let callback_args = [address_jobject.into(), session_jobject.into()];
-        self.env
-            .call_method(self.store, "storeSession", callback_sig, &callback_args)?;
-        exception_check(self.env, "storeSession")?;
+        call_method_checked(
+            self.env,
+            self.store,
+            "storeSession",
+            callback_sig,
+            &callback_args,
+        )?;
+        Ok(())
+    }
+
+    fn do_load_existing_sessions(
+        &self,
+        addresses: &[&ProtocolAddress],
+    ) -> Result<Vec<SessionRecord>, SignalJniError> {
+        let jobject_addresses = self.env.new_object_array(
+            addresses.len() as jsize,
+            jni_cache::find_class(
+                self.env,
+                "org/whispersystems/libsignal/SignalProtocolAddress",
+            )?,
+            JObject::null(),
+        )?;
+        for (i, address) in addresses.iter().enumerate() {
+            let address_jobject = protocol_address_to_jobject(self.env, address)?;
+            self.env
+                .set_object_array_element(jobject_addresses, i as jsize, address_jobject)?;
+        }
+
+        let callback_sig = "([Lorg/whispersystems/libsignal/SignalProtocolAddress;)[Lorg/whispersystems/libsignal/state/SessionRecord;";
+        let callback_args = [JObject::from(jobject_addresses).into()];
+        let rvalue = call_method_checked(
+            self.env,
+            self.store,
+            "loadSessions",
+            callback_sig,
+            &callback_args,
+        )?;
+
+        let sessions = match rvalue {
+            JValue::Object(o) if !o.is_null() => *o,
+            _ => {
+                return Err(SignalJniError::UnexpectedJniResultType(
+                    "loadSessions",
+                    rvalue.type_name(),
+                ))
+            }
+        };
+
+        let mut records = Vec::with_capacity(addresses.len());
+        for (i, address) in addresses.iter().enumerate() {
+            let session = self.env.get_object_array_element(sessions, i as jsize)?;
+            if session.is_null() {
+                return Err(
+                    SignalProtocolError::SessionNotFoundForAddress((*address).clone()).into(),
+                );
+            }
+            let bytes = call_method_checked(self.env, session, "serialize", "()[B", &[])?;
+            match bytes {
+                JValue::Object(o) => records.push(SessionRecord::deserialize(
+                    &self.env.convert_byte_array(*o)?,
+                )?),
+                _ => {
+                    return Err(SignalJniError::UnexpectedJniResultType(
+                        "serialize",
+                        bytes.type_name(),
+                    ))
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    fn do_get_sub_device_sessions(&self, name: &str) -> Result<Vec<u32>, SignalJniError> {
+        let callback_sig = "(Ljava/lang/String;)Ljava/util/List;";
+        let callback_args = [JObject::from(self.env.new_string(name)?).into()];
+        let rvalue = call_method_checked(
+            self.env,
+            self.store,
+            "getSubDeviceSessions",
+            callback_sig,
+            &callback_args,
+        )?;
+
+        let device_ids = match rvalue {
+            JValue::Object(o) if !o.is_null() => self.env.get_list(o)?,
+            _ => {
+                return Err(SignalJniError::UnexpectedJniResultType(
+                    "getSubDeviceSessions",
+                    rvalue.type_name(),
+                ))
+            }
+        };
+
+        let mut result = Vec::new();
+        for device_id in device_ids.iter()? {
+            let rvalue = call_method_checked(self.env, device_id, "intValue", "()I", &[])?;
+            match rvalue {
+                JValue::Int(i) => result.push(jint_to_u32(i)?),
+                _ => {
+                    return Err(SignalJniError::UnexpectedJniResultType(
+                        "intValue",
+                        rvalue.type_name(),
+                    ))
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn do_delete_session(&mut self, address: &ProtocolAddress) -> Result<(), SignalJniError> {
+        let address_jobject = protocol_address_to_jobject(self.env, address)?;
+
+        let callback_sig = "(Lorg/whispersystems/libsignal/SignalProtocolAddress;)V";
+        let callback_args = [address_jobject.into()];
+        call_method_checked(
+            self.env,
+            self.store,
+            "deleteSession",
+            callback_sig,
+            &callback_args,
+        )?;
+        Ok(())
+    }
+
+    fn do_delete_all_sessions(&mut self, name: &str) -> Result<(), SignalJniError> {
+        let callback_sig = "(Ljava/lang/String;)V";
+        let callback_args = [JObject::from(self.env.new_string(name)?).into()];
+        call_method_checked(
+            self.env,
+            self.store,
+            "deleteAllSessions",
+            callback_sig,
+            &callback_args,
+        )?;
         Ok(())
     }
 }
@@ -1242,6 +2176,52 @@ impl<'a> SessionStore for JniSessionStore<'a> {
     ) -> Result<(), SignalProtocolError> {
         Ok(self.do_store_session(address, record)?)
     }
+
+    async fn load_existing_sessions(
+        &self,
+        addresses: &[&ProtocolAddress],
+        _ctx: Context,
+    ) -> Result<Vec<SessionRecord>, SignalProtocolError> {
+        if !self.supports_batches {
+            let mut records = Vec::with_capacity(addresses.len());
+            for address in addresses {
+                match self.do_load_session(address)? {
+                    Some(record) => records.push(record),
+                    None => {
+                        return Err(SignalProtocolError::SessionNotFoundForAddress(
+                            (*address).clone(),
+                        ))
+                    }
+                }
+            }
+            return Ok(records);
+        }
+        Ok(self.do_load_existing_sessions(addresses)?)
+    }
+
+    async fn get_sub_device_sessions(
+        &self,
+        name: &str,
+        _ctx: Context,
+    ) -> Result<Vec<u32>, SignalProtocolError> {
+        Ok(self.do_get_sub_device_sessions(name)?)
+    }
+
+    async fn delete_session(
+        &mut self,
+        address: &ProtocolAddress,
+        _ctx: Context,
+    ) -> Result<(), SignalProtocolError> {
+        Ok(self.do_delete_session(address)?)
+    }
+
+    async fn delete_all_sessions(
+        &mut self,
+        name: &str,
+        _ctx: Context,
+    ) -> Result<(), SignalProtocolError> {
+        Ok(self.do_delete_all_sessions(name)?)
+    }
 }
 
 #[no_mangle]
@@ -1252,7 +2232,7 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionBuilder_1
     protocol_address: ObjectHandle,
     session_store: JavaSessionStore,
     identity_key_store: JavaIdentityKeyStore,
-) {
+) -> JavaPreKeyBundleReport {
     run_ffi_safe(&env, || {
         let bundle = native_handle_cast::<PreKeyBundle>(bundle)?;
         let protocol_address = native_handle_cast::<ProtocolAddress>(protocol_address)?;
@@ -1261,18 +2241,36 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionBuilder_1
         let mut session_store = JniSessionStore::new(&env, session_store)?;
 
         let mut csprng = rand::rngs::OsRng;
-        expect_ready(process_prekey_bundle(
+        let report = expect_ready(process_prekey_bundle_with_report(
             &protocol_address,
             &mut session_store,
             &mut identity_key_store,
             bundle,
+            &SessionConfig::default(),
             &mut csprng,
             None,
         ))?;
 
-        Ok(())
-    })
-}
+        // Matches the switch in PreKeyBundleReport's constructor.
+        let identity_change = match report.identity_change {
+            IdentityChange::NewIdentity => 0,
+            IdentityChange::ReplacedExisting => 1,
+            IdentityChange::Unchanged => 2,
+        };
+
+        let report_class =
+            jni_cache::find_class(&env, "org/whispersystems/libsignal/PreKeyBundleReport")?;
+        let report_ctor_args = [
+            JValue::from(report.pre_key_id.is_some()),
+            JValue::from(jint_from_u32(Ok(report.pre_key_id.unwrap_or(0)))?),
+            JValue::from(jint_from_u32(Ok(report.signed_pre_key_id))?),
+            JValue::from(jint_from_u32(Ok(report.session_version))?),
+            JValue::from(identity_change),
+        ];
+        let report_jobject = env.new_object(report_class, "(ZIIII)V", &report_ctor_args)?;
+        Ok(report_jobject.into_inner())
+    })
+}
 
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionCipher_1EncryptMessage(
@@ -1283,6 +2281,58 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionCipher_1E
     session_store: JavaSessionStore,
     identity_key_store: JavaIdentityKeyStore,
 ) -> JavaCiphertextMessage {
+    run_ffi_safe(&env, || {
+        let message = env.convert_byte_array(message)?;
+        let protocol_address = native_handle_cast::<ProtocolAddress>(protocol_address)?;
+        encrypt_message(
+            &env,
+            &message,
+            protocol_address,
+            session_store,
+            identity_key_store,
+        )
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionCipher_1EncryptMessageAsync(
+    env: JNIEnv,
+    _class: JClass,
+    message: jbyteArray,
+    protocol_address: ObjectHandle,
+    session_store: JavaSessionStore,
+    identity_key_store: JavaIdentityKeyStore,
+    session_lock: JavaObject,
+) -> JavaCompletableFuture {
+    run_ffi_safe(&env, || {
+        let message = env.convert_byte_array(message)?;
+        let protocol_address = native_handle_cast::<ProtocolAddress>(protocol_address)?.clone();
+        let session_store = env.new_global_ref(JObject::from(session_store))?;
+        let identity_key_store = env.new_global_ref(JObject::from(identity_key_store))?;
+        let session_lock = env.new_global_ref(JObject::from(session_lock))?;
+
+        run_ffi_async(&env, move |env| {
+            let _session_lock = env.lock_obj(session_lock.as_obj())?;
+            encrypt_message(
+                env,
+                &message,
+                &protocol_address,
+                session_store.as_obj().into_inner(),
+                identity_key_store.as_obj().into_inner(),
+            )
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionCipher_1EncryptMessageWithDetails(
+    env: JNIEnv,
+    _class: JClass,
+    message: jbyteArray,
+    protocol_address: ObjectHandle,
+    session_store: JavaSessionStore,
+    identity_key_store: JavaIdentityKeyStore,
+) -> JavaEncryptedMessage {
     run_ffi_safe(&env, || {
         let message = env.convert_byte_array(message)?;
         let protocol_address = native_handle_cast::<ProtocolAddress>(protocol_address)?;
@@ -1290,32 +2340,163 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionCipher_1E
         let mut identity_key_store = JniIdentityKeyStore::new(&env, identity_key_store)?;
         let mut session_store = JniSessionStore::new(&env, session_store)?;
 
-        let ctext = expect_ready(message_encrypt(
+        let encrypted = expect_ready(message_encrypt_with_details(
             &message,
-            &protocol_address,
+            protocol_address,
             &mut session_store,
             &mut identity_key_store,
             None,
         ))?;
 
-        let obj = match ctext {
-            CiphertextMessage::SignalMessage(m) => jobject_from_native_handle(
-                &env,
-                "org/whispersystems/libsignal/protocol/SignalMessage",
-                box_object::<SignalMessage>(Ok(m))?,
-            ),
-            CiphertextMessage::PreKeySignalMessage(m) => jobject_from_native_handle(
-                &env,
-                "org/whispersystems/libsignal/protocol/PreKeySignalMessage",
-                box_object::<PreKeySignalMessage>(Ok(m))?,
+        let encrypted_class =
+            jni_cache::find_class(&env, "org/whispersystems/libsignal/EncryptedMessage")?;
+        let encrypted_ctor_args = [
+            JValue::from(to_jbytearray(&env, Ok(encrypted.serialized))?),
+            JValue::from(encrypted.message_type as jint),
+            JValue::from(jint_from_u32(Ok(encrypted.remote_registration_id.into()))?),
+        ];
+        let encrypted_jobject = env.new_object(encrypted_class, "([BII)V", &encrypted_ctor_args)?;
+        Ok(encrypted_jobject.into_inner())
+    })
+}
+
+fn encrypt_message(
+    env: &JNIEnv,
+    message: &[u8],
+    protocol_address: &ProtocolAddress,
+    session_store: JavaSessionStore,
+    identity_key_store: JavaIdentityKeyStore,
+) -> Result<JavaCiphertextMessage, SignalJniError> {
+    let mut identity_key_store = JniIdentityKeyStore::new(env, identity_key_store)?;
+    let mut session_store = JniSessionStore::new(env, session_store)?;
+
+    let ctext = expect_ready(message_encrypt(
+        message,
+        protocol_address,
+        &mut session_store,
+        &mut identity_key_store,
+        None,
+    ))?;
+
+    let obj = match ctext {
+        CiphertextMessage::SignalMessage(m) => jobject_from_native_handle(
+            env,
+            "org/whispersystems/libsignal/protocol/SignalMessage",
+            box_object::<SignalMessage>(Ok(m))?,
+        ),
+        CiphertextMessage::PreKeySignalMessage(m) => jobject_from_native_handle(
+            env,
+            "org/whispersystems/libsignal/protocol/PreKeySignalMessage",
+            box_object::<PreKeySignalMessage>(Ok(m))?,
+        ),
+        _ => Err(SignalJniError::Signal(SignalProtocolError::InternalError(
+            "Unexpected result type from message_encrypt",
+        ))),
+    };
+
+    Ok(obj?.into_inner())
+}
+
+pub struct JniTransactionalStore<'a> {
+    env: &'a JNIEnv<'a>,
+    store: jobject,
+}
+
+impl<'a> JniTransactionalStore<'a> {
+    /// Returns `None` if `store` does not implement TransactionalStore.
+    fn new(env: &'a JNIEnv, store: jobject) -> Result<Option<Self>, SignalJniError> {
+        if !env.is_instance_of(
+            store,
+            "org/whispersystems/libsignal/state/TransactionalStore",
+        )? {
+            return Ok(None);
+        }
+        Ok(Some(Self { env, store }))
+    }
+}
+
+impl<'a> JniTransactionalStore<'a> {
+    fn store_op_to_jobject(&self, op: &StoreOp) -> Result<JObject<'a>, SignalJniError> {
+        let (factory, factory_sig, args) = match op {
+            StoreOp::SaveIdentity { address, identity } => {
+                let address_jobject = protocol_address_to_jobject(self.env, address)?;
+                let key_jobject = jobject_from_serialized(
+                    self.env,
+                    "org/whispersystems/libsignal/IdentityKey",
+                    identity.serialize().as_ref(),
+                )?;
+                (
+                    "saveIdentity",
+                    "(Lorg/whispersystems/libsignal/SignalProtocolAddress;Lorg/whispersystems/libsignal/IdentityKey;)Lorg/whispersystems/libsignal/state/StoreOp;",
+                    vec![address_jobject.into(), key_jobject.into()],
+                )
+            }
+            StoreOp::StoreSession { address, record } => {
+                let address_jobject = protocol_address_to_jobject(self.env, address)?;
+                let session_jobject = jobject_from_serialized(
+                    self.env,
+                    "org/whispersystems/libsignal/state/SessionRecord",
+                    &record.serialize()?,
+                )?;
+                (
+                    "storeSession",
+                    "(Lorg/whispersystems/libsignal/SignalProtocolAddress;Lorg/whispersystems/libsignal/state/SessionRecord;)Lorg/whispersystems/libsignal/state/StoreOp;",
+                    vec![address_jobject.into(), session_jobject.into()],
+                )
+            }
+            StoreOp::RemovePreKey(id) => (
+                "removePreKey",
+                "(I)Lorg/whispersystems/libsignal/state/StoreOp;",
+                vec![JValue::from(jint_from_u32(Ok(*id))?)],
             ),
-            _ => Err(SignalJniError::Signal(SignalProtocolError::InternalError(
-                "Unexpected result type from message_encrypt",
-            ))),
         };
 
-        Ok(obj?.into_inner())
-    })
+        let result = self.env.call_static_method(
+            "org/whispersystems/libsignal/state/StoreOp",
+            factory,
+            factory_sig,
+            &args,
+        );
+        exception_check(self.env, factory)?;
+        let result = result?;
+
+        match result {
+            JValue::Object(o) if !o.is_null() => Ok(o),
+            _ => Err(SignalJniError::UnexpectedJniResultType(
+                factory,
+                result.type_name(),
+            )),
+        }
+    }
+
+    fn do_commit(&mut self, ops: &[StoreOp]) -> Result<(), SignalJniError> {
+        let jobject_ops = self.env.new_object_array(
+            ops.len() as jsize,
+            jni_cache::find_class(self.env, "org/whispersystems/libsignal/state/StoreOp")?,
+            JObject::null(),
+        )?;
+        for (i, op) in ops.iter().enumerate() {
+            let op_jobject = self.store_op_to_jobject(op)?;
+            self.env
+                .set_object_array_element(jobject_ops, i as jsize, op_jobject)?;
+        }
+
+        let callback_sig = "([Lorg/whispersystems/libsignal/state/StoreOp;)V";
+        let callback_args = [JObject::from(jobject_ops).into()];
+        call_method_checked(self.env, self.store, "commit", callback_sig, &callback_args)?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> TransactionalStore for JniTransactionalStore<'a> {
+    async fn commit(
+        &mut self,
+        ops: Vec<StoreOp>,
+        _ctx: Context,
+    ) -> Result<(), SignalProtocolError> {
+        Ok(self.do_commit(&ops)?)
+    }
 }
 
 #[no_mangle]
@@ -1330,22 +2511,83 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionCipher_1D
     run_ffi_safe(&env, || {
         let message = native_handle_cast::<SignalMessage>(message)?;
         let protocol_address = native_handle_cast::<ProtocolAddress>(protocol_address)?;
+        decrypt_signal_message(
+            &env,
+            message,
+            protocol_address,
+            session_store,
+            identity_key_store,
+        )
+    })
+}
 
-        let mut identity_key_store = JniIdentityKeyStore::new(&env, identity_key_store)?;
-        let mut session_store = JniSessionStore::new(&env, session_store)?;
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionCipher_1DecryptSignalMessageAsync(
+    env: JNIEnv,
+    _class: JClass,
+    message: ObjectHandle,
+    protocol_address: ObjectHandle,
+    session_store: JavaSessionStore,
+    identity_key_store: JavaIdentityKeyStore,
+    session_lock: JavaObject,
+) -> JavaCompletableFuture {
+    run_ffi_safe(&env, || {
+        let message = native_handle_cast::<SignalMessage>(message)?.clone();
+        let protocol_address = native_handle_cast::<ProtocolAddress>(protocol_address)?.clone();
+        let session_store = env.new_global_ref(JObject::from(session_store))?;
+        let identity_key_store = env.new_global_ref(JObject::from(identity_key_store))?;
+        let session_lock = env.new_global_ref(JObject::from(session_lock))?;
+
+        run_ffi_async(&env, move |env| {
+            let _session_lock = env.lock_obj(session_lock.as_obj())?;
+            decrypt_signal_message(
+                env,
+                &message,
+                &protocol_address,
+                session_store.as_obj().into_inner(),
+                identity_key_store.as_obj().into_inner(),
+            )
+        })
+    })
+}
 
-        let mut csprng = rand::rngs::OsRng;
-        let ptext = expect_ready(message_decrypt_signal(
-            &message,
-            &protocol_address,
+fn decrypt_signal_message(
+    env: &JNIEnv,
+    message: &SignalMessage,
+    protocol_address: &ProtocolAddress,
+    session_store: JavaSessionStore,
+    identity_key_store: JavaIdentityKeyStore,
+) -> Result<jbyteArray, SignalJniError> {
+    let mut identity_key_store = JniIdentityKeyStore::new(env, identity_key_store)?;
+    let mut session_store = JniSessionStore::new(env, session_store)?;
+
+    let mut csprng = rand::rngs::OsRng;
+    let ptext = if let Some(mut transactional_store) =
+        JniTransactionalStore::new(env, session_store.store)?
+    {
+        let (ptext, transaction) = expect_ready(message_decrypt_signal_transactional(
+            message,
+            protocol_address,
+            &session_store,
+            &identity_key_store,
+            &SessionConfig::default(),
+            &mut csprng,
+            None,
+        ))?;
+        expect_ready(transaction.commit(&mut transactional_store, None))?;
+        ptext
+    } else {
+        expect_ready(message_decrypt_signal(
+            message,
+            protocol_address,
             &mut session_store,
             &mut identity_key_store,
             &mut csprng,
             None,
-        ))?;
+        ))?
+    };
 
-        to_jbytearray(&env, Ok(ptext))
-    })
+    to_jbytearray(env, Ok(ptext))
 }
 
 #[no_mangle]
@@ -1362,30 +2604,107 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionCipher_1D
     run_ffi_safe(&env, || {
         let message = native_handle_cast::<PreKeySignalMessage>(message)?;
         let protocol_address = native_handle_cast::<ProtocolAddress>(protocol_address)?;
-        let mut identity_key_store = JniIdentityKeyStore::new(&env, identity_key_store)?;
-        let mut session_store = JniSessionStore::new(&env, session_store)?;
-        let mut prekey_store = JniPreKeyStore::new(&env, prekey_store)?;
-        let mut signed_prekey_store = JniSignedPreKeyStore::new(&env, signed_prekey_store)?;
+        decrypt_pre_key_signal_message(
+            &env,
+            message,
+            protocol_address,
+            session_store,
+            identity_key_store,
+            prekey_store,
+            signed_prekey_store,
+        )
+    })
+}
 
-        let mut csprng = rand::rngs::OsRng;
-        let ptext = expect_ready(message_decrypt_prekey(
-            &message,
-            &protocol_address,
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionCipher_1DecryptPreKeySignalMessageAsync(
+    env: JNIEnv,
+    _class: JClass,
+    message: ObjectHandle,
+    protocol_address: ObjectHandle,
+    session_store: JavaSessionStore,
+    identity_key_store: JavaIdentityKeyStore,
+    prekey_store: JavaPreKeyStore,
+    signed_prekey_store: JavaSignedPreKeyStore,
+    session_lock: JavaObject,
+) -> JavaCompletableFuture {
+    run_ffi_safe(&env, || {
+        let message = native_handle_cast::<PreKeySignalMessage>(message)?.clone();
+        let protocol_address = native_handle_cast::<ProtocolAddress>(protocol_address)?.clone();
+        let session_store = env.new_global_ref(JObject::from(session_store))?;
+        let identity_key_store = env.new_global_ref(JObject::from(identity_key_store))?;
+        let prekey_store = env.new_global_ref(JObject::from(prekey_store))?;
+        let signed_prekey_store = env.new_global_ref(JObject::from(signed_prekey_store))?;
+        let session_lock = env.new_global_ref(JObject::from(session_lock))?;
+
+        run_ffi_async(&env, move |env| {
+            let _session_lock = env.lock_obj(session_lock.as_obj())?;
+            decrypt_pre_key_signal_message(
+                env,
+                &message,
+                &protocol_address,
+                session_store.as_obj().into_inner(),
+                identity_key_store.as_obj().into_inner(),
+                prekey_store.as_obj().into_inner(),
+                signed_prekey_store.as_obj().into_inner(),
+            )
+        })
+    })
+}
+
+fn decrypt_pre_key_signal_message(
+    env: &JNIEnv,
+    message: &PreKeySignalMessage,
+    protocol_address: &ProtocolAddress,
+    session_store: JavaSessionStore,
+    identity_key_store: JavaIdentityKeyStore,
+    prekey_store: JavaPreKeyStore,
+    signed_prekey_store: JavaSignedPreKeyStore,
+) -> Result<jbyteArray, SignalJniError> {
+    let mut identity_key_store = JniIdentityKeyStore::new(env, identity_key_store)?;
+    let mut session_store = JniSessionStore::new(env, session_store)?;
+    let mut prekey_store = JniPreKeyStore::new(env, prekey_store)?;
+    let mut signed_prekey_store = JniSignedPreKeyStore::new(env, signed_prekey_store)?;
+
+    let mut csprng = rand::rngs::OsRng;
+    let ptext = if let Some(mut transactional_store) =
+        JniTransactionalStore::new(env, session_store.store)?
+    {
+        let (ptext, transaction) = expect_ready(message_decrypt_prekey_transactional(
+            message,
+            protocol_address,
+            &ReadOnlySessionStores {
+                session_store: &session_store,
+                identity_store: &identity_key_store,
+                pre_key_store: &prekey_store,
+                signed_pre_key_store: &signed_prekey_store,
+            },
+            &SessionConfig::default(),
+            &mut csprng,
+            None,
+        ))?;
+        expect_ready(transaction.commit(&mut transactional_store, None))?;
+        ptext
+    } else {
+        expect_ready(message_decrypt_prekey(
+            message,
+            protocol_address,
             &mut session_store,
             &mut identity_key_store,
             &mut prekey_store,
             &mut signed_prekey_store,
             &mut csprng,
             None,
-        ))?;
+        ))?
+    };
 
-        to_jbytearray(&env, Ok(ptext))
-    })
+    to_jbytearray(env, Ok(ptext))
 }
 
 pub struct JniSenderKeyStore<'a> {
     env: &'a JNIEnv<'a>,
     store: jobject,
+    supports_enumeration: bool,
 }
 
 impl<'a> JniSenderKeyStore<'a> {
@@ -1395,7 +2714,15 @@ impl<'a> JniSenderKeyStore<'a> {
             store,
             "org/whispersystems/libsignal/groups/state/SenderKeyStore",
         )?;
-        Ok(Self { env, store })
+        let supports_enumeration = env.is_instance_of(
+            store,
+            "org/whispersystems/libsignal/groups/state/EnumerableSenderKeyStore",
+        )?;
+        Ok(Self {
+            env,
+            store,
+            supports_enumeration,
+        })
     }
 }
 
@@ -1417,13 +2744,13 @@ impl<'a> JniSenderKeyStore<'a> {
             sender_key_record_jobject.into(),
         ];
         let callback_sig = "(Lorg/whispersystems/libsignal/groups/SenderKeyName;Lorg/whispersystems/libsignal/groups/state/SenderKeyRecord;)V";
-        self.env.call_method(
+        call_method_checked(
+            self.env,
             self.store,
             "storeSenderKey",
             callback_sig,
             &callback_args[..],
         )?;
-        exception_check(self.env, "storeSenderKey")?;
 
         Ok(())
     }
@@ -1446,6 +2773,53 @@ impl<'a> JniSenderKeyStore<'a> {
 
         Ok(skr)
     }
+
+    fn do_get_senders_for_group(
+        &self,
+        group_id: &str,
+    ) -> Result<Vec<ProtocolAddress>, SignalJniError> {
+        let callback_sig =
+            "(Ljava/lang/String;)[Lorg/whispersystems/libsignal/SignalProtocolAddress;";
+        let callback_args = [JObject::from(self.env.new_string(group_id)?).into()];
+        let rvalue = call_method_checked(
+            self.env,
+            self.store,
+            "getSendersForGroup",
+            callback_sig,
+            &callback_args,
+        )?;
+
+        let senders = match rvalue {
+            JValue::Object(o) if !o.is_null() => *o,
+            _ => {
+                return Err(SignalJniError::UnexpectedJniResultType(
+                    "getSendersForGroup",
+                    rvalue.type_name(),
+                ))
+            }
+        };
+
+        let count = self.env.get_array_length(senders)?;
+        let mut result = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let sender = self.env.get_object_array_element(senders, i)?;
+            result.push(protocol_address_from_jobject(self.env, sender)?);
+        }
+        Ok(result)
+    }
+
+    fn do_delete_sender_keys_for_group(&mut self, group_id: &str) -> Result<(), SignalJniError> {
+        let callback_sig = "(Ljava/lang/String;)V";
+        let callback_args = [JObject::from(self.env.new_string(group_id)?).into()];
+        call_method_checked(
+            self.env,
+            self.store,
+            "deleteSenderKeysForGroup",
+            callback_sig,
+            &callback_args,
+        )?;
+        Ok(())
+    }
 }
 
 #[async_trait(?Send)]
@@ -1466,6 +2840,32 @@ impl<'a> SenderKeyStore for JniSenderKeyStore<'a> {
     ) -> Result<Option<SenderKeyRecord>, SignalProtocolError> {
         Ok(self.do_load_sender_key(sender_key_name)?)
     }
+
+    async fn senders_for_group(
+        &self,
+        group_id: &str,
+        _ctx: Context,
+    ) -> Result<Vec<ProtocolAddress>, SignalProtocolError> {
+        if !self.supports_enumeration {
+            return Err(SignalProtocolError::OperationNotSupported(
+                "senders_for_group",
+            ));
+        }
+        Ok(self.do_get_senders_for_group(group_id)?)
+    }
+
+    async fn delete_sender_keys_for_group(
+        &mut self,
+        group_id: &str,
+        _ctx: Context,
+    ) -> Result<(), SignalProtocolError> {
+        if !self.supports_enumeration {
+            return Err(SignalProtocolError::OperationNotSupported(
+                "delete_sender_keys_for_group",
+            ));
+        }
+        Ok(self.do_delete_sender_keys_for_group(group_id)?)
+    }
 }
 
 #[no_mangle]
@@ -1514,6 +2914,33 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_GroupSessionBuil
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_GroupSessionBuilder_1ProcessSenderKeyDistributionMessageWithExpectedId(
+    env: JNIEnv,
+    _class: JClass,
+    sender_key_name: ObjectHandle,
+    sender_key_distribution_message: ObjectHandle,
+    expected_id: jint,
+    store: JavaSenderKeyStore,
+) {
+    run_ffi_safe(&env, || {
+        let sender_key_name = native_handle_cast::<SenderKeyName>(sender_key_name)?;
+        let sender_key_distribution_message =
+            native_handle_cast::<SenderKeyDistributionMessage>(sender_key_distribution_message)?;
+        let expected_id = jint_to_u32(expected_id)?;
+        let mut sender_key_store = JniSenderKeyStore::new(&env, store)?;
+
+        expect_ready(process_sender_key_distribution_message_with_expected_id(
+            sender_key_name,
+            sender_key_distribution_message,
+            Some(expected_id),
+            &mut sender_key_store,
+            None,
+        ))?;
+        Ok(())
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_signal_client_internal_Native_GroupCipher_1EncryptMessage(
     env: JNIEnv,
@@ -1541,6 +2968,43 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_GroupCipher_1Enc
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_GroupCipher_1EncryptMessageWithMetadata(
+    env: JNIEnv,
+    _class: JClass,
+    sender_key_name: ObjectHandle,
+    message: jbyteArray,
+    store: JavaSenderKeyStore,
+) -> JavaGroupEncryptedMessage {
+    run_ffi_safe(&env, || {
+        let sender_key_name = native_handle_cast::<SenderKeyName>(sender_key_name)?;
+        let message = env.convert_byte_array(message)?;
+        let mut sender_key_store = JniSenderKeyStore::new(&env, store)?;
+
+        let mut rng = rand::rngs::OsRng;
+
+        let encrypted = expect_ready(group_encrypt_with_metadata(
+            &mut sender_key_store,
+            &sender_key_name,
+            &message,
+            &mut rng,
+            None,
+        ))?;
+
+        let encrypted_class = jni_cache::find_class(
+            &env,
+            "org/whispersystems/libsignal/groups/GroupEncryptedMessage",
+        )?;
+        let encrypted_ctor_args = [
+            JValue::from(to_jbytearray(&env, Ok(encrypted.serialized))?),
+            JValue::from(jint_from_u32(Ok(encrypted.chain_id))?),
+            JValue::from(jint_from_u32(Ok(encrypted.iteration))?),
+        ];
+        let encrypted_jobject = env.new_object(encrypted_class, "([BII)V", &encrypted_ctor_args)?;
+        Ok(encrypted_jobject.into_inner())
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_signal_client_internal_Native_GroupCipher_1DecryptMessage(
     env: JNIEnv,
@@ -1567,6 +3031,65 @@ pub unsafe extern "C" fn Java_org_signal_client_internal_Native_GroupCipher_1Dec
 
 // The following are just exposed to make it possible to retain some of the Java tests:
 
+// Debugging aids: these report counters and flags of a serialized session state, never keys.
+// Optional counters are returned as -1 when absent.
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionState_1DebugHasUsableSenderChain(
+    env: JNIEnv,
+    _class: JClass,
+    session_state: jbyteArray,
+) -> jboolean {
+    run_ffi_safe(&env, || {
+        let session_state = SessionState::deserialize(&env.convert_byte_array(session_state)?)?;
+        Ok(session_state.has_usable_sender_chain()? as jboolean)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionState_1DebugSenderChainCounter(
+    env: JNIEnv,
+    _class: JClass,
+    session_state: jbyteArray,
+) -> jint {
+    run_ffi_safe(&env, || {
+        let session_state = SessionState::deserialize(&env.convert_byte_array(session_state)?)?;
+        match session_state.sender_chain_counter()? {
+            Some(counter) => jint_from_u32(Ok(counter)),
+            None => Ok(-1),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionState_1DebugReceiverChainCounter(
+    env: JNIEnv,
+    _class: JClass,
+    session_state: jbyteArray,
+    their_ephemeral: ObjectHandle,
+) -> jint {
+    run_ffi_safe(&env, || {
+        let session_state = SessionState::deserialize(&env.convert_byte_array(session_state)?)?;
+        let their_ephemeral = native_handle_cast::<PublicKey>(their_ephemeral)?;
+        match session_state.receiver_chain_counter(their_ephemeral)? {
+            Some(counter) => jint_from_u32(Ok(counter)),
+            None => Ok(-1),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionState_1DebugNumberOfReceiverChains(
+    env: JNIEnv,
+    _class: JClass,
+    session_state: jbyteArray,
+) -> jint {
+    run_ffi_safe(&env, || {
+        let session_state = SessionState::deserialize(&env.convert_byte_array(session_state)?)?;
+        jint_from_u32(session_state.number_of_receiver_chains().map(|n| n as u32))
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SessionState_1InitializeAliceSession(
     env: JNIEnv,
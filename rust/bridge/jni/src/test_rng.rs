@@ -0,0 +1,53 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Entry points that take a seeded `TestRng` in place of OsRng, so that callers can reproduce
+//! the shared test vectors. Only built with the test-rng feature.
+
+use jni::objects::JClass;
+use jni::sys::{jbyteArray, jlong};
+use jni::JNIEnv;
+use libsignal_protocol_rust::*;
+
+use crate::jni_fn_destroy;
+use crate::util::*;
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_TestRng_1New(
+    env: JNIEnv,
+    _class: JClass,
+    seed: jlong,
+) -> ObjectHandle {
+    run_ffi_safe(&env, || {
+        box_object::<TestRng>(Ok(TestRng::new(seed as u64)))
+    })
+}
+
+jni_fn_destroy!(Java_org_signal_client_internal_Native_TestRng_1Destroy destroys TestRng);
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_ECPrivateKey_1GenerateWithTestRng(
+    env: JNIEnv,
+    _class: JClass,
+    rng: ObjectHandle,
+) -> ObjectHandle {
+    run_ffi_safe(&env, || {
+        let rng = native_handle_cast::<TestRng>(rng)?;
+        let keypair = KeyPair::generate(rng);
+        box_object::<PrivateKey>(Ok(keypair.private_key))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_signal_client_internal_Native_SealedSender_1UnrestrictedUnidentifiedAccessKeyWithTestRng(
+    env: JNIEnv,
+    _class: JClass,
+    rng: ObjectHandle,
+) -> jbyteArray {
+    run_ffi_safe(&env, || {
+        let rng = native_handle_cast::<TestRng>(rng)?;
+        to_jbytearray(&env, Ok(unrestricted_unidentified_access_key(rng)))
+    })
+}
@@ -5,15 +5,20 @@
 
 use futures::pin_mut;
 use futures::task::noop_waker_ref;
-use jni::objects::{JObject, JString, JThrowable, JValue};
-use jni::sys::{_jobject, jboolean, jbyteArray, jint, jlong, jobject, jstring};
+use jni::objects::{GlobalRef, JObject, JString, JThrowable, JValue};
+use jni::sys::{_jobject, jboolean, jbyteArray, jint, jlong, jobject, jobjectArray, jstring};
 use jni::JNIEnv;
 use libsignal_protocol_rust::SignalProtocolError;
+use std::cell::RefCell;
 use std::convert::TryFrom;
 use std::fmt;
 use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc;
 use std::task::{self, Poll};
 
+use crate::jni_cache;
+
 #[derive(Debug)]
 pub enum SignalJniError {
     Signal(SignalProtocolError),
@@ -24,6 +29,7 @@ pub enum SignalJniError {
     IntegerOverflow(String),
     UnexpectedPanic(std::boxed::Box<dyn std::any::Any + std::marker::Send>),
     ExceptionDuringCallback(String),
+    CacheNotInitialized,
 }
 
 impl SignalJniError {
@@ -48,6 +54,10 @@ impl fmt::Display for SignalJniError {
                 write!(f, "exception recieved during callback {}", s)
             }
             SignalJniError::NullHandle => write!(f, "null handle"),
+            SignalJniError::CacheNotInitialized => write!(
+                f,
+                "JNI_OnLoad did not run; load the library with System.loadLibrary"
+            ),
             SignalJniError::BadJniParameter(m) => write!(f, "bad parameter type {}", m),
             SignalJniError::UnexpectedJniResultType(m, t) => {
                 write!(f, "calling {} returned unexpected type {}", m, t)
@@ -88,7 +98,42 @@ impl From<SignalJniError> for SignalProtocolError {
     }
 }
 
+fn throw_duplicated_message(
+    env: &JNIEnv,
+    message: String,
+    current_iteration: u32,
+    counter: u32,
+) -> Result<(), SignalJniError> {
+    let message = env.new_string(message)?;
+    let exception = env.new_object(
+        jni_cache::find_class(
+            env,
+            "org/whispersystems/libsignal/DuplicateMessageException",
+        )?,
+        "(Ljava/lang/String;II)V",
+        &[
+            JValue::from(JObject::from(message)),
+            JValue::from(current_iteration as jint),
+            JValue::from(counter as jint),
+        ],
+    )?;
+    env.throw(JThrowable::from(exception))?;
+    Ok(())
+}
+
 pub fn throw_error(env: &JNIEnv, error: SignalJniError) {
+    // Duplicated messages carry their counters so that callers can tell a replay from a
+    // desynchronized chain.
+    if let SignalJniError::Signal(SignalProtocolError::DuplicatedMessage {
+        current_iteration,
+        counter,
+    }) = error
+    {
+        if throw_duplicated_message(env, format!("{}", error), current_iteration, counter).is_ok() {
+            return;
+        }
+    }
+
     let exception_type = match error {
         SignalJniError::NullHandle => "java/lang/NullPointerException",
         SignalJniError::UnexpectedPanic(_) => "java/lang/AssertionError",
@@ -98,13 +143,16 @@ pub fn throw_error(env: &JNIEnv, error: SignalJniError) {
 
         SignalJniError::ExceptionDuringCallback(_) => "java/lang/RuntimeException",
 
-        SignalJniError::Signal(SignalProtocolError::DuplicatedMessage(_, _)) => {
+        SignalJniError::CacheNotInitialized => "java/lang/IllegalStateException",
+
+        SignalJniError::Signal(SignalProtocolError::DuplicatedMessage { .. }) => {
             "org/whispersystems/libsignal/DuplicateMessageException"
         }
 
         SignalJniError::Signal(SignalProtocolError::InvalidPreKeyId)
         | SignalJniError::Signal(SignalProtocolError::InvalidSignedPreKeyId)
-        | SignalJniError::Signal(SignalProtocolError::InvalidSenderKeyId) => {
+        | SignalJniError::Signal(SignalProtocolError::InvalidSenderKeyId)
+        | SignalJniError::Signal(SignalProtocolError::SenderKeyDistributionIdMismatch { .. }) => {
             "org/whispersystems/libsignal/InvalidKeyIdException"
         }
 
@@ -115,16 +163,27 @@ pub fn throw_error(env: &JNIEnv, error: SignalJniError) {
             "org/whispersystems/libsignal/InvalidKeyException"
         }
 
-        SignalJniError::Signal(SignalProtocolError::SessionNotFound) => {
+        SignalJniError::Signal(SignalProtocolError::FingerprintParsingError(_)) => {
+            "org/whispersystems/libsignal/fingerprint/FingerprintParsingException"
+        }
+
+        SignalJniError::Signal(SignalProtocolError::SessionNotFound)
+        | SignalJniError::Signal(SignalProtocolError::SessionNotFoundForAddress(_)) => {
             "org/whispersystems/libsignal/NoSessionException"
         }
 
         SignalJniError::Signal(SignalProtocolError::InvalidMessage(_))
+        | SignalJniError::Signal(SignalProtocolError::MessageTooFarInFuture { .. })
         | SignalJniError::Signal(SignalProtocolError::CiphertextMessageTooShort(_))
         | SignalJniError::Signal(SignalProtocolError::UnrecognizedCiphertextVersion(_))
         | SignalJniError::Signal(SignalProtocolError::UnrecognizedMessageVersion(_))
         | SignalJniError::Signal(SignalProtocolError::InvalidCiphertext)
-        | SignalJniError::Signal(SignalProtocolError::InvalidProtobufEncoding) => {
+        | SignalJniError::Signal(SignalProtocolError::InvalidCiphertextLength(_))
+        | SignalJniError::Signal(SignalProtocolError::InvalidCiphertextPadding)
+        | SignalJniError::Signal(SignalProtocolError::InvalidProtobufEncoding)
+        | SignalJniError::Signal(SignalProtocolError::InvalidRegistrationId(_))
+        | SignalJniError::Signal(SignalProtocolError::UnrecognizedRecordVersion(_))
+        | SignalJniError::Signal(SignalProtocolError::UnrecognizedCiphertextMessageType(_)) => {
             "org/whispersystems/libsignal/InvalidMessageException"
         }
 
@@ -146,6 +205,10 @@ pub fn throw_error(env: &JNIEnv, error: SignalJniError) {
             "java/lang/IllegalArgumentException"
         }
 
+        SignalJniError::Signal(SignalProtocolError::OperationNotSupported(_)) => {
+            "java/lang/UnsupportedOperationException"
+        }
+
         SignalJniError::Signal(_) => "java/lang/RuntimeException",
 
         SignalJniError::Jni(_) => "java/lang/RuntimeException",
@@ -155,10 +218,14 @@ pub fn throw_error(env: &JNIEnv, error: SignalJniError) {
         SignalJniError::Signal(SignalProtocolError::UntrustedIdentity(addr)) => {
             addr.name().to_string()
         }
+        SignalJniError::Signal(e) => e.display_with_causes(),
         e => format!("{}", e),
     };
 
-    let _ = env.throw_new(exception_type, error_string);
+    let _ = match jni_cache::find_class(env, exception_type) {
+        Ok(class) => env.throw_new(class, error_string),
+        Err(_) => env.throw_new(exception_type, error_string),
+    };
 }
 
 pub type ObjectHandle = jlong;
@@ -228,19 +295,168 @@ pub fn run_ffi_safe<F: FnOnce() -> Result<R, SignalJniError> + std::panic::Unwin
 where
     R: JniDummyValue,
 {
-    match std::panic::catch_unwind(f) {
-        Ok(Ok(r)) => r,
+    throw_on_failure(env, std::panic::catch_unwind(f)).unwrap_or_else(R::dummy_value)
+}
+
+/// Returns the result of a native call, or throws the Java exception for its failure.
+fn throw_on_failure<R>(
+    env: &JNIEnv,
+    result: std::thread::Result<Result<R, SignalJniError>>,
+) -> Option<R> {
+    let callback_exception = take_callback_exception();
+    match result {
+        Ok(Ok(r)) => Some(r),
         Ok(Err(e)) => {
-            throw_error(env, e);
-            R::dummy_value()
+            match callback_exception {
+                Some((callback_fn, original)) if thrown_by(&e, callback_fn) => {
+                    throw_callback_error(env, e, original)
+                }
+                _ => throw_error(env, e),
+            }
+            None
         }
         Err(r) => {
             throw_error(env, SignalJniError::UnexpectedPanic(r));
-            R::dummy_value()
+            None
         }
     }
 }
 
+/// Runs `f` on a new thread attached to the JVM, and returns a `CompletableFuture` for its
+/// result.
+///
+/// The future is completed exceptionally with the exception [run_ffi_safe] would have thrown.
+/// `f` must only use global references from the calling thread. If the thread cannot be started
+/// or attached, `f` is dropped and the future is completed exceptionally before this returns.
+pub fn run_ffi_async<F>(env: &JNIEnv, f: F) -> Result<jobject, SignalJniError>
+where
+    F: FnOnce(&JNIEnv) -> Result<jobject, SignalJniError> + Send + 'static,
+{
+    let future_class = jni_cache::find_class(env, "java/util/concurrent/CompletableFuture")?;
+    let future = match jni_cache::method("<init>", "()V")? {
+        Some(ctor) => env.new_object_unchecked(future_class, ctor.id(), &[])?,
+        None => env.new_object(future_class, "()V", &[])?,
+    };
+    let completion = env.new_global_ref(future)?;
+    let vm = env.get_java_vm()?;
+
+    let (attached_sender, attached) = mpsc::sync_channel(1);
+    let spawned = std::thread::Builder::new()
+        .name("signal-jni-async".to_string())
+        .spawn(move || {
+            let env = match vm.attach_current_thread() {
+                Ok(env) => env,
+                Err(e) => {
+                    let _ = attached_sender.send(Err(SignalJniError::from(e)));
+                    return;
+                }
+            };
+            let _ = attached_sender.send(Ok(()));
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| f(&env)));
+            complete_future(&env, completion.as_obj(), result);
+        });
+
+    // Until the new thread is attached it has no way to reach the future, so a failure to get
+    // that far is reported from here.
+    let started = match spawned {
+        Ok(_) => attached.recv().unwrap_or_else(|_| {
+            Err(SignalProtocolError::InternalError("async thread exited before attaching").into())
+        }),
+        Err(_) => Err(SignalProtocolError::InternalError(
+            "could not start a thread for an async call",
+        )
+        .into()),
+    };
+    if let Err(e) = started {
+        complete_future(env, future, Ok(Err(e)));
+    }
+
+    Ok(future.into_inner())
+}
+
+fn complete_future(
+    env: &JNIEnv,
+    future: JObject,
+    result: std::thread::Result<Result<jobject, SignalJniError>>,
+) {
+    let completed = match throw_on_failure(env, result) {
+        Some(value) => call_method_checked(
+            env,
+            future,
+            "complete",
+            "(Ljava/lang/Object;)Z",
+            &[JValue::from(JObject::from(value))],
+        ),
+        None => take_pending_exception(env).and_then(|exception| {
+            call_method_checked(
+                env,
+                future,
+                "completeExceptionally",
+                "(Ljava/lang/Throwable;)Z",
+                &[JValue::from(JObject::from(exception))],
+            )
+        }),
+    };
+    if let Err(e) = completed {
+        let _ = env.exception_clear();
+        // The future must not be left pending, so fall back to the simplest exception there is.
+        let fallback = (|| -> Result<(), SignalJniError> {
+            let message = env.new_string(format!("could not complete the future: {}", e))?;
+            let exception = env.new_object(
+                "java/lang/RuntimeException",
+                "(Ljava/lang/String;)V",
+                &[JValue::from(JObject::from(message))],
+            )?;
+            call_method_checked(
+                env,
+                future,
+                "completeExceptionally",
+                "(Ljava/lang/Throwable;)Z",
+                &[JValue::from(exception)],
+            )?;
+            Ok(())
+        })();
+        if fallback.is_err() {
+            let _ = env.exception_clear();
+        }
+    }
+}
+
+fn take_pending_exception<'a>(env: &JNIEnv<'a>) -> Result<JThrowable<'a>, SignalJniError> {
+    let exception = env.exception_occurred()?;
+    env.exception_clear()?;
+    Ok(exception)
+}
+
+fn thrown_by(error: &SignalJniError, callback_fn: &str) -> bool {
+    matches!(
+        error,
+        SignalJniError::Signal(SignalProtocolError::ApplicationCallbackThrewException(name, _, _))
+            if *name == callback_fn
+    )
+}
+
+/// Throws a RuntimeException for `error` whose cause is `original`, the throwable the callback
+/// threw.
+fn throw_callback_error(env: &JNIEnv, error: SignalJniError, original: GlobalRef) {
+    let thrown = (|| -> Result<(), SignalJniError> {
+        let message = env.new_string(format!("{}", error))?;
+        let exception = env.new_object(
+            "java/lang/RuntimeException",
+            "(Ljava/lang/String;Ljava/lang/Throwable;)V",
+            &[
+                JValue::from(JObject::from(message)),
+                JValue::from(original.as_obj()),
+            ],
+        )?;
+        env.throw(JThrowable::from(exception))?;
+        Ok(())
+    })();
+    if thrown.is_err() {
+        throw_error(env, error);
+    }
+}
+
 #[track_caller]
 pub fn expect_ready<F: Future>(future: F) -> F::Output {
     pin_mut!(future);
@@ -269,6 +485,23 @@ pub fn to_jbytearray<T: AsRef<[u8]>>(
     Ok(out)
 }
 
+/// Copies the contents of a Java `byte[][]`.
+pub fn convert_byte_array_array(
+    env: &JNIEnv,
+    array: jobjectArray,
+) -> Result<Vec<Vec<u8>>, SignalJniError> {
+    let len = env.get_array_length(array)?;
+    (0..len)
+        .map(|i| {
+            let element = env.get_object_array_element(array, i)?;
+            if element.is_null() {
+                return Err(SignalJniError::NullHandle);
+            }
+            Ok(env.convert_byte_array(element.into_inner())?)
+        })
+        .collect()
+}
+
 pub fn jint_to_u32(v: jint) -> Result<u32, SignalJniError> {
     if v < 0 {
         return Err(SignalJniError::IntegerOverflow(format!("{} to u32", v)));
@@ -315,6 +548,43 @@ pub fn jlong_from_u64(value: Result<u64, SignalProtocolError>) -> Result<jlong,
     }
 }
 
+thread_local! {
+    /// The throwable most recently caught by [exception_check] on this thread, with the name of
+    /// the callback that threw it.
+    ///
+    /// [SignalProtocolError] cannot hold a JNI reference, so the original throwable waits here
+    /// while the error it was turned into propagates through the protocol code. [run_ffi_safe]
+    /// takes it when the call returns, so it never outlives the native call that caught it.
+    static CALLBACK_EXCEPTION: RefCell<Option<(&'static str, GlobalRef)>> = RefCell::new(None);
+}
+
+fn take_callback_exception() -> Option<(&'static str, GlobalRef)> {
+    CALLBACK_EXCEPTION.with(|slot| slot.borrow_mut().take())
+}
+
+/// Calls `callback_fn` on `obj`, turning any exception it throws into
+/// [SignalProtocolError::ApplicationCallbackThrewException] (see [exception_check]).
+pub fn call_method_checked<'a, O: Into<JObject<'a>>>(
+    env: &JNIEnv<'a>,
+    obj: O,
+    callback_fn: &'static str,
+    callback_sig: &'static str,
+    callback_args: &[JValue],
+) -> Result<JValue<'a>, SignalJniError> {
+    // A throwing call returns an error while the exception is still pending, so check for it
+    // before looking at the result.
+    let obj = obj.into();
+    let result = match jni_cache::method(callback_fn, callback_sig)? {
+        Some(method) => {
+            debug_assert!(env.is_instance_of(obj, jni_cache::find_class(env, method.class)?)?);
+            env.call_method_unchecked(obj, method.id(), method.ret.clone(), callback_args)
+        }
+        None => env.call_method(obj, callback_fn, callback_sig, callback_args),
+    };
+    exception_check(env, callback_fn)?;
+    Ok(result?)
+}
+
 pub fn exception_check(env: &JNIEnv, fn_name: &'static str) -> Result<(), SignalJniError> {
     fn exception_class_name(env: &JNIEnv, exn: JThrowable) -> Result<String, SignalJniError> {
         let class_type = env.call_method(exn, "getClass", "()Ljava/lang/Class;", &[])?;
@@ -343,6 +613,9 @@ pub fn exception_check(env: &JNIEnv, fn_name: &'static str) -> Result<(), Signal
         let throwable = env.exception_occurred()?;
         env.exception_clear()?;
 
+        let original = env.new_global_ref(JObject::from(throwable))?;
+        CALLBACK_EXCEPTION.with(|slot| *slot.borrow_mut() = Some((fn_name, original)));
+
         let getmessage_sig = "()Ljava/lang/String;";
 
         let exn_type = exception_class_name(env, throwable).ok();
@@ -379,7 +652,7 @@ pub fn check_jobject_type(
         return Err(SignalJniError::NullHandle);
     }
 
-    let class = env.find_class(class_name)?;
+    let class = jni_cache::find_class(env, class_name)?;
 
     if !env.is_instance_of(obj, class)? {
         return Err(SignalJniError::BadJniParameter(class_name));
@@ -395,8 +668,7 @@ pub fn get_object_with_native_handle<T: 'static + Clone>(
     callback_sig: &'static str,
     callback_fn: &'static str,
 ) -> Result<Option<T>, SignalJniError> {
-    let rvalue = env.call_method(store_obj, callback_fn, callback_sig, &callback_args)?;
-    exception_check(env, callback_fn)?;
+    let rvalue = call_method_checked(env, store_obj, callback_fn, callback_sig, &callback_args)?;
 
     let obj = match rvalue {
         JValue::Object(o) => *o,
@@ -412,8 +684,7 @@ pub fn get_object_with_native_handle<T: 'static + Clone>(
         return Ok(None);
     }
 
-    let handle = env.call_method(obj, "nativeHandle", "()J", &[])?;
-    exception_check(env, "nativeHandle")?;
+    let handle = call_method_checked(env, obj, "nativeHandle", "()J", &[])?;
     match handle {
         JValue::Long(handle) => {
             if handle == 0 {
@@ -436,8 +707,7 @@ pub fn get_object_with_serialization(
     callback_sig: &'static str,
     callback_fn: &'static str,
 ) -> Result<Option<Vec<u8>>, SignalJniError> {
-    let rvalue = env.call_method(store_obj, callback_fn, callback_sig, &callback_args)?;
-    exception_check(env, callback_fn)?;
+    let rvalue = call_method_checked(env, store_obj, callback_fn, callback_sig, &callback_args)?;
 
     let obj = match rvalue {
         JValue::Object(o) => *o,
@@ -453,8 +723,7 @@ pub fn get_object_with_serialization(
         return Ok(None);
     }
 
-    let bytes = env.call_method(obj, "serialize", "()[B", &[])?;
-    exception_check(env, "serialize")?;
+    let bytes = call_method_checked(env, obj, "serialize", "()[B", &[])?;
 
     match bytes {
         JValue::Object(o) => Ok(Some(env.convert_byte_array(*o)?)),
@@ -467,10 +736,10 @@ pub fn get_object_with_serialization(
 
 pub fn jobject_from_serialized<'a>(
     env: &'a JNIEnv,
-    class_name: &str,
+    class_name: &'static str,
     serialized: &[u8],
 ) -> Result<JObject<'a>, SignalJniError> {
-    let class_type = env.find_class(class_name)?;
+    let class_type = jni_cache::find_class(env, class_name)?;
     let ctor_sig = "([B)V";
     let ctor_args = [JValue::from(to_jbytearray(env, Ok(serialized))?)];
     Ok(env.new_object(class_type, ctor_sig, &ctor_args)?)
@@ -478,10 +747,10 @@ pub fn jobject_from_serialized<'a>(
 
 pub fn jobject_from_native_handle<'a>(
     env: &'a JNIEnv,
-    class_name: &str,
+    class_name: &'static str,
     boxed_handle: ObjectHandle,
 ) -> Result<JObject<'a>, SignalJniError> {
-    let class_type = env.find_class(class_name)?;
+    let class_type = jni_cache::find_class(env, class_name)?;
     let ctor_sig = "(J)V";
     let ctor_args = [JValue::from(boxed_handle)];
     Ok(env.new_object(class_type, ctor_sig, &ctor_args)?)
@@ -622,3 +891,82 @@ macro_rules! jni_fn_destroy {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jni::{InitArgsBuilder, JNIVersion, JavaVM};
+    use once_cell::sync::Lazy;
+
+    static VM: Lazy<JavaVM> = Lazy::new(|| {
+        let args = InitArgsBuilder::new()
+            .version(JNIVersion::V8)
+            .build()
+            .expect("valid JVM arguments");
+        let vm = JavaVM::new(args).expect("JVM starts");
+        jni_cache::init(
+            &vm.attach_current_thread().expect("attached"),
+            &["java/lang/IllegalArgumentException"],
+            &[
+                "java/util/concurrent/CompletableFuture",
+                "org/example/DoesNotExist",
+            ],
+            &[
+                ("java/util/concurrent/CompletableFuture", "<init>", "()V"),
+                (
+                    "java/util/concurrent/CompletableFuture",
+                    "complete",
+                    "(Ljava/lang/Object;)Z",
+                ),
+                (
+                    "java/util/concurrent/CompletableFuture",
+                    "completeExceptionally",
+                    "(Ljava/lang/Throwable;)Z",
+                ),
+            ],
+        )
+        .expect("cache initialized");
+        vm
+    });
+
+    /// Waits for `future`, returning its value or the exception `join` threw.
+    fn join<'a>(env: &JNIEnv<'a>, future: jobject) -> Result<JObject<'a>, JObject<'a>> {
+        match env.call_method(future, "join", "()Ljava/lang/Object;", &[]) {
+            Ok(value) => Ok(value.l().expect("object result")),
+            Err(_) => Err(take_pending_exception(env)
+                .expect("exception pending")
+                .into()),
+        }
+    }
+
+    #[test]
+    fn async_results_complete_the_future() {
+        let env = VM.attach_current_thread().expect("attached");
+        let future = run_ffi_async(&env, |env| to_jbytearray(env, Ok([1, 2, 3]))).unwrap();
+
+        let result = join(&env, future).expect("completes normally");
+        assert_eq!(
+            env.convert_byte_array(result.into_inner()).unwrap(),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn async_errors_complete_the_future_exceptionally() {
+        let env = VM.attach_current_thread().expect("attached");
+        let future = run_ffi_async(&env, |_| {
+            Err(SignalProtocolError::InvalidArgument("bad input".to_string()).into())
+        })
+        .unwrap();
+
+        let exception = join(&env, future).expect_err("completes exceptionally");
+        let cause = env
+            .call_method(exception, "getCause", "()Ljava/lang/Throwable;", &[])
+            .unwrap()
+            .l()
+            .unwrap();
+        assert!(env
+            .is_instance_of(cause, "java/lang/IllegalArgumentException")
+            .unwrap());
+    }
+}
@@ -0,0 +1,237 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Writes the TypeScript declarations for the native module to stdout.
+//!
+//! The declarations are generated from the annotations in src/exports.rs, the same table lib.rs
+//! registers with Neon. To update the checked-in copy, run
+//! `cargo run -p libsignal-node --bin gen_ts_decl > node/libsignal_client.d.ts`
+//! from the repository root.
+
+const LINE_LIMIT: usize = 80;
+
+const HEADER: &str = "//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+// Generated by rust/bridge/node/src/bin/gen_ts_decl.rs; do not edit.
+
+export type Bytes = ArrayBuffer | ArrayBufferView;
+";
+
+struct Param {
+    name: &'static str,
+    ty: &'static str,
+}
+
+struct Signature {
+    name: &'static str,
+    params: Vec<Param>,
+    /// None for a constructor.
+    result: Option<String>,
+}
+
+struct Class {
+    name: &'static str,
+    members: Vec<Signature>,
+}
+
+struct Module {
+    classes: Vec<Class>,
+    function_groups: Vec<Vec<Signature>>,
+    async_functions: Vec<Signature>,
+}
+
+macro_rules! node_exports {
+    (
+        classes {
+            $($class:ident = $js_class:ident {
+                constructor ( $($ctor_arg:ident : $ctor_ty:literal),* $(,)? ) ;
+                $($method:ident ( $($arg:ident : $ty:literal),* $(,)? ) -> $method_ret:literal ;)*
+            })*
+        }
+        functions {
+            $({ $($name:ident = $rust_fn:ident (
+                $($fn_arg:ident : $fn_ty:literal),* $(,)?
+            ) -> $ret:literal ;)* })*
+        }
+        async_functions {
+            $($async_name:ident = $async_rust_fn:ident (
+                $($async_arg:ident : $async_ty:literal),* $(,)?
+            ) -> $async_ret:literal ;)*
+        }
+    ) => {
+        fn exports() -> Module {
+            Module {
+                classes: vec![$(Class {
+                    name: stringify!($class),
+                    members: vec![
+                        Signature {
+                            name: "constructor",
+                            params: vec![$(Param { name: stringify!($ctor_arg), ty: $ctor_ty }),*],
+                            result: None,
+                        },
+                        $(Signature {
+                            name: stringify!($method),
+                            params: vec![$(Param { name: stringify!($arg), ty: $ty }),*],
+                            result: Some($method_ret.to_string()),
+                        }),*
+                    ],
+                }),*],
+                function_groups: vec![$(vec![$(Signature {
+                    name: stringify!($name),
+                    params: vec![$(Param { name: stringify!($fn_arg), ty: $fn_ty }),*],
+                    result: Some($ret.to_string()),
+                }),*]),*],
+                async_functions: vec![$(Signature {
+                    name: stringify!($async_name),
+                    params: vec![$(Param { name: stringify!($async_arg), ty: $async_ty }),*],
+                    result: Some(format!("Promise<{}>", $async_ret)),
+                }),*],
+            }
+        }
+    };
+}
+
+include!("../exports.rs");
+
+/// Writes one declaration, on a single line if it fits and with one parameter per line if not.
+fn write_signature(out: &mut String, indent: &str, prefix: &str, signature: &Signature) {
+    let params: Vec<String> = signature
+        .params
+        .iter()
+        .map(|p| match p.ty.strip_suffix('?') {
+            Some(ty) => format!("{}?: {}", p.name, ty),
+            None => format!("{}: {}", p.name, p.ty),
+        })
+        .collect();
+    let result = match &signature.result {
+        Some(result) => format!(": {}", result),
+        None => String::new(),
+    };
+
+    let single_line = format!(
+        "{}{}{}({}){};",
+        indent,
+        prefix,
+        signature.name,
+        params.join(", "),
+        result
+    );
+    if single_line.len() <= LINE_LIMIT || params.is_empty() {
+        out.push_str(&single_line);
+        out.push('\n');
+        return;
+    }
+
+    out.push_str(&format!("{}{}{}(\n", indent, prefix, signature.name));
+    let inner_indent = format!("{}  ", indent);
+    out.push_str(&format!(
+        "{}{}\n",
+        inner_indent,
+        params.join(&format!(",\n{}", inner_indent))
+    ));
+    out.push_str(&format!("{}){};\n", indent, result));
+}
+
+fn render(module: &Module) -> String {
+    let mut out = HEADER.to_string();
+
+    for class in &module.classes {
+        out.push_str(&format!("\nexport class {} {{\n", class.name));
+        for member in &class.members {
+            write_signature(&mut out, "  ", "", member);
+        }
+        out.push_str("}\n");
+    }
+
+    let groups = module
+        .function_groups
+        .iter()
+        .chain(std::iter::once(&module.async_functions))
+        .filter(|group| !group.is_empty());
+    for group in groups {
+        out.push('\n');
+        for function in group {
+            write_signature(&mut out, "", "export function ", function);
+        }
+    }
+
+    out
+}
+
+fn main() {
+    print!("{}", render(&exports()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_in_declarations_are_up_to_date() {
+        let checked_in = include_str!("../../../../../node/libsignal_client.d.ts");
+        assert!(
+            render(&exports()) == checked_in,
+            "node/libsignal_client.d.ts is out of date; regenerate it with \
+             `cargo run -p libsignal-node --bin gen_ts_decl > node/libsignal_client.d.ts`"
+        );
+    }
+
+    #[test]
+    fn async_functions_return_promises() {
+        node_exports! {
+            classes {}
+            functions {}
+            async_functions {
+                decryptLater = decrypt_later(ctext: "Buffer") -> "Buffer";
+            }
+        }
+        assert!(render(&exports())
+            .ends_with("\nexport function decryptLater(ctext: Buffer): Promise<Buffer>;\n"));
+    }
+
+    #[test]
+    fn optional_parameters_are_marked() {
+        node_exports! {
+            classes {}
+            functions {
+                {
+                    generate = generate_js(rng: "TestRng?") -> "Buffer";
+                }
+            }
+            async_functions {}
+        }
+        assert!(
+            render(&exports()).ends_with("\nexport function generate(rng?: TestRng): Buffer;\n")
+        );
+    }
+
+    #[test]
+    fn long_signatures_are_wrapped() {
+        let param = |name| Param { name, ty: "Buffer" };
+        let module = Module {
+            classes: vec![Class {
+                name: "Example",
+                members: vec![Signature {
+                    name: "verify",
+                    params: vec![
+                        param("firstIdentityKey"),
+                        param("secondIdentityKey"),
+                        param("thirdIdentityKey"),
+                    ],
+                    result: Some("boolean".to_string()),
+                }],
+            }],
+            function_groups: vec![],
+            async_functions: vec![],
+        };
+        assert!(render(&module).ends_with(
+            "\nexport class Example {\n  verify(\n    firstIdentityKey: Buffer,\n    \
+             secondIdentityKey: Buffer,\n    thirdIdentityKey: Buffer\n  ): boolean;\n}\n"
+        ));
+    }
+}
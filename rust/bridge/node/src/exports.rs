@@ -0,0 +1,188 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+// Everything the native module exports, with the TypeScript type of each argument and result.
+//
+// This file is included both by lib.rs, which registers the classes and functions with Neon,
+// and by bin/gen_ts_decl.rs, which writes node/libsignal_client.d.ts from the annotations; the
+// two define `node_exports!` differently. Functions are listed in groups, which are separated by
+// a blank line in the generated declarations. Entries under `async_functions` are declared as
+// returning a `Promise`. Byte arguments are declared as `Bytes` (any ArrayBuffer or view of one);
+// byte results are always a `Buffer`. A type ending in `?` marks an optional trailing argument.
+//
+// Functions that use randomness take an optional `TestRng`, which only builds with the test-rng
+// feature can create; without one they use the OS RNG.
+
+node_exports! {
+    classes {
+        PrivateKey = JsPrivateKey {
+            constructor(rng: "TestRng?");
+            serialize() -> "Buffer";
+            serializePublicKey() -> "Buffer";
+            signWithRandomness(message: "Bytes", randomness: "Bytes") -> "Buffer";
+            signAlternateIdentity(otherIdentityKey: "Bytes", rng: "TestRng?") -> "Buffer";
+        }
+        SessionRecord = JsSessionRecord {
+            constructor(serialized: "Bytes");
+            localRegistrationId() -> "number";
+            remoteRegistrationId() -> "number";
+            aliceBaseKey() -> "Buffer";
+            sessionVersion() -> "number";
+            remoteIdentityKey() -> "Buffer | null";
+            debugHasUsableSenderChain() -> "boolean";
+            debugSenderChainCounter() -> "number | null";
+            debugReceiverChainCounter(theirEphemeral: "Bytes") -> "number | null";
+            debugNumberOfReceiverChains() -> "number";
+        }
+        SenderKeyRecord = JsSenderKeyRecord {
+            constructor(serialized: "Bytes");
+            serialize() -> "Buffer";
+            stateCount() -> "number";
+            chainIds() -> "number[]";
+            maxIterationForChain(chainId: "number") -> "number | null";
+        }
+        PreKeyRecord = JsPreKeyRecord {
+            constructor(serialized: "Bytes");
+            serialize() -> "Buffer";
+            id() -> "number";
+            publicKey() -> "Buffer";
+            privateKey() -> "Buffer";
+        }
+        SignedPreKeyRecord = JsSignedPreKeyRecord {
+            constructor(serialized: "Bytes");
+            serialize() -> "Buffer";
+            id() -> "number";
+            timestamp() -> "number";
+            signature() -> "Buffer";
+            publicKey() -> "Buffer";
+            privateKey() -> "Buffer";
+        }
+        SignalMessage = JsSignalMessage {
+            constructor(serialized: "Bytes");
+            serialize() -> "Buffer";
+            messageVersion() -> "number";
+            counter() -> "number";
+            senderRatchetKey() -> "Buffer";
+            body() -> "Buffer";
+            verifyMac(
+                senderIdentityKey: "Bytes",
+                receiverIdentityKey: "Bytes",
+                macKey: "Bytes",
+            ) -> "boolean";
+        }
+        PreKeySignalMessage = JsPreKeySignalMessage {
+            constructor(serialized: "Bytes");
+            serialize() -> "Buffer";
+            messageVersion() -> "number";
+            registrationId() -> "number";
+            preKeyId() -> "number | null";
+            signedPreKeyId() -> "number";
+            baseKey() -> "Buffer";
+            identityKey() -> "Buffer";
+        }
+        DecryptionErrorMessage = JsDecryptionErrorMessage {
+            constructor(serialized: "Bytes");
+            serialize() -> "Buffer";
+            timestamp() -> "number";
+            deviceId() -> "number";
+            ratchetKey() -> "Buffer | null";
+            toPlaintextContent() -> "PlaintextContent";
+        }
+        PlaintextContent = JsPlaintextContent {
+            constructor(serialized: "Bytes");
+            serialize() -> "Buffer";
+            body() -> "Buffer";
+        }
+        TestRng = JsTestRng {
+            constructor(seed: "number");
+        }
+    }
+
+    functions {
+        {
+            decryptionErrorMessageForOriginal = decryption_error_message_for_original(
+                originalBytes: "Bytes",
+                originalType: "number",
+                originalTimestamp: "number",
+                originalSenderDeviceId: "number",
+            ) -> "DecryptionErrorMessage";
+            extractDecryptionErrorMessageFromSerializedContent =
+                extract_decryption_error_message_from_serialized_content_js(
+                    body: "Bytes",
+                ) -> "DecryptionErrorMessage";
+        }
+        {
+            verifyAlternateIdentity = verify_alternate_identity(
+                identityKey: "Bytes",
+                otherIdentityKey: "Bytes",
+                signature: "Bytes",
+            ) -> "boolean";
+        }
+        {
+            fingerprint = fingerprint_js(
+                version: "number",
+                iterations: "number",
+                localIdentifier: "Bytes",
+                localKey: "Bytes",
+                remoteIdentifier: "Bytes",
+                remoteKey: "Bytes",
+            ) -> "{ displayable: string; scannable: Buffer }";
+            displayableFingerprint = displayable_fingerprint_js(
+                iterations: "number",
+                localIdentifier: "Bytes",
+                localKey: "Bytes",
+                remoteIdentifier: "Bytes",
+                remoteKey: "Bytes",
+            ) -> "string";
+            compareScannableFingerprints = compare_scannable_fingerprints_js(
+                ours: "Bytes",
+                scanned: "Bytes",
+            ) -> "{ result: 'match' | 'localMismatch' | 'remoteMismatch' | 'versionMismatch'; ourVersion?: number; theirVersion?: number }";
+        }
+        {
+            sha512 = sha512_digest(input: "Bytes") -> "Buffer";
+            hmacSha512 = hmac_sha512_mac(key: "Bytes", input: "Bytes") -> "Buffer";
+            padMessage = pad_message_js(body: "Bytes") -> "Buffer";
+            unpadMessage = unpad_message_js(padded: "Bytes") -> "Buffer";
+            hkdfDeriveSecrets = hkdf_derive_secrets(
+                outputLength: "number",
+                version: "number",
+                inputKeyMaterial: "Bytes",
+                info: "Bytes",
+                salt: "Bytes | null",
+            ) -> "Buffer";
+            deriveUnidentifiedAccessKey = derive_unidentified_access_key_js(
+                profileKey: "Bytes",
+            ) -> "Buffer";
+            unrestrictedUnidentifiedAccessKey = unrestricted_unidentified_access_key_js(
+                rng: "TestRng?",
+            ) -> "Buffer";
+        }
+        {
+            aes256GcmSivEncrypt = aes_256_gcm_siv_encrypt_async(
+                ptext: "Bytes",
+                key: "Bytes",
+                nonce: "Bytes",
+                aad: "Bytes",
+                callback: "(err: Error | null, ctext: Buffer) => void",
+            ) -> "void";
+            aes256GcmSivDecrypt = aes_256_gcm_siv_decrypt_async(
+                ctext: "Bytes",
+                key: "Bytes",
+                nonce: "Bytes",
+                aad: "Bytes",
+                callback: "(err: Error | null, ptext: Buffer) => void",
+            ) -> "void";
+        }
+        {
+            initLogger = init_logger(
+                maxLevel: "number",
+                callback: "(level: number, target: string, file: string | null, line: number | null, message: string) => void",
+            ) -> "void";
+        }
+    }
+
+    async_functions {}
+}
@@ -5,7 +5,15 @@
 
 use libsignal_protocol_rust::*;
 use neon::context::Context;
+use neon::object::This;
 use neon::prelude::*;
+use std::convert::TryFrom;
+
+mod logging;
+use logging::init_logger;
+
+mod test_rng;
+use test_rng::{with_rng, JsTestRng};
 
 fn borrow_this<'a, V, T, F>(cx: &mut MethodContext<'a, V>, f: F) -> T
 where
@@ -16,12 +24,357 @@ where
     cx.borrow(&this, f)
 }
 
+/// Copies out the contents of a Buffer, ArrayBuffer, or ArrayBuffer view (a TypedArray or
+/// DataView), throwing a TypeError that mentions `name` for anything else.
+fn bytes_contents<'a, C: Context<'a>>(
+    cx: &mut C,
+    value: Handle<'a, JsValue>,
+    name: &str,
+) -> NeonResult<Vec<u8>> {
+    if let Ok(buffer) = value.downcast::<JsBuffer>() {
+        return Ok(cx.borrow(&buffer, |data| data.as_slice::<u8>().to_vec()));
+    }
+    if let Ok(array_buffer) = value.downcast::<JsArrayBuffer>() {
+        return Ok(cx.borrow(&array_buffer, |data| data.as_slice::<u8>().to_vec()));
+    }
+
+    let array_buffer_class = cx
+        .global()
+        .get(cx, "ArrayBuffer")?
+        .downcast_or_throw::<JsObject, _>(cx)?;
+    let is_view = array_buffer_class
+        .get(cx, "isView")?
+        .downcast_or_throw::<JsFunction, _>(cx)?
+        .call(cx, array_buffer_class, vec![value])?;
+    if !is_view.downcast::<JsBoolean>().map_or(false, |b| b.value()) {
+        return cx.throw_type_error(format!(
+            "{} must be a Buffer, TypedArray, DataView, or ArrayBuffer",
+            name
+        ));
+    }
+
+    let view = value.downcast_or_throw::<JsObject, _>(cx)?;
+    let array_buffer = view
+        .get(cx, "buffer")?
+        .downcast_or_throw::<JsArrayBuffer, _>(cx)?;
+    let offset = view
+        .get(cx, "byteOffset")?
+        .downcast_or_throw::<JsNumber, _>(cx)?
+        .value() as usize;
+    let length = view
+        .get(cx, "byteLength")?
+        .downcast_or_throw::<JsNumber, _>(cx)?
+        .value() as usize;
+    let contents = cx.borrow(&array_buffer, |data| {
+        data.as_slice::<u8>()
+            .get(offset..offset.saturating_add(length))
+            .map(<[u8]>::to_vec)
+    });
+    match contents {
+        Some(contents) => Ok(contents),
+        None => cx.throw_range_error(format!("{} is outside of its ArrayBuffer", name)),
+    }
+}
+
+fn bytes_argument<'a, T: This>(
+    cx: &mut CallContext<'a, T>,
+    i: i32,
+    name: &str,
+) -> NeonResult<Vec<u8>> {
+    let value = cx.argument::<JsValue>(i)?;
+    bytes_contents(cx, value, name)
+}
+
+fn bytes_to_buffer<'a, C: Context<'a>>(cx: &mut C, bytes: &[u8]) -> JsResult<'a, JsBuffer> {
+    // FIXME: check for truncation
+    let mut buffer = cx.buffer(bytes.len() as u32)?;
+    cx.borrow_mut(&mut buffer, |raw_buffer| {
+        raw_buffer.as_mut_slice().copy_from_slice(bytes);
+    });
+    Ok(buffer)
+}
+
+fn sha512_digest(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+    let input = bytes_argument(&mut cx, 0, "input")?;
+    bytes_to_buffer(&mut cx, &sha512(&input))
+}
+
+fn hmac_sha512_mac(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+    let key = bytes_argument(&mut cx, 0, "key")?;
+    let input = bytes_argument(&mut cx, 1, "input")?;
+    match hmac_sha512(&key, &input) {
+        Ok(mac) => bytes_to_buffer(&mut cx, &mac),
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+fn pad_message_js(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+    let body = bytes_argument(&mut cx, 0, "body")?;
+    bytes_to_buffer(&mut cx, &pad_message(&body))
+}
+
+fn unpad_message_js(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+    let padded = bytes_argument(&mut cx, 0, "padded")?;
+    match unpad_message(&padded) {
+        Ok(body) => bytes_to_buffer(&mut cx, body),
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+fn derive_unidentified_access_key_js(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+    let profile_key = bytes_argument(&mut cx, 0, "profileKey")?;
+    let profile_key = match <&[u8; 32]>::try_from(&profile_key[..]) {
+        Ok(profile_key) => profile_key,
+        Err(_) => return cx.throw_range_error("profile key must be 32 bytes"),
+    };
+    match derive_unidentified_access_key(profile_key) {
+        Ok(access_key) => bytes_to_buffer(&mut cx, &access_key),
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+fn unrestricted_unidentified_access_key_js(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+    let access_key = with_rng(&mut cx, 0, |rng| unrestricted_unidentified_access_key(rng))?;
+    bytes_to_buffer(&mut cx, &access_key)
+}
+
+fn decryption_error_message_for_original(
+    mut cx: FunctionContext,
+) -> JsResult<JsDecryptionErrorMessage> {
+    let original_bytes = bytes_argument(&mut cx, 0, "originalBytes")?;
+    let original_type = cx.argument::<JsNumber>(1)?.value() as u8;
+    let original_timestamp = cx.argument::<JsNumber>(2)?.value() as u64;
+    let original_sender_device_id = cx.argument::<JsNumber>(3)?.value() as u32;
+
+    let message = CiphertextMessageType::try_from(original_type).and_then(|original_type| {
+        DecryptionErrorMessage::for_original(
+            &original_bytes,
+            original_type,
+            original_timestamp,
+            original_sender_device_id,
+        )
+    });
+    match message {
+        Ok(message) => {
+            let serialized = bytes_to_buffer(&mut cx, message.serialized())?;
+            JsDecryptionErrorMessage::new(&mut cx, vec![serialized])
+        }
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+fn extract_decryption_error_message_from_serialized_content_js(
+    mut cx: FunctionContext,
+) -> JsResult<JsDecryptionErrorMessage> {
+    let bytes = bytes_argument(&mut cx, 0, "body")?;
+    match extract_decryption_error_message_from_serialized_content(&bytes) {
+        Ok(message) => {
+            let serialized = bytes_to_buffer(&mut cx, message.serialized())?;
+            JsDecryptionErrorMessage::new(&mut cx, vec![serialized])
+        }
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+fn hkdf_derive_secrets(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+    let output_length = cx.argument::<JsNumber>(0)?.value();
+    let version = cx.argument::<JsNumber>(1)?.value();
+    let input_key_material = bytes_argument(&mut cx, 2, "inputKeyMaterial")?;
+    let info = bytes_argument(&mut cx, 3, "info")?;
+    let salt = match cx.argument_opt(4) {
+        Some(salt) if !salt.is_a::<JsNull>() && !salt.is_a::<JsUndefined>() => {
+            Some(bytes_contents(&mut cx, salt, "salt")?)
+        }
+        _ => None,
+    };
+
+    if output_length.fract() != 0.0 || output_length < 0.0 {
+        return cx.throw_range_error("output length must be a non-negative integer");
+    }
+    if version.fract() != 0.0 || version < 0.0 || version > u32::MAX as f64 {
+        return cx.throw_range_error("version must be a non-negative integer");
+    }
+
+    let derived = HKDF::new(version as u32).and_then(|hkdf| match salt {
+        Some(salt) => {
+            hkdf.derive_salted_secrets(&input_key_material, &salt, &info, output_length as usize)
+        }
+        None => hkdf.derive_secrets(&input_key_material, &info, output_length as usize),
+    });
+    match derived {
+        Ok(derived) => bytes_to_buffer(&mut cx, &derived),
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+fn verify_alternate_identity(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let identity_key = bytes_argument(&mut cx, 0, "identityKey")?;
+    let other_identity_key = bytes_argument(&mut cx, 1, "otherIdentityKey")?;
+    let signature = bytes_argument(&mut cx, 2, "signature")?;
+
+    let result = IdentityKey::decode(&identity_key).and_then(|identity_key| {
+        let other_identity_key = IdentityKey::decode(&other_identity_key)?;
+        identity_key.verify_alternate_identity(&other_identity_key, &signature)
+    });
+    match result {
+        Ok(valid) => Ok(cx.boolean(valid)),
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+fn fingerprint_js(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let version = cx.argument::<JsNumber>(0)?.value() as u32;
+    let iterations = cx.argument::<JsNumber>(1)?.value() as u32;
+    let local_identifier = bytes_argument(&mut cx, 2, "localIdentifier")?;
+    let local_key = bytes_argument(&mut cx, 3, "localKey")?;
+    let remote_identifier = bytes_argument(&mut cx, 4, "remoteIdentifier")?;
+    let remote_key = bytes_argument(&mut cx, 5, "remoteKey")?;
+
+    let result = IdentityKey::decode(&local_key).and_then(|local_key| {
+        let remote_key = IdentityKey::decode(&remote_key)?;
+        let fingerprint = Fingerprint::new(
+            version,
+            iterations,
+            &local_identifier,
+            &local_key,
+            &remote_identifier,
+            &remote_key,
+        )?;
+        Ok((
+            fingerprint.display_string()?,
+            fingerprint.scannable.serialize()?,
+        ))
+    });
+    let (displayable, scannable) = match result {
+        Ok(result) => result,
+        Err(e) => return cx.throw_error(e.to_string()),
+    };
+
+    let object = cx.empty_object();
+    let displayable = cx.string(displayable);
+    object.set(&mut cx, "displayable", displayable)?;
+    let scannable = bytes_to_buffer(&mut cx, &scannable)?;
+    object.set(&mut cx, "scannable", scannable)?;
+    Ok(object)
+}
+
+fn displayable_fingerprint_js(mut cx: FunctionContext) -> JsResult<JsString> {
+    let iterations = cx.argument::<JsNumber>(0)?.value() as u32;
+    let local_identifier = bytes_argument(&mut cx, 1, "localIdentifier")?;
+    let local_key = bytes_argument(&mut cx, 2, "localKey")?;
+    let remote_identifier = bytes_argument(&mut cx, 3, "remoteIdentifier")?;
+    let remote_key = bytes_argument(&mut cx, 4, "remoteKey")?;
+
+    let result = IdentityKey::decode(&local_key).and_then(|local_key| {
+        let remote_key = IdentityKey::decode(&remote_key)?;
+        Fingerprint::new_displayable(
+            iterations,
+            &local_identifier,
+            &local_key,
+            &remote_identifier,
+            &remote_key,
+        )
+    });
+    match result {
+        Ok(fingerprint) => Ok(cx.string(fingerprint.to_string())),
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+fn compare_scannable_fingerprints_js(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let ours = bytes_argument(&mut cx, 0, "ours")?;
+    let scanned = bytes_argument(&mut cx, 1, "scanned")?;
+
+    let result =
+        ScannableFingerprint::deserialize(&ours).and_then(|ours| ours.compare_scanned(&scanned));
+    let comparison = match result {
+        Ok(comparison) => comparison,
+        Err(e) => return cx.throw_error(e.to_string()),
+    };
+
+    let object = cx.empty_object();
+    let (result, versions) = match comparison {
+        FingerprintComparison::Match => ("match", None),
+        FingerprintComparison::LocalMismatch => ("localMismatch", None),
+        FingerprintComparison::RemoteMismatch => ("remoteMismatch", None),
+        FingerprintComparison::VersionMismatch { ours, theirs } => {
+            ("versionMismatch", Some((ours, theirs)))
+        }
+    };
+    let result = cx.string(result);
+    object.set(&mut cx, "result", result)?;
+    if let Some((ours, theirs)) = versions {
+        let ours = cx.number(ours);
+        object.set(&mut cx, "ourVersion", ours)?;
+        let theirs = cx.number(theirs);
+        object.set(&mut cx, "theirVersion", theirs)?;
+    }
+    Ok(object)
+}
+
+type AeadOperation = fn(&[u8], &[u8], &[u8], &[u8]) -> Result<Vec<u8>, SignalProtocolError>;
+
+struct AeadTask {
+    operation: AeadOperation,
+    input: Vec<u8>,
+    key: Vec<u8>,
+    nonce: Vec<u8>,
+    aad: Vec<u8>,
+}
+
+impl Task for AeadTask {
+    type Output = Vec<u8>;
+    type Error = SignalProtocolError;
+    type JsEvent = JsBuffer;
+
+    fn perform(&self) -> Result<Vec<u8>, SignalProtocolError> {
+        (self.operation)(&self.input, &self.key, &self.nonce, &self.aad)
+    }
+
+    fn complete(
+        self,
+        mut cx: TaskContext,
+        result: Result<Vec<u8>, SignalProtocolError>,
+    ) -> JsResult<JsBuffer> {
+        match result {
+            Ok(output) => bytes_to_buffer(&mut cx, &output),
+            Err(e) => cx.throw_error(e.to_string()),
+        }
+    }
+}
+
+/// Runs `operation` on the libuv thread pool, passing the result to the callback in argument 4.
+fn schedule_aead(mut cx: FunctionContext, operation: AeadOperation) -> JsResult<JsUndefined> {
+    let input = bytes_argument(&mut cx, 0, "input")?;
+    let key = bytes_argument(&mut cx, 1, "key")?;
+    let nonce = bytes_argument(&mut cx, 2, "nonce")?;
+    let aad = bytes_argument(&mut cx, 3, "aad")?;
+    let callback = cx.argument::<JsFunction>(4)?;
+
+    let task = AeadTask {
+        operation,
+        input,
+        key,
+        nonce,
+        aad,
+    };
+    task.schedule(callback);
+    Ok(cx.undefined())
+}
+
+fn aes_256_gcm_siv_encrypt_async(cx: FunctionContext) -> JsResult<JsUndefined> {
+    schedule_aead(cx, aes_256_gcm_siv_encrypt)
+}
+
+fn aes_256_gcm_siv_decrypt_async(cx: FunctionContext) -> JsResult<JsUndefined> {
+    schedule_aead(cx, aes_256_gcm_siv_decrypt)
+}
+
 declare_types! {
     pub class JsPrivateKey for PrivateKey {
-        init(_cx) {
+        init(mut cx) {
             // FIXME: guard against calling this directly
-            let mut rng = rand::rngs::OsRng;
-            let keypair = KeyPair::generate(&mut rng);
+            let keypair = with_rng(&mut cx, 0, |rng| KeyPair::generate(rng))?;
             Ok(keypair.private_key)
         }
 
@@ -29,17 +382,480 @@ declare_types! {
             let bytes = borrow_this(&mut cx, |k| {
                 k.serialize()
             });
-            // FIXME: check for truncation
-            let mut buffer = cx.buffer(bytes.len() as u32)?;
-            cx.borrow_mut(&mut buffer, |raw_buffer| {
-                raw_buffer.as_mut_slice().copy_from_slice(&bytes);
+            Ok(bytes_to_buffer(&mut cx, &bytes)?.upcast())
+        }
+
+        method serializePublicKey(mut cx) {
+            let public_key = borrow_this(&mut cx, |k| k.public_key());
+            match public_key {
+                Ok(public_key) => Ok(bytes_to_buffer(&mut cx, &public_key.serialize())?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method signWithRandomness(mut cx) {
+            let message = bytes_argument(&mut cx, 0, "message")?;
+            let randomness = bytes_argument(&mut cx, 1, "randomness")?;
+            let randomness = match <&[u8; 64]>::try_from(&randomness[..]) {
+                Ok(randomness) => randomness,
+                Err(_) => return cx.throw_error("signature randomness must be 64 bytes"),
+            };
+            let signature = borrow_this(&mut cx, |k| {
+                k.calculate_signature_with_randomness(&message, randomness)
+            });
+            match signature {
+                Ok(signature) => Ok(bytes_to_buffer(&mut cx, &signature)?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method signAlternateIdentity(mut cx) {
+            let other_identity_key = bytes_argument(&mut cx, 0, "otherIdentityKey")?;
+            let other_identity_key = match IdentityKey::decode(&other_identity_key) {
+                Ok(key) => key,
+                Err(e) => return cx.throw_error(e.to_string()),
+            };
+            let identity_key_pair = borrow_this(&mut cx, |k| {
+                k.public_key()
+                    .map(|public_key| IdentityKeyPair::new(IdentityKey::new(public_key), **k))
+            });
+            let identity_key_pair = match identity_key_pair {
+                Ok(key_pair) => key_pair,
+                Err(e) => return cx.throw_error(e.to_string()),
+            };
+            let signature = with_rng(&mut cx, 1, |rng| {
+                identity_key_pair.sign_alternate_identity(&other_identity_key, rng)
+            })?;
+            match signature {
+                Ok(signature) => Ok(bytes_to_buffer(&mut cx, &signature)?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+    }
+
+    pub class JsSenderKeyRecord for SenderKeyRecord {
+        init(mut cx) {
+            let serialized = bytes_argument(&mut cx, 0, "serialized")?;
+            match SenderKeyRecord::deserialize(&serialized) {
+                Ok(record) => Ok(record),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method serialize(mut cx) {
+            let bytes = borrow_this(&mut cx, |r| r.serialize());
+            match bytes {
+                Ok(bytes) => Ok(bytes_to_buffer(&mut cx, &bytes)?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method stateCount(mut cx) {
+            let count = borrow_this(&mut cx, |r| r.state_count());
+            match count {
+                Ok(count) => Ok(cx.number(count as f64).upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method chainIds(mut cx) {
+            let chain_ids = match borrow_this(&mut cx, |r| r.chain_ids()) {
+                Ok(chain_ids) => chain_ids,
+                Err(e) => return cx.throw_error(e.to_string()),
+            };
+            let result = JsArray::new(&mut cx, chain_ids.len() as u32);
+            for (i, chain_id) in chain_ids.into_iter().enumerate() {
+                let chain_id = cx.number(chain_id);
+                result.set(&mut cx, i as u32, chain_id)?;
+            }
+            Ok(result.upcast())
+        }
+
+        method maxIterationForChain(mut cx) {
+            let chain_id = cx.argument::<JsNumber>(0)?.value() as u32;
+            let iteration = borrow_this(&mut cx, |r| r.max_iteration_for_chain(chain_id));
+            match iteration {
+                Ok(Some(iteration)) => Ok(cx.number(iteration).upcast()),
+                Ok(None) => Ok(cx.null().upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+    }
+
+    pub class JsPreKeyRecord for PreKeyRecord {
+        init(mut cx) {
+            let serialized = bytes_argument(&mut cx, 0, "serialized")?;
+            match PreKeyRecord::deserialize(&serialized) {
+                Ok(record) => Ok(record),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method serialize(mut cx) {
+            let bytes = borrow_this(&mut cx, |r| r.serialize());
+            match bytes {
+                Ok(bytes) => Ok(bytes_to_buffer(&mut cx, &bytes)?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method id(mut cx) {
+            let id = borrow_this(&mut cx, |r| r.id());
+            match id {
+                Ok(id) => Ok(cx.number(id).upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method publicKey(mut cx) {
+            let key = borrow_this(&mut cx, |r| r.public_key());
+            match key {
+                Ok(key) => Ok(bytes_to_buffer(&mut cx, &key.serialize())?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method privateKey(mut cx) {
+            let key = borrow_this(&mut cx, |r| r.private_key());
+            match key {
+                Ok(key) => Ok(bytes_to_buffer(&mut cx, &key.serialize())?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+    }
+
+    pub class JsSignedPreKeyRecord for SignedPreKeyRecord {
+        init(mut cx) {
+            let serialized = bytes_argument(&mut cx, 0, "serialized")?;
+            match SignedPreKeyRecord::deserialize(&serialized) {
+                Ok(record) => Ok(record),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method serialize(mut cx) {
+            let bytes = borrow_this(&mut cx, |r| r.serialize());
+            match bytes {
+                Ok(bytes) => Ok(bytes_to_buffer(&mut cx, &bytes)?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method id(mut cx) {
+            let id = borrow_this(&mut cx, |r| r.id());
+            match id {
+                Ok(id) => Ok(cx.number(id).upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method timestamp(mut cx) {
+            let timestamp = borrow_this(&mut cx, |r| r.timestamp());
+            match timestamp {
+                Ok(timestamp) => Ok(cx.number(timestamp as f64).upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method signature(mut cx) {
+            let signature = borrow_this(&mut cx, |r| r.signature());
+            match signature {
+                Ok(signature) => Ok(bytes_to_buffer(&mut cx, &signature)?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method publicKey(mut cx) {
+            let key = borrow_this(&mut cx, |r| r.public_key());
+            match key {
+                Ok(key) => Ok(bytes_to_buffer(&mut cx, &key.serialize())?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method privateKey(mut cx) {
+            let key = borrow_this(&mut cx, |r| r.private_key());
+            match key {
+                Ok(key) => Ok(bytes_to_buffer(&mut cx, &key.serialize())?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+    }
+
+    pub class JsSignalMessage for SignalMessage {
+        init(mut cx) {
+            let serialized = bytes_argument(&mut cx, 0, "serialized")?;
+            match SignalMessage::try_from(&serialized[..]) {
+                Ok(message) => Ok(message),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method serialize(mut cx) {
+            let bytes = borrow_this(&mut cx, |m| m.serialized().to_vec());
+            Ok(bytes_to_buffer(&mut cx, &bytes)?.upcast())
+        }
+
+        method messageVersion(mut cx) {
+            let version = borrow_this(&mut cx, |m| m.message_version());
+            Ok(cx.number(version).upcast())
+        }
+
+        method counter(mut cx) {
+            let counter = borrow_this(&mut cx, |m| m.counter());
+            Ok(cx.number(counter).upcast())
+        }
+
+        method senderRatchetKey(mut cx) {
+            let key = borrow_this(&mut cx, |m| m.sender_ratchet_key().serialize());
+            Ok(bytes_to_buffer(&mut cx, &key)?.upcast())
+        }
+
+        method body(mut cx) {
+            let body = borrow_this(&mut cx, |m| m.body().to_vec());
+            Ok(bytes_to_buffer(&mut cx, &body)?.upcast())
+        }
+
+        method verifyMac(mut cx) {
+            let sender_identity_key = bytes_argument(&mut cx, 0, "senderIdentityKey")?;
+            let receiver_identity_key = bytes_argument(&mut cx, 1, "receiverIdentityKey")?;
+            let mac_key = bytes_argument(&mut cx, 2, "macKey")?;
+
+            let result = borrow_this(&mut cx, |m| {
+                let sender_identity_key = IdentityKey::decode(&sender_identity_key)?;
+                let receiver_identity_key = IdentityKey::decode(&receiver_identity_key)?;
+                m.verify_mac(&sender_identity_key, &receiver_identity_key, &mac_key)
+            });
+            match result {
+                Ok(valid) => Ok(cx.boolean(valid).upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+    }
+
+    pub class JsPreKeySignalMessage for PreKeySignalMessage {
+        init(mut cx) {
+            let serialized = bytes_argument(&mut cx, 0, "serialized")?;
+            match PreKeySignalMessage::try_from(&serialized[..]) {
+                Ok(message) => Ok(message),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method serialize(mut cx) {
+            let bytes = borrow_this(&mut cx, |m| m.serialized().to_vec());
+            Ok(bytes_to_buffer(&mut cx, &bytes)?.upcast())
+        }
+
+        method messageVersion(mut cx) {
+            let version = borrow_this(&mut cx, |m| m.message_version());
+            Ok(cx.number(version).upcast())
+        }
+
+        method registrationId(mut cx) {
+            let id = borrow_this(&mut cx, |m| u32::from(m.registration_id()));
+            Ok(cx.number(id).upcast())
+        }
+
+        method preKeyId(mut cx) {
+            match borrow_this(&mut cx, |m| m.pre_key_id()) {
+                Some(id) => Ok(cx.number(id).upcast()),
+                None => Ok(cx.null().upcast()),
+            }
+        }
+
+        method signedPreKeyId(mut cx) {
+            let id = borrow_this(&mut cx, |m| m.signed_pre_key_id());
+            Ok(cx.number(id).upcast())
+        }
+
+        method baseKey(mut cx) {
+            let key = borrow_this(&mut cx, |m| m.base_key().serialize());
+            Ok(bytes_to_buffer(&mut cx, &key)?.upcast())
+        }
+
+        method identityKey(mut cx) {
+            let key = borrow_this(&mut cx, |m| m.identity_key().serialize());
+            Ok(bytes_to_buffer(&mut cx, &key)?.upcast())
+        }
+    }
+
+    pub class JsDecryptionErrorMessage for DecryptionErrorMessage {
+        init(mut cx) {
+            let serialized = bytes_argument(&mut cx, 0, "serialized")?;
+            match DecryptionErrorMessage::try_from(&serialized[..]) {
+                Ok(message) => Ok(message),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method serialize(mut cx) {
+            let bytes = borrow_this(&mut cx, |m| m.serialized().to_vec());
+            Ok(bytes_to_buffer(&mut cx, &bytes)?.upcast())
+        }
+
+        method timestamp(mut cx) {
+            let timestamp = borrow_this(&mut cx, |m| m.timestamp());
+            Ok(cx.number(timestamp as f64).upcast())
+        }
+
+        method deviceId(mut cx) {
+            let device_id = borrow_this(&mut cx, |m| m.device_id());
+            Ok(cx.number(device_id).upcast())
+        }
+
+        method ratchetKey(mut cx) {
+            match borrow_this(&mut cx, |m| m.ratchet_key().map(|k| k.serialize())) {
+                Some(key) => Ok(bytes_to_buffer(&mut cx, &key)?.upcast()),
+                None => Ok(cx.null().upcast()),
+            }
+        }
+
+        method toPlaintextContent(mut cx) {
+            let bytes = borrow_this(&mut cx, |m| {
+                PlaintextContent::from(m.clone()).serialized().to_vec()
+            });
+            let bytes = bytes_to_buffer(&mut cx, &bytes)?;
+            Ok(JsPlaintextContent::new(&mut cx, vec![bytes])?.upcast())
+        }
+    }
+
+    pub class JsPlaintextContent for PlaintextContent {
+        init(mut cx) {
+            let serialized = bytes_argument(&mut cx, 0, "serialized")?;
+            match PlaintextContent::try_from(&serialized[..]) {
+                Ok(content) => Ok(content),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method serialize(mut cx) {
+            let bytes = borrow_this(&mut cx, |c| c.serialized().to_vec());
+            Ok(bytes_to_buffer(&mut cx, &bytes)?.upcast())
+        }
+
+        method body(mut cx) {
+            let body = borrow_this(&mut cx, |c| c.body().to_vec());
+            Ok(bytes_to_buffer(&mut cx, &body)?.upcast())
+        }
+    }
+
+    pub class JsSessionRecord for SessionRecord {
+        init(mut cx) {
+            let serialized = bytes_argument(&mut cx, 0, "serialized")?;
+            match SessionRecord::deserialize(&serialized) {
+                Ok(record) => Ok(record),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method localRegistrationId(mut cx) {
+            let id = borrow_this(&mut cx, |r| r.local_registration_id().map(u32::from));
+            match id {
+                Ok(id) => Ok(cx.number(id).upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method remoteRegistrationId(mut cx) {
+            let id = borrow_this(&mut cx, |r| r.remote_registration_id().map(u32::from));
+            match id {
+                Ok(id) => Ok(cx.number(id).upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method aliceBaseKey(mut cx) {
+            let key = borrow_this(&mut cx, |r| r.alice_base_key().map(|k| k.to_vec()));
+            match key {
+                Ok(key) => Ok(bytes_to_buffer(&mut cx, &key)?.upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method sessionVersion(mut cx) {
+            let version = borrow_this(&mut cx, |r| r.session_version());
+            match version {
+                Ok(version) => Ok(cx.number(version).upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method remoteIdentityKey(mut cx) {
+            let key = borrow_this(&mut cx, |r| r.remote_identity_key());
+            match key {
+                Ok(Some(key)) => Ok(bytes_to_buffer(&mut cx, &key.serialize())?.upcast()),
+                Ok(None) => Ok(cx.null().upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        // Debugging aids: these report counters and flags of the current state, never keys.
+
+        method debugHasUsableSenderChain(mut cx) {
+            let usable = borrow_this(&mut cx, |r| r.session_state()?.has_usable_sender_chain());
+            match usable {
+                Ok(usable) => Ok(cx.boolean(usable).upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method debugSenderChainCounter(mut cx) {
+            let counter = borrow_this(&mut cx, |r| r.session_state()?.sender_chain_counter());
+            match counter {
+                Ok(Some(counter)) => Ok(cx.number(counter).upcast()),
+                Ok(None) => Ok(cx.null().upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method debugReceiverChainCounter(mut cx) {
+            let their_ephemeral = bytes_argument(&mut cx, 0, "theirEphemeral")?;
+            let their_ephemeral = match PublicKey::deserialize(&their_ephemeral) {
+                Ok(key) => key,
+                Err(e) => return cx.throw_error(e.to_string()),
+            };
+            let counter = borrow_this(&mut cx, |r| {
+                r.session_state()?.receiver_chain_counter(&their_ephemeral)
             });
-            Ok(buffer.upcast())
+            match counter {
+                Ok(Some(counter)) => Ok(cx.number(counter).upcast()),
+                Ok(None) => Ok(cx.null().upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
+        }
+
+        method debugNumberOfReceiverChains(mut cx) {
+            let count = borrow_this(&mut cx, |r| r.session_state()?.number_of_receiver_chains());
+            match count {
+                Ok(count) => Ok(cx.number(count as f64).upcast()),
+                Err(e) => cx.throw_error(e.to_string()),
+            }
         }
     }
 }
 
-register_module!(mut cx, {
-    cx.export_class::<JsPrivateKey>("PrivateKey")?;
-    Ok(())
-});
+// Registers each class and function listed in exports.rs. The type annotations there are only
+// used by bin/gen_ts_decl.rs.
+macro_rules! node_exports {
+    (
+        classes {
+            $($class:ident = $js_class:ident { $($member:tt)* })*
+        }
+        functions {
+            $({ $($name:ident = $rust_fn:ident ( $($args:tt)* ) -> $ret:literal ;)* })*
+        }
+        async_functions {
+            $($async_name:ident = $async_rust_fn:ident ( $($async_args:tt)* ) -> $async_ret:literal ;)*
+        }
+    ) => {
+        register_module!(mut cx, {
+            $(cx.export_class::<$js_class>(stringify!($class))?;)*
+            $($(cx.export_function(stringify!($name), $rust_fn)?;)*)*
+            $(cx.export_function(stringify!($async_name), $async_rust_fn)?;)*
+            Ok(())
+        });
+    };
+}
+
+include!("exports.rs");
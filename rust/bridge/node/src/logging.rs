@@ -0,0 +1,94 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use neon::event::EventHandler;
+use neon::prelude::*;
+
+/// Forwards log records to a JS callback.
+///
+/// The callback is invoked on the JS thread; `log` only queues the call, so it never blocks the
+/// thread that produced the record.
+struct NodeLogger {
+    callback: EventHandler,
+}
+
+impl log::Log for NodeLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let level = record.level() as u32;
+        let target = record.target().to_string();
+        let file = record.file().map(str::to_string);
+        let line = record.line();
+        let message = record.args().to_string();
+
+        self.callback.schedule(move |cx| {
+            let file: Handle<JsValue> = match file {
+                Some(file) => cx.string(file).upcast(),
+                None => cx.null().upcast(),
+            };
+            let line: Handle<JsValue> = match line {
+                Some(line) => cx.number(line).upcast(),
+                None => cx.null().upcast(),
+            };
+            vec![
+                cx.number(level).upcast(),
+                cx.string(target).upcast(),
+                file,
+                line,
+                cx.string(message).upcast(),
+            ]
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+fn level_filter(level: f64) -> Option<log::LevelFilter> {
+    if level.fract() != 0.0 {
+        return None;
+    }
+    match level as i64 {
+        0 => Some(log::LevelFilter::Off),
+        1 => Some(log::LevelFilter::Error),
+        2 => Some(log::LevelFilter::Warn),
+        3 => Some(log::LevelFilter::Info),
+        4 => Some(log::LevelFilter::Debug),
+        5 => Some(log::LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// Installs `callback` (argument 1) as the logger for the Rust code, passing it records at or
+/// above `maxLevel` (argument 0). Can only be called once per process.
+pub fn init_logger(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let max_level = cx.argument::<JsNumber>(0)?.value();
+    let callback = cx.argument::<JsFunction>(1)?;
+
+    let max_level = match level_filter(max_level) {
+        Some(max_level) => max_level,
+        None => return cx.throw_range_error("log level must be an integer from 0 to 5"),
+    };
+
+    let this = cx.undefined();
+    let logger = NodeLogger {
+        callback: EventHandler::new(&cx, this, callback),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_err() {
+        return cx.throw_error("logger has already been initialized");
+    }
+    log::set_max_level(max_level);
+    log::info!(
+        "Initializing libsignal-client version {}",
+        env!("CARGO_PKG_VERSION")
+    );
+    Ok(cx.undefined())
+}
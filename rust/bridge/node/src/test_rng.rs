@@ -0,0 +1,105 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use neon::object::This;
+use neon::prelude::*;
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+
+/// The state behind a JS `TestRng`.
+#[cfg(feature = "test-rng")]
+pub struct NodeTestRng(libsignal_protocol_rust::TestRng);
+
+/// Without the test-rng feature, a `TestRng` can never be created.
+#[cfg(not(feature = "test-rng"))]
+pub enum NodeTestRng {}
+
+impl NodeTestRng {
+    #[cfg(feature = "test-rng")]
+    fn new<'a>(_cx: &mut impl Context<'a>, seed: u64) -> NeonResult<Self> {
+        Ok(Self(libsignal_protocol_rust::TestRng::new(seed)))
+    }
+
+    #[cfg(not(feature = "test-rng"))]
+    fn new<'a>(cx: &mut impl Context<'a>, _seed: u64) -> NeonResult<Self> {
+        cx.throw_error("TestRng is only available in builds with the test-rng feature")
+    }
+
+    #[cfg(feature = "test-rng")]
+    fn inner(&mut self) -> &mut dyn RngCore {
+        &mut self.0
+    }
+
+    #[cfg(not(feature = "test-rng"))]
+    fn inner(&mut self) -> &mut dyn RngCore {
+        match *self {}
+    }
+}
+
+/// The RNG for a call: OsRng, unless the caller passed a `TestRng`.
+pub enum NodeRng<'a> {
+    Os(OsRng),
+    Test(&'a mut NodeTestRng),
+}
+
+impl RngCore for NodeRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            NodeRng::Os(rng) => rng.next_u32(),
+            NodeRng::Test(rng) => rng.inner().next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            NodeRng::Os(rng) => rng.next_u64(),
+            NodeRng::Test(rng) => rng.inner().next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            NodeRng::Os(rng) => rng.fill_bytes(dest),
+            NodeRng::Test(rng) => rng.inner().fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            NodeRng::Os(rng) => rng.try_fill_bytes(dest),
+            NodeRng::Test(rng) => rng.inner().try_fill_bytes(dest),
+        }
+    }
+}
+
+impl CryptoRng for NodeRng<'_> {}
+
+/// Calls `f` with the `TestRng` passed as argument `i`, or with OsRng if that argument is
+/// missing or undefined.
+pub fn with_rng<T: This, R>(
+    cx: &mut CallContext<T>,
+    i: i32,
+    f: impl FnOnce(&mut NodeRng) -> R,
+) -> NeonResult<R> {
+    match cx.argument_opt(i) {
+        Some(rng) if !rng.is_a::<JsUndefined>() => {
+            let rng = rng.downcast_or_throw::<JsTestRng, _>(cx)?;
+            Ok(cx.borrow_mut(&rng, |mut rng| f(&mut NodeRng::Test(&mut **rng))))
+        }
+        _ => Ok(f(&mut NodeRng::Os(OsRng))),
+    }
+}
+
+declare_types! {
+    pub class JsTestRng for NodeTestRng {
+        init(mut cx) {
+            let seed = cx.argument::<JsNumber>(0)?.value();
+            if seed < 0.0 || seed.fract() != 0.0 || seed > 9007199254740991.0 {
+                return cx.throw_range_error("seed must be a non-negative safe integer");
+            }
+            NodeTestRng::new(&mut cx, seed as u64)
+        }
+    }
+}
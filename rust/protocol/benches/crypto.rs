@@ -0,0 +1,34 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use libsignal_protocol_rust::*;
+
+pub fn aes_256_cbc(c: &mut Criterion) {
+    let key = [0x42u8; 32];
+    let iv = [0x24u8; 16];
+    let ptext = vec![0x11u8; 1024];
+    let ctext = aes_256_cbc_encrypt(&ptext, &key, &iv).expect("success");
+
+    c.bench_function("aes-256-cbc encrypt 1KiB", |b| {
+        b.iter(|| aes_256_cbc_encrypt(&ptext, &key, &iv).expect("success"))
+    });
+    c.bench_function("aes-256-cbc encrypt 1KiB into reused buffer", |b| {
+        let mut out = Vec::new();
+        b.iter(|| aes_256_cbc_encrypt_into(&ptext, &key, &iv, &mut out).expect("success"))
+    });
+
+    c.bench_function("aes-256-cbc decrypt 1KiB", |b| {
+        b.iter(|| aes_256_cbc_decrypt(&ctext, &key, &iv).expect("success"))
+    });
+    c.bench_function("aes-256-cbc decrypt 1KiB into reused buffer", |b| {
+        let mut out = Vec::new();
+        b.iter(|| aes_256_cbc_decrypt_into(&ctext, &key, &iv, &mut out).expect("success"))
+    });
+}
+
+criterion_group!(benches, aes_256_cbc);
+
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use libsignal_protocol_rust::*;
+use rand::rngs::OsRng;
+
+pub fn signature_verification(c: &mut Criterion) {
+    let mut csprng = OsRng;
+
+    let key_pairs: Vec<KeyPair> = (0..128).map(|_| KeyPair::generate(&mut csprng)).collect();
+    let message = [0x42u8; 128];
+    let signatures: Vec<Box<[u8]>> = key_pairs
+        .iter()
+        .map(|kp| {
+            kp.calculate_signature(&message, &mut csprng)
+                .expect("success")
+        })
+        .collect();
+    let items: Vec<(&PublicKey, &[u8], &[u8])> = key_pairs
+        .iter()
+        .zip(&signatures)
+        .map(|(kp, sig)| (&kp.public_key, &message[..], &sig[..]))
+        .collect();
+
+    c.bench_function("verify 128 signatures sequentially", |b| {
+        b.iter(|| {
+            for (key, message, signature) in &items {
+                assert!(key.verify_signature(message, signature).expect("success"));
+            }
+        })
+    });
+    c.bench_function("verify 128 signatures in a batch", |b| {
+        b.iter(|| {
+            let results = verify_signatures_batch(&items, &mut csprng);
+            assert!(results.iter().all(|valid| *valid));
+        })
+    });
+}
+
+criterion_group!(benches, signature_verification);
+
+criterion_main!(benches);
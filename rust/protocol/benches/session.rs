@@ -14,8 +14,8 @@ pub fn session_encrypt_result(c: &mut Criterion) -> Result<(), SignalProtocolErr
     let alice_session_record = SessionRecord::new(alice_session);
     let bob_session_record = SessionRecord::new(bob_session);
 
-    let alice_address = ProtocolAddress::new("+14159999999".to_owned(), 1);
-    let bob_address = ProtocolAddress::new("+14158888888".to_owned(), 1);
+    let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+    let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
 
     let mut alice_store = support::test_in_memory_protocol_store();
     let mut bob_store = support::test_in_memory_protocol_store();
@@ -55,8 +55,8 @@ pub fn session_encrypt_decrypt_result(c: &mut Criterion) -> Result<(), SignalPro
     let alice_session_record = SessionRecord::new(alice_session);
     let bob_session_record = SessionRecord::new(bob_session);
 
-    let alice_address = ProtocolAddress::new("+14159999999".to_owned(), 1);
-    let bob_address = ProtocolAddress::new("+14158888888".to_owned(), 1);
+    let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+    let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
 
     let mut alice_store = support::test_in_memory_protocol_store();
     let mut bob_store = support::test_in_memory_protocol_store();
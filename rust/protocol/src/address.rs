@@ -3,26 +3,179 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use crate::error::{Result, SignalProtocolError};
+use std::convert::TryFrom;
 use std::fmt;
+use uuid::Uuid;
+
+/// A device identifier, guaranteed to be in the range 1 to 127 inclusive.
+///
+/// The upper bound matches the encoding constraints of sealed sender, which carries device ids
+/// in a single byte.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub struct DeviceId(u8);
+
+impl DeviceId {
+    pub const MIN: u32 = 1;
+    pub const MAX: u32 = 127;
+}
+
+impl TryFrom<u32> for DeviceId {
+    type Error = SignalProtocolError;
+
+    fn try_from(value: u32) -> Result<Self> {
+        if !(Self::MIN..=Self::MAX).contains(&value) {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "device id {} out of range",
+                value
+            )));
+        }
+        Ok(DeviceId(value as u8))
+    }
+}
+
+impl From<DeviceId> for u32 {
+    fn from(value: DeviceId) -> Self {
+        value.0.into()
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The length of a [ServiceId] in its fixed-width binary encoding.
+pub const SERVICE_ID_BINARY_LEN: usize = 17;
+
+const PNI_STRING_PREFIX: &str = "PNI:";
+const ACI_STRING_PREFIX: &str = "ACI:";
+
+/// An account identifier, tagged with the kind of identity it belongs to.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub enum ServiceId {
+    /// An account's primary identity.
+    Aci(Uuid),
+    /// An account's phone number identity.
+    Pni(Uuid),
+}
+
+impl ServiceId {
+    pub fn uuid(&self) -> Uuid {
+        match self {
+            ServiceId::Aci(uuid) | ServiceId::Pni(uuid) => *uuid,
+        }
+    }
+
+    fn kind_byte(&self) -> u8 {
+        match self {
+            ServiceId::Aci(_) => 0x00,
+            ServiceId::Pni(_) => 0x01,
+        }
+    }
+
+    /// Returns the canonical string form.
+    ///
+    /// ACIs are written as a bare lowercase UUID, so that existing session and identity records
+    /// keyed by an ACI remain valid; PNIs are prefixed with `PNI:`.
+    pub fn service_id_string(&self) -> String {
+        match self {
+            ServiceId::Aci(uuid) => uuid.to_hyphenated_ref().to_string(),
+            ServiceId::Pni(uuid) => {
+                format!("{}{}", PNI_STRING_PREFIX, uuid.to_hyphenated_ref())
+            }
+        }
+    }
+
+    /// Returns the fixed-width binary form: a kind byte (0 for ACI, 1 for PNI) followed by the
+    /// 16 bytes of the UUID.
+    pub fn service_id_binary(&self) -> [u8; SERVICE_ID_BINARY_LEN] {
+        let mut result = [0u8; SERVICE_ID_BINARY_LEN];
+        result[0] = self.kind_byte();
+        result[1..].copy_from_slice(self.uuid().as_bytes());
+        result
+    }
+
+    /// Parses the canonical string form. An explicit `ACI:` prefix is also accepted.
+    pub fn parse_from_service_id_string(input: &str) -> Result<Self> {
+        let parse_uuid = |s: &str| {
+            // Only the hyphenated form is accepted; Uuid::parse_str also allows other layouts.
+            if s.len() != 36 {
+                return Err(SignalProtocolError::InvalidArgument(format!(
+                    "invalid service ID {:?}",
+                    input
+                )));
+            }
+            Uuid::parse_str(s).map_err(|_| {
+                SignalProtocolError::InvalidArgument(format!("invalid service ID {:?}", input))
+            })
+        };
+
+        if let Some(uuid) = input.strip_prefix(PNI_STRING_PREFIX) {
+            Ok(ServiceId::Pni(parse_uuid(uuid)?))
+        } else if let Some(uuid) = input.strip_prefix(ACI_STRING_PREFIX) {
+            Ok(ServiceId::Aci(parse_uuid(uuid)?))
+        } else {
+            Ok(ServiceId::Aci(parse_uuid(input)?))
+        }
+    }
+
+    /// Parses the fixed-width binary form produced by [ServiceId::service_id_binary].
+    pub fn parse_from_service_id_binary(input: &[u8]) -> Result<Self> {
+        if input.len() != SERVICE_ID_BINARY_LEN {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "service ID must be {} bytes, got {}",
+                SERVICE_ID_BINARY_LEN,
+                input.len()
+            )));
+        }
+        let uuid = Uuid::from_slice(&input[1..]).expect("correct length");
+        match input[0] {
+            0x00 => Ok(ServiceId::Aci(uuid)),
+            0x01 => Ok(ServiceId::Pni(uuid)),
+            kind => Err(SignalProtocolError::InvalidArgument(format!(
+                "unknown service ID kind {}",
+                kind
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ServiceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.service_id_string())
+    }
+}
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
 pub struct ProtocolAddress {
     name: String,
-    device_id: u32,
+    device_id: DeviceId,
 }
 
 impl ProtocolAddress {
-    pub fn new(name: String, device_id: u32) -> Self {
+    pub fn new(name: String, device_id: DeviceId) -> Self {
         ProtocolAddress { name, device_id }
     }
 
+    /// Creates an address whose name is the canonical string form of `service_id`.
+    pub fn from_service_id(service_id: ServiceId, device_id: DeviceId) -> Self {
+        ProtocolAddress::new(service_id.service_id_string(), device_id)
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
 
-    pub fn device_id(&self) -> u32 {
+    pub fn device_id(&self) -> DeviceId {
         self.device_id
     }
+
+    /// Interprets the name as a service ID, if it is one.
+    pub fn service_id(&self) -> Option<ServiceId> {
+        ServiceId::parse_from_service_id_string(&self.name).ok()
+    }
 }
 
 impl fmt::Display for ProtocolAddress {
@@ -30,3 +183,104 @@ impl fmt::Display for ProtocolAddress {
         write!(f, "{} {}", self.name, self.device_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "9d0652a3-dcc3-4d11-975f-74d61598733f";
+
+    #[test]
+    fn service_id_encodings() {
+        let uuid = Uuid::parse_str(UUID).unwrap();
+        let aci = ServiceId::Aci(uuid);
+        let pni = ServiceId::Pni(uuid);
+
+        assert_eq!(aci.service_id_string(), UUID);
+        assert_eq!(pni.service_id_string(), format!("PNI:{}", UUID));
+        assert_eq!(
+            hex::encode(&aci.service_id_binary()[..]),
+            "009d0652a3dcc34d11975f74d61598733f"
+        );
+        assert_eq!(
+            hex::encode(&pni.service_id_binary()[..]),
+            "019d0652a3dcc34d11975f74d61598733f"
+        );
+
+        for id in &[aci, pni] {
+            assert_eq!(
+                ServiceId::parse_from_service_id_string(&id.service_id_string()).unwrap(),
+                *id
+            );
+            assert_eq!(
+                ServiceId::parse_from_service_id_binary(&id.service_id_binary()).unwrap(),
+                *id
+            );
+        }
+        assert_eq!(
+            ServiceId::parse_from_service_id_string(&format!("ACI:{}", UUID)).unwrap(),
+            aci
+        );
+        assert_eq!(
+            ServiceId::parse_from_service_id_string(&UUID.to_uppercase()).unwrap(),
+            aci
+        );
+    }
+
+    #[test]
+    fn service_id_rejects_garbage() {
+        for bad in &[
+            "",
+            "PNI:",
+            "+14152222222",
+            "9d0652a3-dcc3-4d11-975f-74d61598733",
+            "9d0652a3dcc34d11975f74d61598733f",
+            "PNI:9d0652a3-dcc3-4d11-975f-74d61598733g",
+            "XYZ:9d0652a3-dcc3-4d11-975f-74d61598733f",
+        ] {
+            assert!(
+                matches!(
+                    ServiceId::parse_from_service_id_string(bad),
+                    Err(SignalProtocolError::InvalidArgument(_))
+                ),
+                "{:?}",
+                bad
+            );
+        }
+
+        let mut binary = ServiceId::Aci(Uuid::nil()).service_id_binary();
+        assert!(ServiceId::parse_from_service_id_binary(&binary[1..]).is_err());
+        binary[0] = 0x02;
+        assert!(ServiceId::parse_from_service_id_binary(&binary).is_err());
+    }
+
+    #[test]
+    fn device_id_range() {
+        for bad in &[0, 128, u32::MAX] {
+            assert!(matches!(
+                DeviceId::try_from(*bad),
+                Err(SignalProtocolError::InvalidArgument(_))
+            ));
+        }
+        for good in &[1, 2, 127] {
+            let device_id = DeviceId::try_from(*good).unwrap();
+            assert_eq!(u32::from(device_id), *good);
+            assert_eq!(device_id.to_string(), good.to_string());
+        }
+    }
+
+    #[test]
+    fn protocol_address_service_id() {
+        let pni = ServiceId::Pni(Uuid::parse_str(UUID).unwrap());
+        let device_id = DeviceId::try_from(2).unwrap();
+        let address = ProtocolAddress::from_service_id(pni, device_id);
+        assert_eq!(address.name(), format!("PNI:{}", UUID));
+        assert_eq!(address.device_id(), device_id);
+        assert_eq!(address.service_id(), Some(pni));
+
+        assert_eq!(
+            ProtocolAddress::new("+14152222222".to_string(), device_id).service_id(),
+            None
+        );
+    }
+}
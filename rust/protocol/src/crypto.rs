@@ -3,25 +3,160 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use crate::kdf::HKDF;
 use crate::{error::Result, SignalProtocolError};
 
 use aes::Aes256;
-use block_modes::{block_padding::Pkcs7, BlockMode, Cbc};
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use aes_gcm_siv::Aes256GcmSiv;
+use block_modes::block_padding::{Padding, Pkcs7};
+use block_modes::{BlockMode, Cbc};
+use ctr::cipher::stream::{NewStreamCipher, SyncStreamCipher};
+use ctr::Ctr128;
 use hmac::{Hmac, Mac, NewMac};
-use sha2::Sha256;
+use sha2::{Digest, Sha256, Sha512};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+use std::fmt;
+use std::ops::Deref;
+
+/// Owned secret bytes which are zeroized when dropped.
+///
+/// Cloning produces an independent copy which is itself zeroized on drop.
+pub(crate) struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    /// Reserve enough space up front so that extending never reallocates (and so never leaves
+    /// an unzeroized copy of the contents behind).
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self(Vec::with_capacity(capacity))
+    }
+
+    pub(crate) fn extend_from_slice(&mut self, bytes: &[u8]) {
+        if self.0.len() + bytes.len() > self.0.capacity() {
+            let mut larger = Vec::with_capacity(self.0.len() + bytes.len());
+            larger.extend_from_slice(&self.0);
+            self.0.zeroize();
+            self.0 = larger;
+        }
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Clone for SecretBytes {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl Zeroize for SecretBytes {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBytes({} bytes)", self.0.len())
+    }
+}
+
+impl From<Vec<u8>> for SecretBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Box<[u8]>> for SecretBytes {
+    fn from(bytes: Box<[u8]>) -> Self {
+        Self(bytes.into_vec())
+    }
+}
+
+impl From<[u8; 32]> for SecretBytes {
+    fn from(mut bytes: [u8; 32]) -> Self {
+        let result = Self(bytes.to_vec());
+        bytes.zeroize();
+        result
+    }
+}
 
 pub fn aes_256_cbc_encrypt(ptext: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>> {
-    match Cbc::<Aes256, Pkcs7>::new_var(key, iv) {
-        Ok(mode) => Ok(mode.encrypt_vec(&ptext)),
-        Err(block_modes::InvalidKeyIvLength) => Err(
-            SignalProtocolError::InvalidCipherCryptographicParameters(key.len(), iv.len()),
-        ),
+    let mut ctext = Vec::new();
+    aes_256_cbc_encrypt_into(ptext, key, iv, &mut ctext)?;
+    Ok(ctext)
+}
+
+/// Like [`aes_256_cbc_encrypt`], but writes the ciphertext into `out`, reusing its allocation.
+///
+/// Any previous contents of `out` are discarded. Returns the length of the ciphertext.
+pub fn aes_256_cbc_encrypt_into(
+    ptext: &[u8],
+    key: &[u8],
+    iv: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<usize> {
+    let mode = match Cbc::<Aes256, Pkcs7>::new_var(key, iv) {
+        Ok(mode) => mode,
+        Err(block_modes::InvalidKeyIvLength) => {
+            return Err(SignalProtocolError::InvalidCipherCryptographicParameters(
+                key.len(),
+                iv.len(),
+            ))
+        }
+    };
+
+    let padded_len = (ptext.len() / AES_BLOCK_SIZE + 1) * AES_BLOCK_SIZE;
+    out.clear();
+    out.reserve(padded_len);
+    out.extend_from_slice(ptext);
+    out.resize(padded_len, 0);
+
+    match mode.encrypt(out, ptext.len()) {
+        Ok(ctext) => Ok(ctext.len()),
+        Err(_) => Err(SignalProtocolError::InternalError(
+            "AES-256-CBC padding buffer was too small",
+        )),
     }
 }
 
 pub fn aes_256_cbc_decrypt(ctext: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>> {
+    let mut ptext = Vec::new();
+    aes_256_cbc_decrypt_into(ctext, key, iv, &mut ptext)?;
+    Ok(ptext)
+}
+
+/// Like [`aes_256_cbc_decrypt`], but writes the plaintext into `out`, reusing its allocation.
+///
+/// Any previous contents of `out` are zeroized and discarded, since they may be an earlier
+/// plaintext; on error `out` is left empty. Returns the length of the plaintext.
+pub fn aes_256_cbc_decrypt_into(
+    ctext: &[u8],
+    key: &[u8],
+    iv: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<usize> {
+    out.zeroize();
+
     if ctext.is_empty() || ctext.len() % 16 != 0 {
-        return Err(SignalProtocolError::InvalidCiphertext);
+        return Err(SignalProtocolError::InvalidCiphertextLength(ctext.len()));
     }
 
     let mode = match Cbc::<Aes256, Pkcs7>::new_var(key, iv) {
@@ -34,9 +169,289 @@ pub fn aes_256_cbc_decrypt(ctext: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8
         }
     };
 
-    Ok(mode
-        .decrypt_vec(ctext)
-        .map_err(|_| SignalProtocolError::InvalidCiphertext)?)
+    out.extend_from_slice(ctext);
+    match mode.decrypt(out) {
+        Ok(ptext) => {
+            let ptext_len = ptext.len();
+            out.truncate(ptext_len);
+            Ok(ptext_len)
+        }
+        Err(_) => {
+            out.zeroize();
+            Err(SignalProtocolError::InvalidCiphertextPadding)
+        }
+    }
+}
+
+const AES_BLOCK_SIZE: usize = 16;
+
+/// Incremental AES-256-CBC encryption with PKCS7 padding.
+///
+/// Produces the same output as [`aes_256_cbc_encrypt`] without requiring the whole plaintext to
+/// be in memory at once. Input which does not fill a whole block is buffered until the next call
+/// to `update` or `finalize`.
+pub struct Aes256CbcEncryptStream {
+    mode: Cbc<Aes256, Pkcs7>,
+    buffer: Vec<u8>,
+}
+
+impl Aes256CbcEncryptStream {
+    pub fn new(key: &[u8], iv: &[u8]) -> Result<Self> {
+        Ok(Self {
+            mode: aes_256_cbc_mode(key, iv)?,
+            buffer: Vec::with_capacity(AES_BLOCK_SIZE),
+        })
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.buffer.extend_from_slice(chunk);
+        let complete_len = self.buffer.len() - self.buffer.len() % AES_BLOCK_SIZE;
+        let mut output: Vec<u8> = self.buffer.drain(..complete_len).collect();
+        encrypt_blocks(&mut self.mode, &mut output);
+        output
+    }
+
+    pub fn finalize(mut self) -> Vec<u8> {
+        let pad_len = AES_BLOCK_SIZE - self.buffer.len();
+        self.buffer.resize(AES_BLOCK_SIZE, pad_len as u8);
+        let mut output = std::mem::take(&mut self.buffer);
+        encrypt_blocks(&mut self.mode, &mut output);
+        output
+    }
+}
+
+impl Drop for Aes256CbcEncryptStream {
+    fn drop(&mut self) {
+        self.buffer.zeroize();
+    }
+}
+
+/// Incremental AES-256-CBC decryption with PKCS7 padding.
+///
+/// The last block of ciphertext is always held back until `finalize`, since only then is it
+/// known to be the block carrying the padding. Truncated ciphertexts and padding errors are
+/// reported by `finalize`, with the same errors as [`aes_256_cbc_decrypt`].
+pub struct Aes256CbcDecryptStream {
+    mode: Cbc<Aes256, Pkcs7>,
+    buffer: Vec<u8>,
+    ctext_len: usize,
+}
+
+impl Aes256CbcDecryptStream {
+    pub fn new(key: &[u8], iv: &[u8]) -> Result<Self> {
+        Ok(Self {
+            mode: aes_256_cbc_mode(key, iv)?,
+            buffer: Vec::with_capacity(2 * AES_BLOCK_SIZE),
+            ctext_len: 0,
+        })
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.ctext_len += chunk.len();
+        self.buffer.extend_from_slice(chunk);
+        // Keep between 1 and 16 bytes buffered so the final block is never released early.
+        let ready_len = self.buffer.len().saturating_sub(1) / AES_BLOCK_SIZE * AES_BLOCK_SIZE;
+        let mut output: Vec<u8> = self.buffer.drain(..ready_len).collect();
+        decrypt_blocks(&mut self.mode, &mut output);
+        output
+    }
+
+    pub fn finalize(mut self) -> Result<Vec<u8>> {
+        if self.buffer.len() != AES_BLOCK_SIZE {
+            return Err(SignalProtocolError::InvalidCiphertextLength(self.ctext_len));
+        }
+
+        let mut last_block = std::mem::take(&mut self.buffer);
+        decrypt_blocks(&mut self.mode, &mut last_block);
+
+        let result = match Pkcs7::unpad(&last_block) {
+            Ok(ptext) => Ok(ptext.to_vec()),
+            Err(_) => Err(SignalProtocolError::InvalidCiphertextPadding),
+        };
+        last_block.zeroize();
+        result
+    }
+}
+
+fn aes_256_cbc_mode(key: &[u8], iv: &[u8]) -> Result<Cbc<Aes256, Pkcs7>> {
+    Cbc::<Aes256, Pkcs7>::new_var(key, iv).map_err(|block_modes::InvalidKeyIvLength| {
+        SignalProtocolError::InvalidCipherCryptographicParameters(key.len(), iv.len())
+    })
+}
+
+fn encrypt_blocks(mode: &mut Cbc<Aes256, Pkcs7>, buf: &mut [u8]) {
+    for block in buf.chunks_exact_mut(AES_BLOCK_SIZE) {
+        mode.encrypt_blocks(std::slice::from_mut(GenericArray::from_mut_slice(block)));
+    }
+}
+
+fn decrypt_blocks(mode: &mut Cbc<Aes256, Pkcs7>, buf: &mut [u8]) {
+    for block in buf.chunks_exact_mut(AES_BLOCK_SIZE) {
+        mode.decrypt_blocks(std::slice::from_mut(GenericArray::from_mut_slice(block)));
+    }
+}
+
+/// Encrypts with AES-256-CBC and appends the first `mac_len` bytes of an HMAC-SHA256 computed
+/// over the ciphertext.
+pub fn aes_256_cbc_encrypt_then_mac(
+    ptext: &[u8],
+    cipher_key: &[u8],
+    mac_key: &[u8],
+    iv: &[u8],
+    mac_len: usize,
+) -> Result<Vec<u8>> {
+    check_truncated_mac_length(mac_len)?;
+
+    let mut ctext = aes_256_cbc_encrypt(ptext, cipher_key, iv)?;
+    let mac = hmac_sha256(mac_key, &ctext)?;
+    ctext.extend_from_slice(&mac[..mac_len]);
+    Ok(ctext)
+}
+
+/// Checks the trailing `mac_len` byte tag produced by [`aes_256_cbc_encrypt_then_mac`] and
+/// only decrypts if it matches.
+pub fn aes_256_cbc_verify_then_decrypt(
+    ctext_and_mac: &[u8],
+    cipher_key: &[u8],
+    mac_key: &[u8],
+    iv: &[u8],
+    mac_len: usize,
+) -> Result<Vec<u8>> {
+    check_truncated_mac_length(mac_len)?;
+
+    if ctext_and_mac.len() < mac_len {
+        return Err(SignalProtocolError::InvalidCiphertext);
+    }
+
+    let (ctext, their_mac) = ctext_and_mac.split_at(ctext_and_mac.len() - mac_len);
+    hmac_sha256_verify(mac_key, ctext, their_mac)?;
+
+    aes_256_cbc_decrypt(ctext, cipher_key, iv)
+}
+
+fn check_truncated_mac_length(mac_len: usize) -> Result<()> {
+    if mac_len == 0 || mac_len > 32 {
+        return Err(SignalProtocolError::InvalidArgument(format!(
+            "HMAC-SHA256 tag length must be between 1 and 32 bytes, got {}",
+            mac_len
+        )));
+    }
+    Ok(())
+}
+
+const AES_256_GCM_NONCE_LEN: usize = 12;
+const AES_256_GCM_TAG_LEN: usize = 16;
+
+/// AES-256-GCM with a 12-byte nonce. The 16-byte tag is appended to the returned ciphertext.
+pub fn aes_256_gcm_encrypt(ptext: &[u8], key: &[u8], nonce: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let cipher = aes_256_gcm_cipher(key, nonce)?;
+    cipher
+        .encrypt(GenericArray::from_slice(nonce), Payload { msg: ptext, aad })
+        .map_err(|_| SignalProtocolError::InternalError("AES-256-GCM encryption failed"))
+}
+
+pub fn aes_256_gcm_decrypt(ctext: &[u8], key: &[u8], nonce: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let cipher = aes_256_gcm_cipher(key, nonce)?;
+
+    if ctext.len() < AES_256_GCM_TAG_LEN {
+        return Err(SignalProtocolError::InvalidCiphertext);
+    }
+
+    cipher
+        .decrypt(GenericArray::from_slice(nonce), Payload { msg: ctext, aad })
+        .map_err(|_| SignalProtocolError::InvalidCiphertext)
+}
+
+fn aes_256_gcm_cipher(key: &[u8], nonce: &[u8]) -> Result<Aes256Gcm> {
+    if nonce.len() != AES_256_GCM_NONCE_LEN {
+        return Err(SignalProtocolError::InvalidCipherCryptographicParameters(
+            key.len(),
+            nonce.len(),
+        ));
+    }
+
+    Aes256Gcm::new_varkey(key).map_err(|_| {
+        SignalProtocolError::InvalidCipherCryptographicParameters(key.len(), nonce.len())
+    })
+}
+
+/// AES-256-GCM-SIV (RFC 8452) with a 12-byte nonce. The 16-byte tag is appended to the returned
+/// ciphertext.
+pub fn aes_256_gcm_siv_encrypt(
+    ptext: &[u8],
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    let cipher = Aes256GcmSiv::new(key).map_err(|e| aes_gcm_siv_error(e, key, nonce))?;
+
+    let mut buf = ptext.to_vec();
+    let tag = cipher
+        .encrypt(&mut buf, nonce, aad)
+        .map_err(|e| aes_gcm_siv_error(e, key, nonce))?;
+    buf.extend_from_slice(&tag);
+    Ok(buf)
+}
+
+pub fn aes_256_gcm_siv_decrypt(
+    ctext: &[u8],
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    let cipher = Aes256GcmSiv::new(key).map_err(|e| aes_gcm_siv_error(e, key, nonce))?;
+
+    if ctext.len() < AES_256_GCM_TAG_LEN {
+        return Err(SignalProtocolError::InvalidCiphertext);
+    }
+
+    let (ctext, tag) = ctext.split_at(ctext.len() - AES_256_GCM_TAG_LEN);
+    let mut buf = ctext.to_vec();
+    cipher
+        .decrypt(&mut buf, nonce, aad, tag)
+        .map_err(|e| aes_gcm_siv_error(e, key, nonce))?;
+    Ok(buf)
+}
+
+fn aes_gcm_siv_error(err: aes_gcm_siv::Error, key: &[u8], nonce: &[u8]) -> SignalProtocolError {
+    match err {
+        aes_gcm_siv::Error::InvalidKeySize | aes_gcm_siv::Error::InvalidNonceSize => {
+            SignalProtocolError::InvalidCipherCryptographicParameters(key.len(), nonce.len())
+        }
+        aes_gcm_siv::Error::InvalidTag => SignalProtocolError::InvalidCiphertext,
+        aes_gcm_siv::Error::InvalidInputSize => {
+            SignalProtocolError::InvalidArgument("input too large for AES-256-GCM-SIV".to_string())
+        }
+        aes_gcm_siv::Error::InvalidOutputBuffer | aes_gcm_siv::Error::CpuidFailure => {
+            SignalProtocolError::InternalError("AES-256-GCM-SIV failure")
+        }
+    }
+}
+
+/// AES-256 in CTR mode. The nonce is the full 16-byte initial counter block; a 12-byte nonce
+/// with an implicit counter is not accepted and is rejected like any other bad length.
+pub fn aes_256_ctr_encrypt(ptext: &[u8], key: &[u8], nonce: &[u8]) -> Result<Vec<u8>> {
+    aes_256_ctr_apply_keystream(ptext, key, nonce)
+}
+
+pub fn aes_256_ctr_decrypt(ctext: &[u8], key: &[u8], nonce: &[u8]) -> Result<Vec<u8>> {
+    aes_256_ctr_apply_keystream(ctext, key, nonce)
+}
+
+fn aes_256_ctr_apply_keystream(input: &[u8], key: &[u8], nonce: &[u8]) -> Result<Vec<u8>> {
+    let mut mode = match Ctr128::<Aes256>::new_var(key, nonce) {
+        Ok(mode) => mode,
+        Err(ctr::cipher::stream::InvalidKeyNonceLength) => {
+            return Err(SignalProtocolError::InvalidCipherCryptographicParameters(
+                key.len(),
+                nonce.len(),
+            ))
+        }
+    };
+
+    let mut output = input.to_vec();
+    mode.apply_keystream(&mut output);
+    Ok(output)
 }
 
 pub fn hmac_sha256(key: &[u8], input: &[u8]) -> Result<[u8; 32]> {
@@ -45,6 +460,123 @@ pub fn hmac_sha256(key: &[u8], input: &[u8]) -> Result<[u8; 32]> {
     Ok(hmac.finalize().into_bytes().into())
 }
 
+pub fn sha512(input: &[u8]) -> [u8; 64] {
+    let mut digest = [0u8; 64];
+    digest.copy_from_slice(&Sha512::digest(input));
+    digest
+}
+
+pub fn hmac_sha512(key: &[u8], input: &[u8]) -> Result<[u8; 64]> {
+    let mut hmac = Hmac::<Sha512>::new_varkey(key).expect("HMAC-SHA512 should accept any size key");
+    hmac.update(input);
+    let mut tag = [0u8; 64];
+    tag.copy_from_slice(&hmac.finalize().into_bytes());
+    Ok(tag)
+}
+
+/// Computes HMAC-SHA256 over `input` and compares it in constant time with `expected_tag`.
+///
+/// `expected_tag` may be a truncated tag of 1 to 32 bytes, in which case only that prefix of the
+/// MAC is compared; callers must take the tag length from the message format, never from the
+/// untrusted input itself.
+pub fn hmac_sha256_verify(key: &[u8], input: &[u8], expected_tag: &[u8]) -> Result<()> {
+    if expected_tag.is_empty() || expected_tag.len() > 32 {
+        return Err(SignalProtocolError::InvalidCiphertext);
+    }
+
+    let our_tag = hmac_sha256(key, input)?;
+    if bool::from(our_tag[..expected_tag.len()].ct_eq(expected_tag)) {
+        Ok(())
+    } else {
+        Err(SignalProtocolError::InvalidCiphertext)
+    }
+}
+
+/// RFC 5869 HKDF using SHA-256. If `salt` is `None` a string of zero bytes is used.
+///
+/// This is the protocol's own version 3 [HKDF], returning a `Vec`.
+pub fn hkdf_sha256(
+    ikm: &[u8],
+    salt: Option<&[u8]>,
+    info: &[u8],
+    output_len: usize,
+) -> Result<Vec<u8>> {
+    let hkdf = HKDF::new(3)?;
+    let okm = match salt {
+        Some(salt) => hkdf.derive_salted_secrets(ikm, salt, info, output_len)?,
+        None => hkdf.derive_secrets(ikm, info, output_len)?,
+    };
+    Ok(okm.into_vec())
+}
+
+/// The RFC 5869 HKDF-Expand step on its own, for callers that already hold a pseudorandom key.
+pub fn hkdf_expand_only(prk: &[u8], info: &[u8], output_len: usize) -> Result<Vec<u8>> {
+    Ok(HKDF::new(3)?.expand(prk, info, output_len)?.into_vec())
+}
+
+const COMMITTING_AEAD_VERSION: u8 = 1;
+const COMMITTING_AEAD_COMMITMENT_LEN: usize = 32;
+const COMMITTING_AEAD_KEY_LEN: usize = 32;
+const COMMITTING_AEAD_LABEL: &[u8] = b"Signal_Committing_AEAD_AES_256_GCM";
+
+/// AES-256-GCM which also commits to the key, so a ciphertext can only be decrypted under the key
+/// it was produced with.
+///
+/// The encryption key and a commitment value are derived from `key` with HKDF-SHA256. The output
+/// is `version || commitment || ciphertext || tag`, where `version` is currently 1.
+pub fn committing_aead_encrypt(
+    ptext: &[u8],
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    let (cipher_key, commitment) = committing_aead_derive_keys(key)?;
+    let ctext = aes_256_gcm_encrypt(ptext, &cipher_key, nonce, aad)?;
+
+    let mut result = Vec::with_capacity(1 + commitment.len() + ctext.len());
+    result.push(COMMITTING_AEAD_VERSION);
+    result.extend_from_slice(&commitment);
+    result.extend_from_slice(&ctext);
+    Ok(result)
+}
+
+/// Verifies the key commitment of a [`committing_aead_encrypt`] output before decrypting it.
+pub fn committing_aead_decrypt(
+    ctext: &[u8],
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    if ctext.len() < 1 + COMMITTING_AEAD_COMMITMENT_LEN + AES_256_GCM_TAG_LEN {
+        return Err(SignalProtocolError::CiphertextMessageTooShort(ctext.len()));
+    }
+    if ctext[0] != COMMITTING_AEAD_VERSION {
+        return Err(SignalProtocolError::UnrecognizedCiphertextVersion(ctext[0]));
+    }
+
+    let (their_commitment, ctext) = ctext[1..].split_at(COMMITTING_AEAD_COMMITMENT_LEN);
+    let (cipher_key, commitment) = committing_aead_derive_keys(key)?;
+
+    if !bool::from(commitment.ct_eq(their_commitment)) {
+        return Err(SignalProtocolError::InvalidCiphertext);
+    }
+
+    aes_256_gcm_decrypt(ctext, &cipher_key, nonce, aad)
+}
+
+fn committing_aead_derive_keys(key: &[u8]) -> Result<(SecretBytes, Vec<u8>)> {
+    let mut derived = SecretBytes::from(hkdf_sha256(
+        key,
+        None,
+        COMMITTING_AEAD_LABEL,
+        COMMITTING_AEAD_KEY_LEN + COMMITTING_AEAD_COMMITMENT_LEN,
+    )?);
+    let cipher_key = SecretBytes::from(derived[..COMMITTING_AEAD_KEY_LEN].to_vec());
+    let commitment = derived[COMMITTING_AEAD_KEY_LEN..].to_vec();
+    derived.zeroize();
+    Ok((cipher_key, commitment))
+}
+
 #[cfg(test)]
 mod test {
 
@@ -65,13 +597,567 @@ mod test {
         let recovered = super::aes_256_cbc_decrypt(&ctext, &key, &iv).unwrap();
         assert_eq!(hex::encode(ptext), hex::encode(recovered.clone()));
 
+        // length is invalid:
+        assert_eq!(
+            super::aes_256_cbc_decrypt(&recovered, &key, &iv),
+            Err(crate::SignalProtocolError::InvalidCiphertextLength(10))
+        );
+        assert_eq!(
+            super::aes_256_cbc_decrypt(&[], &key, &iv),
+            Err(crate::SignalProtocolError::InvalidCiphertextLength(0))
+        );
+
         // padding is invalid:
-        assert!(super::aes_256_cbc_decrypt(&recovered, &key, &iv).is_err());
-        assert!(super::aes_256_cbc_decrypt(&ctext, &key, &ctext).is_err());
+        assert_eq!(
+            super::aes_256_cbc_decrypt(&ctext, &key, &ctext),
+            Err(crate::SignalProtocolError::InvalidCiphertextPadding)
+        );
 
         // bitflip the IV to cause a change in the recovered text
         let bad_iv = hex::decode("ef8a557ddc0a140c878063a6d5f31d3d").unwrap();
         let recovered = super::aes_256_cbc_decrypt(&ctext, &key, &bad_iv).unwrap();
         assert_eq!(hex::encode(recovered), "b0736294a124482a4159");
     }
+
+    #[test]
+    fn secret_bytes_test() {
+        use super::SecretBytes;
+        use zeroize::Zeroize;
+
+        let mut secret = SecretBytes::from(vec![1u8, 2, 3, 4]);
+        assert_eq!(&*secret, &[1u8, 2, 3, 4]);
+
+        let copy = secret.clone();
+        secret.zeroize();
+        assert!(secret.iter().all(|&b| b == 0));
+        assert_eq!(&*copy, &[1u8, 2, 3, 4]);
+
+        let mut secret = SecretBytes::with_capacity(2);
+        secret.extend_from_slice(&[1, 2]);
+        secret.extend_from_slice(&[3, 4, 5]);
+        assert_eq!(&*secret, &[1u8, 2, 3, 4, 5]);
+
+        let boxed: Box<[u8]> = vec![5u8; 32].into_boxed_slice();
+        let secret = SecretBytes::from(boxed);
+        assert_eq!(secret.len(), 32);
+        assert_eq!(format!("{:?}", secret), "SecretBytes(32 bytes)");
+    }
+
+    #[test]
+    fn sha512_test() {
+        // FIPS 180-2 examples
+        assert_eq!(
+            hex::encode(&super::sha512(b"abc")[..]),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        assert_eq!(
+            hex::encode(
+                &super::sha512(
+                    b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmn\
+                  hijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu"
+                )[..]
+            ),
+            "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018\
+             501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909"
+        );
+        assert_eq!(
+            hex::encode(&super::sha512(b"")[..]),
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+             47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+        );
+    }
+
+    #[test]
+    fn hmac_sha512_test() {
+        // RFC 4231 test case 1
+        assert_eq!(
+            hex::encode(&super::hmac_sha512(&[0x0b; 20], b"Hi There").unwrap()[..]),
+            "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cde\
+             daa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854"
+        );
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex::encode(&super::hmac_sha512(b"Jefe", b"what do ya want for nothing?").unwrap()[..]),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+             9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        );
+    }
+
+    #[test]
+    fn hmac_sha256_verify_test() {
+        let key = [0x0bu8; 20];
+        let input = b"Hi There";
+        let tag = super::hmac_sha256(&key, input).unwrap();
+
+        // RFC 4231 test case 1
+        assert_eq!(
+            hex::encode(tag),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+
+        assert_eq!(super::hmac_sha256_verify(&key, input, &tag), Ok(()));
+        assert_eq!(super::hmac_sha256_verify(&key, input, &tag[..8]), Ok(()));
+
+        let mut bad_tag = tag;
+        bad_tag[31] ^= 0x80;
+        assert_eq!(
+            super::hmac_sha256_verify(&key, input, &bad_tag),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+        assert_eq!(
+            super::hmac_sha256_verify(&key, b"Hi there", &tag),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+
+        // wrong tag lengths
+        let mut long_tag = tag.to_vec();
+        long_tag.push(0);
+        assert_eq!(
+            super::hmac_sha256_verify(&key, input, &long_tag),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+        assert_eq!(
+            super::hmac_sha256_verify(&key, input, &[]),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+    }
+
+    #[test]
+    fn aes_cbc_hmac_test() {
+        let cipher_key =
+            hex::decode("4e22eb16d964779994222e82192ce9f747da72dc4abe49dfdeeb71d0ffe3796e")
+                .unwrap();
+        let mac_key = [0x42u8; 32];
+        let iv = hex::decode("6f8a557ddc0a140c878063a6d5f31d3d").unwrap();
+        let ptext = hex::decode("30736294a124482a4159").unwrap();
+
+        for &mac_len in &[8, 10, 32] {
+            let ctext =
+                super::aes_256_cbc_encrypt_then_mac(&ptext, &cipher_key, &mac_key, &iv, mac_len)
+                    .unwrap();
+            assert_eq!(ctext.len(), 16 + mac_len);
+            assert_eq!(
+                hex::encode(&ctext[..16]),
+                "dd3f573ab4508b9ed0e45e0baf5608f3"
+            );
+
+            let recovered =
+                super::aes_256_cbc_verify_then_decrypt(&ctext, &cipher_key, &mac_key, &iv, mac_len)
+                    .unwrap();
+            assert_eq!(recovered, ptext);
+
+            // any modification is caught by the MAC, including in the ciphertext itself
+            for i in 0..ctext.len() {
+                let mut bad_ctext = ctext.clone();
+                bad_ctext[i] ^= 0x01;
+                assert_eq!(
+                    super::aes_256_cbc_verify_then_decrypt(
+                        &bad_ctext,
+                        &cipher_key,
+                        &mac_key,
+                        &iv,
+                        mac_len
+                    ),
+                    Err(crate::SignalProtocolError::InvalidCiphertext)
+                );
+            }
+
+            assert_eq!(
+                super::aes_256_cbc_verify_then_decrypt(
+                    &ctext,
+                    &cipher_key,
+                    &[0x43u8; 32],
+                    &iv,
+                    mac_len
+                ),
+                Err(crate::SignalProtocolError::InvalidCiphertext)
+            );
+
+            // input shorter than the tag
+            assert_eq!(
+                super::aes_256_cbc_verify_then_decrypt(
+                    &ctext[..mac_len - 1],
+                    &cipher_key,
+                    &mac_key,
+                    &iv,
+                    mac_len
+                ),
+                Err(crate::SignalProtocolError::InvalidCiphertext)
+            );
+        }
+
+        assert!(matches!(
+            super::aes_256_cbc_encrypt_then_mac(&ptext, &cipher_key, &mac_key, &iv, 33),
+            Err(crate::SignalProtocolError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            super::aes_256_cbc_verify_then_decrypt(&ptext, &cipher_key, &mac_key, &iv, 0),
+            Err(crate::SignalProtocolError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn aes_cbc_into_test() {
+        let key = hex::decode("4e22eb16d964779994222e82192ce9f747da72dc4abe49dfdeeb71d0ffe3796e")
+            .unwrap();
+        let iv = hex::decode("6f8a557ddc0a140c878063a6d5f31d3d").unwrap();
+
+        let mut ctext = Vec::new();
+        let mut ptext = Vec::new();
+
+        for len in &[0, 10, 16, 100, 10] {
+            let input = vec![0x42u8; *len];
+            let ctext_len = super::aes_256_cbc_encrypt_into(&input, &key, &iv, &mut ctext).unwrap();
+            assert_eq!(ctext_len, ctext.len());
+            assert_eq!(
+                ctext,
+                super::aes_256_cbc_encrypt(&input, &key, &iv).unwrap()
+            );
+
+            let ptext_len = super::aes_256_cbc_decrypt_into(&ctext, &key, &iv, &mut ptext).unwrap();
+            assert_eq!(ptext_len, *len);
+            assert_eq!(ptext, input);
+        }
+
+        // buffers which are already large enough are not reallocated
+        let capacity = ptext.capacity();
+        super::aes_256_cbc_decrypt_into(&ctext, &key, &iv, &mut ptext).unwrap();
+        assert_eq!(ptext.capacity(), capacity);
+
+        // on error the output is cleared
+        ctext.truncate(ctext.len() - 1);
+        assert_eq!(
+            super::aes_256_cbc_decrypt_into(&ctext, &key, &iv, &mut ptext),
+            Err(crate::SignalProtocolError::InvalidCiphertextLength(15))
+        );
+        assert!(ptext.is_empty());
+    }
+
+    #[test]
+    fn aes_cbc_stream_test() {
+        let key = hex::decode("4e22eb16d964779994222e82192ce9f747da72dc4abe49dfdeeb71d0ffe3796e")
+            .unwrap();
+        let iv = hex::decode("6f8a557ddc0a140c878063a6d5f31d3d").unwrap();
+
+        for ptext_len in &[0, 1, 15, 16, 17, 31, 32, 100, 1000] {
+            let ptext: Vec<u8> = (0..*ptext_len).map(|i| (i % 251) as u8).collect();
+            let expected = super::aes_256_cbc_encrypt(&ptext, &key, &iv).unwrap();
+
+            for chunk_size in &[1, 3, 7, 15, 16, 17, 33, 1000] {
+                let mut enc = super::Aes256CbcEncryptStream::new(&key, &iv).unwrap();
+                let mut ctext = Vec::new();
+                for chunk in ptext.chunks(*chunk_size) {
+                    ctext.extend(enc.update(chunk));
+                }
+                ctext.extend(enc.finalize());
+                assert_eq!(hex::encode(&ctext), hex::encode(&expected));
+
+                let mut dec = super::Aes256CbcDecryptStream::new(&key, &iv).unwrap();
+                let mut recovered = Vec::new();
+                for chunk in ctext.chunks(*chunk_size) {
+                    recovered.extend(dec.update(chunk));
+                }
+                recovered.extend(dec.finalize().unwrap());
+                assert_eq!(hex::encode(&recovered), hex::encode(&ptext));
+            }
+        }
+    }
+
+    #[test]
+    fn aes_cbc_stream_errors_test() {
+        let key = hex::decode("4e22eb16d964779994222e82192ce9f747da72dc4abe49dfdeeb71d0ffe3796e")
+            .unwrap();
+        let iv = hex::decode("6f8a557ddc0a140c878063a6d5f31d3d").unwrap();
+        let ctext = super::aes_256_cbc_encrypt(&[0x42; 40], &key, &iv).unwrap();
+
+        // truncated mid-block
+        for len in &[0, 20, 47] {
+            let mut dec = super::Aes256CbcDecryptStream::new(&key, &iv).unwrap();
+            dec.update(&ctext[..*len]);
+            assert_eq!(
+                dec.finalize(),
+                Err(crate::SignalProtocolError::InvalidCiphertextLength(*len))
+            );
+        }
+
+        // truncated on a block boundary, so the last block no longer carries the padding
+        let mut dec = super::Aes256CbcDecryptStream::new(&key, &iv).unwrap();
+        dec.update(&ctext[..32]);
+        assert_eq!(
+            dec.finalize(),
+            Err(crate::SignalProtocolError::InvalidCiphertextPadding)
+        );
+
+        assert!(super::Aes256CbcEncryptStream::new(&key[..16], &iv).is_err());
+        assert!(super::Aes256CbcDecryptStream::new(&key, &iv[..8]).is_err());
+    }
+
+    #[test]
+    fn aes_gcm_test() {
+        // Test case 16 from "The Galois/Counter Mode of Operation (GCM)", McGrew and Viega
+        let key = hex::decode("feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308")
+            .unwrap();
+        let nonce = hex::decode("cafebabefacedbaddecaf888").unwrap();
+        let aad = hex::decode("feedfacedeadbeeffeedfacedeadbeefabaddad2").unwrap();
+        let ptext = hex::decode(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+        )
+        .unwrap();
+
+        let ctext = super::aes_256_gcm_encrypt(&ptext, &key, &nonce, &aad).unwrap();
+        assert_eq!(
+            hex::encode(&ctext),
+            "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+             8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662\
+             76fc6ece0f4e1768cddf8853bb2d551b"
+        );
+
+        let recovered = super::aes_256_gcm_decrypt(&ctext, &key, &nonce, &aad).unwrap();
+        assert_eq!(recovered, ptext);
+
+        // AAD mismatch
+        assert_eq!(
+            super::aes_256_gcm_decrypt(&ctext, &key, &nonce, &aad[1..]),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+
+        // modified tag
+        let mut bad_ctext = ctext.clone();
+        let last = bad_ctext.len() - 1;
+        bad_ctext[last] ^= 0x01;
+        assert_eq!(
+            super::aes_256_gcm_decrypt(&bad_ctext, &key, &nonce, &aad),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+
+        // shorter than the tag
+        assert_eq!(
+            super::aes_256_gcm_decrypt(&ctext[..15], &key, &nonce, &aad),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+        assert_eq!(
+            super::aes_256_gcm_decrypt(&[], &key, &nonce, &aad),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+
+        // bad parameters
+        assert_eq!(
+            super::aes_256_gcm_encrypt(&ptext, &key, &nonce[..8], &aad),
+            Err(crate::SignalProtocolError::InvalidCipherCryptographicParameters(32, 8))
+        );
+        assert_eq!(
+            super::aes_256_gcm_decrypt(&ctext, &key[..16], &nonce, &aad),
+            Err(crate::SignalProtocolError::InvalidCipherCryptographicParameters(16, 12))
+        );
+    }
+
+    #[test]
+    fn aes_gcm_siv_test() {
+        let kat_data = include_str!("../../aes-gcm-siv/tests/data/boringssl.txt");
+
+        for kat in kat_data.split("\n\n").filter(|kat| !kat.trim().is_empty()) {
+            let field = |name: &str| {
+                let prefix = format!("{}: ", name);
+                let line = kat
+                    .lines()
+                    .find(|line| line.starts_with(&prefix))
+                    .expect("field present");
+                hex::decode(&line[prefix.len()..]).expect("valid hex")
+            };
+
+            let key = field("KEY");
+            let nonce = field("NONCE");
+            let ptext = field("IN");
+            let aad = field("AD");
+            let mut expected = field("CT");
+            expected.extend(field("TAG"));
+
+            let ctext = super::aes_256_gcm_siv_encrypt(&ptext, &key, &nonce, &aad).unwrap();
+            assert_eq!(hex::encode(&ctext), hex::encode(&expected));
+
+            let recovered = super::aes_256_gcm_siv_decrypt(&ctext, &key, &nonce, &aad).unwrap();
+            assert_eq!(hex::encode(&recovered), hex::encode(&ptext));
+
+            let mut bad_ctext = ctext.clone();
+            bad_ctext[0] ^= 0x01;
+            assert_eq!(
+                super::aes_256_gcm_siv_decrypt(&bad_ctext, &key, &nonce, &aad),
+                Err(crate::SignalProtocolError::InvalidCiphertext)
+            );
+        }
+
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        assert_eq!(
+            super::aes_256_gcm_siv_decrypt(&[0u8; 15], &key, &nonce, &[]),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+        assert_eq!(
+            super::aes_256_gcm_siv_encrypt(b"abc", &key[..16], &nonce, &[]),
+            Err(crate::SignalProtocolError::InvalidCipherCryptographicParameters(16, 12))
+        );
+        assert_eq!(
+            super::aes_256_gcm_siv_encrypt(b"abc", &key, &nonce[..8], &[]),
+            Err(crate::SignalProtocolError::InvalidCipherCryptographicParameters(32, 8))
+        );
+    }
+
+    #[test]
+    fn committing_aead_test() {
+        let key = [0x42u8; 32];
+        let other_key = [0x43u8; 32];
+        let nonce = [0x24u8; 12];
+        let aad = b"associated data";
+        let ptext = b"sender key distribution message";
+
+        let ctext = super::committing_aead_encrypt(ptext, &key, &nonce, aad).unwrap();
+        assert_eq!(ctext.len(), 1 + 32 + ptext.len() + 16);
+        assert_eq!(ctext[0], 1);
+
+        let recovered = super::committing_aead_decrypt(&ctext, &key, &nonce, aad).unwrap();
+        assert_eq!(recovered, ptext.to_vec());
+
+        // a different key fails the commitment check
+        assert_eq!(
+            super::committing_aead_decrypt(&ctext, &other_key, &nonce, aad),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+
+        // as does a modified commitment, even with the right key
+        let mut bad_ctext = ctext.clone();
+        bad_ctext[1] ^= 0x01;
+        assert_eq!(
+            super::committing_aead_decrypt(&bad_ctext, &key, &nonce, aad),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+
+        // the underlying AEAD still authenticates the rest
+        assert_eq!(
+            super::committing_aead_decrypt(&ctext, &key, &nonce, b"other data"),
+            Err(crate::SignalProtocolError::InvalidCiphertext)
+        );
+
+        let mut bad_version = ctext.clone();
+        bad_version[0] = 2;
+        assert_eq!(
+            super::committing_aead_decrypt(&bad_version, &key, &nonce, aad),
+            Err(crate::SignalProtocolError::UnrecognizedCiphertextVersion(2))
+        );
+
+        assert_eq!(
+            super::committing_aead_decrypt(&ctext[..48], &key, &nonce, aad),
+            Err(crate::SignalProtocolError::CiphertextMessageTooShort(48))
+        );
+    }
+
+    #[test]
+    fn aes_ctr_test() {
+        // NIST SP 800-38A F.5.5 CTR-AES256.Encrypt
+        let key = hex::decode("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4")
+            .unwrap();
+        let nonce = hex::decode("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff").unwrap();
+
+        let ptext = hex::decode(
+            "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51\
+             30c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710",
+        )
+        .unwrap();
+
+        let ctext = super::aes_256_ctr_encrypt(&ptext, &key, &nonce).unwrap();
+        assert_eq!(
+            hex::encode(ctext.clone()),
+            "601ec313775789a5b7a7f504bbf3d228f443e3ca4d62b59aca84e990cacaf5c5\
+             2b0930daa23de94ce87017ba2d84988ddfc9c58db67aada613c2dd08457941a6"
+        );
+
+        let recovered = super::aes_256_ctr_decrypt(&ctext, &key, &nonce).unwrap();
+        assert_eq!(hex::encode(&ptext), hex::encode(recovered));
+
+        // partial blocks are fine in CTR mode
+        let recovered = super::aes_256_ctr_decrypt(&ctext[..21], &key, &nonce).unwrap();
+        assert_eq!(
+            hex::encode(recovered),
+            "6bc1bee22e409f96e93d7e117393172aae2d8a571e"
+        );
+
+        assert_eq!(
+            super::aes_256_ctr_encrypt(&ptext, &key[..16], &nonce),
+            Err(crate::SignalProtocolError::InvalidCipherCryptographicParameters(16, 16))
+        );
+        assert_eq!(
+            super::aes_256_ctr_encrypt(&ptext, &key, &nonce[..12]),
+            Err(crate::SignalProtocolError::InvalidCipherCryptographicParameters(32, 12))
+        );
+    }
+
+    #[test]
+    fn hkdf_sha256_rfc5869_test() {
+        // RFC 5869 test case 1
+        let ikm = hex::decode("0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b").unwrap();
+        let salt = hex::decode("000102030405060708090a0b0c").unwrap();
+        let info = hex::decode("f0f1f2f3f4f5f6f7f8f9").unwrap();
+
+        let okm = super::hkdf_sha256(&ikm, Some(&salt), &info, 42).unwrap();
+        assert_eq!(
+            hex::encode(okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"
+        );
+
+        let prk = hex::decode("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5")
+            .unwrap();
+        let okm = super::hkdf_expand_only(&prk, &info, 42).unwrap();
+        assert_eq!(
+            hex::encode(okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"
+        );
+
+        // RFC 5869 test case 2
+        let ikm: Vec<u8> = (0x00..=0x4f).collect();
+        let salt: Vec<u8> = (0x60..=0xaf).collect();
+        let info: Vec<u8> = (0xb0..=0xff).collect();
+
+        let okm = super::hkdf_sha256(&ikm, Some(&salt), &info, 82).unwrap();
+        assert_eq!(
+            hex::encode(okm),
+            "b11e398dc80327a1c8e7f78c596a49344f012eda2d4efad8a050cc4c19afa97c\
+             59045a99cac7827271cb41c65e590e09da3275600c2f09b8367793a9aca3db71\
+             cc30c58179ec3e87c14c01d5c1f3434f1d87"
+        );
+
+        // RFC 5869 test case 3: empty salt and info
+        let ikm = hex::decode("0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b").unwrap();
+        let expected =
+            "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d9d201395faa4b61a96c8";
+
+        let okm = super::hkdf_sha256(&ikm, Some(&[]), &[], 42).unwrap();
+        assert_eq!(hex::encode(okm), expected);
+        let okm = super::hkdf_sha256(&ikm, None, &[], 42).unwrap();
+        assert_eq!(hex::encode(okm), expected);
+
+        let prk = hex::decode("19ef24a32c717b167f33a91d6f648bdf96596776afdb6377ac434c1c293ccb04")
+            .unwrap();
+        let okm = super::hkdf_expand_only(&prk, &[], 42).unwrap();
+        assert_eq!(hex::encode(okm), expected);
+    }
+
+    #[test]
+    fn hkdf_sha256_output_length_test() {
+        let ikm = [0x0bu8; 22];
+
+        assert_eq!(super::hkdf_sha256(&ikm, None, &[], 0).unwrap().len(), 0);
+        assert_eq!(
+            super::hkdf_sha256(&ikm, None, &[], 255 * 32).unwrap().len(),
+            255 * 32
+        );
+        assert!(matches!(
+            super::hkdf_sha256(&ikm, None, &[], 255 * 32 + 1),
+            Err(crate::SignalProtocolError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            super::hkdf_expand_only(&[0u8; 32], &[], 255 * 32 + 1),
+            Err(crate::SignalProtocolError::InvalidArgument(_))
+        ));
+    }
 }
@@ -0,0 +1,144 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Minimal DER and PEM encodings of X25519 keys (RFC 8410): PKCS#8 `PrivateKeyInfo` for private
+//! keys and `SubjectPublicKeyInfo` for public keys, as produced by `openssl genpkey -algorithm
+//! X25519`.
+
+use crate::error::{Result, SignalProtocolError};
+
+const KEY_LENGTH: usize = 32;
+
+// id-X25519 is 1.3.101.110; 1.3.101.112 is Ed25519, and so on.
+const OID_ARC_PREFIX: [u8; 2] = [0x2b, 0x65];
+const X25519_OID_LAST: u8 = 0x6e;
+
+const PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x6e, 0x04, 0x22, 0x04, 0x20,
+];
+const PKCS8_OID_OFFSET: usize = 9;
+
+const SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x6e, 0x03, 0x21, 0x00,
+];
+const SPKI_OID_OFFSET: usize = 6;
+
+const PEM_PRIVATE_KEY_LABEL: &str = "PRIVATE KEY";
+const PEM_PUBLIC_KEY_LABEL: &str = "PUBLIC KEY";
+const PEM_LINE_LENGTH: usize = 64;
+
+pub(super) fn private_key_to_der(key: &[u8; KEY_LENGTH]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(PKCS8_PREFIX.len() + KEY_LENGTH);
+    result.extend_from_slice(&PKCS8_PREFIX);
+    result.extend_from_slice(key);
+    result
+}
+
+pub(super) fn private_key_from_der(der: &[u8]) -> Result<[u8; KEY_LENGTH]> {
+    decode("PKCS#8 private key", der, &PKCS8_PREFIX, PKCS8_OID_OFFSET)
+}
+
+pub(super) fn public_key_to_der(key: &[u8; KEY_LENGTH]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(SPKI_PREFIX.len() + KEY_LENGTH);
+    result.extend_from_slice(&SPKI_PREFIX);
+    result.extend_from_slice(key);
+    result
+}
+
+pub(super) fn public_key_from_der(der: &[u8]) -> Result<[u8; KEY_LENGTH]> {
+    decode("SubjectPublicKeyInfo", der, &SPKI_PREFIX, SPKI_OID_OFFSET)
+}
+
+pub(super) fn private_key_to_pem(key: &[u8; KEY_LENGTH]) -> String {
+    pem_encode(PEM_PRIVATE_KEY_LABEL, &private_key_to_der(key))
+}
+
+pub(super) fn private_key_from_pem(pem: &str) -> Result<[u8; KEY_LENGTH]> {
+    private_key_from_der(&pem_decode(PEM_PRIVATE_KEY_LABEL, pem)?)
+}
+
+pub(super) fn public_key_to_pem(key: &[u8; KEY_LENGTH]) -> String {
+    pem_encode(PEM_PUBLIC_KEY_LABEL, &public_key_to_der(key))
+}
+
+pub(super) fn public_key_from_pem(pem: &str) -> Result<[u8; KEY_LENGTH]> {
+    public_key_from_der(&pem_decode(PEM_PUBLIC_KEY_LABEL, pem)?)
+}
+
+fn decode(what: &str, der: &[u8], prefix: &[u8], oid_offset: usize) -> Result<[u8; KEY_LENGTH]> {
+    if der.len() >= oid_offset + 3 && der[oid_offset..oid_offset + 2] == OID_ARC_PREFIX {
+        let oid_last = der[oid_offset + 2];
+        if oid_last != X25519_OID_LAST {
+            return Err(SignalProtocolError::BadKeyType(oid_last));
+        }
+    }
+
+    if der.len() != prefix.len() + KEY_LENGTH {
+        return Err(SignalProtocolError::InvalidArgument(format!(
+            "{} for an X25519 key must be {} bytes, got {}",
+            what,
+            prefix.len() + KEY_LENGTH,
+            der.len()
+        )));
+    }
+    if der[..prefix.len()] != *prefix {
+        return Err(SignalProtocolError::InvalidArgument(format!(
+            "{} is not a DER-encoded X25519 key",
+            what
+        )));
+    }
+
+    let mut key = [0u8; KEY_LENGTH];
+    key.copy_from_slice(&der[prefix.len()..]);
+    Ok(key)
+}
+
+fn pem_encode(label: &str, der: &[u8]) -> String {
+    let encoded = base64::encode(der);
+    let mut result = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(PEM_LINE_LENGTH) {
+        result.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        result.push('\n');
+    }
+    result.push_str(&format!("-----END {}-----\n", label));
+    result
+}
+
+fn pem_decode(label: &str, pem: &str) -> Result<Vec<u8>> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+
+    let mut lines = pem.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.next() != Some(&begin) {
+        return Err(SignalProtocolError::InvalidArgument(format!(
+            "PEM input must start with {}",
+            begin
+        )));
+    }
+
+    let mut body = String::new();
+    loop {
+        match lines.next() {
+            Some(line) if line == end => break,
+            Some(line) => body.push_str(line),
+            None => {
+                return Err(SignalProtocolError::InvalidArgument(format!(
+                    "PEM input is missing {}",
+                    end
+                )))
+            }
+        }
+    }
+    if lines.next().is_some() {
+        return Err(SignalProtocolError::InvalidArgument(format!(
+            "unexpected data after {}",
+            end
+        )));
+    }
+
+    base64::decode(&body).map_err(|e| {
+        SignalProtocolError::InvalidArgument(format!("PEM body is not valid base64: {}", e))
+    })
+}
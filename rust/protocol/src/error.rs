@@ -7,9 +7,48 @@ use crate::curve::KeyType;
 
 use std::error::Error;
 use std::fmt;
+use std::sync::Arc;
 
 pub type Result<T> = std::result::Result<T, SignalProtocolError>;
 
+/// An underlying failure carried by a [SignalProtocolError] and returned from its
+/// [source](Error::source).
+///
+/// The failure is shared rather than owned so that errors remain cloneable. Two causes are equal
+/// if they display the same way.
+#[derive(Clone)]
+pub struct ErrorCause(Arc<dyn Error + Send + Sync + 'static>);
+
+impl ErrorCause {
+    pub fn new(cause: impl Into<Box<dyn Error + Send + Sync + 'static>>) -> Self {
+        Self(Arc::from(cause.into()))
+    }
+
+    pub fn get(&self) -> &(dyn Error + Send + Sync + 'static) {
+        &*self.0
+    }
+}
+
+impl fmt::Debug for ErrorCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for ErrorCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl PartialEq for ErrorCause {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl Eq for ErrorCause {}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum SignalProtocolError {
     InvalidArgument(String),
@@ -23,9 +62,13 @@ pub enum SignalProtocolError {
     LegacyCiphertextVersion(u8),
     UnrecognizedCiphertextVersion(u8),
     UnrecognizedMessageVersion(u32),
+    UnrecognizedRecordVersion(u32),
+    /// A message type byte that is not a [crate::CiphertextMessageType].
+    UnrecognizedCiphertextMessageType(u8),
 
     FingerprintIdentifierMismatch,
     FingerprintVersionMismatch,
+    FingerprintParsingError(&'static str),
 
     NoKeyTypeIdentifier,
     BadKeyType(u8),
@@ -41,6 +84,13 @@ pub enum SignalProtocolError {
     InvalidPreKeyId,
     InvalidSignedPreKeyId,
     InvalidSenderKeyId,
+    /// A sender key distribution message was for chain `found`, but the caller expected `expected`.
+    SenderKeyDistributionIdMismatch {
+        expected: u32,
+        found: u32,
+    },
+    /// A message carried a registration id above [crate::RegistrationId::MAX].
+    InvalidRegistrationId(u32),
 
     InvalidPreKeyBundle,
 
@@ -50,17 +100,44 @@ pub enum SignalProtocolError {
     InvalidMacKeyLength(usize),
     InvalidCipherCryptographicParameters(usize, usize),
     InvalidCiphertext,
+    InvalidCiphertextLength(usize),
+    InvalidCiphertextPadding,
 
     NoSenderKeyState,
     SenderKeySigningKeyMissing,
 
     SessionNotFound,
+    /// Like [SignalProtocolError::SessionNotFound], but naming the address that has no session.
+    SessionNotFoundForAddress(crate::ProtocolAddress),
     InvalidSessionStructure,
 
-    DuplicatedMessage(u32, u32),
+    /// A message whose key has already been used, or was discarded: `counter` is behind the
+    /// receiving chain.
+    DuplicatedMessage {
+        /// The iteration the receiving chain has reached, i.e. the counter of the next message
+        /// key it would derive.
+        current_iteration: u32,
+        /// The counter carried by the rejected message.
+        counter: u32,
+    },
+    /// A sender key message whose `counter` is further ahead of the receiving chain than the
+    /// configured maximum allows.
+    MessageTooFarInFuture {
+        /// The iteration the receiving sender chain has reached, i.e. the counter of the next
+        /// message key it would derive.
+        current_iteration: u32,
+        /// The counter carried by the rejected message.
+        counter: u32,
+    },
     InvalidMessage(&'static str),
     InternalError(&'static str),
+    /// A store was asked to perform an optional operation, named here, that it does not support.
+    OperationNotSupported(&'static str),
+    /// A persistent store failed to read or write the data at the given path.
+    StorageIoError(String, ErrorCause),
     FfiBindingError(String),
+    /// A store or other application-provided callback, named here, failed.
+    ApplicationCallbackError(&'static str, ErrorCause),
     ApplicationCallbackThrewException(&'static str, Option<String>, String),
     ApplicationCallbackReturnedIntegerError(&'static str, i32),
 }
@@ -70,11 +147,35 @@ impl Error for SignalProtocolError {
         match self {
             SignalProtocolError::ProtobufEncodingError(e) => Some(e),
             SignalProtocolError::ProtobufDecodingError(e) => Some(e),
+            SignalProtocolError::StorageIoError(_, cause)
+            | SignalProtocolError::ApplicationCallbackError(_, cause) => Some(cause.get()),
             _ => None,
         }
     }
 }
 
+impl SignalProtocolError {
+    /// Formats the error followed by each of its underlying causes, one per line.
+    ///
+    /// A cause is left out when the line before it already ends with its text, as the messages of
+    /// most variants include their immediate cause.
+    pub fn display_with_causes(&self) -> String {
+        let mut message = self.to_string();
+        let mut previous = message.clone();
+        let mut source = self.source();
+        while let Some(cause) = source {
+            let text = cause.to_string();
+            if !previous.ends_with(&text) {
+                message.push_str("\ncaused by: ");
+                message.push_str(&text);
+            }
+            previous = text;
+            source = cause.source();
+        }
+        message
+    }
+}
+
 impl From<prost::DecodeError> for SignalProtocolError {
     fn from(value: prost::DecodeError) -> SignalProtocolError {
         SignalProtocolError::ProtobufDecodingError(value)
@@ -115,12 +216,21 @@ impl fmt::Display for SignalProtocolError {
             SignalProtocolError::UnrecognizedMessageVersion(message_version) => {
                 write!(f, "unrecognized message version <{}>", message_version)
             }
+            SignalProtocolError::UnrecognizedRecordVersion(version) => {
+                write!(f, "unrecognized record version <{}>", version)
+            }
+            SignalProtocolError::UnrecognizedCiphertextMessageType(message_type) => {
+                write!(f, "unrecognized ciphertext message type <{}>", message_type)
+            }
             SignalProtocolError::FingerprintIdentifierMismatch => {
                 write!(f, "fingerprint identifiers do not match")
             }
             SignalProtocolError::FingerprintVersionMismatch => {
                 write!(f, "fingerprint version numbers do not match")
             }
+            SignalProtocolError::FingerprintParsingError(m) => {
+                write!(f, "fingerprint could not be parsed: {}", m)
+            }
             SignalProtocolError::NoKeyTypeIdentifier => write!(f, "no key type identifier"),
             SignalProtocolError::BadKeyType(t) => write!(f, "bad key type <{:#04x}>", t),
             SignalProtocolError::BadKeyLength(t, l) => {
@@ -160,14 +270,54 @@ impl fmt::Display for SignalProtocolError {
             }
             SignalProtocolError::InvalidPreKeyBundle => write!(f, "invalid pre key bundle format"),
             SignalProtocolError::InvalidCiphertext => write!(f, "invalid ciphertext message"),
+            SignalProtocolError::InvalidCiphertextLength(l) => {
+                write!(
+                    f,
+                    "ciphertext length <{}> is not a positive multiple of the block size",
+                    l
+                )
+            }
+            SignalProtocolError::InvalidCiphertextPadding => {
+                write!(f, "ciphertext padding was invalid after decryption")
+            }
             SignalProtocolError::SessionNotFound => write!(f, "session not found"),
-            SignalProtocolError::InvalidSessionStructure => write!(f, "invalid session structure"),
-            SignalProtocolError::DuplicatedMessage(i, c) => {
-                write!(f, "message with old counter {} / {}", i, c)
+            SignalProtocolError::SessionNotFoundForAddress(addr) => {
+                write!(f, "session not found for address {}", addr)
             }
+            SignalProtocolError::InvalidSessionStructure => write!(f, "invalid session structure"),
+            SignalProtocolError::DuplicatedMessage {
+                current_iteration,
+                counter,
+            } => write!(
+                f,
+                "message with old counter {} (current iteration {})",
+                counter, current_iteration
+            ),
+            SignalProtocolError::MessageTooFarInFuture {
+                current_iteration,
+                counter,
+            } => write!(
+                f,
+                "message with counter {} too far ahead of current iteration {}",
+                counter, current_iteration
+            ),
             SignalProtocolError::InvalidMessage(m) => write!(f, "invalid message {}", m),
             SignalProtocolError::InternalError(m) => write!(f, "internal error {}", m),
+            SignalProtocolError::OperationNotSupported(op) => {
+                write!(f, "operation not supported: {}", op)
+            }
+            SignalProtocolError::StorageIoError(path, cause) => {
+                write!(f, "storage I/O error: {}: {}", path, cause)
+            }
             SignalProtocolError::InvalidSenderKeyId => write!(f, "invalid send key id"),
+            SignalProtocolError::SenderKeyDistributionIdMismatch { expected, found } => write!(
+                f,
+                "sender key distribution message for chain {} (expected {})",
+                found, expected
+            ),
+            SignalProtocolError::InvalidRegistrationId(id) => {
+                write!(f, "registration id <{}> out of range", id)
+            }
             SignalProtocolError::NoSenderKeyState => write!(f, "no sender key state"),
             SignalProtocolError::SenderKeySigningKeyMissing => {
                 write!(f, "sender key signature key missing")
@@ -178,6 +328,9 @@ impl fmt::Display for SignalProtocolError {
             SignalProtocolError::FfiBindingError(m) => {
                 write!(f, "error while invoking an ffi callback: {}", m)
             }
+            SignalProtocolError::ApplicationCallbackError(func, cause) => {
+                write!(f, "application callback {} failed: {}", func, cause)
+            }
             SignalProtocolError::ApplicationCallbackReturnedIntegerError(func, c) => {
                 write!(f, "application callback {} returned error code {}", func, c)
             }
@@ -196,3 +349,66 @@ impl fmt::Display for SignalProtocolError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+    use std::io;
+
+    #[test]
+    fn test_decode_error_source() {
+        let err = SignalProtocolError::from(
+            crate::proto::wire::SignalMessage::decode(&[0xFFu8][..]).unwrap_err(),
+        );
+        let source = err.source().expect("has a source");
+        assert!(source.is::<prost::DecodeError>());
+        // The cause is already part of the message, so it isn't repeated.
+        assert_eq!(err.display_with_causes(), err.to_string());
+    }
+
+    #[test]
+    fn test_callback_error_source() {
+        let err = SignalProtocolError::ApplicationCallbackError(
+            "load_session",
+            ErrorCause::new(io::Error::new(io::ErrorKind::Other, "database locked")),
+        );
+        let source = err.source().expect("has a source");
+        assert!(source.is::<io::Error>());
+        assert_eq!(source.to_string(), "database locked");
+        assert_eq!(
+            err.to_string(),
+            "application callback load_session failed: database locked"
+        );
+    }
+
+    #[derive(Debug)]
+    struct Wrapped(io::Error);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "query failed")
+        }
+    }
+
+    impl Error for Wrapped {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn test_display_with_causes() {
+        let err = SignalProtocolError::ApplicationCallbackError(
+            "load_session",
+            ErrorCause::new(Wrapped(io::Error::new(
+                io::ErrorKind::Other,
+                "database locked",
+            ))),
+        );
+        assert_eq!(
+            err.display_with_causes(),
+            "application callback load_session failed: query failed\ncaused by: database locked"
+        );
+    }
+}
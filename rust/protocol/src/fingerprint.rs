@@ -8,9 +8,16 @@ use crate::proto;
 use crate::IdentityKey;
 use prost::Message;
 use sha2::{digest::Digest, Sha512};
+use std::convert::TryFrom;
 use std::fmt;
 use subtle::ConstantTimeEq;
 
+/// The smallest iteration count accepted when generating a fingerprint.
+pub const MIN_FINGERPRINT_ITERATIONS: u32 = 1000;
+
+/// The scannable fingerprint version used by [Fingerprint::new_multi].
+pub const MULTI_IDENTIFIER_FINGERPRINT_VERSION: u32 = 3;
+
 #[derive(Debug, Clone)]
 pub struct DisplayableFingerprint {
     local: String,
@@ -63,6 +70,21 @@ impl DisplayableFingerprint {
     }
 }
 
+/// The outcome of comparing a scanned fingerprint against our own.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FingerprintComparison {
+    Match,
+    /// The scanning party sees a different identity key for us than our own.
+    LocalMismatch,
+    /// The scanning party's identity key is not the one we have for them.
+    RemoteMismatch,
+    /// The scanned fingerprint was generated with a different fingerprint version.
+    VersionMismatch {
+        ours: u32,
+        theirs: u32,
+    },
+}
+
 #[derive(Debug, Clone)]
 pub struct ScannableFingerprint {
     version: u32,
@@ -112,31 +134,54 @@ impl ScannableFingerprint {
     }
 
     pub fn compare(&self, combined: &[u8]) -> Result<bool> {
+        match self.compare_scanned(combined)? {
+            FingerprintComparison::Match => Ok(true),
+            FingerprintComparison::LocalMismatch | FingerprintComparison::RemoteMismatch => {
+                Ok(false)
+            }
+            FingerprintComparison::VersionMismatch { .. } => {
+                Err(SignalProtocolError::FingerprintVersionMismatch)
+            }
+        }
+    }
+
+    /// Compares a scanned fingerprint against this one, reporting why they differ.
+    ///
+    /// Data that is not a protobuf at all produces [SignalProtocolError::ProtobufDecodingError],
+    /// while a same-version fingerprint lacking one of its halves produces
+    /// [SignalProtocolError::FingerprintParsingError]. If both halves differ, the result is
+    /// [FingerprintComparison::RemoteMismatch].
+    pub fn compare_scanned(&self, combined: &[u8]) -> Result<FingerprintComparison> {
         let combined = proto::fingerprint::CombinedFingerprints::decode(combined)?;
 
         if combined.version != self.version {
-            return Err(SignalProtocolError::FingerprintVersionMismatch);
+            return Ok(FingerprintComparison::VersionMismatch {
+                ours: self.version,
+                theirs: combined.version,
+            });
         }
 
-        // This follows the Java logic but seems misleading - use InvalidProtobufEncoding instead?
-        if combined.local_fingerprint.is_none() || combined.remote_fingerprint.is_none() {
-            return Err(SignalProtocolError::FingerprintVersionMismatch);
-        }
+        let their_local =
+            combined
+                .local_fingerprint
+                .ok_or(SignalProtocolError::FingerprintParsingError(
+                    "missing local fingerprint",
+                ))?;
+        let their_remote =
+            combined
+                .remote_fingerprint
+                .ok_or(SignalProtocolError::FingerprintParsingError(
+                    "missing remote fingerprint",
+                ))?;
 
-        let same1 = combined
-            .local_fingerprint
-            .as_ref()
-            .unwrap()
-            .content
-            .ct_eq(&self.remote_fingerprint);
-        let same2 = combined
-            .remote_fingerprint
-            .as_ref()
-            .unwrap()
-            .content
-            .ct_eq(&self.local_fingerprint);
+        let remote_matches: bool = their_local.content.ct_eq(&self.remote_fingerprint).into();
+        let local_matches: bool = their_remote.content.ct_eq(&self.local_fingerprint).into();
 
-        Ok(same1.into() && same2.into())
+        Ok(match (remote_matches, local_matches) {
+            (true, true) => FingerprintComparison::Match,
+            (true, false) => FingerprintComparison::LocalMismatch,
+            (false, _) => FingerprintComparison::RemoteMismatch,
+        })
     }
 }
 
@@ -152,14 +197,53 @@ impl Fingerprint {
         local_id: &[u8],
         local_key: &IdentityKey,
     ) -> Result<Vec<u8>> {
-        if iterations <= 1 || iterations > 1000000 {
+        Self::get_fingerprint_with_version(iterations, [0u8, 0u8], local_id, local_key)
+    }
+
+    /// Hashes a list of identifiers in a canonical order.
+    ///
+    /// The identifiers are sorted bytewise and each is prefixed with its length as a big-endian
+    /// u32, so that no two distinct lists hash the same input. A distinct hash version (0x0001)
+    /// keeps the result from ever colliding with a single-identifier fingerprint.
+    fn get_multi_fingerprint(
+        iterations: u32,
+        local_ids: &[&[u8]],
+        local_key: &IdentityKey,
+    ) -> Result<Vec<u8>> {
+        if local_ids.is_empty() {
+            return Err(SignalProtocolError::InvalidArgument(
+                "fingerprint requires at least one identifier".to_string(),
+            ));
+        }
+
+        let mut sorted_ids = local_ids.to_vec();
+        sorted_ids.sort_unstable();
+
+        let mut encoded_ids = Vec::new();
+        for id in sorted_ids {
+            let len = u32::try_from(id.len()).map_err(|_| {
+                SignalProtocolError::InvalidArgument("fingerprint identifier too long".to_string())
+            })?;
+            encoded_ids.extend_from_slice(&len.to_be_bytes());
+            encoded_ids.extend_from_slice(id);
+        }
+
+        Self::get_fingerprint_with_version(iterations, [0u8, 1u8], &encoded_ids, local_key)
+    }
+
+    fn get_fingerprint_with_version(
+        iterations: u32,
+        fingerprint_version: [u8; 2],
+        local_id: &[u8],
+        local_key: &IdentityKey,
+    ) -> Result<Vec<u8>> {
+        if !(MIN_FINGERPRINT_ITERATIONS..=1000000).contains(&iterations) {
             return Err(SignalProtocolError::InvalidArgument(format!(
                 "Invalid fingerprint iterations {}",
                 iterations
             )));
         }
 
-        let fingerprint_version = [0u8, 0u8]; // 0x0000
         let key_bytes = local_key.serialize();
 
         let mut sha512 = Sha512::new();
@@ -198,6 +282,49 @@ impl Fingerprint {
         })
     }
 
+    /// Computes only the displayable half of a fingerprint, for callers that never show or scan
+    /// QR codes.
+    ///
+    /// The result is identical to the `display` of [Fingerprint::new] with the same arguments.
+    pub fn new_displayable(
+        iterations: u32,
+        local_id: &[u8],
+        local_key: &IdentityKey,
+        remote_id: &[u8],
+        remote_key: &IdentityKey,
+    ) -> Result<DisplayableFingerprint> {
+        let local_fingerprint = Fingerprint::get_fingerprint(iterations, local_id, local_key)?;
+        let remote_fingerprint = Fingerprint::get_fingerprint(iterations, remote_id, remote_key)?;
+        DisplayableFingerprint::new(&local_fingerprint, &remote_fingerprint)
+    }
+
+    /// Computes a fingerprint over several identifiers per party, such as a UUID and an E164.
+    ///
+    /// The identifiers may be given in any order; both parties get the same result as long as
+    /// they agree on the set of identifiers. The scannable encoding is always
+    /// [MULTI_IDENTIFIER_FINGERPRINT_VERSION].
+    pub fn new_multi(
+        iterations: u32,
+        local_ids: &[&[u8]],
+        local_key: &IdentityKey,
+        remote_ids: &[&[u8]],
+        remote_key: &IdentityKey,
+    ) -> Result<Fingerprint> {
+        let local_fingerprint =
+            Fingerprint::get_multi_fingerprint(iterations, local_ids, local_key)?;
+        let remote_fingerprint =
+            Fingerprint::get_multi_fingerprint(iterations, remote_ids, remote_key)?;
+
+        Ok(Fingerprint {
+            display: DisplayableFingerprint::new(&local_fingerprint, &remote_fingerprint)?,
+            scannable: ScannableFingerprint::new(
+                MULTI_IDENTIFIER_FINGERPRINT_VERSION,
+                &local_fingerprint,
+                &remote_fingerprint,
+            ),
+        })
+    }
+
     pub fn display_string(&self) -> Result<String> {
         Ok(format!("{}", self.display))
     }
@@ -222,6 +349,14 @@ mod test {
     const ALICE_STABLE_ID: &str = "+14152222222";
     const BOB_STABLE_ID: &str = "+14153333333";
 
+    const ALICE_UUID: &str = "6a3d4c8e-1f0b-4d2a-9c5e-7b8f0a1d2e3c";
+    const BOB_UUID: &str = "0f9e8d7c-6b5a-4e3d-8c2b-1a0f9e8d7c6b";
+
+    const DISPLAYABLE_FINGERPRINT_MULTI: &str =
+        "632860439795916348638199572294663952284327893052686922753464";
+    const ALICE_SCANNABLE_FINGERPRINT_MULTI : &str = "080312220a205252e081d66d9fa7b8ad6797c362cc7ad305118ff66f9f8c8bfddc5c7386d5341a220a2013174c795bc7af436a5b20c318ad95707fd69074238b78408b43b08ebd784481";
+    const BOB_SCANNABLE_FINGERPRINT_MULTI   : &str = "080312220a2013174c795bc7af436a5b20c318ad95707fd69074238b78408b43b08ebd7844811a220a205252e081d66d9fa7b8ad6797c362cc7ad305118ff66f9f8c8bfddc5c7386d534";
+
     #[test]
     fn fingerprint_test_v1() {
         // testVectorsVersion1 in Java
@@ -327,6 +462,245 @@ mod test {
         );
     }
 
+    #[test]
+    fn fingerprint_compare_reports_reason() {
+        use crate::IdentityKeyPair;
+        use rand::rngs::OsRng;
+
+        let a_key = IdentityKey::decode(&hex::decode(ALICE_IDENTITY).unwrap()).unwrap();
+        let b_key = IdentityKey::decode(&hex::decode(BOB_IDENTITY).unwrap()).unwrap();
+        let m_key = *IdentityKeyPair::generate(&mut OsRng).identity_key();
+
+        let fprint = |version, local_key, remote_key| {
+            Fingerprint::new(
+                version,
+                5200,
+                ALICE_STABLE_ID.as_bytes(),
+                local_key,
+                BOB_STABLE_ID.as_bytes(),
+                remote_key,
+            )
+            .unwrap()
+            .scannable
+        };
+        let b_fprint = Fingerprint::new(
+            1,
+            5200,
+            BOB_STABLE_ID.as_bytes(),
+            &b_key,
+            ALICE_STABLE_ID.as_bytes(),
+            &a_key,
+        )
+        .unwrap()
+        .scannable;
+        let b_scanned = b_fprint.serialize().unwrap();
+
+        assert_eq!(
+            fprint(1, &a_key, &b_key)
+                .compare_scanned(&b_scanned)
+                .unwrap(),
+            FingerprintComparison::Match
+        );
+        // Bob's key was swapped out from under Alice.
+        assert_eq!(
+            fprint(1, &a_key, &m_key)
+                .compare_scanned(&b_scanned)
+                .unwrap(),
+            FingerprintComparison::RemoteMismatch
+        );
+        // Bob has a stale key for Alice.
+        assert_eq!(
+            fprint(1, &m_key, &b_key)
+                .compare_scanned(&b_scanned)
+                .unwrap(),
+            FingerprintComparison::LocalMismatch
+        );
+
+        // Alice runs a newer app than Bob.
+        let a_fprint_v2 = fprint(2, &a_key, &b_key);
+        assert_eq!(
+            a_fprint_v2.compare_scanned(&b_scanned).unwrap(),
+            FingerprintComparison::VersionMismatch { ours: 2, theirs: 1 }
+        );
+        assert_eq!(
+            a_fprint_v2.compare(&b_scanned).unwrap_err(),
+            SignalProtocolError::FingerprintVersionMismatch
+        );
+        // ...and the other way around.
+        assert_eq!(
+            b_fprint
+                .compare_scanned(&a_fprint_v2.serialize().unwrap())
+                .unwrap(),
+            FingerprintComparison::VersionMismatch { ours: 1, theirs: 2 }
+        );
+    }
+
+    #[test]
+    fn fingerprint_compare_malformed() {
+        let a_key = IdentityKey::decode(&hex::decode(ALICE_IDENTITY).unwrap()).unwrap();
+        let b_key = IdentityKey::decode(&hex::decode(BOB_IDENTITY).unwrap()).unwrap();
+        let a_fprint = Fingerprint::new(
+            1,
+            5200,
+            ALICE_STABLE_ID.as_bytes(),
+            &a_key,
+            BOB_STABLE_ID.as_bytes(),
+            &b_key,
+        )
+        .unwrap()
+        .scannable;
+
+        let missing_remote = proto::fingerprint::CombinedFingerprints {
+            version: 1,
+            local_fingerprint: Some(proto::fingerprint::LogicalFingerprint {
+                content: vec![0; 32],
+            }),
+            remote_fingerprint: None,
+        };
+        let mut missing_remote_bytes = Vec::new();
+        missing_remote.encode(&mut missing_remote_bytes).unwrap();
+        assert_eq!(
+            a_fprint.compare_scanned(&missing_remote_bytes).unwrap_err(),
+            SignalProtocolError::FingerprintParsingError("missing remote fingerprint")
+        );
+
+        // A truncated field is corrupted data, not a missing field.
+        let corrupted = &hex::decode(ALICE_SCANNABLE_FINGERPRINT_V1).unwrap()[..20];
+        assert!(matches!(
+            a_fprint.compare_scanned(corrupted).unwrap_err(),
+            SignalProtocolError::ProtobufDecodingError(_)
+        ));
+    }
+
+    #[test]
+    fn fingerprint_displayable_only() {
+        let a_key = IdentityKey::decode(&hex::decode(ALICE_IDENTITY).unwrap()).unwrap();
+        let b_key = IdentityKey::decode(&hex::decode(BOB_IDENTITY).unwrap()).unwrap();
+
+        let a_display = Fingerprint::new_displayable(
+            5200,
+            ALICE_STABLE_ID.as_bytes(),
+            &a_key,
+            BOB_STABLE_ID.as_bytes(),
+            &b_key,
+        )
+        .unwrap();
+        let b_display = Fingerprint::new_displayable(
+            5200,
+            BOB_STABLE_ID.as_bytes(),
+            &b_key,
+            ALICE_STABLE_ID.as_bytes(),
+            &a_key,
+        )
+        .unwrap();
+
+        assert_eq!(format!("{}", a_display), DISPLAYABLE_FINGERPRINT_V1);
+        assert_eq!(format!("{}", b_display), DISPLAYABLE_FINGERPRINT_V1);
+    }
+
+    #[test]
+    fn fingerprint_iteration_bounds() {
+        let a_key = IdentityKey::decode(&hex::decode(ALICE_IDENTITY).unwrap()).unwrap();
+        let b_key = IdentityKey::decode(&hex::decode(BOB_IDENTITY).unwrap()).unwrap();
+
+        let fprint = |iterations| {
+            Fingerprint::new(
+                1,
+                iterations,
+                ALICE_STABLE_ID.as_bytes(),
+                &a_key,
+                BOB_STABLE_ID.as_bytes(),
+                &b_key,
+            )
+        };
+        let display = |iterations| {
+            Fingerprint::new_displayable(
+                iterations,
+                ALICE_STABLE_ID.as_bytes(),
+                &a_key,
+                BOB_STABLE_ID.as_bytes(),
+                &b_key,
+            )
+        };
+
+        for iterations in &[0, 2, MIN_FINGERPRINT_ITERATIONS - 1, 1000001] {
+            assert!(matches!(
+                fprint(*iterations),
+                Err(SignalProtocolError::InvalidArgument(_))
+            ));
+            assert!(matches!(
+                display(*iterations),
+                Err(SignalProtocolError::InvalidArgument(_))
+            ));
+        }
+
+        let fprint = fprint(MIN_FINGERPRINT_ITERATIONS).unwrap();
+        let display = display(MIN_FINGERPRINT_ITERATIONS).unwrap();
+        assert_eq!(format!("{}", fprint.display), format!("{}", display));
+        assert_ne!(format!("{}", display), DISPLAYABLE_FINGERPRINT_V1);
+    }
+
+    #[test]
+    fn fingerprint_multi_identifier() {
+        let a_key = IdentityKey::decode(&hex::decode(ALICE_IDENTITY).unwrap()).unwrap();
+        let b_key = IdentityKey::decode(&hex::decode(BOB_IDENTITY).unwrap()).unwrap();
+        let alice_ids: [&[u8]; 2] = [ALICE_UUID.as_bytes(), ALICE_STABLE_ID.as_bytes()];
+        let bob_ids: [&[u8]; 2] = [BOB_STABLE_ID.as_bytes(), BOB_UUID.as_bytes()];
+        let alice_ids_reordered: [&[u8]; 2] = [ALICE_STABLE_ID.as_bytes(), ALICE_UUID.as_bytes()];
+
+        let a_fprint = Fingerprint::new_multi(5200, &alice_ids, &a_key, &bob_ids, &b_key).unwrap();
+        let b_fprint =
+            Fingerprint::new_multi(5200, &bob_ids, &b_key, &alice_ids_reordered, &a_key).unwrap();
+
+        assert_eq!(
+            format!("{}", a_fprint.display),
+            DISPLAYABLE_FINGERPRINT_MULTI
+        );
+        assert_eq!(
+            format!("{}", b_fprint.display),
+            DISPLAYABLE_FINGERPRINT_MULTI
+        );
+        assert_eq!(
+            hex::encode(a_fprint.scannable.serialize().unwrap()),
+            ALICE_SCANNABLE_FINGERPRINT_MULTI
+        );
+        assert_eq!(
+            hex::encode(b_fprint.scannable.serialize().unwrap()),
+            BOB_SCANNABLE_FINGERPRINT_MULTI
+        );
+        assert!(a_fprint
+            .scannable
+            .compare(&b_fprint.scannable.serialize().unwrap())
+            .unwrap());
+
+        // A single identifier is still hashed differently from the legacy construction.
+        let single = Fingerprint::new_multi(
+            5200,
+            &[ALICE_STABLE_ID.as_bytes()],
+            &a_key,
+            &[BOB_STABLE_ID.as_bytes()],
+            &b_key,
+        )
+        .unwrap();
+        assert_ne!(format!("{}", single.display), DISPLAYABLE_FINGERPRINT_V1);
+
+        // Length prefixes keep the split between identifiers significant.
+        let split = Fingerprint::new_multi(
+            5200,
+            &[b"+1415", b"2222222"],
+            &a_key,
+            &[BOB_STABLE_ID.as_bytes()],
+            &b_key,
+        )
+        .unwrap();
+        assert_ne!(format!("{}", single.display), format!("{}", split.display));
+
+        assert!(matches!(
+            Fingerprint::new_multi(5200, &[], &a_key, &bob_ids, &b_key),
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+    }
+
     #[test]
     fn fingerprint_matching_identifiers() {
         // testMatchingFingerprints
@@ -21,6 +21,31 @@ pub async fn group_encrypt<R: Rng + CryptoRng>(
     csprng: &mut R,
     ctx: Context,
 ) -> Result<Vec<u8>> {
+    let encrypted =
+        group_encrypt_with_metadata(sender_key_store, sender_key_id, plaintext, csprng, ctx)
+            .await?;
+    Ok(encrypted.serialized)
+}
+
+/// A serialized [`SenderKeyMessage`] from [`group_encrypt_with_metadata`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupEncryptedMessage {
+    pub serialized: Vec<u8>,
+    /// The sender key ID of the chain the message was encrypted with.
+    pub chain_id: u32,
+    /// The message's position in that chain; each encryption advances it by one.
+    pub iteration: u32,
+}
+
+/// Like [`group_encrypt`], but also reports which chain key was used, so that retried sends can
+/// be recognized as such.
+pub async fn group_encrypt_with_metadata<R: Rng + CryptoRng>(
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender_key_id: &SenderKeyName,
+    plaintext: &[u8],
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<GroupEncryptedMessage> {
     let mut record = sender_key_store
         .load_sender_key(&sender_key_id, ctx)
         .await?
@@ -37,13 +62,9 @@ pub async fn group_encrypt<R: Rng + CryptoRng>(
         .signing_key_private()?
         .ok_or(SignalProtocolError::SenderKeySigningKeyMissing)?;
 
-    let skm = SenderKeyMessage::new(
-        sender_key_state.sender_key_id()?,
-        sender_key.iteration()?,
-        &ciphertext,
-        csprng,
-        &signing_key,
-    )?;
+    let chain_id = sender_key_state.sender_key_id()?;
+    let iteration = sender_key.iteration()?;
+    let skm = SenderKeyMessage::new(chain_id, iteration, &ciphertext, csprng, &signing_key)?;
 
     sender_key_state.set_sender_chain_key(sender_key_state.sender_chain_key()?.next()?)?;
 
@@ -51,34 +72,63 @@ pub async fn group_encrypt<R: Rng + CryptoRng>(
         .store_sender_key(sender_key_id, &record, ctx)
         .await?;
 
-    Ok(skm.serialized().to_vec())
+    Ok(GroupEncryptedMessage {
+        serialized: skm.serialized().to_vec(),
+        chain_id,
+        iteration,
+    })
 }
 
-fn get_sender_key(state: &mut SenderKeyState, iteration: u32) -> Result<SenderMessageKey> {
+/// Limits applied when decrypting sender key messages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupCipherConfig {
+    /// How far ahead of the receiving chain a message may be. A message further ahead is rejected
+    /// with [`SignalProtocolError::MessageTooFarInFuture`], and at most this many skipped keys are
+    /// kept per sender key state (the oldest are discarded first).
+    pub max_skipped_message_keys: usize,
+}
+
+impl Default for GroupCipherConfig {
+    fn default() -> Self {
+        Self {
+            max_skipped_message_keys: consts::MAX_FORWARD_JUMPS,
+        }
+    }
+}
+
+fn get_sender_key(
+    state: &mut SenderKeyState,
+    iteration: u32,
+    config: &GroupCipherConfig,
+) -> Result<SenderMessageKey> {
     let sender_chain_key = state.sender_chain_key()?;
 
     if sender_chain_key.iteration()? > iteration {
         if let Some(smk) = state.remove_sender_message_key(iteration)? {
             return Ok(smk);
         } else {
-            return Err(SignalProtocolError::DuplicatedMessage(
-                sender_chain_key.iteration()?,
-                iteration,
-            ));
+            return Err(SignalProtocolError::DuplicatedMessage {
+                current_iteration: sender_chain_key.iteration()?,
+                counter: iteration,
+            });
         }
     }
 
     let jump = (iteration - sender_chain_key.iteration()?) as usize;
-    if jump > consts::MAX_FORWARD_JUMPS {
-        return Err(SignalProtocolError::InvalidMessage(
-            "message from too far into the future",
-        ));
+    if jump > config.max_skipped_message_keys {
+        return Err(SignalProtocolError::MessageTooFarInFuture {
+            current_iteration: sender_chain_key.iteration()?,
+            counter: iteration,
+        });
     }
 
     let mut sender_chain_key = sender_chain_key;
 
     while sender_chain_key.iteration()? < iteration {
-        state.add_sender_message_key(&sender_chain_key.sender_message_key()?)?;
+        state.add_sender_message_key_with_limit(
+            &sender_chain_key.sender_message_key()?,
+            config.max_skipped_message_keys,
+        )?;
         sender_chain_key = sender_chain_key.next()?;
     }
 
@@ -92,6 +142,77 @@ pub async fn group_decrypt(
     sender_key_id: &SenderKeyName,
     ctx: Context,
 ) -> Result<Vec<u8>> {
+    group_decrypt_with_config(
+        skm_bytes,
+        sender_key_store,
+        sender_key_id,
+        &GroupCipherConfig::default(),
+        ctx,
+    )
+    .await
+}
+
+pub async fn group_decrypt_with_config(
+    skm_bytes: &[u8],
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender_key_id: &SenderKeyName,
+    config: &GroupCipherConfig,
+    ctx: Context,
+) -> Result<Vec<u8>> {
+    let (plaintext, _metadata) =
+        group_decrypt_with_metadata(skm_bytes, sender_key_store, sender_key_id, config, ctx)
+            .await?;
+    Ok(plaintext)
+}
+
+/// Which sender chain produced a message, from [`group_decrypt_with_metadata`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GroupDecryptionMetadata {
+    /// The sender key ID of the chain.
+    pub chain_id: u32,
+    pub iteration: u32,
+    /// The key that verified the message's signature, as distributed for this chain.
+    pub signing_key: curve::PublicKey,
+}
+
+/// Like [`group_decrypt_with_config`], but also reports which chain and signing key the message
+/// was attributed to.
+///
+/// The signature is checked before any message keys are derived or looked up, so a forged
+/// message reveals nothing about the chain's state.
+pub async fn group_decrypt_with_metadata(
+    skm_bytes: &[u8],
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender_key_id: &SenderKeyName,
+    config: &GroupCipherConfig,
+    ctx: Context,
+) -> Result<(Vec<u8>, GroupDecryptionMetadata)> {
+    let mut plaintext = Vec::new();
+    let metadata = group_decrypt_into(
+        skm_bytes,
+        sender_key_store,
+        sender_key_id,
+        config,
+        &mut plaintext,
+        ctx,
+    )
+    .await?;
+    Ok((plaintext, metadata))
+}
+
+/// Like [`group_decrypt_with_metadata`], but writes the plaintext into `plaintext`, reusing its
+/// allocation.
+///
+/// Any previous contents of `plaintext` are discarded. Callers decrypting many messages can pass
+/// the same buffer each time.
+pub async fn group_decrypt_into(
+    skm_bytes: &[u8],
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender_key_id: &SenderKeyName,
+    config: &GroupCipherConfig,
+    plaintext: &mut Vec<u8>,
+    ctx: Context,
+) -> Result<GroupDecryptionMetadata> {
     let mut record = sender_key_store
         .load_sender_key(&sender_key_id, ctx)
         .await?
@@ -106,19 +227,24 @@ pub async fn group_decrypt(
         return Err(SignalProtocolError::SignatureValidationFailed);
     }
 
-    let sender_key = get_sender_key(&mut sender_key_state, skm.iteration())?;
+    let sender_key = get_sender_key(&mut sender_key_state, skm.iteration(), config)?;
 
-    let plaintext = crypto::aes_256_cbc_decrypt(
+    crypto::aes_256_cbc_decrypt_into(
         skm.ciphertext(),
         &sender_key.cipher_key()?,
         &sender_key.iv()?,
+        plaintext,
     )?;
 
     sender_key_store
         .store_sender_key(sender_key_id, &record, ctx)
         .await?;
 
-    Ok(plaintext)
+    Ok(GroupDecryptionMetadata {
+        chain_id: skm.key_id(),
+        iteration: skm.iteration(),
+        signing_key,
+    })
 }
 
 pub async fn process_sender_key_distribution_message(
@@ -127,13 +253,55 @@ pub async fn process_sender_key_distribution_message(
     sender_key_store: &mut dyn SenderKeyStore,
     ctx: Context,
 ) -> Result<()> {
+    process_sender_key_distribution_message_with_expected_id(
+        sender_key_name,
+        skdm,
+        None,
+        sender_key_store,
+        ctx,
+    )
+    .await?;
+    Ok(())
+}
+
+/// The key parameters of a processed [`SenderKeyDistributionMessage`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SenderKeyDistributionReport {
+    /// The sender key ID of the distributed chain.
+    pub chain_id: u32,
+    /// The iteration the chain starts at.
+    pub iteration: u32,
+}
+
+/// Like [`process_sender_key_distribution_message`], but refuses a message whose chain ID is not
+/// `expected_chain_id`, and reports the parameters of the chain that was stored.
+///
+/// A mismatched message fails with [`SignalProtocolError::SenderKeyDistributionIdMismatch`] and
+/// leaves the store untouched.
+pub async fn process_sender_key_distribution_message_with_expected_id(
+    sender_key_name: &SenderKeyName,
+    skdm: &SenderKeyDistributionMessage,
+    expected_chain_id: Option<u32>,
+    sender_key_store: &mut dyn SenderKeyStore,
+    ctx: Context,
+) -> Result<SenderKeyDistributionReport> {
+    let chain_id = skdm.id()?;
+    if let Some(expected) = expected_chain_id {
+        if expected != chain_id {
+            return Err(SignalProtocolError::SenderKeyDistributionIdMismatch {
+                expected,
+                found: chain_id,
+            });
+        }
+    }
+
     let mut sender_key_record = sender_key_store
         .load_sender_key(sender_key_name, ctx)
         .await?
         .unwrap_or_else(SenderKeyRecord::new_empty);
 
     sender_key_record.add_sender_key_state(
-        skdm.id()?,
+        chain_id,
         skdm.iteration()?,
         skdm.chain_key()?,
         *skdm.signing_key()?,
@@ -142,7 +310,10 @@ pub async fn process_sender_key_distribution_message(
     sender_key_store
         .store_sender_key(sender_key_name, &sender_key_record, ctx)
         .await?;
-    Ok(())
+    Ok(SenderKeyDistributionReport {
+        chain_id,
+        iteration: skdm.iteration()?,
+    })
 }
 
 pub async fn create_sender_key_distribution_message<R: Rng + CryptoRng>(
@@ -157,23 +328,78 @@ pub async fn create_sender_key_distribution_message<R: Rng + CryptoRng>(
         .unwrap_or_else(SenderKeyRecord::new_empty);
 
     if sender_key_record.is_empty()? {
-        // libsignal-protocol-java uses 31-bit integers for sender key IDs
-        let sender_key_id = (csprng.gen::<u32>()) >> 1;
-        let iteration = 0;
-        let sender_key: [u8; 32] = csprng.gen();
-        let signing_key = curve::KeyPair::generate(csprng);
-        sender_key_record.set_sender_key_state(
-            sender_key_id,
-            iteration,
-            &sender_key,
-            signing_key.public_key,
-            Some(signing_key.private_key),
-        )?;
+        add_new_sender_key_state(&mut sender_key_record, csprng)?;
         sender_key_store
             .store_sender_key(sender_key_name, &sender_key_record, ctx)
             .await?;
     }
 
+    current_distribution_message(&mut sender_key_record)
+}
+
+/// What [`rotate_sender_key`] does with the sender key states it replaces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SenderKeyRotationPolicy {
+    /// Drop the previous states entirely.
+    Discard,
+    /// Keep the previous states, minus their signing keys, so that messages already sent under
+    /// them can still be decrypted with this record.
+    Archive,
+}
+
+/// Replaces our sender key for `sender_key_name` with a freshly generated one, returning the
+/// distribution message to send to the remaining members.
+///
+/// Subsequent calls to [`group_encrypt`] use the new key. Recipients who do not receive the new
+/// distribution message (such as a member who was just removed) cannot decrypt those messages.
+pub async fn rotate_sender_key<R: Rng + CryptoRng>(
+    sender_key_name: &SenderKeyName,
+    sender_key_store: &mut dyn SenderKeyStore,
+    policy: SenderKeyRotationPolicy,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<SenderKeyDistributionMessage> {
+    let mut sender_key_record = match policy {
+        SenderKeyRotationPolicy::Discard => SenderKeyRecord::new_empty(),
+        SenderKeyRotationPolicy::Archive => {
+            let mut record = sender_key_store
+                .load_sender_key(sender_key_name, ctx)
+                .await?
+                .unwrap_or_else(SenderKeyRecord::new_empty);
+            record.remove_signing_keys_private();
+            record
+        }
+    };
+
+    add_new_sender_key_state(&mut sender_key_record, csprng)?;
+    sender_key_store
+        .store_sender_key(sender_key_name, &sender_key_record, ctx)
+        .await?;
+
+    current_distribution_message(&mut sender_key_record)
+}
+
+fn add_new_sender_key_state<R: Rng + CryptoRng>(
+    sender_key_record: &mut SenderKeyRecord,
+    csprng: &mut R,
+) -> Result<()> {
+    // libsignal-protocol-java uses 31-bit integers for sender key IDs
+    let sender_key_id = (csprng.gen::<u32>()) >> 1;
+    let iteration = 0;
+    let sender_key: [u8; 32] = csprng.gen();
+    let signing_key = curve::KeyPair::generate(csprng);
+    sender_key_record.add_sender_key_state(
+        sender_key_id,
+        iteration,
+        &sender_key,
+        signing_key.public_key,
+        Some(signing_key.private_key),
+    )
+}
+
+fn current_distribution_message(
+    sender_key_record: &mut SenderKeyRecord,
+) -> Result<SenderKeyDistributionMessage> {
     let state = sender_key_record.sender_key_state()?;
     let sender_chain_key = state.sender_chain_key()?;
 
@@ -9,11 +9,32 @@ use crate::proto;
 use crate::error::{Result, SignalProtocolError};
 
 use rand::{CryptoRng, Rng};
+use std::cmp::Ordering;
 use std::convert::TryFrom;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
 
 use prost::Message;
 
-#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Clone, Copy)]
+// Prepended to the signed message so that alternate identity signatures can never be mistaken for
+// signatures over a serialized public key, e.g. a signed pre-key.
+const ALTERNATE_IDENTITY_SIGNATURE_PREFIX_1: &[u8] = &[0xFF; 32];
+const ALTERNATE_IDENTITY_SIGNATURE_PREFIX_2: &[u8] = b"Signal_PNI_Signature";
+
+fn alternate_identity_message(other: &IdentityKey) -> Vec<u8> {
+    let other = other.serialize();
+    let mut message = Vec::with_capacity(
+        ALTERNATE_IDENTITY_SIGNATURE_PREFIX_1.len()
+            + ALTERNATE_IDENTITY_SIGNATURE_PREFIX_2.len()
+            + other.len(),
+    );
+    message.extend_from_slice(ALTERNATE_IDENTITY_SIGNATURE_PREFIX_1);
+    message.extend_from_slice(ALTERNATE_IDENTITY_SIGNATURE_PREFIX_2);
+    message.extend_from_slice(&other);
+    message
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct IdentityKey {
     public_key: curve::PublicKey,
 }
@@ -37,6 +58,35 @@ impl IdentityKey {
         let pk = curve::PublicKey::deserialize(value)?;
         Ok(Self { public_key: pk })
     }
+
+    /// Checks a signature produced by [`IdentityKeyPair::sign_alternate_identity`], i.e. that the
+    /// holder of this identity vouches for `other`.
+    pub fn verify_alternate_identity(&self, other: &IdentityKey, signature: &[u8]) -> Result<bool> {
+        self.public_key
+            .verify_signature(&alternate_identity_message(other), signature)
+    }
+}
+
+/// Compares the serialized keys in constant time, so that trust decisions do not leak timing.
+impl PartialEq for IdentityKey {
+    fn eq(&self, other: &IdentityKey) -> bool {
+        self.public_key == other.public_key
+    }
+}
+
+impl Eq for IdentityKey {}
+
+/// Orders keys by their serialized form, in constant time.
+impl Ord for IdentityKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.public_key.cmp(&other.public_key)
+    }
+}
+
+impl PartialOrd for IdentityKey {
+    fn partial_cmp(&self, other: &IdentityKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl TryFrom<&[u8]> for IdentityKey {
@@ -53,29 +103,76 @@ impl From<curve::PublicKey> for IdentityKey {
     }
 }
 
-#[derive(Copy, Clone)]
+/// The operations the protocol performs with an identity private key.
+///
+/// [`curve::PrivateKey`] implements this in software. A key the library can never read, such as
+/// one held in a hardware keystore, can implement it instead and be wrapped with
+/// [`IdentityKeyPair::with_operations`].
+pub trait PrivateKeyOperations: Send + Sync {
+    /// Signs `message` with XEdDSA. `randomness` is fresh from the caller's RNG; an implementation
+    /// that generates its own may ignore it.
+    fn calculate_signature(&self, message: &[u8], randomness: &[u8; 64]) -> Result<Box<[u8]>>;
+
+    /// Computes the X25519 shared secret with `their_key`.
+    fn calculate_agreement(&self, their_key: &curve::PublicKey) -> Result<Box<[u8]>>;
+}
+
+impl PrivateKeyOperations for curve::PrivateKey {
+    fn calculate_signature(&self, message: &[u8], randomness: &[u8; 64]) -> Result<Box<[u8]>> {
+        self.calculate_signature_with_randomness(message, randomness)
+    }
+
+    fn calculate_agreement(&self, their_key: &curve::PublicKey) -> Result<Box<[u8]>> {
+        curve::PrivateKey::calculate_agreement(self, their_key)
+    }
+}
+
+#[derive(Clone)]
+enum IdentityPrivateKey {
+    Software(curve::PrivateKey),
+    External(Arc<dyn PrivateKeyOperations>),
+}
+
+impl IdentityPrivateKey {
+    fn operations(&self) -> &dyn PrivateKeyOperations {
+        match self {
+            IdentityPrivateKey::Software(key) => key,
+            IdentityPrivateKey::External(operations) => operations.as_ref(),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct IdentityKeyPair {
     identity_key: IdentityKey,
-    private_key: curve::PrivateKey,
+    private_key: IdentityPrivateKey,
 }
 
 impl IdentityKeyPair {
     pub fn new(identity_key: IdentityKey, private_key: curve::PrivateKey) -> Self {
         Self {
             identity_key,
-            private_key,
+            private_key: IdentityPrivateKey::Software(private_key),
         }
     }
 
-    pub fn generate<R: CryptoRng + Rng>(csprng: &mut R) -> Self {
-        let keypair = curve::KeyPair::generate(csprng);
-
+    /// Creates a key pair whose private key is only reachable through `operations`. Such a pair
+    /// can sign and agree on keys, but [`private_key`](Self::private_key) and
+    /// [`serialize`](Self::serialize) fail with `OperationNotSupported`.
+    pub fn with_operations(
+        identity_key: IdentityKey,
+        operations: Arc<dyn PrivateKeyOperations>,
+    ) -> Self {
         Self {
-            identity_key: keypair.public_key.into(),
-            private_key: keypair.private_key,
+            identity_key,
+            private_key: IdentityPrivateKey::External(operations),
         }
     }
 
+    pub fn generate<R: CryptoRng + Rng>(csprng: &mut R) -> Self {
+        curve::KeyPair::generate(csprng).into()
+    }
+
     #[inline]
     pub fn identity_key(&self) -> &IdentityKey {
         &self.identity_key
@@ -86,19 +183,72 @@ impl IdentityKeyPair {
         &self.identity_key.public_key()
     }
 
-    #[inline]
-    pub fn private_key(&self) -> &curve::PrivateKey {
-        &self.private_key
+    pub fn private_key(&self) -> Result<&curve::PrivateKey> {
+        match &self.private_key {
+            IdentityPrivateKey::Software(key) => Ok(key),
+            IdentityPrivateKey::External(_) => Err(SignalProtocolError::OperationNotSupported(
+                "reading an external identity private key",
+            )),
+        }
     }
 
-    pub fn serialize(&self) -> Box<[u8]> {
+    pub fn calculate_signature<R: CryptoRng + Rng>(
+        &self,
+        message: &[u8],
+        csprng: &mut R,
+    ) -> Result<Box<[u8]>> {
+        let mut randomness = [0u8; 64];
+        csprng.fill_bytes(&mut randomness);
+        self.private_key
+            .operations()
+            .calculate_signature(message, &randomness)
+    }
+
+    /// Computes the X25519 shared secret with `their_key`, rejecting the all-zero result of a
+    /// low-order public key as [`curve::PrivateKey::calculate_agreement_checked`] does.
+    pub fn calculate_agreement(&self, their_key: &curve::PublicKey) -> Result<[u8; 32]> {
+        let agreement = self
+            .private_key
+            .operations()
+            .calculate_agreement(their_key)?;
+        let agreement = <[u8; 32]>::try_from(&agreement[..]).map_err(|_| {
+            SignalProtocolError::InvalidArgument(format!(
+                "X25519 agreement must be 32 bytes, not {}",
+                agreement.len()
+            ))
+        })?;
+        if bool::from(agreement.ct_eq(&[0u8; 32])) {
+            return Err(SignalProtocolError::InvalidArgument(
+                "X25519 agreement produced an all-zero shared secret (low-order public key)"
+                    .to_string(),
+            ));
+        }
+        Ok(agreement)
+    }
+
+    /// Signs a statement that `other` is an alternate identity belonging to the same user (for
+    /// example, linking a phone number identity to an account identity).
+    ///
+    /// The signed message is domain-separated, so the result cannot be confused with an ordinary
+    /// signature made by this key. Check it with [`IdentityKey::verify_alternate_identity`].
+    pub fn sign_alternate_identity<R: CryptoRng + Rng>(
+        &self,
+        other: &IdentityKey,
+        csprng: &mut R,
+    ) -> Result<Vec<u8>> {
+        Ok(self
+            .calculate_signature(&alternate_identity_message(other), csprng)?
+            .into_vec())
+    }
+
+    pub fn serialize(&self) -> Result<Box<[u8]>> {
         let structure = proto::storage::IdentityKeyPairStructure {
             public_key: self.identity_key.serialize().to_vec(),
-            private_key: self.private_key.serialize().to_vec(),
+            private_key: self.private_key()?.serialize().to_vec(),
         };
         let mut result = Vec::new();
-        structure.encode(&mut result).unwrap();
-        result.into_boxed_slice()
+        structure.encode(&mut result)?;
+        Ok(result.into_boxed_slice())
     }
 }
 
@@ -107,19 +257,16 @@ impl TryFrom<&[u8]> for IdentityKeyPair {
 
     fn try_from(value: &[u8]) -> Result<Self> {
         let structure = proto::storage::IdentityKeyPairStructure::decode(value)?;
-        Ok(Self {
-            identity_key: IdentityKey::try_from(&structure.public_key[..])?,
-            private_key: curve::PrivateKey::deserialize(&structure.private_key)?,
-        })
+        Ok(Self::new(
+            IdentityKey::try_from(&structure.public_key[..])?,
+            curve::PrivateKey::deserialize(&structure.private_key)?,
+        ))
     }
 }
 
 impl From<curve::KeyPair> for IdentityKeyPair {
     fn from(value: curve::KeyPair) -> Self {
-        Self {
-            identity_key: value.public_key.into(),
-            private_key: value.private_key,
-        }
+        Self::new(value.public_key.into(), value.private_key)
     }
 }
 
@@ -137,22 +284,150 @@ mod tests {
         assert_eq!(key_pair_public_serialized, identity_key.serialize());
     }
 
+    #[test]
+    fn test_identity_key_comparison() {
+        let key_pair = curve::KeyPair::generate(&mut OsRng);
+        let serialized = key_pair.public_key.serialize();
+        let identity_key = IdentityKey::decode(&serialized).unwrap();
+
+        let mut modified = serialized.to_vec();
+        modified[32] ^= 0x01;
+        let other_key = IdentityKey::decode(&modified).unwrap();
+
+        assert_ne!(identity_key, other_key);
+        assert_eq!(
+            identity_key.cmp(&other_key),
+            serialized[..].cmp(&modified[..])
+        );
+        assert_eq!(
+            identity_key,
+            IdentityKey::decode(&identity_key.serialize()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_identity_key_ordering_is_stable() {
+        let mut keys: Vec<IdentityKey> = (0..20)
+            .map(|_| IdentityKey::from(curve::KeyPair::generate(&mut OsRng).public_key))
+            .collect();
+        keys.sort();
+
+        let mut round_tripped: Vec<IdentityKey> = keys
+            .iter()
+            .rev()
+            .map(|k| IdentityKey::decode(&k.serialize()).unwrap())
+            .collect();
+        round_tripped.sort();
+        assert_eq!(keys, round_tripped);
+
+        let mut serialized: Vec<Box<[u8]>> = keys.iter().map(|k| k.serialize()).collect();
+        serialized.sort();
+        assert_eq!(
+            serialized,
+            keys.iter().map(|k| k.serialize()).collect::<Vec<_>>()
+        );
+
+        let map: std::collections::BTreeMap<IdentityKey, usize> =
+            keys.iter().enumerate().map(|(i, k)| (*k, i)).collect();
+        for (i, k) in round_tripped.iter().enumerate() {
+            assert_eq!(map[k], i);
+        }
+    }
+
+    #[test]
+    fn test_alternate_identity_signing() {
+        let primary = IdentityKeyPair::generate(&mut OsRng);
+        let secondary = IdentityKeyPair::generate(&mut OsRng);
+
+        let signature = secondary
+            .sign_alternate_identity(primary.identity_key(), &mut OsRng)
+            .unwrap();
+        assert!(secondary
+            .identity_key()
+            .verify_alternate_identity(primary.identity_key(), &signature)
+            .unwrap());
+        // Not symmetric.
+        assert!(!primary
+            .identity_key()
+            .verify_alternate_identity(secondary.identity_key(), &signature)
+            .unwrap());
+        // Not an ordinary signature over the other key.
+        assert!(!secondary
+            .public_key()
+            .verify_signature(&primary.identity_key().serialize(), &signature)
+            .unwrap());
+
+        let ordinary = secondary
+            .calculate_signature(&primary.identity_key().serialize(), &mut OsRng)
+            .unwrap();
+        assert!(!secondary
+            .identity_key()
+            .verify_alternate_identity(primary.identity_key(), &ordinary)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_alternate_identity_known_vector() {
+        let primary = IdentityKey::decode(
+            &hex::decode("05a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8a209")
+                .unwrap(),
+        )
+        .unwrap();
+        let secondary: IdentityKeyPair = curve::KeyPair::from_seed(&[0x02; 32]).unwrap().into();
+        assert_eq!(
+            hex::encode(secondary.identity_key().serialize()),
+            "05ce8d3ad1ccb633ec7b70c17814a5c76ecd029685050d344745ba05870e587d59"
+        );
+
+        let message = alternate_identity_message(&primary);
+        assert_eq!(
+            hex::encode(&message),
+            format!(
+                "{}{}{}",
+                "ff".repeat(32),
+                "5369676e616c5f504e495f5369676e6174757265", // "Signal_PNI_Signature"
+                "05a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8a209"
+            )
+        );
+
+        let signature = secondary
+            .private_key()
+            .unwrap()
+            .calculate_signature_with_randomness(&message, &[0x5a; 64])
+            .unwrap();
+        assert_eq!(
+            hex::encode(&signature),
+            "f830ada06052f15e498012149f957fa66f7700fc07988de28941036639a6c279\
+             dbe4d24fefc843e83cd801b47660526b17079fcb2d4ac5258554f2a3d3814f0d"
+        );
+        assert!(secondary
+            .identity_key()
+            .verify_alternate_identity(&primary, &signature)
+            .unwrap());
+    }
+
     #[test]
     fn test_serialize_identity_key_pair() {
         let identity_key_pair = IdentityKeyPair::generate(&mut OsRng);
-        let serialized = identity_key_pair.serialize();
+        let serialized = identity_key_pair.serialize().unwrap();
         let deserialized_identity_key_pair = IdentityKeyPair::try_from(&serialized[..]).unwrap();
         assert_eq!(
             identity_key_pair.identity_key(),
             deserialized_identity_key_pair.identity_key()
         );
         assert_eq!(
-            identity_key_pair.private_key().key_type(),
-            deserialized_identity_key_pair.private_key().key_type()
+            identity_key_pair.private_key().unwrap().key_type(),
+            deserialized_identity_key_pair
+                .private_key()
+                .unwrap()
+                .key_type()
         );
         assert_eq!(
-            identity_key_pair.private_key().serialize(),
-            deserialized_identity_key_pair.private_key().serialize()
+            identity_key_pair.private_key().unwrap().serialize(),
+            deserialized_identity_key_pair
+                .private_key()
+                .unwrap()
+                .serialize()
         );
     }
 }
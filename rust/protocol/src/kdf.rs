@@ -8,6 +8,12 @@ use crate::error::{Result, SignalProtocolError};
 use hmac::{Hmac, Mac, NewMac};
 use sha2::Sha256;
 
+/// HKDF-SHA256 as used by the Signal protocol itself.
+///
+/// Version 3 messages use standard RFC 5869 HKDF. Version 2 messages used a variant whose
+/// expansion counter starts at 0 instead of 1; it is kept so that old sessions can still be read.
+/// Applications deriving their own keys should use this type (with the version their messages
+/// use) so that they stay consistent with the protocol's own derivations.
 #[derive(Clone, Copy, Debug)]
 pub struct HKDF {
     iteration_start_offset: u8,
@@ -16,6 +22,8 @@ pub struct HKDF {
 impl HKDF {
     const HASH_OUTPUT_SIZE: usize = 32;
 
+    /// Returns the KDF used for the given message version, or
+    /// [`SignalProtocolError::UnrecognizedMessageVersion`] if there is none.
     pub fn new(message_version: u32) -> Result<Self> {
         match message_version {
             2 => Ok(HKDF {
@@ -30,6 +38,7 @@ impl HKDF {
         }
     }
 
+    /// Derives `output_length` bytes from `input_key_material` using an all-zero salt.
     pub fn derive_secrets(
         self,
         input_key_material: &[u8],
@@ -44,6 +53,9 @@ impl HKDF {
         )
     }
 
+    /// Derives `output_length` bytes from `input_key_material` and `salt`.
+    ///
+    /// At most 255 (or, for version 2, 256) hash blocks of output can be produced.
     pub fn derive_salted_secrets(
         self,
         input_key_material: &[u8],
@@ -63,13 +75,15 @@ impl HKDF {
         crate::crypto::hmac_sha256(salt, input_key_material)
     }
 
-    fn expand(
-        self,
-        prk: &[u8; Self::HASH_OUTPUT_SIZE],
-        info: &[u8],
-        output_length: usize,
-    ) -> Result<Box<[u8]>> {
+    /// The expansion step on its own, for a pseudorandom key the caller already has.
+    pub(crate) fn expand(self, prk: &[u8], info: &[u8], output_length: usize) -> Result<Box<[u8]>> {
         let iterations = (output_length + Self::HASH_OUTPUT_SIZE - 1) / Self::HASH_OUTPUT_SIZE;
+        if iterations > 256 - self.iteration_start_offset as usize {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "HKDF output length {} is too large",
+                output_length
+            )));
+        }
         let mut result = Vec::<u8>::with_capacity(iterations * Self::HASH_OUTPUT_SIZE);
         let mut mac =
             Hmac::<Sha256>::new_varkey(prk).expect("HMAC-SHA256 should accept any size key");
@@ -185,4 +199,36 @@ mod tests {
 
         assert_eq!(&okm[..], &output[..]);
     }
+
+    #[test]
+    fn test_versions_and_output_length() {
+        assert!(matches!(
+            HKDF::new(1),
+            Err(SignalProtocolError::UnrecognizedMessageVersion(1))
+        ));
+        assert!(matches!(
+            HKDF::new(4),
+            Err(SignalProtocolError::UnrecognizedMessageVersion(4))
+        ));
+
+        let v3 = HKDF::new(3).unwrap();
+        assert_eq!(
+            v3.derive_secrets(b"ikm", b"info", 255 * 32).unwrap().len(),
+            255 * 32
+        );
+        assert!(matches!(
+            v3.derive_secrets(b"ikm", b"info", 255 * 32 + 1),
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+
+        let v2 = HKDF::new(2).unwrap();
+        assert_eq!(
+            v2.derive_secrets(b"ikm", b"info", 256 * 32).unwrap().len(),
+            256 * 32
+        );
+        assert!(matches!(
+            v2.derive_secrets(b"ikm", b"info", 256 * 32 + 1),
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+    }
 }
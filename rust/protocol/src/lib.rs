@@ -12,50 +12,92 @@ mod crypto;
 mod curve;
 mod error;
 mod fingerprint;
+#[cfg(feature = "fuzz")]
+pub mod fuzz_targets;
 mod group_cipher;
 mod identity_key;
 mod kdf;
+mod padding;
 mod proto;
 mod protocol;
 mod ratchet;
+mod sealed_sender;
 mod sender_keys;
 mod session;
 mod session_cipher;
 mod state;
 mod storage;
+#[cfg(feature = "test-rng")]
+mod test_rng;
 mod utils;
 
 pub use {
-    address::ProtocolAddress,
-    curve::{KeyPair, PrivateKey, PublicKey},
-    error::SignalProtocolError,
-    fingerprint::{DisplayableFingerprint, Fingerprint, ScannableFingerprint},
+    address::{DeviceId, ProtocolAddress, ServiceId, SERVICE_ID_BINARY_LEN},
+    crypto::{
+        aes_256_cbc_decrypt, aes_256_cbc_decrypt_into, aes_256_cbc_encrypt,
+        aes_256_cbc_encrypt_into, aes_256_cbc_encrypt_then_mac, aes_256_cbc_verify_then_decrypt,
+        aes_256_ctr_decrypt, aes_256_ctr_encrypt, aes_256_gcm_decrypt, aes_256_gcm_encrypt,
+        aes_256_gcm_siv_decrypt, aes_256_gcm_siv_encrypt, committing_aead_decrypt,
+        committing_aead_encrypt, hkdf_expand_only, hkdf_sha256, hmac_sha256_verify, hmac_sha512,
+        sha512, Aes256CbcDecryptStream, Aes256CbcEncryptStream,
+    },
+    curve::{verify_signatures_batch, KeyPair, PrivateKey, PublicKey},
+    error::{ErrorCause, SignalProtocolError},
+    fingerprint::{
+        DisplayableFingerprint, Fingerprint, FingerprintComparison, ScannableFingerprint,
+        MIN_FINGERPRINT_ITERATIONS, MULTI_IDENTIFIER_FINGERPRINT_VERSION,
+    },
     group_cipher::{
-        create_sender_key_distribution_message, group_decrypt, group_encrypt,
-        process_sender_key_distribution_message,
+        create_sender_key_distribution_message, group_decrypt, group_decrypt_into,
+        group_decrypt_with_config, group_decrypt_with_metadata, group_encrypt,
+        group_encrypt_with_metadata, process_sender_key_distribution_message,
+        process_sender_key_distribution_message_with_expected_id, rotate_sender_key,
+        GroupCipherConfig, GroupDecryptionMetadata, GroupEncryptedMessage,
+        SenderKeyDistributionReport, SenderKeyRotationPolicy,
     },
-    identity_key::{IdentityKey, IdentityKeyPair},
+    identity_key::{IdentityKey, IdentityKeyPair, PrivateKeyOperations},
     kdf::HKDF,
+    padding::{pad_message, unpad_message},
     protocol::{
-        CiphertextMessage, CiphertextMessageType, PreKeySignalMessage,
+        extract_decryption_error_message_from_serialized_content, CiphertextMessage,
+        CiphertextMessageType, DecryptionErrorMessage, PlaintextContent, PreKeySignalMessage,
         SenderKeyDistributionMessage, SenderKeyMessage, SignalMessage,
     },
     ratchet::{
         are_we_alice, initialize_alice_session, initialize_bob_session,
         AliceSignalProtocolParameters, BobSignalProtocolParameters, ChainKey, MessageKeys, RootKey,
     },
+    sealed_sender::{
+        derive_unidentified_access_key, unrestricted_unidentified_access_key,
+        UNIDENTIFIED_ACCESS_KEY_LEN,
+    },
     sender_keys::{
         SenderChainKey, SenderKeyName, SenderKeyRecord, SenderKeyState, SenderMessageKey,
     },
     session::*,
     session_cipher::{
-        message_decrypt, message_decrypt_prekey, message_decrypt_signal, message_encrypt,
-        remote_registration_id, session_version,
+        message_decrypt, message_decrypt_batch, message_decrypt_prekey,
+        message_decrypt_prekey_transactional, message_decrypt_prekey_with_config,
+        message_decrypt_signal, message_decrypt_signal_transactional,
+        message_decrypt_signal_with_config, message_decrypt_transactional,
+        message_decrypt_with_config, message_decrypt_with_report, message_encrypt,
+        message_encrypt_with_details, remote_registration_id, session_version, DecryptionReport,
+        EncryptedMessage, ReadOnlySessionStores, SessionConfig, SessionStateUsed, SessionStores,
+    },
+    state::{
+        PreKeyBundle, PreKeyRecord, RegistrationId, SessionRecord, SessionState, SignedPreKeyRecord,
     },
-    state::{PreKeyBundle, PreKeyRecord, SessionRecord, SessionState, SignedPreKeyRecord},
     storage::{
-        Context, Direction, IdentityKeyStore, InMemIdentityKeyStore, InMemPreKeyStore,
-        InMemSenderKeyStore, InMemSessionStore, InMemSignalProtocolStore, InMemSignedPreKeyStore,
-        PreKeyStore, ProtocolStore, SenderKeyStore, SessionStore, SignedPreKeyStore,
+        remove_expired_signed_pre_keys, Context, Direction, IdentityChange, IdentityKeyStore,
+        InMemIdentityKeyStore, InMemPreKeyStore, InMemSenderKeyStore, InMemSessionStore,
+        InMemSignalProtocolStore, InMemSignedPreKeyStore, PreKeyStore, ProtocolStore,
+        SenderKeyStore, SessionStore, SignedPreKeyStore, StoreOp, StoreTransaction,
+        TransactionalStore,
     },
 };
+
+#[cfg(feature = "fs-store")]
+pub use storage::{FileSignalProtocolStore, FILE_STORE_VERSION};
+
+#[cfg(feature = "test-rng")]
+pub use test_rng::TestRng;
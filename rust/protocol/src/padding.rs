@@ -0,0 +1,71 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! The padding applied to message bodies before they are encrypted, so that the ciphertext only
+//! reveals the length to the nearest bucket.
+//!
+//! The body is followed by a 0x80 terminator and then zeros. Padded bodies are one byte short of
+//! a multiple of [PADDING_BUCKET_LEN], as in the Android client.
+
+use crate::error::{Result, SignalProtocolError};
+
+const PADDING_BUCKET_LEN: usize = 160;
+const PADDING_TERMINATOR: u8 = 0x80;
+
+/// Pads `body` to the end of its bucket.
+pub fn pad_message(body: &[u8]) -> Vec<u8> {
+    // Room for the terminator, plus the byte the Android client leaves out of every bucket.
+    let bucket_count = (body.len() + 2 + PADDING_BUCKET_LEN - 1) / PADDING_BUCKET_LEN;
+    let mut padded = vec![0u8; bucket_count * PADDING_BUCKET_LEN - 1];
+    padded[..body.len()].copy_from_slice(body);
+    padded[body.len()] = PADDING_TERMINATOR;
+    padded
+}
+
+/// Strips the padding added by [pad_message].
+///
+/// Any number of trailing zeros is accepted, but they must follow a terminator.
+pub fn unpad_message(padded: &[u8]) -> Result<&[u8]> {
+    match padded.iter().rposition(|&b| b != 0) {
+        Some(end) if padded[end] == PADDING_TERMINATOR => Ok(&padded[..end]),
+        Some(_) => Err(SignalProtocolError::InvalidMessage(
+            "message padding is malformed",
+        )),
+        None => Err(SignalProtocolError::InvalidMessage(
+            "message padding has no terminator",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_boundaries() {
+        assert_eq!(pad_message(&[]).len(), 159);
+        assert_eq!(pad_message(&[1; 158]).len(), 159);
+        assert_eq!(pad_message(&[1; 159]).len(), 319);
+        assert_eq!(pad_message(&[1; 318]).len(), 319);
+        assert_eq!(pad_message(&[1; 319]).len(), 479);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_trailing_terminators_and_zeros() {
+        let body = [1, 0x80, 0, 0x80, 0];
+        assert_eq!(unpad_message(&pad_message(&body)).unwrap(), &body);
+    }
+
+    #[test]
+    fn test_malformed_padding_is_rejected() {
+        for padded in &[&[][..], &[0, 0, 0], &[1, 2, 3], &[0x80, 0, 1]] {
+            assert!(matches!(
+                unpad_message(padded),
+                Err(SignalProtocolError::InvalidMessage(_))
+            ));
+        }
+        assert_eq!(unpad_message(&[0x80]).unwrap(), &[] as &[u8]);
+    }
+}
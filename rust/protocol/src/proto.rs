@@ -6,3 +6,22 @@
 pub mod fingerprint;
 pub mod storage;
 pub mod wire;
+
+/// Reads the `uint32` field `tag` from the top level of an encoded message, skipping the other
+/// fields without decoding them. Returns 0, the protobuf default, if the field is absent.
+pub(crate) fn peek_uint32_field(mut buf: &[u8], tag: u32) -> Result<u32, prost::DecodeError> {
+    use prost::encoding::{check_wire_type, decode_key, decode_varint, skip_field};
+    use prost::encoding::{DecodeContext, WireType};
+
+    let mut value = 0;
+    while !buf.is_empty() {
+        let (field, wire_type) = decode_key(&mut buf)?;
+        if field == tag {
+            check_wire_type(WireType::Varint, wire_type)?;
+            value = decode_varint(&mut buf)? as u32;
+        } else {
+            skip_field(wire_type, field, &mut buf, DecodeContext::default())?;
+        }
+    }
+    Ok(value)
+}
@@ -4,16 +4,15 @@
 //
 
 use crate::error::{Result, SignalProtocolError};
+use crate::session_cipher::SessionConfig;
+use crate::state::RegistrationId;
 use crate::IdentityKey;
-use crate::{curve, proto};
+use crate::{crypto, curve, proto};
 
 use std::convert::TryFrom;
 
-use hmac::{Hmac, Mac, NewMac};
 use prost::Message;
 use rand::{CryptoRng, Rng};
-use sha2::Sha256;
-use subtle::ConstantTimeEq;
 
 pub const CIPHERTEXT_MESSAGE_CURRENT_VERSION: u8 = 3;
 
@@ -22,6 +21,7 @@ pub enum CiphertextMessage {
     PreKeySignalMessage(PreKeySignalMessage),
     SenderKeyMessage(SenderKeyMessage),
     SenderKeyDistributionMessage(SenderKeyDistributionMessage),
+    PlaintextContent(PlaintextContent),
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -30,9 +30,56 @@ pub enum CiphertextMessageType {
     PreKey = 3,
     SenderKey = 4,
     SenderKeyDistribution = 5,
+    Plaintext = 8,
+}
+
+impl TryFrom<u8> for CiphertextMessageType {
+    type Error = SignalProtocolError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            2 => Ok(CiphertextMessageType::Whisper),
+            3 => Ok(CiphertextMessageType::PreKey),
+            4 => Ok(CiphertextMessageType::SenderKey),
+            5 => Ok(CiphertextMessageType::SenderKeyDistribution),
+            8 => Ok(CiphertextMessageType::Plaintext),
+            _ => Err(SignalProtocolError::UnrecognizedCiphertextMessageType(
+                value,
+            )),
+        }
+    }
+}
+
+impl From<CiphertextMessageType> for u8 {
+    fn from(message_type: CiphertextMessageType) -> Self {
+        message_type as u8
+    }
 }
 
 impl CiphertextMessage {
+    /// Parses a message received with the given envelope type.
+    pub fn deserialize(message_type: CiphertextMessageType, bytes: &[u8]) -> Result<Self> {
+        Ok(match message_type {
+            CiphertextMessageType::Whisper => {
+                CiphertextMessage::SignalMessage(SignalMessage::try_from(bytes)?)
+            }
+            CiphertextMessageType::PreKey => {
+                CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(bytes)?)
+            }
+            CiphertextMessageType::SenderKey => {
+                CiphertextMessage::SenderKeyMessage(SenderKeyMessage::try_from(bytes)?)
+            }
+            CiphertextMessageType::SenderKeyDistribution => {
+                CiphertextMessage::SenderKeyDistributionMessage(
+                    SenderKeyDistributionMessage::try_from(bytes)?,
+                )
+            }
+            CiphertextMessageType::Plaintext => {
+                CiphertextMessage::PlaintextContent(PlaintextContent::try_from(bytes)?)
+            }
+        })
+    }
+
     pub fn message_type(&self) -> CiphertextMessageType {
         match self {
             CiphertextMessage::SignalMessage(_) => CiphertextMessageType::Whisper,
@@ -41,6 +88,7 @@ impl CiphertextMessage {
             CiphertextMessage::SenderKeyDistributionMessage(_) => {
                 CiphertextMessageType::SenderKeyDistribution
             }
+            CiphertextMessage::PlaintextContent(_) => CiphertextMessageType::Plaintext,
         }
     }
 
@@ -50,6 +98,7 @@ impl CiphertextMessage {
             CiphertextMessage::PreKeySignalMessage(x) => x.serialized(),
             CiphertextMessage::SenderKeyMessage(x) => x.serialized(),
             CiphertextMessage::SenderKeyDistributionMessage(x) => x.serialized(),
+            CiphertextMessage::PlaintextContent(x) => x.serialized(),
         }
     }
 }
@@ -106,6 +155,14 @@ impl SignalMessage {
         })
     }
 
+    /// Parses a message like `try_from`, additionally rejecting message versions outside
+    /// `config.accepted_versions`.
+    pub fn try_from_with_config(value: &[u8], config: &SessionConfig) -> Result<Self> {
+        let message = Self::try_from(value)?;
+        config.check_version(message.message_version())?;
+        Ok(message)
+    }
+
     #[inline]
     pub fn message_version(&self) -> u8 {
         self.message_version
@@ -131,20 +188,47 @@ impl SignalMessage {
         &*self.ciphertext
     }
 
+    /// Checks the message's truncated MAC, as decryption does, without needing a session.
+    ///
+    /// The MAC covers both identity keys and the whole serialized message (including its version
+    /// byte) except the MAC itself. Returns `Ok(false)` on a mismatch.
     pub fn verify_mac(
         &self,
         sender_identity_key: &IdentityKey,
         receiver_identity_key: &IdentityKey,
         mac_key: &[u8],
     ) -> Result<bool> {
-        let our_mac = &Self::compute_mac(
-            sender_identity_key,
-            receiver_identity_key,
-            mac_key,
-            &self.serialized[..self.serialized.len() - Self::MAC_LENGTH],
-        )?;
-        let their_mac = &self.serialized[self.serialized.len() - Self::MAC_LENGTH..];
-        Ok(our_mac.ct_eq(their_mac).into())
+        if mac_key.len() != 32 {
+            return Err(SignalProtocolError::InvalidMacKeyLength(mac_key.len()));
+        }
+
+        let (message, their_mac) = self
+            .serialized
+            .split_at(self.serialized.len() - Self::MAC_LENGTH);
+        let mac_input = Self::mac_input(sender_identity_key, receiver_identity_key, message);
+
+        match crypto::hmac_sha256_verify(mac_key, &mac_input, their_mac) {
+            Ok(()) => Ok(true),
+            Err(SignalProtocolError::InvalidCiphertext) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn mac_input(
+        sender_identity_key: &IdentityKey,
+        receiver_identity_key: &IdentityKey,
+        message: &[u8],
+    ) -> Vec<u8> {
+        let sender_identity_key = sender_identity_key.public_key().serialize();
+        let receiver_identity_key = receiver_identity_key.public_key().serialize();
+
+        let mut mac_input = Vec::with_capacity(
+            sender_identity_key.len() + receiver_identity_key.len() + message.len(),
+        );
+        mac_input.extend_from_slice(&sender_identity_key);
+        mac_input.extend_from_slice(&receiver_identity_key);
+        mac_input.extend_from_slice(message);
+        mac_input
     }
 
     fn compute_mac(
@@ -156,18 +240,10 @@ impl SignalMessage {
         if mac_key.len() != 32 {
             return Err(SignalProtocolError::InvalidMacKeyLength(mac_key.len()));
         }
-        let mut mac = Hmac::<Sha256>::new_varkey(mac_key).map_err(|_| {
-            SignalProtocolError::InvalidArgument(format!(
-                "Invalid HMAC key length <{}>",
-                mac_key.len()
-            ))
-        })?;
-
-        mac.update(sender_identity_key.public_key().serialize().as_ref());
-        mac.update(receiver_identity_key.public_key().serialize().as_ref());
-        mac.update(message);
+        let mac_input = Self::mac_input(sender_identity_key, receiver_identity_key, message);
+        let mac = crypto::hmac_sha256(mac_key, &mac_input)?;
         let mut result = [0u8; Self::MAC_LENGTH];
-        result.copy_from_slice(&mac.finalize().into_bytes()[..Self::MAC_LENGTH]);
+        result.copy_from_slice(&mac[..Self::MAC_LENGTH]);
         Ok(result)
     }
 }
@@ -228,7 +304,7 @@ impl TryFrom<&[u8]> for SignalMessage {
 #[derive(Debug, Clone)]
 pub struct PreKeySignalMessage {
     message_version: u8,
-    registration_id: u32,
+    registration_id: RegistrationId,
     pre_key_id: Option<u32>,
     signed_pre_key_id: u32,
     base_key: curve::PublicKey,
@@ -240,7 +316,7 @@ pub struct PreKeySignalMessage {
 impl PreKeySignalMessage {
     pub fn new(
         message_version: u8,
-        registration_id: u32,
+        registration_id: RegistrationId,
         pre_key_id: Option<u32>,
         signed_pre_key_id: u32,
         base_key: curve::PublicKey,
@@ -248,7 +324,7 @@ impl PreKeySignalMessage {
         message: SignalMessage,
     ) -> Result<Self> {
         let proto_message = proto::wire::PreKeySignalMessage {
-            registration_id: Some(registration_id),
+            registration_id: Some(registration_id.into()),
             pre_key_id,
             signed_pre_key_id: Some(signed_pre_key_id),
             base_key: Some(base_key.serialize().into_vec()),
@@ -270,13 +346,21 @@ impl PreKeySignalMessage {
         })
     }
 
+    /// Parses a message like `try_from`, additionally rejecting message versions outside
+    /// `config.accepted_versions`.
+    pub fn try_from_with_config(value: &[u8], config: &SessionConfig) -> Result<Self> {
+        let message = Self::try_from(value)?;
+        config.check_version(message.message_version())?;
+        Ok(message)
+    }
+
     #[inline]
     pub fn message_version(&self) -> u8 {
         self.message_version
     }
 
     #[inline]
-    pub fn registration_id(&self) -> u32 {
+    pub fn registration_id(&self) -> RegistrationId {
         self.registration_id
     }
 
@@ -346,10 +430,13 @@ impl TryFrom<&[u8]> for PreKeySignalMessage {
         {
             return Err(SignalProtocolError::InvalidProtobufEncoding);
         }
+        let registration_id = proto_structure.registration_id.unwrap_or(0);
+        let registration_id = RegistrationId::try_from(registration_id)
+            .map_err(|_| SignalProtocolError::InvalidRegistrationId(registration_id))?;
         let base_key = curve::decode_point(proto_structure.base_key.unwrap().as_ref())?;
         Ok(PreKeySignalMessage {
             message_version,
-            registration_id: proto_structure.registration_id.unwrap_or(0),
+            registration_id,
             pre_key_id: proto_structure.pre_key_id,
             signed_pre_key_id: proto_structure.signed_pre_key_id.unwrap(),
             base_key,
@@ -618,6 +705,195 @@ impl TryFrom<&[u8]> for SenderKeyDistributionMessage {
     }
 }
 
+/// A request for the sender of an undecryptable message to reset the session it was sent on.
+///
+/// This is sent back to the original sender as a [PlaintextContent], since by definition there is
+/// no working session to encrypt it with.
+#[derive(Debug, Clone)]
+pub struct DecryptionErrorMessage {
+    ratchet_key: Option<curve::PublicKey>,
+    timestamp: u64,
+    device_id: u32,
+    serialized: Box<[u8]>,
+}
+
+impl DecryptionErrorMessage {
+    /// Builds an error message for `original_bytes`, a message of type `original_type` that
+    /// failed to decrypt.
+    ///
+    /// For 1:1 messages the sender's ratchet key is extracted so that the sender can tell whether
+    /// the session has since moved on; sender key messages carry no ratchet key.
+    pub fn for_original(
+        original_bytes: &[u8],
+        original_type: CiphertextMessageType,
+        original_timestamp: u64,
+        original_sender_device_id: u32,
+    ) -> Result<Self> {
+        let ratchet_key = match original_type {
+            CiphertextMessageType::Whisper => {
+                Some(*SignalMessage::try_from(original_bytes)?.sender_ratchet_key())
+            }
+            CiphertextMessageType::PreKey => Some(
+                *PreKeySignalMessage::try_from(original_bytes)?
+                    .message()
+                    .sender_ratchet_key(),
+            ),
+            CiphertextMessageType::SenderKey => None,
+            CiphertextMessageType::SenderKeyDistribution | CiphertextMessageType::Plaintext => {
+                return Err(SignalProtocolError::InvalidArgument(format!(
+                    "cannot create a DecryptionErrorMessage for a {:?} message",
+                    original_type
+                )));
+            }
+        };
+
+        let proto_message = proto::wire::DecryptionErrorMessage {
+            ratchet_key: ratchet_key.map(|k| k.serialize().to_vec()),
+            timestamp: Some(original_timestamp),
+            device_id: Some(original_sender_device_id),
+        };
+        let mut serialized = Vec::with_capacity(proto_message.encoded_len());
+        proto_message.encode(&mut serialized)?;
+
+        Ok(Self {
+            ratchet_key,
+            timestamp: original_timestamp,
+            device_id: original_sender_device_id,
+            serialized: serialized.into_boxed_slice(),
+        })
+    }
+
+    #[inline]
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    #[inline]
+    pub fn ratchet_key(&self) -> Option<&curve::PublicKey> {
+        self.ratchet_key.as_ref()
+    }
+
+    #[inline]
+    pub fn device_id(&self) -> u32 {
+        self.device_id
+    }
+
+    #[inline]
+    pub fn serialized(&self) -> &[u8] {
+        &*self.serialized
+    }
+}
+
+impl AsRef<[u8]> for DecryptionErrorMessage {
+    fn as_ref(&self) -> &[u8] {
+        &*self.serialized
+    }
+}
+
+impl TryFrom<&[u8]> for DecryptionErrorMessage {
+    type Error = SignalProtocolError;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let proto_structure = proto::wire::DecryptionErrorMessage::decode(value)?;
+        let timestamp = proto_structure
+            .timestamp
+            .ok_or(SignalProtocolError::InvalidProtobufEncoding)?;
+        let ratchet_key = proto_structure
+            .ratchet_key
+            .map(|k| curve::PublicKey::deserialize(&k))
+            .transpose()?;
+        Ok(Self {
+            ratchet_key,
+            timestamp,
+            device_id: proto_structure.device_id.unwrap_or_default(),
+            serialized: Box::from(value),
+        })
+    }
+}
+
+/// Content that is sent without encryption or padding, such as a [DecryptionErrorMessage].
+#[derive(Debug, Clone)]
+pub struct PlaintextContent {
+    serialized: Box<[u8]>,
+}
+
+impl PlaintextContent {
+    /// Distinguishes plaintext content from padded content, whose first byte is always part of a
+    /// protobuf field tag.
+    const PLAINTEXT_CONTEXT_IDENTIFIER_BYTE: u8 = 0xC0;
+    /// Marks the end of the content, as in the padding scheme used for encrypted content.
+    const PADDING_BOUNDARY_BYTE: u8 = 0x80;
+
+    /// The content as it should be delivered to the application, including the trailing padding
+    /// boundary.
+    #[inline]
+    pub fn body(&self) -> &[u8] {
+        &self.serialized[1..]
+    }
+
+    #[inline]
+    pub fn serialized(&self) -> &[u8] {
+        &*self.serialized
+    }
+}
+
+impl From<DecryptionErrorMessage> for PlaintextContent {
+    fn from(message: DecryptionErrorMessage) -> Self {
+        let proto_structure = proto::wire::Content {
+            decryption_error_message: Some(message.serialized().to_vec()),
+        };
+        let mut serialized = vec![Self::PLAINTEXT_CONTEXT_IDENTIFIER_BYTE];
+        proto_structure
+            .encode(&mut serialized)
+            .expect("can always encode to a Vec");
+        serialized.push(Self::PADDING_BOUNDARY_BYTE);
+        Self {
+            serialized: serialized.into_boxed_slice(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for PlaintextContent {
+    fn as_ref(&self) -> &[u8] {
+        &*self.serialized
+    }
+}
+
+impl TryFrom<&[u8]> for PlaintextContent {
+    type Error = SignalProtocolError;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.is_empty() {
+            return Err(SignalProtocolError::CiphertextMessageTooShort(0));
+        }
+        if value[0] != Self::PLAINTEXT_CONTEXT_IDENTIFIER_BYTE {
+            return Err(SignalProtocolError::InvalidMessage(
+                "plaintext content does not start with the plaintext identifier byte",
+            ));
+        }
+        Ok(Self {
+            serialized: Box::from(value),
+        })
+    }
+}
+
+/// Extracts the [DecryptionErrorMessage] from the [body](PlaintextContent::body) of a
+/// [PlaintextContent].
+pub fn extract_decryption_error_message_from_serialized_content(
+    bytes: &[u8],
+) -> Result<DecryptionErrorMessage> {
+    if bytes.last() != Some(&PlaintextContent::PADDING_BOUNDARY_BYTE) {
+        return Err(SignalProtocolError::InvalidMessage(
+            "plaintext content is missing its padding boundary",
+        ));
+    }
+    let proto_structure = proto::wire::Content::decode(&bytes[..bytes.len() - 1])?;
+    let message = proto_structure
+        .decryption_error_message
+        .ok_or(SignalProtocolError::InvalidProtobufEncoding)?;
+    DecryptionErrorMessage::try_from(&message[..])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -680,7 +956,7 @@ mod tests {
         let message = create_signal_message(&mut csprng);
         let pre_key_signal_message = PreKeySignalMessage::new(
             3,
-            365,
+            RegistrationId::try_from(365).unwrap(),
             None,
             97,
             base_key_pair.public_key,
@@ -725,6 +1001,199 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_message_fields_without_session() -> Result<()> {
+        let signal_message = hex::decode(
+            "330a2105a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8a209102a182922\
+             0a636970686572746578748b506a04ce17c4e8",
+        )
+        .expect("valid hex");
+        let pre_key_signal_message = hex::decode(format!(
+            "3308d209122105ac01b2209e86354fb853237b5de0f4fab13c7fcbf433a61c019369617fecf10b1a21\
+             05ce8d3ad1ccb633ec7b70c17814a5c76ecd029685050d344745ba05870e587d59223c{}28ed023061",
+            hex::encode(&signal_message)
+        ))
+        .expect("valid hex");
+
+        let message = SignalMessage::try_from(&signal_message[..])?;
+        assert_eq!(message.message_version(), 3);
+        assert_eq!(message.counter(), 42);
+        assert_eq!(
+            hex::encode(message.sender_ratchet_key().serialize()),
+            "05a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8a209"
+        );
+        assert_eq!(message.body(), b"ciphertext");
+
+        let message = PreKeySignalMessage::try_from(&pre_key_signal_message[..])?;
+        assert_eq!(message.message_version(), 3);
+        assert_eq!(u32::from(message.registration_id()), 365);
+        assert_eq!(message.pre_key_id(), Some(1234));
+        assert_eq!(message.signed_pre_key_id(), 97);
+        assert_eq!(
+            hex::encode(message.base_key().serialize()),
+            "05ac01b2209e86354fb853237b5de0f4fab13c7fcbf433a61c019369617fecf10b"
+        );
+        assert_eq!(
+            hex::encode(message.identity_key().serialize()),
+            "05ce8d3ad1ccb633ec7b70c17814a5c76ecd029685050d344745ba05870e587d59"
+        );
+        assert_eq!(message.message().serialized(), &signal_message[..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_messages_are_rejected() {
+        let mut csprng = OsRng;
+        let identity_key_pair = curve::KeyPair::generate(&mut csprng);
+
+        // Well-formed protobufs that lack required fields.
+        let mut serialized = vec![0x33];
+        proto::wire::SignalMessage {
+            ratchet_key: Some(identity_key_pair.public_key.serialize().to_vec()),
+            counter: None,
+            previous_counter: None,
+            ciphertext: Some(vec![1, 2, 3]),
+        }
+        .encode(&mut serialized)
+        .expect("can encode");
+        serialized.extend_from_slice(&[0; SignalMessage::MAC_LENGTH]);
+        assert_eq!(
+            SignalMessage::try_from(&serialized[..]).unwrap_err(),
+            SignalProtocolError::InvalidProtobufEncoding
+        );
+
+        let message = create_signal_message(&mut csprng);
+        let mut serialized = vec![0x33];
+        proto::wire::PreKeySignalMessage {
+            registration_id: Some(365),
+            pre_key_id: None,
+            signed_pre_key_id: Some(97),
+            base_key: None,
+            identity_key: Some(identity_key_pair.public_key.serialize().to_vec()),
+            message: Some(message.serialized().to_vec()),
+        }
+        .encode(&mut serialized)
+        .expect("can encode");
+        assert_eq!(
+            PreKeySignalMessage::try_from(&serialized[..]).unwrap_err(),
+            SignalProtocolError::InvalidProtobufEncoding
+        );
+
+        // Truncated input must fail without panicking.
+        let signal_message = message.serialized().to_vec();
+        for len in 0..signal_message.len() {
+            assert!(SignalMessage::try_from(&signal_message[..len]).is_err());
+        }
+        let pre_key_signal_message = PreKeySignalMessage::new(
+            3,
+            RegistrationId::try_from(365).unwrap(),
+            None,
+            97,
+            identity_key_pair.public_key,
+            identity_key_pair.public_key.into(),
+            message,
+        )
+        .expect("valid message");
+        let pre_key_signal_message = pre_key_signal_message.serialized();
+        for len in 0..pre_key_signal_message.len() {
+            assert!(PreKeySignalMessage::try_from(&pre_key_signal_message[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_pre_key_signal_message_registration_id_range() {
+        let mut csprng = OsRng;
+        let identity_key_pair = curve::KeyPair::generate(&mut csprng);
+        let message = create_signal_message(&mut csprng);
+
+        let encode = |registration_id: u32| {
+            let mut serialized = vec![0x33];
+            proto::wire::PreKeySignalMessage {
+                registration_id: Some(registration_id),
+                pre_key_id: None,
+                signed_pre_key_id: Some(97),
+                base_key: Some(identity_key_pair.public_key.serialize().to_vec()),
+                identity_key: Some(identity_key_pair.public_key.serialize().to_vec()),
+                message: Some(message.serialized().to_vec()),
+            }
+            .encode(&mut serialized)
+            .expect("can encode");
+            serialized
+        };
+
+        let parsed = PreKeySignalMessage::try_from(&encode(16383)[..]).expect("in range");
+        assert_eq!(u32::from(parsed.registration_id()), 16383);
+        assert_eq!(
+            PreKeySignalMessage::try_from(&encode(16384)[..]).unwrap_err(),
+            SignalProtocolError::InvalidRegistrationId(16384)
+        );
+    }
+
+    #[test]
+    fn test_signal_message_verify_mac() -> Result<()> {
+        let mut csprng = OsRng;
+        let mac_key = [7u8; 32];
+        let sender_identity_key: IdentityKey =
+            curve::KeyPair::generate(&mut csprng).public_key.into();
+        let receiver_identity_key: IdentityKey =
+            curve::KeyPair::generate(&mut csprng).public_key.into();
+        let ratchet_key_pair = curve::KeyPair::generate(&mut csprng);
+
+        let message = SignalMessage::new(
+            3,
+            &mac_key,
+            ratchet_key_pair.public_key,
+            42,
+            41,
+            b"ciphertext",
+            &sender_identity_key,
+            &receiver_identity_key,
+        )?;
+        assert!(message.verify_mac(&sender_identity_key, &receiver_identity_key, &mac_key)?);
+        assert!(!message.verify_mac(&receiver_identity_key, &sender_identity_key, &mac_key)?);
+        assert!(!message.verify_mac(&sender_identity_key, &receiver_identity_key, &[8u8; 32])?);
+        assert_eq!(
+            message
+                .verify_mac(&sender_identity_key, &receiver_identity_key, &mac_key[..16])
+                .unwrap_err(),
+            SignalProtocolError::InvalidMacKeyLength(16)
+        );
+
+        // The ciphertext is the last protobuf field, immediately before the MAC.
+        let mut tampered = message.serialized().to_vec();
+        let ciphertext_end = tampered.len() - SignalMessage::MAC_LENGTH;
+        tampered[ciphertext_end - 1] ^= 0x01;
+        let tampered = SignalMessage::try_from(&tampered[..])?;
+        assert_eq!(tampered.body(), b"ciphertexu");
+        assert!(!tampered.verify_mac(&sender_identity_key, &receiver_identity_key, &mac_key)?);
+
+        let mut tampered = message.serialized().to_vec();
+        *tampered.last_mut().expect("non-empty") ^= 0x80;
+        let tampered = SignalMessage::try_from(&tampered[..])?;
+        assert!(!tampered.verify_mac(&sender_identity_key, &receiver_identity_key, &mac_key)?);
+
+        // A legacy version byte is covered by the MAC like any other.
+        let legacy = SignalMessage::new(
+            2,
+            &mac_key,
+            ratchet_key_pair.public_key,
+            42,
+            41,
+            b"ciphertext",
+            &sender_identity_key,
+            &receiver_identity_key,
+        )?;
+        assert_eq!(legacy.serialized()[0], 0x23);
+        assert!(legacy.verify_mac(&sender_identity_key, &receiver_identity_key, &mac_key)?);
+        let mut upgraded = legacy.serialized().to_vec();
+        upgraded[0] = 0x33;
+        let upgraded = SignalMessage::try_from(&upgraded[..])?;
+        assert!(!upgraded.verify_mac(&sender_identity_key, &receiver_identity_key, &mac_key)?);
+
+        Ok(())
+    }
+
     #[test]
     fn test_sender_key_message_serialize_deserialize() {
         let mut csprng = OsRng;
@@ -757,4 +1226,177 @@ mod tests {
             deser_sender_key_message.serialized
         );
     }
+
+    #[test]
+    fn test_decryption_error_extracts_ratchet_key() -> Result<()> {
+        let mut csprng = OsRng;
+        let identity_key_pair = curve::KeyPair::generate(&mut csprng);
+        let base_key_pair = curve::KeyPair::generate(&mut csprng);
+        let message = create_signal_message(&mut csprng);
+        let ratchet_key = message.sender_ratchet_key;
+
+        let error_message = DecryptionErrorMessage::for_original(
+            message.serialized(),
+            CiphertextMessageType::Whisper,
+            0x1234_5678_9abc,
+            7,
+        )?;
+        assert_eq!(error_message.ratchet_key(), Some(&ratchet_key));
+        assert_eq!(error_message.timestamp(), 0x1234_5678_9abc);
+        assert_eq!(error_message.device_id(), 7);
+
+        let pre_key_signal_message = PreKeySignalMessage::new(
+            3,
+            RegistrationId::try_from(365).unwrap(),
+            None,
+            97,
+            base_key_pair.public_key,
+            identity_key_pair.public_key.into(),
+            message,
+        )?;
+        let error_message = DecryptionErrorMessage::for_original(
+            pre_key_signal_message.serialized(),
+            CiphertextMessageType::PreKey,
+            1000,
+            2,
+        )?;
+        // The inner message's ratchet key, not the pre-key message's base key.
+        assert_eq!(error_message.ratchet_key(), Some(&ratchet_key));
+        assert_ne!(error_message.ratchet_key(), Some(&base_key_pair.public_key));
+
+        let deser_error_message = DecryptionErrorMessage::try_from(error_message.serialized())?;
+        assert_eq!(deser_error_message.ratchet_key(), Some(&ratchet_key));
+        assert_eq!(deser_error_message.timestamp(), 1000);
+        assert_eq!(deser_error_message.device_id(), 2);
+
+        // The types must match; a PreKeySignalMessage does not parse as a SignalMessage.
+        assert!(DecryptionErrorMessage::for_original(
+            pre_key_signal_message.serialized(),
+            CiphertextMessageType::Whisper,
+            1000,
+            2,
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decryption_error_for_sender_key_message() -> Result<()> {
+        let mut csprng = OsRng;
+        let signature_key_pair = curve::KeyPair::generate(&mut csprng);
+        let sender_key_message = SenderKeyMessage::new(
+            42,
+            7,
+            &[1u8, 2, 3],
+            &mut csprng,
+            &signature_key_pair.private_key,
+        )?;
+        let error_message = DecryptionErrorMessage::for_original(
+            sender_key_message.serialized(),
+            CiphertextMessageType::SenderKey,
+            1000,
+            3,
+        )?;
+        assert_eq!(error_message.ratchet_key(), None);
+
+        let deser_error_message = DecryptionErrorMessage::try_from(error_message.serialized())?;
+        assert_eq!(deser_error_message.ratchet_key(), None);
+        assert_eq!(deser_error_message.device_id(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decryption_error_in_plaintext_content() -> Result<()> {
+        let mut csprng = OsRng;
+        let message = create_signal_message(&mut csprng);
+        let error_message = DecryptionErrorMessage::for_original(
+            message.serialized(),
+            CiphertextMessageType::Whisper,
+            1000,
+            2,
+        )?;
+        let plaintext = PlaintextContent::from(error_message.clone());
+        assert_eq!(plaintext.serialized()[0], 0xC0);
+        assert_eq!(plaintext.body(), &plaintext.serialized()[1..]);
+        assert_eq!(
+            CiphertextMessage::PlaintextContent(plaintext.clone()).message_type(),
+            CiphertextMessageType::Plaintext
+        );
+
+        let deser_plaintext = PlaintextContent::try_from(plaintext.serialized())?;
+        let extracted =
+            extract_decryption_error_message_from_serialized_content(deser_plaintext.body())?;
+        assert_eq!(extracted.serialized(), error_message.serialized());
+        assert_eq!(extracted.ratchet_key(), Some(&message.sender_ratchet_key));
+
+        assert!(PlaintextContent::try_from(&plaintext.body()[..]).is_err());
+        assert!(extract_decryption_error_message_from_serialized_content(
+            &plaintext.body()[..plaintext.body().len() - 1]
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_by_message_type() -> Result<()> {
+        let mut csprng = OsRng;
+        let key_pair = curve::KeyPair::generate(&mut csprng);
+        let signal_message = create_signal_message(&mut csprng);
+        let pre_key_signal_message = PreKeySignalMessage::new(
+            3,
+            RegistrationId::try_from(365)?,
+            None,
+            97,
+            key_pair.public_key,
+            key_pair.public_key.into(),
+            signal_message.clone(),
+        )?;
+        let sender_key_message =
+            SenderKeyMessage::new(1, 2, b"ciphertext", &mut csprng, &key_pair.private_key)?;
+        let distribution_message =
+            SenderKeyDistributionMessage::new(1, 2, &[0u8; 32], key_pair.public_key)?;
+        let plaintext = PlaintextContent::from(DecryptionErrorMessage::for_original(
+            signal_message.serialized(),
+            CiphertextMessageType::Whisper,
+            1000,
+            2,
+        )?);
+
+        for (message_type, serialized) in &[
+            (CiphertextMessageType::Whisper, signal_message.serialized()),
+            (
+                CiphertextMessageType::PreKey,
+                pre_key_signal_message.serialized(),
+            ),
+            (
+                CiphertextMessageType::SenderKey,
+                sender_key_message.serialized(),
+            ),
+            (
+                CiphertextMessageType::SenderKeyDistribution,
+                distribution_message.serialized(),
+            ),
+            (CiphertextMessageType::Plaintext, plaintext.serialized()),
+        ] {
+            assert_eq!(
+                CiphertextMessageType::try_from(u8::from(*message_type))?,
+                *message_type
+            );
+            let message = CiphertextMessage::deserialize(*message_type, serialized)?;
+            assert_eq!(message.message_type(), *message_type);
+            assert_eq!(message.serialize(), *serialized);
+        }
+
+        assert!(CiphertextMessage::deserialize(
+            CiphertextMessageType::Plaintext,
+            signal_message.serialized()
+        )
+        .is_err());
+        assert_eq!(
+            CiphertextMessageType::try_from(7),
+            Err(SignalProtocolError::UnrecognizedCiphertextMessageType(7))
+        );
+        Ok(())
+    }
 }
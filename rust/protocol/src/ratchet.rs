@@ -8,6 +8,7 @@ mod params;
 
 pub use self::keys::{ChainKey, MessageKeys, RootKey};
 pub use self::params::{AliceSignalProtocolParameters, BobSignalProtocolParameters};
+use crate::crypto::SecretBytes;
 use crate::curve;
 use crate::error::Result;
 use crate::proto::storage::SessionStructure;
@@ -18,7 +19,7 @@ use rand::{CryptoRng, Rng};
 fn derive_keys(secret_input: &[u8]) -> Result<(RootKey, ChainKey)> {
     let kdf = crate::kdf::HKDF::new(3)?;
 
-    let secrets = kdf.derive_secrets(secret_input, b"WhisperText", 64)?;
+    let secrets = SecretBytes::from(kdf.derive_secrets(secret_input, b"WhisperText", 64)?);
 
     let root_key = RootKey::new(kdf, &secrets[0..32])?;
     let chain_key = ChainKey::new(kdf, &secrets[32..64], 0)?;
@@ -34,32 +35,33 @@ pub fn initialize_alice_session<R: Rng + CryptoRng>(
 
     let sending_ratchet_key = curve::KeyPair::generate(&mut csprng);
 
-    let mut secrets = Vec::with_capacity(32 * 5);
+    let mut secrets = SecretBytes::with_capacity(32 * 5);
 
     secrets.extend_from_slice(&[0xFFu8; 32]); // "discontinuity bytes"
 
     let our_base_private_key = parameters.our_base_key_pair().private_key;
 
-    secrets.extend_from_slice(&curve::calculate_agreement(
-        parameters.their_signed_pre_key(),
-        parameters.our_identity_key_pair().private_key(),
-    )?);
+    secrets.extend_from_slice(&SecretBytes::from(
+        parameters
+            .our_identity_key_pair()
+            .calculate_agreement(parameters.their_signed_pre_key())?,
+    ));
 
-    secrets.extend_from_slice(&curve::calculate_agreement(
+    secrets.extend_from_slice(&SecretBytes::from(curve::calculate_agreement_checked(
         parameters.their_identity_key().public_key(),
         &our_base_private_key,
-    )?);
+    )?));
 
-    secrets.extend_from_slice(&curve::calculate_agreement(
+    secrets.extend_from_slice(&SecretBytes::from(curve::calculate_agreement_checked(
         parameters.their_signed_pre_key(),
         &our_base_private_key,
-    )?);
+    )?));
 
     if let Some(their_one_time_prekey) = parameters.their_one_time_pre_key() {
-        secrets.extend_from_slice(&curve::calculate_agreement(
+        secrets.extend_from_slice(&SecretBytes::from(curve::calculate_agreement_checked(
             their_one_time_prekey,
             &our_base_private_key,
-        )?);
+        )?));
     }
 
     let (root_key, chain_key) = derive_keys(&secrets)?;
@@ -82,6 +84,8 @@ pub fn initialize_alice_session<R: Rng + CryptoRng>(
         local_registration_id: 0,
         needs_refresh: false,
         alice_base_key: vec![],
+        creation_time: 0,
+        last_use_time: 0,
     };
 
     let mut session = SessionState::new(session);
@@ -95,30 +99,31 @@ pub fn initialize_alice_session<R: Rng + CryptoRng>(
 pub fn initialize_bob_session(parameters: &BobSignalProtocolParameters) -> Result<SessionState> {
     let local_identity = parameters.our_identity_key_pair().identity_key();
 
-    let mut secrets = Vec::with_capacity(32 * 5);
+    let mut secrets = SecretBytes::with_capacity(32 * 5);
 
     secrets.extend_from_slice(&[0xFFu8; 32]); // "discontinuity bytes"
 
-    secrets.extend_from_slice(&curve::calculate_agreement(
+    secrets.extend_from_slice(&SecretBytes::from(curve::calculate_agreement_checked(
         parameters.their_identity_key().public_key(),
         &parameters.our_signed_pre_key_pair().private_key,
-    )?);
+    )?));
 
-    secrets.extend_from_slice(&curve::calculate_agreement(
-        parameters.their_base_key(),
-        parameters.our_identity_key_pair().private_key(),
-    )?);
+    secrets.extend_from_slice(&SecretBytes::from(
+        parameters
+            .our_identity_key_pair()
+            .calculate_agreement(parameters.their_base_key())?,
+    ));
 
-    secrets.extend_from_slice(&curve::calculate_agreement(
+    secrets.extend_from_slice(&SecretBytes::from(curve::calculate_agreement_checked(
         parameters.their_base_key(),
         &parameters.our_signed_pre_key_pair().private_key,
-    )?);
+    )?));
 
     if let Some(our_one_time_pre_key_pair) = parameters.our_one_time_pre_key_pair() {
-        secrets.extend_from_slice(&curve::calculate_agreement(
+        secrets.extend_from_slice(&SecretBytes::from(curve::calculate_agreement_checked(
             parameters.their_base_key(),
             &our_one_time_pre_key_pair.private_key,
-        )?);
+        )?));
     }
 
     let (root_key, chain_key) = derive_keys(&secrets)?;
@@ -136,6 +141,8 @@ pub fn initialize_bob_session(parameters: &BobSignalProtocolParameters) -> Resul
         local_registration_id: 0,
         needs_refresh: false,
         alice_base_key: vec![],
+        creation_time: 0,
+        last_use_time: 0,
     };
 
     let mut session = SessionState::new(session);
@@ -4,8 +4,9 @@
 //
 
 use arrayref::array_ref;
+use zeroize::Zeroize;
 
-use crate::crypto;
+use crate::crypto::{self, SecretBytes};
 use crate::curve;
 use crate::error::{Result, SignalProtocolError};
 use crate::kdf::HKDF;
@@ -19,7 +20,8 @@ pub struct MessageKeys {
 
 impl MessageKeys {
     pub fn derive_keys(input_key_material: &[u8], kdf: HKDF, counter: u32) -> Result<Self> {
-        let okm = kdf.derive_secrets(input_key_material, b"WhisperMessageKeys", 80)?;
+        let okm =
+            SecretBytes::from(kdf.derive_secrets(input_key_material, b"WhisperMessageKeys", 80)?);
         Ok(MessageKeys {
             cipher_key: *array_ref![okm, 0, 32],
             mac_key: *array_ref![okm, 32, 32],
@@ -68,6 +70,14 @@ impl MessageKeys {
     }
 }
 
+impl Drop for MessageKeys {
+    fn drop(&mut self) {
+        self.cipher_key.zeroize();
+        self.mac_key.zeroize();
+        self.iv.zeroize();
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ChainKey {
     kdf: HKDF,
@@ -102,19 +112,21 @@ impl ChainKey {
     }
 
     pub fn next_chain_key(&self) -> Result<Self> {
+        let index = self.index.checked_add(1).ok_or_else(|| {
+            SignalProtocolError::InvalidState("next_chain_key", "chain index overflow".to_owned())
+        })?;
         Ok(Self {
             kdf: self.kdf,
             key: self.calculate_base_material(Self::CHAIN_KEY_SEED)?,
-            index: self.index + 1,
+            index,
         })
     }
 
     pub fn message_keys(&self) -> Result<MessageKeys> {
-        MessageKeys::derive_keys(
-            &self.calculate_base_material(Self::MESSAGE_KEY_SEED)?,
-            self.kdf,
-            self.index,
-        )
+        let mut base_material = self.calculate_base_material(Self::MESSAGE_KEY_SEED)?;
+        let message_keys = MessageKeys::derive_keys(&base_material, self.kdf, self.index);
+        base_material.zeroize();
+        message_keys
     }
 
     fn calculate_base_material(&self, seed: [u8; 1]) -> Result<[u8; 32]> {
@@ -122,6 +134,12 @@ impl ChainKey {
     }
 }
 
+impl Drop for ChainKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct RootKey {
     kdf: HKDF,
@@ -148,13 +166,16 @@ impl RootKey {
         their_ratchet_key: &curve::PublicKey,
         our_ratchet_key: &curve::PrivateKey,
     ) -> Result<(RootKey, ChainKey)> {
-        let shared_secret = curve::calculate_agreement(their_ratchet_key, our_ratchet_key)?;
-        let derived_secret_bytes = self.kdf.derive_salted_secrets(
-            shared_secret.as_ref(),
+        let shared_secret = SecretBytes::from(curve::calculate_agreement_checked(
+            their_ratchet_key,
+            our_ratchet_key,
+        )?);
+        let derived_secret_bytes = SecretBytes::from(self.kdf.derive_salted_secrets(
+            &shared_secret,
             &self.key,
             b"WhisperRatchet",
             64,
-        )?;
+        )?);
         Ok((
             RootKey {
                 kdf: self.kdf,
@@ -169,6 +190,12 @@ impl RootKey {
     }
 }
 
+impl Drop for RootKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,76 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use crate::crypto;
+use crate::error::Result;
+
+use rand::{CryptoRng, Rng};
+
+pub const UNIDENTIFIED_ACCESS_KEY_LEN: usize = 16;
+
+/// Derives the key that lets others send sealed sender messages to the owner of `profile_key`.
+///
+/// This matches the official clients: the first 16 bytes of AES-256-GCM encrypting 16 zero bytes
+/// under the profile key with an all-zero nonce.
+pub fn derive_unidentified_access_key(
+    profile_key: &[u8; 32],
+) -> Result<[u8; UNIDENTIFIED_ACCESS_KEY_LEN]> {
+    let ctext = crypto::aes_256_gcm_encrypt(
+        &[0u8; UNIDENTIFIED_ACCESS_KEY_LEN],
+        profile_key,
+        &[0u8; 12],
+        &[],
+    )?;
+
+    // The ciphertext comes first; the GCM tag that follows it is not part of the key.
+    let mut access_key = [0u8; UNIDENTIFIED_ACCESS_KEY_LEN];
+    access_key.copy_from_slice(&ctext[..UNIDENTIFIED_ACCESS_KEY_LEN]);
+    Ok(access_key)
+}
+
+/// Generates an access key to present to recipients who accept sealed sender messages from
+/// anyone ("unrestricted" access). The server does not check it, so, like the official clients,
+/// this is just random bytes.
+pub fn unrestricted_unidentified_access_key<R: Rng + CryptoRng>(
+    csprng: &mut R,
+) -> [u8; UNIDENTIFIED_ACCESS_KEY_LEN] {
+    csprng.gen()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_derive_unidentified_access_key() -> Result<()> {
+        // With an all-zero key this is AES-GCM test case 14 from McGrew & Viega, "The Galois/Counter
+        // Mode of Operation (GCM)".
+        assert_eq!(
+            hex::encode(derive_unidentified_access_key(&[0u8; 32])?),
+            "cea7403d4d606b6e074ec5d3baf39d18"
+        );
+
+        let mut profile_key = [0u8; 32];
+        for (i, b) in profile_key.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        assert_eq!(
+            hex::encode(derive_unidentified_access_key(&profile_key)?),
+            "0ebcb5deb52c83bd08a8a935182c9199"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unrestricted_unidentified_access_key() {
+        let mut csprng = OsRng;
+        let a = unrestricted_unidentified_access_key(&mut csprng);
+        let b = unrestricted_unidentified_access_key(&mut csprng);
+        assert_ne!(a, b);
+    }
+}
@@ -8,6 +8,7 @@ use crate::crypto::hmac_sha256;
 use crate::curve;
 use crate::error::{Result, SignalProtocolError};
 use crate::kdf::HKDF;
+use crate::proto;
 use crate::proto::storage as storage_proto;
 use crate::ProtocolAddress;
 
@@ -34,7 +35,7 @@ impl SenderKeyName {
     }
 
     pub fn sender_device_id(&self) -> Result<u32> {
-        Ok(self.sender.device_id())
+        Ok(self.sender.device_id().into())
     }
 
     pub fn sender(&self) -> Result<ProtocolAddress> {
@@ -122,8 +123,11 @@ impl SenderChainKey {
     }
 
     pub fn next(&self) -> Result<SenderChainKey> {
+        let iteration = self.iteration.checked_add(1).ok_or_else(|| {
+            SignalProtocolError::InvalidState("next", "sender chain iteration overflow".to_owned())
+        })?;
         Ok(SenderChainKey::new(
-            self.iteration + 1,
+            iteration,
             self.get_derivative(Self::CHAIN_KEY_SEED)?,
         )?)
     }
@@ -223,10 +227,11 @@ impl SenderKeyState {
     }
 
     pub fn signing_key_private(&self) -> Result<Option<curve::PrivateKey>> {
-        if let Some(ref signing_key) = self.state.sender_signing_key {
-            Ok(Some(curve::PrivateKey::deserialize(&signing_key.private)?))
-        } else {
-            Ok(None)
+        match self.state.sender_signing_key {
+            Some(ref signing_key) if !signing_key.private.is_empty() => {
+                Ok(Some(curve::PrivateKey::deserialize(&signing_key.private)?))
+            }
+            _ => Ok(None),
         }
     }
 
@@ -243,11 +248,27 @@ impl SenderKeyState {
         Ok(self.state.clone())
     }
 
+    pub(crate) fn remove_signing_key_private(&mut self) {
+        if let Some(ref mut signing_key) = self.state.sender_signing_key {
+            signing_key.private.clear();
+        }
+    }
+
     pub fn add_sender_message_key(&mut self, sender_message_key: &SenderMessageKey) -> Result<()> {
+        self.add_sender_message_key_with_limit(sender_message_key, consts::MAX_MESSAGE_KEYS)
+    }
+
+    /// Like [`add_sender_message_key`](Self::add_sender_message_key), but keeps at most
+    /// `max_message_keys` keys, discarding the oldest.
+    pub(crate) fn add_sender_message_key_with_limit(
+        &mut self,
+        sender_message_key: &SenderMessageKey,
+        max_message_keys: usize,
+    ) -> Result<()> {
         self.state
             .sender_message_keys
             .push(sender_message_key.as_protobuf()?);
-        while self.state.sender_message_keys.len() > consts::MAX_MESSAGE_KEYS {
+        while self.state.sender_message_keys.len() > max_message_keys {
             self.state.sender_message_keys.remove(0);
         }
         Ok(())
@@ -277,6 +298,10 @@ pub struct SenderKeyRecord {
 }
 
 impl SenderKeyRecord {
+    /// The format version written by [`serialize`](Self::serialize). Records with a later version
+    /// are rejected rather than misparsed.
+    pub const SERIALIZED_VERSION: u32 = 1;
+
     pub fn new_empty() -> Self {
         Self {
             states: VecDeque::new(),
@@ -285,6 +310,9 @@ impl SenderKeyRecord {
 
     pub fn deserialize(buf: &[u8]) -> Result<SenderKeyRecord> {
         let skr = storage_proto::SenderKeyRecordStructure::decode(buf)?;
+        if skr.version > Self::SERIALIZED_VERSION {
+            return Err(SignalProtocolError::UnrecognizedRecordVersion(skr.version));
+        }
 
         let mut states = VecDeque::with_capacity(skr.sender_key_states.len());
         for state in skr.sender_key_states {
@@ -293,10 +321,40 @@ impl SenderKeyRecord {
         Ok(Self { states })
     }
 
+    /// Reads the format version of a serialized sender key record without decoding the rest of it.
+    pub fn serialized_version(buf: &[u8]) -> Result<u32> {
+        Ok(proto::peek_uint32_field(buf, 2)?)
+    }
+
+    /// Rewrites a serialized sender key record of any supported version in the current format.
+    pub fn migrate(buf: &[u8]) -> Result<Vec<u8>> {
+        Self::deserialize(buf)?.serialize()
+    }
+
     pub fn is_empty(&self) -> Result<bool> {
         Ok(self.states.is_empty())
     }
 
+    pub fn state_count(&self) -> Result<usize> {
+        Ok(self.states.len())
+    }
+
+    /// The sender key IDs of the record's states, most recent first.
+    pub fn chain_ids(&self) -> Result<Vec<u32>> {
+        self.states.iter().map(|s| s.sender_key_id()).collect()
+    }
+
+    /// The iteration of the next message key the chain with ID `chain_id` will produce, or `None`
+    /// if the record has no such chain.
+    pub fn max_iteration_for_chain(&self, chain_id: u32) -> Result<Option<u32>> {
+        for state in &self.states {
+            if state.sender_key_id()? == chain_id {
+                return Ok(Some(state.sender_chain_key()?.iteration()?));
+            }
+        }
+        Ok(None)
+    }
+
     pub fn sender_key_state(&mut self) -> Result<&mut SenderKeyState> {
         if !self.states.is_empty() {
             return Ok(&mut self.states[0]);
@@ -353,6 +411,12 @@ impl SenderKeyRecord {
         )
     }
 
+    pub(crate) fn remove_signing_keys_private(&mut self) {
+        for state in self.states.iter_mut() {
+            state.remove_signing_key_private();
+        }
+    }
+
     pub fn as_protobuf(&self) -> Result<storage_proto::SenderKeyRecordStructure> {
         let mut states = Vec::with_capacity(self.states.len());
         for state in &self.states {
@@ -361,6 +425,7 @@ impl SenderKeyRecord {
 
         Ok(storage_proto::SenderKeyRecordStructure {
             sender_key_states: states,
+            version: Self::SERIALIZED_VERSION,
         })
     }
 
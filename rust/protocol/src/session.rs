@@ -4,16 +4,16 @@
 //
 
 use crate::{
-    Context, IdentityKeyStore, PreKeyStore, ProtocolAddress, SessionRecord, SessionStore,
-    SignalProtocolError, SignedPreKeyStore,
+    Context, IdentityChange, IdentityKeyStore, PreKeyStore, ProtocolAddress, SessionConfig,
+    SessionRecord, SessionStore, SignalProtocolError, SignedPreKeyStore,
 };
 
 use crate::curve;
 use crate::error::Result;
-use crate::protocol::PreKeySignalMessage;
+use crate::protocol::{PreKeySignalMessage, CIPHERTEXT_MESSAGE_CURRENT_VERSION};
 use crate::ratchet;
 use crate::ratchet::{AliceSignalProtocolParameters, BobSignalProtocolParameters};
-use crate::state::{PreKeyBundle, PreKeyId};
+use crate::state::{PreKeyBundle, PreKeyId, SignedPreKeyId};
 use crate::storage::Direction;
 use rand::{CryptoRng, Rng};
 
@@ -34,6 +34,61 @@ pub async fn process_prekey(
     pre_key_store: &mut dyn PreKeyStore,
     signed_prekey_store: &mut dyn SignedPreKeyStore,
     ctx: Context,
+) -> Result<Option<PreKeyId>> {
+    process_prekey_with_config(
+        message,
+        remote_address,
+        session_record,
+        identity_store,
+        pre_key_store,
+        signed_prekey_store,
+        &SessionConfig::default(),
+        ctx,
+    )
+    .await
+}
+
+/// Like [process_prekey], but records `config.now_ms` as the new session's creation time.
+pub(crate) async fn process_prekey_with_config(
+    message: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    session_record: &mut SessionRecord,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_prekey_store: &mut dyn SignedPreKeyStore,
+    config: &SessionConfig,
+    ctx: Context,
+) -> Result<Option<PreKeyId>> {
+    let unsigned_pre_key_id = process_prekey_without_saving_identity(
+        message,
+        remote_address,
+        session_record,
+        identity_store,
+        pre_key_store,
+        signed_prekey_store,
+        config,
+        ctx,
+    )
+    .await?;
+
+    identity_store
+        .save_identity(&remote_address, message.identity_key(), ctx)
+        .await?;
+
+    Ok(unsigned_pre_key_id)
+}
+
+/// Does everything [process_prekey_with_config] does except saving the sender's identity, which
+/// is left to the caller.
+pub(crate) async fn process_prekey_without_saving_identity(
+    message: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    session_record: &mut SessionRecord,
+    identity_store: &dyn IdentityKeyStore,
+    pre_key_store: &dyn PreKeyStore,
+    signed_prekey_store: &dyn SignedPreKeyStore,
+    config: &SessionConfig,
+    ctx: Context,
 ) -> Result<Option<PreKeyId>> {
     let their_identity_key = message.identity_key();
 
@@ -51,29 +106,25 @@ pub async fn process_prekey(
         ));
     }
 
-    let unsigned_pre_key_id = process_prekey_v3(
+    process_prekey_v3(
         message,
         session_record,
         signed_prekey_store,
         pre_key_store,
         identity_store,
+        config.now_ms,
         ctx,
     )
-    .await?;
-
-    identity_store
-        .save_identity(&remote_address, their_identity_key, ctx)
-        .await?;
-
-    Ok(unsigned_pre_key_id)
+    .await
 }
 
 async fn process_prekey_v3(
     message: &PreKeySignalMessage,
     session_record: &mut SessionRecord,
-    signed_prekey_store: &mut dyn SignedPreKeyStore,
-    pre_key_store: &mut dyn PreKeyStore,
-    identity_store: &mut dyn IdentityKeyStore,
+    signed_prekey_store: &dyn SignedPreKeyStore,
+    pre_key_store: &dyn PreKeyStore,
+    identity_store: &dyn IdentityKeyStore,
+    now_ms: Option<u64>,
     ctx: Context,
 ) -> Result<Option<PreKeyId>> {
     if session_record.has_session_state(
@@ -116,20 +167,87 @@ async fn process_prekey_v3(
     new_session.set_local_registration_id(identity_store.get_local_registration_id(ctx).await?)?;
     new_session.set_remote_registration_id(message.registration_id())?;
     new_session.set_alice_base_key(&message.base_key().serialize())?;
+    if let Some(now_ms) = now_ms {
+        new_session.set_creation_time(now_ms)?;
+    }
 
     session_record.promote_state(new_session)?;
 
     Ok(message.pre_key_id())
 }
 
+/// Starts a session with `remote_address` from their published `bundle`.
+///
+/// Returns how saving the bundle's identity changed `identity_store`, so that callers can tell
+/// the user when a safety number has changed.
 pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
     remote_address: &ProtocolAddress,
     session_store: &mut dyn SessionStore,
     identity_store: &mut dyn IdentityKeyStore,
     bundle: &PreKeyBundle,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<IdentityChange> {
+    process_prekey_bundle_with_config(
+        remote_address,
+        session_store,
+        identity_store,
+        bundle,
+        &SessionConfig::default(),
+        csprng,
+        ctx,
+    )
+    .await
+}
+
+/// Like [`process_prekey_bundle`], but refuses to start a session whose version is outside
+/// `config.accepted_versions`.
+pub async fn process_prekey_bundle_with_config<R: Rng + CryptoRng>(
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    bundle: &PreKeyBundle,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<IdentityChange> {
+    let report = process_prekey_bundle_with_report(
+        remote_address,
+        session_store,
+        identity_store,
+        bundle,
+        config,
+        csprng,
+        ctx,
+    )
+    .await?;
+    Ok(report.identity_change)
+}
+
+/// Details of a session started by [`process_prekey_bundle_with_report`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PreKeyBundleReport {
+    /// The one-time pre-key the session uses, if the bundle had one. The recipient removes it
+    /// once they receive the first message, so it should not be used again.
+    pub pre_key_id: Option<PreKeyId>,
+    pub signed_pre_key_id: SignedPreKeyId,
+    pub session_version: u32,
+    pub identity_change: IdentityChange,
+}
+
+/// Like [`process_prekey_bundle_with_config`], but reports which of the bundle's keys were used
+/// as well as how the identity was saved.
+pub async fn process_prekey_bundle_with_report<R: Rng + CryptoRng>(
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    bundle: &PreKeyBundle,
+    config: &SessionConfig,
     mut csprng: &mut R,
     ctx: Context,
-) -> Result<()> {
+) -> Result<PreKeyBundleReport> {
+    config.check_version(CIPHERTEXT_MESSAGE_CURRENT_VERSION)?;
+
     let their_identity_key = bundle.identity_key()?;
 
     if !identity_store
@@ -141,11 +259,7 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
         ));
     }
 
-    if !curve::verify_signature(
-        their_identity_key.public_key(),
-        &bundle.signed_pre_key_public()?.serialize(),
-        bundle.signed_pre_key_signature()?,
-    )? {
+    if !bundle.has_valid_signature()? {
         return Err(SignalProtocolError::SignatureValidationFailed);
     }
 
@@ -153,6 +267,7 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
         .load_session(&remote_address, ctx)
         .await?
         .unwrap_or_else(SessionRecord::new_fresh);
+    session_record.set_max_previous_sessions(config.max_previous_sessions);
 
     let our_base_key_pair = curve::KeyPair::generate(&mut csprng);
     let their_signed_prekey = bundle.signed_pre_key_public()?;
@@ -173,25 +288,35 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
 
     let mut session = ratchet::initialize_alice_session(&parameters, csprng)?;
 
+    let their_signed_prekey_id = bundle.signed_pre_key_id()?;
     session.set_unacknowledged_pre_key_message(
         their_one_time_prekey_id,
-        bundle.signed_pre_key_id()?,
+        their_signed_prekey_id,
         &our_base_key_pair.public_key,
     )?;
 
     session.set_local_registration_id(identity_store.get_local_registration_id(ctx).await?)?;
     session.set_remote_registration_id(bundle.registration_id()?)?;
     session.set_alice_base_key(&our_base_key_pair.public_key.serialize())?;
+    if let Some(now_ms) = config.now_ms {
+        session.set_creation_time(now_ms)?;
+    }
 
-    identity_store
+    let identity_change = identity_store
         .save_identity(&remote_address, their_identity_key, ctx)
         .await?;
 
+    let session_version = session.session_version()?;
     session_record.promote_state(session)?;
 
     session_store
         .store_session(&remote_address, &session_record, ctx)
         .await?;
 
-    Ok(())
+    Ok(PreKeyBundleReport {
+        pre_key_id: their_one_time_prekey_id,
+        signed_pre_key_id: their_signed_prekey_id,
+        session_version,
+        identity_change,
+    })
 }
@@ -4,20 +4,102 @@
 //
 
 use crate::{
-    Context, IdentityKeyStore, PreKeyStore, ProtocolAddress, SessionRecord, SessionState,
-    SessionStore, SignalProtocolError, SignedPreKeyStore,
+    Context, IdentityKey, IdentityKeyStore, PreKeyStore, ProtocolAddress, RegistrationId,
+    SessionRecord, SessionState, SessionStore, SignalProtocolError, SignedPreKeyStore,
 };
 
-use crate::consts::MAX_FORWARD_JUMPS;
+use crate::consts;
 use crate::crypto;
 use crate::curve;
 use crate::error::Result;
-use crate::protocol::{CiphertextMessage, PreKeySignalMessage, SignalMessage};
+use crate::protocol::{
+    CiphertextMessage, CiphertextMessageType, PreKeySignalMessage, SignalMessage,
+    CIPHERTEXT_MESSAGE_CURRENT_VERSION,
+};
 use crate::ratchet::{ChainKey, MessageKeys};
 use crate::session;
-use crate::storage::Direction;
+use crate::storage::{Direction, StoreOp, StoreTransaction};
 
 use rand::{CryptoRng, Rng};
+use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+
+/// Runtime policy for decrypting session messages.
+///
+/// None of this is stored in the session record; the same record can be used with different
+/// configurations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionConfig {
+    /// How many message keys may be skipped when a message arrives out of order. A message further
+    /// ahead in its chain than this is rejected, and at most this many skipped keys are kept per
+    /// receiving chain (the oldest are discarded first).
+    pub max_skipped_message_keys: usize,
+    /// Which message versions are accepted, both for incoming messages and for sessions started
+    /// from a pre-key bundle. Versions this library does not implement are rejected regardless.
+    pub accepted_versions: RangeInclusive<u8>,
+    /// How many archived states each session record keeps; see
+    /// [`SessionRecord::set_max_previous_sessions`]. Applied to every record as it is loaded.
+    pub max_previous_sessions: usize,
+    /// The current time, in milliseconds since the Unix epoch. It is recorded as the creation time
+    /// of new sessions and as the last use time of a session that decrypts a message. The library
+    /// never reads the clock itself, so when this is `None` no times are recorded.
+    pub now_ms: Option<u64>,
+}
+
+impl SessionConfig {
+    pub(crate) fn check_version(&self, version: u8) -> Result<()> {
+        if version < *self.accepted_versions.start() {
+            Err(SignalProtocolError::LegacyCiphertextVersion(version))
+        } else if version > *self.accepted_versions.end() {
+            Err(SignalProtocolError::UnrecognizedMessageVersion(
+                version as u32,
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            max_skipped_message_keys: consts::MAX_FORWARD_JUMPS,
+            accepted_versions: CIPHERTEXT_MESSAGE_CURRENT_VERSION
+                ..=CIPHERTEXT_MESSAGE_CURRENT_VERSION,
+            max_previous_sessions: consts::ARCHIVED_STATES_MAX_LENGTH,
+            now_ms: None,
+        }
+    }
+}
+
+/// Which of a session record's states decrypted a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionStateUsed {
+    Current,
+    /// The index into [`SessionRecord::previous_session_states`] before decryption; the state
+    /// has since been promoted to be the current one.
+    Previous(usize),
+}
+
+/// Details of a successful decryption, from [`message_decrypt_with_report`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecryptionReport {
+    pub state_used: SessionStateUsed,
+    pub session_version: u32,
+    pub counter: u32,
+}
+
+/// A session message ready to be put in a transport envelope, from
+/// [`message_encrypt_with_details`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncryptedMessage {
+    pub serialized: Vec<u8>,
+    /// Either [`CiphertextMessageType::Whisper`] or [`CiphertextMessageType::PreKey`].
+    pub message_type: CiphertextMessageType,
+    /// Taken from the session the message was encrypted with, so callers don't need to load it
+    /// again.
+    pub remote_registration_id: u32,
+}
 
 pub async fn message_encrypt(
     ptext: &[u8],
@@ -26,6 +108,35 @@ pub async fn message_encrypt(
     identity_store: &mut dyn IdentityKeyStore,
     ctx: Context,
 ) -> Result<CiphertextMessage> {
+    let (message, _remote_registration_id) =
+        encrypt_with_session(ptext, remote_address, session_store, identity_store, ctx).await?;
+    Ok(message)
+}
+
+/// Like [`message_encrypt`], but also returns what is needed to address the message.
+pub async fn message_encrypt_with_details(
+    ptext: &[u8],
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    ctx: Context,
+) -> Result<EncryptedMessage> {
+    let (message, remote_registration_id) =
+        encrypt_with_session(ptext, remote_address, session_store, identity_store, ctx).await?;
+    Ok(EncryptedMessage {
+        serialized: message.serialize().to_vec(),
+        message_type: message.message_type(),
+        remote_registration_id,
+    })
+}
+
+async fn encrypt_with_session(
+    ptext: &[u8],
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    ctx: Context,
+) -> Result<(CiphertextMessage, u32)> {
     let mut session_record = session_store
         .load_session(&remote_address, ctx)
         .await?
@@ -39,6 +150,7 @@ pub async fn message_encrypt(
     let sender_ephemeral = session_state.sender_ratchet_key()?;
     let previous_counter = session_state.previous_counter()?;
     let session_version = session_state.session_version()? as u8;
+    let remote_registration_id = session_state.remote_registration_id()?;
 
     let local_identity_key = session_state.local_identity_key()?;
     let their_identity_key = session_state
@@ -48,7 +160,8 @@ pub async fn message_encrypt(
     let ctext = crypto::aes_256_cbc_encrypt(ptext, message_keys.cipher_key(), message_keys.iv())?;
 
     let message = if let Some(items) = session_state.unacknowledged_pre_key_message_items()? {
-        let local_registration_id = session_state.local_registration_id()?;
+        let local_registration_id =
+            RegistrationId::try_from(session_state.local_registration_id()?)?;
 
         let message = SignalMessage::new(
             session_version,
@@ -108,7 +221,23 @@ pub async fn message_encrypt(
     session_store
         .store_session(&remote_address, &session_record, ctx)
         .await?;
-    Ok(message)
+    Ok((message, remote_registration_id))
+}
+
+/// The stores that decrypting a session message reads and updates.
+pub struct SessionStores<'a> {
+    pub session_store: &'a mut dyn SessionStore,
+    pub identity_store: &'a mut dyn IdentityKeyStore,
+    pub pre_key_store: &'a mut dyn PreKeyStore,
+    pub signed_pre_key_store: &'a mut dyn SignedPreKeyStore,
+}
+
+/// The stores that a transactional decrypt reads from; see [`message_decrypt_transactional`].
+pub struct ReadOnlySessionStores<'a> {
+    pub session_store: &'a dyn SessionStore,
+    pub identity_store: &'a dyn IdentityKeyStore,
+    pub pre_key_store: &'a dyn PreKeyStore,
+    pub signed_pre_key_store: &'a dyn SignedPreKeyStore,
 }
 
 pub async fn message_decrypt<R: Rng + CryptoRng>(
@@ -121,30 +250,65 @@ pub async fn message_decrypt<R: Rng + CryptoRng>(
     csprng: &mut R,
     ctx: Context,
 ) -> Result<Vec<u8>> {
+    let mut stores = SessionStores {
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+    };
+    message_decrypt_with_config(
+        ciphertext,
+        remote_address,
+        &mut stores,
+        &SessionConfig::default(),
+        csprng,
+        ctx,
+    )
+    .await
+}
+
+/// Like [`message_decrypt`], but decrypts according to `config`.
+pub async fn message_decrypt_with_config<R: Rng + CryptoRng>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    stores: &mut SessionStores<'_>,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<Vec<u8>> {
+    let (ptext, _report) =
+        message_decrypt_with_report(ciphertext, remote_address, stores, config, csprng, ctx)
+            .await?;
+    Ok(ptext)
+}
+
+/// Like [`message_decrypt`], but also reports which session state was used.
+///
+/// A message which only decrypts with a previous state suggests that the sender missed a session
+/// reset, so callers may want to log it or start a new session.
+pub async fn message_decrypt_with_report<R: Rng + CryptoRng>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    stores: &mut SessionStores<'_>,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<(Vec<u8>, DecryptionReport)> {
     match ciphertext {
         CiphertextMessage::SignalMessage(m) => {
-            message_decrypt_signal(
+            decrypt_signal_with_report(
                 m,
                 remote_address,
-                session_store,
-                identity_store,
+                stores.session_store,
+                stores.identity_store,
+                config,
                 csprng,
                 ctx,
             )
             .await
         }
         CiphertextMessage::PreKeySignalMessage(m) => {
-            message_decrypt_prekey(
-                m,
-                remote_address,
-                session_store,
-                identity_store,
-                pre_key_store,
-                signed_pre_key_store,
-                csprng,
-                ctx,
-            )
-            .await
+            decrypt_prekey_with_report(m, remote_address, stores, config, csprng, ctx).await
         }
         _ => Err(SignalProtocolError::InvalidArgument(
             "SessionCipher::decrypt cannot decrypt this message type".to_owned(),
@@ -162,33 +326,79 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
     csprng: &mut R,
     ctx: Context,
 ) -> Result<Vec<u8>> {
-    let mut session_record = session_store
+    let mut stores = SessionStores {
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+    };
+    message_decrypt_prekey_with_config(
+        ciphertext,
+        remote_address,
+        &mut stores,
+        &SessionConfig::default(),
+        csprng,
+        ctx,
+    )
+    .await
+}
+
+/// Like [`message_decrypt_prekey`], but decrypts according to `config`.
+pub async fn message_decrypt_prekey_with_config<R: Rng + CryptoRng>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    stores: &mut SessionStores<'_>,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<Vec<u8>> {
+    let (ptext, _report) =
+        decrypt_prekey_with_report(ciphertext, remote_address, stores, config, csprng, ctx).await?;
+    Ok(ptext)
+}
+
+async fn decrypt_prekey_with_report<R: Rng + CryptoRng>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    stores: &mut SessionStores<'_>,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<(Vec<u8>, DecryptionReport)> {
+    config.check_version(ciphertext.message_version())?;
+
+    let mut session_record = stores
+        .session_store
         .load_session(&remote_address, ctx)
         .await?
         .unwrap_or_else(SessionRecord::new_fresh);
+    session_record.set_max_previous_sessions(config.max_previous_sessions);
 
-    let pre_key_id = session::process_prekey(
+    let pre_key_id = session::process_prekey_with_config(
         ciphertext,
         &remote_address,
         &mut session_record,
-        identity_store,
-        pre_key_store,
-        signed_pre_key_store,
+        stores.identity_store,
+        stores.pre_key_store,
+        stores.signed_pre_key_store,
+        config,
         ctx,
     )
     .await?;
 
-    let ptext = decrypt_message_with_record(&mut session_record, ciphertext.message(), csprng)?;
+    let result =
+        decrypt_message_with_record(&mut session_record, ciphertext.message(), config, csprng)?;
 
-    session_store
+    stores
+        .session_store
         .store_session(&remote_address, &session_record, ctx)
         .await?;
 
     if let Some(pre_key_id) = pre_key_id {
-        pre_key_store.remove_pre_key(pre_key_id, ctx).await?;
+        stores.pre_key_store.remove_pre_key(pre_key_id, ctx).await?;
     }
 
-    Ok(ptext)
+    Ok(result)
 }
 
 pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
@@ -199,14 +409,331 @@ pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
     csprng: &mut R,
     ctx: Context,
 ) -> Result<Vec<u8>> {
+    message_decrypt_signal_with_config(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        &SessionConfig::default(),
+        csprng,
+        ctx,
+    )
+    .await
+}
+
+/// Like [`message_decrypt_signal`], but decrypts according to `config`.
+pub async fn message_decrypt_signal_with_config<R: Rng + CryptoRng>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<Vec<u8>> {
+    let (ptext, _report) = decrypt_signal_with_report(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        config,
+        csprng,
+        ctx,
+    )
+    .await?;
+    Ok(ptext)
+}
+
+async fn decrypt_signal_with_report<R: Rng + CryptoRng>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<(Vec<u8>, DecryptionReport)> {
     let mut session_record = session_store
         .load_session(&remote_address, ctx)
         .await?
         .ok_or(SignalProtocolError::SessionNotFound)?;
+    session_record.set_max_previous_sessions(config.max_previous_sessions);
 
-    let ptext = decrypt_message_with_record(&mut session_record, ciphertext, csprng)?;
+    let result = decrypt_message_with_record(&mut session_record, ciphertext, config, csprng)?;
 
     // Why are we performing this check after decryption instead of before?
+    trust_remote_identity(&session_record, remote_address, identity_store, ctx).await?;
+
+    session_store
+        .store_session(&remote_address, &session_record, ctx)
+        .await?;
+
+    Ok(result)
+}
+
+/// Like [`message_decrypt`], but leaves every store write to the caller.
+///
+/// The stores are only read from. The returned [`StoreTransaction`] holds the writes that
+/// [`message_decrypt`] would have made, in the same order, so that they can be committed
+/// together. Nothing is collected for a message that fails to decrypt.
+pub async fn message_decrypt_transactional<R: Rng + CryptoRng>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    stores: &ReadOnlySessionStores<'_>,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<(Vec<u8>, StoreTransaction)> {
+    match ciphertext {
+        CiphertextMessage::SignalMessage(m) => {
+            message_decrypt_signal_transactional(
+                m,
+                remote_address,
+                stores.session_store,
+                stores.identity_store,
+                config,
+                csprng,
+                ctx,
+            )
+            .await
+        }
+        CiphertextMessage::PreKeySignalMessage(m) => {
+            message_decrypt_prekey_transactional(m, remote_address, stores, config, csprng, ctx)
+                .await
+        }
+        _ => Err(SignalProtocolError::InvalidArgument(
+            "SessionCipher::decrypt cannot decrypt this message type".to_owned(),
+        )),
+    }
+}
+
+/// Like [`message_decrypt_prekey`], but leaves every store write to the caller.
+///
+/// See [`message_decrypt_transactional`].
+pub async fn message_decrypt_prekey_transactional<R: Rng + CryptoRng>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    stores: &ReadOnlySessionStores<'_>,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<(Vec<u8>, StoreTransaction)> {
+    config.check_version(ciphertext.message_version())?;
+
+    let mut session_record = stores
+        .session_store
+        .load_session(&remote_address, ctx)
+        .await?
+        .unwrap_or_else(SessionRecord::new_fresh);
+    session_record.set_max_previous_sessions(config.max_previous_sessions);
+
+    let pre_key_id = session::process_prekey_without_saving_identity(
+        ciphertext,
+        &remote_address,
+        &mut session_record,
+        stores.identity_store,
+        stores.pre_key_store,
+        stores.signed_pre_key_store,
+        config,
+        ctx,
+    )
+    .await?;
+
+    let (ptext, _report) =
+        decrypt_message_with_record(&mut session_record, ciphertext.message(), config, csprng)?;
+
+    let mut transaction = StoreTransaction::new();
+    transaction.push(StoreOp::SaveIdentity {
+        address: remote_address.clone(),
+        identity: *ciphertext.identity_key(),
+    });
+    transaction.push(StoreOp::StoreSession {
+        address: remote_address.clone(),
+        record: Box::new(session_record),
+    });
+    if let Some(pre_key_id) = pre_key_id {
+        transaction.push(StoreOp::RemovePreKey(pre_key_id));
+    }
+
+    Ok((ptext, transaction))
+}
+
+/// Like [`message_decrypt_signal`], but leaves every store write to the caller.
+///
+/// See [`message_decrypt_transactional`].
+pub async fn message_decrypt_signal_transactional<R: Rng + CryptoRng>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &dyn SessionStore,
+    identity_store: &dyn IdentityKeyStore,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<(Vec<u8>, StoreTransaction)> {
+    let mut session_record = session_store
+        .load_session(&remote_address, ctx)
+        .await?
+        .ok_or(SignalProtocolError::SessionNotFound)?;
+    session_record.set_max_previous_sessions(config.max_previous_sessions);
+
+    let (ptext, _report) =
+        decrypt_message_with_record(&mut session_record, ciphertext, config, csprng)?;
+
+    let their_identity_key =
+        check_remote_identity(&session_record, remote_address, identity_store, ctx).await?;
+
+    let mut transaction = StoreTransaction::new();
+    transaction.push(StoreOp::SaveIdentity {
+        address: remote_address.clone(),
+        identity: their_identity_key,
+    });
+    transaction.push(StoreOp::StoreSession {
+        address: remote_address.clone(),
+        record: Box::new(session_record),
+    });
+
+    Ok((ptext, transaction))
+}
+
+/// Decrypts a batch of messages from one sender, in order.
+///
+/// The session is loaded once before the batch and stored once after it, instead of once per
+/// message. Each message gets its own result; a message that fails to decrypt leaves the session
+/// as it was and does not stop the rest of the batch. One-time pre-keys used by the batch are
+/// removed after the session has been stored.
+///
+/// An `Err` is only returned for a failure to load or store the session or to remove a pre-key.
+pub async fn message_decrypt_batch<R: Rng + CryptoRng>(
+    messages: &[CiphertextMessage],
+    remote_address: &ProtocolAddress,
+    stores: &mut SessionStores<'_>,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ctx: Context,
+) -> Result<Vec<Result<Vec<u8>>>> {
+    let mut session_record = stores
+        .session_store
+        .load_session(&remote_address, ctx)
+        .await?;
+    if let Some(session_record) = &mut session_record {
+        session_record.set_max_previous_sessions(config.max_previous_sessions);
+    }
+    let mut used_pre_key_ids = Vec::new();
+    let mut modified = false;
+    let mut results = Vec::with_capacity(messages.len());
+
+    for message in messages {
+        // Work on a copy so that a failed message cannot leave a half-updated session behind.
+        let mut updated_record = match (message, &session_record) {
+            (CiphertextMessage::PreKeySignalMessage(_), None) => {
+                let mut record = SessionRecord::new_fresh();
+                record.set_max_previous_sessions(config.max_previous_sessions);
+                record
+            }
+            (_, Some(record)) => record.clone(),
+            (_, None) => {
+                results.push(Err(SignalProtocolError::SessionNotFound));
+                continue;
+            }
+        };
+
+        let result = match message {
+            CiphertextMessage::SignalMessage(m) => {
+                match decrypt_message_with_record(&mut updated_record, m, config, csprng) {
+                    Ok((ptext, _report)) => trust_remote_identity(
+                        &updated_record,
+                        remote_address,
+                        stores.identity_store,
+                        ctx,
+                    )
+                    .await
+                    .map(|()| ptext),
+                    Err(e) => Err(e),
+                }
+            }
+            CiphertextMessage::PreKeySignalMessage(m) => {
+                let processed = match config.check_version(m.message_version()) {
+                    Ok(()) => {
+                        session::process_prekey_with_config(
+                            m,
+                            &remote_address,
+                            &mut updated_record,
+                            stores.identity_store,
+                            stores.pre_key_store,
+                            stores.signed_pre_key_store,
+                            config,
+                            ctx,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                match processed {
+                    Ok(pre_key_id) => decrypt_message_with_record(
+                        &mut updated_record,
+                        m.message(),
+                        config,
+                        csprng,
+                    )
+                    .map(|(ptext, _report)| {
+                        if let Some(pre_key_id) = pre_key_id {
+                            if !used_pre_key_ids.contains(&pre_key_id) {
+                                used_pre_key_ids.push(pre_key_id);
+                            }
+                        }
+                        ptext
+                    }),
+                    Err(e) => Err(e),
+                }
+            }
+            _ => Err(SignalProtocolError::InvalidArgument(
+                "SessionCipher::decrypt cannot decrypt this message type".to_owned(),
+            )),
+        };
+
+        if result.is_ok() {
+            session_record = Some(updated_record);
+            modified = true;
+        }
+        results.push(result);
+    }
+
+    if let (true, Some(session_record)) = (modified, &session_record) {
+        stores
+            .session_store
+            .store_session(&remote_address, session_record, ctx)
+            .await?;
+    }
+
+    for pre_key_id in used_pre_key_ids {
+        stores.pre_key_store.remove_pre_key(pre_key_id, ctx).await?;
+    }
+
+    Ok(results)
+}
+
+async fn trust_remote_identity(
+    session_record: &SessionRecord,
+    remote_address: &ProtocolAddress,
+    identity_store: &mut dyn IdentityKeyStore,
+    ctx: Context,
+) -> Result<()> {
+    let their_identity_key =
+        check_remote_identity(session_record, remote_address, identity_store, ctx).await?;
+
+    identity_store
+        .save_identity(&remote_address, &their_identity_key, ctx)
+        .await?;
+
+    Ok(())
+}
+
+async fn check_remote_identity(
+    session_record: &SessionRecord,
+    remote_address: &ProtocolAddress,
+    identity_store: &dyn IdentityKeyStore,
+    ctx: Context,
+) -> Result<IdentityKey> {
     let their_identity_key = session_record
         .session_state()?
         .remote_identity_key()?
@@ -226,35 +753,55 @@ pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
         ));
     }
 
-    identity_store
-        .save_identity(&remote_address, &their_identity_key, ctx)
-        .await?;
-
-    session_store
-        .store_session(&remote_address, &session_record, ctx)
-        .await?;
-
-    Ok(ptext)
+    Ok(their_identity_key)
 }
 
 fn decrypt_message_with_record<R: Rng + CryptoRng>(
     record: &mut SessionRecord,
     ciphertext: &SignalMessage,
+    config: &SessionConfig,
     csprng: &mut R,
-) -> Result<Vec<u8>> {
-    let mut current_state = record.session_state()?.clone();
+) -> Result<(Vec<u8>, DecryptionReport)> {
+    // One plaintext buffer is shared by every state we try.
+    let mut ptext = Vec::new();
+    let report = decrypt_message_with_record_into(record, ciphertext, config, csprng, &mut ptext)?;
+    Ok((ptext, report))
+}
+
+fn decrypt_message_with_record_into<R: Rng + CryptoRng>(
+    record: &mut SessionRecord,
+    ciphertext: &SignalMessage,
+    config: &SessionConfig,
+    csprng: &mut R,
+    ptext: &mut Vec<u8>,
+) -> Result<DecryptionReport> {
+    config.check_version(ciphertext.message_version())?;
 
-    let result = decrypt_message_with_state(&mut current_state, ciphertext, csprng);
+    // The current state may have been archived, in which case only previous states are tried.
+    if let Ok(current_state) = record.session_state() {
+        let mut current_state = current_state.clone();
 
-    match result {
-        Ok(ptext) => {
-            record.set_session_state(current_state)?; // update the state
-            return Ok(ptext);
-        }
-        Err(SignalProtocolError::DuplicatedMessage(_, _)) => {
-            return result;
+        let result =
+            decrypt_message_with_state(&mut current_state, ciphertext, config, csprng, ptext);
+
+        match result {
+            Ok(()) => {
+                if let Some(now_ms) = config.now_ms {
+                    current_state.set_last_use_time(now_ms)?;
+                }
+                let report = DecryptionReport {
+                    state_used: SessionStateUsed::Current,
+                    session_version: current_state.session_version()?,
+                    counter: ciphertext.counter(),
+                };
+                record.set_session_state(current_state)?; // update the state
+                return Ok(report);
+            }
+            Err(e @ SignalProtocolError::DuplicatedMessage { .. }) => {
+                return Err(e);
+            }
+            Err(_) => {}
         }
-        Err(_) => {}
     }
 
     let mut updated_session = None;
@@ -262,24 +809,37 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
     for (idx, previous) in record.previous_session_states()?.enumerate() {
         let mut updated = previous.clone();
 
-        let result = decrypt_message_with_state(&mut updated, ciphertext, csprng);
+        let result = decrypt_message_with_state(&mut updated, ciphertext, config, csprng, ptext);
 
         match result {
-            Ok(ptext) => {
-                updated_session = Some((ptext, idx, updated));
+            Ok(()) => {
+                updated_session = Some((idx, updated));
                 break;
             }
-            Err(SignalProtocolError::DuplicatedMessage(_, _)) => {
-                return result;
+            Err(e @ SignalProtocolError::DuplicatedMessage { .. }) => {
+                return Err(e);
             }
             _ => {}
         }
     }
 
-    if let Some((ptext, idx, updated_session)) = updated_session {
-        record.promote_old_session(idx, updated_session)?;
-        Ok(ptext)
+    if let Some((idx, mut updated_session)) = updated_session {
+        if let Some(now_ms) = config.now_ms {
+            updated_session.set_last_use_time(now_ms)?;
+        }
+        let report = DecryptionReport {
+            state_used: SessionStateUsed::Previous(idx),
+            session_version: updated_session.session_version()?,
+            counter: ciphertext.counter(),
+        };
+        record.promote_old_session(idx)?;
+        record.set_session_state(updated_session)?;
+        Ok(report)
     } else {
+        log::debug!(
+            "no session state could decrypt the message (counter {})",
+            ciphertext.counter()
+        );
         Err(SignalProtocolError::InvalidMessage(
             "decryption failed; no matching session state",
         ))
@@ -289,9 +849,11 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
 fn decrypt_message_with_state<R: Rng + CryptoRng>(
     state: &mut SessionState,
     ciphertext: &SignalMessage,
+    config: &SessionConfig,
     csprng: &mut R,
-) -> Result<Vec<u8>> {
-    if !state.has_sender_chain()? {
+    ptext: &mut Vec<u8>,
+) -> Result<()> {
+    if !state.has_usable_sender_chain()? {
         return Err(SignalProtocolError::InvalidSessionStructure);
     }
 
@@ -305,7 +867,8 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
     let their_ephemeral = ciphertext.sender_ratchet_key();
     let counter = ciphertext.counter();
     let chain_key = get_or_create_chain_key(state, their_ephemeral, csprng)?;
-    let message_keys = get_or_create_message_key(state, their_ephemeral, &chain_key, counter)?;
+    let message_keys =
+        get_or_create_message_key(state, their_ephemeral, &chain_key, counter, config)?;
 
     let their_identity_key = state
         .remote_identity_key()?
@@ -321,15 +884,16 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
         return Err(SignalProtocolError::InvalidCiphertext);
     }
 
-    let ptext = crypto::aes_256_cbc_decrypt(
+    crypto::aes_256_cbc_decrypt_into(
         ciphertext.body(),
         message_keys.cipher_key(),
         message_keys.iv(),
+        ptext,
     )?;
 
     state.clear_unacknowledged_pre_key_message()?;
 
-    Ok(ptext)
+    Ok(())
 }
 
 pub async fn remote_registration_id(
@@ -393,13 +957,17 @@ fn get_or_create_message_key(
     their_ephemeral: &curve::PublicKey,
     chain_key: &ChainKey,
     counter: u32,
+    config: &SessionConfig,
 ) -> Result<MessageKeys> {
     let chain_index = chain_key.index();
 
     if chain_index > counter {
         return match state.get_message_keys(their_ephemeral, counter)? {
             Some(keys) => Ok(keys),
-            None => Err(SignalProtocolError::DuplicatedMessage(chain_index, counter)),
+            None => Err(SignalProtocolError::DuplicatedMessage {
+                current_iteration: chain_index,
+                counter,
+            }),
         };
     }
 
@@ -407,7 +975,7 @@ fn get_or_create_message_key(
 
     let jump = (counter - chain_index) as usize;
 
-    if jump > MAX_FORWARD_JUMPS {
+    if jump > config.max_skipped_message_keys {
         return Err(SignalProtocolError::InvalidMessage(
             "message from too far into the future",
         ));
@@ -417,7 +985,11 @@ fn get_or_create_message_key(
 
     while chain_key.index() < counter {
         let message_keys = chain_key.message_keys()?;
-        state.set_message_keys(their_ephemeral, &message_keys)?;
+        state.set_message_keys_with_limit(
+            their_ephemeral,
+            &message_keys,
+            config.max_skipped_message_keys,
+        )?;
         chain_key = chain_key.next_chain_key()?;
     }
 
@@ -5,10 +5,12 @@
 
 mod bundle;
 mod prekey;
+mod registration_id;
 mod session;
 mod signed_prekey;
 
 pub use bundle::PreKeyBundle;
 pub use prekey::{PreKeyId, PreKeyRecord};
+pub use registration_id::RegistrationId;
 pub use session::{SessionRecord, SessionState};
 pub use signed_prekey::{SignedPreKeyId, SignedPreKeyRecord};
@@ -4,17 +4,16 @@
 //
 
 use crate::curve;
-use crate::IdentityKey;
+use crate::{DeviceId, IdentityKey};
 
 use crate::error::{Result, SignalProtocolError};
-use crate::state::{PreKeyId, SignedPreKeyId};
+use crate::state::{PreKeyId, RegistrationId, SignedPreKeyId};
 
 #[derive(Debug, Clone)]
 pub struct PreKeyBundle {
-    registration_id: u32,
-    device_id: u32,
-    pre_key_id: Option<PreKeyId>,
-    pre_key_public: Option<curve::PublicKey>,
+    registration_id: RegistrationId,
+    device_id: DeviceId,
+    pre_key: Option<(PreKeyId, curve::PublicKey)>,
     signed_pre_key_id: SignedPreKeyId,
     signed_pre_key_public: curve::PublicKey,
     signed_pre_key_signature: Vec<u8>,
@@ -22,25 +21,21 @@ pub struct PreKeyBundle {
 }
 
 impl PreKeyBundle {
+    /// Creates a bundle without checking the signed pre-key signature; that happens when the
+    /// bundle is processed. `pre_key` is the optional one-time pre-key and its id.
     pub fn new(
-        registration_id: u32,
-        device_id: u32,
-        pre_key_id: Option<PreKeyId>,
-        pre_key_public: Option<curve::PublicKey>,
+        registration_id: RegistrationId,
+        device_id: DeviceId,
+        pre_key: Option<(PreKeyId, curve::PublicKey)>,
         signed_pre_key_id: SignedPreKeyId,
         signed_pre_key_public: curve::PublicKey,
         signed_pre_key_signature: Vec<u8>,
         identity_key: IdentityKey,
     ) -> Result<Self> {
-        if pre_key_public.is_some() != pre_key_id.is_some() {
-            return Err(SignalProtocolError::InvalidPreKeyBundle);
-        }
-
         Ok(Self {
             registration_id,
             device_id,
-            pre_key_id,
-            pre_key_public,
+            pre_key,
             signed_pre_key_id,
             signed_pre_key_public,
             signed_pre_key_signature,
@@ -48,20 +43,55 @@ impl PreKeyBundle {
         })
     }
 
-    pub fn registration_id(&self) -> Result<u32> {
+    /// Like [`PreKeyBundle::new`], but fails with
+    /// [`SignalProtocolError::SignatureValidationFailed`] unless the signed pre-key is signed by
+    /// `identity_key`.
+    pub fn new_validated(
+        registration_id: RegistrationId,
+        device_id: DeviceId,
+        pre_key: Option<(PreKeyId, curve::PublicKey)>,
+        signed_pre_key_id: SignedPreKeyId,
+        signed_pre_key_public: curve::PublicKey,
+        signed_pre_key_signature: Vec<u8>,
+        identity_key: IdentityKey,
+    ) -> Result<Self> {
+        let bundle = Self::new(
+            registration_id,
+            device_id,
+            pre_key,
+            signed_pre_key_id,
+            signed_pre_key_public,
+            signed_pre_key_signature,
+            identity_key,
+        )?;
+        if !bundle.has_valid_signature()? {
+            return Err(SignalProtocolError::SignatureValidationFailed);
+        }
+        Ok(bundle)
+    }
+
+    pub(crate) fn has_valid_signature(&self) -> Result<bool> {
+        curve::verify_signature(
+            self.identity_key.public_key(),
+            &self.signed_pre_key_public.serialize(),
+            &self.signed_pre_key_signature,
+        )
+    }
+
+    pub fn registration_id(&self) -> Result<RegistrationId> {
         Ok(self.registration_id)
     }
 
-    pub fn device_id(&self) -> Result<u32> {
+    pub fn device_id(&self) -> Result<DeviceId> {
         Ok(self.device_id)
     }
 
     pub fn pre_key_id(&self) -> Result<Option<PreKeyId>> {
-        Ok(self.pre_key_id)
+        Ok(self.pre_key.map(|(id, _)| id))
     }
 
     pub fn pre_key_public(&self) -> Result<Option<curve::PublicKey>> {
-        Ok(self.pre_key_public)
+        Ok(self.pre_key.map(|(_, key)| key))
     }
 
     pub fn signed_pre_key_id(&self) -> Result<SignedPreKeyId> {
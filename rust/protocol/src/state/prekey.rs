@@ -4,7 +4,8 @@
 //
 
 use crate::curve;
-use crate::error::Result;
+use crate::error::{Result, SignalProtocolError};
+use crate::proto;
 use crate::proto::storage::PreKeyRecordStructure;
 use prost::Message;
 
@@ -16,6 +17,9 @@ pub struct PreKeyRecord {
 }
 
 impl PreKeyRecord {
+    /// The format version written by [`serialize`](Self::serialize).
+    pub const SERIALIZED_VERSION: u32 = 1;
+
     pub fn new(id: PreKeyId, key: &curve::KeyPair) -> Self {
         let public_key = key.public_key.serialize().to_vec();
         let private_key = key.private_key.serialize().to_vec();
@@ -24,30 +28,38 @@ impl PreKeyRecord {
                 id,
                 public_key,
                 private_key,
+                version: Self::SERIALIZED_VERSION,
             },
         }
     }
 
     pub fn deserialize(data: &[u8]) -> Result<Self> {
-        Ok(Self {
-            pre_key: PreKeyRecordStructure::decode(data)?,
-        })
+        let mut pre_key = PreKeyRecordStructure::decode(data)?;
+        if pre_key.version > Self::SERIALIZED_VERSION {
+            return Err(SignalProtocolError::UnrecognizedRecordVersion(
+                pre_key.version,
+            ));
+        }
+        pre_key.version = Self::SERIALIZED_VERSION;
+        Ok(Self { pre_key })
     }
 
-    pub fn id(&self) -> Result<PreKeyId> {
-        Ok(self.pre_key.id)
+    /// Reads the format version of a serialized pre-key record without decoding the rest of it.
+    pub fn serialized_version(data: &[u8]) -> Result<u32> {
+        Ok(proto::peek_uint32_field(data, 4)?)
     }
 
-    pub fn key_pair(&self) -> Result<curve::KeyPair> {
-        curve::KeyPair::from_public_and_private(&self.pre_key.public_key, &self.pre_key.private_key)
+    /// Rewrites a serialized pre-key record of any supported version in the current format.
+    pub fn migrate(data: &[u8]) -> Result<Vec<u8>> {
+        Self::deserialize(data)?.serialize()
     }
 
-    pub fn public_key(&self) -> Result<curve::PublicKey> {
-        curve::PublicKey::deserialize(&self.pre_key.public_key)
+    pub fn id(&self) -> Result<PreKeyId> {
+        Ok(self.pre_key.id)
     }
 
-    pub fn private_key(&self) -> Result<curve::PrivateKey> {
-        curve::PrivateKey::deserialize(&self.pre_key.private_key)
+    pub fn public_key(&self) -> Result<curve::PublicKey> {
+        curve::PublicKey::deserialize(&self.pre_key.public_key)
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>> {
@@ -56,3 +68,16 @@ impl PreKeyRecord {
         Ok(buf)
     }
 }
+
+/// Access to the private key. Callers building an upload payload only need the accessors above;
+/// a record whose private key lives elsewhere (in hardware, say) would fail these with
+/// `OperationNotSupported`.
+impl PreKeyRecord {
+    pub fn private_key(&self) -> Result<curve::PrivateKey> {
+        curve::PrivateKey::deserialize(&self.pre_key.private_key)
+    }
+
+    pub fn key_pair(&self) -> Result<curve::KeyPair> {
+        curve::KeyPair::from_public_and_private(&self.pre_key.public_key, &self.pre_key.private_key)
+    }
+}
@@ -0,0 +1,61 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use crate::error::{Result, SignalProtocolError};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A registration id, guaranteed to be at most 16383 (0x3FFF).
+///
+/// The upper bound matches the encoding constraints of sealed sender v2, which carries
+/// registration ids in 14 bits.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub struct RegistrationId(u16);
+
+impl RegistrationId {
+    pub const MAX: u32 = 0x3FFF;
+}
+
+impl TryFrom<u32> for RegistrationId {
+    type Error = SignalProtocolError;
+
+    fn try_from(value: u32) -> Result<Self> {
+        if value > Self::MAX {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "registration id {} out of range",
+                value
+            )));
+        }
+        Ok(RegistrationId(value as u16))
+    }
+}
+
+impl From<RegistrationId> for u32 {
+    fn from(value: RegistrationId) -> Self {
+        value.0.into()
+    }
+}
+
+impl fmt::Display for RegistrationId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_is_enforced() {
+        assert_eq!(u32::from(RegistrationId::try_from(0).unwrap()), 0);
+        assert_eq!(u32::from(RegistrationId::try_from(16383).unwrap()), 16383);
+        assert!(matches!(
+            RegistrationId::try_from(16384),
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+        assert!(RegistrationId::try_from(u32::MAX).is_err());
+    }
+}
@@ -5,11 +5,13 @@
 
 use crate::error::{Result, SignalProtocolError};
 use crate::ratchet::{ChainKey, MessageKeys, RootKey};
+use crate::state::RegistrationId;
 use crate::IdentityKey;
 
 use crate::consts;
 use crate::curve;
 use crate::kdf;
+use crate::proto;
 use crate::proto::storage::session_structure;
 use crate::proto::storage::{RecordStructure, SessionStructure};
 use prost::Message;
@@ -135,6 +137,50 @@ impl SessionState {
         Ok(self.session.sender_chain.is_some())
     }
 
+    /// Whether the sender chain has both a ratchet key and a chain key, so that this state can be
+    /// used to encrypt and to accept replies.
+    pub fn has_usable_sender_chain(&self) -> Result<bool> {
+        match &self.session.sender_chain {
+            None => Ok(false),
+            Some(chain) => Ok(!chain.sender_ratchet_key.is_empty() && chain.chain_key.is_some()),
+        }
+    }
+
+    /// The counter of the next message this state will send, or `None` if there is no sender
+    /// chain.
+    pub fn sender_chain_counter(&self) -> Result<Option<u32>> {
+        match &self.session.sender_chain {
+            None => Ok(None),
+            Some(chain) => match &chain.chain_key {
+                None => Err(SignalProtocolError::InvalidProtobufEncoding),
+                Some(c) => Ok(Some(c.index)),
+            },
+        }
+    }
+
+    /// The counter of the next message expected on the receiver chain for `their_ephemeral`, or
+    /// `None` if there is no such chain.
+    ///
+    /// Messages with a lower counter can only be decrypted if their keys were kept when they were
+    /// skipped.
+    pub fn receiver_chain_counter(
+        &self,
+        their_ephemeral: &curve::PublicKey,
+    ) -> Result<Option<u32>> {
+        match self.get_receiver_chain(their_ephemeral)? {
+            None => Ok(None),
+            Some((chain, _)) => match chain.chain_key {
+                None => Err(SignalProtocolError::InvalidProtobufEncoding),
+                Some(c) => Ok(Some(c.index)),
+            },
+        }
+    }
+
+    /// The number of receiver chains kept; the oldest are dropped once there are too many.
+    pub fn number_of_receiver_chains(&self) -> Result<usize> {
+        Ok(self.session.receiver_chains.len())
+    }
+
     pub fn get_receiver_chain(
         &self,
         sender: &curve::PublicKey,
@@ -294,6 +340,17 @@ impl SessionState {
         &mut self,
         sender: &curve::PublicKey,
         message_keys: &MessageKeys,
+    ) -> Result<()> {
+        self.set_message_keys_with_limit(sender, message_keys, consts::MAX_MESSAGE_KEYS)
+    }
+
+    /// Like [`set_message_keys`](Self::set_message_keys), but keeps at most `max_message_keys`
+    /// keys for the chain, discarding the oldest.
+    pub(crate) fn set_message_keys_with_limit(
+        &mut self,
+        sender: &curve::PublicKey,
+        message_keys: &MessageKeys,
+        max_message_keys: usize,
     ) -> Result<()> {
         let new_keys = session_structure::chain::MessageKey {
             cipher_key: message_keys.cipher_key().to_vec(),
@@ -306,9 +363,7 @@ impl SessionState {
             let mut updated_chain = chain_and_index.0;
             updated_chain.message_keys.insert(0, new_keys);
 
-            if updated_chain.message_keys.len() > consts::MAX_MESSAGE_KEYS {
-                updated_chain.message_keys.pop();
-            }
+            updated_chain.message_keys.truncate(max_message_keys);
 
             self.session.receiver_chains[chain_and_index.1] = updated_chain;
             Ok(())
@@ -379,24 +434,61 @@ impl SessionState {
         Ok(())
     }
 
-    pub fn set_remote_registration_id(&mut self, registration_id: u32) -> Result<()> {
-        self.session.remote_registration_id = registration_id;
+    pub fn set_remote_registration_id(&mut self, registration_id: RegistrationId) -> Result<()> {
+        self.session.remote_registration_id = registration_id.into();
         Ok(())
     }
 
+    /// Returned as stored, without range checking, so that sessions created before
+    /// [RegistrationId] was enforced keep working.
     pub fn remote_registration_id(&self) -> Result<u32> {
         Ok(self.session.remote_registration_id)
     }
 
-    pub fn set_local_registration_id(&mut self, registration_id: u32) -> Result<()> {
-        self.session.local_registration_id = registration_id;
+    pub fn set_local_registration_id(&mut self, registration_id: RegistrationId) -> Result<()> {
+        self.session.local_registration_id = registration_id.into();
         Ok(())
     }
 
+    /// Returned as stored; see [remote_registration_id](Self::remote_registration_id).
     pub fn local_registration_id(&self) -> Result<u32> {
         Ok(self.session.local_registration_id)
     }
 
+    /// When this session was created, in milliseconds since the Unix epoch, if the application
+    /// recorded it.
+    ///
+    /// The library never reads the clock itself; the time comes from
+    /// [`SessionConfig::now_ms`](crate::SessionConfig) when the session is created, or from
+    /// [`set_creation_time`](Self::set_creation_time).
+    pub fn creation_time(&self) -> Result<Option<u64>> {
+        Ok(nonzero_time(self.session.creation_time))
+    }
+
+    pub fn set_creation_time(&mut self, time_ms: u64) -> Result<()> {
+        self.session.creation_time = time_ms;
+        Ok(())
+    }
+
+    /// When a message was last successfully decrypted with this session, in milliseconds since
+    /// the Unix epoch, if the application recorded it.
+    pub fn last_use_time(&self) -> Result<Option<u64>> {
+        Ok(nonzero_time(self.session.last_use_time))
+    }
+
+    pub fn set_last_use_time(&mut self, time_ms: u64) -> Result<()> {
+        self.session.last_use_time = time_ms;
+        Ok(())
+    }
+
+    /// The most recent of the creation and last use times, if either is known.
+    fn last_activity_time(&self) -> Option<u64> {
+        nonzero_time(std::cmp::max(
+            self.session.creation_time,
+            self.session.last_use_time,
+        ))
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>> {
         let mut buf = vec![];
         self.session.encode(&mut buf)?;
@@ -404,6 +496,14 @@ impl SessionState {
     }
 }
 
+fn nonzero_time(time_ms: u64) -> Option<u64> {
+    if time_ms == 0 {
+        None
+    } else {
+        Some(time_ms)
+    }
+}
+
 impl From<SessionStructure> for SessionState {
     fn from(value: SessionStructure) -> SessionState {
         SessionState::new(value)
@@ -426,13 +526,19 @@ impl From<&SessionState> for SessionStructure {
 pub struct SessionRecord {
     pub current_session: Option<SessionState>,
     pub previous_sessions: VecDeque<SessionState>,
+    max_previous_sessions: usize,
 }
 
 impl SessionRecord {
+    /// The format version written by [`serialize`](Self::serialize). Records written before
+    /// versions were recorded have version 0; records with a later version are rejected.
+    pub const SERIALIZED_VERSION: u32 = 1;
+
     pub fn new_fresh() -> Self {
         Self {
             current_session: None,
             previous_sessions: VecDeque::new(),
+            max_previous_sessions: consts::ARCHIVED_STATES_MAX_LENGTH,
         }
     }
 
@@ -440,11 +546,17 @@ impl SessionRecord {
         Self {
             current_session: Some(state),
             previous_sessions: VecDeque::new(),
+            max_previous_sessions: consts::ARCHIVED_STATES_MAX_LENGTH,
         }
     }
 
     pub fn deserialize(bytes: &[u8]) -> Result<Self> {
         let record = RecordStructure::decode(bytes)?;
+        if record.version > Self::SERIALIZED_VERSION {
+            return Err(SignalProtocolError::UnrecognizedRecordVersion(
+                record.version,
+            ));
+        }
 
         let mut previous = VecDeque::with_capacity(record.previous_sessions.len());
         for s in record.previous_sessions {
@@ -454,9 +566,20 @@ impl SessionRecord {
         Ok(Self {
             current_session: record.current_session.map(|s| s.into()),
             previous_sessions: previous,
+            max_previous_sessions: consts::ARCHIVED_STATES_MAX_LENGTH,
         })
     }
 
+    /// Reads the format version of a serialized session record without decoding the rest of it.
+    pub fn serialized_version(bytes: &[u8]) -> Result<u32> {
+        Ok(proto::peek_uint32_field(bytes, 3)?)
+    }
+
+    /// Rewrites a serialized session record of any supported version in the current format.
+    pub fn migrate(bytes: &[u8]) -> Result<Vec<u8>> {
+        Self::deserialize(bytes)?.serialize()
+    }
+
     pub fn has_session_state(&self, version: u32, alice_base_key: &[u8]) -> Result<bool> {
         if let Some(current_session) = &self.current_session {
             if current_session.session_version()? == version
@@ -478,14 +601,42 @@ impl SessionRecord {
     }
 
     pub fn session_state(&self) -> Result<&SessionState> {
-        if let Some(ref session) = self.current_session {
-            Ok(session)
-        } else {
-            Err(SignalProtocolError::InvalidState(
-                "session_state",
-                "No session".into(),
-            ))
-        }
+        self.current_session_for("session_state")
+    }
+
+    fn current_session_for(&self, caller: &'static str) -> Result<&SessionState> {
+        self.current_session
+            .as_ref()
+            .ok_or_else(|| SignalProtocolError::InvalidState(caller, "No session".into()))
+    }
+
+    /// The local registration ID of the current session.
+    pub fn local_registration_id(&self) -> Result<u32> {
+        self.current_session_for("local_registration_id")?
+            .local_registration_id()
+    }
+
+    /// The remote registration ID of the current session.
+    pub fn remote_registration_id(&self) -> Result<u32> {
+        self.current_session_for("remote_registration_id")?
+            .remote_registration_id()
+    }
+
+    /// The base key the current session was built from.
+    pub fn alice_base_key(&self) -> Result<&[u8]> {
+        self.current_session_for("alice_base_key")?.alice_base_key()
+    }
+
+    /// The protocol version of the current session.
+    pub fn session_version(&self) -> Result<u32> {
+        self.current_session_for("session_version")?
+            .session_version()
+    }
+
+    /// The remote identity of the current session, if one was recorded.
+    pub fn remote_identity_key(&self) -> Result<Option<IdentityKey>> {
+        self.current_session_for("remote_identity_key")?
+            .remote_identity_key()
     }
 
     pub fn session_state_mut(&mut self) -> Result<&mut SessionState> {
@@ -504,19 +655,35 @@ impl SessionRecord {
         Ok(())
     }
 
+    /// Archived session states, most recently archived first.
     pub fn previous_session_states(&self) -> Result<impl Iterator<Item = &SessionState>> {
         Ok(self.previous_sessions.iter())
     }
 
-    pub fn promote_old_session(
-        &mut self,
-        old_session: usize,
-        updated_session: SessionState,
-    ) -> Result<()> {
-        self.previous_sessions.remove(old_session).ok_or_else(|| {
+    /// Makes the archived state at `old_session` (an index into
+    /// [`previous_session_states`](Self::previous_session_states)) current again, archiving the
+    /// current state in its place.
+    pub fn promote_old_session(&mut self, old_session: usize) -> Result<()> {
+        let session = self.previous_sessions.remove(old_session).ok_or_else(|| {
             SignalProtocolError::InvalidState("promote_old_session", "out of range".into())
         })?;
-        self.promote_state(updated_session)
+        self.promote_state(session)
+    }
+
+    /// The number of archived states kept; older ones are discarded as new states are archived.
+    ///
+    /// This is not serialized: records always start with the default of 40. The session cipher
+    /// functions replace it with [`SessionConfig::max_previous_sessions`](crate::SessionConfig)
+    /// whenever they load a record.
+    pub fn max_previous_sessions(&self) -> usize {
+        self.max_previous_sessions
+    }
+
+    /// Changes the number of archived states kept, immediately discarding the oldest states if
+    /// there are already more than `max_previous_sessions`.
+    pub fn set_max_previous_sessions(&mut self, max_previous_sessions: usize) {
+        self.max_previous_sessions = max_previous_sessions;
+        self.previous_sessions.truncate(max_previous_sessions);
     }
 
     pub fn is_fresh(&self) -> Result<bool> {
@@ -533,20 +700,34 @@ impl SessionRecord {
         if self.current_session.is_some() {
             self.previous_sessions
                 .push_front(self.current_session.take().expect("Checked is_some"));
-            if self.previous_sessions.len() > consts::ARCHIVED_STATES_MAX_LENGTH {
-                self.previous_sessions.pop_back();
-            }
+            self.previous_sessions.truncate(self.max_previous_sessions);
         }
 
         Ok(())
     }
 
+    /// Discards previous session states whose last activity (creation or last use, whichever is
+    /// later) was before `cutoff_ms`, returning how many were removed.
+    ///
+    /// The current state is never removed, nor are previous states with no recorded times (such
+    /// as those from records written before times were tracked).
+    pub fn remove_states_older_than(&mut self, cutoff_ms: u64) -> usize {
+        let before = self.previous_sessions.len();
+        self.previous_sessions
+            .retain(|state| match state.last_activity_time() {
+                Some(time_ms) => time_ms >= cutoff_ms,
+                None => true,
+            });
+        before - self.previous_sessions.len()
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>> {
         let mut buf = vec![];
 
         let record = RecordStructure {
             current_session: self.current_session.as_ref().map(|s| s.into()),
             previous_sessions: self.previous_sessions.iter().map(|s| s.into()).collect(),
+            version: Self::SERIALIZED_VERSION,
         };
         record.encode(&mut buf)?;
         Ok(buf)
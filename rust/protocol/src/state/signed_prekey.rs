@@ -4,7 +4,8 @@
 //
 
 use crate::curve;
-use crate::error::Result;
+use crate::error::{Result, SignalProtocolError};
+use crate::proto;
 use crate::proto::storage::SignedPreKeyRecordStructure;
 use prost::Message;
 
@@ -16,6 +17,9 @@ pub struct SignedPreKeyRecord {
 }
 
 impl SignedPreKeyRecord {
+    /// The format version written by [`serialize`](Self::serialize).
+    pub const SERIALIZED_VERSION: u32 = 1;
+
     pub fn new(id: SignedPreKeyId, timestamp: u64, key: &curve::KeyPair, signature: &[u8]) -> Self {
         let public_key = key.public_key.serialize().to_vec();
         let private_key = key.private_key.serialize().to_vec();
@@ -27,14 +31,30 @@ impl SignedPreKeyRecord {
                 public_key,
                 private_key,
                 signature,
+                version: Self::SERIALIZED_VERSION,
             },
         }
     }
 
     pub fn deserialize(data: &[u8]) -> Result<Self> {
-        Ok(Self {
-            signed_pre_key: SignedPreKeyRecordStructure::decode(data)?,
-        })
+        let mut signed_pre_key = SignedPreKeyRecordStructure::decode(data)?;
+        if signed_pre_key.version > Self::SERIALIZED_VERSION {
+            return Err(SignalProtocolError::UnrecognizedRecordVersion(
+                signed_pre_key.version,
+            ));
+        }
+        signed_pre_key.version = Self::SERIALIZED_VERSION;
+        Ok(Self { signed_pre_key })
+    }
+
+    /// Reads the format version of a serialized signed pre-key record without decoding the rest of it.
+    pub fn serialized_version(data: &[u8]) -> Result<u32> {
+        Ok(proto::peek_uint32_field(data, 6)?)
+    }
+
+    /// Rewrites a serialized signed pre-key record of any supported version in the current format.
+    pub fn migrate(data: &[u8]) -> Result<Vec<u8>> {
+        Self::deserialize(data)?.serialize()
     }
 
     pub fn id(&self) -> Result<SignedPreKeyId> {
@@ -53,6 +73,16 @@ impl SignedPreKeyRecord {
         curve::PublicKey::deserialize(&self.signed_pre_key.public_key)
     }
 
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        self.signed_pre_key.encode(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Access to the private key, kept apart from the accessors needed to upload the signed pre-key,
+/// as for [`PreKeyRecord`](crate::PreKeyRecord).
+impl SignedPreKeyRecord {
     pub fn private_key(&self) -> Result<curve::PrivateKey> {
         curve::PrivateKey::deserialize(&self.signed_pre_key.private_key)
     }
@@ -63,10 +93,4 @@ impl SignedPreKeyRecord {
             &self.signed_pre_key.private_key,
         )
     }
-
-    pub fn serialize(&self) -> Result<Vec<u8>> {
-        let mut buf = vec![];
-        self.signed_pre_key.encode(&mut buf)?;
-        Ok(buf)
-    }
 }
@@ -3,16 +3,25 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+#[cfg(feature = "fs-store")]
+mod file;
 mod inmem;
+mod rotation;
 mod traits;
+mod transaction;
 
 pub use {
     inmem::{
         InMemIdentityKeyStore, InMemPreKeyStore, InMemSenderKeyStore, InMemSessionStore,
         InMemSignalProtocolStore, InMemSignedPreKeyStore,
     },
+    rotation::remove_expired_signed_pre_keys,
     traits::{
-        Context, Direction, IdentityKeyStore, PreKeyStore, ProtocolStore, SenderKeyStore,
-        SessionStore, SignedPreKeyStore,
+        Context, Direction, IdentityChange, IdentityKeyStore, PreKeyStore, ProtocolStore,
+        SenderKeyStore, SessionStore, SignedPreKeyStore, TransactionalStore,
     },
+    transaction::{StoreOp, StoreTransaction},
 };
+
+#[cfg(feature = "fs-store")]
+pub use file::{FileSignalProtocolStore, FILE_STORE_VERSION};
@@ -0,0 +1,781 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use crate::{
+    DeviceId, IdentityKey, IdentityKeyPair, PreKeyRecord, ProtocolAddress, SenderKeyName,
+    SenderKeyRecord, SessionRecord, SignalProtocolError, SignedPreKeyRecord,
+};
+
+use crate::error::{ErrorCause, Result};
+
+use crate::state::{PreKeyId, RegistrationId, SignedPreKeyId};
+use crate::storage::traits;
+use crate::storage::Context;
+
+use async_trait::async_trait;
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// The layout version written to the `VERSION` file of every store directory.
+pub const FILE_STORE_VERSION: u32 = 1;
+
+const VERSION_FILE: &str = "VERSION";
+const IDENTITY_KEY_PAIR_FILE: &str = "identity_key_pair";
+const REGISTRATION_ID_FILE: &str = "registration_id";
+const CURRENT_SIGNED_PRE_KEY_FILE: &str = "current_signed_pre_key";
+const TEMPORARY_SUFFIX: &str = ".tmp";
+
+const SESSIONS_DIR: &str = "sessions";
+const IDENTITIES_DIR: &str = "identities";
+const PRE_KEYS_DIR: &str = "pre_keys";
+const SIGNED_PRE_KEYS_DIR: &str = "signed_pre_keys";
+const SENDER_KEYS_DIR: &str = "sender_keys";
+
+/// A protocol store that keeps each record in its own file under a directory.
+///
+/// Records are stored in their usual protobuf serialization. Every write goes to a temporary file
+/// which is then renamed over the record, so a reader, or a store reopened after a crash, sees
+/// either the old record or the new one, never a partial write. Leftover temporary files are
+/// ignored.
+///
+/// No locking is done: any number of stores may read a directory concurrently, but only one may
+/// write to it at a time, and callers are responsible for ensuring that.
+///
+/// On Unix, the directories the store creates are only accessible to their owner (mode 0700),
+/// and so are its files (mode 0600). A `root` that already exists keeps its permissions.
+pub struct FileSignalProtocolStore {
+    root: PathBuf,
+    key_pair: IdentityKeyPair,
+    registration_id: RegistrationId,
+}
+
+impl FileSignalProtocolStore {
+    /// Creates a new store in `root`, which may already exist but must not contain a store.
+    ///
+    /// The `VERSION` file is written last, so a directory left behind by an interrupted call is
+    /// not a store and can be passed to `create` again.
+    pub fn create<P: AsRef<Path>>(
+        root: P,
+        key_pair: IdentityKeyPair,
+        registration_id: RegistrationId,
+    ) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        let version_path = root.join(VERSION_FILE);
+        if version_path.exists() {
+            return Err(SignalProtocolError::InvalidState(
+                "FileSignalProtocolStore::create",
+                format!("{} already contains a store", root.display()),
+            ));
+        }
+
+        create_private_dir(&root)?;
+        for dir in &[
+            SESSIONS_DIR,
+            IDENTITIES_DIR,
+            PRE_KEYS_DIR,
+            SIGNED_PRE_KEYS_DIR,
+            SENDER_KEYS_DIR,
+        ] {
+            create_private_dir(&root.join(dir))?;
+        }
+        write_atomically(&root.join(IDENTITY_KEY_PAIR_FILE), &key_pair.serialize()?)?;
+        write_atomically(
+            &root.join(REGISTRATION_ID_FILE),
+            &u32::from(registration_id).to_be_bytes(),
+        )?;
+        write_atomically(&version_path, FILE_STORE_VERSION.to_string().as_bytes())?;
+
+        Ok(Self {
+            root,
+            key_pair,
+            registration_id,
+        })
+    }
+
+    /// Opens a store previously made by [FileSignalProtocolStore::create].
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+
+        let version = read_if_present(&root.join(VERSION_FILE))?
+            .and_then(|v| String::from_utf8(v).ok())
+            .and_then(|v| v.trim().parse::<u32>().ok());
+        match version {
+            Some(FILE_STORE_VERSION) => {}
+            Some(v) => return Err(SignalProtocolError::UnrecognizedRecordVersion(v)),
+            None => {
+                return Err(SignalProtocolError::InvalidState(
+                    "FileSignalProtocolStore::open",
+                    format!("{} does not contain a store", root.display()),
+                ))
+            }
+        }
+
+        let key_pair = read_required(&root.join(IDENTITY_KEY_PAIR_FILE))?;
+        let key_pair = IdentityKeyPair::try_from(&key_pair[..])?;
+
+        let registration_id = read_required(&root.join(REGISTRATION_ID_FILE))?;
+        let registration_id = <[u8; 4]>::try_from(&registration_id[..])
+            .map(u32::from_be_bytes)
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        let registration_id = RegistrationId::try_from(registration_id)?;
+
+        Ok(Self {
+            root,
+            key_pair,
+            registration_id,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn address_path(&self, dir: &str, address: &ProtocolAddress) -> PathBuf {
+        self.root.join(dir).join(address_file_name(address))
+    }
+
+    fn pre_key_path(&self, dir: &str, id: u32) -> PathBuf {
+        self.root.join(dir).join(id.to_string())
+    }
+
+    fn sender_key_path(&self, sender_key_name: &SenderKeyName) -> Result<PathBuf> {
+        let file_name = format!(
+            "{}.{}",
+            encode_name(&sender_key_name.group_id()?),
+            address_file_name(&sender_key_name.sender()?)
+        );
+        Ok(self.root.join(SENDER_KEYS_DIR).join(file_name))
+    }
+
+    /// Lists the sender key records for `group_id`, with the sender each belongs to.
+    fn group_sender_key_files(&self, group_id: &str) -> Result<Vec<(String, ProtocolAddress)>> {
+        let prefix = format!("{}.", encode_name(group_id));
+        let mut files = Vec::new();
+        for file_name in list_records(&self.root.join(SENDER_KEYS_DIR))? {
+            if let Some(sender) = file_name
+                .strip_prefix(&prefix)
+                .and_then(parse_address_file_name)
+            {
+                files.push((file_name, sender));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Lists the addresses of the records in `dir`.
+    fn addresses(&self, dir: &str) -> Result<Vec<ProtocolAddress>> {
+        let mut addresses = Vec::new();
+        for file_name in list_records(&self.root.join(dir))? {
+            if let Some(address) = parse_address_file_name(&file_name) {
+                addresses.push(address);
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+fn io_error(path: &Path, e: io::Error) -> SignalProtocolError {
+    SignalProtocolError::StorageIoError(path.display().to_string(), ErrorCause::new(e))
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut temporary = OsString::from(path.as_os_str());
+    temporary.push(TEMPORARY_SUFFIX);
+    PathBuf::from(temporary)
+}
+
+/// Creates `path` and any missing parents, readable only by the owner.
+fn create_private_dir(path: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(path).map_err(|e| io_error(path, e))
+}
+
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let temporary = temporary_path(path);
+    // A leftover temporary file could have any permissions, so start from a new one.
+    remove_if_present(&temporary)?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(&temporary)
+        .map_err(|e| io_error(&temporary, e))?;
+    file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| io_error(&temporary, e))?;
+    fs::rename(&temporary, path).map_err(|e| io_error(path, e))?;
+    sync_parent_dir(path)
+}
+
+/// Makes a rename into `path`'s directory durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    fs::File::open(parent)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| io_error(parent, e))
+}
+
+/// Directories can't be opened as files here, and renames don't need them synced.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn read_if_present(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(io_error(path, e)),
+    }
+}
+
+fn read_required(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| io_error(path, e))
+}
+
+/// Removes `path`, returning whether it existed.
+fn remove_if_present(path: &Path) -> Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(io_error(path, e)),
+    }
+}
+
+/// Lists the record files in `dir`, skipping any temporary files left by interrupted writes.
+fn list_records(dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| io_error(dir, e))? {
+        let entry = entry.map_err(|e| io_error(dir, e))?;
+        if let Ok(name) = entry.file_name().into_string() {
+            if !name.ends_with(TEMPORARY_SUFFIX) {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Names may contain any character, so they are hex encoded to make file names.
+fn encode_name(name: &str) -> String {
+    name.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_name(encoded: &str) -> Option<String> {
+    if encoded.len() % 2 != 0 {
+        return None;
+    }
+    let bytes = (0..encoded.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(encoded.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn address_file_name(address: &ProtocolAddress) -> String {
+    format!("{}.{}", encode_name(address.name()), address.device_id())
+}
+
+fn parse_address_file_name(file_name: &str) -> Option<ProtocolAddress> {
+    let (name, device_id) = file_name.split_at(file_name.rfind('.')?);
+    let device_id = DeviceId::try_from(device_id[1..].parse::<u32>().ok()?).ok()?;
+    Some(ProtocolAddress::new(decode_name(name)?, device_id))
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityKeyStore for FileSignalProtocolStore {
+    async fn get_identity_key_pair(&self, _ctx: Context) -> Result<IdentityKeyPair> {
+        Ok(self.key_pair.clone())
+    }
+
+    async fn get_local_registration_id(&self, _ctx: Context) -> Result<RegistrationId> {
+        Ok(self.registration_id)
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        ctx: Context,
+    ) -> Result<traits::IdentityChange> {
+        let change = match self.get_identity(address, ctx).await? {
+            None => traits::IdentityChange::NewIdentity,
+            Some(k) if k == *identity => return Ok(traits::IdentityChange::Unchanged),
+            Some(_k) => traits::IdentityChange::ReplacedExisting,
+        };
+        write_atomically(
+            &self.address_path(IDENTITIES_DIR, address),
+            &identity.serialize(),
+        )?;
+        Ok(change)
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        _direction: traits::Direction,
+        ctx: Context,
+    ) -> Result<bool> {
+        match self.get_identity(address, ctx).await? {
+            None => {
+                Ok(true) // first use
+            }
+            Some(k) => Ok(k == *identity),
+        }
+    }
+
+    async fn get_identity(
+        &self,
+        address: &ProtocolAddress,
+        _ctx: Context,
+    ) -> Result<Option<IdentityKey>> {
+        match read_if_present(&self.address_path(IDENTITIES_DIR, address))? {
+            None => Ok(None),
+            Some(k) => Ok(Some(IdentityKey::decode(&k)?)),
+        }
+    }
+
+    async fn all_identities(&self, ctx: Context) -> Result<Vec<(ProtocolAddress, IdentityKey)>> {
+        let mut identities = Vec::new();
+        for address in self.addresses(IDENTITIES_DIR)? {
+            // Skip any identity deleted since the directory was listed.
+            if let Some(identity) = self.get_identity(&address, ctx).await? {
+                identities.push((address, identity));
+            }
+        }
+        Ok(identities)
+    }
+
+    async fn delete_identity(&mut self, address: &ProtocolAddress, _ctx: Context) -> Result<bool> {
+        remove_if_present(&self.address_path(IDENTITIES_DIR, address))
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::PreKeyStore for FileSignalProtocolStore {
+    async fn get_pre_key(&self, id: PreKeyId, _ctx: Context) -> Result<PreKeyRecord> {
+        match read_if_present(&self.pre_key_path(PRE_KEYS_DIR, id))? {
+            None => Err(SignalProtocolError::InvalidPreKeyId),
+            Some(record) => PreKeyRecord::deserialize(&record),
+        }
+    }
+
+    async fn save_pre_key(
+        &mut self,
+        id: PreKeyId,
+        record: &PreKeyRecord,
+        _ctx: Context,
+    ) -> Result<()> {
+        write_atomically(&self.pre_key_path(PRE_KEYS_DIR, id), &record.serialize()?)
+    }
+
+    async fn remove_pre_key(&mut self, id: PreKeyId, _ctx: Context) -> Result<()> {
+        remove_if_present(&self.pre_key_path(PRE_KEYS_DIR, id))?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SignedPreKeyStore for FileSignalProtocolStore {
+    async fn get_signed_pre_key(
+        &self,
+        id: SignedPreKeyId,
+        _ctx: Context,
+    ) -> Result<SignedPreKeyRecord> {
+        match read_if_present(&self.pre_key_path(SIGNED_PRE_KEYS_DIR, id))? {
+            None => Err(SignalProtocolError::InvalidSignedPreKeyId),
+            Some(record) => SignedPreKeyRecord::deserialize(&record),
+        }
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+        _ctx: Context,
+    ) -> Result<()> {
+        write_atomically(
+            &self.pre_key_path(SIGNED_PRE_KEYS_DIR, id),
+            &record.serialize()?,
+        )
+    }
+
+    async fn all_signed_pre_key_ids(&self, _ctx: Context) -> Result<Vec<SignedPreKeyId>> {
+        Ok(list_records(&self.root.join(SIGNED_PRE_KEYS_DIR))?
+            .iter()
+            .filter_map(|name| name.parse().ok())
+            .collect())
+    }
+
+    async fn current_signed_pre_key_id(&self, _ctx: Context) -> Result<Option<SignedPreKeyId>> {
+        match read_if_present(&self.root.join(CURRENT_SIGNED_PRE_KEY_FILE))? {
+            None => Ok(None),
+            Some(id) => Ok(Some(
+                <[u8; 4]>::try_from(&id[..])
+                    .map(u32::from_be_bytes)
+                    .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?,
+            )),
+        }
+    }
+
+    async fn set_current_signed_pre_key_id(
+        &mut self,
+        id: SignedPreKeyId,
+        _ctx: Context,
+    ) -> Result<()> {
+        if !self.pre_key_path(SIGNED_PRE_KEYS_DIR, id).exists() {
+            return Err(SignalProtocolError::InvalidSignedPreKeyId);
+        }
+        write_atomically(
+            &self.root.join(CURRENT_SIGNED_PRE_KEY_FILE),
+            &id.to_be_bytes(),
+        )
+    }
+
+    async fn remove_signed_pre_key(&mut self, id: SignedPreKeyId, ctx: Context) -> Result<()> {
+        if self.current_signed_pre_key_id(ctx).await? == Some(id) {
+            remove_if_present(&self.root.join(CURRENT_SIGNED_PRE_KEY_FILE))?;
+        }
+        remove_if_present(&self.pre_key_path(SIGNED_PRE_KEYS_DIR, id))?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SessionStore for FileSignalProtocolStore {
+    async fn load_session(
+        &self,
+        address: &ProtocolAddress,
+        _ctx: Context,
+    ) -> Result<Option<SessionRecord>> {
+        match read_if_present(&self.address_path(SESSIONS_DIR, address))? {
+            None => Ok(None),
+            Some(record) => Ok(Some(SessionRecord::deserialize(&record)?)),
+        }
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+        _ctx: Context,
+    ) -> Result<()> {
+        write_atomically(
+            &self.address_path(SESSIONS_DIR, address),
+            &record.serialize()?,
+        )
+    }
+
+    async fn get_sub_device_sessions(&self, name: &str, _ctx: Context) -> Result<Vec<u32>> {
+        let mut device_ids: Vec<u32> = self
+            .addresses(SESSIONS_DIR)?
+            .iter()
+            .filter(|address| address.name() == name)
+            .map(|address| address.device_id().into())
+            .filter(|&device_id| device_id != 1)
+            .collect();
+        device_ids.sort_unstable();
+        Ok(device_ids)
+    }
+
+    async fn delete_session(&mut self, address: &ProtocolAddress, _ctx: Context) -> Result<()> {
+        remove_if_present(&self.address_path(SESSIONS_DIR, address))?;
+        Ok(())
+    }
+
+    async fn delete_all_sessions(&mut self, name: &str, _ctx: Context) -> Result<()> {
+        for address in self.addresses(SESSIONS_DIR)? {
+            if address.name() == name {
+                remove_if_present(&self.address_path(SESSIONS_DIR, &address))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SenderKeyStore for FileSignalProtocolStore {
+    async fn store_sender_key(
+        &mut self,
+        sender_key_name: &SenderKeyName,
+        record: &SenderKeyRecord,
+        _ctx: Context,
+    ) -> Result<()> {
+        write_atomically(
+            &self.sender_key_path(sender_key_name)?,
+            &record.serialize()?,
+        )
+    }
+
+    async fn load_sender_key(
+        &mut self,
+        sender_key_name: &SenderKeyName,
+        _ctx: Context,
+    ) -> Result<Option<SenderKeyRecord>> {
+        match read_if_present(&self.sender_key_path(sender_key_name)?)? {
+            None => Ok(None),
+            Some(record) => Ok(Some(SenderKeyRecord::deserialize(&record)?)),
+        }
+    }
+
+    async fn senders_for_group(
+        &self,
+        group_id: &str,
+        _ctx: Context,
+    ) -> Result<Vec<ProtocolAddress>> {
+        Ok(self
+            .group_sender_key_files(group_id)?
+            .into_iter()
+            .map(|(_, sender)| sender)
+            .collect())
+    }
+
+    async fn delete_sender_keys_for_group(&mut self, group_id: &str, _ctx: Context) -> Result<()> {
+        for (file_name, _) in self.group_sender_key_files(group_id)? {
+            remove_if_present(&self.root.join(SENDER_KEYS_DIR).join(file_name))?;
+        }
+        Ok(())
+    }
+}
+
+impl traits::ProtocolStore for FileSignalProtocolStore {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::traits::{IdentityKeyStore, PreKeyStore, SessionStore};
+    use crate::KeyPair;
+    use futures::executor::block_on;
+    use rand::rngs::OsRng;
+
+    /// A fresh directory under the system temporary directory, removed when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "libsignal-file-store-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn address(name: &str, device_id: u32) -> Result<ProtocolAddress> {
+        Ok(ProtocolAddress::new(
+            name.to_owned(),
+            DeviceId::try_from(device_id)?,
+        ))
+    }
+
+    #[test]
+    fn records_survive_reopening() -> Result<()> {
+        block_on(async {
+            let dir = TestDir::new("reopen");
+            let key_pair = IdentityKeyPair::generate(&mut OsRng);
+            let mut store = FileSignalProtocolStore::create(
+                &dir.0,
+                key_pair.clone(),
+                RegistrationId::try_from(1234)?,
+            )?;
+
+            // Names are encoded, so ones that aren't valid file names still work.
+            let bob = address("bob/../\u{1f600}.5", 3)?;
+            let bob_identity = *IdentityKeyPair::generate(&mut OsRng).identity_key();
+            let pre_key = PreKeyRecord::new(7, &KeyPair::generate(&mut OsRng));
+            store.save_identity(&bob, &bob_identity, None).await?;
+            store
+                .store_session(&bob, &SessionRecord::new_fresh(), None)
+                .await?;
+            store.save_pre_key(7, &pre_key, None).await?;
+
+            let store = FileSignalProtocolStore::open(&dir.0)?;
+            assert_eq!(
+                store.get_identity_key_pair(None).await?.serialize(),
+                key_pair.serialize()
+            );
+            assert_eq!(
+                u32::from(store.get_local_registration_id(None).await?),
+                1234
+            );
+            assert_eq!(
+                store.all_identities(None).await?,
+                vec![(bob.clone(), bob_identity)]
+            );
+            assert!(store.load_session(&bob, None).await?.is_some());
+            assert_eq!(
+                store.get_sub_device_sessions(bob.name(), None).await?,
+                vec![3]
+            );
+            assert_eq!(
+                store.get_pre_key(7, None).await?.serialize()?,
+                pre_key.serialize()?
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn interrupted_writes_leave_a_consistent_store() -> Result<()> {
+        block_on(async {
+            let dir = TestDir::new("interrupted");
+            let key_pair = IdentityKeyPair::generate(&mut OsRng);
+
+            // A create that stopped before writing VERSION leaves no store behind.
+            FileSignalProtocolStore::create(
+                &dir.0,
+                key_pair.clone(),
+                RegistrationId::try_from(1)?,
+            )?;
+            fs::remove_file(dir.0.join(VERSION_FILE)).expect("can remove VERSION");
+            assert!(FileSignalProtocolStore::open(&dir.0).is_err());
+            let mut store =
+                FileSignalProtocolStore::create(&dir.0, key_pair, RegistrationId::try_from(1)?)?;
+
+            let bob = address("+14151111112", 2)?;
+            let old_pre_key = PreKeyRecord::new(1, &KeyPair::generate(&mut OsRng));
+            store
+                .store_session(&bob, &SessionRecord::new_fresh(), None)
+                .await?;
+            store.save_pre_key(1, &old_pre_key, None).await?;
+
+            // Simulate processes killed partway through replacing each record, and through
+            // adding a new one: only truncated temporary files were written.
+            let new_pre_key = PreKeyRecord::new(1, &KeyPair::generate(&mut OsRng)).serialize()?;
+            let pre_key_path = store.pre_key_path(PRE_KEYS_DIR, 1);
+            fs::write(temporary_path(&pre_key_path), &new_pre_key[..10]).expect("can write");
+            let session_path = store.address_path(SESSIONS_DIR, &bob);
+            fs::write(temporary_path(&session_path), b"\x0a").expect("can write");
+            let carol = address("+14151111113", 3)?;
+            let carol_path = store.address_path(SESSIONS_DIR, &carol);
+            fs::write(temporary_path(&carol_path), b"\x0a").expect("can write");
+
+            let mut store = FileSignalProtocolStore::open(&dir.0)?;
+            assert_eq!(
+                store.get_pre_key(1, None).await?.serialize()?,
+                old_pre_key.serialize()?
+            );
+            assert!(store.load_session(&bob, None).await?.is_some());
+            assert!(store.load_session(&carol, None).await?.is_none());
+            assert_eq!(
+                store.get_sub_device_sessions("+14151111113", None).await?,
+                Vec::<u32>::new()
+            );
+
+            // Later writes replace the leftover temporary files.
+            store
+                .store_session(&carol, &SessionRecord::new_fresh(), None)
+                .await?;
+            assert!(store.load_session(&carol, None).await?.is_some());
+            assert!(!temporary_path(&carol_path).exists());
+            Ok(())
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_are_private_to_the_owner() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        block_on(async {
+            let dir = TestDir::new("permissions");
+            let mut store = FileSignalProtocolStore::create(
+                &dir.0,
+                IdentityKeyPair::generate(&mut OsRng),
+                RegistrationId::try_from(1)?,
+            )?;
+
+            // Even a leftover temporary file that anyone could read doesn't leak into the record.
+            let bob = address("+14151111112", 2)?;
+            let session_path = store.address_path(SESSIONS_DIR, &bob);
+            fs::write(temporary_path(&session_path), b"\x0a").expect("can write");
+            fs::set_permissions(
+                temporary_path(&session_path),
+                fs::Permissions::from_mode(0o644),
+            )
+            .expect("can set permissions");
+            store
+                .store_session(&bob, &SessionRecord::new_fresh(), None)
+                .await?;
+
+            let mode =
+                |path: &Path| fs::metadata(path).expect("exists").permissions().mode() & 0o777;
+            assert_eq!(mode(&dir.0), 0o700);
+            assert_eq!(mode(&dir.0.join(SESSIONS_DIR)), 0o700);
+            assert_eq!(mode(&dir.0.join(IDENTITY_KEY_PAIR_FILE)), 0o600);
+            assert_eq!(mode(&session_path), 0o600);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn open_checks_version() {
+        let dir = TestDir::new("version");
+        let key_pair = IdentityKeyPair::generate(&mut OsRng);
+        assert!(FileSignalProtocolStore::open(&dir.0).is_err());
+
+        let registration_id = RegistrationId::try_from(1).expect("valid");
+        FileSignalProtocolStore::create(&dir.0, key_pair.clone(), registration_id)
+            .expect("can create");
+        assert!(FileSignalProtocolStore::create(&dir.0, key_pair, registration_id).is_err());
+
+        fs::write(dir.0.join(VERSION_FILE), b"2").expect("can write");
+        assert_eq!(
+            FileSignalProtocolStore::open(&dir.0).err(),
+            Some(SignalProtocolError::UnrecognizedRecordVersion(2))
+        );
+    }
+
+    #[test]
+    fn sender_keys_for_group_enumeration_and_wipe() -> Result<()> {
+        use crate::storage::traits::SenderKeyStore;
+
+        block_on(async {
+            let dir = TestDir::new("sender-keys");
+            let key_pair = IdentityKeyPair::generate(&mut OsRng);
+            let mut store =
+                FileSignalProtocolStore::create(&dir.0, key_pair, RegistrationId::try_from(1234)?)?;
+
+            // The encoding of "group" is a prefix of the encoding of "group2".
+            let bob = address("bob", 3)?;
+            let record = SenderKeyRecord::new_empty();
+            for group_id in &["group", "group2"] {
+                let name = SenderKeyName::new(group_id.to_string(), bob.clone())?;
+                store.store_sender_key(&name, &record, None).await?;
+            }
+
+            assert_eq!(
+                store.senders_for_group("group", None).await?,
+                vec![bob.clone()]
+            );
+            store.delete_sender_keys_for_group("group", None).await?;
+            assert!(store.senders_for_group("group", None).await?.is_empty());
+            assert_eq!(
+                store.senders_for_group("group2", None).await?,
+                vec![bob.clone()]
+            );
+            Ok(())
+        })
+    }
+}
@@ -4,23 +4,34 @@
 //
 
 use crate::error::{Result, SignalProtocolError};
-use crate::state::{PreKeyId, PreKeyRecord, SessionRecord, SignedPreKeyId, SignedPreKeyRecord};
+use crate::proto::storage::in_mem_protocol_store_structure::{
+    Identity as InMemProtocolStoreIdentity, PreKey as InMemProtocolStorePreKey,
+    SenderKey as InMemProtocolStoreSenderKey, Session as InMemProtocolStoreSession,
+};
+use crate::proto::storage::InMemProtocolStoreStructure;
+use crate::state::{
+    PreKeyId, PreKeyRecord, RegistrationId, SessionRecord, SignedPreKeyId, SignedPreKeyRecord,
+};
 use crate::storage::traits;
-use crate::storage::Context;
-use crate::{IdentityKey, IdentityKeyPair, ProtocolAddress, SenderKeyName, SenderKeyRecord};
+use crate::storage::{Context, StoreOp};
+use crate::{
+    DeviceId, IdentityKey, IdentityKeyPair, ProtocolAddress, SenderKeyName, SenderKeyRecord,
+};
 
 use async_trait::async_trait;
+use prost::Message;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 #[derive(Clone)]
 pub struct InMemIdentityKeyStore {
     key_pair: IdentityKeyPair,
-    id: u32,
+    id: RegistrationId,
     known_keys: HashMap<ProtocolAddress, IdentityKey>,
 }
 
 impl InMemIdentityKeyStore {
-    pub fn new(key_pair: IdentityKeyPair, id: u32) -> Self {
+    pub fn new(key_pair: IdentityKeyPair, id: RegistrationId) -> Self {
         Self {
             key_pair,
             id,
@@ -32,10 +43,10 @@ impl InMemIdentityKeyStore {
 #[async_trait(?Send)]
 impl traits::IdentityKeyStore for InMemIdentityKeyStore {
     async fn get_identity_key_pair(&self, _ctx: Context) -> Result<IdentityKeyPair> {
-        Ok(self.key_pair)
+        Ok(self.key_pair.clone())
     }
 
-    async fn get_local_registration_id(&self, _ctx: Context) -> Result<u32> {
+    async fn get_local_registration_id(&self, _ctx: Context) -> Result<RegistrationId> {
         Ok(self.id)
     }
 
@@ -44,18 +55,16 @@ impl traits::IdentityKeyStore for InMemIdentityKeyStore {
         address: &ProtocolAddress,
         identity: &IdentityKey,
         _ctx: Context,
-    ) -> Result<bool> {
+    ) -> Result<traits::IdentityChange> {
         match self.known_keys.get(address) {
             None => {
                 self.known_keys.insert(address.clone(), *identity);
-                Ok(false) // new key
-            }
-            Some(k) if k == identity => {
-                Ok(false) // same key
+                Ok(traits::IdentityChange::NewIdentity)
             }
+            Some(k) if k == identity => Ok(traits::IdentityChange::Unchanged),
             Some(_k) => {
                 self.known_keys.insert(address.clone(), *identity);
-                Ok(true) // overwrite
+                Ok(traits::IdentityChange::ReplacedExisting)
             }
         }
     }
@@ -85,6 +94,18 @@ impl traits::IdentityKeyStore for InMemIdentityKeyStore {
             Some(k) => Ok(Some(k.to_owned())),
         }
     }
+
+    async fn all_identities(&self, _ctx: Context) -> Result<Vec<(ProtocolAddress, IdentityKey)>> {
+        Ok(self
+            .known_keys
+            .iter()
+            .map(|(address, identity)| (address.clone(), *identity))
+            .collect())
+    }
+
+    async fn delete_identity(&mut self, address: &ProtocolAddress, _ctx: Context) -> Result<bool> {
+        Ok(self.known_keys.remove(address).is_some())
+    }
 }
 
 #[derive(Clone)]
@@ -132,17 +153,35 @@ impl traits::PreKeyStore for InMemPreKeyStore {
         self.pre_keys.remove(&id);
         Ok(())
     }
+
+    async fn save_pre_keys(
+        &mut self,
+        records: &[(PreKeyId, PreKeyRecord)],
+        _ctx: Context,
+    ) -> Result<()> {
+        self.pre_keys.extend(records.iter().cloned());
+        Ok(())
+    }
+
+    async fn remove_pre_keys(&mut self, ids: &[PreKeyId], _ctx: Context) -> Result<()> {
+        for id in ids {
+            self.pre_keys.remove(id);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
 pub struct InMemSignedPreKeyStore {
     signed_pre_keys: HashMap<SignedPreKeyId, SignedPreKeyRecord>,
+    current: Option<SignedPreKeyId>,
 }
 
 impl InMemSignedPreKeyStore {
     pub fn new() -> Self {
         Self {
             signed_pre_keys: HashMap::new(),
+            current: None,
         }
     }
 }
@@ -177,6 +216,34 @@ impl traits::SignedPreKeyStore for InMemSignedPreKeyStore {
         self.signed_pre_keys.insert(id, record.to_owned());
         Ok(())
     }
+
+    async fn all_signed_pre_key_ids(&self, _ctx: Context) -> Result<Vec<SignedPreKeyId>> {
+        Ok(self.signed_pre_keys.keys().copied().collect())
+    }
+
+    async fn current_signed_pre_key_id(&self, _ctx: Context) -> Result<Option<SignedPreKeyId>> {
+        Ok(self.current)
+    }
+
+    async fn set_current_signed_pre_key_id(
+        &mut self,
+        id: SignedPreKeyId,
+        _ctx: Context,
+    ) -> Result<()> {
+        if !self.signed_pre_keys.contains_key(&id) {
+            return Err(SignalProtocolError::InvalidSignedPreKeyId);
+        }
+        self.current = Some(id);
+        Ok(())
+    }
+
+    async fn remove_signed_pre_key(&mut self, id: SignedPreKeyId, _ctx: Context) -> Result<()> {
+        self.signed_pre_keys.remove(&id);
+        if self.current == Some(id) {
+            self.current = None;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -220,6 +287,44 @@ impl traits::SessionStore for InMemSessionStore {
         self.sessions.insert(address.clone(), record.clone());
         Ok(())
     }
+
+    async fn load_existing_sessions(
+        &self,
+        addresses: &[&ProtocolAddress],
+        _ctx: Context,
+    ) -> Result<Vec<SessionRecord>> {
+        addresses
+            .iter()
+            .map(|&address| {
+                self.sessions
+                    .get(address)
+                    .cloned()
+                    .ok_or_else(|| SignalProtocolError::SessionNotFoundForAddress(address.clone()))
+            })
+            .collect()
+    }
+
+    async fn get_sub_device_sessions(&self, name: &str, _ctx: Context) -> Result<Vec<u32>> {
+        let mut device_ids: Vec<u32> = self
+            .sessions
+            .keys()
+            .filter(|address| address.name() == name)
+            .map(|address| address.device_id().into())
+            .filter(|&device_id| device_id != 1)
+            .collect();
+        device_ids.sort_unstable();
+        Ok(device_ids)
+    }
+
+    async fn delete_session(&mut self, address: &ProtocolAddress, _ctx: Context) -> Result<()> {
+        self.sessions.remove(address);
+        Ok(())
+    }
+
+    async fn delete_all_sessions(&mut self, name: &str, _ctx: Context) -> Result<()> {
+        self.sessions.retain(|address, _| address.name() != name);
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -260,6 +365,26 @@ impl traits::SenderKeyStore for InMemSenderKeyStore {
     ) -> Result<Option<SenderKeyRecord>> {
         Ok(self.keys.get(&sender_key_name).cloned())
     }
+
+    async fn senders_for_group(
+        &self,
+        group_id: &str,
+        _ctx: Context,
+    ) -> Result<Vec<ProtocolAddress>> {
+        let mut senders = Vec::new();
+        for name in self.keys.keys() {
+            if name.group_id()? == group_id {
+                senders.push(name.sender()?);
+            }
+        }
+        Ok(senders)
+    }
+
+    async fn delete_sender_keys_for_group(&mut self, group_id: &str, _ctx: Context) -> Result<()> {
+        self.keys
+            .retain(|name, _| name.group_id().map_or(true, |id| id != group_id));
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -272,7 +397,7 @@ pub struct InMemSignalProtocolStore {
 }
 
 impl InMemSignalProtocolStore {
-    pub fn new(key_pair: IdentityKeyPair, registration_id: u32) -> Result<Self> {
+    pub fn new(key_pair: IdentityKeyPair, registration_id: RegistrationId) -> Result<Self> {
         Ok(Self {
             session_store: InMemSessionStore::new(),
             pre_key_store: InMemPreKeyStore::new(),
@@ -281,6 +406,149 @@ impl InMemSignalProtocolStore {
             sender_key_store: InMemSenderKeyStore::new(),
         })
     }
+
+    /// The version written by [`serialize`](Self::serialize).
+    pub const SERIALIZED_VERSION: u32 = 1;
+
+    /// Writes out everything in the store, so that it can be restored with
+    /// [`deserialize`](Self::deserialize).
+    ///
+    /// Records are written in a fixed order, so two stores with the same contents serialize to
+    /// the same bytes.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut sessions = Vec::with_capacity(self.session_store.sessions.len());
+        for (address, record) in &self.session_store.sessions {
+            sessions.push(InMemProtocolStoreSession {
+                name: address.name().to_owned(),
+                device_id: address.device_id().into(),
+                record: record.serialize()?,
+            });
+        }
+        sessions.sort_by(|a, b| (&a.name, a.device_id).cmp(&(&b.name, b.device_id)));
+
+        let mut identities: Vec<_> = self
+            .identity_store
+            .known_keys
+            .iter()
+            .map(|(address, key)| InMemProtocolStoreIdentity {
+                name: address.name().to_owned(),
+                device_id: address.device_id().into(),
+                identity_key: key.serialize().into_vec(),
+            })
+            .collect();
+        identities.sort_by(|a, b| (&a.name, a.device_id).cmp(&(&b.name, b.device_id)));
+
+        let mut pre_keys = Vec::with_capacity(self.pre_key_store.pre_keys.len());
+        for (id, record) in &self.pre_key_store.pre_keys {
+            pre_keys.push(InMemProtocolStorePreKey {
+                id: *id,
+                record: record.serialize()?,
+            });
+        }
+        pre_keys.sort_by_key(|k| k.id);
+
+        let mut signed_pre_keys =
+            Vec::with_capacity(self.signed_pre_key_store.signed_pre_keys.len());
+        for (id, record) in &self.signed_pre_key_store.signed_pre_keys {
+            signed_pre_keys.push(InMemProtocolStorePreKey {
+                id: *id,
+                record: record.serialize()?,
+            });
+        }
+        signed_pre_keys.sort_by_key(|k| k.id);
+
+        let mut sender_keys = Vec::with_capacity(self.sender_key_store.keys.len());
+        for (name, record) in &self.sender_key_store.keys {
+            sender_keys.push(InMemProtocolStoreSenderKey {
+                group_id: name.group_id()?,
+                sender_name: name.sender_name()?,
+                sender_device_id: name.sender_device_id()?,
+                record: record.serialize()?,
+            });
+        }
+        sender_keys.sort_by(|a, b| {
+            (&a.group_id, &a.sender_name, a.sender_device_id).cmp(&(
+                &b.group_id,
+                &b.sender_name,
+                b.sender_device_id,
+            ))
+        });
+
+        let structure = InMemProtocolStoreStructure {
+            version: Self::SERIALIZED_VERSION,
+            identity_key_pair: self.identity_store.key_pair.serialize()?.into_vec(),
+            registration_id: self.identity_store.id.into(),
+            sessions,
+            identities,
+            pre_keys,
+            signed_pre_keys,
+            has_current_signed_pre_key: self.signed_pre_key_store.current.is_some(),
+            current_signed_pre_key_id: self.signed_pre_key_store.current.unwrap_or(0),
+            sender_keys,
+        };
+
+        let mut buf = Vec::with_capacity(structure.encoded_len());
+        structure.encode(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Restores a store written by [`serialize`](Self::serialize).
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        let structure = InMemProtocolStoreStructure::decode(bytes)?;
+        if structure.version != Self::SERIALIZED_VERSION {
+            return Err(SignalProtocolError::UnrecognizedRecordVersion(
+                structure.version,
+            ));
+        }
+
+        let key_pair = IdentityKeyPair::try_from(&structure.identity_key_pair[..])?;
+        let registration_id = RegistrationId::try_from(structure.registration_id)?;
+        let mut store = Self::new(key_pair, registration_id)?;
+
+        for session in structure.sessions {
+            let address =
+                ProtocolAddress::new(session.name, DeviceId::try_from(session.device_id)?);
+            store
+                .session_store
+                .sessions
+                .insert(address, SessionRecord::deserialize(&session.record)?);
+        }
+        for identity in structure.identities {
+            let address =
+                ProtocolAddress::new(identity.name, DeviceId::try_from(identity.device_id)?);
+            store
+                .identity_store
+                .known_keys
+                .insert(address, IdentityKey::decode(&identity.identity_key)?);
+        }
+        for pre_key in structure.pre_keys {
+            store
+                .pre_key_store
+                .pre_keys
+                .insert(pre_key.id, PreKeyRecord::deserialize(&pre_key.record)?);
+        }
+        for signed_pre_key in structure.signed_pre_keys {
+            store.signed_pre_key_store.signed_pre_keys.insert(
+                signed_pre_key.id,
+                SignedPreKeyRecord::deserialize(&signed_pre_key.record)?,
+            );
+        }
+        if structure.has_current_signed_pre_key {
+            store.signed_pre_key_store.current = Some(structure.current_signed_pre_key_id);
+        }
+        for sender_key in structure.sender_keys {
+            let sender = ProtocolAddress::new(
+                sender_key.sender_name,
+                DeviceId::try_from(sender_key.sender_device_id)?,
+            );
+            store.sender_key_store.keys.insert(
+                SenderKeyName::new(sender_key.group_id, sender)?,
+                SenderKeyRecord::deserialize(&sender_key.record)?,
+            );
+        }
+
+        Ok(store)
+    }
 }
 
 #[async_trait(?Send)]
@@ -289,7 +557,7 @@ impl traits::IdentityKeyStore for InMemSignalProtocolStore {
         self.identity_store.get_identity_key_pair(ctx).await
     }
 
-    async fn get_local_registration_id(&self, ctx: Context) -> Result<u32> {
+    async fn get_local_registration_id(&self, ctx: Context) -> Result<RegistrationId> {
         self.identity_store.get_local_registration_id(ctx).await
     }
 
@@ -298,7 +566,7 @@ impl traits::IdentityKeyStore for InMemSignalProtocolStore {
         address: &ProtocolAddress,
         identity: &IdentityKey,
         ctx: Context,
-    ) -> Result<bool> {
+    ) -> Result<traits::IdentityChange> {
         self.identity_store
             .save_identity(address, identity, ctx)
             .await
@@ -323,6 +591,14 @@ impl traits::IdentityKeyStore for InMemSignalProtocolStore {
     ) -> Result<Option<IdentityKey>> {
         self.identity_store.get_identity(address, ctx).await
     }
+
+    async fn all_identities(&self, ctx: Context) -> Result<Vec<(ProtocolAddress, IdentityKey)>> {
+        self.identity_store.all_identities(ctx).await
+    }
+
+    async fn delete_identity(&mut self, address: &ProtocolAddress, ctx: Context) -> Result<bool> {
+        self.identity_store.delete_identity(address, ctx).await
+    }
 }
 
 #[async_trait(?Send)]
@@ -343,6 +619,18 @@ impl traits::PreKeyStore for InMemSignalProtocolStore {
     async fn remove_pre_key(&mut self, id: PreKeyId, ctx: Context) -> Result<()> {
         self.pre_key_store.remove_pre_key(id, ctx).await
     }
+
+    async fn save_pre_keys(
+        &mut self,
+        records: &[(PreKeyId, PreKeyRecord)],
+        ctx: Context,
+    ) -> Result<()> {
+        self.pre_key_store.save_pre_keys(records, ctx).await
+    }
+
+    async fn remove_pre_keys(&mut self, ids: &[PreKeyId], ctx: Context) -> Result<()> {
+        self.pre_key_store.remove_pre_keys(ids, ctx).await
+    }
 }
 
 #[async_trait(?Send)]
@@ -365,6 +653,32 @@ impl traits::SignedPreKeyStore for InMemSignalProtocolStore {
             .save_signed_pre_key(id, record, ctx)
             .await
     }
+
+    async fn all_signed_pre_key_ids(&self, ctx: Context) -> Result<Vec<SignedPreKeyId>> {
+        self.signed_pre_key_store.all_signed_pre_key_ids(ctx).await
+    }
+
+    async fn current_signed_pre_key_id(&self, ctx: Context) -> Result<Option<SignedPreKeyId>> {
+        self.signed_pre_key_store
+            .current_signed_pre_key_id(ctx)
+            .await
+    }
+
+    async fn set_current_signed_pre_key_id(
+        &mut self,
+        id: SignedPreKeyId,
+        ctx: Context,
+    ) -> Result<()> {
+        self.signed_pre_key_store
+            .set_current_signed_pre_key_id(id, ctx)
+            .await
+    }
+
+    async fn remove_signed_pre_key(&mut self, id: SignedPreKeyId, ctx: Context) -> Result<()> {
+        self.signed_pre_key_store
+            .remove_signed_pre_key(id, ctx)
+            .await
+    }
 }
 
 #[async_trait(?Send)]
@@ -385,6 +699,28 @@ impl traits::SessionStore for InMemSignalProtocolStore {
     ) -> Result<()> {
         self.session_store.store_session(address, record, ctx).await
     }
+
+    async fn load_existing_sessions(
+        &self,
+        addresses: &[&ProtocolAddress],
+        ctx: Context,
+    ) -> Result<Vec<SessionRecord>> {
+        self.session_store
+            .load_existing_sessions(addresses, ctx)
+            .await
+    }
+
+    async fn get_sub_device_sessions(&self, name: &str, ctx: Context) -> Result<Vec<u32>> {
+        self.session_store.get_sub_device_sessions(name, ctx).await
+    }
+
+    async fn delete_session(&mut self, address: &ProtocolAddress, ctx: Context) -> Result<()> {
+        self.session_store.delete_session(address, ctx).await
+    }
+
+    async fn delete_all_sessions(&mut self, name: &str, ctx: Context) -> Result<()> {
+        self.session_store.delete_all_sessions(name, ctx).await
+    }
 }
 
 #[async_trait(?Send)]
@@ -409,6 +745,516 @@ impl traits::SenderKeyStore for InMemSignalProtocolStore {
             .load_sender_key(sender_key_name, ctx)
             .await
     }
+
+    async fn senders_for_group(
+        &self,
+        group_id: &str,
+        ctx: Context,
+    ) -> Result<Vec<ProtocolAddress>> {
+        self.sender_key_store.senders_for_group(group_id, ctx).await
+    }
+
+    async fn delete_sender_keys_for_group(&mut self, group_id: &str, ctx: Context) -> Result<()> {
+        self.sender_key_store
+            .delete_sender_keys_for_group(group_id, ctx)
+            .await
+    }
 }
 
 impl traits::ProtocolStore for InMemSignalProtocolStore {}
+
+#[async_trait(?Send)]
+impl traits::TransactionalStore for InMemSignalProtocolStore {
+    async fn commit(&mut self, ops: Vec<StoreOp>, ctx: Context) -> Result<()> {
+        // Stage the writes on a copy so that a failure leaves this store untouched.
+        let mut staged = self.clone();
+        for op in &ops {
+            op.apply(
+                &mut staged.session_store,
+                &mut staged.identity_store,
+                &mut staged.pre_key_store,
+                ctx,
+            )
+            .await?;
+        }
+        *self = staged;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyPair;
+    use futures::executor::block_on;
+    use rand::rngs::OsRng;
+
+    /// Implements only the required methods, so the batch operations use the trait defaults.
+    struct SingularPreKeyStore(InMemPreKeyStore);
+
+    #[async_trait(?Send)]
+    impl traits::PreKeyStore for SingularPreKeyStore {
+        async fn get_pre_key(&self, id: PreKeyId, ctx: Context) -> Result<PreKeyRecord> {
+            self.0.get_pre_key(id, ctx).await
+        }
+
+        async fn save_pre_key(
+            &mut self,
+            id: PreKeyId,
+            record: &PreKeyRecord,
+            ctx: Context,
+        ) -> Result<()> {
+            self.0.save_pre_key(id, record, ctx).await
+        }
+
+        async fn remove_pre_key(&mut self, id: PreKeyId, ctx: Context) -> Result<()> {
+            self.0.remove_pre_key(id, ctx).await
+        }
+    }
+
+    async fn exercise_batch_operations(store: &mut dyn traits::PreKeyStore) -> Result<()> {
+        let records: Vec<(PreKeyId, PreKeyRecord)> = (1..=100)
+            .map(|id| (id, PreKeyRecord::new(id, &KeyPair::generate(&mut OsRng))))
+            .collect();
+        store.save_pre_keys(&records, None).await?;
+        store.remove_pre_keys(&[3, 50, 1000], None).await?;
+
+        for (id, record) in &records {
+            let stored = store.get_pre_key(*id, None).await;
+            if *id == 3 || *id == 50 {
+                assert!(matches!(stored, Err(SignalProtocolError::InvalidPreKeyId)));
+            } else {
+                assert_eq!(stored?.serialize()?, record.serialize()?);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn batch_pre_key_operations() -> Result<()> {
+        block_on(async {
+            let mut singular = SingularPreKeyStore(InMemPreKeyStore::new());
+            exercise_batch_operations(&mut singular).await?;
+
+            let mut batched = InMemPreKeyStore::new();
+            exercise_batch_operations(&mut batched).await?;
+
+            let mut singular_ids: Vec<_> = singular.0.pre_keys.keys().copied().collect();
+            let mut batched_ids: Vec<_> = batched.pre_keys.keys().copied().collect();
+            singular_ids.sort_unstable();
+            batched_ids.sort_unstable();
+            assert_eq!(singular_ids, batched_ids);
+            assert_eq!(batched_ids.len(), 98);
+            Ok(())
+        })
+    }
+
+    /// Implements only the required methods, so batch loading, enumeration and deletion use the
+    /// trait defaults.
+    struct SingularSessionStore(InMemSessionStore);
+
+    #[async_trait(?Send)]
+    impl traits::SessionStore for SingularSessionStore {
+        async fn load_session(
+            &self,
+            address: &ProtocolAddress,
+            ctx: Context,
+        ) -> Result<Option<SessionRecord>> {
+            self.0.load_session(address, ctx).await
+        }
+
+        async fn store_session(
+            &mut self,
+            address: &ProtocolAddress,
+            record: &SessionRecord,
+            ctx: Context,
+        ) -> Result<()> {
+            self.0.store_session(address, record, ctx).await
+        }
+    }
+
+    async fn exercise_load_existing_sessions(store: &mut dyn traits::SessionStore) -> Result<()> {
+        use crate::DeviceId;
+        use std::convert::TryFrom;
+
+        let addresses: Vec<ProtocolAddress> = (1..=4)
+            .map(|id| {
+                Ok(ProtocolAddress::new(
+                    "+14151111111".to_owned(),
+                    DeviceId::try_from(id)?,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        store
+            .store_session(&addresses[0], &SessionRecord::new_fresh(), None)
+            .await?;
+        store
+            .store_session(&addresses[2], &SessionRecord::new_fresh(), None)
+            .await?;
+
+        let sessions = store
+            .load_existing_sessions(&[&addresses[2], &addresses[0]], None)
+            .await?;
+        assert_eq!(sessions.len(), 2);
+
+        let all: Vec<&ProtocolAddress> = addresses.iter().collect();
+        assert_eq!(
+            store.load_existing_sessions(&all, None).await.unwrap_err(),
+            SignalProtocolError::SessionNotFoundForAddress(addresses[1].clone())
+        );
+        assert!(store.load_existing_sessions(&[], None).await?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn load_existing_sessions() -> Result<()> {
+        block_on(async {
+            exercise_load_existing_sessions(&mut SingularSessionStore(InMemSessionStore::new()))
+                .await?;
+            exercise_load_existing_sessions(&mut InMemSessionStore::new()).await
+        })
+    }
+
+    #[test]
+    fn session_enumeration_and_deletion() -> Result<()> {
+        use crate::DeviceId;
+        use std::convert::TryFrom;
+        use traits::SessionStore;
+
+        block_on(async {
+            let mut store = InMemSessionStore::new();
+            let address = |name: &str, device_id: u32| -> Result<ProtocolAddress> {
+                Ok(ProtocolAddress::new(
+                    name.to_owned(),
+                    DeviceId::try_from(device_id)?,
+                ))
+            };
+            for &(name, device_id) in &[
+                ("+14151111111", 1),
+                ("+14151111111", 5),
+                ("+14151111111", 2),
+                ("+14152222222", 3),
+            ] {
+                store
+                    .store_session(
+                        &address(name, device_id)?,
+                        &SessionRecord::new_fresh(),
+                        None,
+                    )
+                    .await?;
+            }
+
+            assert_eq!(
+                store.get_sub_device_sessions("+14151111111", None).await?,
+                vec![2, 5]
+            );
+            assert!(store
+                .get_sub_device_sessions("+14153333333", None)
+                .await?
+                .is_empty());
+
+            store
+                .delete_session(&address("+14151111111", 5)?, None)
+                .await?;
+            store
+                .delete_session(&address("+14151111111", 5)?, None)
+                .await?;
+            assert_eq!(
+                store.get_sub_device_sessions("+14151111111", None).await?,
+                vec![2]
+            );
+
+            store.delete_all_sessions("+14151111111", None).await?;
+            store.delete_all_sessions("+14153333333", None).await?;
+            assert!(store
+                .load_session(&address("+14151111111", 1)?, None)
+                .await?
+                .is_none());
+            assert!(store
+                .get_sub_device_sessions("+14151111111", None)
+                .await?
+                .is_empty());
+            assert_eq!(
+                store.get_sub_device_sessions("+14152222222", None).await?,
+                vec![3]
+            );
+
+            let mut store = SingularSessionStore(store);
+            assert_eq!(
+                store
+                    .get_sub_device_sessions("+14152222222", None)
+                    .await
+                    .unwrap_err(),
+                SignalProtocolError::OperationNotSupported("get_sub_device_sessions")
+            );
+            assert_eq!(
+                store
+                    .delete_session(&address("+14152222222", 3)?, None)
+                    .await
+                    .unwrap_err(),
+                SignalProtocolError::OperationNotSupported("delete_session")
+            );
+            assert_eq!(
+                store
+                    .delete_all_sessions("+14152222222", None)
+                    .await
+                    .unwrap_err(),
+                SignalProtocolError::OperationNotSupported("delete_all_sessions")
+            );
+            assert!(store
+                .load_session(&address("+14152222222", 3)?, None)
+                .await?
+                .is_some());
+            Ok(())
+        })
+    }
+
+    /// Forwards only the required methods, so enumeration and deletion use the trait defaults.
+    struct UnenumerableIdentityKeyStore(InMemIdentityKeyStore);
+
+    #[async_trait(?Send)]
+    impl traits::IdentityKeyStore for UnenumerableIdentityKeyStore {
+        async fn get_identity_key_pair(&self, ctx: Context) -> Result<IdentityKeyPair> {
+            self.0.get_identity_key_pair(ctx).await
+        }
+
+        async fn get_local_registration_id(&self, ctx: Context) -> Result<RegistrationId> {
+            self.0.get_local_registration_id(ctx).await
+        }
+
+        async fn save_identity(
+            &mut self,
+            address: &ProtocolAddress,
+            identity: &IdentityKey,
+            ctx: Context,
+        ) -> Result<traits::IdentityChange> {
+            self.0.save_identity(address, identity, ctx).await
+        }
+
+        async fn is_trusted_identity(
+            &self,
+            address: &ProtocolAddress,
+            identity: &IdentityKey,
+            direction: traits::Direction,
+            ctx: Context,
+        ) -> Result<bool> {
+            self.0
+                .is_trusted_identity(address, identity, direction, ctx)
+                .await
+        }
+
+        async fn get_identity(
+            &self,
+            address: &ProtocolAddress,
+            ctx: Context,
+        ) -> Result<Option<IdentityKey>> {
+            self.0.get_identity(address, ctx).await
+        }
+    }
+
+    #[test]
+    fn identity_enumeration_and_deletion() -> Result<()> {
+        use crate::DeviceId;
+        use std::convert::TryFrom;
+        use traits::IdentityKeyStore;
+
+        block_on(async {
+            let mut store = InMemIdentityKeyStore::new(
+                IdentityKeyPair::generate(&mut OsRng),
+                RegistrationId::try_from(5)?,
+            );
+            let alice = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+            let bob = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
+            let alice_identity = *IdentityKeyPair::generate(&mut OsRng).identity_key();
+            let bob_identity = *IdentityKeyPair::generate(&mut OsRng).identity_key();
+            store.save_identity(&alice, &alice_identity, None).await?;
+            store.save_identity(&bob, &bob_identity, None).await?;
+
+            let mut identities = store.all_identities(None).await?;
+            identities.sort_by(|(a, _), (b, _)| a.name().cmp(b.name()));
+            assert_eq!(
+                identities,
+                vec![(alice.clone(), alice_identity), (bob.clone(), bob_identity)]
+            );
+
+            assert!(store.delete_identity(&alice, None).await?);
+            assert!(!store.delete_identity(&alice, None).await?);
+            assert_eq!(store.get_identity(&alice, None).await?, None);
+            assert_eq!(
+                store.all_identities(None).await?,
+                vec![(bob.clone(), bob_identity)]
+            );
+
+            let mut store = UnenumerableIdentityKeyStore(store);
+            assert_eq!(
+                store.all_identities(None).await.unwrap_err(),
+                SignalProtocolError::OperationNotSupported("all_identities")
+            );
+            assert_eq!(
+                store.delete_identity(&bob, None).await.unwrap_err(),
+                SignalProtocolError::OperationNotSupported("delete_identity")
+            );
+            assert_eq!(store.get_identity(&bob, None).await?, Some(bob_identity));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn save_identity_reports_change() -> Result<()> {
+        use crate::DeviceId;
+        use std::convert::TryFrom;
+        use traits::{IdentityChange, IdentityKeyStore};
+
+        block_on(async {
+            let mut store = InMemIdentityKeyStore::new(
+                IdentityKeyPair::generate(&mut OsRng),
+                RegistrationId::try_from(5)?,
+            );
+            let address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+            let identity = *IdentityKeyPair::generate(&mut OsRng).identity_key();
+            let new_identity = *IdentityKeyPair::generate(&mut OsRng).identity_key();
+
+            assert_eq!(
+                store.save_identity(&address, &identity, None).await?,
+                IdentityChange::NewIdentity
+            );
+            assert_eq!(
+                store.save_identity(&address, &identity, None).await?,
+                IdentityChange::Unchanged
+            );
+            assert_eq!(
+                store.save_identity(&address, &new_identity, None).await?,
+                IdentityChange::ReplacedExisting
+            );
+            assert_eq!(
+                store.get_identity(&address, None).await?,
+                Some(new_identity)
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn sender_keys_for_group_enumeration_and_wipe() -> Result<()> {
+        use crate::DeviceId;
+        use std::convert::TryFrom;
+        use traits::SenderKeyStore;
+
+        block_on(async {
+            let mut store = InMemSenderKeyStore::new();
+            let alice = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+            let bob = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(2)?);
+            let record = SenderKeyRecord::new_empty();
+            for group_id in &["group", "group2"] {
+                for sender in &[&alice, &bob] {
+                    let name = SenderKeyName::new(group_id.to_string(), (*sender).clone())?;
+                    store.store_sender_key(&name, &record, None).await?;
+                }
+            }
+
+            let mut senders = store.senders_for_group("group", None).await?;
+            senders.sort();
+            assert_eq!(senders, vec![alice.clone(), bob.clone()]);
+            assert!(store.senders_for_group("nobody", None).await?.is_empty());
+
+            store.delete_sender_keys_for_group("group", None).await?;
+            assert!(store.senders_for_group("group", None).await?.is_empty());
+            let group_name = SenderKeyName::new("group".to_owned(), alice.clone())?;
+            assert!(store.load_sender_key(&group_name, None).await?.is_none());
+
+            let mut senders = store.senders_for_group("group2", None).await?;
+            senders.sort();
+            assert_eq!(senders, vec![alice.clone(), bob.clone()]);
+            let other_name = SenderKeyName::new("group2".to_owned(), alice)?;
+            assert!(store.load_sender_key(&other_name, None).await?.is_some());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn serialization_round_trip() -> Result<()> {
+        use traits::{
+            IdentityKeyStore, PreKeyStore, SenderKeyStore, SessionStore, SignedPreKeyStore,
+        };
+
+        block_on(async {
+            let key_pair = IdentityKeyPair::generate(&mut OsRng);
+            let mut store =
+                InMemSignalProtocolStore::new(key_pair.clone(), RegistrationId::try_from(1234)?)?;
+            let bob = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(2)?);
+            let bob_identity = *IdentityKeyPair::generate(&mut OsRng).identity_key();
+            let pre_key = PreKeyRecord::new(7, &KeyPair::generate(&mut OsRng));
+            let signed_pre_key =
+                SignedPreKeyRecord::new(0, 42, &KeyPair::generate(&mut OsRng), &[1u8; 64]);
+            let sender_key_name = SenderKeyName::new("group".to_owned(), bob.clone())?;
+
+            store.save_identity(&bob, &bob_identity, None).await?;
+            store
+                .store_session(&bob, &SessionRecord::new_fresh(), None)
+                .await?;
+            store.save_pre_key(7, &pre_key, None).await?;
+            store.save_signed_pre_key(0, &signed_pre_key, None).await?;
+            store.set_current_signed_pre_key_id(0, None).await?;
+            store
+                .store_sender_key(&sender_key_name, &SenderKeyRecord::new_empty(), None)
+                .await?;
+
+            let serialized = store.serialize()?;
+            let mut restored = InMemSignalProtocolStore::deserialize(&serialized)?;
+            assert_eq!(restored.serialize()?, serialized);
+
+            assert_eq!(
+                restored.get_identity_key_pair(None).await?.serialize()?,
+                key_pair.serialize()?
+            );
+            assert_eq!(
+                u32::from(restored.get_local_registration_id(None).await?),
+                1234
+            );
+            assert_eq!(restored.get_identity(&bob, None).await?, Some(bob_identity));
+            assert!(restored.load_session(&bob, None).await?.is_some());
+            assert_eq!(
+                restored.get_pre_key(7, None).await?.serialize()?,
+                pre_key.serialize()?
+            );
+            assert_eq!(restored.current_signed_pre_key_id(None).await?, Some(0));
+            assert!(restored
+                .load_sender_key(&sender_key_name, None)
+                .await?
+                .is_some());
+
+            // Changes to the restored copy do not affect the original.
+            restored.remove_pre_key(7, None).await?;
+            assert!(store.get_pre_key(7, None).await.is_ok());
+
+            let empty = InMemSignalProtocolStore::new(key_pair, RegistrationId::try_from(1234)?)?
+                .serialize()?;
+            assert_eq!(
+                InMemSignalProtocolStore::deserialize(&empty)?
+                    .current_signed_pre_key_id(None)
+                    .await?,
+                None
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn serialization_checks_version() -> Result<()> {
+        let key_pair = IdentityKeyPair::generate(&mut OsRng);
+        let mut structure = InMemProtocolStoreStructure::decode(
+            &InMemSignalProtocolStore::new(key_pair, RegistrationId::try_from(1234)?)?
+                .serialize()?[..],
+        )?;
+        structure.version = InMemSignalProtocolStore::SERIALIZED_VERSION + 1;
+        let mut buf = Vec::new();
+        structure.encode(&mut buf)?;
+
+        assert!(matches!(
+            InMemSignalProtocolStore::deserialize(&buf),
+            Err(SignalProtocolError::UnrecognizedRecordVersion(v))
+                if v == InMemSignalProtocolStore::SERIALIZED_VERSION + 1
+        ));
+        Ok(())
+    }
+}
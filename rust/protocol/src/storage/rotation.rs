@@ -0,0 +1,82 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use crate::error::{Result, SignalProtocolError};
+use crate::state::SignedPreKeyId;
+use crate::storage::{Context, SignedPreKeyStore};
+
+/// Removes every signed pre-key that is more than `max_age_ms` older than `now_ms`, according to
+/// the timestamp in its record, and returns the IDs that were removed.
+///
+/// The current signed pre-key is never removed, however old it is, since peers may still be
+/// fetching it; stores that don't track a current key have every expired key removed. Requires a
+/// store that supports [SignedPreKeyStore::all_signed_pre_key_ids] and
+/// [SignedPreKeyStore::remove_signed_pre_key].
+pub async fn remove_expired_signed_pre_keys(
+    store: &mut dyn SignedPreKeyStore,
+    now_ms: u64,
+    max_age_ms: u64,
+    ctx: Context,
+) -> Result<Vec<SignedPreKeyId>> {
+    let current = match store.current_signed_pre_key_id(ctx).await {
+        Err(SignalProtocolError::OperationNotSupported(_)) => None,
+        result => result?,
+    };
+
+    let mut removed = Vec::new();
+    for id in store.all_signed_pre_key_ids(ctx).await? {
+        if Some(id) == current {
+            continue;
+        }
+        let timestamp = store.get_signed_pre_key(id, ctx).await?.timestamp()?;
+        if now_ms.saturating_sub(timestamp) > max_age_ms {
+            store.remove_signed_pre_key(id, ctx).await?;
+            removed.push(id);
+        }
+    }
+    removed.sort_unstable();
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InMemSignedPreKeyStore, KeyPair, SignedPreKeyRecord};
+    use futures::executor::block_on;
+    use rand::rngs::OsRng;
+
+    const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+    #[test]
+    fn only_recent_signed_pre_keys_survive_cleanup() -> Result<()> {
+        block_on(async {
+            let mut store = InMemSignedPreKeyStore::new();
+
+            // An initial key, then three rotations at twenty day intervals.
+            for (id, day) in (1..=4).zip(&[0, 20, 40, 60]) {
+                let record =
+                    SignedPreKeyRecord::new(id, day * DAY_MS, &KeyPair::generate(&mut OsRng), &[]);
+                store.save_signed_pre_key(id, &record, None).await?;
+                store.set_current_signed_pre_key_id(id, None).await?;
+            }
+            assert_eq!(store.current_signed_pre_key_id(None).await?, Some(4));
+
+            let removed =
+                remove_expired_signed_pre_keys(&mut store, 61 * DAY_MS, 30 * DAY_MS, None).await?;
+            assert_eq!(removed, vec![1, 2]);
+
+            let mut remaining = store.all_signed_pre_key_ids(None).await?;
+            remaining.sort_unstable();
+            assert_eq!(remaining, vec![3, 4]);
+
+            // Long after the last rotation, only the current key is kept.
+            let removed =
+                remove_expired_signed_pre_keys(&mut store, 365 * DAY_MS, 30 * DAY_MS, None).await?;
+            assert_eq!(removed, vec![3]);
+            assert_eq!(store.all_signed_pre_key_ids(None).await?, vec![4]);
+            Ok(())
+        })
+    }
+}
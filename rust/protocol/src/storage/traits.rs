@@ -5,8 +5,11 @@
 
 use async_trait::async_trait;
 
-use crate::error::Result;
-use crate::state::{PreKeyId, PreKeyRecord, SessionRecord, SignedPreKeyId, SignedPreKeyRecord};
+use crate::error::{Result, SignalProtocolError};
+use crate::state::{
+    PreKeyId, PreKeyRecord, RegistrationId, SessionRecord, SignedPreKeyId, SignedPreKeyRecord,
+};
+use crate::storage::StoreOp;
 use crate::{IdentityKey, IdentityKeyPair, ProtocolAddress, SenderKeyName, SenderKeyRecord};
 
 pub type Context = Option<*mut std::ffi::c_void>;
@@ -17,18 +20,31 @@ pub enum Direction {
     Receiving,
 }
 
+/// What [IdentityKeyStore::save_identity] did with the identity it was given.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IdentityChange {
+    /// There was no identity saved for the address before.
+    NewIdentity,
+    /// A different identity was saved for the address, and has been replaced.
+    ///
+    /// This is the case where clients should tell the user that the safety number has changed.
+    ReplacedExisting,
+    /// The same identity was already saved for the address.
+    Unchanged,
+}
+
 #[async_trait(?Send)]
 pub trait IdentityKeyStore {
     async fn get_identity_key_pair(&self, ctx: Context) -> Result<IdentityKeyPair>;
 
-    async fn get_local_registration_id(&self, ctx: Context) -> Result<u32>;
+    async fn get_local_registration_id(&self, ctx: Context) -> Result<RegistrationId>;
 
     async fn save_identity(
         &mut self,
         address: &ProtocolAddress,
         identity: &IdentityKey,
         ctx: Context,
-    ) -> Result<bool>;
+    ) -> Result<IdentityChange>;
 
     async fn is_trusted_identity(
         &self,
@@ -43,6 +59,23 @@ pub trait IdentityKeyStore {
         address: &ProtocolAddress,
         ctx: Context,
     ) -> Result<Option<IdentityKey>>;
+
+    /// Returns every saved identity, in no particular order.
+    ///
+    /// Not every store can enumerate its identities cheaply, so the default implementation fails
+    /// with [SignalProtocolError::OperationNotSupported].
+    async fn all_identities(&self, _ctx: Context) -> Result<Vec<(ProtocolAddress, IdentityKey)>> {
+        Err(SignalProtocolError::OperationNotSupported("all_identities"))
+    }
+
+    /// Forgets the saved identity for `address`, returning whether there was one.
+    ///
+    /// The default implementation fails with [SignalProtocolError::OperationNotSupported].
+    async fn delete_identity(&mut self, _address: &ProtocolAddress, _ctx: Context) -> Result<bool> {
+        Err(SignalProtocolError::OperationNotSupported(
+            "delete_identity",
+        ))
+    }
 }
 
 #[async_trait(?Send)]
@@ -57,6 +90,32 @@ pub trait PreKeyStore {
     ) -> Result<()>;
 
     async fn remove_pre_key(&mut self, prekey_id: PreKeyId, ctx: Context) -> Result<()>;
+
+    /// Saves several pre-keys at once.
+    ///
+    /// The default implementation calls [PreKeyStore::save_pre_key] for each record in turn;
+    /// stores that can write in bulk should override it.
+    async fn save_pre_keys(
+        &mut self,
+        records: &[(PreKeyId, PreKeyRecord)],
+        ctx: Context,
+    ) -> Result<()> {
+        for (prekey_id, record) in records {
+            self.save_pre_key(*prekey_id, record, ctx).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes several pre-keys at once.
+    ///
+    /// The default implementation calls [PreKeyStore::remove_pre_key] for each id in turn;
+    /// stores that can delete in bulk should override it.
+    async fn remove_pre_keys(&mut self, prekey_ids: &[PreKeyId], ctx: Context) -> Result<()> {
+        for prekey_id in prekey_ids {
+            self.remove_pre_key(*prekey_id, ctx).await?;
+        }
+        Ok(())
+    }
 }
 
 #[async_trait(?Send)]
@@ -73,6 +132,45 @@ pub trait SignedPreKeyStore {
         record: &SignedPreKeyRecord,
         ctx: Context,
     ) -> Result<()>;
+
+    /// Returns the IDs of every stored signed pre-key, in no particular order.
+    ///
+    /// This and the other rotation methods are optional; by default they fail with
+    /// [SignalProtocolError::OperationNotSupported].
+    async fn all_signed_pre_key_ids(&self, _ctx: Context) -> Result<Vec<SignedPreKeyId>> {
+        Err(SignalProtocolError::OperationNotSupported(
+            "all_signed_pre_key_ids",
+        ))
+    }
+
+    /// Returns the ID of the signed pre-key currently being published, if one has been set.
+    async fn current_signed_pre_key_id(&self, _ctx: Context) -> Result<Option<SignedPreKeyId>> {
+        Err(SignalProtocolError::OperationNotSupported(
+            "current_signed_pre_key_id",
+        ))
+    }
+
+    /// Marks a stored signed pre-key as the one currently being published.
+    async fn set_current_signed_pre_key_id(
+        &mut self,
+        _signed_prekey_id: SignedPreKeyId,
+        _ctx: Context,
+    ) -> Result<()> {
+        Err(SignalProtocolError::OperationNotSupported(
+            "set_current_signed_pre_key_id",
+        ))
+    }
+
+    /// Removes a signed pre-key. Removing a key that isn't stored succeeds.
+    async fn remove_signed_pre_key(
+        &mut self,
+        _signed_prekey_id: SignedPreKeyId,
+        _ctx: Context,
+    ) -> Result<()> {
+        Err(SignalProtocolError::OperationNotSupported(
+            "remove_signed_pre_key",
+        ))
+    }
 }
 
 #[async_trait(?Send)]
@@ -89,6 +187,51 @@ pub trait SessionStore {
         record: &SessionRecord,
         ctx: Context,
     ) -> Result<()>;
+
+    /// Loads the sessions for several addresses, such as all of a recipient's devices.
+    ///
+    /// Fails with [SignalProtocolError::SessionNotFoundForAddress] naming the first address,
+    /// in the order given, that has no session. The default implementation calls
+    /// [SessionStore::load_session] for each address in turn.
+    async fn load_existing_sessions(
+        &self,
+        addresses: &[&ProtocolAddress],
+        ctx: Context,
+    ) -> Result<Vec<SessionRecord>> {
+        let mut sessions = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            let session = self.load_session(address, ctx).await?.ok_or_else(|| {
+                SignalProtocolError::SessionNotFoundForAddress((*address).clone())
+            })?;
+            sessions.push(session);
+        }
+        Ok(sessions)
+    }
+
+    /// Returns the device IDs of every other device of `name` that has a session.
+    ///
+    /// Following the Java `SessionStore` convention, the primary device (device 1) is never
+    /// included; callers that want it should look up its session directly.
+    ///
+    /// This and the deletion methods are optional; by default they fail with
+    /// [SignalProtocolError::OperationNotSupported].
+    async fn get_sub_device_sessions(&self, _name: &str, _ctx: Context) -> Result<Vec<u32>> {
+        Err(SignalProtocolError::OperationNotSupported(
+            "get_sub_device_sessions",
+        ))
+    }
+
+    /// Removes the session for `address`, if there is one.
+    async fn delete_session(&mut self, _address: &ProtocolAddress, _ctx: Context) -> Result<()> {
+        Err(SignalProtocolError::OperationNotSupported("delete_session"))
+    }
+
+    /// Removes the sessions for every device of `name`.
+    async fn delete_all_sessions(&mut self, _name: &str, _ctx: Context) -> Result<()> {
+        Err(SignalProtocolError::OperationNotSupported(
+            "delete_all_sessions",
+        ))
+    }
 }
 
 #[async_trait(?Send)]
@@ -105,6 +248,36 @@ pub trait SenderKeyStore {
         sender_key_name: &SenderKeyName,
         ctx: Context,
     ) -> Result<Option<SenderKeyRecord>>;
+
+    /// Returns every sender with a sender key saved for `group_id`, in no particular order.
+    ///
+    /// The default implementation fails with [SignalProtocolError::OperationNotSupported].
+    async fn senders_for_group(
+        &self,
+        _group_id: &str,
+        _ctx: Context,
+    ) -> Result<Vec<ProtocolAddress>> {
+        Err(SignalProtocolError::OperationNotSupported(
+            "senders_for_group",
+        ))
+    }
+
+    /// Forgets every sender key saved for `group_id`, such as when the group is destroyed.
+    ///
+    /// The default implementation fails with [SignalProtocolError::OperationNotSupported].
+    async fn delete_sender_keys_for_group(&mut self, _group_id: &str, _ctx: Context) -> Result<()> {
+        Err(SignalProtocolError::OperationNotSupported(
+            "delete_sender_keys_for_group",
+        ))
+    }
 }
 
 pub trait ProtocolStore: SessionStore + PreKeyStore + SignedPreKeyStore + IdentityKeyStore {}
+
+/// A store that can apply several writes as one atomic unit, such as a single database
+/// transaction.
+#[async_trait(?Send)]
+pub trait TransactionalStore {
+    /// Performs every one of `ops`, in order, or none of them.
+    async fn commit(&mut self, ops: Vec<StoreOp>, ctx: Context) -> Result<()>;
+}
@@ -0,0 +1,107 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use crate::error::Result;
+use crate::state::{PreKeyId, SessionRecord};
+use crate::storage::traits::{
+    Context, IdentityKeyStore, PreKeyStore, SessionStore, TransactionalStore,
+};
+use crate::{IdentityKey, ProtocolAddress};
+
+/// A single store write that has been decided on but not yet performed.
+#[derive(Clone, Debug)]
+pub enum StoreOp {
+    SaveIdentity {
+        address: ProtocolAddress,
+        identity: IdentityKey,
+    },
+    StoreSession {
+        address: ProtocolAddress,
+        record: Box<SessionRecord>,
+    },
+    RemovePreKey(PreKeyId),
+}
+
+impl StoreOp {
+    pub(crate) async fn apply(
+        &self,
+        session_store: &mut dyn SessionStore,
+        identity_store: &mut dyn IdentityKeyStore,
+        pre_key_store: &mut dyn PreKeyStore,
+        ctx: Context,
+    ) -> Result<()> {
+        match self {
+            StoreOp::SaveIdentity { address, identity } => {
+                identity_store.save_identity(address, identity, ctx).await?;
+            }
+            StoreOp::StoreSession { address, record } => {
+                session_store.store_session(address, record, ctx).await?;
+            }
+            StoreOp::RemovePreKey(id) => {
+                pre_key_store.remove_pre_key(*id, ctx).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The store writes collected by one of the `_transactional` session cipher functions.
+///
+/// Nothing has been written when this is returned. Pass it to [StoreTransaction::commit] for a
+/// store that implements [TransactionalStore], or to [StoreTransaction::apply] to perform the
+/// writes one at a time, exactly as the non-transactional functions do.
+#[derive(Clone, Debug, Default)]
+pub struct StoreTransaction {
+    ops: Vec<StoreOp>,
+}
+
+impl StoreTransaction {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn push(&mut self, op: StoreOp) {
+        self.ops.push(op)
+    }
+
+    /// The pending writes, in the order they must be performed.
+    pub fn ops(&self) -> &[StoreOp] {
+        &self.ops
+    }
+
+    pub fn into_ops(self) -> Vec<StoreOp> {
+        self.ops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Hands all of the pending writes to `store` at once.
+    pub async fn commit(self, store: &mut dyn TransactionalStore, ctx: Context) -> Result<()> {
+        if self.ops.is_empty() {
+            return Ok(());
+        }
+        store.commit(self.ops, ctx).await
+    }
+
+    /// Performs the pending writes immediately, one at a time.
+    ///
+    /// This is for stores that cannot group writes together; if it fails partway through, the
+    /// writes before the failure have already been made.
+    pub async fn apply(
+        self,
+        session_store: &mut dyn SessionStore,
+        identity_store: &mut dyn IdentityKeyStore,
+        pre_key_store: &mut dyn PreKeyStore,
+        ctx: Context,
+    ) -> Result<()> {
+        for op in &self.ops {
+            op.apply(session_store, identity_store, pre_key_store, ctx)
+                .await?;
+        }
+        Ok(())
+    }
+}
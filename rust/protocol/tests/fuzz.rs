@@ -0,0 +1,123 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Runs the fuzz targets over random and mutated inputs. Only built with `--features fuzz,test-rng`.
+
+#![cfg(all(feature = "fuzz", feature = "test-rng"))]
+
+use libsignal_protocol_rust::fuzz_targets::*;
+use libsignal_protocol_rust::*;
+use rand::{Rng, RngCore};
+use std::convert::TryFrom;
+
+const ITERATIONS: usize = 2000;
+
+fn valid_inputs() -> Result<Vec<(u8, Vec<u8>)>, SignalProtocolError> {
+    let mut csprng = TestRng::new(1);
+    let identity_key = IdentityKey::new(KeyPair::generate(&mut csprng).public_key);
+    let signing_key_pair = KeyPair::generate(&mut csprng);
+
+    let signal_message = SignalMessage::new(
+        3,
+        &[1u8; 32],
+        KeyPair::generate(&mut csprng).public_key,
+        7,
+        6,
+        b"ciphertext",
+        &identity_key,
+        &identity_key,
+    )?;
+    let pre_key_signal_message = PreKeySignalMessage::new(
+        3,
+        RegistrationId::try_from(1234)?,
+        Some(5),
+        6,
+        KeyPair::generate(&mut csprng).public_key,
+        identity_key,
+        signal_message.clone(),
+    )?;
+    let sender_key_message = SenderKeyMessage::new(
+        8,
+        9,
+        b"ciphertext",
+        &mut csprng,
+        &signing_key_pair.private_key,
+    )?;
+    let sender_key_distribution_message =
+        SenderKeyDistributionMessage::new(8, 9, &[2u8; 32], signing_key_pair.public_key)?;
+    let bob_base_key = KeyPair::generate(&mut csprng);
+    let alice_params = AliceSignalProtocolParameters::new(
+        IdentityKeyPair::generate(&mut csprng),
+        KeyPair::generate(&mut csprng),
+        identity_key,
+        bob_base_key.public_key,
+        None,
+        bob_base_key.public_key,
+    );
+    let session_record = SessionRecord::new(initialize_alice_session(&alice_params, &mut csprng)?);
+
+    Ok(vec![
+        (KIND_SIGNAL_MESSAGE, signal_message.serialized().to_vec()),
+        (
+            KIND_PRE_KEY_SIGNAL_MESSAGE,
+            pre_key_signal_message.serialized().to_vec(),
+        ),
+        (
+            KIND_SENDER_KEY_MESSAGE,
+            sender_key_message.serialized().to_vec(),
+        ),
+        (
+            KIND_SENDER_KEY_DISTRIBUTION_MESSAGE,
+            sender_key_distribution_message.serialized().to_vec(),
+        ),
+        (KIND_SESSION_RECORD, session_record.serialize()?),
+    ])
+}
+
+#[test]
+fn parse_random_bytes() {
+    let mut rng = TestRng::new(2);
+    for _ in 0..ITERATIONS {
+        let mut data = vec![0u8; rng.gen_range(0, 256)];
+        rng.fill_bytes(&mut data);
+        for kind in 0..KIND_COUNT {
+            parse_any(kind, &data);
+        }
+    }
+}
+
+#[test]
+fn parse_mutated_inputs() -> Result<(), SignalProtocolError> {
+    let mut rng = TestRng::new(3);
+    for (kind, valid) in valid_inputs()? {
+        parse_any(kind, &valid);
+
+        for len in 0..valid.len() {
+            parse_any(kind, &valid[..len]);
+        }
+
+        for _ in 0..ITERATIONS {
+            let mut data = valid.clone();
+            for _ in 0..rng.gen_range(1, 4) {
+                let index = rng.gen_range(0, data.len());
+                data[index] = rng.gen();
+            }
+            parse_any(kind, &data);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn arbitrary_values_round_trip() {
+    let mut rng = TestRng::new(4);
+    for _ in 0..ITERATIONS {
+        let mut data = vec![0u8; rng.gen_range(0, 512)];
+        rng.fill_bytes(&mut data);
+        for kind in 0..KIND_COUNT {
+            round_trip_any(kind, &data);
+        }
+    }
+}
@@ -18,7 +18,7 @@ use support::test_in_memory_protocol_store;
 fn group_no_send_session() -> Result<(), SignalProtocolError> {
     let mut csprng = OsRng;
 
-    let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1);
+    let sender_address = ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
     let group_sender =
         SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
 
@@ -79,7 +79,8 @@ fn group_using_context_arg() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1);
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
         let group_sender =
             SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
 
@@ -106,7 +107,8 @@ fn group_no_recv_session() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1);
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
         let group_sender =
             SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
 
@@ -147,7 +149,8 @@ fn group_basic_encrypt_decrypt() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1);
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
         let group_sender =
             SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
 
@@ -196,7 +199,8 @@ fn group_large_messages() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1);
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
         let group_sender =
             SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
 
@@ -250,7 +254,8 @@ fn group_basic_ratchet() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1);
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
         let group_sender =
             SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
 
@@ -307,7 +312,10 @@ fn group_basic_ratchet() -> Result<(), SignalProtocolError> {
 
         assert_eq!(
             group_decrypt(&alice_ciphertext1, &mut bob_store, &group_sender, None).await,
-            Err(SignalProtocolError::DuplicatedMessage(1, 0))
+            Err(SignalProtocolError::DuplicatedMessage {
+                current_iteration: 1,
+                counter: 0
+            })
         );
 
         let bob_plaintext3 =
@@ -327,7 +335,8 @@ fn group_late_join() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1);
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
         let group_sender =
             SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
 
@@ -387,7 +396,8 @@ fn group_out_of_order() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1);
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
         let group_sender =
             SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
 
@@ -454,7 +464,8 @@ fn group_too_far_in_the_future() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1);
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
         let group_sender =
             SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
 
@@ -500,22 +511,148 @@ fn group_too_far_in_the_future() -> Result<(), SignalProtocolError> {
         )
         .await?;
 
-        assert!(
+        assert_eq!(
             group_decrypt(&alice_ciphertext, &mut bob_store, &group_sender, None)
                 .await
-                .is_err()
+                .unwrap_err(),
+            SignalProtocolError::MessageTooFarInFuture {
+                current_iteration: 0,
+                counter: 2001,
+            }
         );
 
         Ok(())
     })
 }
 
+#[test]
+fn group_configurable_forward_window() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
+        let group_sender =
+            SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
+
+        let mut alice_store = test_in_memory_protocol_store();
+        let mut bob_store = test_in_memory_protocol_store();
+
+        let distribution_message = create_sender_key_distribution_message(
+            &group_sender,
+            &mut alice_store,
+            &mut csprng,
+            None,
+        )
+        .await?;
+        process_sender_key_distribution_message(
+            &group_sender,
+            &distribution_message,
+            &mut bob_store,
+            None,
+        )
+        .await?;
+
+        let mut ciphertexts = Vec::new();
+        for i in 0..20 {
+            ciphertexts.push(
+                group_encrypt(
+                    &mut alice_store,
+                    &group_sender,
+                    format!("message {}", i).as_bytes(),
+                    &mut csprng,
+                    None,
+                )
+                .await?,
+            );
+        }
+
+        let config = GroupCipherConfig {
+            max_skipped_message_keys: 10,
+        };
+
+        // Just over the cap.
+        assert_eq!(
+            group_decrypt_with_config(
+                &ciphertexts[11],
+                &mut bob_store,
+                &group_sender,
+                &config,
+                None
+            )
+            .await
+            .unwrap_err(),
+            SignalProtocolError::MessageTooFarInFuture {
+                current_iteration: 0,
+                counter: 11,
+            }
+        );
+
+        // Exactly at the cap.
+        assert_eq!(
+            group_decrypt_with_config(
+                &ciphertexts[10],
+                &mut bob_store,
+                &group_sender,
+                &config,
+                None
+            )
+            .await?,
+            b"message 10"
+        );
+
+        // Skipping four more keys evicts the four oldest stored ones.
+        assert_eq!(
+            group_decrypt_with_config(
+                &ciphertexts[15],
+                &mut bob_store,
+                &group_sender,
+                &config,
+                None
+            )
+            .await?,
+            b"message 15"
+        );
+        assert_eq!(
+            group_decrypt_with_config(
+                &ciphertexts[3],
+                &mut bob_store,
+                &group_sender,
+                &config,
+                None
+            )
+            .await
+            .unwrap_err(),
+            SignalProtocolError::DuplicatedMessage {
+                current_iteration: 16,
+                counter: 3,
+            }
+        );
+        for i in &[4, 9, 11, 14] {
+            assert_eq!(
+                group_decrypt_with_config(
+                    &ciphertexts[*i],
+                    &mut bob_store,
+                    &group_sender,
+                    &config,
+                    None
+                )
+                .await?,
+                format!("message {}", i).as_bytes()
+            );
+        }
+
+        Ok(())
+    })
+}
+
 #[test]
 fn group_message_key_limit() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1);
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
         let group_sender =
             SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
 
@@ -585,3 +722,508 @@ fn group_message_key_limit() -> Result<(), SignalProtocolError> {
         Ok(())
     })
 }
+
+#[test]
+fn group_sender_key_rotation() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
+        let group_sender =
+            SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
+
+        let mut alice_store = test_in_memory_protocol_store();
+        let mut bob_store = test_in_memory_protocol_store();
+        let mut carol_store = test_in_memory_protocol_store();
+
+        let distribution_message = create_sender_key_distribution_message(
+            &group_sender,
+            &mut alice_store,
+            &mut csprng,
+            None,
+        )
+        .await?;
+        for store in [&mut bob_store, &mut carol_store].iter_mut() {
+            process_sender_key_distribution_message(
+                &group_sender,
+                &distribution_message,
+                *store,
+                None,
+            )
+            .await?;
+        }
+
+        let before_removal = group_encrypt(
+            &mut alice_store,
+            &group_sender,
+            "space camp?".as_bytes(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+        assert_eq!(
+            group_decrypt(&before_removal, &mut carol_store, &group_sender, None).await?,
+            b"space camp?"
+        );
+
+        // Carol leaves the group; only Bob gets the new key.
+        let rotated_message = rotate_sender_key(
+            &group_sender,
+            &mut alice_store,
+            SenderKeyRotationPolicy::Archive,
+            &mut csprng,
+            None,
+        )
+        .await?;
+        assert_ne!(rotated_message.id()?, distribution_message.id()?);
+        assert_eq!(rotated_message.iteration()?, 0);
+        process_sender_key_distribution_message(
+            &group_sender,
+            &rotated_message,
+            &mut bob_store,
+            None,
+        )
+        .await?;
+
+        let after_removal = group_encrypt(
+            &mut alice_store,
+            &group_sender,
+            "moon camp!".as_bytes(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+        assert_eq!(
+            SenderKeyMessage::try_from(&after_removal[..])?.key_id(),
+            rotated_message.id()?
+        );
+        assert_eq!(
+            group_decrypt(&after_removal, &mut bob_store, &group_sender, None).await?,
+            b"moon camp!"
+        );
+        assert_eq!(
+            group_decrypt(&after_removal, &mut carol_store, &group_sender, None)
+                .await
+                .unwrap_err(),
+            SignalProtocolError::NoSenderKeyState
+        );
+
+        // Messages sent before the rotation still decrypt.
+        assert_eq!(
+            group_decrypt(&before_removal, &mut bob_store, &group_sender, None).await?,
+            b"space camp?"
+        );
+
+        // Alice's archived state is kept, but can no longer sign.
+        let mut alice_record = alice_store
+            .load_sender_key(&group_sender, None)
+            .await?
+            .expect("record exists");
+        let archived_state = alice_record.sender_key_state_for_keyid(distribution_message.id()?)?;
+        assert!(archived_state.signing_key_private()?.is_none());
+
+        // Discarding drops the old keys from Alice's record.
+        rotate_sender_key(
+            &group_sender,
+            &mut alice_store,
+            SenderKeyRotationPolicy::Discard,
+            &mut csprng,
+            None,
+        )
+        .await?;
+        let mut alice_record = alice_store
+            .load_sender_key(&group_sender, None)
+            .await?
+            .expect("record exists");
+        assert!(alice_record
+            .sender_key_state_for_keyid(rotated_message.id()?)
+            .is_err());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn group_sender_key_record_introspection() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
+        let group_sender =
+            SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
+
+        let mut alice_store = test_in_memory_protocol_store();
+
+        let first = create_sender_key_distribution_message(
+            &group_sender,
+            &mut alice_store,
+            &mut csprng,
+            None,
+        )
+        .await?;
+        for _ in 0..3 {
+            group_encrypt(
+                &mut alice_store,
+                &group_sender,
+                "space camp?".as_bytes(),
+                &mut csprng,
+                None,
+            )
+            .await?;
+        }
+        let second = rotate_sender_key(
+            &group_sender,
+            &mut alice_store,
+            SenderKeyRotationPolicy::Archive,
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        let record = alice_store
+            .load_sender_key(&group_sender, None)
+            .await?
+            .expect("record exists");
+        assert_eq!(record.state_count()?, 2);
+        assert_eq!(record.chain_ids()?, vec![second.id()?, first.id()?]);
+        assert_eq!(record.max_iteration_for_chain(first.id()?)?, Some(3));
+        assert_eq!(record.max_iteration_for_chain(second.id()?)?, Some(0));
+        assert_eq!(record.max_iteration_for_chain(first.id()? ^ 1)?, None);
+
+        let serialized = record.serialize()?;
+        let restored = SenderKeyRecord::deserialize(&serialized)?;
+        assert_eq!(restored.chain_ids()?, record.chain_ids()?);
+
+        // A record from a future version (field 2, the version, set to 2) is rejected.
+        let mut future = serialized.clone();
+        future.extend_from_slice(&[0x10, 0x02]);
+        assert_eq!(
+            SenderKeyRecord::deserialize(&future).unwrap_err(),
+            SignalProtocolError::UnrecognizedRecordVersion(2)
+        );
+
+        // Records written before versioning are still accepted.
+        let mut legacy = serialized;
+        legacy.extend_from_slice(&[0x10, 0x00]);
+        assert_eq!(SenderKeyRecord::deserialize(&legacy)?.state_count()?, 2);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn group_decrypt_reports_sender_chain() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
+        let group_sender =
+            SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
+
+        let mut alice_store = test_in_memory_protocol_store();
+        let mut bob_store = test_in_memory_protocol_store();
+
+        let distribution_message = create_sender_key_distribution_message(
+            &group_sender,
+            &mut alice_store,
+            &mut csprng,
+            None,
+        )
+        .await?;
+        process_sender_key_distribution_message(
+            &group_sender,
+            &distribution_message,
+            &mut bob_store,
+            None,
+        )
+        .await?;
+
+        group_encrypt(
+            &mut alice_store,
+            &group_sender,
+            "space camp?".as_bytes(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+        let alice_ciphertext = group_encrypt(
+            &mut alice_store,
+            &group_sender,
+            "moon camp!".as_bytes(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        // A copy re-signed by someone other than the distributor is rejected.
+        let skm = SenderKeyMessage::try_from(&alice_ciphertext[..])?;
+        let forger_key_pair = KeyPair::generate(&mut csprng);
+        let forged = SenderKeyMessage::new(
+            skm.key_id(),
+            skm.iteration(),
+            skm.ciphertext(),
+            &mut csprng,
+            &forger_key_pair.private_key,
+        )?;
+        assert_eq!(
+            group_decrypt_with_metadata(
+                forged.serialized(),
+                &mut bob_store,
+                &group_sender,
+                &GroupCipherConfig::default(),
+                None,
+            )
+            .await
+            .unwrap_err(),
+            SignalProtocolError::SignatureValidationFailed
+        );
+
+        let (plaintext, metadata) = group_decrypt_with_metadata(
+            &alice_ciphertext,
+            &mut bob_store,
+            &group_sender,
+            &GroupCipherConfig::default(),
+            None,
+        )
+        .await?;
+        assert_eq!(plaintext, b"moon camp!");
+        assert_eq!(
+            metadata,
+            GroupDecryptionMetadata {
+                chain_id: distribution_message.id()?,
+                iteration: 1,
+                signing_key: *distribution_message.signing_key()?,
+            }
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn group_decrypt_into_reuses_buffer() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
+        let group_sender =
+            SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
+
+        let mut alice_store = test_in_memory_protocol_store();
+        let mut bob_store = test_in_memory_protocol_store();
+
+        let sent_distribution_message = create_sender_key_distribution_message(
+            &group_sender,
+            &mut alice_store,
+            &mut csprng,
+            None,
+        )
+        .await?;
+        let recv_distribution_message =
+            SenderKeyDistributionMessage::try_from(sent_distribution_message.serialized())?;
+        process_sender_key_distribution_message(
+            &group_sender,
+            &recv_distribution_message,
+            &mut bob_store,
+            None,
+        )
+        .await?;
+
+        let mut plaintext = Vec::new();
+
+        for message in &["a much longer first message", "short"] {
+            let ciphertext = group_encrypt(
+                &mut alice_store,
+                &group_sender,
+                message.as_bytes(),
+                &mut csprng,
+                None,
+            )
+            .await?;
+            group_decrypt_into(
+                &ciphertext,
+                &mut bob_store,
+                &group_sender,
+                &GroupCipherConfig::default(),
+                &mut plaintext,
+                None,
+            )
+            .await?;
+            assert_eq!(plaintext, message.as_bytes());
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn group_distribution_id_mismatch() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
+        let group_sender =
+            SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
+
+        let mut alice_store = test_in_memory_protocol_store();
+        let mut bob_store = test_in_memory_protocol_store();
+
+        let distribution_message = create_sender_key_distribution_message(
+            &group_sender,
+            &mut alice_store,
+            &mut csprng,
+            None,
+        )
+        .await?;
+        let chain_id = distribution_message.id()?;
+
+        assert_eq!(
+            process_sender_key_distribution_message_with_expected_id(
+                &group_sender,
+                &distribution_message,
+                Some(chain_id.wrapping_add(1)),
+                &mut bob_store,
+                None,
+            )
+            .await
+            .unwrap_err(),
+            SignalProtocolError::SenderKeyDistributionIdMismatch {
+                expected: chain_id.wrapping_add(1),
+                found: chain_id,
+            }
+        );
+        assert!(bob_store
+            .load_sender_key(&group_sender, None)
+            .await?
+            .is_none());
+
+        let report = process_sender_key_distribution_message_with_expected_id(
+            &group_sender,
+            &distribution_message,
+            Some(chain_id),
+            &mut bob_store,
+            None,
+        )
+        .await?;
+        assert_eq!(
+            report,
+            SenderKeyDistributionReport {
+                chain_id,
+                iteration: distribution_message.iteration()?,
+            }
+        );
+
+        let alice_ciphertext = group_encrypt(
+            &mut alice_store,
+            &group_sender,
+            "space camp?".as_bytes(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+        let bob_plaintext =
+            group_decrypt(&alice_ciphertext, &mut bob_store, &group_sender, None).await?;
+        assert_eq!(bob_plaintext, b"space camp?");
+
+        Ok(())
+    })
+}
+
+#[test]
+fn group_encrypt_reports_chain_and_iteration() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
+        let group_sender =
+            SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
+
+        let mut alice_store = test_in_memory_protocol_store();
+
+        let distribution_message = create_sender_key_distribution_message(
+            &group_sender,
+            &mut alice_store,
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        let first = group_encrypt_with_metadata(
+            &mut alice_store,
+            &group_sender,
+            "space camp?".as_bytes(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+        let second = group_encrypt_with_metadata(
+            &mut alice_store,
+            &group_sender,
+            "moon camp!".as_bytes(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        assert_eq!(first.chain_id, distribution_message.id()?);
+        assert_eq!(second.chain_id, distribution_message.id()?);
+        assert_eq!(first.iteration, distribution_message.iteration()?);
+        assert_eq!(second.iteration, first.iteration + 1);
+
+        let skm = SenderKeyMessage::try_from(&second.serialized[..])?;
+        assert_eq!(skm.key_id(), second.chain_id);
+        assert_eq!(skm.iteration(), second.iteration);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn group_chain_at_max_iteration() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
+        let group_sender =
+            SenderKeyName::new("summer camp planning committee".to_owned(), sender_address)?;
+
+        let mut bob_store = test_in_memory_protocol_store();
+
+        // A distribution message can claim any starting iteration, including the last one.
+        let signing_key_pair = KeyPair::generate(&mut csprng);
+        let distribution_message = SenderKeyDistributionMessage::new(
+            7,
+            u32::MAX,
+            &[1u8; 32],
+            signing_key_pair.public_key,
+        )?;
+        process_sender_key_distribution_message(
+            &group_sender,
+            &distribution_message,
+            &mut bob_store,
+            None,
+        )
+        .await?;
+
+        let message = SenderKeyMessage::new(
+            7,
+            u32::MAX,
+            b"ciphertext",
+            &mut csprng,
+            &signing_key_pair.private_key,
+        )?;
+        assert!(matches!(
+            group_decrypt(message.serialized(), &mut bob_store, &group_sender, None).await,
+            Err(SignalProtocolError::InvalidState(..))
+        ));
+
+        Ok(())
+    })
+}
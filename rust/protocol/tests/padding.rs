@@ -0,0 +1,38 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use libsignal_protocol_rust::*;
+
+const VECTORS: &str = include_str!("data/padding-vectors.txt");
+
+fn vectors() -> Vec<(&'static str, Vec<u8>, Vec<u8>)> {
+    VECTORS
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next().expect("name");
+            let body = match fields.next().expect("body") {
+                "-" => vec![],
+                body => hex::decode(body).expect("valid hex"),
+            };
+            let padded = hex::decode(fields.next().expect("padded")).expect("valid hex");
+            (name, body, padded)
+        })
+        .collect()
+}
+
+#[test]
+fn shared_vectors_match() -> Result<(), SignalProtocolError> {
+    for (name, body, padded) in vectors() {
+        assert!(pad_message(&body) == padded, "{} padded differently", name);
+        assert!(
+            unpad_message(&padded)? == &body[..],
+            "{} unpadded differently",
+            name
+        );
+    }
+    Ok(())
+}
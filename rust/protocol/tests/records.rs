@@ -0,0 +1,159 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use libsignal_protocol_rust::*;
+use rand::rngs::OsRng;
+
+// Written by the current version; these should only change along with SERIALIZED_VERSION.
+const SESSION_RECORD: &[u8] = include_bytes!("data/session-record-v1.bin");
+const PRE_KEY_RECORD: &[u8] = include_bytes!("data/pre-key-record-v1.bin");
+const SIGNED_PRE_KEY_RECORD: &[u8] = include_bytes!("data/signed-pre-key-record-v1.bin");
+const SENDER_KEY_RECORD: &[u8] = include_bytes!("data/sender-key-record-v1.bin");
+
+/// Strips the trailing version field, which protobuf encodes last because it has the highest tag,
+/// giving the record as it was written before versions were recorded.
+fn unversioned(record: &[u8], version_key: u8) -> Vec<u8> {
+    assert_eq!(record[record.len() - 2..], [version_key, 1]);
+    record[..record.len() - 2].to_vec()
+}
+
+#[test]
+fn test_session_record_fixture() -> Result<(), SignalProtocolError> {
+    assert_eq!(
+        SessionRecord::serialized_version(SESSION_RECORD)?,
+        SessionRecord::SERIALIZED_VERSION
+    );
+    let record = SessionRecord::deserialize(SESSION_RECORD)?;
+    assert_eq!(record.previous_session_states()?.count(), 1);
+    assert_eq!(record.serialize()?, SESSION_RECORD);
+    assert_eq!(SessionRecord::migrate(SESSION_RECORD)?, SESSION_RECORD);
+    Ok(())
+}
+
+#[test]
+fn test_pre_key_record_fixture() -> Result<(), SignalProtocolError> {
+    assert_eq!(
+        PreKeyRecord::serialized_version(PRE_KEY_RECORD)?,
+        PreKeyRecord::SERIALIZED_VERSION
+    );
+    let record = PreKeyRecord::deserialize(PRE_KEY_RECORD)?;
+    assert_eq!(record.id()?, 7);
+    assert_eq!(record.serialize()?, PRE_KEY_RECORD);
+    assert_eq!(PreKeyRecord::migrate(PRE_KEY_RECORD)?, PRE_KEY_RECORD);
+    Ok(())
+}
+
+#[test]
+fn test_signed_pre_key_record_fixture() -> Result<(), SignalProtocolError> {
+    assert_eq!(
+        SignedPreKeyRecord::serialized_version(SIGNED_PRE_KEY_RECORD)?,
+        SignedPreKeyRecord::SERIALIZED_VERSION
+    );
+    let record = SignedPreKeyRecord::deserialize(SIGNED_PRE_KEY_RECORD)?;
+    assert_eq!(record.id()?, 9);
+    assert!(record
+        .public_key()?
+        .verify_signature(&record.public_key()?.serialize(), &record.signature()?)?);
+    assert_eq!(record.serialize()?, SIGNED_PRE_KEY_RECORD);
+    assert_eq!(
+        SignedPreKeyRecord::migrate(SIGNED_PRE_KEY_RECORD)?,
+        SIGNED_PRE_KEY_RECORD
+    );
+    Ok(())
+}
+
+#[test]
+fn test_sender_key_record_fixture() -> Result<(), SignalProtocolError> {
+    assert_eq!(
+        SenderKeyRecord::serialized_version(SENDER_KEY_RECORD)?,
+        SenderKeyRecord::SERIALIZED_VERSION
+    );
+    let record = SenderKeyRecord::deserialize(SENDER_KEY_RECORD)?;
+    assert_eq!(record.state_count()?, 1);
+    assert_eq!(record.serialize()?, SENDER_KEY_RECORD);
+    assert_eq!(
+        SenderKeyRecord::migrate(SENDER_KEY_RECORD)?,
+        SENDER_KEY_RECORD
+    );
+    Ok(())
+}
+
+#[test]
+fn test_unversioned_records_migrate() -> Result<(), SignalProtocolError> {
+    let session = unversioned(SESSION_RECORD, 0x18);
+    assert_eq!(SessionRecord::serialized_version(&session)?, 0);
+    assert_eq!(SessionRecord::migrate(&session)?, SESSION_RECORD);
+
+    let pre_key = unversioned(PRE_KEY_RECORD, 0x20);
+    assert_eq!(PreKeyRecord::serialized_version(&pre_key)?, 0);
+    assert_eq!(PreKeyRecord::migrate(&pre_key)?, PRE_KEY_RECORD);
+
+    let signed_pre_key = unversioned(SIGNED_PRE_KEY_RECORD, 0x30);
+    assert_eq!(SignedPreKeyRecord::serialized_version(&signed_pre_key)?, 0);
+    assert_eq!(
+        SignedPreKeyRecord::migrate(&signed_pre_key)?,
+        SIGNED_PRE_KEY_RECORD
+    );
+
+    let sender_key = unversioned(SENDER_KEY_RECORD, 0x10);
+    assert_eq!(SenderKeyRecord::serialized_version(&sender_key)?, 0);
+    assert_eq!(SenderKeyRecord::migrate(&sender_key)?, SENDER_KEY_RECORD);
+    Ok(())
+}
+
+#[test]
+fn test_newer_records_are_rejected() -> Result<(), SignalProtocolError> {
+    // A repeated scalar field takes its last value, so this overrides the fixture's version.
+    let mut newer = PRE_KEY_RECORD.to_vec();
+    newer.extend_from_slice(&[0x20, 2]);
+    assert_eq!(PreKeyRecord::serialized_version(&newer)?, 2);
+    assert!(matches!(
+        PreKeyRecord::migrate(&newer),
+        Err(SignalProtocolError::UnrecognizedRecordVersion(2))
+    ));
+
+    let mut newer = SESSION_RECORD.to_vec();
+    newer.extend_from_slice(&[0x18, 2]);
+    assert_eq!(SessionRecord::serialized_version(&newer)?, 2);
+    assert!(matches!(
+        SessionRecord::deserialize(&newer),
+        Err(SignalProtocolError::UnrecognizedRecordVersion(2))
+    ));
+
+    assert!(SessionRecord::serialized_version(&[0x18]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_pre_key_record_accessors_round_trip() -> Result<(), SignalProtocolError> {
+    let mut csprng = OsRng;
+    let key_pair = KeyPair::generate(&mut csprng);
+
+    let record = PreKeyRecord::new(42, &key_pair);
+    let record = PreKeyRecord::deserialize(&record.serialize()?)?;
+    assert_eq!(record.id()?, 42);
+    assert_eq!(record.public_key()?, key_pair.public_key);
+    assert_eq!(
+        record.private_key()?.serialize(),
+        key_pair.private_key.serialize()
+    );
+    assert_eq!(record.key_pair()?.public_key, key_pair.public_key);
+
+    let signature = key_pair
+        .private_key
+        .calculate_signature(&key_pair.public_key.serialize(), &mut csprng)?;
+    let record = SignedPreKeyRecord::new(43, 1_600_000_000_000, &key_pair, &signature);
+    let record = SignedPreKeyRecord::deserialize(&record.serialize()?)?;
+    assert_eq!(record.id()?, 43);
+    assert_eq!(record.timestamp()?, 1_600_000_000_000);
+    assert_eq!(record.signature()?, signature.to_vec());
+    assert_eq!(record.public_key()?, key_pair.public_key);
+    assert_eq!(
+        record.private_key()?.serialize(),
+        key_pair.private_key.serialize()
+    );
+    assert_eq!(record.key_pair()?.public_key, key_pair.public_key);
+    Ok(())
+}
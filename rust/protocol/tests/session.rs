@@ -7,8 +7,12 @@ mod support;
 
 use futures::executor::block_on;
 use libsignal_protocol_rust::*;
-use rand::rngs::OsRng;
+use rand::rngs::{OsRng, StdRng};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use std::convert::TryFrom;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use support::*;
 
 #[test]
@@ -16,8 +20,8 @@ fn test_basic_prekey_v3() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1);
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let mut bob_store = support::test_in_memory_protocol_store();
@@ -29,7 +33,6 @@ fn test_basic_prekey_v3() -> Result<(), SignalProtocolError> {
         let bob_signed_pre_key_signature = bob_store
             .get_identity_key_pair(None)
             .await?
-            .private_key()
             .calculate_signature(&bob_signed_pre_key_public, &mut csprng)?;
 
         let pre_key_id = 31337;
@@ -37,24 +40,26 @@ fn test_basic_prekey_v3() -> Result<(), SignalProtocolError> {
 
         let bob_pre_key_bundle = PreKeyBundle::new(
             bob_store.get_local_registration_id(None).await?,
-            1,                                 // device id
-            Some(pre_key_id),                  // pre key id
-            Some(bob_pre_key_pair.public_key), // pre key
-            signed_pre_key_id,                 // signed pre key id
+            DeviceId::try_from(1)?, // device id
+            Some((pre_key_id, bob_pre_key_pair.public_key)),
+            signed_pre_key_id, // signed pre key id
             bob_signed_pre_key_pair.public_key,
             bob_signed_pre_key_signature.to_vec(),
             *bob_store.get_identity_key_pair(None).await?.identity_key(),
         )?;
 
-        process_prekey_bundle(
-            &bob_address,
-            &mut alice_store.session_store,
-            &mut alice_store.identity_store,
-            &bob_pre_key_bundle,
-            &mut csprng,
-            None,
-        )
-        .await?;
+        assert_eq!(
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                &mut csprng,
+                None,
+            )
+            .await?,
+            IdentityChange::NewIdentity
+        );
 
         assert!(alice_store
             .load_session(&bob_address, None)
@@ -151,7 +156,6 @@ fn test_basic_prekey_v3() -> Result<(), SignalProtocolError> {
         let bob_signed_pre_key_signature = bob_store
             .get_identity_key_pair(None)
             .await?
-            .private_key()
             .calculate_signature(&bob_signed_pre_key_public, &mut csprng)?;
 
         let pre_key_id = 31337;
@@ -159,9 +163,8 @@ fn test_basic_prekey_v3() -> Result<(), SignalProtocolError> {
 
         let bob_pre_key_bundle = PreKeyBundle::new(
             bob_store.get_local_registration_id(None).await?,
-            1, // device id
-            Some(pre_key_id + 1),
-            Some(bob_pre_key_pair.public_key), // pre key
+            DeviceId::try_from(1)?, // device id
+            Some((pre_key_id + 1, bob_pre_key_pair.public_key)),
             signed_pre_key_id + 1,
             bob_signed_pre_key_pair.public_key,
             bob_signed_pre_key_signature.to_vec(),
@@ -218,7 +221,7 @@ fn test_basic_prekey_v3() -> Result<(), SignalProtocolError> {
                     None,
                 )
                 .await?,
-            true
+            IdentityChange::ReplacedExisting
         );
 
         let decrypted = decrypt(&mut bob_store, &alice_address, &outgoing_message).await?;
@@ -227,9 +230,8 @@ fn test_basic_prekey_v3() -> Result<(), SignalProtocolError> {
         // Sign pre-key with wrong key:
         let bob_pre_key_bundle = PreKeyBundle::new(
             bob_store.get_local_registration_id(None).await?,
-            1, // device id
-            Some(pre_key_id),
-            Some(bob_pre_key_pair.public_key), // pre key
+            DeviceId::try_from(1)?, // device id
+            Some((pre_key_id, bob_pre_key_pair.public_key)),
             signed_pre_key_id,
             bob_signed_pre_key_pair.public_key,
             bob_signed_pre_key_signature.to_vec(),
@@ -257,7 +259,7 @@ fn test_basic_prekey_v3() -> Result<(), SignalProtocolError> {
 #[test]
 fn test_bad_signed_pre_key_signature() -> Result<(), SignalProtocolError> {
     block_on(async {
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let bob_store = support::test_in_memory_protocol_store();
@@ -270,7 +272,6 @@ fn test_bad_signed_pre_key_signature() -> Result<(), SignalProtocolError> {
         let bob_signed_pre_key_signature = bob_store
             .get_identity_key_pair(None)
             .await?
-            .private_key()
             .calculate_signature(&bob_signed_pre_key_public, &mut csprng)?
             .to_vec();
 
@@ -284,9 +285,8 @@ fn test_bad_signed_pre_key_signature() -> Result<(), SignalProtocolError> {
 
             let bob_pre_key_bundle = PreKeyBundle::new(
                 bob_store.get_local_registration_id(None).await?,
-                1,
-                Some(pre_key_id),
-                Some(bob_pre_key_pair.public_key),
+                DeviceId::try_from(1)?,
+                Some((pre_key_id, bob_pre_key_pair.public_key)),
                 signed_pre_key_id,
                 bob_signed_pre_key_pair.public_key,
                 bad_signature,
@@ -309,9 +309,8 @@ fn test_bad_signed_pre_key_signature() -> Result<(), SignalProtocolError> {
 
         let bob_pre_key_bundle = PreKeyBundle::new(
             bob_store.get_local_registration_id(None).await?,
-            1,
-            Some(pre_key_id),
-            Some(bob_pre_key_pair.public_key),
+            DeviceId::try_from(1)?,
+            Some((pre_key_id, bob_pre_key_pair.public_key)),
             signed_pre_key_id,
             bob_signed_pre_key_pair.public_key,
             bob_signed_pre_key_signature,
@@ -337,8 +336,8 @@ fn test_bad_signed_pre_key_signature() -> Result<(), SignalProtocolError> {
 #[test]
 fn repeat_bundle_message_v3() -> Result<(), SignalProtocolError> {
     block_on(async {
-        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1);
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let mut bob_store = support::test_in_memory_protocol_store();
@@ -351,7 +350,6 @@ fn repeat_bundle_message_v3() -> Result<(), SignalProtocolError> {
         let bob_signed_pre_key_signature = bob_store
             .get_identity_key_pair(None)
             .await?
-            .private_key()
             .calculate_signature(&bob_signed_pre_key_public, &mut csprng)?;
 
         let pre_key_id = 31337;
@@ -359,10 +357,9 @@ fn repeat_bundle_message_v3() -> Result<(), SignalProtocolError> {
 
         let bob_pre_key_bundle = PreKeyBundle::new(
             bob_store.get_local_registration_id(None).await?,
-            1,                                 // device id
-            Some(pre_key_id),                  // pre key id
-            Some(bob_pre_key_pair.public_key), // pre key
-            signed_pre_key_id,                 // signed pre key id
+            DeviceId::try_from(1)?, // device id
+            Some((pre_key_id, bob_pre_key_pair.public_key)),
+            signed_pre_key_id, // signed pre key id
             bob_signed_pre_key_pair.public_key,
             bob_signed_pre_key_signature.to_vec(),
             *bob_store.get_identity_key_pair(None).await?.identity_key(),
@@ -460,8 +457,8 @@ fn bad_message_bundle() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1);
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let mut bob_store = support::test_in_memory_protocol_store();
@@ -473,7 +470,6 @@ fn bad_message_bundle() -> Result<(), SignalProtocolError> {
         let bob_signed_pre_key_signature = bob_store
             .get_identity_key_pair(None)
             .await?
-            .private_key()
             .calculate_signature(&bob_signed_pre_key_public, &mut csprng)?;
 
         let pre_key_id = 31337;
@@ -481,10 +477,9 @@ fn bad_message_bundle() -> Result<(), SignalProtocolError> {
 
         let bob_pre_key_bundle = PreKeyBundle::new(
             bob_store.get_local_registration_id(None).await?,
-            1,                                 // device id
-            Some(pre_key_id),                  // pre key id
-            Some(bob_pre_key_pair.public_key), // pre key
-            signed_pre_key_id,                 // signed pre key id
+            DeviceId::try_from(1)?, // device id
+            Some((pre_key_id, bob_pre_key_pair.public_key)),
+            signed_pre_key_id, // signed pre key id
             bob_signed_pre_key_pair.public_key,
             bob_signed_pre_key_signature.to_vec(),
             *bob_store.get_identity_key_pair(None).await?.identity_key(),
@@ -534,197 +529,1583 @@ fn bad_message_bundle() -> Result<(), SignalProtocolError> {
             3
         );
 
-        let original_message = "L'homme est condamné à être libre";
+        let original_message = "L'homme est condamné à être libre";
+
+        assert!(bob_store.get_pre_key(pre_key_id, None).await.is_ok());
+        let outgoing_message = encrypt(&mut alice_store, &bob_address, original_message).await?;
+
+        assert_eq!(
+            outgoing_message.message_type(),
+            CiphertextMessageType::PreKey
+        );
+
+        let outgoing_message = outgoing_message.serialize().to_vec();
+
+        let mut corrupted_message: Vec<u8> = outgoing_message.clone();
+        corrupted_message[outgoing_message.len() - 10] ^= 1;
+
+        let incoming_message = CiphertextMessage::PreKeySignalMessage(
+            PreKeySignalMessage::try_from(corrupted_message.as_slice())?,
+        );
+
+        assert!(decrypt(&mut bob_store, &alice_address, &incoming_message)
+            .await
+            .is_err());
+        assert!(bob_store.get_pre_key(pre_key_id, None).await.is_ok());
+
+        let incoming_message = CiphertextMessage::PreKeySignalMessage(
+            PreKeySignalMessage::try_from(outgoing_message.as_slice())?,
+        );
+
+        let ptext = decrypt(&mut bob_store, &alice_address, &incoming_message).await?;
+
+        assert_eq!(String::from_utf8(ptext).unwrap(), original_message);
+        assert_eq!(
+            bob_store.get_pre_key(pre_key_id, None).await.unwrap_err(),
+            SignalProtocolError::InvalidPreKeyId
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn optional_one_time_prekey() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        let mut csprng = OsRng;
+        let bob_signed_pre_key_pair = KeyPair::generate(&mut csprng);
+
+        let bob_signed_pre_key_public = bob_signed_pre_key_pair.public_key.serialize();
+        let bob_signed_pre_key_signature = bob_store
+            .get_identity_key_pair(None)
+            .await?
+            .calculate_signature(&bob_signed_pre_key_public, &mut csprng)?;
+
+        let signed_pre_key_id = 22;
+
+        let bob_pre_key_bundle = PreKeyBundle::new(
+            bob_store.get_local_registration_id(None).await?,
+            DeviceId::try_from(1)?, // device id
+            None,                   // no pre key
+            signed_pre_key_id,      // signed pre key id
+            bob_signed_pre_key_pair.public_key,
+            bob_signed_pre_key_signature.to_vec(),
+            *bob_store.get_identity_key_pair(None).await?.identity_key(),
+        )?;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        assert_eq!(
+            alice_store
+                .load_session(&bob_address, None)
+                .await?
+                .unwrap()
+                .session_state()?
+                .session_version()?,
+            3
+        );
+
+        let original_message = "L'homme est condamné à être libre";
+
+        let outgoing_message = encrypt(&mut alice_store, &bob_address, original_message).await?;
+
+        assert_eq!(
+            outgoing_message.message_type(),
+            CiphertextMessageType::PreKey
+        );
+
+        let incoming_message = CiphertextMessage::PreKeySignalMessage(
+            PreKeySignalMessage::try_from(outgoing_message.serialize())?,
+        );
+
+        bob_store
+            .save_signed_pre_key(
+                signed_pre_key_id,
+                &SignedPreKeyRecord::new(
+                    signed_pre_key_id,
+                    /*timestamp*/ 42,
+                    &bob_signed_pre_key_pair,
+                    &bob_signed_pre_key_signature,
+                ),
+                None,
+            )
+            .await?;
+
+        let ptext = decrypt(&mut bob_store, &alice_address, &incoming_message).await?;
+
+        assert_eq!(String::from_utf8(ptext).unwrap(), original_message);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn prekey_bundle_report() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        let mut csprng = OsRng;
+        let bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+
+        let report = process_prekey_bundle_with_report(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bundle,
+            &SessionConfig::default(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+        assert_eq!(
+            report,
+            PreKeyBundleReport {
+                pre_key_id: bundle.pre_key_id()?,
+                signed_pre_key_id: bundle.signed_pre_key_id()?,
+                session_version: 3,
+                identity_change: IdentityChange::NewIdentity,
+            }
+        );
+        assert!(report.pre_key_id.is_some());
+
+        // The same identity in a bundle without a one-time pre-key.
+        let bundle_without_pre_key = PreKeyBundle::new(
+            bundle.registration_id()?,
+            bundle.device_id()?,
+            None,
+            bundle.signed_pre_key_id()?,
+            bundle.signed_pre_key_public()?,
+            bundle.signed_pre_key_signature()?.to_vec(),
+            *bundle.identity_key()?,
+        )?;
+        let report = process_prekey_bundle_with_report(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bundle_without_pre_key,
+            &SessionConfig::default(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+        assert_eq!(report.pre_key_id, None);
+        assert_eq!(report.signed_pre_key_id, bundle.signed_pre_key_id()?);
+        assert_eq!(report.identity_change, IdentityChange::Unchanged);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn registration_id_at_limit_reaches_the_recipient() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
+
+        let mut csprng = OsRng;
+        let mut alice_store = InMemSignalProtocolStore::new(
+            IdentityKeyPair::generate(&mut csprng),
+            RegistrationId::try_from(16383)?,
+        )?;
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        let bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bundle,
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        let message = encrypt(&mut alice_store, &bob_address, "hi").await?;
+        match &message {
+            CiphertextMessage::PreKeySignalMessage(m) => {
+                assert_eq!(u32::from(m.registration_id()), 16383)
+            }
+            _ => panic!("expected a pre-key message"),
+        }
+        decrypt(&mut bob_store, &alice_address, &message).await?;
+        assert_eq!(
+            u32::from(
+                remote_registration_id(&alice_address, &mut bob_store.session_store, None).await?
+            ),
+            16383
+        );
+
+        assert!(matches!(
+            RegistrationId::try_from(16384),
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn stored_session_with_large_remote_registration_id_still_encrypts(
+) -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let (alice_session, bob_session) = initialize_sessions_v3()?;
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
+
+        // Sessions stored before registration ids were range checked can hold larger ids.
+        // Appending a field overrides the earlier value: remote_registration_id (10) = 20000.
+        let mut alice_session_bytes = alice_session.serialize()?;
+        alice_session_bytes.extend_from_slice(&[0x50, 0xA0, 0x9C, 0x01]);
+        let alice_session = SessionState::deserialize(&alice_session_bytes)?;
+        assert_eq!(alice_session.remote_registration_id()?, 20000);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+        alice_store
+            .store_session(&bob_address, &SessionRecord::new(alice_session), None)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &SessionRecord::new(bob_session), None)
+            .await?;
+
+        let outgoing = message_encrypt_with_details(
+            b"hi",
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            None,
+        )
+        .await?;
+        assert_eq!(outgoing.remote_registration_id, 20000);
+        assert_eq!(
+            remote_registration_id(&bob_address, &mut alice_store.session_store, None).await?,
+            20000
+        );
+
+        let incoming =
+            CiphertextMessage::SignalMessage(SignalMessage::try_from(&outgoing.serialized[..])?);
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &incoming).await?,
+            b"hi".to_vec()
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn encrypt_with_details() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        let mut csprng = OsRng;
+        let bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bundle,
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        let outgoing = message_encrypt_with_details(
+            b"hi",
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            None,
+        )
+        .await?;
+        assert_eq!(outgoing.message_type, CiphertextMessageType::PreKey);
+        assert_eq!(
+            outgoing.remote_registration_id,
+            u32::from(bundle.registration_id()?)
+        );
+
+        let incoming = CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+            &outgoing.serialized[..],
+        )?);
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &incoming).await?,
+            b"hi".to_vec()
+        );
+
+        let reply = message_encrypt_with_details(
+            b"hello",
+            &alice_address,
+            &mut bob_store.session_store,
+            &mut bob_store.identity_store,
+            None,
+        )
+        .await?;
+        assert_eq!(reply.message_type, CiphertextMessageType::Whisper);
+        assert_eq!(
+            reply.remote_registration_id,
+            u32::from(alice_store.get_local_registration_id(None).await?)
+        );
+
+        let incoming =
+            CiphertextMessage::SignalMessage(SignalMessage::try_from(&reply.serialized[..])?);
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &incoming).await?,
+            b"hello".to_vec()
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn basic_session_v3() -> Result<(), SignalProtocolError> {
+    let (alice_session, bob_session) = initialize_sessions_v3()?;
+    let alice_session_record = SessionRecord::new(alice_session);
+    let bob_session_record = SessionRecord::new(bob_session);
+    run_session_interaction(alice_session_record, bob_session_record)?;
+    Ok(())
+}
+
+#[test]
+fn session_record_serialization_round_trip() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let (alice_session, bob_session) = initialize_sessions_v3()?;
+        let alice_session_record = SessionRecord::new(alice_session);
+        let bob_session_record = SessionRecord::new(bob_session);
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        alice_store
+            .store_session(&bob_address, &alice_session_record, None)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session_record, None)
+            .await?;
+
+        for i in 0..5 {
+            let msg = encrypt(&mut alice_store, &bob_address, &format!("ping {}", i)).await?;
+            decrypt(&mut bob_store, &alice_address, &msg).await?;
+            let msg = encrypt(&mut bob_store, &alice_address, &format!("pong {}", i)).await?;
+            decrypt(&mut alice_store, &bob_address, &msg).await?;
+        }
+
+        for (store, address) in &[(&alice_store, &bob_address), (&bob_store, &alice_address)] {
+            let record = store
+                .load_session(address, None)
+                .await?
+                .expect("session found");
+            let serialized = record.serialize()?;
+            assert_eq!(
+                SessionRecord::deserialize(&serialized)?.serialize()?,
+                serialized
+            );
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn message_key_limits() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let (alice_session, bob_session) = initialize_sessions_v3()?;
+        let alice_session_record = SessionRecord::new(alice_session);
+        let bob_session_record = SessionRecord::new(bob_session);
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        alice_store
+            .store_session(&bob_address, &alice_session_record, None)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session_record, None)
+            .await?;
+
+        const MAX_MESSAGE_KEYS: usize = 2000; // same value as in library
+        const TOO_MANY_MESSAGES: usize = MAX_MESSAGE_KEYS + 300;
+
+        let mut inflight = Vec::with_capacity(TOO_MANY_MESSAGES);
+
+        for i in 0..TOO_MANY_MESSAGES {
+            inflight
+                .push(encrypt(&mut alice_store, &bob_address, &format!("It's over {}", i)).await?);
+        }
+
+        assert_eq!(
+            String::from_utf8(decrypt(&mut bob_store, &alice_address, &inflight[1000]).await?)
+                .unwrap(),
+            "It's over 1000"
+        );
+        assert_eq!(
+            String::from_utf8(
+                decrypt(
+                    &mut bob_store,
+                    &alice_address,
+                    &inflight[TOO_MANY_MESSAGES - 1]
+                )
+                .await?
+            )
+            .unwrap(),
+            format!("It's over {}", TOO_MANY_MESSAGES - 1)
+        );
+
+        let err = decrypt(&mut bob_store, &alice_address, &inflight[5])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            SignalProtocolError::DuplicatedMessage {
+                current_iteration: 2300,
+                counter: 5
+            }
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn configured_message_key_limits() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let (alice_session, bob_session) = initialize_sessions_v3()?;
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        alice_store
+            .store_session(&bob_address, &SessionRecord::new(alice_session), None)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &SessionRecord::new(bob_session), None)
+            .await?;
+
+        const MAX_SKIPPED: usize = 10;
+        let config = SessionConfig {
+            max_skipped_message_keys: MAX_SKIPPED,
+            ..SessionConfig::default()
+        };
+
+        let mut inflight = Vec::new();
+        for i in 0..(3 * MAX_SKIPPED + 3) {
+            inflight
+                .push(encrypt(&mut alice_store, &bob_address, &format!("It's over {}", i)).await?);
+        }
+
+        // One past the limit is rejected without changing the session...
+        let err = decrypt_with_config(
+            &mut bob_store,
+            &alice_address,
+            &inflight[MAX_SKIPPED + 1],
+            &config,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, SignalProtocolError::InvalidMessage(_)));
+
+        // ...but exactly at the limit is fine, and every skipped key is kept.
+        assert_eq!(
+            decrypt_with_config(
+                &mut bob_store,
+                &alice_address,
+                &inflight[MAX_SKIPPED],
+                &config
+            )
+            .await?,
+            format!("It's over {}", MAX_SKIPPED).into_bytes()
+        );
+        assert_eq!(
+            decrypt_with_config(&mut bob_store, &alice_address, &inflight[0], &config).await?,
+            b"It's over 0".to_vec()
+        );
+
+        // Skipping another full window evicts the oldest skipped keys.
+        assert_eq!(
+            decrypt_with_config(
+                &mut bob_store,
+                &alice_address,
+                &inflight[2 * MAX_SKIPPED + 1],
+                &config
+            )
+            .await?,
+            format!("It's over {}", 2 * MAX_SKIPPED + 1).into_bytes()
+        );
+        let err = decrypt_with_config(&mut bob_store, &alice_address, &inflight[1], &config)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            SignalProtocolError::DuplicatedMessage {
+                current_iteration: 2 * MAX_SKIPPED as u32 + 2,
+                counter: 1
+            }
+        );
+        assert_eq!(
+            decrypt_with_config(
+                &mut bob_store,
+                &alice_address,
+                &inflight[MAX_SKIPPED + 1],
+                &config
+            )
+            .await?,
+            format!("It's over {}", MAX_SKIPPED + 1).into_bytes()
+        );
+
+        // The default configuration accepts what the lower limit rejected.
+        assert_eq!(
+            decrypt(
+                &mut bob_store,
+                &alice_address,
+                &inflight[3 * MAX_SKIPPED + 2]
+            )
+            .await?,
+            format!("It's over {}", 3 * MAX_SKIPPED + 2).into_bytes()
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn archive_and_promote_session_state() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let (alice_session, bob_session) = initialize_sessions_v3()?;
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        alice_store
+            .store_session(&bob_address, &SessionRecord::new(alice_session), None)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &SessionRecord::new(bob_session), None)
+            .await?;
+
+        let first = encrypt(&mut alice_store, &bob_address, "first").await?;
+        let second = encrypt(&mut alice_store, &bob_address, "second").await?;
+        let third = encrypt(&mut alice_store, &bob_address, "third").await?;
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &first).await?,
+            b"first".to_vec()
+        );
+
+        let mut record = bob_store
+            .load_session(&alice_address, None)
+            .await?
+            .expect("session found");
+        let session_id = record.session_state()?.alice_base_key()?.to_vec();
+
+        record.archive_current_state()?;
+        assert!(record.session_state().is_err());
+        assert_eq!(record.previous_session_states()?.count(), 1);
+        assert!(record.promote_old_session(1).is_err());
+
+        record.promote_old_session(0)?;
+        assert_eq!(record.session_state()?.alice_base_key()?, &session_id[..]);
+        assert_eq!(record.previous_session_states()?.count(), 0);
+        bob_store
+            .store_session(&alice_address, &record, None)
+            .await?;
+
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &second).await?,
+            b"second".to_vec()
+        );
+
+        // An archived state is still tried (and promoted) on decryption.
+        let mut record = bob_store
+            .load_session(&alice_address, None)
+            .await?
+            .expect("session found");
+        record.archive_current_state()?;
+        bob_store
+            .store_session(&alice_address, &record, None)
+            .await?;
+
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &third).await?,
+            b"third".to_vec()
+        );
+        let record = bob_store
+            .load_session(&alice_address, None)
+            .await?
+            .expect("session found");
+        assert_eq!(record.session_state()?.alice_base_key()?, &session_id[..]);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn decryption_report_identifies_state() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let (alice_session, bob_session) = initialize_sessions_v3()?;
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        alice_store
+            .store_session(&bob_address, &SessionRecord::new(alice_session), None)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &SessionRecord::new(bob_session), None)
+            .await?;
+
+        let first = encrypt(&mut alice_store, &bob_address, "first").await?;
+        let second = encrypt(&mut alice_store, &bob_address, "second").await?;
+
+        let (ptext, report) = decrypt_with_report(&mut bob_store, &alice_address, &first).await?;
+        assert_eq!(ptext, b"first".to_vec());
+        assert_eq!(
+            report,
+            DecryptionReport {
+                state_used: SessionStateUsed::Current,
+                session_version: 3,
+                counter: 0,
+            }
+        );
+
+        let mut record = bob_store
+            .load_session(&alice_address, None)
+            .await?
+            .expect("session found");
+        record.archive_current_state()?;
+        bob_store
+            .store_session(&alice_address, &record, None)
+            .await?;
+
+        let (ptext, report) = decrypt_with_report(&mut bob_store, &alice_address, &second).await?;
+        assert_eq!(ptext, b"second".to_vec());
+        assert_eq!(report.state_used, SessionStateUsed::Previous(0));
+        assert_eq!(report.session_version, 3);
+        assert_eq!(report.counter, 1);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn batch_decryption_interleaves_prekey_messages() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        let first_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &first_bundle,
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        let hello = encrypt(&mut alice_store, &bob_address, "hello").await?;
+        decrypt(&mut bob_store, &alice_address, &hello).await?;
+        let reply = encrypt(&mut bob_store, &alice_address, "hi").await?;
+        decrypt(&mut alice_store, &bob_address, &reply).await?;
+
+        let whisper1 = encrypt(&mut alice_store, &bob_address, "whisper 1").await?;
+        let whisper2 = encrypt(&mut alice_store, &bob_address, "whisper 2").await?;
+        assert_eq!(whisper1.message_type(), CiphertextMessageType::Whisper);
+
+        // Alice starts a new session; Bob sees its messages interleaved with the old one's.
+        let second_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &second_bundle,
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        let prekey1 = encrypt(&mut alice_store, &bob_address, "prekey 1").await?;
+        let prekey2 = encrypt(&mut alice_store, &bob_address, "prekey 2").await?;
+        assert_eq!(prekey1.message_type(), CiphertextMessageType::PreKey);
+
+        let duplicate =
+            CiphertextMessage::SignalMessage(SignalMessage::try_from(whisper1.serialize())?);
+        let batch = vec![whisper1, prekey1, duplicate, whisper2, prekey2];
+        let results = message_decrypt_batch(
+            &batch,
+            &alice_address,
+            &mut SessionStores {
+                session_store: &mut bob_store.session_store,
+                identity_store: &mut bob_store.identity_store,
+                pre_key_store: &mut bob_store.pre_key_store,
+                signed_pre_key_store: &mut bob_store.signed_pre_key_store,
+            },
+            &SessionConfig::default(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].as_ref().unwrap(), b"whisper 1");
+        assert_eq!(results[1].as_ref().unwrap(), b"prekey 1");
+        assert!(matches!(
+            results[2],
+            Err(SignalProtocolError::DuplicatedMessage { .. })
+        ));
+        assert_eq!(results[3].as_ref().unwrap(), b"whisper 2");
+        assert_eq!(results[4].as_ref().unwrap(), b"prekey 2");
+
+        let second_pre_key_id = second_bundle.pre_key_id()?.expect("has pre-key");
+        assert!(bob_store
+            .get_pre_key(second_pre_key_id, None)
+            .await
+            .is_err());
+
+        // The stored session is the new one, and works in both directions.
+        let reply = encrypt(&mut bob_store, &alice_address, "got them").await?;
+        assert_eq!(reply.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &reply).await?,
+            b"got them".to_vec()
+        );
+
+        // A sender without a session only fails its non-prekey messages.
+        let carol_address = ProtocolAddress::new("+14151111113".to_owned(), DeviceId::try_from(1)?);
+        let results = message_decrypt_batch(
+            &[reply],
+            &carol_address,
+            &mut SessionStores {
+                session_store: &mut bob_store.session_store,
+                identity_store: &mut bob_store.identity_store,
+                pre_key_store: &mut bob_store.pre_key_store,
+                signed_pre_key_store: &mut bob_store.signed_pre_key_store,
+            },
+            &SessionConfig::default(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+        assert!(matches!(
+            results[..],
+            [Err(SignalProtocolError::SessionNotFound)]
+        ));
+        assert!(bob_store
+            .load_session(&carol_address, None)
+            .await?
+            .is_none());
+
+        Ok(())
+    })
+}
+
+/// Decrypts `msg` with [message_decrypt] and with [message_decrypt_transactional], both applying
+/// the collected writes one at a time and committing them together, and checks that all three
+/// leave the store in exactly the same state. Returns the updated store.
+async fn decrypt_all_ways(
+    store: &InMemSignalProtocolStore,
+    remote_address: &ProtocolAddress,
+    msg: &CiphertextMessage,
+    seed: u64,
+) -> Result<InMemSignalProtocolStore, SignalProtocolError> {
+    let mut immediate = store.clone();
+    let ptext = message_decrypt(
+        msg,
+        remote_address,
+        &mut immediate.session_store,
+        &mut immediate.identity_store,
+        &mut immediate.pre_key_store,
+        &mut immediate.signed_pre_key_store,
+        &mut StdRng::seed_from_u64(seed),
+        None,
+    )
+    .await?;
+
+    let mut fallback = store.clone();
+    let (fallback_ptext, transaction) = message_decrypt_transactional(
+        msg,
+        remote_address,
+        &ReadOnlySessionStores {
+            session_store: &fallback.session_store,
+            identity_store: &fallback.identity_store,
+            pre_key_store: &fallback.pre_key_store,
+            signed_pre_key_store: &fallback.signed_pre_key_store,
+        },
+        &SessionConfig::default(),
+        &mut StdRng::seed_from_u64(seed),
+        None,
+    )
+    .await?;
+    assert_eq!(fallback_ptext, ptext);
+    transaction
+        .apply(
+            &mut fallback.session_store,
+            &mut fallback.identity_store,
+            &mut fallback.pre_key_store,
+            None,
+        )
+        .await?;
+
+    let mut committed = store.clone();
+    let (committed_ptext, transaction) = message_decrypt_transactional(
+        msg,
+        remote_address,
+        &ReadOnlySessionStores {
+            session_store: &committed.session_store,
+            identity_store: &committed.identity_store,
+            pre_key_store: &committed.pre_key_store,
+            signed_pre_key_store: &committed.signed_pre_key_store,
+        },
+        &SessionConfig::default(),
+        &mut StdRng::seed_from_u64(seed),
+        None,
+    )
+    .await?;
+    assert_eq!(committed_ptext, ptext);
+    transaction.commit(&mut committed, None).await?;
+
+    for other in &[&fallback, &committed] {
+        assert_eq!(
+            other
+                .load_session(remote_address, None)
+                .await?
+                .expect("session stored")
+                .serialize()?,
+            immediate
+                .load_session(remote_address, None)
+                .await?
+                .expect("session stored")
+                .serialize()?
+        );
+        assert_eq!(
+            other
+                .get_identity(remote_address, None)
+                .await?
+                .map(|k| k.serialize()),
+            immediate
+                .get_identity(remote_address, None)
+                .await?
+                .map(|k| k.serialize())
+        );
+        if let CiphertextMessage::PreKeySignalMessage(m) = msg {
+            if let Some(pre_key_id) = m.pre_key_id() {
+                assert!(other.get_pre_key(pre_key_id, None).await.is_err());
+                assert!(immediate.get_pre_key(pre_key_id, None).await.is_err());
+            }
+        }
+    }
+
+    Ok(immediate)
+}
+
+#[test]
+fn transactional_decryption_matches_immediate_writes() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        let hello = encrypt(&mut alice_store, &bob_address, "hello").await?;
+        assert_eq!(hello.message_type(), CiphertextMessageType::PreKey);
+
+        // Nothing is written until the transaction is applied, and the writes are in the order
+        // message_decrypt makes them.
+        let (_, transaction) = message_decrypt_transactional(
+            &hello,
+            &alice_address,
+            &ReadOnlySessionStores {
+                session_store: &bob_store.session_store,
+                identity_store: &bob_store.identity_store,
+                pre_key_store: &bob_store.pre_key_store,
+                signed_pre_key_store: &bob_store.signed_pre_key_store,
+            },
+            &SessionConfig::default(),
+            &mut csprng,
+            None,
+        )
+        .await?;
+        assert!(matches!(
+            transaction.ops(),
+            [
+                StoreOp::SaveIdentity { .. },
+                StoreOp::StoreSession { .. },
+                StoreOp::RemovePreKey(_)
+            ]
+        ));
+        assert!(bob_store
+            .load_session(&alice_address, None)
+            .await?
+            .is_none());
+        assert!(bob_store
+            .get_identity(&alice_address, None)
+            .await?
+            .is_none());
+
+        let mut bob_store = decrypt_all_ways(&bob_store, &alice_address, &hello, 1).await?;
+
+        let reply = encrypt(&mut bob_store, &alice_address, "hi").await?;
+        decrypt(&mut alice_store, &bob_address, &reply).await?;
+        let whisper = encrypt(&mut alice_store, &bob_address, "whisper").await?;
+        assert_eq!(whisper.message_type(), CiphertextMessageType::Whisper);
+
+        let bob_store = decrypt_all_ways(&bob_store, &alice_address, &whisper, 2).await?;
+
+        // A message that fails to decrypt collects nothing.
+        assert!(matches!(
+            message_decrypt_transactional(
+                &whisper,
+                &alice_address,
+                &ReadOnlySessionStores {
+                    session_store: &bob_store.session_store,
+                    identity_store: &bob_store.identity_store,
+                    pre_key_store: &bob_store.pre_key_store,
+                    signed_pre_key_store: &bob_store.signed_pre_key_store,
+                },
+                &SessionConfig::default(),
+                &mut csprng,
+                None,
+            )
+            .await,
+            Err(SignalProtocolError::DuplicatedMessage { .. })
+        ));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn decryption_is_deterministic_from_a_snapshot() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        for round in 0..20 {
+            // A random number of messages, delivered in a random order, followed by a reply so
+            // that the next round needs a new ratchet step.
+            let mut messages = Vec::new();
+            for i in 0..csprng.gen_range(1, 5) {
+                let ptext = format!("round {} message {}", round, i);
+                let ctext = encrypt(&mut alice_store, &bob_address, &ptext).await?;
+                messages.push((ptext, ctext));
+            }
+            messages.shuffle(&mut csprng);
+
+            for (ptext, ctext) in &messages {
+                let seed = csprng.gen::<u64>();
+                let snapshot = bob_store.serialize()?;
+
+                let first = message_decrypt(
+                    ctext,
+                    &alice_address,
+                    &mut bob_store.session_store,
+                    &mut bob_store.identity_store,
+                    &mut bob_store.pre_key_store,
+                    &mut bob_store.signed_pre_key_store,
+                    &mut StdRng::seed_from_u64(seed),
+                    None,
+                )
+                .await?;
+                let after_first = bob_store.serialize()?;
+
+                // Roll back and do it again with the same randomness.
+                bob_store = InMemSignalProtocolStore::deserialize(&snapshot)?;
+                assert_eq!(bob_store.serialize()?, snapshot);
+                let second = message_decrypt(
+                    ctext,
+                    &alice_address,
+                    &mut bob_store.session_store,
+                    &mut bob_store.identity_store,
+                    &mut bob_store.pre_key_store,
+                    &mut bob_store.signed_pre_key_store,
+                    &mut StdRng::seed_from_u64(seed),
+                    None,
+                )
+                .await?;
+
+                assert_eq!(first, ptext.as_bytes(), "seed {}", seed);
+                assert_eq!(second, first, "seed {}", seed);
+                assert_eq!(bob_store.serialize()?, after_first, "seed {}", seed);
+            }
+
+            let reply = encrypt(&mut bob_store, &alice_address, "reply").await?;
+            decrypt(&mut alice_store, &bob_address, &reply).await?;
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn chain_counters() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let (alice_session, bob_session) = initialize_sessions_v3()?;
+
+        assert!(alice_session.has_usable_sender_chain()?);
+        assert_eq!(alice_session.sender_chain_counter()?, Some(0));
+        assert_eq!(alice_session.number_of_receiver_chains()?, 1);
+        assert_eq!(bob_session.sender_chain_counter()?, Some(0));
+        assert_eq!(bob_session.number_of_receiver_chains()?, 0);
+
+        let alice_ratchet_key = alice_session.sender_ratchet_key()?;
+        assert_eq!(
+            bob_session.receiver_chain_counter(&alice_ratchet_key)?,
+            None
+        );
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        alice_store
+            .store_session(&bob_address, &SessionRecord::new(alice_session), None)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &SessionRecord::new(bob_session), None)
+            .await?;
+
+        let mut messages = Vec::new();
+        for i in 0..3 {
+            messages.push(encrypt(&mut alice_store, &bob_address, &format!("{}", i)).await?);
+        }
+        let alice_record = alice_store
+            .load_session(&bob_address, None)
+            .await?
+            .expect("session found");
+        assert_eq!(
+            alice_record.session_state()?.sender_chain_counter()?,
+            Some(3)
+        );
+
+        // Skipping ahead moves the expected counter past the skipped messages.
+        decrypt(&mut bob_store, &alice_address, &messages[2]).await?;
+        let bob_record = bob_store
+            .load_session(&alice_address, None)
+            .await?
+            .expect("session found");
+        let bob_state = bob_record.session_state()?;
+        assert_eq!(bob_state.number_of_receiver_chains()?, 1);
+        assert_eq!(
+            bob_state.receiver_chain_counter(&alice_ratchet_key)?,
+            Some(3)
+        );
+        assert_eq!(
+            bob_state.receiver_chain_counter(&KeyPair::generate(&mut OsRng).public_key)?,
+            None
+        );
+
+        decrypt(&mut bob_store, &alice_address, &messages[0]).await?;
+        let bob_record = bob_store
+            .load_session(&alice_address, None)
+            .await?
+            .expect("session found");
+        assert_eq!(
+            bob_record
+                .session_state()?
+                .receiver_chain_counter(&alice_ratchet_key)?,
+            Some(3)
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn duplicate_message_reports_counter() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let (alice_session, bob_session) = initialize_sessions_v3()?;
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        alice_store
+            .store_session(&bob_address, &SessionRecord::new(alice_session), None)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &SessionRecord::new(bob_session), None)
+            .await?;
+
+        for i in 0..3 {
+            let message = encrypt(&mut alice_store, &bob_address, &format!("{}", i)).await?;
+            decrypt(&mut bob_store, &alice_address, &message).await?;
+        }
+
+        let message = encrypt(&mut alice_store, &bob_address, "replayed").await?;
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &message).await?,
+            b"replayed".to_vec()
+        );
+
+        let err = decrypt(&mut bob_store, &alice_address, &message)
+            .await
+            .unwrap_err();
+        match err {
+            SignalProtocolError::DuplicatedMessage {
+                current_iteration,
+                counter,
+            } => {
+                assert_eq!(current_iteration, 4);
+                assert_eq!(counter, 3);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn pre_key_bundle_validation() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let bob_identity = IdentityKeyPair::generate(&mut csprng);
+        let mallory_identity = IdentityKeyPair::generate(&mut csprng);
+
+        let pre_key_pair = KeyPair::generate(&mut csprng);
+        let signed_pre_key_pair = KeyPair::generate(&mut csprng);
+        let signed_pre_key_public = signed_pre_key_pair.public_key.serialize();
+        let signature = bob_identity.calculate_signature(&signed_pre_key_public, &mut csprng)?;
+        let mallory_signature =
+            mallory_identity.calculate_signature(&signed_pre_key_public, &mut csprng)?;
+
+        let bundle = PreKeyBundle::new_validated(
+            RegistrationId::try_from(5)?,
+            DeviceId::try_from(1)?,
+            Some((31337, pre_key_pair.public_key)),
+            22,
+            signed_pre_key_pair.public_key,
+            signature.to_vec(),
+            *bob_identity.identity_key(),
+        )?;
+        assert_eq!(bundle.pre_key_id()?, Some(31337));
+        assert_eq!(bundle.pre_key_public()?, Some(pre_key_pair.public_key));
+
+        // The pre-key id and key are given together, so they are either both present or both
+        // absent.
+        let bundle = PreKeyBundle::new_validated(
+            RegistrationId::try_from(5)?,
+            DeviceId::try_from(1)?,
+            None,
+            22,
+            signed_pre_key_pair.public_key,
+            signature.to_vec(),
+            *bob_identity.identity_key(),
+        )?;
+        assert_eq!(bundle.pre_key_id()?, None);
+        assert_eq!(bundle.pre_key_public()?, None);
+
+        assert!(matches!(
+            PreKeyBundle::new_validated(
+                RegistrationId::try_from(5)?,
+                DeviceId::try_from(1)?,
+                None,
+                22,
+                signed_pre_key_pair.public_key,
+                mallory_signature.to_vec(),
+                *bob_identity.identity_key(),
+            ),
+            Err(SignalProtocolError::SignatureValidationFailed)
+        ));
+
+        // Without validation, a bad signature is only caught when the bundle is processed.
+        let bundle = PreKeyBundle::new(
+            RegistrationId::try_from(5)?,
+            DeviceId::try_from(1)?,
+            None,
+            22,
+            signed_pre_key_pair.public_key,
+            mallory_signature.to_vec(),
+            *bob_identity.identity_key(),
+        )?;
+        assert!(matches!(
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bundle,
+                &mut csprng,
+                None,
+            )
+            .await,
+            Err(SignalProtocolError::SignatureValidationFailed)
+        ));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn restricted_message_versions() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+        let (alice_session, bob_session) = initialize_sessions_v3()?;
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
+
+        alice_store
+            .store_session(&bob_address, &SessionRecord::new(alice_session), None)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &SessionRecord::new(bob_session), None)
+            .await?;
 
-        assert!(bob_store.get_pre_key(pre_key_id, None).await.is_ok());
-        let outgoing_message = encrypt(&mut alice_store, &bob_address, original_message).await?;
+        let newer_only = SessionConfig {
+            accepted_versions: 4..=4,
+            ..SessionConfig::default()
+        };
+        let older_only = SessionConfig {
+            accepted_versions: 2..=2,
+            ..SessionConfig::default()
+        };
 
+        let message = encrypt(&mut alice_store, &bob_address, "v3").await?;
         assert_eq!(
-            outgoing_message.message_type(),
-            CiphertextMessageType::PreKey
+            SignalMessage::try_from_with_config(message.serialize(), &newer_only).unwrap_err(),
+            SignalProtocolError::LegacyCiphertextVersion(3)
         );
-
-        let outgoing_message = outgoing_message.serialize().to_vec();
-
-        let mut corrupted_message: Vec<u8> = outgoing_message.clone();
-        corrupted_message[outgoing_message.len() - 10] ^= 1;
-
-        let incoming_message = CiphertextMessage::PreKeySignalMessage(
-            PreKeySignalMessage::try_from(corrupted_message.as_slice())?,
+        assert_eq!(
+            SignalMessage::try_from_with_config(message.serialize(), &older_only).unwrap_err(),
+            SignalProtocolError::UnrecognizedMessageVersion(3)
         );
+        assert!(SignalMessage::try_from_with_config(
+            message.serialize(),
+            &SessionConfig::default()
+        )
+        .is_ok());
 
-        assert!(decrypt(&mut bob_store, &alice_address, &incoming_message)
-            .await
-            .is_err());
-        assert!(bob_store.get_pre_key(pre_key_id, None).await.is_ok());
-
-        let incoming_message = CiphertextMessage::PreKeySignalMessage(
-            PreKeySignalMessage::try_from(outgoing_message.as_slice())?,
+        assert_eq!(
+            decrypt_with_config(&mut bob_store, &alice_address, &message, &newer_only)
+                .await
+                .unwrap_err(),
+            SignalProtocolError::LegacyCiphertextVersion(3)
+        );
+        // The rejected message did not disturb the session.
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &message).await?,
+            b"v3".to_vec()
         );
 
-        let ptext = decrypt(&mut bob_store, &alice_address, &incoming_message).await?;
-
-        assert_eq!(String::from_utf8(ptext).unwrap(), original_message);
+        let carol_address = ProtocolAddress::new("+14157777777".to_owned(), DeviceId::try_from(1)?);
+        let mut carol_store = support::test_in_memory_protocol_store();
+        let carol_bundle = create_pre_key_bundle(&mut carol_store, &mut csprng).await?;
         assert_eq!(
-            bob_store.get_pre_key(pre_key_id, None).await.unwrap_err(),
-            SignalProtocolError::InvalidPreKeyId
+            process_prekey_bundle_with_config(
+                &carol_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &carol_bundle,
+                &newer_only,
+                &mut csprng,
+                None,
+            )
+            .await
+            .unwrap_err(),
+            SignalProtocolError::LegacyCiphertextVersion(3)
         );
+        assert!(alice_store
+            .load_session(&carol_address, None)
+            .await?
+            .is_none());
 
         Ok(())
     })
 }
 
 #[test]
-fn optional_one_time_prekey() -> Result<(), SignalProtocolError> {
-    block_on(async {
-        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1);
-
-        let mut alice_store = support::test_in_memory_protocol_store();
-        let mut bob_store = support::test_in_memory_protocol_store();
-
-        let mut csprng = OsRng;
-        let bob_signed_pre_key_pair = KeyPair::generate(&mut csprng);
-
-        let bob_signed_pre_key_public = bob_signed_pre_key_pair.public_key.serialize();
-        let bob_signed_pre_key_signature = bob_store
-            .get_identity_key_pair(None)
-            .await?
-            .private_key()
-            .calculate_signature(&bob_signed_pre_key_public, &mut csprng)?;
-
-        let signed_pre_key_id = 22;
-
-        let bob_pre_key_bundle = PreKeyBundle::new(
-            bob_store.get_local_registration_id(None).await?,
-            1, // device id
-            None,
-            None,              // no pre key
-            signed_pre_key_id, // signed pre key id
-            bob_signed_pre_key_pair.public_key,
-            bob_signed_pre_key_signature.to_vec(),
-            *bob_store.get_identity_key_pair(None).await?.identity_key(),
-        )?;
+fn previous_session_state_limit() -> Result<(), SignalProtocolError> {
+    let mut states = Vec::new();
+    for _ in 0..4 {
+        states.push(initialize_sessions_v3()?.0);
+    }
+    let base_key = |state: &SessionState| state.alice_base_key().map(|k| k.to_vec());
 
-        process_prekey_bundle(
-            &bob_address,
-            &mut alice_store.session_store,
-            &mut alice_store.identity_store,
-            &bob_pre_key_bundle,
-            &mut csprng,
-            None,
-        )
-        .await?;
+    let mut record = SessionRecord::new(states[0].clone());
+    assert_eq!(record.max_previous_sessions(), 40);
+    record.set_max_previous_sessions(2);
+    for state in &states[1..] {
+        record.promote_state(state.clone())?;
+    }
 
-        assert_eq!(
-            alice_store
-                .load_session(&bob_address, None)
-                .await?
-                .unwrap()
-                .session_state()?
-                .session_version()?,
-            3
-        );
+    let previous = record
+        .previous_session_states()?
+        .map(base_key)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(previous, vec![base_key(&states[2])?, base_key(&states[1])?]);
 
-        let original_message = "L'homme est condamné à être libre";
+    record.set_max_previous_sessions(1);
+    assert_eq!(record.previous_session_states()?.count(), 1);
 
-        let outgoing_message = encrypt(&mut alice_store, &bob_address, original_message).await?;
+    let record = SessionRecord::deserialize(&record.serialize()?)?;
+    assert_eq!(record.max_previous_sessions(), 40);
+    assert_eq!(record.previous_session_states()?.count(), 1);
+    Ok(())
+}
 
-        assert_eq!(
-            outgoing_message.message_type(),
-            CiphertextMessageType::PreKey
-        );
+#[test]
+fn configured_previous_session_limit() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
 
-        let incoming_message = CiphertextMessage::PreKeySignalMessage(
-            PreKeySignalMessage::try_from(outgoing_message.serialize())?,
-        );
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = support::test_in_memory_protocol_store();
 
-        bob_store
-            .save_signed_pre_key(
-                signed_pre_key_id,
-                &SignedPreKeyRecord::new(
-                    signed_pre_key_id,
-                    /*timestamp*/ 42,
-                    &bob_signed_pre_key_pair,
-                    &bob_signed_pre_key_signature,
-                ),
+        let one_previous = SessionConfig {
+            max_previous_sessions: 1,
+            ..SessionConfig::default()
+        };
+
+        for (i, config) in [
+            &one_previous,
+            &one_previous,
+            &one_previous,
+            &SessionConfig::default(),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let bob_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+            process_prekey_bundle_with_config(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_bundle,
+                config,
+                &mut csprng,
                 None,
             )
             .await?;
 
-        let ptext = decrypt(&mut bob_store, &alice_address, &incoming_message).await?;
+            let message = encrypt(&mut alice_store, &bob_address, "hi").await?;
+            assert_eq!(
+                decrypt_with_config(&mut bob_store, &alice_address, &message, config).await?,
+                b"hi".to_vec()
+            );
 
-        assert_eq!(String::from_utf8(ptext).unwrap(), original_message);
+            // The limit is not stored, so a later default config lets the records grow again.
+            let expected_previous = if i < 3 { i.min(1) } else { 2 };
+            for (store, address) in &[(&alice_store, &bob_address), (&bob_store, &alice_address)] {
+                let record = store
+                    .load_session(address, None)
+                    .await?
+                    .expect("session found");
+                assert_eq!(record.previous_session_states()?.count(), expected_previous);
+            }
+        }
 
         Ok(())
     })
 }
 
 #[test]
-fn basic_session_v3() -> Result<(), SignalProtocolError> {
-    let (alice_session, bob_session) = initialize_sessions_v3()?;
-    let alice_session_record = SessionRecord::new(alice_session);
-    let bob_session_record = SessionRecord::new(bob_session);
-    run_session_interaction(alice_session_record, bob_session_record)?;
+fn remove_states_older_than() -> Result<(), SignalProtocolError> {
+    let mut states = Vec::new();
+    for _ in 0..5 {
+        states.push(initialize_sessions_v3()?.0);
+    }
+    for state in &states {
+        assert_eq!(state.creation_time()?, None);
+        assert_eq!(state.last_use_time()?, None);
+    }
+
+    // Oldest first: no times, created at 1000, created at 1000 but used at 3000, created at 2000.
+    states[1].set_creation_time(1000)?;
+    states[2].set_creation_time(1000)?;
+    states[2].set_last_use_time(3000)?;
+    states[3].set_creation_time(2000)?;
+    // The current state is older than everything else.
+    states[4].set_creation_time(500)?;
+
+    let mut record = SessionRecord::new(states[0].clone());
+    for state in &states[1..] {
+        record.promote_state(state.clone())?;
+    }
+
+    let record_bytes = record.serialize()?;
+    let mut record = SessionRecord::deserialize(&record_bytes)?;
+    assert_eq!(record.session_state()?.creation_time()?, Some(500));
+    assert_eq!(record.previous_session_states()?.count(), 4);
+
+    assert_eq!(record.remove_states_older_than(1000), 0);
+    assert_eq!(record.remove_states_older_than(2500), 2);
+
+    let remaining = record
+        .previous_session_states()?
+        .map(|state| Ok((state.creation_time()?, state.last_use_time()?)))
+        .collect::<Result<Vec<_>, SignalProtocolError>>()?;
+    assert_eq!(remaining, vec![(Some(1000), Some(3000)), (None, None)]);
+    assert_eq!(record.session_state()?.creation_time()?, Some(500));
+
+    assert_eq!(record.remove_states_older_than(u64::MAX), 1);
+    assert_eq!(record.previous_session_states()?.count(), 1);
+    assert!(record.session_state().is_ok());
     Ok(())
 }
 
 #[test]
-fn message_key_limits() -> Result<(), SignalProtocolError> {
+fn session_times_from_config() -> Result<(), SignalProtocolError> {
     block_on(async {
-        let (alice_session, bob_session) = initialize_sessions_v3()?;
-        let alice_session_record = SessionRecord::new(alice_session);
-        let bob_session_record = SessionRecord::new(bob_session);
-
-        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), 1);
+        let mut csprng = OsRng;
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let mut bob_store = support::test_in_memory_protocol_store();
 
-        alice_store
-            .store_session(&bob_address, &alice_session_record, None)
-            .await?;
-        bob_store
-            .store_session(&alice_address, &bob_session_record, None)
-            .await?;
+        let at = |now_ms| SessionConfig {
+            now_ms: Some(now_ms),
+            ..SessionConfig::default()
+        };
 
-        const MAX_MESSAGE_KEYS: usize = 2000; // same value as in library
-        const TOO_MANY_MESSAGES: usize = MAX_MESSAGE_KEYS + 300;
+        let bob_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        process_prekey_bundle_with_config(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_bundle,
+            &at(1000),
+            &mut csprng,
+            None,
+        )
+        .await?;
 
-        let mut inflight = Vec::with_capacity(TOO_MANY_MESSAGES);
+        let message = encrypt(&mut alice_store, &bob_address, "hi").await?;
+        decrypt_with_config(&mut bob_store, &alice_address, &message, &at(2000)).await?;
+        let reply = encrypt(&mut bob_store, &alice_address, "hello").await?;
+        decrypt_with_config(&mut alice_store, &bob_address, &reply, &at(3000)).await?;
 
-        for i in 0..TOO_MANY_MESSAGES {
-            inflight
-                .push(encrypt(&mut alice_store, &bob_address, &format!("It's over {}", i)).await?);
-        }
+        // Without a time, nothing is recorded.
+        let message = encrypt(&mut alice_store, &bob_address, "again").await?;
+        decrypt(&mut bob_store, &alice_address, &message).await?;
 
-        assert_eq!(
-            String::from_utf8(decrypt(&mut bob_store, &alice_address, &inflight[1000]).await?)
-                .unwrap(),
-            "It's over 1000"
-        );
-        assert_eq!(
-            String::from_utf8(
-                decrypt(
-                    &mut bob_store,
-                    &alice_address,
-                    &inflight[TOO_MANY_MESSAGES - 1]
-                )
-                .await?
-            )
-            .unwrap(),
-            format!("It's over {}", TOO_MANY_MESSAGES - 1)
-        );
+        let times = |record: SessionRecord| -> Result<_, SignalProtocolError> {
+            let state = record.session_state()?;
+            Ok((state.creation_time()?, state.last_use_time()?))
+        };
+        let alice_record = alice_store
+            .load_session(&bob_address, None)
+            .await?
+            .expect("session found");
+        assert_eq!(times(alice_record)?, (Some(1000), Some(3000)));
+        let bob_record = bob_store
+            .load_session(&alice_address, None)
+            .await?
+            .expect("session found");
+        assert_eq!(times(bob_record)?, (Some(2000), Some(2000)));
 
-        let err = decrypt(&mut bob_store, &alice_address, &inflight[5])
-            .await
-            .unwrap_err();
-        assert_eq!(err, SignalProtocolError::DuplicatedMessage(2300, 5));
         Ok(())
     })
 }
 
+#[test]
+fn session_record_accessors() -> Result<(), SignalProtocolError> {
+    let (mut alice_session, _) = initialize_sessions_v3()?;
+    alice_session.set_local_registration_id(RegistrationId::try_from(123)?)?;
+    alice_session.set_remote_registration_id(RegistrationId::try_from(456)?)?;
+    alice_session.set_alice_base_key(&[5u8; 33])?;
+    let remote_identity = alice_session.remote_identity_key()?;
+    assert!(remote_identity.is_some());
+
+    let record = SessionRecord::new(alice_session);
+    assert_eq!(u32::from(record.local_registration_id()?), 123);
+    assert_eq!(u32::from(record.remote_registration_id()?), 456);
+    assert_eq!(record.alice_base_key()?, &[5u8; 33][..]);
+    assert_eq!(record.session_version()?, 3);
+    assert_eq!(record.remote_identity_key()?, remote_identity);
+
+    let fresh = SessionRecord::new_fresh();
+    assert_eq!(
+        fresh.local_registration_id().unwrap_err(),
+        SignalProtocolError::InvalidState("local_registration_id", "No session".to_string())
+    );
+    assert!(fresh.remote_registration_id().is_err());
+    assert!(fresh.alice_base_key().is_err());
+    assert!(fresh.session_version().is_err());
+    assert!(fresh.remote_identity_key().is_err());
+    Ok(())
+}
+
 fn run_session_interaction(
     alice_session: SessionRecord,
     bob_session: SessionRecord,
@@ -732,8 +2113,8 @@ fn run_session_interaction(
     block_on(async {
         use rand::seq::SliceRandom;
 
-        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), 1);
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let mut bob_store = support::test_in_memory_protocol_store();
@@ -914,8 +2295,8 @@ fn basic_simultaneous_initiate() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1);
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let mut bob_store = support::test_in_memory_protocol_store();
@@ -1056,8 +2437,8 @@ fn simultaneous_initiate_with_lossage() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1);
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let mut bob_store = support::test_in_memory_protocol_store();
@@ -1182,8 +2563,8 @@ fn simultaneous_initiate_lost_message() -> Result<(), SignalProtocolError> {
     block_on(async {
         let mut csprng = OsRng;
 
-        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1);
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let mut bob_store = support::test_in_memory_protocol_store();
@@ -1313,8 +2694,8 @@ fn simultaneous_initiate_repeated_messages() -> Result<(), SignalProtocolError>
     block_on(async {
         let mut csprng = OsRng;
 
-        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1);
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let mut bob_store = support::test_in_memory_protocol_store();
@@ -1509,8 +2890,8 @@ fn simultaneous_initiate_lost_message_repeated_messages() -> Result<(), SignalPr
     block_on(async {
         let mut csprng = OsRng;
 
-        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1);
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1);
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
 
         let mut alice_store = support::test_in_memory_protocol_store();
         let mut bob_store = support::test_in_memory_protocol_store();
@@ -1748,3 +3129,114 @@ fn simultaneous_initiate_lost_message_repeated_messages() -> Result<(), SignalPr
         Ok(())
     })
 }
+
+/// Stands in for a hardware keystore: it holds the key, but only signs and agrees on keys.
+struct MockKeystore {
+    key: PrivateKey,
+    signatures: AtomicUsize,
+    agreements: AtomicUsize,
+}
+
+impl PrivateKeyOperations for MockKeystore {
+    fn calculate_signature(
+        &self,
+        message: &[u8],
+        randomness: &[u8; 64],
+    ) -> Result<Box<[u8]>, SignalProtocolError> {
+        self.signatures.fetch_add(1, Ordering::SeqCst);
+        self.key
+            .calculate_signature_with_randomness(message, randomness)
+    }
+
+    fn calculate_agreement(&self, their_key: &PublicKey) -> Result<Box<[u8]>, SignalProtocolError> {
+        self.agreements.fetch_add(1, Ordering::SeqCst);
+        self.key.calculate_agreement(their_key)
+    }
+}
+
+#[test]
+fn session_with_external_identity_key() -> Result<(), SignalProtocolError> {
+    block_on(async {
+        let mut csprng = OsRng;
+
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::try_from(1)?);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), DeviceId::try_from(1)?);
+
+        let bob_identity = KeyPair::generate(&mut csprng);
+        let keystore = Arc::new(MockKeystore {
+            key: bob_identity.private_key,
+            signatures: AtomicUsize::new(0),
+            agreements: AtomicUsize::new(0),
+        });
+        let bob_identity_key_pair = IdentityKeyPair::with_operations(
+            IdentityKey::new(bob_identity.public_key),
+            keystore.clone(),
+        );
+        assert!(matches!(
+            bob_identity_key_pair.private_key(),
+            Err(SignalProtocolError::OperationNotSupported(_))
+        ));
+
+        let mut alice_store = support::test_in_memory_protocol_store();
+        let mut bob_store = InMemSignalProtocolStore::new(
+            bob_identity_key_pair.clone(),
+            RegistrationId::try_from(6)?,
+        )?;
+
+        let bob_signed_pre_key_pair = KeyPair::generate(&mut csprng);
+        let bob_signed_pre_key_signature = bob_identity_key_pair
+            .calculate_signature(&bob_signed_pre_key_pair.public_key.serialize(), &mut csprng)?;
+        assert_eq!(keystore.signatures.load(Ordering::SeqCst), 1);
+
+        let signed_pre_key_id = 22;
+        bob_store
+            .save_signed_pre_key(
+                signed_pre_key_id,
+                &SignedPreKeyRecord::new(
+                    signed_pre_key_id,
+                    /*timestamp*/ 42,
+                    &bob_signed_pre_key_pair,
+                    &bob_signed_pre_key_signature,
+                ),
+                None,
+            )
+            .await?;
+
+        let bob_pre_key_bundle = PreKeyBundle::new(
+            bob_store.get_local_registration_id(None).await?,
+            DeviceId::try_from(1)?,
+            None,
+            signed_pre_key_id,
+            bob_signed_pre_key_pair.public_key,
+            bob_signed_pre_key_signature.to_vec(),
+            *bob_identity_key_pair.identity_key(),
+        )?;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            &mut csprng,
+            None,
+        )
+        .await?;
+
+        let original_message = "hardware says hello";
+        let outgoing_message = encrypt(&mut alice_store, &bob_address, original_message).await?;
+        let incoming_message = CiphertextMessage::PreKeySignalMessage(
+            PreKeySignalMessage::try_from(outgoing_message.serialize())?,
+        );
+        let ptext = decrypt(&mut bob_store, &alice_address, &incoming_message).await?;
+        assert_eq!(String::from_utf8(ptext).unwrap(), original_message);
+        assert_eq!(keystore.agreements.load(Ordering::SeqCst), 1);
+
+        let bobs_response = "and the keystore agrees";
+        let response = encrypt(&mut bob_store, &alice_address, bobs_response).await?;
+        assert_eq!(response.message_type(), CiphertextMessageType::Whisper);
+        let ptext = decrypt(&mut alice_store, &bob_address, &response).await?;
+        assert_eq!(String::from_utf8(ptext).unwrap(), bobs_response);
+
+        Ok(())
+    })
+}
@@ -5,11 +5,12 @@
 
 use libsignal_protocol_rust::*;
 use rand::{rngs::OsRng, CryptoRng, Rng};
+use std::convert::TryFrom;
 
 pub fn test_in_memory_protocol_store() -> InMemSignalProtocolStore {
     let mut csprng = OsRng;
     let identity_key = IdentityKeyPair::generate(&mut csprng);
-    let registration_id = 5; // fixme randomly generate this
+    let registration_id = RegistrationId::try_from(5).unwrap(); // fixme randomly generate this
 
     InMemSignalProtocolStore::new(identity_key, registration_id).unwrap()
 }
@@ -35,15 +36,51 @@ pub async fn decrypt(
     store: &mut InMemSignalProtocolStore,
     remote_address: &ProtocolAddress,
     msg: &CiphertextMessage,
+) -> Result<Vec<u8>, SignalProtocolError> {
+    decrypt_with_config(store, remote_address, msg, &SessionConfig::default()).await
+}
+
+#[allow(dead_code)]
+pub async fn decrypt_with_config(
+    store: &mut InMemSignalProtocolStore,
+    remote_address: &ProtocolAddress,
+    msg: &CiphertextMessage,
+    config: &SessionConfig,
 ) -> Result<Vec<u8>, SignalProtocolError> {
     let mut csprng = OsRng;
-    message_decrypt(
+    message_decrypt_with_config(
         msg,
         &remote_address,
-        &mut store.session_store,
-        &mut store.identity_store,
-        &mut store.pre_key_store,
-        &mut store.signed_pre_key_store,
+        &mut SessionStores {
+            session_store: &mut store.session_store,
+            identity_store: &mut store.identity_store,
+            pre_key_store: &mut store.pre_key_store,
+            signed_pre_key_store: &mut store.signed_pre_key_store,
+        },
+        config,
+        &mut csprng,
+        None,
+    )
+    .await
+}
+
+#[allow(dead_code)]
+pub async fn decrypt_with_report(
+    store: &mut InMemSignalProtocolStore,
+    remote_address: &ProtocolAddress,
+    msg: &CiphertextMessage,
+) -> Result<(Vec<u8>, DecryptionReport), SignalProtocolError> {
+    let mut csprng = OsRng;
+    message_decrypt_with_report(
+        msg,
+        &remote_address,
+        &mut SessionStores {
+            session_store: &mut store.session_store,
+            identity_store: &mut store.identity_store,
+            pre_key_store: &mut store.pre_key_store,
+            signed_pre_key_store: &mut store.signed_pre_key_store,
+        },
+        &SessionConfig::default(),
         &mut csprng,
         None,
     )
@@ -62,18 +99,16 @@ pub async fn create_pre_key_bundle<R: Rng + CryptoRng>(
     let signed_pre_key_signature = store
         .get_identity_key_pair(None)
         .await?
-        .private_key()
         .calculate_signature(&signed_pre_key_public, &mut csprng)?;
 
-    let device_id: u32 = csprng.gen();
+    let device_id = DeviceId::try_from(csprng.gen_range(DeviceId::MIN, DeviceId::MAX + 1))?;
     let pre_key_id: u32 = csprng.gen();
     let signed_pre_key_id: u32 = csprng.gen();
 
     let pre_key_bundle = PreKeyBundle::new(
         store.get_local_registration_id(None).await?,
         device_id,
-        Some(pre_key_id),
-        Some(pre_key_pair.public_key),
+        Some((pre_key_id, pre_key_pair.public_key)),
         signed_pre_key_id,
         signed_pre_key_pair.public_key,
         signed_pre_key_signature.to_vec(),
@@ -118,7 +153,7 @@ pub fn initialize_sessions_v3() -> Result<(SessionState, SessionState), SignalPr
     let bob_ephemeral_key = bob_base_key;
 
     let alice_params = AliceSignalProtocolParameters::new(
-        alice_identity,
+        alice_identity.clone(),
         alice_base_key,
         *bob_identity.identity_key(),
         bob_base_key.public_key,
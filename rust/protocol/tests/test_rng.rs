@@ -0,0 +1,103 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Checks the values in tests/data/test-rng-vectors.txt, which the binding test suites reproduce
+//! with their own TestRng. Only built with `--features test-rng`.
+
+#![cfg(feature = "test-rng")]
+
+use futures::executor::block_on;
+use libsignal_protocol_rust::*;
+use std::convert::TryFrom;
+
+const VECTORS: &str = include_str!("data/test-rng-vectors.txt");
+
+const MESSAGE: &[u8] = b"test vector message";
+
+fn computed_vectors() -> Result<Vec<(&'static str, Vec<u8>)>, SignalProtocolError> {
+    let key_pair = KeyPair::generate(&mut TestRng::new(1));
+    let signature = key_pair
+        .private_key
+        .calculate_signature(MESSAGE, &mut TestRng::new(2))?;
+
+    let other_identity = IdentityKey::new(KeyPair::generate(&mut TestRng::new(3)).public_key);
+    let identity_key_pair =
+        IdentityKeyPair::new(IdentityKey::new(key_pair.public_key), key_pair.private_key);
+    let alternate_identity_signature =
+        identity_key_pair.sign_alternate_identity(&other_identity, &mut TestRng::new(4))?;
+
+    let access_key = unrestricted_unidentified_access_key(&mut TestRng::new(5));
+
+    let sender_address = ProtocolAddress::new("+14159999111".to_owned(), DeviceId::try_from(1)?);
+    let sender_key_name = SenderKeyName::new("test vector group".to_owned(), sender_address)?;
+    let mut sender_key_store = InMemSenderKeyStore::new();
+    let distribution_message = block_on(create_sender_key_distribution_message(
+        &sender_key_name,
+        &mut sender_key_store,
+        &mut TestRng::new(6),
+        None,
+    ))?;
+    let group_ciphertext = block_on(group_encrypt(
+        &mut sender_key_store,
+        &sender_key_name,
+        MESSAGE,
+        &mut TestRng::new(7),
+        None,
+    ))?;
+
+    Ok(vec![
+        ("private_key", key_pair.private_key.serialize()),
+        ("public_key", key_pair.public_key.serialize().into_vec()),
+        ("signature", signature.into_vec()),
+        ("alternate_identity_signature", alternate_identity_signature),
+        ("unidentified_access_key", access_key.to_vec()),
+        (
+            "sender_key_distribution_message",
+            distribution_message.serialized().to_vec(),
+        ),
+        ("group_ciphertext", group_ciphertext),
+    ])
+}
+
+fn expected_vectors() -> Vec<(&'static str, Vec<u8>)> {
+    VECTORS
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next().expect("name");
+            let value = hex::decode(fields.next().expect("value")).expect("valid hex");
+            (name, value)
+        })
+        .collect()
+}
+
+#[test]
+fn test_rng_is_deterministic() {
+    use rand::RngCore;
+
+    assert_eq!(TestRng::new(9).next_u64(), TestRng::new(9).next_u64());
+    assert_ne!(TestRng::new(9).next_u64(), TestRng::new(10).next_u64());
+}
+
+#[test]
+fn shared_vectors_match() -> Result<(), SignalProtocolError> {
+    let computed = computed_vectors()?;
+    let expected = expected_vectors();
+    assert_eq!(
+        computed.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+        expected.iter().map(|(name, _)| *name).collect::<Vec<_>>()
+    );
+    for ((name, value), (_, expected_value)) in computed.iter().zip(&expected) {
+        assert!(
+            value == expected_value,
+            "{} changed; the new line is\n{} {}",
+            name,
+            name,
+            hex::encode(value)
+        );
+    }
+    Ok(())
+}
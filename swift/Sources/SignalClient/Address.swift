@@ -4,6 +4,51 @@
 //
 
 import SignalFfi
+import Foundation
+
+/// An account identifier, tagged with the kind of identity it belongs to.
+public enum ServiceId: Hashable {
+    /// An account's primary identity.
+    case aci(UUID)
+    /// An account's phone number identity.
+    case pni(UUID)
+
+    public var uuid: UUID {
+        switch self {
+        case .aci(let uuid), .pni(let uuid):
+            return uuid
+        }
+    }
+
+    /// The 17-byte fixed-width encoding: a kind byte (0 for ACI, 1 for PNI) followed by the UUID.
+    public var serviceIdBinary: [UInt8] {
+        let kind: UInt8
+        switch self {
+        case .aci:
+            kind = 0
+        case .pni:
+            kind = 1
+        }
+        return [kind] + withUnsafeBytes(of: uuid.uuid) { Array($0) }
+    }
+
+    internal init?(serviceIdBinary bytes: [UInt8]) {
+        guard bytes.count == 17 else {
+            return nil
+        }
+        var rawUuid: uuid_t = (0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0)
+        withUnsafeMutableBytes(of: &rawUuid) { $0.copyBytes(from: bytes[1...]) }
+        let uuid = UUID(uuid: rawUuid)
+        switch bytes[0] {
+        case 0:
+            self = .aci(uuid)
+        case 1:
+            self = .pni(uuid)
+        default:
+            return nil
+        }
+    }
+}
 
 public class ProtocolAddress: ClonableHandleOwner {
     public init(name: String, deviceId: UInt32) throws {
@@ -14,6 +59,16 @@ public class ProtocolAddress: ClonableHandleOwner {
         super.init(owned: handle!)
     }
 
+    /// Creates an address whose name is the canonical string form of `serviceId`.
+    public init(serviceId: ServiceId, deviceId: UInt32) throws {
+        var handle: OpaquePointer?
+        try checkError(signal_address_new_from_service_id(&handle,
+                                                          serviceId.serviceIdBinary,
+                                                          17,
+                                                          deviceId))
+        super.init(owned: handle!)
+    }
+
     internal override init(borrowing handle: OpaquePointer?) {
         super.init(borrowing: handle)
     }
@@ -37,6 +92,14 @@ public class ProtocolAddress: ClonableHandleOwner {
             signal_address_get_device_id(nativeHandle, $0)
         }
     }
+
+    /// The service ID named by this address, or `nil` if the name is not a service ID.
+    public var serviceId: ServiceId? {
+        let bytes = try! invokeFnReturningArray {
+            signal_address_get_service_id(nativeHandle, $0, $1)
+        }
+        return ServiceId(serviceIdBinary: bytes)
+    }
 }
 
 extension ProtocolAddress: Hashable {
@@ -14,7 +14,7 @@ public class InMemorySignalProtocolStore: IdentityKeyStore, PreKeyStore, SignedP
 
     public init() throws {
         privateKey = try IdentityKeyPair.generate()
-        deviceId = UInt32.random(in: 0...65535)
+        deviceId = UInt32.random(in: 0...0x3FFF)
     }
 
     public init(identity: IdentityKeyPair, deviceId: UInt32) {
@@ -30,11 +30,14 @@ public class InMemorySignalProtocolStore: IdentityKeyStore, PreKeyStore, SignedP
         return deviceId
     }
 
-    public func saveIdentity(_ identity: IdentityKey, for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws -> Bool {
-        if publicKeys.updateValue(identity, forKey: address) == nil {
-            return false; // newly created
-        } else {
-            return true
+    public func saveIdentity(_ identity: IdentityKey, for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws -> IdentityChange {
+        switch publicKeys.updateValue(identity, forKey: address) {
+        case nil:
+            return .newIdentity
+        case identity?:
+            return .unchanged
+        default:
+            return .replacedExisting
         }
     }
 
@@ -86,6 +89,18 @@ public class InMemorySignalProtocolStore: IdentityKeyStore, PreKeyStore, SignedP
         sessionMap[address] = record
     }
 
+    public func subDeviceSessions(for name: String, context: UnsafeMutableRawPointer?) throws -> [UInt32] {
+        return sessionMap.keys.filter { $0.name == name && $0.deviceId != 1 }.map { $0.deviceId }.sorted()
+    }
+
+    public func deleteSession(for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws {
+        sessionMap[address] = nil
+    }
+
+    public func deleteAllSessions(for name: String, context: UnsafeMutableRawPointer?) throws {
+        sessionMap = sessionMap.filter { $0.key.name != name }
+    }
+
     public func storeSenderKey(name: SenderKeyName, record: SenderKeyRecord, context: UnsafeMutableRawPointer?) throws {
         senderKeyMap[name] = record
     }
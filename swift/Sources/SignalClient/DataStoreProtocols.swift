@@ -10,10 +10,22 @@ public enum Direction {
     case receiving
 }
 
+/// What `IdentityKeyStore.saveIdentity` did with the identity it was given.
+public enum IdentityChange {
+    /// There was no identity saved for the address before.
+    case newIdentity
+    /// A different identity was saved for the address, and has been replaced.
+    ///
+    /// This is when the user should be told that the safety number has changed.
+    case replacedExisting
+    /// The same identity was already saved for the address.
+    case unchanged
+}
+
 public protocol IdentityKeyStore: AnyObject {
     func identityKeyPair(context: UnsafeMutableRawPointer?) throws -> IdentityKeyPair
     func localRegistrationId(context: UnsafeMutableRawPointer?) throws -> UInt32
-    func saveIdentity(_ identity: IdentityKey, for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws -> Bool
+    func saveIdentity(_ identity: IdentityKey, for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws -> IdentityChange
     func isTrustedIdentity(_ identity: IdentityKey, for address: ProtocolAddress, direction: Direction, context: UnsafeMutableRawPointer?) throws -> Bool
     func identity(for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws -> IdentityKey?
 }
@@ -32,6 +44,25 @@ public protocol SignedPreKeyStore: AnyObject {
 public protocol SessionStore: AnyObject {
     func loadSession(for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws -> SessionRecord?
     func storeSession(_ record: SessionRecord, for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws
+    /// Returns the device IDs of every device of `name` other than device 1 that has a session.
+    func subDeviceSessions(for name: String, context: UnsafeMutableRawPointer?) throws -> [UInt32]
+    func deleteSession(for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws
+    func deleteAllSessions(for name: String, context: UnsafeMutableRawPointer?) throws
+}
+
+/// Stores that only load and save sessions can leave out the rest, matching the Rust `SessionStore`.
+extension SessionStore {
+    public func subDeviceSessions(for name: String, context: UnsafeMutableRawPointer?) throws -> [UInt32] {
+        throw SignalError.operationNotSupported("subDeviceSessions")
+    }
+
+    public func deleteSession(for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws {
+        throw SignalError.operationNotSupported("deleteSession")
+    }
+
+    public func deleteAllSessions(for name: String, context: UnsafeMutableRawPointer?) throws {
+        throw SignalError.operationNotSupported("deleteAllSessions")
+    }
 }
 
 public protocol SenderKeyStore: AnyObject {
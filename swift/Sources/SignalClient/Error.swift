@@ -13,21 +13,25 @@ public enum SignalError: Error {
     case invalidType(String)
     case invalidUtf8String(String)
     case insufficientOutputSize(String)
+    case operationNotSupported(String)
     case protobufError(String)
     case invalidCiphertext(String)
     case legacyCiphertextVersion(String)
     case unknownCiphertextVersion(String)
     case unrecognizedMessageVersion(String)
     case invalidMessage(String)
+    case invalidPreKeyBundle(String)
     case invalidKey(String)
     case invalidSignature(String)
     case fingerprintIdentifierMismatch(String)
     case fingerprintVersionMismatch(String)
+    case fingerprintParsingError(String)
     case untrustedIdentity(String)
     case invalidKeyIdentifier(String)
     case sessionNotFound(String)
-    case duplicatedMessage(String)
+    case duplicatedMessage(String, chainIndex: UInt32, counter: UInt32)
     case callbackError(String)
+    case storageError(String)
     case unknown(UInt32, String)
 }
 
@@ -41,6 +45,11 @@ internal func checkError(_ error: SignalFfiErrorRef?) throws {
     let errStr = try! invokeFnReturningString {
         signal_error_get_message(error, $0)
     }
+    var chainIndex: UInt32 = 0
+    var counter: UInt32 = 0
+    if SignalErrorCode(errType) == SignalErrorCode_DuplicatedMessage {
+        _ = signal_error_get_duplicated_message_counter(error, &chainIndex, &counter)
+    }
     signal_error_free(error)
 
     switch SignalErrorCode(errType) {
@@ -58,6 +67,8 @@ internal func checkError(_ error: SignalFfiErrorRef?) throws {
         throw SignalError.invalidUtf8String(errStr)
     case SignalErrorCode_InsufficientOutputSize:
         throw SignalError.insufficientOutputSize(errStr)
+    case SignalErrorCode_OperationNotSupported:
+        throw SignalError.operationNotSupported(errStr)
     case SignalErrorCode_ProtobufError:
         throw SignalError.protobufError(errStr)
     case SignalErrorCode_InvalidCiphertext:
@@ -70,6 +81,8 @@ internal func checkError(_ error: SignalFfiErrorRef?) throws {
         throw SignalError.unrecognizedMessageVersion(errStr)
     case SignalErrorCode_InvalidMessage:
         throw SignalError.invalidMessage(errStr)
+    case SignalErrorCode_InvalidPreKeyBundle:
+        throw SignalError.invalidPreKeyBundle(errStr)
     case SignalErrorCode_InvalidKey:
         throw SignalError.invalidKey(errStr)
     case SignalErrorCode_InvalidSignature:
@@ -78,6 +91,8 @@ internal func checkError(_ error: SignalFfiErrorRef?) throws {
         throw SignalError.fingerprintIdentifierMismatch(errStr)
     case SignalErrorCode_FingerprintVersionMismatch:
         throw SignalError.fingerprintVersionMismatch(errStr)
+    case SignalErrorCode_FingerprintParsingError:
+        throw SignalError.fingerprintParsingError(errStr)
     case SignalErrorCode_UntrustedIdentity:
         throw SignalError.untrustedIdentity(errStr)
     case SignalErrorCode_InvalidKeyIdentifier:
@@ -85,9 +100,11 @@ internal func checkError(_ error: SignalFfiErrorRef?) throws {
     case SignalErrorCode_SessionNotFound:
         throw SignalError.sessionNotFound(errStr)
     case SignalErrorCode_DuplicatedMessage:
-        throw SignalError.duplicatedMessage(errStr)
+        throw SignalError.duplicatedMessage(errStr, chainIndex: chainIndex, counter: counter)
     case SignalErrorCode_CallbackError:
         throw SignalError.callbackError(errStr)
+    case SignalErrorCode_StorageError:
+        throw SignalError.storageError(errStr)
     default:
         throw SignalError.unknown(errType, errStr)
     }
@@ -14,6 +14,15 @@ public struct DisplayableFingerprint {
     }
 }
 
+public enum FingerprintComparison: Equatable {
+    case match
+    /// The scanning party sees a different identity key for us than our own.
+    case localMismatch
+    /// The scanning party's identity key is not the one we have for them.
+    case remoteMismatch
+    case versionMismatch(ours: UInt32, theirs: UInt32)
+}
+
 public struct ScannableFingerprint {
     public let encoding: [UInt8]
 
@@ -27,6 +36,27 @@ public struct ScannableFingerprint {
                                                   other.encoding, other.encoding.count))
         return result
     }
+
+    public func compareScanned(_ scanned: ScannableFingerprint) throws -> FingerprintComparison {
+        var result: UInt8 = 0
+        var ourVersion: UInt32 = 0
+        var theirVersion: UInt32 = 0
+        try checkError(signal_fingerprint_compare_scanned(&result, &ourVersion, &theirVersion,
+                                                          encoding, encoding.count,
+                                                          scanned.encoding, scanned.encoding.count))
+        switch SignalFingerprintComparison(UInt32(result)) {
+        case SignalFingerprintComparison_Match:
+            return .match
+        case SignalFingerprintComparison_LocalMismatch:
+            return .localMismatch
+        case SignalFingerprintComparison_RemoteMismatch:
+            return .remoteMismatch
+        case SignalFingerprintComparison_VersionMismatch:
+            return .versionMismatch(ours: ourVersion, theirs: theirVersion)
+        default:
+            throw SignalError.internalError("unexpected fingerprint comparison result \(result)")
+        }
+    }
 }
 
 public struct Fingerprint {
@@ -63,6 +93,30 @@ public struct NumericFingerprintGenerator {
             }
         }
 
+        return try NumericFingerprintGenerator.consumeFingerprint(obj)
+    }
+
+    /// Creates a fingerprint over several identifiers per party, such as a UUID and an E164.
+    ///
+    /// The identifiers may be given in any order.
+    public func createMulti(localIdentifiers: [[UInt8]],
+                            localKey: PublicKey,
+                            remoteIdentifiers: [[UInt8]],
+                            remoteKey: PublicKey) throws -> Fingerprint {
+        var obj: OpaquePointer?
+        try checkError(signal_fingerprint_new_multi(&obj, UInt32(iterations),
+                                                    localIdentifiers.flatMap { $0 },
+                                                    localIdentifiers.map { $0.count },
+                                                    localIdentifiers.count,
+                                                    localKey.nativeHandle,
+                                                    remoteIdentifiers.flatMap { $0 },
+                                                    remoteIdentifiers.map { $0.count },
+                                                    remoteIdentifiers.count,
+                                                    remoteKey.nativeHandle))
+        return try NumericFingerprintGenerator.consumeFingerprint(obj)
+    }
+
+    private static func consumeFingerprint(_ obj: OpaquePointer?) throws -> Fingerprint {
         let fprintStr = try invokeFnReturningString {
             signal_fingerprint_display_string(obj, $0)
         }
@@ -76,4 +130,24 @@ public struct NumericFingerprintGenerator {
 
         return Fingerprint(displayable: displayable, scannable: scannable)
     }
+
+    /// Computes only the displayable half of a fingerprint, skipping the scannable encoding.
+    public func createDisplayable<LocalBytes, RemoteBytes>(localIdentifier: LocalBytes,
+                                                           localKey: PublicKey,
+                                                           remoteIdentifier: RemoteBytes,
+                                                           remoteKey: PublicKey) throws -> DisplayableFingerprint
+    where LocalBytes: ContiguousBytes, RemoteBytes: ContiguousBytes {
+        let fprintStr = try localIdentifier.withUnsafeBytes { localBytes in
+            try remoteIdentifier.withUnsafeBytes { remoteBytes in
+                try invokeFnReturningString {
+                    signal_fingerprint_new_displayable($0, UInt32(iterations),
+                                                       localBytes.baseAddress?.assumingMemoryBound(to: UInt8.self), localBytes.count,
+                                                       localKey.nativeHandle,
+                                                       remoteBytes.baseAddress?.assumingMemoryBound(to: UInt8.self), remoteBytes.count,
+                                                       remoteKey.nativeHandle)
+                }
+            }
+        }
+        return DisplayableFingerprint(formatted: fprintStr)
+    }
 }
@@ -20,6 +20,14 @@ public struct IdentityKey: Equatable {
     public func serialize() throws -> [UInt8] {
         return try publicKey.serialize()
     }
+
+    public func verifyAlternateIdentity<Bytes: ContiguousBytes>(_ other: IdentityKey, signature: Bytes) throws -> Bool {
+        var result: Bool = false
+        try signature.withUnsafeBytes {
+            try checkError(signal_identitykey_verify_alternate_identity(&result, publicKey.nativeHandle, other.publicKey.nativeHandle, $0.baseAddress?.assumingMemoryBound(to: UInt8.self), $0.count))
+        }
+        return result
+    }
 }
 
 public struct IdentityKeyPair {
@@ -54,6 +62,12 @@ public struct IdentityKeyPair {
         }
     }
 
+    public func signAlternateIdentity(_ other: IdentityKey) throws -> [UInt8] {
+        return try invokeFnReturningArray {
+            signal_identitykeypair_sign_alternate_identity($0, $1, publicKey.nativeHandle, privateKey.nativeHandle, other.publicKey.nativeHandle)
+        }
+    }
+
     public var identityKey: IdentityKey {
         return IdentityKey(publicKey: publicKey)
     }
@@ -0,0 +1,25 @@
+//
+// Copyright 2020 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import SignalFfi
+import Foundation
+
+/// Pads a message body before encryption, as the other Signal clients do.
+public func padMessage<Bytes: ContiguousBytes>(_ body: Bytes) throws -> [UInt8] {
+    return try body.withUnsafeBytes { bodyBytes in
+        try invokeFnReturningArray {
+            signal_pad_message($0, $1, bodyBytes.baseAddress?.assumingMemoryBound(to: UInt8.self), bodyBytes.count)
+        }
+    }
+}
+
+/// Strips the padding added by `padMessage`, throwing if it is malformed.
+public func unpadMessage<Bytes: ContiguousBytes>(_ padded: Bytes) throws -> [UInt8] {
+    return try padded.withUnsafeBytes { paddedBytes in
+        try invokeFnReturningArray {
+            signal_unpad_message($0, $1, paddedBytes.baseAddress?.assumingMemoryBound(to: UInt8.self), paddedBytes.count)
+        }
+    }
+}
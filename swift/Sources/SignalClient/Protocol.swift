@@ -7,7 +7,8 @@ import SignalFfi
 import Foundation
 
 /*
- SignalFfiError *signal_process_prekey_bundle(PreKeyBundle *bundle,
+ SignalFfiError *signal_process_prekey_bundle(uint8_t *identity_change,
+                                             PreKeyBundle *bundle,
                                              const ProtocolAddress *protocol_address,
                                              FfiSessionStoreStruct *session_store,
                                              FfiIdentityKeyStoreStruct *identity_key_store,
@@ -58,6 +59,32 @@ public func signalEncrypt<Bytes: ContiguousBytes>(message: Bytes,
     }
 }
 
+/// A session message with what is needed to address it, from `signalEncryptWithDetails`.
+public struct EncryptedMessage {
+    public let serialized: [UInt8]
+    public let messageType: CiphertextMessage.MessageType
+    public let remoteRegistrationId: UInt32
+}
+
+public func signalEncryptWithDetails<Bytes: ContiguousBytes>(message: Bytes,
+                                                             for address: ProtocolAddress,
+                                                             sessionStore: SessionStore,
+                                                             identityStore: IdentityKeyStore,
+                                                             context: UnsafeMutableRawPointer?) throws -> EncryptedMessage {
+    var result = SignalEncryptedMessage()
+    try message.withUnsafeBytes { messageBytes in
+        try withSessionStore(sessionStore) { ffiSessionStore in
+            try withIdentityKeyStore(identityStore) { ffiIdentityStore in
+                try checkError(signal_encrypt_message_with_details(&result, messageBytes.baseAddress?.assumingMemoryBound(to: UInt8.self), messageBytes.count, address.nativeHandle, ffiSessionStore, ffiIdentityStore, context))
+            }
+        }
+    }
+    defer { signal_free_buffer(result.serialized, result.serialized_len) }
+    return EncryptedMessage(serialized: Array(UnsafeBufferPointer(start: result.serialized, count: result.serialized_len)),
+                            messageType: CiphertextMessage.MessageType(rawValue: result.message_type),
+                            remoteRegistrationId: result.remote_registration_id)
+}
+
 public func signalDecrypt(message: SignalMessage,
                           from address: ProtocolAddress,
                           sessionStore: SessionStore,
@@ -92,16 +119,59 @@ public func signalDecryptPreKey(message: PreKeySignalMessage,
     }
 }
 
+/// Returns how the bundle's identity was saved; `.replacedExisting` means the safety number has changed.
+@discardableResult
 public func processPreKeyBundle(_ bundle: PreKeyBundle,
                                 for address: ProtocolAddress,
                                 sessionStore: SessionStore,
                                 identityStore: IdentityKeyStore,
-                                context: UnsafeMutableRawPointer?) throws {
-    return try withSessionStore(sessionStore) { ffiSessionStore in
+                                context: UnsafeMutableRawPointer?) throws -> IdentityChange {
+    var result: UInt8 = 0
+    try withSessionStore(sessionStore) { ffiSessionStore in
+        try withIdentityKeyStore(identityStore) { ffiIdentityStore in
+            try checkError(signal_process_prekey_bundle(&result, bundle.nativeHandle, address.nativeHandle, ffiSessionStore, ffiIdentityStore, context))
+        }
+    }
+    return try identityChange(fromFfi: result)
+}
+
+/// What `processPreKeyBundleWithReport` did with a bundle.
+public struct PreKeyBundleReport {
+    /// The one-time pre-key the session was built with, if the bundle had one.
+    public let preKeyId: UInt32?
+    public let signedPreKeyId: UInt32
+    public let sessionVersion: UInt32
+    public let identityChange: IdentityChange
+}
+
+public func processPreKeyBundleWithReport(_ bundle: PreKeyBundle,
+                                          for address: ProtocolAddress,
+                                          sessionStore: SessionStore,
+                                          identityStore: IdentityKeyStore,
+                                          context: UnsafeMutableRawPointer?) throws -> PreKeyBundleReport {
+    var report = SignalPreKeyBundleReport()
+    try withSessionStore(sessionStore) { ffiSessionStore in
         try withIdentityKeyStore(identityStore) { ffiIdentityStore in
-            try checkError(signal_process_prekey_bundle(bundle.nativeHandle, address.nativeHandle, ffiSessionStore, ffiIdentityStore, context))
+            try checkError(signal_process_prekey_bundle_with_report(&report, bundle.nativeHandle, address.nativeHandle, ffiSessionStore, ffiIdentityStore, context))
         }
     }
+    return PreKeyBundleReport(preKeyId: report.has_pre_key_id ? report.pre_key_id : nil,
+                              signedPreKeyId: report.signed_pre_key_id,
+                              sessionVersion: report.session_version,
+                              identityChange: try identityChange(fromFfi: report.identity_change))
+}
+
+private func identityChange(fromFfi raw: UInt8) throws -> IdentityChange {
+    switch SignalIdentityChange(UInt32(raw)) {
+    case SignalIdentityChange_NewIdentity:
+        return .newIdentity
+    case SignalIdentityChange_ReplacedExisting:
+        return .replacedExisting
+    case SignalIdentityChange_Unchanged:
+        return .unchanged
+    default:
+        throw SignalError.internalError("unexpected identity change \(raw)")
+    }
 }
 
 public func groupEncrypt<Bytes: ContiguousBytes>(groupId: SenderKeyName,
@@ -117,6 +187,29 @@ public func groupEncrypt<Bytes: ContiguousBytes>(groupId: SenderKeyName,
     }
 }
 
+/// A group message with the chain position it was encrypted at, from `groupEncryptWithMetadata`.
+public struct GroupEncryptedMessage {
+    public let serialized: [UInt8]
+    public let chainId: UInt32
+    public let iteration: UInt32
+}
+
+public func groupEncryptWithMetadata<Bytes: ContiguousBytes>(groupId: SenderKeyName,
+                                                             message: Bytes,
+                                                             store: SenderKeyStore,
+                                                             context: UnsafeMutableRawPointer?) throws -> GroupEncryptedMessage {
+    var result = SignalGroupEncryptedMessage()
+    try message.withUnsafeBytes { messageBytes in
+        try withSenderKeyStore(store) { ffiStore in
+            try checkError(signal_group_encrypt_message_with_metadata(&result, groupId.nativeHandle, messageBytes.baseAddress?.assumingMemoryBound(to: UInt8.self), messageBytes.count, ffiStore, context))
+        }
+    }
+    defer { signal_free_buffer(result.serialized, result.serialized_len) }
+    return GroupEncryptedMessage(serialized: Array(UnsafeBufferPointer(start: result.serialized, count: result.serialized_len)),
+                                 chainId: result.chain_id,
+                                 iteration: result.iteration)
+}
+
 public func groupDecrypt<Bytes: ContiguousBytes>(groupId: SenderKeyName,
                                                  message: Bytes,
                                                  store: SenderKeyStore,
@@ -140,3 +233,16 @@ public func processSenderKeyDistributionMessage(sender: SenderKeyName,
                                                                       $0, context))
     }
 }
+
+public func processSenderKeyDistributionMessage(sender: SenderKeyName,
+                                                message: SenderKeyDistributionMessage,
+                                                expectedId: UInt32,
+                                                store: SenderKeyStore,
+                                                context: UnsafeMutableRawPointer?) throws {
+    try withSenderKeyStore(store) {
+        try checkError(signal_process_sender_key_distribution_message_with_expected_id(sender.nativeHandle,
+                                                                                       message.nativeHandle,
+                                                                                       expectedId,
+                                                                                       $0, context))
+    }
+}
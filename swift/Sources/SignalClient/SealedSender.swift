@@ -0,0 +1,23 @@
+//
+// Copyright 2020 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import SignalFfi
+import Foundation
+
+public func deriveUnidentifiedAccessKey<Bytes: ContiguousBytes>(profileKey: Bytes) throws -> [UInt8] {
+    return try profileKey.withUnsafeBytes { profileKeyBytes in
+        try invokeFnReturningArray {
+            signal_derive_unidentified_access_key($0, $1,
+                                                  profileKeyBytes.baseAddress?.assumingMemoryBound(to: UInt8.self),
+                                                  profileKeyBytes.count)
+        }
+    }
+}
+
+public func unrestrictedUnidentifiedAccessKey() throws -> [UInt8] {
+    return try invokeFnReturningArray {
+        signal_unrestricted_unidentified_access_key($0, $1)
+    }
+}
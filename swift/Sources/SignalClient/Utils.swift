@@ -90,11 +90,13 @@ internal func withIdentityKeyStore<Result>(_ store: IdentityKeyStore, _ body: (U
             var public_key = PublicKey(borrowing: public_key)
             defer { cloneOrForgetAsNeeded(&public_key) }
             let identity = IdentityKey(publicKey: public_key)
-            let new_id = try store.saveIdentity(identity, for: address, context: ctx)
-            if new_id {
-                return 1
-            } else {
-                return 0
+            switch try store.saveIdentity(identity, for: address, context: ctx) {
+            case .newIdentity:
+                return Int32(SignalIdentityChange_NewIdentity.rawValue)
+            case .replacedExisting:
+                return Int32(SignalIdentityChange_ReplacedExisting.rawValue)
+            case .unchanged:
+                return Int32(SignalIdentityChange_Unchanged.rawValue)
             }
         } catch {
             return -1
@@ -296,13 +298,64 @@ internal func withSessionStore<Result>(_ store: SessionStore, _ body: (UnsafePoi
         }
     }
 
+    func ffiShimGetSubDeviceSessions(store_ctx: UnsafeMutableRawPointer?,
+                                     device_ids: UnsafeMutablePointer<UInt32>?,
+                                     device_ids_capacity: Int,
+                                     device_ids_len: UnsafeMutablePointer<Int>?,
+                                     name: UnsafePointer<CChar>?,
+                                     ctx: UnsafeMutableRawPointer?) -> Int32 {
+        do {
+            let store = store_ctx!.assumingMemoryBound(to: SessionStore.self).pointee
+            let ids = try store.subDeviceSessions(for: String(cString: name!), context: ctx)
+            if ids.count > device_ids_capacity {
+                return -1
+            }
+            for (i, id) in ids.enumerated() {
+                device_ids![i] = id
+            }
+            device_ids_len!.pointee = ids.count
+            return 0
+        } catch {
+            return -1
+        }
+    }
+
+    func ffiShimDeleteSession(store_ctx: UnsafeMutableRawPointer?,
+                              address: OpaquePointer?,
+                              ctx: UnsafeMutableRawPointer?) -> Int32 {
+        do {
+            let store = store_ctx!.assumingMemoryBound(to: SessionStore.self).pointee
+            var address = ProtocolAddress(borrowing: address)
+            defer { cloneOrForgetAsNeeded(&address) }
+            try store.deleteSession(for: address, context: ctx)
+            return 0
+        } catch {
+            return -1
+        }
+    }
+
+    func ffiShimDeleteAllSessions(store_ctx: UnsafeMutableRawPointer?,
+                                  name: UnsafePointer<CChar>?,
+                                  ctx: UnsafeMutableRawPointer?) -> Int32 {
+        do {
+            let store = store_ctx!.assumingMemoryBound(to: SessionStore.self).pointee
+            try store.deleteAllSessions(for: String(cString: name!), context: ctx)
+            return 0
+        } catch {
+            return -1
+        }
+    }
+
     return try withUnsafePointer(to: store) {
         // We're not actually going to mutate through 'ffiStore.ctx';
         // it's just the usual convention of `void *` for context fields.
         var ffiStore = SignalSessionStore(
             ctx: UnsafeMutableRawPointer(mutating: $0),
             load_session: ffiShimLoadSession,
-            store_session: ffiShimStoreSession)
+            store_session: ffiShimStoreSession,
+            get_sub_device_sessions: ffiShimGetSubDeviceSessions,
+            delete_session: ffiShimDeleteSession,
+            delete_all_sessions: ffiShimDeleteAllSessions)
         return try body(&ffiStore)
     }
 }
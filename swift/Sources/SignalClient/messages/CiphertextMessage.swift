@@ -30,6 +30,9 @@ public class CiphertextMessage {
         public static var senderKeyDistribution: Self {
             return Self(SignalCiphertextMessageType_SenderKeyDistribution)
         }
+        public static var plaintext: Self {
+            return Self(SignalCiphertextMessageType_Plaintext)
+        }
     }
 
     deinit {
@@ -40,6 +43,15 @@ public class CiphertextMessage {
         handle = rawPtr
     }
 
+    /// Parses a message received with the given envelope type.
+    public init<Bytes: ContiguousBytes>(type: MessageType, bytes: Bytes) throws {
+        handle = try bytes.withUnsafeBytes {
+            var result: OpaquePointer?
+            try checkError(signal_ciphertext_message_deserialize(&result, type.rawValue, $0.baseAddress?.assumingMemoryBound(to: UInt8.self), $0.count))
+            return result
+        }
+    }
+
     public func serialize() throws -> [UInt8] {
         return try invokeFnReturningArray {
             signal_ciphertext_message_serialize($0, $1, handle)
@@ -0,0 +1,78 @@
+//
+// Copyright 2020 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import SignalFfi
+import Foundation
+
+public class DecryptionErrorMessage {
+    private var handle: OpaquePointer?
+
+    deinit {
+        signal_decryption_error_message_destroy(handle)
+    }
+
+    internal var nativeHandle: OpaquePointer? {
+        return handle
+    }
+
+    internal init(owned rawPtr: OpaquePointer?) {
+        handle = rawPtr
+    }
+
+    public init<Bytes: ContiguousBytes>(originalMessageBytes bytes: Bytes,
+                                        type: CiphertextMessage.MessageType,
+                                        timestamp: UInt64,
+                                        originalSenderDeviceId: UInt32) throws {
+        handle = try bytes.withUnsafeBytes {
+            var result: OpaquePointer?
+            try checkError(signal_decryption_error_message_for_original_message(&result,
+                                                                                 $0.baseAddress?.assumingMemoryBound(to: UInt8.self),
+                                                                                 $0.count,
+                                                                                 type.rawValue,
+                                                                                 timestamp,
+                                                                                 originalSenderDeviceId))
+            return result
+        }
+    }
+
+    public init(bytes: [UInt8]) throws {
+        try checkError(signal_decryption_error_message_deserialize(&handle, bytes, bytes.count))
+    }
+
+    /// Extracts a message from the body of a `PlaintextContent`.
+    public static func extractFromSerializedContent<Bytes: ContiguousBytes>(_ bytes: Bytes) throws -> DecryptionErrorMessage {
+        return try bytes.withUnsafeBytes {
+            var result: OpaquePointer?
+            try checkError(signal_decryption_error_message_extract_from_serialized_content(&result,
+                                                                                            $0.baseAddress?.assumingMemoryBound(to: UInt8.self),
+                                                                                            $0.count))
+            return DecryptionErrorMessage(owned: result)
+        }
+    }
+
+    public func serialize() throws -> [UInt8] {
+        return try invokeFnReturningArray {
+            signal_decryption_error_message_serialize(handle, $0, $1)
+        }
+    }
+
+    public func ratchetKey() throws -> PublicKey? {
+        return try invokeFnReturningOptionalPublicKey {
+            signal_decryption_error_message_get_ratchet_key($0, handle)
+        }
+    }
+
+    public func timestamp() throws -> UInt64 {
+        return try invokeFnReturningInteger {
+            signal_decryption_error_message_get_timestamp(handle, $0)
+        }
+    }
+
+    public func deviceId() throws -> UInt32 {
+        return try invokeFnReturningInteger {
+            signal_decryption_error_message_get_device_id(handle, $0)
+        }
+    }
+}
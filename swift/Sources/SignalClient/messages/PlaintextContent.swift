@@ -0,0 +1,35 @@
+//
+// Copyright 2020 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import SignalFfi
+import Foundation
+
+public class PlaintextContent {
+    private var handle: OpaquePointer?
+
+    deinit {
+        signal_plaintext_content_destroy(handle)
+    }
+
+    public init(_ message: DecryptionErrorMessage) throws {
+        try checkError(signal_plaintext_content_from_decryption_error_message(&handle, message.nativeHandle))
+    }
+
+    public init(bytes: [UInt8]) throws {
+        try checkError(signal_plaintext_content_deserialize(&handle, bytes, bytes.count))
+    }
+
+    public func serialize() throws -> [UInt8] {
+        return try invokeFnReturningArray {
+            signal_plaintext_content_serialize(handle, $0, $1)
+        }
+    }
+
+    public func body() throws -> [UInt8] {
+        return try invokeFnReturningArray {
+            signal_plaintext_content_get_body(handle, $0, $1)
+        }
+    }
+}
@@ -43,4 +43,32 @@ public class SessionRecord: ClonableHandleOwner {
     public func archiveCurrentState() throws {
         return try checkError(signal_session_record_archive_current_state(nativeHandle))
     }
+
+    // Debugging aids for the current session state; these expose counters, never keys.
+
+    public func debugHasUsableSenderChain() throws -> Bool {
+        var result: Bool = false
+        try checkError(signal_session_record_debug_has_usable_sender_chain(&result, nativeHandle))
+        return result
+    }
+
+    public func debugSenderChainCounter() throws -> UInt32? {
+        let counter = try invokeFnReturningInteger {
+            signal_session_record_debug_sender_chain_counter(nativeHandle, $0)
+        }
+        return counter == 0xFFFFFFFF ? nil : counter
+    }
+
+    public func debugReceiverChainCounter(theirEphemeral: PublicKey) throws -> UInt32? {
+        let counter = try invokeFnReturningInteger {
+            signal_session_record_debug_receiver_chain_counter(nativeHandle, theirEphemeral.nativeHandle, $0)
+        }
+        return counter == 0xFFFFFFFF ? nil : counter
+    }
+
+    public func debugNumberOfReceiverChains() throws -> UInt32 {
+        return try invokeFnReturningInteger {
+            signal_session_record_debug_number_of_receiver_chains(nativeHandle, $0)
+        }
+    }
 }
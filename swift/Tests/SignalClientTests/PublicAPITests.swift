@@ -52,10 +52,31 @@ class PublicAPITests: XCTestCase {
         XCTAssertEqual(derived, okm)
     }
 
+    func testMessagePadding() {
+        let body = Array("Hello, world!".utf8)
+        let padded = try! padMessage(body)
+        XCTAssertEqual(padded.count, 159)
+        XCTAssertEqual(padded[body.count], 0x80)
+        XCTAssertEqual(try! unpadMessage(padded), body)
+
+        XCTAssertEqual(try! padMessage([UInt8](repeating: 1, count: 159)).count, 319)
+        XCTAssertThrowsError(try unpadMessage([0x80, 0, 1]))
+        XCTAssertThrowsError(try unpadMessage([0, 0, 0]))
+    }
+
     func testAddress() {
         let addr = try! ProtocolAddress(name: "addr1", deviceId: 5)
         XCTAssertEqual(addr.name, "addr1")
         XCTAssertEqual(addr.deviceId, 5)
+        XCTAssertNil(addr.serviceId)
+        XCTAssertThrowsError(try ProtocolAddress(name: "addr1", deviceId: 0))
+        XCTAssertThrowsError(try ProtocolAddress(name: "addr1", deviceId: 128))
+
+        let uuid = UUID(uuidString: "9d0652a3-dcc3-4d11-975f-74d61598733f")!
+        let pniAddr = try! ProtocolAddress(serviceId: .pni(uuid), deviceId: 2)
+        XCTAssertEqual(pniAddr.name, "PNI:9d0652a3-dcc3-4d11-975f-74d61598733f")
+        XCTAssertEqual(pniAddr.serviceId, .pni(uuid))
+        XCTAssertEqual(try! ProtocolAddress(name: "9d0652a3-dcc3-4d11-975f-74d61598733f", deviceId: 1).serviceId, .aci(uuid))
     }
 
     func testPkOperations() {
@@ -100,6 +121,16 @@ class PublicAPITests: XCTestCase {
         XCTAssertEqual(shared_secret1, shared_secret2)
     }
 
+    func testAlternateIdentity() {
+        let primary = try! IdentityKeyPair.generate()
+        let secondary = try! IdentityKeyPair.generate()
+
+        let signature = try! secondary.signAlternateIdentity(primary.identityKey)
+        XCTAssert(try! secondary.identityKey.verifyAlternateIdentity(primary.identityKey, signature: signature))
+        XCTAssertFalse(try! primary.identityKey.verifyAlternateIdentity(secondary.identityKey, signature: signature))
+        XCTAssertFalse(try! secondary.publicKey.verifySignature(message: try! primary.identityKey.serialize(), signature: signature))
+    }
+
     func testFingerprint() {
 
         let ALICE_IDENTITY: [UInt8] = [0x05, 0x06, 0x86, 0x3b, 0xc6, 0x6d, 0x02, 0xb4, 0x0d, 0x27, 0xb8, 0xd4, 0x9c, 0xa7, 0xc0, 0x9e, 0x92, 0x39, 0x23, 0x6f, 0x9d, 0x7d, 0x25, 0xd6, 0xfc, 0xca, 0x5c, 0xe1, 0x3c, 0x70, 0x64, 0xd8, 0x68]
@@ -162,6 +193,47 @@ class PublicAPITests: XCTestCase {
         XCTAssertEqual(aliceFingerprint2.scannable.encoding, ALICE_SCANNABLE_FINGERPRINT_V2)
         XCTAssertEqual(bobFingerprint2.scannable.encoding, BOB_SCANNABLE_FINGERPRINT_V2)
 
+        // comparison reasons
+
+        XCTAssertEqual(try! aliceFingerprint.scannable.compareScanned(bobFingerprint.scannable), .match)
+        XCTAssertEqual(try! aliceFingerprint2.scannable.compareScanned(bobFingerprint.scannable),
+                       .versionMismatch(ours: 2, theirs: 1))
+
+        // displayable only
+
+        let aliceDisplayable = try! generator.createDisplayable(localIdentifier: aliceStableId,
+                                                                localKey: aliceIdentityKey,
+                                                                remoteIdentifier: bobStableId,
+                                                                remoteKey: bobIdentityKey)
+        XCTAssertEqual(aliceDisplayable.formatted, DISPLAYABLE_FINGERPRINT_V1)
+
+        XCTAssertThrowsError(try NumericFingerprintGenerator(iterations: 999).createDisplayable(localIdentifier: aliceStableId,
+                                                                                               localKey: aliceIdentityKey,
+                                                                                               remoteIdentifier: bobStableId,
+                                                                                               remoteKey: bobIdentityKey))
+
+        // testMultiIdentifierVectors
+        let DISPLAYABLE_FINGERPRINT_MULTI = "632860439795916348638199572294663952284327893052686922753464"
+        let ALICE_SCANNABLE_FINGERPRINT_MULTI: [UInt8] = [0x08, 0x03, 0x12, 0x22, 0x0a, 0x20, 0x52, 0x52, 0xe0, 0x81, 0xd6, 0x6d, 0x9f, 0xa7, 0xb8, 0xad, 0x67, 0x97, 0xc3, 0x62, 0xcc, 0x7a, 0xd3, 0x05, 0x11, 0x8f, 0xf6, 0x6f, 0x9f, 0x8c, 0x8b, 0xfd, 0xdc, 0x5c, 0x73, 0x86, 0xd5, 0x34, 0x1a, 0x22, 0x0a, 0x20, 0x13, 0x17, 0x4c, 0x79, 0x5b, 0xc7, 0xaf, 0x43, 0x6a, 0x5b, 0x20, 0xc3, 0x18, 0xad, 0x95, 0x70, 0x7f, 0xd6, 0x90, 0x74, 0x23, 0x8b, 0x78, 0x40, 0x8b, 0x43, 0xb0, 0x8e, 0xbd, 0x78, 0x44, 0x81]
+        let BOB_SCANNABLE_FINGERPRINT_MULTI: [UInt8] = [0x08, 0x03, 0x12, 0x22, 0x0a, 0x20, 0x13, 0x17, 0x4c, 0x79, 0x5b, 0xc7, 0xaf, 0x43, 0x6a, 0x5b, 0x20, 0xc3, 0x18, 0xad, 0x95, 0x70, 0x7f, 0xd6, 0x90, 0x74, 0x23, 0x8b, 0x78, 0x40, 0x8b, 0x43, 0xb0, 0x8e, 0xbd, 0x78, 0x44, 0x81, 0x1a, 0x22, 0x0a, 0x20, 0x52, 0x52, 0xe0, 0x81, 0xd6, 0x6d, 0x9f, 0xa7, 0xb8, 0xad, 0x67, 0x97, 0xc3, 0x62, 0xcc, 0x7a, 0xd3, 0x05, 0x11, 0x8f, 0xf6, 0x6f, 0x9f, 0x8c, 0x8b, 0xfd, 0xdc, 0x5c, 0x73, 0x86, 0xd5, 0x34]
+
+        let aliceUuid: [UInt8] = [UInt8]("6a3d4c8e-1f0b-4d2a-9c5e-7b8f0a1d2e3c".utf8)
+        let bobUuid: [UInt8] = [UInt8]("0f9e8d7c-6b5a-4e3d-8c2b-1a0f9e8d7c6b".utf8)
+
+        let aliceMulti = try! generator.createMulti(localIdentifiers: [aliceUuid, aliceStableId],
+                                                    localKey: aliceIdentityKey,
+                                                    remoteIdentifiers: [bobStableId, bobUuid],
+                                                    remoteKey: bobIdentityKey)
+        let bobMulti = try! generator.createMulti(localIdentifiers: [bobStableId, bobUuid],
+                                                  localKey: bobIdentityKey,
+                                                  remoteIdentifiers: [aliceStableId, aliceUuid],
+                                                  remoteKey: aliceIdentityKey)
+
+        XCTAssertEqual(aliceMulti.displayable.formatted, DISPLAYABLE_FINGERPRINT_MULTI)
+        XCTAssertEqual(bobMulti.displayable.formatted, DISPLAYABLE_FINGERPRINT_MULTI)
+        XCTAssertEqual(aliceMulti.scannable.encoding, ALICE_SCANNABLE_FINGERPRINT_MULTI)
+        XCTAssertEqual(bobMulti.scannable.encoding, BOB_SCANNABLE_FINGERPRINT_MULTI)
+
         // testMismatchingFingerprints
 
         let mitmIdentityKey = try! PrivateKey.generate().publicKey()
@@ -225,13 +297,26 @@ class PublicAPITests: XCTestCase {
         let a_ctext = try! groupEncrypt(groupId: group_id, message: [1, 2, 3], store: a_store, context: nil)
 
         let b_store = try! InMemorySignalProtocolStore()
+        XCTAssertThrowsError(try processSenderKeyDistributionMessage(sender: group_id,
+                                                                     message: skdm_r,
+                                                                     expectedId: try! skdm_r.id() ^ 1,
+                                                                     store: b_store,
+                                                                     context: nil))
         try! processSenderKeyDistributionMessage(sender: group_id,
                                                  message: skdm_r,
+                                                 expectedId: try! skdm_r.id(),
                                                  store: b_store,
                                                  context: nil)
         let b_ptext = try! groupDecrypt(groupId: group_id, message: a_ctext, store: b_store, context: nil)
 
         XCTAssertEqual(b_ptext, [1, 2, 3])
+
+        let first = try! groupEncryptWithMetadata(groupId: group_id, message: [4, 5, 6], store: a_store, context: nil)
+        let second = try! groupEncryptWithMetadata(groupId: group_id, message: [7, 8, 9], store: a_store, context: nil)
+        XCTAssertEqual(first.chainId, try! skdm.id())
+        XCTAssertEqual(second.chainId, first.chainId)
+        XCTAssertEqual(second.iteration, first.iteration + 1)
+        XCTAssertEqual(try! groupDecrypt(groupId: group_id, message: second.serialized, store: b_store, context: nil), [7, 8, 9])
     }
 
     func testSessionCipher() {
@@ -262,14 +347,25 @@ class PublicAPITests: XCTestCase {
                                            identity: bob_identity_key)
 
         // Alice processes the bundle:
-        try! processPreKeyBundle(bob_bundle,
-                                 for: bob_address,
-                                 sessionStore: alice_store,
-                                 identityStore: alice_store,
-                                 context: nil)
+        XCTAssertEqual(try! processPreKeyBundle(bob_bundle,
+                                                for: bob_address,
+                                                sessionStore: alice_store,
+                                                identityStore: alice_store,
+                                                context: nil),
+                       .newIdentity)
+        XCTAssertEqual(try! alice_store.saveIdentity(bob_identity_key, for: bob_address, context: nil), .unchanged)
+        XCTAssertEqual(try! alice_store.saveIdentity(try! IdentityKeyPair.generate().identityKey, for: bob_address, context: nil),
+                       .replacedExisting)
+        XCTAssertEqual(try! alice_store.saveIdentity(bob_identity_key, for: bob_address, context: nil), .replacedExisting)
 
         XCTAssertEqual(try! alice_store.loadSession(for: bob_address, context: nil)?.remoteRegistrationId(),
                        try! bob_store.localRegistrationId(context: nil))
+        let alice_session = try! alice_store.loadSession(for: bob_address, context: nil)!
+        XCTAssertTrue(try! alice_session.debugHasUsableSenderChain())
+        XCTAssertEqual(try! alice_session.debugSenderChainCounter(), 0)
+        XCTAssertEqual(try! alice_session.debugNumberOfReceiverChains(), 1)
+        XCTAssertEqual(try! alice_session.debugReceiverChainCounter(theirEphemeral: bob_signed_pre_key.publicKey()), 0)
+        XCTAssertNil(try! alice_session.debugReceiverChainCounter(theirEphemeral: bob_pre_key.publicKey()))
 
         // Bob does the same:
         try! bob_store.storePreKey(PreKeyRecord(id: prekey_id, privateKey: bob_pre_key),
@@ -295,6 +391,10 @@ class PublicAPITests: XCTestCase {
                                          context: nil)
 
         XCTAssertEqual(try! ctext_a.messageType(), .preKey)
+        let reparsed = try! CiphertextMessage(type: .preKey, bytes: try! ctext_a.serialize())
+        XCTAssertEqual(try! reparsed.serialize(), try! ctext_a.serialize())
+        XCTAssertThrowsError(try CiphertextMessage(type: .senderKey, bytes: try! ctext_a.serialize()))
+        XCTAssertThrowsError(try CiphertextMessage(type: CiphertextMessage.MessageType(rawValue: 7), bytes: try! ctext_a.serialize()))
 
         let ctext_b = try! PreKeySignalMessage(bytes: try! ctext_a.serialize())
 
@@ -328,6 +428,185 @@ class PublicAPITests: XCTestCase {
                                           context: nil)
 
         XCTAssertEqual(ptext2_a, ptext2_b)
+
+        // Replaying Bob's reply reports the counter that was already used:
+        XCTAssertThrowsError(try signalDecrypt(message: ctext2_a,
+                                               from: bob_address,
+                                               sessionStore: alice_store,
+                                               identityStore: alice_store,
+                                               context: nil)) { error in
+            guard case SignalError.duplicatedMessage(_, let chainIndex, let counter) = error else {
+                return XCTFail("unexpected error: \(error)")
+            }
+            XCTAssertEqual(chainIndex, 1)
+            XCTAssertEqual(counter, 0)
+        }
+
+        // Device 1 is never reported as a sub-device:
+        XCTAssertEqual(try! alice_store.subDeviceSessions(for: bob_address.name, context: nil), [])
+        let bob_second_address = try! ProtocolAddress(name: bob_address.name, deviceId: 2)
+        try! alice_store.storeSession(alice_store.loadSession(for: bob_address, context: nil)!,
+                                      for: bob_second_address,
+                                      context: nil)
+        XCTAssertEqual(try! alice_store.subDeviceSessions(for: bob_address.name, context: nil), [2])
+
+        try! alice_store.deleteSession(for: bob_second_address, context: nil)
+        try! alice_store.deleteSession(for: bob_second_address, context: nil)
+        XCTAssertEqual(try! alice_store.subDeviceSessions(for: bob_address.name, context: nil), [])
+        XCTAssertNotNil(try! alice_store.loadSession(for: bob_address, context: nil))
+
+        try! alice_store.deleteAllSessions(for: bob_address.name, context: nil)
+        XCTAssertNil(try! alice_store.loadSession(for: bob_address, context: nil))
+    }
+
+    func testPreKeyBundleReport() {
+        let bob_address = try! ProtocolAddress(name: "+14151111112", deviceId: 1)
+
+        let alice_store = try! InMemorySignalProtocolStore()
+        let bob_store = try! InMemorySignalProtocolStore()
+
+        let bob_pre_key = try! PrivateKey.generate()
+        let bob_signed_pre_key = try! PrivateKey.generate()
+        let bob_identity_key = try! bob_store.identityKeyPair(context: nil).identityKey
+        let bob_signed_pre_key_signature = try! bob_store.identityKeyPair(context: nil).privateKey.generateSignature(message: try! bob_signed_pre_key.publicKey().serialize())
+
+        let bob_bundle = try! PreKeyBundle(registrationId: try! bob_store.localRegistrationId(context: nil),
+                                           deviceId: 1,
+                                           prekeyId: 4570,
+                                           prekey: bob_pre_key.publicKey(),
+                                           signedPrekeyId: 3006,
+                                           signedPrekey: try! bob_signed_pre_key.publicKey(),
+                                           signedPrekeySignature: bob_signed_pre_key_signature,
+                                           identity: bob_identity_key)
+
+        let report = try! processPreKeyBundleWithReport(bob_bundle,
+                                                        for: bob_address,
+                                                        sessionStore: alice_store,
+                                                        identityStore: alice_store,
+                                                        context: nil)
+        XCTAssertEqual(report.preKeyId, 4570)
+        XCTAssertEqual(report.signedPreKeyId, 3006)
+        XCTAssertEqual(report.sessionVersion, 3)
+        XCTAssertEqual(report.identityChange, .newIdentity)
+
+        let again = try! processPreKeyBundleWithReport(bob_bundle,
+                                                       for: bob_address,
+                                                       sessionStore: alice_store,
+                                                       identityStore: alice_store,
+                                                       context: nil)
+        XCTAssertEqual(again.identityChange, .unchanged)
+
+        let encrypted = try! signalEncryptWithDetails(message: [1, 2, 3],
+                                                      for: bob_address,
+                                                      sessionStore: alice_store,
+                                                      identityStore: alice_store,
+                                                      context: nil)
+        XCTAssertEqual(encrypted.messageType, .preKey)
+        XCTAssertEqual(encrypted.remoteRegistrationId, try! bob_store.localRegistrationId(context: nil))
+        XCTAssertNoThrow(try PreKeySignalMessage(bytes: encrypted.serialized))
+    }
+
+    func testStoreContextIsPassedThrough() {
+        /// Forwards to an in-memory store, counting each call in the Int that `context` points to.
+        class CountingSessionStore: SessionStore {
+            let inner = try! InMemorySignalProtocolStore()
+
+            func count(_ context: UnsafeMutableRawPointer?) {
+                context!.assumingMemoryBound(to: Int.self).pointee += 1
+            }
+
+            func loadSession(for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws -> SessionRecord? {
+                count(context)
+                return try inner.loadSession(for: address, context: nil)
+            }
+            func storeSession(_ record: SessionRecord, for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws {
+                count(context)
+                try inner.storeSession(record, for: address, context: nil)
+            }
+            func subDeviceSessions(for name: String, context: UnsafeMutableRawPointer?) throws -> [UInt32] {
+                count(context)
+                return try inner.subDeviceSessions(for: name, context: nil)
+            }
+            func deleteSession(for address: ProtocolAddress, context: UnsafeMutableRawPointer?) throws {
+                count(context)
+                try inner.deleteSession(for: address, context: nil)
+            }
+            func deleteAllSessions(for name: String, context: UnsafeMutableRawPointer?) throws {
+                count(context)
+                try inner.deleteAllSessions(for: name, context: nil)
+            }
+        }
+
+        let bob_address = try! ProtocolAddress(name: "+14151111112", deviceId: 1)
+        let alice_identity_store = try! InMemorySignalProtocolStore()
+        let alice_session_store = CountingSessionStore()
+        let bob_store = try! InMemorySignalProtocolStore()
+
+        let bob_signed_pre_key = try! PrivateKey.generate()
+        let bob_signed_pre_key_signature = try! bob_store.identityKeyPair(context: nil).privateKey.generateSignature(message: try! bob_signed_pre_key.publicKey().serialize())
+        let bob_bundle = try! PreKeyBundle(registrationId: try! bob_store.localRegistrationId(context: nil),
+                                           deviceId: 1,
+                                           prekeyId: 1,
+                                           prekey: try! PrivateKey.generate().publicKey(),
+                                           signedPrekeyId: 2,
+                                           signedPrekey: try! bob_signed_pre_key.publicKey(),
+                                           signedPrekeySignature: bob_signed_pre_key_signature,
+                                           identity: try! bob_store.identityKeyPair(context: nil).identityKey)
+
+        var calls = 0
+        withUnsafeMutablePointer(to: &calls) { counter in
+            _ = try! processPreKeyBundle(bob_bundle,
+                                         for: bob_address,
+                                         sessionStore: alice_session_store,
+                                         identityStore: alice_identity_store,
+                                         context: counter)
+            _ = try! signalEncrypt(message: [1, 2, 3],
+                                   for: bob_address,
+                                   sessionStore: alice_session_store,
+                                   identityStore: alice_identity_store,
+                                   context: counter)
+        }
+
+        // Each operation loads and then stores the session.
+        XCTAssertEqual(calls, 4)
+    }
+
+    func testDecryptionErrorMessage() {
+        let signalMessage: [UInt8] = [
+            0x33, 0x0a, 0x21, 0x05, 0xa4, 0xe0, 0x92, 0x92, 0xb6, 0x51, 0xc2, 0x78, 0xb9, 0x77,
+            0x2c, 0x56, 0x9f, 0x5f, 0xa9, 0xbb, 0x13, 0xd9, 0x06, 0xb4, 0x6a, 0xb6, 0x8c, 0x9d,
+            0xf9, 0xdc, 0x2b, 0x44, 0x09, 0xf8, 0xa2, 0x09, 0x10, 0x2a, 0x18, 0x29, 0x22, 0x0a,
+            0x63, 0x69, 0x70, 0x68, 0x65, 0x72, 0x74, 0x65, 0x78, 0x74, 0x8b, 0x50, 0x6a, 0x04,
+            0xce, 0x17, 0xc4, 0xe8,
+        ]
+        let errorMessage = try! DecryptionErrorMessage(originalMessageBytes: signalMessage,
+                                                       type: .whisper,
+                                                       timestamp: 1000,
+                                                       originalSenderDeviceId: 7)
+        XCTAssertEqual(try! errorMessage.ratchetKey()!.serialize(), Array(signalMessage[3..<36]))
+        XCTAssertEqual(try! errorMessage.timestamp(), 1000)
+        XCTAssertEqual(try! errorMessage.deviceId(), 7)
+
+        let content = try! PlaintextContent(bytes: try! PlaintextContent(errorMessage).serialize())
+        let extracted = try! DecryptionErrorMessage.extractFromSerializedContent(try! content.body())
+        XCTAssertEqual(try! extracted.serialize(), try! errorMessage.serialize())
+
+        XCTAssertThrowsError(try DecryptionErrorMessage(originalMessageBytes: signalMessage,
+                                                        type: .preKey,
+                                                        timestamp: 1000,
+                                                        originalSenderDeviceId: 7))
+    }
+
+    func testUnidentifiedAccessKey() {
+        let zeroKey = Array(repeating: UInt8(0x00), count: 32)
+        let derived = try! deriveUnidentifiedAccessKey(profileKey: zeroKey)
+        XCTAssertEqual(derived, [0xce, 0xa7, 0x40, 0x3d, 0x4d, 0x60, 0x6b, 0x6e,
+                                 0x07, 0x4e, 0xc5, 0xd3, 0xba, 0xf3, 0x9d, 0x18])
+
+        XCTAssertThrowsError(try deriveUnidentifiedAccessKey(profileKey: [UInt8](repeating: 0, count: 16)))
+
+        let unrestricted = try! unrestrictedUnidentifiedAccessKey()
+        XCTAssertEqual(unrestricted.count, 16)
     }
 
     static var allTests: [(String, (PublicAPITests) -> () throws -> Void)] {
@@ -335,10 +614,15 @@ class PublicAPITests: XCTestCase {
             ("testAddreses", testAddress),
             ("testFingerprint", testFingerprint),
             ("testPkOperations", testPkOperations),
+            ("testAlternateIdentity", testAlternateIdentity),
             ("testHkdfSimple", testHkdfSimple),
             ("testHkdfUsingRFCExample", testHkdfUsingRFCExample),
             ("testGroupCipher", testGroupCipher),
             ("testSessionCipher", testSessionCipher),
+            ("testPreKeyBundleReport", testPreKeyBundleReport),
+            ("testStoreContextIsPassedThrough", testStoreContextIsPassedThrough),
+            ("testDecryptionErrorMessage", testDecryptionErrorMessage),
+            ("testUnidentifiedAccessKey", testUnidentifiedAccessKey),
         ]
     }
 }